@@ -0,0 +1,232 @@
+//! Shell completions and man pages.
+//!
+//! `cargo faasta completions SHELL` prints a script that has the shell ask
+//! cargo-faasta itself what to complete, through clap_complete's `COMPLETE`
+//! environment variable: subcommands and flags come from the clap definition, and
+//! function names from the server's list of the user's functions when they're
+//! logged in. Shells complete `cargo` as a whole, so the script only answers after
+//! `faasta` and hands anything else to the completion of `cargo` loaded before it
+//! (bash and zsh), or adds to it (fish). PowerShell can't chain completers; there
+//! it completes nothing but `cargo faasta`. `cargo faasta man` renders man pages
+//! from the same definition.
+
+use anyhow::{Context, Result};
+use clap::CommandFactory;
+use clap_complete::env::{Bash, EnvCompleter, Fish, Powershell, Zsh};
+use clap_complete::{CompleteEnv, CompletionCandidate};
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{project, run, Faasta, DEFAULT_SERVER};
+
+/// Environment variable the completion scripts set when asking for completions
+const COMPLETE_ENV: &str = "COMPLETE";
+/// How long completing a function name waits for the server
+const LIST_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Shells completions are generated for
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+/// Answer the shell and exit, if this run is a request for completions
+pub fn complete() {
+    CompleteEnv::with_factory(Faasta::command)
+        .var(COMPLETE_ENV)
+        .complete();
+}
+
+/// Print the script registering completions of `cargo faasta` with `shell`
+pub fn print_script(shell: Shell) -> Result<()> {
+    let exe = std::env::current_exe().context("Failed to find the cargo-faasta executable")?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(script(shell, &exe.to_string_lossy())?.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// The script registering completions of `cargo faasta` with `shell`, asking `exe`
+fn script(shell: Shell, exe: &str) -> Result<String> {
+    let completer: &dyn EnvCompleter = match shell {
+        Shell::Bash => &Bash,
+        Shell::Zsh => &Zsh,
+        Shell::Fish => &Fish,
+        Shell::Powershell => &Powershell,
+    };
+    let mut registration = Vec::new();
+    completer.write_registration(COMPLETE_ENV, "cargo", "cargo", exe, &mut registration)?;
+    let registration = String::from_utf8(registration)?;
+    let script = match shell {
+        Shell::Bash => format!("{BASH_BEFORE}{registration}{BASH_AFTER}"),
+        Shell::Zsh => format!("{ZSH_BEFORE}{registration}{ZSH_AFTER}"),
+        Shell::Fish => only_after_faasta(&registration, FISH_COMMAND, FISH_CONDITION)?,
+        Shell::Powershell => only_after_faasta(&registration, POWERSHELL_BLOCK, POWERSHELL_GUARD)?,
+    };
+    Ok(script)
+}
+
+/// `registration` with `condition` added after `anchor`, so it only completes
+/// `cargo faasta`
+fn only_after_faasta(registration: &str, anchor: &str, condition: &str) -> Result<String> {
+    if !registration.contains(anchor) {
+        anyhow::bail!("Unexpected completion script from clap_complete");
+    }
+    Ok(registration.replacen(anchor, &format!("{anchor}{condition}"), 1))
+}
+
+/// Loads cargo's own bash completion, if bash-completion loads it lazily, and keeps
+/// its function, unless that's this script's from an earlier run
+const BASH_BEFORE: &str = r#"if ! complete -p cargo &> /dev/null && declare -F _completion_loader &> /dev/null; then
+    _completion_loader cargo
+fi
+_cargo_faasta_spec=$(complete -p cargo 2> /dev/null | sed -n 's/.* -F \([^ ]*\) .*/\1/p')
+if [[ $_cargo_faasta_spec != _cargo_faasta_complete ]]; then
+    _cargo_faasta_previous=$_cargo_faasta_spec
+fi
+"#;
+
+/// Answers `cargo faasta` and hands everything else to cargo's completion
+const BASH_AFTER: &str = r#"
+_cargo_faasta_complete() {
+    if [[ $COMP_CWORD -gt 1 && ${COMP_WORDS[1]} == faasta || -z $_cargo_faasta_previous ]]; then
+        _clap_complete_cargo "$@"
+    else
+        compopt +o nospace 2> /dev/null
+        "$_cargo_faasta_previous" "$@"
+    fi
+}
+complete -o nospace -o bashdefault -F _cargo_faasta_complete cargo
+"#;
+
+/// Keeps the function completing `cargo`, unless that's this script's from an
+/// earlier run
+const ZSH_BEFORE: &str = r#"if [[ ${_comps[cargo]} != _cargo_faasta_complete ]]; then
+    _cargo_faasta_previous=${_comps[cargo]}
+fi
+"#;
+
+/// Answers `cargo faasta` and hands everything else to cargo's completion
+const ZSH_AFTER: &str = r#"
+function _cargo_faasta_complete() {
+    if (( CURRENT > 2 )) && [[ ${words[2]} == faasta ]] || [[ -z $_cargo_faasta_previous ]]; then
+        _clap_dynamic_completer_cargo
+    else
+        $_cargo_faasta_previous
+    fi
+}
+
+compdef _cargo_faasta_complete cargo
+"#;
+
+/// Fish adds completions to the ones it has, so they only need a condition
+const FISH_COMMAND: &str = "--command cargo";
+const FISH_CONDITION: &str = " --condition '__fish_seen_subcommand_from faasta'";
+
+/// PowerShell keeps one completer per command, so the script's leaves anything but
+/// `cargo faasta` to the default completion
+const POWERSHELL_BLOCK: &str = "param($wordToComplete, $commandAst, $cursorPosition)\n";
+const POWERSHELL_GUARD: &str = r#"
+    if ($commandAst.CommandElements.Count -lt 2 -or
+        ($commandAst.CommandElements.Count -eq 2 -and $wordToComplete -ne "") -or
+        $commandAst.CommandElements[1].Extent.Text -ne "faasta") {
+        return;
+    }
+"#;
+
+/// Render the man page of `cargo faasta` to stdout, or the pages of it and every
+/// subcommand into `out_dir`
+pub fn man(out_dir: Option<&Path>) -> Result<()> {
+    // Pages for the `help` subcommands would repeat the others
+    let mut root = Faasta::command().disable_help_subcommand(true);
+    root.build();
+    let cmd = root
+        .find_subcommand("faasta")
+        .cloned()
+        .context("The faasta command is missing")?
+        .version(env!("CARGO_PKG_VERSION"));
+    match out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            clap_mangen::generate_to(cmd, dir)
+                .with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+            println!("✅ Man pages written to {}", dir.display());
+        }
+        None => clap_mangen::Man::new(cmd).render(&mut std::io::stdout())?,
+    }
+    Ok(())
+}
+
+/// Names of the user's deployed functions starting with `current`. Nothing when
+/// they aren't logged in or the server doesn't answer in time.
+pub fn function_names(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Some(prefix) = current.to_str() else {
+        return Vec::new();
+    };
+    let names = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(deployed_functions())
+    });
+    names
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// The user's functions on the server of the current project, else the default one
+async fn deployed_functions() -> Option<Vec<String>> {
+    // Completion stays quiet, offering nothing, when it can't reach the server
+    let (username, token) = match crate::load_credentials().await {
+        Ok(Some(credentials)) => credentials,
+        Ok(None) | Err(_) => return None,
+    };
+    let server = project::load(Path::new("."))
+        .ok()
+        .and_then(|config| config.function.server)
+        .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+    let list = async {
+        let client = run::connect_to_function_service(&server).await.ok()?;
+        client
+            .list_functions(
+                tarpc::context::current(),
+                faasta_interface::FunctionQuery::all(),
+                format!("{username}:{token}"),
+            )
+            .await
+            .ok()?
+            .ok()
+    };
+    match tokio::time::timeout(LIST_TIMEOUT, list).await {
+        Ok(Some(page)) => Some(page.functions.into_iter().map(|info| info.name).collect()),
+        Ok(None) | Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripts_only_complete_cargo_faasta() {
+        let exe = "/usr/bin/cargo-faasta";
+        let bash = script(Shell::Bash, exe).unwrap();
+        assert!(bash.contains("_clap_complete_cargo()"));
+        assert!(
+            bash.ends_with("complete -o nospace -o bashdefault -F _cargo_faasta_complete cargo\n")
+        );
+        let zsh = script(Shell::Zsh, exe).unwrap();
+        assert!(zsh.contains("function _clap_dynamic_completer_cargo()"));
+        assert!(zsh.ends_with("compdef _cargo_faasta_complete cargo\n"));
+        let fish = script(Shell::Fish, exe).unwrap();
+        assert!(fish.contains("--command cargo --condition '__fish_seen_subcommand_from faasta'"));
+        let powershell = script(Shell::Powershell, exe).unwrap();
+        assert!(powershell.contains("-ne \"faasta\""));
+    }
+}
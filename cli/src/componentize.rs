@@ -0,0 +1,215 @@
+//! Building JavaScript and Python functions into components.
+//!
+//! A `package.json` with a `faasta` key, or a `pyproject.toml` with a
+//! `[tool.faasta]` table, marks a JavaScript or Python function. Such projects are
+//! built with the language's componentize tool (`jco componentize` for
+//! componentize-js, `componentize-py`) into a wasip2 component under
+//! `target/faasta/`, which then deploys like one built from Rust.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where built components are written, inside the project
+const OUTPUT_DIR: &str = "target/faasta";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    JavaScript,
+    Python,
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Language::JavaScript => "JavaScript",
+            Language::Python => "Python",
+        })
+    }
+}
+
+/// The `faasta` section of `package.json` or `[tool.faasta]` of `pyproject.toml`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ComponentizeSettings {
+    /// Entry script (JavaScript) or app module (Python)
+    pub entry: Option<String>,
+    /// Directory of the WIT files describing the world
+    pub wit: String,
+    /// World the component implements
+    pub world: String,
+}
+
+impl Default for ComponentizeSettings {
+    fn default() -> Self {
+        Self {
+            entry: None,
+            wit: "wit".to_string(),
+            world: "wasi:http/proxy".to_string(),
+        }
+    }
+}
+
+/// A JavaScript or Python function project
+#[derive(Debug)]
+pub struct ForeignProject {
+    pub language: Language,
+    /// Package name, used as the function name
+    pub name: String,
+    pub dir: PathBuf,
+    settings: ComponentizeSettings,
+    /// Entry from the package itself, used without one in the faasta settings
+    default_entry: String,
+}
+
+#[derive(Deserialize)]
+struct PackageJson {
+    name: Option<String>,
+    main: Option<String>,
+    faasta: Option<ComponentizeSettings>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct PyProject {
+    project: PyProjectMetadata,
+    tool: PyProjectTools,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct PyProjectMetadata {
+    name: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct PyProjectTools {
+    faasta: Option<ComponentizeSettings>,
+}
+
+/// The JavaScript or Python function project in `dir`, if there is one
+pub fn detect(dir: &Path) -> Result<Option<ForeignProject>> {
+    let dir_name = || {
+        dir.canonicalize()
+            .ok()
+            .and_then(|dir| dir.file_name()?.to_str().map(String::from))
+            .unwrap_or_else(|| "function".to_string())
+    };
+
+    if let Some(contents) = read_optional(&dir.join("package.json"))? {
+        let package: PackageJson =
+            serde_json::from_str(&contents).context("Invalid package.json")?;
+        if let Some(settings) = package.faasta {
+            // Scoped packages (`@scope/name`) are named after the last part
+            let name = package
+                .name
+                .map(|name| name.rsplit('/').next().unwrap_or_default().to_string())
+                .unwrap_or_else(dir_name);
+            return Ok(Some(ForeignProject {
+                language: Language::JavaScript,
+                name,
+                dir: dir.to_path_buf(),
+                settings,
+                default_entry: package.main.unwrap_or_else(|| "index.js".to_string()),
+            }));
+        }
+    }
+
+    if let Some(contents) = read_optional(&dir.join("pyproject.toml"))? {
+        let pyproject: PyProject = toml::from_str(&contents).context("Invalid pyproject.toml")?;
+        if let Some(settings) = pyproject.tool.faasta {
+            return Ok(Some(ForeignProject {
+                language: Language::Python,
+                name: pyproject.project.name.unwrap_or_else(dir_name),
+                dir: dir.to_path_buf(),
+                settings,
+                default_entry: "app".to_string(),
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+impl ForeignProject {
+    /// Where [`ForeignProject::build`] writes the component
+    pub fn output_path(&self) -> PathBuf {
+        self.dir
+            .join(OUTPUT_DIR)
+            .join(format!("{}.wasm", self.name.replace('-', "_")))
+    }
+
+    /// Build the project into a component with its componentize tool
+    pub fn build(&self) -> Result<PathBuf> {
+        let output = self.output_path();
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let entry = self
+            .settings
+            .entry
+            .as_deref()
+            .unwrap_or(&self.default_entry);
+
+        let (mut command, install_hint) = match self.language {
+            Language::JavaScript => {
+                // Prefer a jco installed as a dev dependency over a global one
+                let local = self.dir.join("node_modules").join(".bin").join("jco");
+                let mut command = Command::new(if local.exists() {
+                    local
+                } else {
+                    PathBuf::from("jco")
+                });
+                command
+                    .arg("componentize")
+                    .arg(entry)
+                    .args(["--wit", &self.settings.wit])
+                    .args(["--world-name", &self.settings.world])
+                    .arg("--out")
+                    .arg(&output);
+                (
+                    command,
+                    "npm install --save-dev @bytecodealliance/jco @bytecodealliance/componentize-js",
+                )
+            }
+            Language::Python => {
+                let mut command = Command::new("componentize-py");
+                command
+                    .args(["-d", &self.settings.wit])
+                    .args(["-w", &self.settings.world])
+                    .args(["componentize", entry])
+                    .arg("-o")
+                    .arg(&output);
+                (command, "pip install componentize-py")
+            }
+        };
+
+        let status = command
+            .current_dir(&self.dir)
+            .status()
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => anyhow!(
+                    "The {} componentize tool is not installed. Install it with '{install_hint}'",
+                    self.language
+                ),
+                _ => anyhow!("Failed to run the {} componentize tool: {e}", self.language),
+            })?;
+        if !status.success() {
+            bail!("Componentizing the {} project failed", self.language);
+        }
+        Ok(output)
+    }
+}
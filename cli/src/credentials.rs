@@ -0,0 +1,197 @@
+//! Storage for the Faasta auth token.
+//!
+//! Tokens live in the platform keyring (macOS Keychain, Windows Credential
+//! Manager or the Secret Service on Linux). Machines without a usable keyring,
+//! such as headless CI boxes, fall back to a ChaCha20-Poly1305 encrypted file
+//! inside the Faasta config directory.
+
+use anyhow::{anyhow, Context, Result};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const KEYRING_SERVICE: &str = "faasta";
+const ENCRYPTED_FILE: &str = "credentials.enc";
+const KEY_FILE: &str = "credentials.key";
+const NONCE_LEN: usize = 12;
+
+/// Store the token for `username`, replacing any previous one
+pub async fn store_token(config_dir: &Path, username: &str, token: &str) -> Result<()> {
+    let user = username.to_string();
+    let secret = token.to_string();
+    let stored = keyring_call(move |entry| entry.set_password(&secret), user).await?;
+
+    if stored.is_none() {
+        let mut tokens = read_encrypted_file(config_dir)?;
+        tokens.insert(username.to_string(), token.to_string());
+        write_encrypted_file(config_dir, &tokens)?;
+    }
+    Ok(())
+}
+
+/// Load the token for `username`, if one has been stored
+pub async fn load_token(config_dir: &Path, username: &str) -> Result<Option<String>> {
+    let user = username.to_string();
+    let from_keyring = keyring_call(
+        |entry| match entry.get_password() {
+            Ok(token) => Ok(Some(token)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e),
+        },
+        user,
+    )
+    .await?;
+
+    match from_keyring.flatten() {
+        Some(token) => Ok(Some(token)),
+        // The token may have been written while no keyring was available
+        None => Ok(read_encrypted_file(config_dir)?.remove(username)),
+    }
+}
+
+/// Remove the token for `username` from the keyring and the fallback file
+pub async fn delete_token(config_dir: &Path, username: &str) -> Result<()> {
+    let user = username.to_string();
+    keyring_call(
+        |entry| match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e),
+        },
+        user,
+    )
+    .await?;
+
+    let mut tokens = read_encrypted_file(config_dir)?;
+    if tokens.remove(username).is_some() {
+        write_encrypted_file(config_dir, &tokens)?;
+    }
+    if tokens.is_empty() {
+        for file in [ENCRYPTED_FILE, KEY_FILE] {
+            let path = config_dir.join(file);
+            if path.exists() {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run `op` against the keyring entry for `username`.
+/// Returns `Ok(None)` when the platform has no usable keyring so callers can fall back.
+async fn keyring_call<T, F>(op: F, username: String) -> Result<Option<T>>
+where
+    T: Send + 'static,
+    F: FnOnce(&keyring::Entry) -> keyring::Result<T> + Send + 'static,
+{
+    // Some keyring backends block on their own runtime, so keep them off ours
+    let result = tokio::task::spawn_blocking(move || {
+        keyring::Entry::new(KEYRING_SERVICE, &username).and_then(|entry| op(&entry))
+    })
+    .await
+    .context("Keyring task panicked")?;
+
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::PlatformFailure(_)) | Err(keyring::Error::NoStorageAccess(_)) => {
+            Ok(None)
+        }
+        Err(e) => Err(anyhow!("Keyring error: {e}")),
+    }
+}
+
+fn read_encrypted_file(config_dir: &Path) -> Result<HashMap<String, String>> {
+    let path = config_dir.join(ENCRYPTED_FILE);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let data = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if data.len() < NONCE_LEN {
+        return Err(anyhow!("Credentials file {} is corrupt", path.display()));
+    }
+
+    let cipher = ChaCha20Poly1305::new(&load_or_create_key(config_dir)?);
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Failed to decrypt {}", path.display()))?;
+
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+fn write_encrypted_file(config_dir: &Path, tokens: &HashMap<String, String>) -> Result<()> {
+    let cipher = ChaCha20Poly1305::new(&load_or_create_key(config_dir)?);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, serde_json::to_vec(tokens)?.as_slice())
+        .map_err(|_| anyhow!("Failed to encrypt credentials"))?;
+
+    let mut data = nonce.to_vec();
+    data.extend_from_slice(&ciphertext);
+    write_private(&config_dir.join(ENCRYPTED_FILE), &data)
+}
+
+fn load_or_create_key(config_dir: &Path) -> Result<Key> {
+    let path = config_dir.join(KEY_FILE);
+    if path.exists() {
+        let bytes =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        if bytes.len() != 32 {
+            return Err(anyhow!("Credentials key {} is corrupt", path.display()));
+        }
+        return Ok(*Key::from_slice(&bytes));
+    }
+
+    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    write_private(&path, &key)?;
+    Ok(key)
+}
+
+/// Write a file readable only by the current user
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.write_all(data)?;
+    }
+
+    #[cfg(not(unix))]
+    fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypted_file_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("faasta-credentials-{}", std::process::id()));
+        let mut tokens = HashMap::new();
+        tokens.insert("octocat".to_string(), "Bearer abc123".to_string());
+
+        write_encrypted_file(&dir, &tokens).unwrap();
+        let raw = fs::read(dir.join(ENCRYPTED_FILE)).unwrap();
+        assert!(!String::from_utf8_lossy(&raw).contains("abc123"));
+        assert_eq!(read_encrypted_file(&dir).unwrap(), tokens);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
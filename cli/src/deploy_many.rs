@@ -0,0 +1,392 @@
+//! Deploying several functions at once.
+//!
+//! `cargo faasta deploy --workspace` deploys every function crate of the cargo
+//! workspace, and `cargo faasta deploy PATH PATH...` the projects given. Each
+//! project is read and checked on its own, then up to [`CONCURRENT_DEPLOYS`] are
+//! uploaded at a time, each with its own progress line, and a table of what was
+//! deployed and what failed is printed at the end. The uploads share one
+//! connection to the server, their requests pipelined on it rather than each
+//! given a stream of its own. A component is only read into memory once its
+//! upload starts.
+//! A project that fails doesn't stop the others, and one unchanged since its last
+//! deploy isn't uploaded again unless `--force` is given.
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{stream, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{componentize, errors, fingerprint, project, run, DeployArgs, DEFAULT_SERVER};
+
+/// Most projects uploaded at a time
+const CONCURRENT_DEPLOYS: usize = 4;
+
+/// A project read and checked, ready to deploy
+struct Project {
+    dir: PathBuf,
+    name: String,
+    /// The component, read when its upload starts
+    wasm_path: PathBuf,
+    config: project::ProjectConfig,
+    routes: Option<Vec<(String, Vec<u8>)>>,
+    metadata: faasta_interface::FunctionMetadata,
+}
+
+/// The cargo package in a directory, as `cargo metadata` reports it
+struct Package {
+    name: String,
+    dir: PathBuf,
+    target_directory: PathBuf,
+    /// Whether it builds a `cdylib`, as function crates do
+    cdylib: bool,
+}
+
+/// Deploy the projects `args` names together and report on each
+pub async fn deploy(args: &DeployArgs, (username, token): (String, String)) -> Result<()> {
+    if args.wasm_path.is_some()
+        || args.from_oci.is_some()
+        || args.provenance.is_some()
+        || args.function_name.is_some()
+    {
+        return Err(errors::failure(
+            &errors::INVALID_INPUT,
+            "--wasm, --from-oci, --provenance and --function-name apply to one project; \
+             set them in each project's faasta.toml instead",
+        ));
+    }
+    let dirs = if args.workspace {
+        workspace_functions()?
+    } else {
+        args.paths.iter().map(PathBuf::from).collect()
+    };
+    if dirs.is_empty() {
+        return Err(errors::failure(
+            &errors::PROJECT_INVALID,
+            "The workspace has no function crates (crate-type = [\"cdylib\"])",
+        ));
+    }
+
+    let prepared: Vec<(PathBuf, Result<Project>)> = dirs
+        .into_iter()
+        .map(|dir| {
+            let project = prepare(&dir);
+            (dir, project)
+        })
+        .collect();
+    let server = server(args, &prepared)?;
+    let (client, info) = run::connect_with_info(&server)
+        .await
+        .map_err(|e| errors::failure(&errors::CONNECT_FAILED, format!("{e:#}")))?;
+    let auth_token = format!("{username}:{token}");
+
+    let progress = MultiProgress::new();
+    let style = ProgressStyle::with_template("{spinner} {prefix:<24} {msg}")
+        .unwrap_or_else(|_| ProgressStyle::default_spinner());
+    let deploys = prepared
+        .into_iter()
+        .enumerate()
+        .map(|(index, (dir, project))| {
+            let bar = progress.add(ProgressBar::new_spinner());
+            bar.set_style(style.clone());
+            bar.enable_steady_tick(Duration::from_millis(100));
+            let (client, info) = (&client, &info);
+            let (auth_token, server) = (auth_token.as_str(), server.as_str());
+            async move {
+                let name = project
+                    .as_ref()
+                    .map_or_else(|_| dir.display().to_string(), |p| p.name.clone());
+                bar.set_prefix(name.clone());
+                let outcome = match project {
+                    Ok(project) => {
+                        deploy_one(client, info, server, project, args, auth_token, &bar).await
+                    }
+                    Err(e) => Err(e),
+                };
+                match &outcome {
+                    Ok(true) => bar.finish_with_message("✅ deployed"),
+                    Ok(false) => bar.finish_with_message("✅ unchanged"),
+                    Err(_) => bar.finish_with_message("❌ failed"),
+                }
+                (index, name, outcome)
+            }
+        });
+    let mut outcomes: Vec<_> = stream::iter(deploys)
+        .buffer_unordered(CONCURRENT_DEPLOYS)
+        .collect()
+        .await;
+    outcomes.sort_by_key(|(index, _, _)| *index);
+    let outcomes: Vec<_> = outcomes
+        .into_iter()
+        .map(|(_, name, outcome)| (name, outcome))
+        .collect();
+
+    let server_host = crate::extract_server_host(&server);
+    println!();
+    println!("{:<24} {:<9} DETAIL", "FUNCTION", "RESULT");
+    for (name, outcome) in &outcomes {
+        match outcome {
+            Ok(uploaded) => println!(
+                "{:<24} {:<9} {}",
+                name,
+                if *uploaded { "deployed" } else { "unchanged" },
+                crate::format_function_url(name, &server_host)
+            ),
+            Err(e) => println!("{:<24} {:<9} {:#}", name, "failed", e),
+        }
+    }
+    let count = |wanted: fn(&Result<bool>) -> bool| {
+        outcomes
+            .iter()
+            .filter(|(_, outcome)| wanted(outcome))
+            .count()
+    };
+    let (deployed, unchanged) = (
+        count(|o| matches!(o, Ok(true))),
+        count(|o| matches!(o, Ok(false))),
+    );
+    println!();
+    println!(
+        "{deployed} deployed, {unchanged} unchanged, {} failed",
+        outcomes.len() - deployed - unchanged
+    );
+    // Exit with the code of the first failure
+    match outcomes.into_iter().find_map(|(_, outcome)| outcome.err()) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// The server every project deploys to: --server, else the one their
+/// `faasta.toml` files agree on
+fn server(args: &DeployArgs, projects: &[(PathBuf, Result<Project>)]) -> Result<String> {
+    if let Some(server) = &args.server {
+        return Ok(server.clone());
+    }
+    let mut servers: Vec<&str> = projects
+        .iter()
+        .filter_map(|(_, project)| project.as_ref().ok()?.config.function.server.as_deref())
+        .collect();
+    servers.sort_unstable();
+    servers.dedup();
+    match servers.as_slice() {
+        [] => Ok(DEFAULT_SERVER.to_string()),
+        [server] => Ok(server.to_string()),
+        _ => Err(errors::failure(
+            &errors::INVALID_INPUT,
+            format!(
+                "The projects deploy to different servers ({}); pick one with --server",
+                servers.join(", ")
+            ),
+        )),
+    }
+}
+
+/// Read the project in `dir` and everything it deploys
+fn prepare(dir: &Path) -> Result<Project> {
+    let config = project::load(dir)
+        .and_then(|config| config.validate().map(|()| config))
+        .map_err(invalid)?;
+    // Built components are deployed, so projects that componentize as part of the
+    // deploy are deployed one at a time
+    if config.function.wasm.is_none() && componentize::detect(dir)?.is_some() {
+        bail!("JavaScript and Python projects are deployed on their own");
+    }
+    let (wasm_path, package_name) = match &config.function.wasm {
+        Some(wasm) => (dir.join(wasm), None),
+        None => {
+            let package = cargo_packages(dir)?
+                .into_iter()
+                .find(|package| same_dir(&package.dir, dir))
+                .ok_or_else(|| anyhow!("{} isn't a cargo package", dir.display()))?;
+            (
+                crate::compiled_wasm_path(&package.target_directory, &package.name),
+                Some(package.name),
+            )
+        }
+    };
+    let name = config
+        .function
+        .name
+        .clone()
+        .or(package_name)
+        .or_else(|| Some(wasm_path.file_stem()?.to_str()?.to_string()))
+        .ok_or_else(|| anyhow!("Could not determine the function name of {}", dir.display()))?;
+
+    // Only the header is read for now, so projects waiting their turn don't hold
+    // their components in memory
+    let mut header = Vec::new();
+    fs::File::open(&wasm_path)
+        .and_then(|file| file.take(8).read_to_end(&mut header))
+        .map_err(|e| {
+            errors::failure(
+                &errors::ARTIFACT_MISSING,
+                format!(
+                    "Failed to read {}: {e}; run 'cargo faasta build' first",
+                    wasm_path.display()
+                ),
+            )
+        })?;
+    if !crate::is_component(&header) {
+        return Err(errors::failure(
+            &errors::INVALID_COMPONENT,
+            format!("{} is not a WebAssembly component", wasm_path.display()),
+        ));
+    }
+    let routes = config
+        .routes
+        .clone()
+        .map(|routes| crate::read_route_handlers(dir, routes))
+        .transpose()
+        .context("Invalid [[routes]] in faasta.toml")
+        .map_err(invalid)?;
+    let metadata = crate::function_metadata(&config.function, routes.as_deref());
+    if let Err(e) = metadata.validate() {
+        return Err(invalid(anyhow!(
+            "Invalid function metadata in faasta.toml: {e}"
+        )));
+    }
+    Ok(Project {
+        dir: dir.to_path_buf(),
+        name,
+        wasm_path,
+        config,
+        routes,
+        metadata,
+    })
+}
+
+/// Publish `project`, then its routes, static assets and policies, as a single
+/// deploy does. Returns whether it was uploaded, which it isn't when unchanged.
+async fn deploy_one(
+    client: &faasta_interface::FunctionServiceClient,
+    info: &faasta_interface::ServerInfo,
+    server: &str,
+    project: Project,
+    args: &DeployArgs,
+    auth_token: &str,
+    bar: &ProgressBar,
+) -> Result<bool> {
+    let Project {
+        dir,
+        name,
+        wasm_path,
+        config,
+        routes,
+        metadata,
+    } = project;
+    let wasm =
+        fs::read(&wasm_path).with_context(|| format!("Failed to read {}", wasm_path.display()))?;
+    let team = args.team.clone().or(config.function.team);
+    let keep_warm = args.keep_warm().or(config.function.keep_warm);
+    let settings = format!("team={team:?} keep-warm={keep_warm:?} provenance=");
+    let fingerprint = fingerprint::of(&dir, &settings, &wasm).ok();
+    if let Some(fingerprint) = fingerprint.as_ref().filter(|_| !args.force) {
+        if Some(fingerprint) == fingerprint::last_deploy(server, &name).as_ref()
+            && fingerprint::is_served(client, &name, auth_token, fingerprint).await
+        {
+            return Ok(false);
+        }
+    }
+
+    bar.set_message("uploading route handlers...");
+    crate::stage_routes(client, routes, &name, auth_token).await?;
+    bar.set_message("uploading...");
+    crate::publish_function(
+        client,
+        info,
+        wasm,
+        None,
+        name.clone(),
+        team,
+        keep_warm,
+        Some(metadata),
+        auth_token.to_string(),
+    )
+    .await?
+    .map_err(crate::server_error)?;
+    bar.set_message("uploading static assets...");
+    crate::publish_static_assets(client, &dir, &name, auth_token).await?;
+    bar.set_message("applying settings...");
+    crate::set_cors_policy(client, config.cors, &name, auth_token).await?;
+    crate::set_jwt_auth(client, config.auth, &name, auth_token).await?;
+    crate::set_transforms(client, config.transforms, &name, auth_token).await?;
+    if let Some(fingerprint) = fingerprint {
+        if let Err(e) = fingerprint::record(server, &name, fingerprint) {
+            bar.println(format!(
+                "Warning: failed to remember the deploy of '{name}': {e:#}"
+            ));
+        }
+    }
+    Ok(true)
+}
+
+/// Directories of the function crates in the current cargo workspace
+fn workspace_functions() -> Result<Vec<PathBuf>> {
+    let dir = std::env::current_dir()?;
+    Ok(cargo_packages(&dir)?
+        .into_iter()
+        .filter(|package| package.cdylib)
+        .map(|package| package.dir)
+        .collect())
+}
+
+/// Packages of the cargo workspace `dir` is in
+fn cargo_packages(dir: &Path) -> Result<Vec<Package>> {
+    let output = std::process::Command::new("cargo")
+        .args(["metadata", "--format-version=1", "--no-deps"])
+        .current_dir(dir)
+        .output()
+        .context("Failed to run cargo metadata")?;
+    if !output.status.success() {
+        bail!(
+            "cargo metadata failed in {}: {}",
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let metadata: serde_json::Value =
+        serde_json::from_slice(&output.stdout).context("Failed to parse cargo metadata")?;
+    let target_directory = metadata
+        .get("target_directory")
+        .and_then(serde_json::Value::as_str)
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("No 'target_directory' found in cargo metadata"))?;
+    let packages = metadata
+        .get("packages")
+        .and_then(serde_json::Value::as_array)
+        .ok_or_else(|| anyhow!("No 'packages' found in cargo metadata"))?;
+    Ok(packages
+        .iter()
+        .filter_map(|package| {
+            let manifest_path = package.get("manifest_path")?.as_str()?;
+            let cdylib = package
+                .get("targets")?
+                .as_array()?
+                .iter()
+                .filter_map(|target| target.get("crate_types")?.as_array())
+                .flatten()
+                .any(|kind| kind.as_str() == Some("cdylib"));
+            Some(Package {
+                name: package.get("name")?.as_str()?.to_string(),
+                dir: Path::new(manifest_path).parent()?.to_path_buf(),
+                target_directory: target_directory.clone(),
+                cdylib,
+            })
+        })
+        .collect())
+}
+
+/// `error` with the code of an invalid project
+fn invalid(error: anyhow::Error) -> anyhow::Error {
+    errors::failure(&errors::PROJECT_INVALID, format!("{error:#}"))
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
@@ -0,0 +1,309 @@
+//! Exit codes and the catalog of error codes.
+//!
+//! Every failure exits with the code of its class, so CI scripts can tell a
+//! rejected login from a flaky network without parsing messages, and prints a
+//! stable error code (`FAASTA-E0201`) that `cargo faasta explain` describes and
+//! support can search for. Errors the server returns map onto the catalog by
+//! their [`FaastaError`] variant.
+
+use faasta_interface::FaastaError;
+use std::fmt;
+use std::io;
+
+/// Class of a failure, which decides the exit code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureClass {
+    /// Anything not in another class, e.g. an unreadable config file
+    Other,
+    /// Missing, invalid or insufficient credentials
+    Auth,
+    /// The server couldn't be reached, or the connection broke
+    Network,
+    /// The project couldn't be built into a component
+    Build,
+    /// The input, project or component was refused as invalid
+    Validation,
+    /// A quota of the account was exhausted
+    Quota,
+    /// The server failed to handle the request
+    Server,
+}
+
+impl FailureClass {
+    /// Exit code of the class. 2 is left to clap's usage errors.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureClass::Other => 1,
+            FailureClass::Auth => 3,
+            FailureClass::Network => 4,
+            FailureClass::Build => 5,
+            FailureClass::Validation => 6,
+            FailureClass::Quota => 7,
+            FailureClass::Server => 8,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FailureClass::Other => "other",
+            FailureClass::Auth => "auth",
+            FailureClass::Network => "network",
+            FailureClass::Build => "build",
+            FailureClass::Validation => "validation",
+            FailureClass::Quota => "quota",
+            FailureClass::Server => "server",
+        }
+    }
+}
+
+/// An entry of the error catalog
+#[derive(Debug, PartialEq, Eq)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub class: FailureClass,
+    /// What went wrong and what to do about it
+    pub description: &'static str,
+}
+
+pub const UNCLASSIFIED: ErrorCode = ErrorCode {
+    code: "FAASTA-E0001",
+    class: FailureClass::Other,
+    description: "The command failed for a reason without a more specific code. \
+                  The message printed above says what went wrong.",
+};
+pub const CONFIG: ErrorCode = ErrorCode {
+    code: "FAASTA-E0002",
+    class: FailureClass::Other,
+    description: "The CLI's configuration or credential store (~/.faasta) couldn't be read \
+                  or written, or holds an invalid setting. Check its permissions and contents.",
+};
+pub const NOT_LOGGED_IN: ErrorCode = ErrorCode {
+    code: "FAASTA-E0101",
+    class: FailureClass::Auth,
+    description: "No credentials are stored. Run 'cargo faasta login', or set FAASTA_API_KEY.",
+};
+pub const AUTH_FAILED: ErrorCode = ErrorCode {
+    code: "FAASTA-E0102",
+    class: FailureClass::Auth,
+    description: "The token or API key was refused as missing, invalid, expired or revoked. \
+                  Run 'cargo faasta login' again, or issue a new API key.",
+};
+pub const PERMISSION_DENIED: ErrorCode = ErrorCode {
+    code: "FAASTA-E0103",
+    class: FailureClass::Auth,
+    description: "You're signed in, but your account or role may not do this, e.g. change a \
+                  function someone else owns.",
+};
+pub const CONNECT_FAILED: ErrorCode = ErrorCode {
+    code: "FAASTA-E0201",
+    class: FailureClass::Network,
+    description: "The server couldn't be reached. Check the address, your network and proxy, \
+                  and the server's certificate (--ca-cert, --pin-sha256).",
+};
+pub const CONNECTION_LOST: ErrorCode = ErrorCode {
+    code: "FAASTA-E0202",
+    class: FailureClass::Network,
+    description: "The connection to the server broke or timed out during the call. \
+                  Retrying usually helps.",
+};
+pub const PROJECT_INVALID: ErrorCode = ErrorCode {
+    code: "FAASTA-E0301",
+    class: FailureClass::Build,
+    description: "The project couldn't be read: cargo metadata failed, the package wasn't \
+                  found, or faasta.toml is invalid.",
+};
+pub const BUILD_FAILED: ErrorCode = ErrorCode {
+    code: "FAASTA-E0302",
+    class: FailureClass::Build,
+    description: "Building the component failed. The compiler output above says why.",
+};
+pub const ARTIFACT_MISSING: ErrorCode = ErrorCode {
+    code: "FAASTA-E0303",
+    class: FailureClass::Build,
+    description: "The compiled component (or a file deployed with it) couldn't be found or \
+                  read. Run 'cargo faasta build' first, or pass its path with --wasm.",
+};
+pub const INVALID_INPUT: ErrorCode = ErrorCode {
+    code: "FAASTA-E0401",
+    class: FailureClass::Validation,
+    description: "An argument or file was refused as invalid, e.g. a function name or a \
+                  definitions file.",
+};
+pub const INVALID_COMPONENT: ErrorCode = ErrorCode {
+    code: "FAASTA-E0402",
+    class: FailureClass::Validation,
+    description: "The upload isn't a WebAssembly component of the wasi:http/proxy world. \
+                  Core modules can be wrapped with 'wasm-tools component new'.",
+};
+pub const TOO_LARGE: ErrorCode = ErrorCode {
+    code: "FAASTA-E0403",
+    class: FailureClass::Validation,
+    description: "The component is larger than the server accepts. Build in release mode \
+                  with strip, LTO and opt-level \"z\", or run wasm-opt -Oz on it.",
+};
+pub const NOT_FOUND: ErrorCode = ErrorCode {
+    code: "FAASTA-E0404",
+    class: FailureClass::Validation,
+    description: "The function, team, key or other object named doesn't exist on the server.",
+};
+pub const QUOTA_EXCEEDED: ErrorCode = ErrorCode {
+    code: "FAASTA-E0501",
+    class: FailureClass::Quota,
+    description: "The account already has as many of something as the server allows, e.g. \
+                  functions. Remove one, or ask the server's operator for a higher limit.",
+};
+pub const SERVER_ERROR: ErrorCode = ErrorCode {
+    code: "FAASTA-E0601",
+    class: FailureClass::Server,
+    description: "The server failed to handle the request. Its operator can look up what \
+                  went wrong under the reference printed with the error.",
+};
+
+/// Every error code, in order
+pub const CATALOG: &[&ErrorCode] = &[
+    &UNCLASSIFIED,
+    &CONFIG,
+    &NOT_LOGGED_IN,
+    &AUTH_FAILED,
+    &PERMISSION_DENIED,
+    &CONNECT_FAILED,
+    &CONNECTION_LOST,
+    &PROJECT_INVALID,
+    &BUILD_FAILED,
+    &ARTIFACT_MISSING,
+    &INVALID_INPUT,
+    &INVALID_COMPONENT,
+    &TOO_LARGE,
+    &NOT_FOUND,
+    &QUOTA_EXCEEDED,
+    &SERVER_ERROR,
+];
+
+/// Look up a code, given in full (`FAASTA-E0201`) or by number (`E0201`, `0201`)
+pub fn lookup(code: &str) -> Option<&'static ErrorCode> {
+    let code = code.trim().to_ascii_uppercase();
+    let number = code.trim_start_matches("FAASTA-").trim_start_matches('E');
+    CATALOG
+        .iter()
+        .copied()
+        .find(|entry| entry.code.ends_with(&format!("E{number}")))
+}
+
+/// Code of an error the server returned
+pub fn for_server_error(error: &FaastaError) -> &'static ErrorCode {
+    match error {
+        FaastaError::AuthFailed(_) => &AUTH_FAILED,
+        FaastaError::PermissionDenied(_) => &PERMISSION_DENIED,
+        FaastaError::QuotaExceeded { .. } => &QUOTA_EXCEEDED,
+        FaastaError::InvalidComponent { .. } => &INVALID_COMPONENT,
+        FaastaError::NotFound(_) => &NOT_FOUND,
+        FaastaError::InvalidInput(_) => &INVALID_INPUT,
+        FaastaError::Internal { .. } => &SERVER_ERROR,
+        FaastaError::TooLarge { .. } => &TOO_LARGE,
+    }
+}
+
+/// A failure with its error code, carried in an [`anyhow::Error`] up to where
+/// the command exits
+#[derive(Debug)]
+pub struct Failure {
+    pub code: &'static ErrorCode,
+    message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// A [`Failure`] with `code`, as an error to return
+pub fn failure(code: &'static ErrorCode, message: impl Into<String>) -> anyhow::Error {
+    anyhow::Error::new(Failure {
+        code,
+        message: message.into(),
+    })
+}
+
+/// Code of an error a command failed with: the code it was given, or one
+/// derived from the typed errors in its chain
+pub fn classify(error: &anyhow::Error) -> &'static ErrorCode {
+    for cause in error.chain() {
+        if let Some(failure) = cause.downcast_ref::<Failure>() {
+            return failure.code;
+        }
+        if let Some(error) = cause.downcast_ref::<FaastaError>() {
+            return for_server_error(error);
+        }
+        if cause.downcast_ref::<tarpc::client::RpcError>().is_some() {
+            return &CONNECTION_LOST;
+        }
+        if let Some(error) = cause.downcast_ref::<io::Error>() {
+            if error.kind() == io::ErrorKind::ConnectionRefused {
+                return &CONNECT_FAILED;
+            }
+            if matches!(
+                error.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::TimedOut
+            ) {
+                return &CONNECTION_LOST;
+            }
+        }
+    }
+    &UNCLASSIFIED
+}
+
+/// Print the error code of a failure and exit with the code of its class
+pub fn exit(code: &ErrorCode) -> ! {
+    eprintln!(
+        "error code: {} (run 'cargo faasta explain {}' for details)",
+        code.code, code.code
+    );
+    std::process::exit(code.class.exit_code())
+}
+
+/// [`exit`] with the code of `error`
+pub fn exit_with(error: &anyhow::Error) -> ! {
+    exit(classify(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_unique_and_found_by_number() {
+        for (i, entry) in CATALOG.iter().enumerate() {
+            assert!(CATALOG[i + 1..]
+                .iter()
+                .all(|other| other.code != entry.code));
+            assert_eq!(lookup(entry.code), Some(*entry));
+        }
+        assert_eq!(lookup("e0201"), Some(&CONNECT_FAILED));
+        assert_eq!(lookup("0501"), Some(&QUOTA_EXCEEDED));
+        assert_eq!(lookup("FAASTA-E9999"), None);
+    }
+
+    #[test]
+    fn test_server_errors_keep_their_class_through_context() {
+        let error = anyhow::Error::new(FaastaError::QuotaExceeded {
+            quota: "projects".to_string(),
+            limit: 10,
+        })
+        .context("Failed to deploy");
+        assert_eq!(classify(&error).class.exit_code(), 7);
+
+        let error = failure(&BUILD_FAILED, "Build failed").context("Failed to deploy");
+        assert_eq!(classify(&error), &BUILD_FAILED);
+        assert_eq!(classify(&anyhow::anyhow!("oops")), &UNCLASSIFIED);
+
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert_eq!(classify(&anyhow::Error::new(refused)), &CONNECT_FAILED);
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert_eq!(classify(&anyhow::Error::new(reset)), &CONNECTION_LOST);
+    }
+}
@@ -0,0 +1,170 @@
+//! Starter configuration for managing existing functions with Terraform or
+//! OpenTofu through the server's `/v1/functions` management API.
+
+use faasta_interface::{LogLevelSetting, RedactionRules, TEAM_OWNER_PREFIX};
+use std::fmt::Write;
+
+/// What is exported of one function
+#[derive(Clone, Debug)]
+pub struct ExportedFunction {
+    pub name: String,
+    pub owner: String,
+    pub log_level: LogLevelSetting,
+    pub redaction: RedactionRules,
+}
+
+/// Terraform configuration importing `functions` as `faasta_function` resources
+/// managed through the API at `endpoint`
+pub fn terraform(endpoint: &str, functions: &[ExportedFunction]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# Generated by `cargo faasta export --tf`.");
+    let _ = writeln!(
+        out,
+        "# The import blocks adopt the functions that already exist; once `terraform apply`"
+    );
+    let _ = writeln!(
+        out,
+        "# has run they can be removed. Point each `source` at the component to deploy."
+    );
+    let _ = writeln!(out);
+    let _ = writeln!(out, "terraform {{");
+    let _ = writeln!(out, "  required_providers {{");
+    let _ = writeln!(out, "    faasta = {{");
+    let _ = writeln!(
+        out,
+        "      # Set this to where the provider you use is published"
+    );
+    let _ = writeln!(out, "      source = \"faasta/faasta\"");
+    let _ = writeln!(out, "    }}");
+    let _ = writeln!(out, "  }}");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "provider \"faasta\" {{");
+    let _ = writeln!(out, "  endpoint = {}", hcl_string(endpoint));
+    let _ = writeln!(out, "  # The API key is read from FAASTA_API_KEY");
+    let _ = writeln!(out, "}}");
+
+    for function in functions {
+        let id = identifier(&function.name);
+        let _ = writeln!(out);
+        let _ = writeln!(out, "import {{");
+        let _ = writeln!(out, "  to = faasta_function.{id}");
+        let _ = writeln!(out, "  id = {}", hcl_string(&function.name));
+        let _ = writeln!(out, "}}");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "resource \"faasta_function\" \"{id}\" {{");
+        let _ = writeln!(out, "  name   = {}", hcl_string(&function.name));
+        let _ = writeln!(
+            out,
+            "  source = \"${{path.module}}/{}.wasm\"",
+            escape(&function.name)
+        );
+        if let Some(team) = function.owner.strip_prefix(TEAM_OWNER_PREFIX) {
+            let _ = writeln!(out, "  team   = {}", hcl_string(team));
+        }
+        // A temporarily verbose level reverts on its own, so it isn't pinned here
+        if function.log_level.expires_at.is_none() {
+            let _ = writeln!(
+                out,
+                "\n  log_level = {}",
+                hcl_string(&function.log_level.level.to_string())
+            );
+        }
+        let rules = &function.redaction;
+        if !rules.headers.is_empty() || !rules.patterns.is_empty() {
+            let _ = writeln!(out, "\n  redaction = {{");
+            let _ = writeln!(out, "    headers  = {}", hcl_list(&rules.headers));
+            let _ = writeln!(out, "    patterns = {}", hcl_list(&rules.patterns));
+            let _ = writeln!(out, "  }}");
+        }
+        let _ = writeln!(out, "}}");
+    }
+    out
+}
+
+/// Resource name for a function: letters, digits, `_` and `-`, not starting
+/// with a digit
+fn identifier(name: &str) -> String {
+    let id: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        id
+    } else {
+        format!("fn_{id}")
+    }
+}
+
+fn hcl_string(value: &str) -> String {
+    format!("\"{}\"", escape(value))
+}
+
+fn hcl_list(values: &[String]) -> String {
+    let items: Vec<String> = values.iter().map(|value| hcl_string(value)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// Escape `value` for a quoted HCL string, including template sequences
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '$' | '%' if chars.peek() == Some(&'{') => {
+                escaped.push(c);
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faasta_interface::LogLevel;
+
+    #[test]
+    fn test_escape_keeps_patterns_literal() {
+        assert_eq!(escape(r#"(?i)bearer\s+"x""#), r#"(?i)bearer\\s+\"x\""#);
+        assert_eq!(escape("${var} %{if} $5"), "$${var} %%{if} $5");
+        assert_eq!(identifier("9lives"), "fn_9lives");
+        assert_eq!(identifier("hello-world"), "hello-world");
+    }
+
+    #[test]
+    fn test_terraform_imports_each_function() {
+        let tf = terraform(
+            "https://faasta.xyz",
+            &[ExportedFunction {
+                name: "hello".to_string(),
+                owner: "team:acme".to_string(),
+                log_level: LogLevelSetting {
+                    level: LogLevel::Warn,
+                    expires_at: None,
+                },
+                redaction: RedactionRules {
+                    headers: vec!["x-secret".to_string()],
+                    patterns: vec![],
+                },
+            }],
+        );
+        assert!(tf.contains("  to = faasta_function.hello\n  id = \"hello\""));
+        assert!(tf.contains("  team   = \"acme\""));
+        assert!(tf.contains("  log_level = \"warn\""));
+        assert!(tf.contains("    headers  = [\"x-secret\"]"));
+    }
+}
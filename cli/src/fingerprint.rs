@@ -0,0 +1,183 @@
+//! Skipping deploys that would change nothing.
+//!
+//! After a successful deploy the CLI remembers, per server and function, a digest
+//! of the project's sources and one of the component it uploaded. The sources are
+//! every file under the project directory but build output and hidden directories,
+//! which covers `Cargo.toml`, `faasta.toml` and `static/`, plus the `Cargo.lock` of
+//! the workspace and the deploy's settings. When both digests are the same on the
+//! next deploy and the server still serves that component, nothing is built or
+//! uploaded; `--force` deploys anyway. Unpublishing, renaming or transferring a
+//! function forgets its last deploy.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// File in the config directory holding the fingerprint of each function's last deploy
+const STATE_FILE: &str = "deploys.json";
+/// Directories holding build output or dependencies rather than sources
+const SKIPPED_DIRS: [&str; 3] = ["target", "node_modules", "__pycache__"];
+
+/// What a deploy uploaded, and from what
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// Digest of the project's files, its Cargo.lock and the deploy's settings
+    pub sources: String,
+    /// Digest of the component
+    pub artifact: String,
+}
+
+/// Fingerprint of deploying `wasm` from the project in `project_dir` with
+/// `settings`, the options that change what's deployed
+pub fn of(project_dir: &Path, settings: &str, wasm: &[u8]) -> Result<Fingerprint> {
+    let mut hasher = Sha256::new();
+    hasher.update(settings.as_bytes());
+    hash_dir(&mut hasher, project_dir, Path::new(""))?;
+    // The lock file of a workspace lives above its members
+    if let Some(lock) = project_dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.is_file())
+    {
+        hash_file(&mut hasher, Path::new("Cargo.lock"), &lock)?;
+    }
+    Ok(Fingerprint {
+        sources: hex::encode(hasher.finalize()),
+        artifact: digest(wasm),
+    })
+}
+
+/// Hex SHA-256 digest of `data`, for settings that are documents
+pub fn digest(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Add the files under `dir` to `hasher`, in name order, each under its path
+/// relative to the project
+fn hash_dir(hasher: &mut Sha256, dir: &Path, relative: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name();
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            let skipped = name
+                .to_str()
+                .is_some_and(|name| name.starts_with('.') || SKIPPED_DIRS.contains(&name));
+            if !skipped {
+                hash_dir(hasher, &path, &relative.join(&name))?;
+            }
+        } else if file_type.is_file() {
+            hash_file(hasher, &relative.join(&name), &path)?;
+        }
+    }
+    Ok(())
+}
+
+fn hash_file(hasher: &mut Sha256, relative: &Path, path: &Path) -> Result<()> {
+    let contents = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    hasher.update(relative.to_string_lossy().as_bytes());
+    hasher.update([0]);
+    hasher.update((contents.len() as u64).to_le_bytes());
+    hasher.update(&contents);
+    Ok(())
+}
+
+/// The fingerprint of the last successful deploy of `name` to `server`
+pub fn last_deploy(server: &str, name: &str) -> Option<Fingerprint> {
+    load(&crate::get_config_dir().join(STATE_FILE)).remove(&key(server, name))
+}
+
+/// Remember `fingerprint` as that of the last deploy of `name` to `server`
+pub fn record(server: &str, name: &str, fingerprint: Fingerprint) -> Result<()> {
+    let path = crate::get_config_dir().join(STATE_FILE);
+    let mut deploys = load(&path);
+    deploys.insert(key(server, name), fingerprint);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(&deploys)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Forget the last deploy of `name` to `server`, once the function is gone from
+/// under that name
+pub fn forget(server: &str, name: &str) -> Result<()> {
+    let path = crate::get_config_dir().join(STATE_FILE);
+    let mut deploys = load(&path);
+    if deploys.remove(&key(server, name)).is_none() {
+        return Ok(());
+    }
+    fs::write(&path, serde_json::to_string_pretty(&deploys)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Whether the server still serves the component of `fingerprint` as `name`. It
+/// may not when the function was redeployed from elsewhere, rolled back or
+/// unpublished by someone else; an unreachable server counts as not.
+pub async fn is_served(
+    client: &faasta_interface::FunctionServiceClient,
+    name: &str,
+    auth_token: &str,
+    fingerprint: &Fingerprint,
+) -> bool {
+    let status = client
+        .function_status(
+            tarpc::context::current(),
+            name.to_string(),
+            auth_token.to_string(),
+        )
+        .await;
+    let expected = format!("sha256:{}", fingerprint.artifact);
+    matches!(status, Ok(Ok(status)) if status.digest.as_deref() == Some(expected.as_str()))
+}
+
+fn key(server: &str, name: &str) -> String {
+    format!("{server}/{name}")
+}
+
+/// Fingerprints by deploy, none if the file is missing or unreadable
+fn load(path: &Path) -> BTreeMap<String, Fingerprint> {
+    fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources_ignore_build_output_but_not_the_lock_file() {
+        let root = std::env::temp_dir().join(format!("faasta-fingerprint-{}", std::process::id()));
+        let project = root.join("hello");
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::create_dir_all(project.join("target")).unwrap();
+        fs::write(project.join("src/lib.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("Cargo.lock"), "version = 4").unwrap();
+        let before = of(&project, "", b"wasm").unwrap();
+
+        fs::write(project.join("target/out.wasm"), "built").unwrap();
+        assert_eq!(of(&project, "", b"wasm").unwrap(), before);
+        assert_ne!(of(&project, "team=acme", b"wasm").unwrap(), before);
+        assert_ne!(
+            of(&project, "", b"other").unwrap().artifact,
+            before.artifact
+        );
+
+        fs::write(root.join("Cargo.lock"), "version = 4\n[[package]]").unwrap();
+        let locked = of(&project, "", b"wasm").unwrap();
+        assert_ne!(locked.sources, before.sources);
+        fs::write(project.join("src/lib.rs"), "fn main() { }").unwrap();
+        assert_ne!(of(&project, "", b"wasm").unwrap().sources, locked.sources);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
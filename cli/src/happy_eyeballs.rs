@@ -0,0 +1,147 @@
+//! Racing connections across all of a server's addresses, as Happy Eyeballs
+//! (RFC 8305) does. Addresses are tried in the resolver's order with the families
+//! interleaved, a new attempt starting whenever the previous one fails or hasn't
+//! succeeded within [`ATTEMPT_DELAY`]. The first connection made wins, so a host
+//! with a broken IPv6 route, or one reachable only over IPv6, connects quickly.
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// How long an attempt gets before the next address is tried alongside it
+pub const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Reorder `addrs` so the address families alternate, starting with the family
+/// of the first address and otherwise keeping the resolver's preference
+pub fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let prefer_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == prefer_v6);
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    preferred.reverse();
+    other.reverse();
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Connect to the first of `addrs` that accepts, racing them as described above.
+/// When all fail, the error of the first attempt `is_transient` doesn't excuse is
+/// returned, as it is likely the one to act on, or else the last error.
+pub async fn race<T, E, F, Fut>(
+    addrs: &[SocketAddr],
+    is_transient: impl Fn(&E) -> bool,
+    mut connect: F,
+) -> Result<T, E>
+where
+    F: FnMut(SocketAddr) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    assert!(!addrs.is_empty(), "no addresses to connect to");
+    let mut remaining = addrs.iter().copied();
+    let mut attempts = FuturesUnordered::new();
+    let mut failure: Option<E> = None;
+
+    loop {
+        if attempts.is_empty() {
+            match remaining.next() {
+                Some(addr) => attempts.push(connect(addr)),
+                None => return Err(failure.expect("every attempt failed")),
+            }
+        }
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(connection) => return Ok(connection),
+                Err(e) => {
+                    let keep_previous = failure.as_ref().is_some_and(|previous| !is_transient(previous));
+                    if !keep_previous {
+                        failure = Some(e);
+                    }
+                    // A failed attempt lets the next one start right away
+                    if let Some(addr) = remaining.next() {
+                        attempts.push(connect(addr));
+                    }
+                }
+            },
+            _ = tokio::time::sleep(ATTEMPT_DELAY), if remaining.len() > 0 => {
+                if let Some(addr) = remaining.next() {
+                    attempts.push(connect(addr));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn addrs(list: &[&str]) -> Vec<SocketAddr> {
+        list.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_families_are_interleaved() {
+        let ordered = interleave(addrs(&[
+            "[2001:db8::1]:4433",
+            "[2001:db8::2]:4433",
+            "192.0.2.1:4433",
+        ]));
+        assert_eq!(
+            ordered,
+            addrs(&["[2001:db8::1]:4433", "192.0.2.1:4433", "[2001:db8::2]:4433"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_a_stalled_address_does_not_hold_up_the_next() {
+        let list = addrs(&["[2001:db8::1]:4433", "192.0.2.1:4433"]);
+        let start = Instant::now();
+        let winner = race(
+            &list,
+            |_: &String| true,
+            |addr| async move {
+                if addr.is_ipv6() {
+                    // A black-holed route never answers
+                    std::future::pending::<()>().await;
+                }
+                Ok::<_, String>(addr)
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(winner, list[1]);
+        assert!(start.elapsed() >= ATTEMPT_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_the_error_to_act_on_is_reported() {
+        let list = addrs(&["[2001:db8::1]:4433", "192.0.2.1:4433"]);
+        let error = race(
+            &list,
+            |e: &String| e == "timeout",
+            |addr| async move {
+                Err::<(), _>(
+                    if addr.is_ipv6() {
+                        "bad certificate"
+                    } else {
+                        "timeout"
+                    }
+                    .to_string(),
+                )
+            },
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error, "bad certificate");
+    }
+}
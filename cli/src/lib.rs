@@ -1,6 +1,18 @@
 //! Library for cargo-faasta CLI commands.
 
 pub mod auth;
+pub mod componentize;
+pub mod credentials;
+pub mod errors;
+pub mod export;
 pub mod github_oauth;
+pub mod happy_eyeballs;
 pub mod init;
+pub mod optimize;
+pub mod preinit;
+pub mod project;
+pub mod proxy;
+pub mod registry;
 pub mod run;
+pub mod tls;
+pub mod transport;
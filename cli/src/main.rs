@@ -1,23 +1,45 @@
 #![warn(unused_extern_crates)]
+mod completions;
+mod componentize;
+mod credentials;
+mod deploy_many;
+mod errors;
+mod export;
+mod fingerprint;
 mod github_oauth;
+mod happy_eyeballs;
 mod init;
+mod optimize;
+mod preflight;
+mod preinit;
+mod project;
+mod proxy;
+mod registry;
 mod run;
+mod tls;
+mod transport;
 
 use anyhow::Error;
 use serde::{Deserialize, Serialize};
 use std::fs;
 // Removed unused imports
 use std::fmt;
+use std::io::Write;
 use std::path::PathBuf;
-use std::process::exit;
+use std::time::Duration;
 // Removed unused imports
 
 const DEFAULT_INVOKE_URL: &str = "https://faasta.xyz/";
+/// Server deployed to unless a flag or faasta.toml names another
+const DEFAULT_SERVER: &str = "faasta.xyz:4433";
 const MAX_PROJECTS_PER_USER: usize = 10;
 const CONFIG_DIR: &str = ".faasta";
 const CONFIG_FILE: &str = "config.json";
+/// Environment variable holding a server-issued API key, used instead of stored credentials
+const API_KEY_ENV: &str = "FAASTA_API_KEY";
 
 #[derive(Debug)]
+#[allow(dead_code)]
 enum CustomError {
     Io(std::io::Error),
     Reqwest(reqwest::Error),
@@ -47,7 +69,24 @@ impl fmt::Display for CustomError {
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 struct FaastaConfig {
     github_username: Option<String>,
+    /// Plaintext token written by older CLI versions, moved into the credential store on load
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     github_token: Option<String>,
+    /// PEM bundle of CAs trusted for the server's certificate (`--ca-cert`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ca_cert: Option<PathBuf>,
+    /// Fingerprint of the only server certificate accepted (`--pin-sha256`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pin_sha256: Option<String>,
+    /// Client certificate chain for servers that require one (`--client-cert`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_cert: Option<PathBuf>,
+    /// Private key of the client certificate (`--client-key`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_key: Option<PathBuf>,
+    /// Proxy the server is reached through (`--proxy`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proxy: Option<String>,
 }
 
 /// Get the configuration directory
@@ -95,14 +134,99 @@ fn save_config(config: &FaastaConfig) -> Result<(), Error> {
     Ok(())
 }
 
+/// Load the stored GitHub username and token.
+/// An API key in `FAASTA_API_KEY` takes precedence over anything stored.
+/// A plaintext token left in the config file is migrated into the credential store.
+async fn load_credentials() -> Result<Option<(String, String)>, Error> {
+    let mut config = load_config()?;
+    if let Ok(key) = std::env::var(API_KEY_ENV) {
+        // The server knows which user a key belongs to, so the username is optional
+        return Ok(Some((config.github_username.unwrap_or_default(), key)));
+    }
+    let Some(username) = config.github_username.clone() else {
+        return Ok(None);
+    };
+    let config_dir = get_config_dir();
+
+    if let Some(token) = config.github_token.take() {
+        credentials::store_token(&config_dir, &username, &token).await?;
+        save_config(&config)?;
+        return Ok(Some((username, token)));
+    }
+
+    Ok(credentials::load_token(&config_dir, &username)
+        .await?
+        .map(|token| (username, token)))
+}
+
+/// Store the token in the credential store and remember the username in the config file
+async fn save_credentials(
+    config: &mut FaastaConfig,
+    username: String,
+    token: &str,
+) -> Result<(), Error> {
+    credentials::store_token(&get_config_dir(), &username, token).await?;
+    config.github_username = Some(username);
+    config.github_token = None;
+    save_config(config)
+}
+
 use crate::init::NewArgs;
 use clap::{Args, Parser, Subcommand};
+use clap_complete::ArgValueCompleter;
 
 /// Main entry point
 #[tokio::main]
 async fn main() {
+    // Answer the shell when it's asking for completions
+    completions::complete();
     let Faasta::Faasta(cli) = Faasta::parse();
 
+    // Command-line options win over the config file
+    let config = load_config().unwrap_or_default();
+    let pin_sha256 = match (cli.pin_sha256, &config.pin_sha256) {
+        (Some(pin), _) => Some(pin),
+        (None, Some(pin)) => match tls::parse_pin(pin) {
+            Ok(pin) => Some(pin),
+            Err(e) => {
+                eprintln!("Invalid pin_sha256 in the config file: {e}");
+                errors::exit(&errors::CONFIG);
+            }
+        },
+        (None, None) => None,
+    };
+    let client_identity = match (
+        cli.client_cert.or(config.client_cert),
+        cli.client_key.or(config.client_key),
+    ) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => {
+            eprintln!("A client certificate needs both client_cert and client_key");
+            errors::exit(&errors::CONFIG);
+        }
+    };
+    let proxy = match (cli.proxy, &config.proxy) {
+        (Some(proxy), _) => Some(proxy),
+        (None, Some(proxy)) => match proxy::Proxy::parse(proxy) {
+            Ok(proxy) => Some(proxy),
+            Err(e) => {
+                eprintln!("Invalid proxy in the config file: {e}");
+                errors::exit(&errors::CONFIG);
+            }
+        },
+        (None, None) => None,
+    };
+    transport::configure(transport::ConnectOptions {
+        transport: cli.transport,
+        proxy,
+        tls: tls::TlsOptions {
+            ca_cert: cli.ca_cert.or(config.ca_cert),
+            pin_sha256,
+            client_identity,
+        },
+    });
+
     match cli.command {
         Commands::Deploy(args) => {
             let spinner = indicatif::ProgressBar::new_spinner();
@@ -117,83 +241,171 @@ async fn main() {
             let _github_config = if args.skip_auth {
                 None
             } else {
-                match load_config() {
-                    Ok(config) => {
-                        match (config.github_username, config.github_token) {
-                            (Some(username), Some(token)) => Some((username, token)),
-                            _ => {
-                                spinner.finish_and_clear();
-                                println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
-                                // println!("Or use --skip-auth to deploy without authentication (limited to one function).");
-                                exit(1);
-                            }
-                        }
+                match load_credentials().await {
+                    Ok(Some(credentials)) => Some(credentials),
+                    Ok(None) => {
+                        spinner.finish_and_clear();
+                        println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                        // println!("Or use --skip-auth to deploy without authentication (limited to one function).");
+                        errors::exit(&errors::NOT_LOGGED_IN);
                     }
                     Err(e) => {
                         spinner.finish_and_clear();
                         eprintln!("Failed to load config: {e}");
-                        exit(1);
+                        errors::exit(&errors::CONFIG);
                     }
                 }
             };
 
-            // Get project information
-            let (target_directory, package_name, _) = match run::get_project_info() {
-                Ok(info) => info,
+            // Several projects deploy together, each showing its own progress
+            if args.workspace || args.paths.len() > 1 {
+                spinner.finish_and_clear();
+                let Some(credentials) = _github_config else {
+                    eprintln!("GitHub credentials required for function upload.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                };
+                if let Err(e) = deploy_many::deploy(&args, credentials).await {
+                    eprintln!("Error: {e:#}");
+                    errors::exit_with(&e);
+                }
+                return;
+            }
+
+            // Settings from faasta.toml in the project directory, if there is one
+            let project_dir = args.paths.first().map_or_else(
+                || std::env::current_dir().unwrap_or_default(),
+                PathBuf::from,
+            );
+            let config =
+                project::load(&project_dir).and_then(|config| config.validate().map(|()| config));
+            let (project, cors, auth, transforms, routes) = match config {
+                Ok(config) => (
+                    config.function,
+                    config.cors,
+                    config.auth,
+                    config.transforms,
+                    config.routes,
+                ),
                 Err(e) => {
                     spinner.finish_and_clear();
-                    eprintln!("Failed to get project information: {e}");
-                    exit(1);
+                    eprintln!("{e:#}");
+                    errors::exit(&errors::PROJECT_INVALID);
+                }
+            };
+            // Route handlers are read up front, so a missing one fails the deploy
+            // before anything is published
+            let routes = match routes.map(|routes| read_route_handlers(&project_dir, routes)) {
+                Some(Err(e)) => {
+                    spinner.finish_and_clear();
+                    eprintln!("Invalid [[routes]] in faasta.toml: {e:#}");
+                    errors::exit(&errors::PROJECT_INVALID);
                 }
+                Some(Ok(routes)) => Some(routes),
+                None => None,
             };
+            let metadata = function_metadata(&project, routes.as_deref());
+            if let Err(e) = metadata.validate() {
+                spinner.finish_and_clear();
+                eprintln!("Invalid function metadata in faasta.toml: {e}");
+                errors::exit(&errors::PROJECT_INVALID);
+            }
 
-            // Path to the WASM file
-            // Note: Rust compiler output converts hyphens to underscores, so we need to
-            // handle this conversion to find the compiled WASM file
-            // This is a client-side only conversion that's needed to locate the compiled artifact
-            let wasm_path = if let Some(explicit_path) = &args.wasm_path {
-                // User provided an explicit WASM path
-                PathBuf::from(explicit_path)
-            } else {
-                // Auto-detect based on package name
-                let rust_compiled_name = package_name.replace('-', "_");
-                let wasm_filename = format!("{rust_compiled_name}.wasm");
-
-                // Path to the compiled WASM file (uses Rust's converted name)
-                target_directory
-                    .join("wasm32-wasip2")
-                    .join("release")
-                    .join(wasm_filename)
-            };
-
-            // For explicit WASM paths, we'll use the filename without extension as the function name
-            // unless the user specified a function name
-            let function_name = if args.wasm_path.is_some() && args.function_name.is_some() {
-                // User provided both WASM path and function name - use the explicit function name
-                args.function_name.clone().unwrap()
-            } else if args.wasm_path.is_some() {
-                // User provided WASM path but no function name - derive from filename
-                wasm_path
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .map(|s| s.to_owned())
-                    .unwrap_or_else(|| {
+            // The server pulls a component published to a registry itself
+            if let Some(reference) = &args.from_oci {
+                spinner.finish_and_clear();
+                let Some((github_username, github_token)) = _github_config else {
+                    eprintln!("GitHub credentials required for function upload.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                };
+                let server = args
+                    .server
+                    .clone()
+                    .or(project.server.clone())
+                    .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+                if let Err(e) = deploy_from_registry(
+                    reference,
+                    args.function_name.clone().or(project.name.clone()),
+                    args.team.clone().or(project.team.clone()),
+                    args.keep_warm().or(project.keep_warm),
+                    &server,
+                    format!("{github_username}:{github_token}"),
+                )
+                .await
+                {
+                    eprintln!("Error: {e:#}");
+                    errors::exit_with(&e);
+                }
+                return;
+            }
+
+            // A prebuilt component (built in CI or by another toolchain) needs no cargo
+            // project. Otherwise the project's compiled component is deployed.
+            let prebuilt_wasm = args
+                .wasm_path
+                .as_ref()
+                .map(PathBuf::from)
+                .or_else(|| project.wasm.as_ref().map(|wasm| project_dir.join(wasm)));
+            let prebuilt = prebuilt_wasm.is_some();
+            let foreign_project = match componentize::detect(&project_dir) {
+                Ok(project) => project,
+                Err(e) => {
+                    spinner.finish_and_clear();
+                    eprintln!("Failed to get project information: {e:#}");
+                    errors::exit(&errors::PROJECT_INVALID);
+                }
+            };
+            let (wasm_path, package_name) = match (prebuilt_wasm, foreign_project) {
+                (Some(path), _) => (path, None),
+                // JavaScript and Python projects are componentized as part of the deploy
+                (None, Some(project)) => (build_foreign_project(&project), Some(project.name)),
+                (None, None) => match run::get_project_info() {
+                    Ok((target_directory, package_name, _)) => (
+                        compiled_wasm_path(&target_directory, &package_name),
+                        Some(package_name),
+                    ),
+                    Err(e) => {
                         spinner.finish_and_clear();
-                        eprintln!("Error: Could not determine function name from WASM filename");
-                        exit(1);
-                    })
-            } else {
-                // Standard flow - use the package name
-                package_name.clone()
+                        eprintln!("Failed to get project information: {e}");
+                        errors::exit(&errors::PROJECT_INVALID);
+                    }
+                },
             };
 
+            // Name the function after --function-name, then faasta.toml, then the package
+            // or, for a prebuilt component, its file name
+            let function_name = args
+                .function_name
+                .clone()
+                .or(project.name)
+                .or(package_name)
+                .unwrap_or_else(|| {
+                    wasm_path
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .map(|s| s.to_owned())
+                        .unwrap_or_else(|| {
+                            spinner.finish_and_clear();
+                            eprintln!(
+                                "Error: Could not determine function name from WASM filename"
+                            );
+                            errors::exit(&errors::INVALID_INPUT);
+                        })
+                });
+            let team = args.team.clone().or(project.team);
+            let keep_warm = args.keep_warm().or(project.keep_warm);
+            let server = args
+                .server
+                .clone()
+                .or(project.server)
+                .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+
             spinner.set_message(format!(
             "Uploading function '{function_name}' to server..."
         ));
 
             if !wasm_path.exists() {
                 spinner.finish_and_clear();
-                if args.wasm_path.is_some() {
+                if prebuilt {
                     eprintln!(
                         "Error: Could not find WASM file at: {}",
                         wasm_path.display()
@@ -205,34 +417,42 @@ async fn main() {
                     );
                     eprintln!("Options:");
                     eprintln!("  1. Run 'cargo faasta build' first with wasm32-wasip2 target");
-                    eprintln!("  2. Specify an explicit WASM file path with --wasm-path");
+                    eprintln!("  2. Specify a prebuilt component with --wasm");
                     eprintln!();
                     eprintln!("If your WASM file is in a non-standard location or has a different name, use:");
-                    eprintln!("  cargo faasta deploy --wasm-path PATH/TO/YOUR/FILE.wasm");
+                    eprintln!("  cargo faasta deploy --wasm PATH/TO/YOUR/FILE.wasm");
                 }
-                exit(1);
+                errors::exit(&errors::ARTIFACT_MISSING);
             }
 
             // Read the WASM file
             let wasm_data = match std::fs::read(&wasm_path) {
-                Ok(data) => {
-                    // Check WASM file size client-side as well (30MB max)
-                    if data.len() > faasta_interface::MAX_WASM_SIZE {
-                        spinner.finish_and_clear();
-                        eprintln!(
-                            "Error: WASM file too large ({}MB). Maximum allowed size is 30MB.",
-                            data.len() / 1024 / 1024
-                        );
-                        exit(1);
-                    }
-                    data
-                }
+                Ok(data) => data,
                 Err(e) => {
                     spinner.finish_and_clear();
                     eprintln!("Failed to read WASM file: {e}");
-                    exit(1);
+                    errors::exit(&errors::ARTIFACT_MISSING);
                 }
             };
+            if !is_component(&wasm_data) {
+                spinner.finish_and_clear();
+                eprintln!(
+                    "Error: {} is not a WebAssembly component",
+                    wasm_path.display()
+                );
+                eprintln!("Faasta runs wasi:http components. Core modules can be wrapped with 'wasm-tools component new'.");
+                errors::exit(&errors::INVALID_COMPONENT);
+            }
+
+            let provenance = read_provenance(args.provenance.as_ref());
+            let settings = format!(
+                "team={team:?} keep-warm={keep_warm:?} provenance={}",
+                provenance
+                    .as_deref()
+                    .map(fingerprint::digest)
+                    .unwrap_or_default()
+            );
+            let fingerprint = fingerprint::of(&project_dir, &settings, &wasm_data).ok();
 
             // Get GitHub credentials
             let (github_username, github_token) = if let Some((username, token)) = _github_config {
@@ -240,53 +460,136 @@ async fn main() {
             } else {
                 spinner.finish_and_clear();
                 eprintln!("GitHub credentials required for function upload.");
-                exit(1);
+                errors::exit(&errors::NOT_LOGGED_IN);
             };
 
             // Connect to the function service
-            let server_addr = &args.server;
+            let server_addr = &server;
 
             // Use the connect function to get a client
-            let client = match run::connect_to_function_service(server_addr).await {
-                Ok(client) => client,
+            let (client, info) = match run::connect_with_info(server_addr).await {
+                Ok(connected) => connected,
                 Err(e) => {
                     spinner.finish_and_clear();
                     eprintln!("Failed to connect to server: {e}");
-                    exit(1);
+                    errors::exit(&errors::CONNECT_FAILED);
                 }
             };
 
-            // Publish the function
             let auth_token = format!("{github_username}:{github_token}");
-            match client
-                .publish(
-                    tarpc::context::current(),
-                    wasm_data,
-                    function_name.clone(),
-                    auth_token,
-                )
-                .await
+
+            // Nothing to upload when neither the project nor the component changed
+            // since the last deploy, and the server still serves that component
+            if let Some(fingerprint) = fingerprint.as_ref().filter(|_| !args.force) {
+                if Some(fingerprint) == fingerprint::last_deploy(&server, &function_name).as_ref()
+                    && fingerprint::is_served(&client, &function_name, &auth_token, fingerprint)
+                        .await
+                {
+                    spinner.finish_and_clear();
+                    println!("✅ '{function_name}' is unchanged since its last deploy (--force deploys it anyway)");
+                    println!(
+                        "Function URL: {}",
+                        format_function_url(&function_name, &extract_server_host(&server))
+                    );
+                    return;
+                }
+            }
+
+            // Route handlers are staged first, to go live with the function
+            spinner.set_message("Uploading route handlers...");
+            let routes = match stage_routes(&client, routes, &function_name, &auth_token).await {
+                Ok(routes) => routes,
+                Err(e) => {
+                    spinner.finish_and_clear();
+                    eprintln!("Error: failed to upload the route handlers: {e:#}");
+                    errors::exit_with(&e);
+                }
+            };
+
+            // Publish the function
+            spinner.set_message(format!("Uploading function '{function_name}' to server..."));
+            match publish_function(
+                &client,
+                &info,
+                wasm_data,
+                provenance,
+                function_name.clone(),
+                team,
+                keep_warm,
+                Some(metadata),
+                auth_token.clone(),
+            )
+            .await
             {
                 Ok(Ok(message)) => {
+                    spinner.set_message("Uploading static assets...");
+                    let assets =
+                        publish_static_assets(&client, &project_dir, &function_name, &auth_token)
+                            .await;
+                    let cors = set_cors_policy(&client, cors, &function_name, &auth_token).await;
+                    let auth = set_jwt_auth(&client, auth, &function_name, &auth_token).await;
+                    let transforms =
+                        set_transforms(&client, transforms, &function_name, &auth_token).await;
                     spinner.finish_and_clear();
                     println!("✅ {message}");
+                    if let Some(routes) = routes {
+                        println!("✅ {routes}");
+                    }
+                    match assets {
+                        Ok(Some(message)) => println!("✅ {message}"),
+                        Ok(None) => {}
+                        Err(e) => {
+                            eprintln!("Error: failed to publish static assets: {e:#}");
+                            errors::exit_with(&e);
+                        }
+                    }
+                    match cors {
+                        Ok(Some(message)) => println!("✅ {message}"),
+                        Ok(None) => {}
+                        Err(e) => {
+                            eprintln!("Error: failed to set the CORS policy: {e:#}");
+                            errors::exit_with(&e);
+                        }
+                    }
+                    match auth {
+                        Ok(Some(message)) => println!("✅ {message}"),
+                        Ok(None) => {}
+                        Err(e) => {
+                            eprintln!("Error: failed to set the JWT authentication: {e:#}");
+                            errors::exit_with(&e);
+                        }
+                    }
+                    match transforms {
+                        Ok(Some(message)) => println!("✅ {message}"),
+                        Ok(None) => {}
+                        Err(e) => {
+                            eprintln!("Error: failed to set the transformation rules: {e:#}");
+                            errors::exit_with(&e);
+                        }
+                    }
 
                     // Extract server hostname from server address (remove port)
-                    let server_host = extract_server_host(&args.server);
+                    let server_host = extract_server_host(&server);
                     println!(
                         "Function URL: {}",
                         format_function_url(&function_name, &server_host)
                     );
+                    if let Some(fingerprint) = fingerprint {
+                        if let Err(e) = fingerprint::record(&server, &function_name, fingerprint) {
+                            eprintln!("Warning: failed to remember this deploy: {e:#}");
+                        }
+                    }
                 }
                 Ok(Err(e)) => {
                     spinner.finish_and_clear();
-                    eprintln!("Server error: {e:?}");
-                    exit(1);
+                    eprintln!("Error: {}", describe_error(&e));
+                    suggest_smaller_artifact(&e);
+                    errors::exit(errors::for_server_error(&e));
                 }
                 Err(e) => {
                     spinner.finish_and_clear();
                     eprintln!("Communication error: {e}");
-                    exit(1);
+                    errors::exit(&errors::CONNECTION_LOST);
                 }
             };
         }
@@ -296,7 +599,10 @@ async fn main() {
                 .await
                 .unwrap_or_else(|e| {
                     eprintln!("Failed to invoke function: {e}");
-                    exit(1);
+                    if e.is_connect() {
+                        errors::exit(&errors::CONNECT_FAILED);
+                    }
+                    errors::exit(&errors::CONNECTION_LOST);
                 });
         }
 
@@ -311,14 +617,14 @@ async fn main() {
             // Delegate to handle_new function
             if let Err(err) = init::handle_new(&new_args) {
                 eprintln!("Failed to initialize project in current directory: {err}");
-                exit(1);
+                errors::exit(&errors::UNCLASSIFIED);
             }
         }
 
         Commands::New(new_args) => {
             if let Err(err) = init::handle_new(&new_args) {
                 eprintln!("Failed to create new project: {err}");
-                exit(1);
+                errors::exit(&errors::UNCLASSIFIED);
             }
         }
 
@@ -327,39 +633,94 @@ async fn main() {
             spinner.set_message("Building project...");
             spinner.enable_steady_tick(std::time::Duration::from_millis(100));
 
-            // Get project information
-            let (target_directory, package_name, package_root) = match run::get_project_info() {
-                Ok(info) => info,
-                Err(e) => {
+            // Build a JavaScript or Python project with its componentize tool, and
+            // anything else as a cargo project
+            let foreign_project = std::env::current_dir()
+                .map_err(anyhow::Error::from)
+                .and_then(|dir| componentize::detect(&dir))
+                .unwrap_or_else(|e| {
                     spinner.finish_and_clear();
-                    eprintln!("Failed to get project information: {e}");
-                    exit(1);
+                    eprintln!("Failed to get project information: {e:#}");
+                    errors::exit(&errors::PROJECT_INVALID);
+                });
+            // Options that change what `--deploy` deploys
+            let settings = format!(
+                "team={:?} optimize={} pre-init={}",
+                build_args.team, build_args.optimize, build_args.pre_init
+            );
+            let (compiled_path, package_name, package_root) = match foreign_project {
+                Some(project) => (build_foreign_project(&project), project.name, None),
+                None => {
+                    let (target_directory, package_name, package_root) =
+                        match run::get_project_info() {
+                            Ok(info) => info,
+                            Err(e) => {
+                                spinner.finish_and_clear();
+                                eprintln!("Failed to get project information: {e}");
+                                errors::exit(&errors::PROJECT_INVALID);
+                            }
+                        };
+                    let compiled_path = compiled_wasm_path(&target_directory, &package_name);
+
+                    // Nothing to build or upload when neither the project nor its
+                    // last build changed since the last deploy
+                    let last_build = fs::read(&compiled_path)
+                        .ok()
+                        .and_then(|wasm| fingerprint::of(&package_root, &settings, &wasm).ok())
+                        .filter(|fingerprint| {
+                            build_args.deploy
+                                && !build_args.force
+                                && build_args.wasm_path.is_none()
+                                && Some(fingerprint)
+                                    == fingerprint::last_deploy(&build_args.server, &package_name)
+                                        .as_ref()
+                        });
+                    let unchanged = match last_build {
+                        Some(fingerprint) => {
+                            is_served(&build_args.server, &package_name, &fingerprint).await
+                        }
+                        None => false,
+                    };
+                    if unchanged {
+                        spinner.finish_and_clear();
+                        println!("✅ '{package_name}' is unchanged since its last deploy (--force deploys it anyway)");
+                        println!(
+                            "Function URL: {}",
+                            format_function_url(
+                                &package_name,
+                                &extract_server_host(&build_args.server)
+                            )
+                        );
+                        return;
+                    }
+
+                    if let Err(e) = run::build_project(
+                        &package_root,
+                        &compiled_path,
+                        build_args.optimize,
+                        build_args.pre_init,
+                        run::BuildKind::Release,
+                    ) {
+                        spinner.finish_and_clear();
+                        eprintln!("Failed to build project: {e}");
+                        errors::exit(&errors::BUILD_FAILED);
+                    }
+                    (compiled_path, package_name, Some(package_root))
                 }
             };
 
-            // Build the project
-            if let Err(e) = run::build_project(&package_root) {
-                spinner.finish_and_clear();
-                eprintln!("Failed to build project: {e}");
-                exit(1);
-            }
-
             // If deploy flag is specified, deploy the function
             if build_args.deploy {
                 spinner.set_message("Deploying function to server...");
 
                 // Load GitHub config for authentication
-                let _github_config = match load_config() {
-                    Ok(config) => {
-                        match (config.github_username, config.github_token) {
-                            (Some(username), Some(token)) => Some((username, token)),
-                            _ => {
-                                spinner.finish_and_clear();
-                                println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
-                                // println!("Or use 'cargo faasta deploy --skip-auth' to deploy without authentication (limited to one function).");
-                                None
-                            }
-                        }
+                let _github_config = match load_credentials().await {
+                    Ok(Some(credentials)) => Some(credentials),
+                    Ok(None) => {
+                        spinner.finish_and_clear();
+                        println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                        // println!("Or use 'cargo faasta deploy --skip-auth' to deploy without authentication (limited to one function).");
+                        None
                     }
                     Err(e) => {
                         spinner.finish_and_clear();
@@ -375,15 +736,8 @@ async fn main() {
                     // User provided an explicit WASM path
                     PathBuf::from(explicit_path)
                 } else {
-                    // Auto-detect based on package name
-                    let rust_compiled_name = package_name.replace('-', "_");
-                    let wasm_filename = format!("{rust_compiled_name}.wasm");
-
-                    // Path to the compiled WASM file (uses Rust's converted name)
-                    target_directory
-                        .join("wasm32-wasip2")
-                        .join("release")
-                        .join(wasm_filename)
+                    // The component just built
+                    compiled_path
                 };
 
                 // For explicit WASM paths, we'll use the filename without extension as the function name
@@ -403,7 +757,7 @@ async fn main() {
                                 eprintln!(
                                     "Error: Could not determine function name from WASM filename"
                                 );
-                                exit(1);
+                                errors::exit(&errors::INVALID_INPUT);
                             })
                     } else {
                         // Standard flow - use the package name
@@ -431,29 +785,22 @@ async fn main() {
                             "  cargo faasta build --deploy --wasm-path PATH/TO/YOUR/FILE.wasm"
                         );
                     }
-                    exit(1);
+                    errors::exit(&errors::ARTIFACT_MISSING);
                 }
 
                 // Read the WASM file
                 let wasm_data = match std::fs::read(&wasm_path) {
-                    Ok(data) => {
-                        // Check WASM file size client-side as well (30MB max)
-                        if data.len() > faasta_interface::MAX_WASM_SIZE {
-                            spinner.finish_and_clear();
-                            eprintln!(
-                                "Error: WASM file too large ({}MB). Maximum allowed size is 30MB.",
-                                data.len() / 1024 / 1024
-                            );
-                            exit(1);
-                        }
-                        data
-                    }
+                    Ok(data) => data,
                     Err(e) => {
                         spinner.finish_and_clear();
                         eprintln!("Failed to read WASM file: {e}");
-                        exit(1);
+                        errors::exit(&errors::ARTIFACT_MISSING);
                     }
                 };
+                // Remembered once deployed, so an unchanged project isn't built again
+                let fingerprint = package_root
+                    .filter(|_| build_args.wasm_path.is_none())
+                    .and_then(|root| fingerprint::of(&root, &settings, &wasm_data).ok());
 
                 // Get GitHub credentials
                 let (github_username, github_token) =
@@ -462,7 +809,7 @@ async fn main() {
                     } else {
                         spinner.finish_and_clear();
                         eprintln!("GitHub credentials required for function upload.");
-                        exit(1);
+                        errors::exit(&errors::NOT_LOGGED_IN);
                     };
 
                 spinner.set_message(format!(
@@ -473,25 +820,29 @@ async fn main() {
                 let server_addr = &build_args.server;
 
                 // Use the connect function to get a client
-                let client = match run::connect_to_function_service(server_addr).await {
-                    Ok(client) => client,
+                let (client, info) = match run::connect_with_info(server_addr).await {
+                    Ok(connected) => connected,
                     Err(e) => {
                         spinner.finish_and_clear();
                         eprintln!("Failed to connect to server: {e}");
-                        exit(1);
+                        errors::exit(&errors::CONNECT_FAILED);
                     }
                 };
 
                 // Publish the function
                 let auth_token = format!("{github_username}:{github_token}");
-                match client
-                    .publish(
-                        tarpc::context::current(),
-                        wasm_data,
-                        function_name.clone(),
-                        auth_token,
-                    )
-                    .await
+                match publish_function(
+                    &client,
+                    &info,
+                    wasm_data,
+                    None,
+                    function_name.clone(),
+                    build_args.team.clone(),
+                    None,
+                    None,
+                    auth_token,
+                )
+                .await
                 {
                     Ok(Ok(message)) => {
                         spinner.finish_and_clear();
@@ -503,51 +854,137 @@ async fn main() {
                             "Function URL: {}",
                             format_function_url(&function_name, &server_host)
                         );
+                        if let Some(fingerprint) = fingerprint {
+                            if let Err(e) =
+                                fingerprint::record(&build_args.server, &function_name, fingerprint)
+                            {
+                                eprintln!("Warning: failed to remember this deploy: {e:#}");
+                            }
+                        }
                     }
                     Ok(Err(e)) => {
                         spinner.finish_and_clear();
-                        eprintln!("Server error: {e:?}");
-                        exit(1);
+                        eprintln!("Error: {}", describe_error(&e));
+                        suggest_smaller_artifact(&e);
+                        errors::exit(errors::for_server_error(&e));
                     }
                     Err(e) => {
                         spinner.finish_and_clear();
                         eprintln!("Communication error: {e}");
-                        exit(1);
+                        errors::exit(&errors::CONNECTION_LOST);
                     }
                 };
             }
         }
 
+        Commands::Push(args) => {
+            if let Err(e) = push_component(args).await {
+                eprintln!("Error: {e:#}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Export(args) => {
+            if !args.tf {
+                eprintln!("Choose what to export, e.g. --tf for Terraform/OpenTofu");
+                errors::exit(&errors::INVALID_INPUT);
+            }
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = export_terraform(&client, &args.server, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Apply(args) => {
+            let definitions = match read_definitions(&args.file) {
+                Ok(definitions) => definitions,
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    errors::exit(&errors::INVALID_INPUT);
+                }
+            };
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = apply_definitions(&client, definitions, args.dry_run, credentials).await
+            {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
         Commands::Login(login_args) => {
             // Load existing config or create a new one
             let mut config = match load_config() {
                 Ok(cfg) => cfg,
                 Err(e) => {
                     eprintln!("Failed to load config: {e}");
-                    exit(1);
+                    errors::exit(&errors::CONFIG);
                 }
             };
 
             if login_args.manual {
                 // Manual login mode - for users who prefer direct token input
                 // Set GitHub username
-                if let Some(username) = login_args.username {
-                    config.github_username = Some(username);
-                } else if config.github_username.is_none() {
-                    eprintln!("GitHub username required. Use --username to provide it.");
-                    exit(1);
-                }
+                let username = match login_args.username.or(config.github_username.clone()) {
+                    Some(username) => username,
+                    None => {
+                        eprintln!("GitHub username required. Use --username to provide it.");
+                        errors::exit(&errors::INVALID_INPUT);
+                    }
+                };
 
-                // Set GitHub token
-                if let Some(token) = login_args.token {
-                    config.github_token = Some(token);
-                } else if config.github_token.is_none() {
-                    eprintln!("GitHub token required. Use --token to provide it.");
-                    exit(1);
-                }
+                // Set GitHub token, keeping the stored one if none was given
+                let token = match login_args.token {
+                    Some(token) => token,
+                    None => match load_credentials().await {
+                        Ok(Some((_, token))) => token,
+                        _ => {
+                            eprintln!("GitHub token required. Use --token to provide it.");
+                            errors::exit(&errors::INVALID_INPUT);
+                        }
+                    },
+                };
 
-                // Save the config
-                match save_config(&config) {
+                // Save the credentials
+                match save_credentials(&mut config, username, &token).await {
                     Ok(_) => {
                         println!("GitHub credentials saved successfully.");
                         println!(
@@ -555,18 +992,15 @@ async fn main() {
                         );
                     }
                     Err(e) => {
-                        eprintln!("Failed to save config: {e}");
-                        exit(1);
+                        eprintln!("Failed to save credentials: {e}");
+                        errors::exit(&errors::CONFIG);
                     }
                 }
             } else {
                 // Interactive OAuth flow
                 match crate::github_oauth::github_oauth_flow().await {
                     Ok((username, token)) => {
-                        config.github_username = Some(username);
-                        config.github_token = Some(token);
-
-                        match save_config(&config) {
+                        match save_credentials(&mut config, username, &token).await {
                             Ok(_) => {
                                 println!("✅ GitHub authentication successful!");
                                 println!(
@@ -574,39 +1008,64 @@ async fn main() {
                                 );
                             }
                             Err(e) => {
-                                eprintln!("Failed to save config: {e}");
-                                exit(1);
+                                eprintln!("Failed to save credentials: {e}");
+                                errors::exit(&errors::CONFIG);
                             }
                         }
                     }
                     Err(e) => {
                         eprintln!("GitHub authentication failed: {e}");
                         eprintln!("Try again or use manual login: cargo faasta login --manual --username <user> --token <token>");
-                        exit(1);
+                        errors::exit(&errors::AUTH_FAILED);
                     }
                 }
             }
         }
 
+        Commands::Logout => {
+            let mut config = match load_config() {
+                Ok(cfg) => cfg,
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let Some(username) = config.github_username.take() else {
+                println!("Not logged in.");
+                return;
+            };
+
+            if let Err(e) = credentials::delete_token(&get_config_dir(), &username).await {
+                eprintln!("Failed to remove stored token: {e}");
+                errors::exit(&errors::CONFIG);
+            }
+
+            config.github_token = None;
+            if let Err(e) = save_config(&config) {
+                eprintln!("Failed to save config: {e}");
+                errors::exit(&errors::CONFIG);
+            }
+            println!("✅ Logged out {username} and removed the stored token.");
+        }
+
         Commands::Metrics(args) => {
             let spinner = indicatif::ProgressBar::new_spinner();
             spinner.set_message("Fetching metrics...");
             spinner.enable_steady_tick(std::time::Duration::from_millis(100));
 
             // Load GitHub config for authentication
-            let github_config = match load_config() {
-                Ok(config) => match (config.github_username, config.github_token) {
-                    (Some(username), Some(token)) => Some((username, token)),
-                    _ => {
-                        spinner.finish_and_clear();
-                        println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
-                        exit(1);
-                    }
-                },
+            let github_config = match load_credentials().await {
+                Ok(Some(credentials)) => Some(credentials),
+                Ok(None) => {
+                    spinner.finish_and_clear();
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
                 Err(e) => {
                     spinner.finish_and_clear();
                     eprintln!("Failed to load config: {e}");
-                    exit(1);
+                    errors::exit(&errors::CONFIG);
                 }
             };
 
@@ -619,105 +1078,366 @@ async fn main() {
                 Err(e) => {
                     spinner.finish_and_clear();
                     eprintln!("Failed to connect to server: {e}");
-                    exit(1);
+                    errors::exit(&errors::CONNECT_FAILED);
                 }
             };
 
             // Call get_metrics
             spinner.finish_and_clear();
-            if let Err(e) = get_metrics(&client, &github_username, &github_token).await {
+            let result = match args.name {
+                Some(name) => {
+                    show_function_metrics(
+                        &client,
+                        name,
+                        args.memory,
+                        args.json,
+                        &github_username,
+                        &github_token,
+                    )
+                    .await
+                }
+                None => get_metrics(&client, args.json, &github_username, &github_token).await,
+            };
+            if let Err(e) = result {
                 eprintln!("Error fetching metrics: {e}");
-                exit(1);
+                errors::exit_with(&e);
             }
         }
 
         Commands::Unpublish(args) => {
-            let spinner = indicatif::ProgressBar::new_spinner();
-            spinner.set_message(format!("Unpublishing function '{}'...", args.name));
-            spinner.enable_steady_tick(std::time::Duration::from_millis(100));
-
-            // Load GitHub config for authentication
-            let github_config = match load_config() {
-                Ok(config) => match (config.github_username, config.github_token) {
-                    (Some(username), Some(token)) => Some((username, token)),
-                    _ => {
-                        spinner.finish_and_clear();
-                        println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
-                        exit(1);
-                    }
-                },
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
                 Err(e) => {
-                    spinner.finish_and_clear();
                     eprintln!("Failed to load config: {e}");
-                    exit(1);
+                    errors::exit(&errors::CONFIG);
                 }
             };
 
-            // Get GitHub credentials
-            let (github_username, github_token) = github_config.unwrap();
-
-            // Connect to the function service
             let client = match run::connect_to_function_service(&args.server).await {
                 Ok(client) => client,
                 Err(e) => {
-                    spinner.finish_and_clear();
                     eprintln!("Failed to connect to server: {e}");
-                    exit(1);
+                    errors::exit(&errors::CONNECT_FAILED);
                 }
             };
 
-            // Create auth token (username:token format)
-            let auth_token = format!("{github_username}:{github_token}");
+            if let Err(e) = unpublish_function(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
 
-            // Call the unpublish RPC
-            match client
-                .unpublish(tarpc::context::current(), args.name.clone(), auth_token)
-                .await
-            {
-                Ok(Ok(_)) => {
-                    spinner.finish_and_clear();
-                    println!("✅ Function '{}' unpublished successfully", args.name);
-                }
-                Ok(Err(e)) => {
-                    spinner.finish_and_clear();
-                    match e {
-                        faasta_interface::FunctionError::NotFound(_) => {
-                            eprintln!("Error: Function '{}' not found", args.name)
-                        }
-                        faasta_interface::FunctionError::PermissionDenied(_) => {
-                            eprintln!("Error: You don't have permission to unpublish this function")
-                        }
-                        _ => eprintln!("Server error: {e:?}"),
-                    }
-                    exit(1);
+        Commands::Release(args) => {
+            let (github_username, github_token) = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
                 }
                 Err(e) => {
-                    spinner.finish_and_clear();
-                    eprintln!("Communication error: {e}");
-                    exit(1);
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
                 }
-            }
-        }
+            };
 
-        Commands::List(args) => {
-            let spinner = indicatif::ProgressBar::new_spinner();
-            spinner.set_message("Fetching function list...");
-            spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+            // Release the function built from the current project unless one is named
+            let project = run::get_project_info();
+            let function_name = match (&args.function_name, &project) {
+                (Some(name), _) => name.clone(),
+                (None, Ok((_, package_name, _))) => package_name.clone(),
+                (None, Err(e)) => {
+                    eprintln!("Failed to get project information: {e}");
+                    errors::exit(&errors::PROJECT_INVALID);
+                }
+            };
 
-            // Load GitHub config for authentication
-            let github_config = match load_config() {
-                Ok(config) => match (config.github_username, config.github_token) {
-                    (Some(username), Some(token)) => Some((username, token)),
-                    _ => {
-                        spinner.finish_and_clear();
-                        println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
-                        exit(1);
+            let (client, info) = match run::connect_with_info(&args.server).await {
+                Ok(connected) => connected,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            let auth_token = format!("{github_username}:{github_token}");
+            let context = tarpc::context::current();
+            let result = if let Some(weight) = args.canary {
+                let wasm_path = match (&args.wasm_path, &project) {
+                    (Some(path), _) => PathBuf::from(path),
+                    (None, Ok((target_directory, package_name, _))) => {
+                        compiled_wasm_path(target_directory, package_name)
                     }
-                },
+                    (None, Err(e)) => {
+                        eprintln!("Failed to get project information: {e}");
+                        errors::exit(&errors::PROJECT_INVALID);
+                    }
+                };
+                let wasm_data = match std::fs::read(&wasm_path) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        eprintln!("Failed to read WASM file at {}: {e}", wasm_path.display());
+                        eprintln!("Run 'cargo faasta build' first or pass --wasm-path.");
+                        errors::exit(&errors::ARTIFACT_MISSING);
+                    }
+                };
+                publish_upload(
+                    &client,
+                    &info,
+                    &wasm_data,
+                    read_provenance(args.provenance.as_ref()),
+                    function_name.clone(),
+                    faasta_interface::PublishTarget::Canary { weight },
+                    None,
+                    None,
+                    auth_token,
+                )
+                .await
+            } else {
+                let update = match (args.weight, args.promote) {
+                    (Some(weight), _) => faasta_interface::CanaryUpdate::Weight(weight),
+                    (None, true) => faasta_interface::CanaryUpdate::Promote,
+                    (None, false) => faasta_interface::CanaryUpdate::Abort,
+                };
+                client
+                    .update_canary(context, function_name, update, auth_token)
+                    .await
+            };
+
+            match result {
+                Ok(Ok(message)) => println!("✅ {message}"),
+                Ok(Err(e)) => {
+                    eprintln!("Error: {}", describe_error(&e));
+                    suggest_smaller_artifact(&e);
+                    errors::exit(errors::for_server_error(&e));
+                }
+                Err(e) => {
+                    eprintln!("Communication error: {e}");
+                    errors::exit(&errors::CONNECTION_LOST);
+                }
+            }
+        }
+
+        Commands::Rename(args) => {
+            let (github_username, github_token) = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            let auth_token = format!("{github_username}:{github_token}");
+            match client
+                .rename_function(
+                    tarpc::context::current(),
+                    args.name.clone(),
+                    args.new_name.clone(),
+                    args.redirect_hours,
+                    auth_token,
+                )
+                .await
+            {
+                Ok(Ok(message)) => {
+                    forget_deploy(&args.server, &args.name);
+                    println!("✅ {message}");
+                    let server_host = extract_server_host(&args.server);
+                    println!(
+                        "Function URL: {}",
+                        format_function_url(&args.new_name, &server_host)
+                    );
+                    if args.redirect_hours > 0 {
+                        println!(
+                            "The old URL redirects here for the next {} hours.",
+                            args.redirect_hours
+                        );
+                    }
+                }
+                Ok(Err(e)) => {
+                    eprintln!("Error: {}", describe_error(&e));
+                    errors::exit(errors::for_server_error(&e));
+                }
+                Err(e) => {
+                    eprintln!("Communication error: {e}");
+                    errors::exit(&errors::CONNECTION_LOST);
+                }
+            }
+        }
+
+        Commands::Transfer(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = transfer_function(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+        Commands::Alias(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = manage_aliases(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+        Commands::Clone(args) => {
+            let (github_username, github_token) = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            let auth_token = format!("{github_username}:{github_token}");
+            match client
+                .clone_function(
+                    tarpc::context::current(),
+                    args.name.clone(),
+                    args.new_name.clone(),
+                    args.with_data,
+                    auth_token,
+                )
+                .await
+            {
+                Ok(Ok(message)) => {
+                    forget_deploy(&args.server, &args.name);
+                    println!("✅ {message}");
+                    let server_host = extract_server_host(&args.server);
+                    println!(
+                        "Function URL: {}",
+                        format_function_url(&args.new_name, &server_host)
+                    );
+                }
+                Ok(Err(e)) => {
+                    eprintln!("Error: {}", describe_error(&e));
+                    errors::exit(errors::for_server_error(&e));
+                }
+                Err(e) => {
+                    eprintln!("Communication error: {e}");
+                    errors::exit(&errors::CONNECTION_LOST);
+                }
+            }
+        }
+
+        Commands::Restore(args) => {
+            let (github_username, github_token) = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            let auth_token = format!("{github_username}:{github_token}");
+            match client
+                .restore(tarpc::context::current(), args.name.clone(), auth_token)
+                .await
+            {
+                Ok(Ok(message)) => println!("✅ {message}"),
+                Ok(Err(e)) => {
+                    eprintln!("Error: {}", describe_error(&e));
+                    errors::exit(errors::for_server_error(&e));
+                }
+                Err(e) => {
+                    eprintln!("Communication error: {e}");
+                    errors::exit(&errors::CONNECTION_LOST);
+                }
+            }
+        }
+
+        Commands::List(args) => {
+            let spinner = indicatif::ProgressBar::new_spinner();
+            spinner.set_message("Fetching function list...");
+            spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+
+            // Load GitHub config for authentication
+            let github_config = match load_credentials().await {
+                Ok(Some(credentials)) => Some(credentials),
+                Ok(None) => {
+                    spinner.finish_and_clear();
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
                 Err(e) => {
                     spinner.finish_and_clear();
                     eprintln!("Failed to load config: {e}");
-                    exit(1);
+                    errors::exit(&errors::CONFIG);
                 }
             };
 
@@ -730,395 +1450,4070 @@ async fn main() {
                 Err(e) => {
                     spinner.finish_and_clear();
                     eprintln!("Failed to connect to server: {e}");
-                    exit(1);
+                    errors::exit(&errors::CONNECT_FAILED);
                 }
             };
 
             // Call list_functions
             spinner.finish_and_clear();
-            if let Err(e) = list_functions(&client, &github_username, &github_token).await {
+            let query = faasta_interface::FunctionQuery {
+                name_filter: args.filter,
+                tag: args.tag,
+                sort: args.sort,
+                reverse: args.reverse,
+                page: args.page,
+                limit: args.limit,
+            };
+            if let Err(e) = list_functions(&client, &github_username, &github_token, query).await {
                 eprintln!("Error listing functions: {e}");
-                exit(1);
+                errors::exit_with(&e);
             }
         }
 
-        Commands::Run(run_args) => {
-            // Call the run module handler
-            run::handle_run(run_args.port).await.unwrap_or_else(|e| {
-                eprintln!("Failed to run function: {e}");
-                exit(1);
-            });
+        Commands::Team(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = manage_teams(&client, args.command, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
         }
-    }
-}
 
-#[derive(Args, Debug)]
-pub struct LoginArgs {
-    /// GitHub username (only needed for manual login)
-    #[arg(long)]
-    username: Option<String>,
+        Commands::Role(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
 
-    /// GitHub token (only needed for manual login)
-    #[arg(long)]
-    token: Option<String>,
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
 
-    /// Skip browser OAuth flow and manually provide credentials
-    #[arg(long)]
-    manual: bool,
-}
+            if let Err(e) = manage_roles(&client, args.command, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
 
-#[derive(Parser)] // requires `derive` feature
-#[command(name = "cargo")]
-#[command(bin_name = "cargo")]
-#[command(styles = CLAP_STYLING)]
-enum Faasta {
-    #[command(name = "faasta")]
-    Faasta(Cli),
+        Commands::Admin(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = run_admin_command(&client, args.command, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Sessions(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = manage_sessions(&client, args.command, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Alerts(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = manage_alerts(&client, args.command, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Logs(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = show_logs(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Provenance(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = show_provenance(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+        Commands::Keys(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = manage_signing_keys(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+        Commands::Webhooks(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = show_webhooks(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+        Commands::Status(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = show_function_status(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+        Commands::Protect(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = protect_function(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+        Commands::Breaker(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = manage_breaker(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Snapshots(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = manage_snapshots(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Profiles(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = manage_profiles(&client, args, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Token(args) => {
+            let credentials = match load_credentials().await {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => {
+                    println!("No GitHub credentials found. Run 'cargo faasta login' to set up authentication.");
+                    errors::exit(&errors::NOT_LOGGED_IN);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config: {e}");
+                    errors::exit(&errors::CONFIG);
+                }
+            };
+
+            let client = match run::connect_to_function_service(&args.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {e}");
+                    errors::exit(&errors::CONNECT_FAILED);
+                }
+            };
+
+            if let Err(e) = manage_api_keys(&client, args.command, credentials).await {
+                eprintln!("Error: {e}");
+                errors::exit_with(&e);
+            }
+        }
+
+        Commands::Run(run_args) => {
+            // Call the run module handler
+            run::handle_run(
+                run_args.port,
+                run_args.replay.as_deref(),
+                run_args
+                    .debug
+                    .then_some((run_args.debugger, run_args.debug_port)),
+                run_args.profile,
+            )
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to run function: {e}");
+                errors::exit(&errors::UNCLASSIFIED);
+            });
+        }
+
+        Commands::Completions(args) => {
+            if let Err(e) = completions::print_script(args.shell) {
+                eprintln!("Error: {e:#}");
+                errors::exit_with(&e);
+            }
+        }
+        Commands::Man(args) => {
+            if let Err(e) = completions::man(args.out.as_deref()) {
+                eprintln!("Error: {e:#}");
+                errors::exit_with(&e);
+            }
+        }
+        Commands::Explain(args) => match args.code {
+            Some(code) => match errors::lookup(&code) {
+                Some(entry) => {
+                    println!(
+                        "{} ({} failure, exit code {})",
+                        entry.code,
+                        entry.class.name(),
+                        entry.class.exit_code()
+                    );
+                    println!();
+                    println!("{}", entry.description);
+                }
+                None => {
+                    eprintln!(
+                        "Unknown error code '{code}'. Run 'cargo faasta explain' to list them."
+                    );
+                    errors::exit(&errors::INVALID_INPUT);
+                }
+            },
+            None => {
+                println!("{:<14} {:<11} {:>4}  DESCRIPTION", "CODE", "CLASS", "EXIT");
+                for entry in errors::CATALOG {
+                    println!(
+                        "{:<14} {:<11} {:>4}  {}",
+                        entry.code,
+                        entry.class.name(),
+                        entry.class.exit_code(),
+                        entry.description
+                    );
+                }
+            }
+        },
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct LoginArgs {
+    /// GitHub username (only needed for manual login)
+    #[arg(long)]
+    username: Option<String>,
+
+    /// GitHub token (only needed for manual login)
+    #[arg(long)]
+    token: Option<String>,
+
+    /// Skip browser OAuth flow and manually provide credentials
+    #[arg(long)]
+    manual: bool,
+}
+
+#[derive(Parser)] // requires `derive` feature
+#[command(name = "cargo")]
+#[command(bin_name = "cargo")]
+#[command(styles = CLAP_STYLING)]
+enum Faasta {
+    #[command(name = "faasta")]
+    Faasta(Cli),
+}
+
+#[derive(Args, Debug)]
+struct Cli {
+    /// How to reach the server: QUIC, TLS over TCP, or QUIC with a TCP fallback
+    #[arg(long, value_enum, default_value_t = transport::Transport::Auto, global = true)]
+    transport: transport::Transport,
+
+    /// Trust the server's certificate if it chains to a CA in this PEM bundle,
+    /// instead of the public web PKI
+    #[arg(long, value_name = "PATH", global = true)]
+    ca_cert: Option<PathBuf>,
+
+    /// Only accept the server certificate with this SHA-256 fingerprint
+    #[arg(long, value_name = "FINGERPRINT", value_parser = tls::parse_pin, global = true)]
+    pin_sha256: Option<[u8; 32]>,
+
+    /// Present this PEM certificate chain to servers that authenticate clients
+    #[arg(long, value_name = "PATH", requires = "client_key", global = true)]
+    client_cert: Option<PathBuf>,
+
+    /// Private key of --client-cert
+    #[arg(long, value_name = "PATH", requires = "client_cert", global = true)]
+    client_key: Option<PathBuf>,
+
+    /// Reach the server over TLS on TCP through this HTTP or SOCKS5 proxy
+    /// (http://, socks5:// or socks5h:// URL), instead of HTTPS_PROXY or ALL_PROXY
+    #[arg(long, value_name = "URL", value_parser = proxy::Proxy::parse, global = true)]
+    proxy: Option<proxy::Proxy>,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Deploys a project to the server
+    Deploy(DeployArgs),
+    /// Invokes a function with the specified name and argument
+    Invoke(InvokeArgs),
+    /// Initialize a new project in the current directory
+    Init,
+    /// Create a new project in a new directory
+    New(NewArgs),
+    /// Build the function (and optionally deploy it)
+    Build(BuildArgs),
+    /// Push the built component to an OCI registry
+    Push(PushArgs),
+    /// Export the deployed functions as infrastructure-as-code configuration
+    Export(ExportArgs),
+    /// Reconcile functions to the definitions in a JSON file
+    Apply(ApplyArgs),
+    /// Set up GitHub authentication
+    Login(LoginArgs),
+    /// Remove stored credentials from this machine
+    Logout,
+    /// Get metrics for deployed functions, or latency, errors and memory of one
+    #[command(alias = "stats")]
+    Metrics(MetricsArgs),
+    /// Show where a function stands: its deploy, artifact, URLs, warmth, errors and quotas
+    Status(StatusArgs),
+    /// List all functions deployed under the current GitHub account
+    List(ListArgs),
+    /// Run a function locally for testing
+    Run(RunArgs),
+    /// Unpublish a function from the server, after confirming
+    #[command(visible_alias = "remove")]
+    Unpublish(UnpublishArgs),
+    /// Restore an unpublished function from the server's trash
+    Restore(RestoreArgs),
+    /// Release a canary version of a function, or promote or abort it
+    Release(ReleaseArgs),
+    /// Rename a deployed function, keeping its history
+    Rename(RenameArgs),
+    /// Copy a deployed function into a new function owned by you
+    Clone(CloneArgs),
+    /// Offer a function to another user, or accept one offered to you
+    Transfer(TransferArgs),
+    /// Give a function other names, or redirect names to it
+    Alias(AliasArgs),
+    /// Manage long-lived API keys for CI and other automation
+    Token(TokenArgs),
+    /// Audit and revoke the logins and API keys used with your account
+    Sessions(SessionsArgs),
+    /// Review unusual deploys and traffic flagged by the server
+    Alerts(AlertsArgs),
+    /// Search what a deployed function logged
+    Logs(LogsArgs),
+    /// Show the SLSA provenance of the artifact a function runs
+    Provenance(ProvenanceArgs),
+    /// Sign a function's responses, and show the keys that verify them
+    Keys(KeysArgs),
+    /// Make a function private with an access key, rotate the key, or make it public again
+    Protect(ProtectArgs),
+    /// Show the webhooks a function queued and how their delivery went
+    Webhooks(WebhooksArgs),
+    /// Snapshot a function's memory when it traps, and download the snapshots
+    Snapshots(SnapshotsArgs),
+    /// Show whether a function's circuit breaker stopped it, and reset the breaker
+    Breaker(BreakerArgs),
+    /// Profile a sample of a canary's invocations, and download the flamegraphs
+    Profiles(ProfilesArgs),
+    /// Manage teams that own functions together
+    Team(TeamArgs),
+    /// Grant platform roles to users and teams (server admins only)
+    Role(RoleArgs),
+    /// Suspend accounts, delete functions and adjust quotas (server admins only)
+    Admin(AdminArgs),
+    /// Describe an error code, or list them all with their exit codes
+    Explain(ExplainArgs),
+    /// Print the script completing commands, flags and function names in a shell
+    Completions(CompletionsArgs),
+    /// Print the man page, or write the pages of every command to a directory
+    Man(ManArgs),
+}
+
+#[derive(Args, Debug)]
+struct CompletionsArgs {
+    /// Shell to complete in
+    #[arg(value_enum)]
+    shell: completions::Shell,
+}
+
+#[derive(Args, Debug)]
+struct ManArgs {
+    /// Directory to write a page per command to, instead of printing the main page
+    #[arg(long, value_name = "DIR")]
+    out: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ExplainArgs {
+    /// Error code printed with a failure, e.g. FAASTA-E0201
+    code: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct DeployArgs {
+    /// Paths to the projects to deploy; several are deployed together
+    #[arg(value_name = "PATH")]
+    paths: Vec<String>,
+
+    /// Deploy every function crate (cdylib) of the current cargo workspace together
+    #[arg(long, conflicts_with = "paths")]
+    workspace: bool,
+
+    /// Skip GitHub authentication
+    #[arg(long)]
+    skip_auth: bool,
+
+    /// Deploy this prebuilt component instead of the cargo project's build
+    #[arg(long = "wasm", alias = "wasm-path", value_name = "PATH")]
+    wasm_path: Option<String>,
+
+    /// Deploy the component of this OCI artifact (e.g. ghcr.io/user/fn:tag), pulled by the server
+    #[arg(long, value_name = "REFERENCE", conflicts_with = "wasm_path")]
+    from_oci: Option<String>,
+
+    /// SLSA provenance of the component (an in-toto statement or DSSE envelope)
+    #[arg(long, value_name = "PATH", conflicts_with = "from_oci")]
+    provenance: Option<PathBuf>,
+
+    /// Function name to use (if different from package name)
+    #[arg(long)]
+    function_name: Option<String>,
+
+    /// Publish the function for this team instead of your own account
+    #[arg(long)]
+    team: Option<String>,
+
+    /// Server address to deploy to [default: faasta.xyz:4433]
+    #[arg(long)]
+    server: Option<String>,
+
+    /// Have the server pre-instantiate the function at startup and after deploys
+    #[arg(long, conflicts_with = "no_keep_warm")]
+    keep_warm: bool,
+
+    /// Stop keeping the function warm
+    #[arg(long)]
+    no_keep_warm: bool,
+
+    /// Deploy even when nothing changed since the last deploy
+    #[arg(long)]
+    force: bool,
+}
+
+impl DeployArgs {
+    /// Keep-warm setting given on the command line, if any
+    fn keep_warm(&self) -> Option<bool> {
+        match (self.keep_warm, self.no_keep_warm) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("action").required(true).args(["canary", "weight", "promote", "abort"])))]
+struct ReleaseArgs {
+    /// Upload the current build as a canary receiving this percentage of requests
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    canary: Option<u8>,
+
+    /// Change the percentage of requests the existing canary receives
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    weight: Option<u8>,
+
+    /// Replace the stable version with the canary
+    #[arg(long)]
+    promote: bool,
+
+    /// Delete the canary and send all requests to the stable version
+    #[arg(long)]
+    abort: bool,
+
+    /// Function name to use (if different from package name)
+    #[arg(long)]
+    function_name: Option<String>,
+
+    /// Explicit path to WASM file (overrides automatic detection)
+    #[arg(long)]
+    wasm_path: Option<String>,
+
+    /// SLSA provenance of the canary's component
+    #[arg(long, value_name = "PATH", requires = "canary")]
+    provenance: Option<PathBuf>,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct ExportArgs {
+    /// Write a Terraform/OpenTofu starter configuration
+    #[arg(long)]
+    tf: bool,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct ApplyArgs {
+    /// JSON file holding a function definition or an array of them
+    #[arg(short, long, value_name = "PATH")]
+    file: PathBuf,
+
+    /// Only show what would change
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct PushArgs {
+    /// Where to push, e.g. ghcr.io/user/fn:tag
+    reference: String,
+
+    /// Push this component instead of the project's build
+    #[arg(long = "wasm", value_name = "PATH")]
+    wasm_path: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct BuildArgs {
+    /// Deploy the function after building
+    #[arg(short, long)]
+    deploy: bool,
+
+    /// Optimize the component for size (see `[optimize]` in faasta.toml)
+    #[arg(long)]
+    optimize: bool,
+
+    /// Run the component's initialization once and deploy the snapshot (see `[pre-init]` in faasta.toml)
+    #[arg(long)]
+    pre_init: bool,
+
+    /// Explicit path to WASM file (overrides automatic detection)
+    #[arg(long)]
+    wasm_path: Option<String>,
+
+    /// Function name to use (if different from package name)
+    #[arg(long)]
+    function_name: Option<String>,
+
+    /// Publish the function for this team instead of your own account
+    #[arg(long)]
+    team: Option<String>,
+
+    /// Server address to deploy to (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+
+    /// With --deploy, build and deploy even when nothing changed since the last deploy
+    #[arg(long, requires = "deploy")]
+    force: bool,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Port to run the local server on
+    #[arg(short, long, default_value = "3000")]
+    port: u16,
+
+    /// Replay the request of a debug snapshot downloaded with `cargo faasta snapshots --download`
+    #[arg(long, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    /// Build with debug info and run under a debug server, for setting breakpoints in the function
+    #[arg(long)]
+    debug: bool,
+
+    /// Debug server to run under
+    #[arg(long, value_enum, default_value = "lldb", requires = "debug")]
+    debugger: run::Debugger,
+
+    /// Port the debug server listens on
+    #[arg(long, default_value = "1234", requires = "debug")]
+    debug_port: u16,
+
+    /// Sample the function's stack while it serves, and write a flamegraph profile
+    #[arg(long, conflicts_with = "debug")]
+    profile: bool,
+}
+
+#[derive(Args, Debug)]
+struct InvokeArgs {
+    /// Name of the function to invoke
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: String,
+    /// Optional argument to pass to the function
+    #[arg(default_value = "")]
+    arg: String,
+}
+
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Only list functions whose name contains this text
+    #[arg(long)]
+    filter: Option<String>,
+    /// Only list functions with this tag (see `tags` in faasta.toml)
+    #[arg(long)]
+    tag: Option<String>,
+    /// Order to list functions in: name, last-deploy or invocations
+    #[arg(long, default_value = "name")]
+    sort: faasta_interface::FunctionSort,
+    /// List in the opposite order
+    #[arg(long)]
+    reverse: bool,
+    /// Page to show, from 1
+    #[arg(long, default_value = "1")]
+    page: u32,
+    /// Functions per page (0 shows them all)
+    #[arg(long, default_value = "20")]
+    limit: u32,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct UnpublishArgs {
+    /// Name of the function to unpublish
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: String,
+    /// Don't ask for confirmation, e.g. in scripts
+    #[arg(short, long)]
+    yes: bool,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct RenameArgs {
+    /// Current name of the function
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: String,
+    /// New name for the function
+    new_name: String,
+    /// Keep redirecting the old URL to the new one for this many hours
+    #[arg(long, default_value = "0")]
+    redirect_hours: u32,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("side").required(true).args(["to", "accept"])))]
+struct TransferArgs {
+    /// Function to transfer (defaults to the current project)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Offer the function to this user; it moves once they accept
+    #[arg(long, value_name = "USER")]
+    to: Option<String>,
+
+    /// Take over a function offered to you
+    #[arg(long)]
+    accept: bool,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct AliasArgs {
+    #[command(subcommand)]
+    command: AliasCommands,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433", global = true)]
+    server: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum AliasCommands {
+    /// Make ALIAS another name of a function, or redirect it to the function
+    Add {
+        /// Name to add, such as `api-v2`
+        alias: String,
+        /// Function the name leads to (defaults to the current project)
+        #[arg(long, value_name = "FUNCTION", add = ArgValueCompleter::new(completions::function_names))]
+        to: Option<String>,
+        /// Redirect to the function with this status (301 or 302) instead of
+        /// serving it under the name
+        #[arg(long, value_name = "STATUS", value_parser = clap::value_parser!(u16).range(301..=302))]
+        redirect: Option<u16>,
+    },
+    /// Remove an alias or redirect, freeing its name
+    Remove { alias: String },
+    /// List the aliases and redirects of a function
+    List {
+        /// Function to list (defaults to the current project)
+        #[arg(add = ArgValueCompleter::new(completions::function_names))]
+        name: Option<String>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct CloneArgs {
+    /// Name of the function to copy
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: String,
+    /// Name of the new function
+    new_name: String,
+    /// Also copy the function's settings and stored data
+    #[arg(long)]
+    with_data: bool,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct RestoreArgs {
+    /// Name of the function to restore
+    name: String,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct TokenArgs {
+    #[command(subcommand)]
+    command: TokenCommands,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433", global = true)]
+    server: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum TokenCommands {
+    /// Create an API key (use it by setting FAASTA_API_KEY)
+    Create {
+        /// Operations the key may perform: deploy, read or manage (repeatable)
+        #[arg(long = "scope", required = true)]
+        scopes: Vec<faasta_interface::ApiKeyScope>,
+        /// Label to recognise the key by later
+        #[arg(long, default_value = "cli")]
+        name: String,
+    },
+    /// List your API keys
+    List,
+    /// Revoke an API key by id
+    Revoke {
+        /// Id of the key, as shown by `cargo faasta token list`
+        id: String,
+    },
+}
+
+#[derive(Args, Debug)]
+struct SessionsArgs {
+    #[command(subcommand)]
+    command: SessionsCommands,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433", global = true)]
+    server: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum SessionsCommands {
+    /// List logins and API keys with when they were last used
+    List,
+    /// Revoke a login or API key by id
+    Revoke {
+        /// Id of the session, as shown by `cargo faasta sessions list`
+        id: String,
+    },
+    /// Name this machine's login so it is easy to recognise in the list
+    Label {
+        /// Device label, e.g. "work laptop"
+        label: String,
+    },
+}
+
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct LogsArgs {
+    #[command(subcommand)]
+    command: Option<LogsCommands>,
+    /// Name of the function
+    #[arg(required = true, add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+    /// Only show lines logged since this time: RFC 3339, or a duration ago like 15m, 2h or 7d
+    #[arg(long, value_parser = parse_log_time)]
+    since: Option<i64>,
+    /// Only show lines logged until this time, in the same formats as --since
+    #[arg(long, value_parser = parse_log_time)]
+    until: Option<i64>,
+    /// Least severe lines to show: debug, info, warn or error
+    #[arg(long)]
+    level: Option<faasta_interface::LogLevel>,
+    /// Only show lines logged while handling this request
+    #[arg(long)]
+    request_id: Option<String>,
+    /// Only show lines containing this text
+    #[arg(long)]
+    grep: Option<String>,
+    /// Most lines to show
+    #[arg(long, default_value = "100")]
+    limit: u32,
+    /// Page through every matching line instead of stopping at --limit
+    #[arg(long)]
+    all: bool,
+    /// Print one JSON object per line
+    #[arg(long)]
+    jsonl: bool,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433", global = true)]
+    server: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum LogsCommands {
+    /// Show or change the least severe level of lines stored from a function
+    Level {
+        /// New level: debug, info, warn or error; omit to show the current one
+        level: Option<faasta_interface::LogLevel>,
+        /// Function to change (defaults to the current project)
+        #[arg(long)]
+        function: Option<String>,
+        /// Revert to the server's default after this long, e.g. 30m or 2h
+        #[arg(long = "for", value_parser = parse_duration_secs)]
+        duration: Option<u64>,
+        /// Go back to the server's default level now
+        #[arg(long, conflicts_with_all = ["level", "duration"])]
+        reset: bool,
+    },
+    /// Show or replace the rules redacting secrets from a function's lines
+    Redact {
+        /// Function to change (defaults to the current project)
+        #[arg(long)]
+        function: Option<String>,
+        /// Redact the value of this header (repeatable)
+        #[arg(long = "header", value_name = "NAME")]
+        headers: Vec<String>,
+        /// Redact matches of this regular expression (repeatable)
+        #[arg(long = "pattern", value_name = "REGEX")]
+        patterns: Vec<String>,
+        /// Remove the function's own rules, keeping those the server enforces
+        #[arg(long, conflicts_with_all = ["headers", "patterns"])]
+        reset: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+struct AlertsArgs {
+    #[command(subcommand)]
+    command: AlertsCommands,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433", global = true)]
+    server: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum AlertsCommands {
+    /// List unresolved alerts about your account, functions and teams
+    List {
+        /// Show every account's alerts (server admins only)
+        #[arg(long)]
+        all: bool,
+    },
+    /// Mark an alert as reviewed
+    Resolve {
+        /// Id of the alert, as shown by `cargo faasta alerts list`
+        id: u64,
+    },
+}
+
+#[derive(Args, Debug)]
+struct TeamArgs {
+    #[command(subcommand)]
+    command: TeamCommands,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433", global = true)]
+    server: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum TeamCommands {
+    /// Create a team with you as its owner
+    Create {
+        /// Name of the team
+        name: String,
+        /// GitHub organization whose members also count as team members
+        #[arg(long)]
+        github_org: Option<String>,
+    },
+    /// List the teams you belong to
+    List,
+    /// Add a member to a team, or change their role
+    Add {
+        /// Name of the team
+        team: String,
+        /// GitHub username of the member
+        username: String,
+        /// Role of the member: viewer, developer or owner
+        #[arg(long, default_value = "developer")]
+        role: faasta_interface::TeamRole,
+    },
+    /// Remove a member from a team (or leave it)
+    Remove {
+        /// Name of the team
+        team: String,
+        /// GitHub username of the member
+        username: String,
+    },
+    /// Delete a team that no longer owns any functions
+    Delete {
+        /// Name of the team
+        team: String,
+    },
+}
+
+#[derive(Args, Debug)]
+struct RoleArgs {
+    #[command(subcommand)]
+    command: RoleCommands,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433", global = true)]
+    server: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum RoleCommands {
+    /// Grant a role to a user, or to a team as `team:<name>`
+    Grant {
+        /// GitHub username, or `team:<name>`
+        principal: String,
+        /// Role to grant: viewer, deployer or admin
+        role: faasta_interface::PlatformRole,
+    },
+    /// Clear a granted role, falling back to the server's default role
+    Revoke {
+        /// GitHub username, or `team:<name>`
+        principal: String,
+    },
+    /// List granted roles
+    List,
+}
+
+#[derive(Args, Debug)]
+struct AdminArgs {
+    #[command(subcommand)]
+    command: AdminCommands,
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433", global = true)]
+    server: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum AdminCommands {
+    /// Suspend an account and stop serving its functions
+    Suspend {
+        /// GitHub username of the account
+        username: String,
+        /// Reason shown to the user when they try to authenticate
+        #[arg(long, default_value = "Suspended by an administrator")]
+        reason: String,
+    },
+    /// Lift an account's suspension
+    Unsuspend {
+        /// GitHub username of the account
+        username: String,
+    },
+    /// Stop invoking a function at once; its requests are answered 503
+    Kill {
+        /// Name of the function
+        name: String,
+        /// Reason recorded in the journal and shown to the owner
+        #[arg(long, default_value = "Killed by an administrator")]
+        reason: String,
+    },
+    /// Permanently delete any user's function, skipping the trash
+    Delete {
+        /// Name of the function
+        name: String,
+    },
+    /// Show the audit log of control-plane calls
+    Audit {
+        /// Only show events after this id
+        #[arg(long)]
+        after: Option<u64>,
+        /// Most events to show
+        #[arg(long, default_value = "100")]
+        limit: u32,
+        /// Page through the whole log instead of stopping at --limit
+        #[arg(long)]
+        all: bool,
+        /// Print one JSON object per line, for compliance tooling
+        #[arg(long)]
+        jsonl: bool,
+    },
+    /// Show the journal of significant platform events
+    Events {
+        /// Only show events after this id
+        #[arg(long)]
+        after: Option<u64>,
+        /// Most events to show
+        #[arg(long, default_value = "100")]
+        limit: u32,
+        /// Page through the whole journal instead of stopping at --limit
+        #[arg(long)]
+        all: bool,
+        /// Least severe events to show: info, warning or error
+        #[arg(long, default_value = "info")]
+        severity: faasta_interface::EventSeverity,
+        /// Only show events of this kind, e.g. canary-promoted
+        #[arg(long)]
+        kind: Option<faasta_interface::ServerEventKind>,
+        /// Print one JSON object per line
+        #[arg(long)]
+        jsonl: bool,
+    },
+    /// Show where function metadata, artifact storage, redirects and project lists disagree
+    Consistency {
+        /// Run a check now instead of showing the last one
+        #[arg(long)]
+        check: bool,
+        /// Run a check now and repair what it can of the given kinds of drift, e.g.
+        /// stale-project,stale-redirect, or of every kind if none are given
+        #[arg(long, num_args = 0.., value_delimiter = ',')]
+        repair: Option<Vec<faasta_interface::DriftKind>>,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show where the server's time, memory and disk go, and how fast they grow
+    Capacity {
+        /// Make a report now instead of showing the latest one
+        #[arg(long)]
+        refresh: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove orphaned artifacts, stale versions and caches, and expired trash and logs
+    Gc {
+        /// Only report what would be removed
+        #[arg(long)]
+        dry_run: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the nodes of the cluster the server belongs to
+    Nodes,
+    /// Take a consistent backup of the server's database and artifacts and download it
+    Backup {
+        /// Tar file to write the backup to (defaults to its name on the server)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Change how many functions a user may own
+    SetLimit {
+        /// GitHub username of the account
+        username: String,
+        /// New limit; omit to reset to the server default
+        limit: Option<u32>,
+    },
+    /// Export every account's usage in a billing period, for invoicing
+    Usage {
+        /// Billing period as YYYY-MM (defaults to the current month)
+        #[arg(long)]
+        period: Option<String>,
+        /// Export as csv or json
+        #[arg(long, default_value = "csv")]
+        format: faasta_interface::UsageFormat,
+        /// File to write the export to (defaults to standard output)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct ProvenanceArgs {
+    /// Function to show (defaults to the current project)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Print the provenance document as uploaded
+    #[arg(long)]
+    raw: bool,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("change").args(["enable", "rotate", "disable"])))]
+struct KeysArgs {
+    /// Function whose keys to show (defaults to the current project)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Sign the function's responses, generating a key if it has none
+    #[arg(long)]
+    enable: bool,
+
+    /// Replace the signing key. The old key keeps signing for a day.
+    #[arg(long)]
+    rotate: bool,
+
+    /// Stop signing the function's responses and delete its keys
+    #[arg(long)]
+    disable: bool,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct StatusArgs {
+    /// Function to show (defaults to the current project)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Print the status as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct ProtectArgs {
+    /// Function to protect (defaults to the current project)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Make the function public again, deleting its access key
+    #[arg(long)]
+    remove: bool,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct BreakerArgs {
+    /// Function whose breaker to show (defaults to the current project)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Invoke the function again after its breaker tripped
+    #[arg(long)]
+    reset: bool,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct MetricsArgs {
+    /// Function to show invocations, errors and latency percentiles of (all functions if omitted)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Show how the function's memory trended over its recent invocations
+    #[arg(long, requires = "name")]
+    memory: bool,
+
+    /// Print the metrics as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct WebhooksArgs {
+    /// Function whose deliveries to show (defaults to the current project)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Only show deliveries that are pending, delivered or failed
+    #[arg(long, conflicts_with = "redeliver")]
+    status: Option<faasta_interface::DeliveryStatus>,
+
+    /// Queue the delivery with this id again, e.g. after fixing the receiver
+    #[arg(long, value_name = "ID")]
+    redeliver: Option<String>,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("change").args(["enable", "disable", "download"])))]
+struct SnapshotsArgs {
+    /// Function whose snapshots to show (defaults to the current project)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Snapshot the function's memory and request whenever it traps
+    #[arg(long)]
+    enable: bool,
+
+    /// Stop snapshotting the function and delete its snapshots
+    #[arg(long)]
+    disable: bool,
+
+    /// Download the snapshot with this id, to replay with `cargo faasta run --replay`
+    #[arg(long, value_name = "ID")]
+    download: Option<String>,
+
+    /// Directory to download the snapshot into (defaults to its id)
+    #[arg(short, long, requires = "download")]
+    output: Option<PathBuf>,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("change").args(["sample_rate", "disable", "download"])))]
+struct ProfilesArgs {
+    /// Function whose profiles to show (defaults to the current project)
+    #[arg(add = ArgValueCompleter::new(completions::function_names))]
+    name: Option<String>,
+
+    /// Profile this share (0 to 1) of the invocations of the function's canary
+    #[arg(long, value_name = "RATE")]
+    sample_rate: Option<f64>,
+
+    /// Stop profiling the function and delete its profiles
+    #[arg(long)]
+    disable: bool,
+
+    /// Download the profile with this id, to open at https://profiler.firefox.com
+    #[arg(long, value_name = "ID")]
+    download: Option<String>,
+
+    /// File to download the profile to (defaults to <ID>.json)
+    #[arg(short, long, requires = "download")]
+    output: Option<PathBuf>,
+
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+#[derive(Args, Debug)]
+struct ServerArgs {
+    /// Server address (e.g., "faasta.xyz:4433")
+    #[arg(long, default_value = "faasta.xyz:4433")]
+    server: String,
+}
+
+/// Custom styling for the CLI
+pub const CLAP_STYLING: clap::builder::styling::Styles = clap::builder::styling::Styles::styled()
+    .header(clap_cargo::style::HEADER)
+    .usage(clap_cargo::style::USAGE)
+    .literal(clap_cargo::style::LITERAL)
+    .placeholder(clap_cargo::style::PLACEHOLDER)
+    .error(clap_cargo::style::ERROR)
+    .valid(clap_cargo::style::VALID)
+    .invalid(clap_cargo::style::INVALID);
+
+/// Formats the function URL based on the server URL
+/// If server is a domain (not localhost or an IP), it uses function_name as a subdomain
+/// Otherwise, it appends function_name as a path
+fn format_function_url(function_name: &str, server: &str) -> String {
+    // Ensure server has a scheme
+    let server_url = if !server.contains("://") {
+        format!("https://{server}")
+    } else {
+        server.to_string()
+    };
+
+    // Parse the server URL to get the hostname
+    // Format: scheme://host/path
+    let url_parts: Vec<&str> = server_url.split("://").collect();
+    if url_parts.len() != 2 {
+        // If URL doesn't follow the expected format, fall back to a simple approach
+        return format!("https://{server}/{function_name}");
+    }
+
+    let scheme = url_parts[0];
+    let rest = url_parts[1];
+
+    // Split host and path
+    let host_path_parts: Vec<&str> = rest.split('/').collect();
+    let host = host_path_parts[0];
+
+    // Check if host is localhost or an IP address
+    if host == "localhost" || host == "127.0.0.1" || is_ip_address(host) {
+        // For localhost or IP, append function_name as a path
+        let base = if server_url.ends_with('/') {
+            server_url
+        } else {
+            format!("{server_url}/")
+        };
+        format!("{base}{function_name}")
+    } else {
+        // For a domain name, use function_name as a subdomain
+        format!("{scheme}://{function_name}.{host}/")
+    }
+}
+
+/// Extract the server host from a server address (removing any port)
+fn extract_server_host(server_addr: &str) -> String {
+    // If it already has a scheme, use it as is
+    if server_addr.contains("://") {
+        return server_addr.to_string();
+    }
+
+    // Remove port if present
+    if let Some(host) = server_addr.split(':').next() {
+        format!("https://{host}")
+    } else {
+        format!("https://{server_addr}")
+    }
+}
+
+/// Check if a host string is an IP address
+fn is_ip_address(host: &str) -> bool {
+    host.parse::<std::net::IpAddr>().is_ok()
+}
+
+async fn invoke_function(name: &str, arg: &str) -> Result<(), reqwest::Error> {
+    let function_url = format_function_url(name, DEFAULT_INVOKE_URL);
+    let invoke_url = if function_url.ends_with('/') {
+        format!("{function_url}{arg}")
+    } else {
+        format!("{function_url}/{arg}")
+    };
+
+    println!("Invoking function at: {invoke_url}");
+
+    // Create a client that accepts invalid certificates (for testing)
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+
+    // Make sure we're using HTTPS
+    let https_url = if !invoke_url.starts_with("https://") && !invoke_url.starts_with("http://") {
+        format!("https://{invoke_url}")
+    } else if invoke_url.starts_with("http://") {
+        invoke_url.replace("http://", "https://")
+    } else {
+        invoke_url
+    };
+
+    let resp = client.get(https_url).send().await?;
+    println!("Response status: {}", resp.status());
+    println!("{}", resp.text().await?);
+    Ok(())
+}
+
+/// Show one function's invocations, errors and latency percentiles, or its
+/// memory trend
+async fn show_function_metrics(
+    client: &faasta_interface::FunctionServiceClient,
+    name: String,
+    memory: bool,
+    json: bool,
+    username: &str,
+    token: &str,
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let stats = client
+        .get_function_metrics(tarpc::context::current(), name, auth_token)
+        .await?
+        .map_err(server_error)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    if memory {
+        show_memory_trend(&stats);
+        return Ok(());
+    }
+
+    let latency = |ms: Option<u64>| ms.map_or("-".to_string(), |ms| format!("{ms} ms"));
+    let error_rate = if stats.invocations > 0 {
+        format!(
+            " ({:.1}%)",
+            stats.errors as f64 * 100.0 / stats.invocations as f64
+        )
+    } else {
+        String::new()
+    };
+    println!("Function      {}", stats.function_name);
+    println!("Invocations   {}", stats.invocations);
+    println!("Errors        {}{error_rate}", stats.errors);
+    println!("Latency p50   {}", latency(stats.p50_ms));
+    println!("Latency p95   {}", latency(stats.p95_ms));
+    println!("Latency p99   {}", latency(stats.p99_ms));
+    if stats.latency_samples > 0 {
+        println!(
+            "              over the last {} invocations",
+            stats.latency_samples
+        );
+    }
+    println!(
+        "Last invoked  {}",
+        stats.last_invoked.as_deref().unwrap_or("never")
+    );
+    Ok(())
+}
+
+fn show_memory_trend(stats: &faasta_interface::FunctionStats) {
+    let Some(memory) = &stats.memory else {
+        println!(
+            "No memory measured for '{}' yet; invoke it and try again",
+            stats.function_name
+        );
+        return;
+    };
+    let mib = |bytes: u64| format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0));
+    println!("Function      {}", stats.function_name);
+    println!("Memory now    {}", mib(memory.latest_bytes));
+    println!(
+        "Range         {} - {}",
+        mib(memory.min_bytes),
+        mib(memory.max_bytes)
+    );
+    println!("              over the last {} invocations", memory.samples);
+    println!(
+        "Growth        {}{}",
+        if memory.growth_bytes < 0 { "-" } else { "+" },
+        mib(memory.growth_bytes.unsigned_abs())
+    );
+    println!("              of the instance that served the latest one");
+    if memory.only_grows {
+        println!("⚠️  That instance's memory only ever grew; the function may be leaking");
+    }
+}
+
+// Function to fetch and display metrics
+async fn get_metrics(
+    client: &faasta_interface::FunctionServiceClient,
+    json: bool,
+    username: &str,
+    token: &str,
+) -> anyhow::Result<()> {
+    // Create auth token (username:token format)
+    let auth_token = format!("{username}:{token}");
+
+    if !json {
+        println!("Fetching metrics from server...");
+    }
+
+    // Call the get_metrics RPC
+    match client
+        .get_metrics(tarpc::context::current(), auth_token)
+        .await
+    {
+        Ok(Ok(metrics)) if json => {
+            println!("{}", serde_json::to_string_pretty(&metrics)?);
+            Ok(())
+        }
+        Ok(Ok(metrics)) => {
+            // Print summary
+            println!("\n╔══════════════════════════════════════════════════════");
+            println!("║ FAASTA FUNCTION METRICS");
+            println!("╠══════════════════════════════════════════════════════");
+            println!("║ Total Function Calls: {}", metrics.total_calls);
+
+            // Format total execution time nicely
+            let total_time = if metrics.total_time > 60000 {
+                format!("{:.2} minutes", metrics.total_time as f64 / 60000.0)
+            } else if metrics.total_time > 1000 {
+                format!("{:.2} seconds", metrics.total_time as f64 / 1000.0)
+            } else {
+                format!("{} ms", metrics.total_time)
+            };
+
+            println!("║ Total Execution Time: {total_time}");
+            println!("║ Functions Deployed: {}", metrics.function_metrics.len());
+            println!("╠══════════════════════════════════════════════════════");
+
+            // If we have no functions, show a message
+            if metrics.function_metrics.is_empty() {
+                println!("║ No function metrics available.");
+                println!("╚══════════════════════════════════════════════════════");
+                return Ok(());
+            }
+
+            // Print detailed metrics for each function
+            println!("║ FUNCTION DETAILS");
+            println!("╠══════════════════════════════════════════════════════");
+
+            for function in metrics.function_metrics {
+                println!("║ Function: {}", function.function_name);
+                println!("║ ├─ Call Count: {}", function.call_count);
+
+                // Format execution time nicely
+                let exec_time = if function.total_time_millis > 60000 {
+                    format!("{:.2} minutes", function.total_time_millis as f64 / 60000.0)
+                } else if function.total_time_millis > 1000 {
+                    format!("{:.2} seconds", function.total_time_millis as f64 / 1000.0)
+                } else {
+                    format!("{} ms", function.total_time_millis)
+                };
+
+                println!("║ ├─ Total Execution Time: {exec_time}");
+
+                // Format average time per call
+                let avg_time = if function.call_count > 0 {
+                    format!(
+                        "{:.2} ms",
+                        function.total_time_millis as f64 / function.call_count as f64
+                    )
+                } else {
+                    "N/A".to_string()
+                };
+
+                println!("║ ├─ Average Time per Call: {avg_time}");
+                println!("║ └─ Last Called: {}", function.last_called);
+                println!("╟──────────────────────────────────────────────────────");
+            }
+            println!("╚══════════════════════════════════════════════════════");
+            Ok(())
+        }
+        Ok(Err(e)) => Err(server_error(e)),
+        Err(e) => Err(anyhow::anyhow!("Communication error: {}", e)),
+    }
+}
+
+// Function to fetch and display list of functions
+async fn list_functions(
+    client: &faasta_interface::FunctionServiceClient,
+    username: &str,
+    token: &str,
+    query: faasta_interface::FunctionQuery,
+) -> anyhow::Result<()> {
+    // Create auth token (username:token format)
+    let auth_token = format!("{username}:{token}");
+
+    println!("Fetching functions for GitHub user: {username}...");
+
+    // Call the list_functions RPC
+    let limit = query.limit;
+    match client
+        .list_functions(tarpc::context::current(), query, auth_token)
+        .await
+    {
+        Ok(Ok(page)) => {
+            if page.total == 0 {
+                println!("\nNo functions deployed under this GitHub account.");
+                println!("Use 'cargo faasta deploy' to deploy a function.");
+                return Ok(());
+            }
+            if page.matching == 0 {
+                println!("\nNone of your {} functions match the filters.", page.total);
+                return Ok(());
+            }
+            if page.functions.is_empty() {
+                println!(
+                    "\nPage {} is past the end; the {} matching functions fit on fewer pages.",
+                    page.page, page.matching
+                );
+                return Ok(());
+            }
+
+            // Print header
+            let first = (page.page as u64 - 1) * limit as u64 + 1;
+            let last = first + page.functions.len() as u64 - 1;
+            println!("\n╔══════════════════════════════════════════════════════");
+            println!("║ FUNCTIONS DEPLOYED BY {}", username.to_uppercase());
+            println!("╠══════════════════════════════════════════════════════");
+            println!("║ Total Functions: {}", page.total);
+            if page.matching < page.total {
+                println!("║ Matching Filters: {}", page.matching);
+            }
+            if limit > 0 && page.matching > limit as u64 {
+                println!("║ Showing: {first}-{last} (page {})", page.page);
+            }
+            println!("╠══════════════════════════════════════════════════════");
+
+            for function in page.functions {
+                println!("║ Function: {}", function.name);
+                let metadata = &function.metadata;
+                if !metadata.description.is_empty() {
+                    println!("║ ├─ Description: {}", metadata.description);
+                }
+                if !metadata.tags.is_empty() {
+                    println!("║ ├─ Tags: {}", metadata.tags.join(", "));
+                }
+                if !metadata.routes.is_empty() {
+                    println!("║ ├─ Routes: {}", metadata.routes.join(", "));
+                }
+
+                // Parse the published_at date for pretty formatting
+                println!("║ ├─ Published: {}", function.published_at);
+
+                // URL
+                println!("║ ├─ URL: {}", function.usage);
+
+                // Add a command to invoke it
+                println!("║ └─ Invoke: cargo faasta invoke {}", function.name);
+                println!("╟──────────────────────────────────────────────────────");
+            }
+            println!("╚══════════════════════════════════════════════════════");
+            if limit > 0 && last < page.matching {
+                println!(
+                    "More functions follow; see them with --page {}",
+                    page.page + 1
+                );
+            }
+
+            Ok(())
+        }
+        Ok(Err(e)) => Err(server_error(e)),
+        Err(e) => Err(anyhow::anyhow!("Communication error: {}", e)),
+    }
+}
+
+/// Parse a `--since`/`--until` time into Unix milliseconds: RFC 3339, or a
+/// duration before now such as `90s`, `15m`, `2h` or `7d`
+fn parse_log_time(value: &str) -> Result<i64, String> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp_millis());
+    }
+    let seconds = parse_duration_secs(value)
+        .map_err(|e| format!("expected RFC 3339 or a duration like 15m: {e}"))?;
+    i64::try_from(seconds)
+        .ok()
+        .and_then(|seconds| seconds.checked_mul(1000))
+        .and_then(|millis| chrono::Utc::now().timestamp_millis().checked_sub(millis))
+        .ok_or_else(|| format!("duration '{value}' is too long"))
+}
+
+/// Parse a duration such as `90s`, `15m`, `2h` or `7d` into seconds
+fn parse_duration_secs(value: &str) -> Result<u64, String> {
+    let units = value.trim_start_matches(|c: char| c.is_ascii_digit());
+    let amount: u64 = value[..value.len() - units.len()]
+        .parse()
+        .map_err(|_| format!("invalid duration '{value}'"))?;
+    let seconds = match units {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit '{units}' (expected s, m, h or d)")),
+    };
+    amount
+        .checked_mul(seconds)
+        .ok_or_else(|| format!("duration '{value}' is too long"))
+}
+
+/// Name of the function in the current directory: the one in faasta.toml, or the
+/// cargo package's
+fn current_function_name() -> anyhow::Result<String> {
+    let project = project::load(&std::env::current_dir()?)?;
+    match project.function.name {
+        Some(name) => Ok(name),
+        None => Ok(run::get_project_info()?.1),
+    }
+}
+
+/// Componentize a JavaScript or Python project, exiting if that fails
+fn build_foreign_project(project: &componentize::ForeignProject) -> PathBuf {
+    println!(
+        "Componentizing {} project '{}'",
+        project.language, project.name
+    );
+    match project.build() {
+        Ok(path) => {
+            println!("✅ Build successful!");
+            path
+        }
+        Err(e) => {
+            eprintln!("Failed to build project: {e:#}");
+            errors::exit(&errors::BUILD_FAILED);
+        }
+    }
+}
+
+/// Whether `wasm` is a WebAssembly component rather than a core module
+fn is_component(wasm: &[u8]) -> bool {
+    // The magic number, then the component encoding's version and layer
+    wasm.starts_with(b"\0asm\x0d\0\x01\0")
+}
+
+/// Where `cargo faasta build` leaves a package's component. The compiler turns
+/// hyphens in the package name into underscores.
+fn compiled_wasm_path(target_directory: &std::path::Path, package_name: &str) -> PathBuf {
+    target_directory
+        .join("wasm32-wasip2")
+        .join("release")
+        .join(format!("{}.wasm", package_name.replace('-', "_")))
+}
+
+/// Read the provenance file passed with --provenance, if any
+fn read_provenance(path: Option<&PathBuf>) -> Option<Vec<u8>> {
+    let path = path?;
+    match fs::read(path) {
+        Ok(document) => Some(document),
+        Err(e) => {
+            eprintln!("Failed to read provenance at {}: {e}", path.display());
+            errors::exit(&errors::ARTIFACT_MISSING);
+        }
+    }
+}
+
+// Publish a function for the caller, or for a team when `team` is set
+#[allow(clippy::too_many_arguments)]
+async fn publish_function(
+    client: &faasta_interface::FunctionServiceClient,
+    info: &faasta_interface::ServerInfo,
+    wasm_data: Vec<u8>,
+    provenance: Option<Vec<u8>>,
+    function_name: String,
+    team: Option<String>,
+    keep_warm: Option<bool>,
+    metadata: Option<faasta_interface::FunctionMetadata>,
+    auth_token: String,
+) -> Result<faasta_interface::FunctionResult<String>, tarpc::client::RpcError> {
+    let target = match team {
+        Some(team) => faasta_interface::PublishTarget::Team(team),
+        None => faasta_interface::PublishTarget::Function,
+    };
+    publish_upload(
+        client,
+        info,
+        &wasm_data,
+        provenance,
+        function_name,
+        target,
+        keep_warm,
+        metadata,
+        auth_token,
+    )
+    .await
+}
+
+/// Upload a component in chunks, then publish it as `target`
+#[allow(clippy::too_many_arguments)]
+async fn publish_upload(
+    client: &faasta_interface::FunctionServiceClient,
+    info: &faasta_interface::ServerInfo,
+    wasm_data: &[u8],
+    provenance: Option<Vec<u8>>,
+    function_name: String,
+    target: faasta_interface::PublishTarget,
+    keep_warm: Option<bool>,
+    metadata: Option<faasta_interface::FunctionMetadata>,
+    auth_token: String,
+) -> Result<faasta_interface::FunctionResult<String>, tarpc::client::RpcError> {
+    // Fail here rather than after the upload when the server would refuse the component
+    if let Err(e) = preflight::check(info, wasm_data) {
+        return Ok(Err(e));
+    }
+    let upload_id = match upload_delta(client, wasm_data, &function_name, &auth_token).await? {
+        Ok(upload_id) => upload_id,
+        Err(e) => return Ok(Err(e)),
+    };
+    if let Some(document) = provenance {
+        if let Err(e) = client
+            .attach_provenance(
+                tarpc::context::current(),
+                upload_id.clone(),
+                document,
+                auth_token.clone(),
+            )
+            .await?
+        {
+            return Ok(Err(e));
+        }
+    }
+
+    let publish = client.publish_upload(
+        publish_context(),
+        upload_id,
+        function_name.clone(),
+        target,
+        keep_warm,
+        metadata,
+        auth_token.clone(),
+    );
+    with_queue_position(client, &function_name, &auth_token, publish).await
+}
+
+/// Upload `data` in chunks, returning the upload's id
+async fn upload_in_chunks(
+    client: &faasta_interface::FunctionServiceClient,
+    data: &[u8],
+    auth_token: &str,
+) -> Result<faasta_interface::FunctionResult<String>, tarpc::client::RpcError> {
+    // The server checks the size against its limit before anything is sent
+    let upload_id = match client
+        .begin_upload(
+            tarpc::context::current(),
+            data.len() as u64,
+            auth_token.to_string(),
+        )
+        .await?
+    {
+        Ok(upload_id) => upload_id,
+        Err(e) => return Ok(Err(e)),
+    };
+    if let Err(e) = send_bytes(client, &upload_id, 0, data, auth_token).await? {
+        return Ok(Err(e));
+    }
+    Ok(Ok(upload_id))
+}
+
+/// Upload `data` to publish as `name`, sending only the chunks its deployed
+/// artifact doesn't have, and return the upload's id
+async fn upload_delta(
+    client: &faasta_interface::FunctionServiceClient,
+    data: &[u8],
+    name: &str,
+    auth_token: &str,
+) -> Result<faasta_interface::FunctionResult<String>, tarpc::client::RpcError> {
+    use faasta_interface::chunking;
+
+    let delta = match client
+        .begin_delta_upload(
+            tarpc::context::current(),
+            data.len() as u64,
+            name.to_string(),
+            auth_token.to_string(),
+        )
+        .await?
+    {
+        Ok(delta) => delta,
+        Err(e) => return Ok(Err(e)),
+    };
+    let base: std::collections::HashSet<String> = delta.base_chunks.into_iter().collect();
+    let upload_id = delta.upload_id;
+
+    // Runs of chunks are sent together: known ones by digest, new ones as bytes
+    enum Run {
+        Known(Vec<String>, std::ops::Range<usize>),
+        New(std::ops::Range<usize>),
+    }
+    let mut runs: Vec<Run> = Vec::new();
+    for range in chunking::chunks(data) {
+        let digest = chunking::digest(&data[range.clone()]);
+        match (runs.last_mut(), base.contains(&digest)) {
+            (Some(Run::Known(digests, bytes)), true) => {
+                digests.push(digest);
+                bytes.end = range.end;
+            }
+            (Some(Run::New(bytes)), false) => bytes.end = range.end,
+            (_, true) => runs.push(Run::Known(vec![digest], range)),
+            (_, false) => runs.push(Run::New(range)),
+        }
+    }
+    for run in runs {
+        let sent = match run {
+            Run::Known(digests, bytes) => {
+                let send = || {
+                    client.upload_known_chunks_at(
+                        tarpc::context::current(),
+                        upload_id.clone(),
+                        bytes.start as u64,
+                        digests.clone(),
+                        auth_token.to_string(),
+                    )
+                };
+                retry_timed_out(send).await?
+            }
+            Run::New(bytes) => {
+                let offset = bytes.start as u64;
+                send_bytes(client, &upload_id, offset, &data[bytes], auth_token).await?
+            }
+        };
+        if let Err(e) = sent {
+            return Ok(Err(e));
+        }
+    }
+    Ok(Ok(upload_id))
+}
+
+/// Append `data`, which starts at byte `offset` of an upload, in chunks of at
+/// most `UPLOAD_CHUNK_SIZE` bytes
+async fn send_bytes(
+    client: &faasta_interface::FunctionServiceClient,
+    upload_id: &str,
+    offset: u64,
+    data: &[u8],
+    auth_token: &str,
+) -> Result<faasta_interface::FunctionResult<()>, tarpc::client::RpcError> {
+    let mut offset = offset;
+    for chunk in data.chunks(faasta_interface::UPLOAD_CHUNK_SIZE) {
+        let send = || {
+            client.upload_chunk_at(
+                tarpc::context::current(),
+                upload_id.to_string(),
+                offset,
+                chunk.to_vec(),
+                auth_token.to_string(),
+            )
+        };
+        if let Err(e) = retry_timed_out(send).await? {
+            return Ok(Err(e));
+        }
+        offset += chunk.len() as u64;
+    }
+    Ok(Ok(()))
+}
+
+/// Make the call `send` again if it timed out, once. Only for calls the server
+/// skips when they were already made, such as appending at an offset.
+async fn retry_timed_out<T, F>(send: impl Fn() -> F) -> Result<T, tarpc::client::RpcError>
+where
+    F: std::future::Future<Output = Result<T, tarpc::client::RpcError>>,
+{
+    match send().await {
+        Err(tarpc::client::RpcError::DeadlineExceeded) => send().await,
+        result => result,
+    }
+}
+
+/// The routes and components of a project's `[[routes]]`
+fn read_route_handlers(
+    project_dir: &std::path::Path,
+    routes: Vec<project::RouteSettings>,
+) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    faasta_interface::validate_routes(routes.iter().map(|route| route.route.as_str()))
+        .map_err(anyhow::Error::msg)?;
+    routes
+        .into_iter()
+        .map(|route| {
+            let path = project_dir.join(&route.wasm);
+            let wasm = fs::read(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", path.display()))?;
+            if !is_component(&wasm) {
+                anyhow::bail!("{} is not a WebAssembly component", path.display());
+            }
+            Ok((route.route, wasm))
+        })
+        .collect()
+}
+
+/// The description, tags and routes the project declares, empty when it declares
+/// none so that removing them from faasta.toml removes them from the function
+fn function_metadata(
+    project: &project::FunctionSettings,
+    routes: Option<&[(String, Vec<u8>)]>,
+) -> faasta_interface::FunctionMetadata {
+    faasta_interface::FunctionMetadata {
+        description: project.description.clone().unwrap_or_default(),
+        tags: project.tags.clone().unwrap_or_default(),
+        routes: routes
+            .unwrap_or_default()
+            .iter()
+            .map(|(route, _)| route.clone())
+            .collect(),
+    }
+}
+
+/// Stage the project's `[[routes]]` as the route handlers of `function_name`, for
+/// the publish that follows to swap them in with the main component. Projects
+/// without any are left alone. Returns what's published with the function.
+async fn stage_routes(
+    client: &faasta_interface::FunctionServiceClient,
+    routes: Option<Vec<(String, Vec<u8>)>>,
+    function_name: &str,
+    auth_token: &str,
+) -> anyhow::Result<Option<String>> {
+    let Some(routes) = routes else {
+        return Ok(None);
+    };
+    let mut uploads = Vec::with_capacity(routes.len());
+    for (route, wasm) in routes {
+        let upload_id = upload_in_chunks(client, &wasm, auth_token).await??;
+        uploads.push(faasta_interface::RouteUpload { route, upload_id });
+    }
+    client
+        .stage_routes(
+            publish_context(),
+            function_name.to_string(),
+            uploads.clone(),
+            auth_token.to_string(),
+        )
+        .await??;
+    let routes: Vec<String> = uploads.into_iter().map(|upload| upload.route).collect();
+    Ok(Some(if routes.is_empty() {
+        format!("Removed the route handlers of '{function_name}'")
+    } else {
+        format!("Published handlers for {}", routes.join(", "))
+    }))
+}
+
+/// Files under a project's `static/` directory, or `None` if it has none
+fn read_static_assets(
+    project_dir: &std::path::Path,
+) -> anyhow::Result<Option<Vec<faasta_interface::StaticAsset>>> {
+    fn walk(
+        dir: &std::path::Path,
+        prefix: &str,
+        assets: &mut Vec<faasta_interface::StaticAsset>,
+    ) -> anyhow::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = format!("{prefix}{name}");
+            if entry.path().is_dir() {
+                walk(&entry.path(), &format!("{path}/"), assets)?;
+            } else {
+                faasta_interface::validate_static_path(&path).map_err(anyhow::Error::msg)?;
+                assets.push(faasta_interface::StaticAsset {
+                    path,
+                    content: fs::read(entry.path())?,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    let dir = project_dir.join(faasta_interface::STATIC_ASSETS_DIR);
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let mut assets = Vec::new();
+    walk(&dir, "", &mut assets)?;
+    if assets.len() > faasta_interface::MAX_STATIC_ASSETS {
+        anyhow::bail!(
+            "{} has {} files, more than the {} allowed",
+            dir.display(),
+            assets.len(),
+            faasta_interface::MAX_STATIC_ASSETS
+        );
+    }
+    Ok(Some(assets))
+}
+
+/// Replace the static assets of `function_name` with those in the project's
+/// `static/` directory, removing them if it's empty. Projects without one are left
+/// alone.
+async fn publish_static_assets(
+    client: &faasta_interface::FunctionServiceClient,
+    project_dir: &std::path::Path,
+    function_name: &str,
+    auth_token: &str,
+) -> anyhow::Result<Option<String>> {
+    let Some(assets) = read_static_assets(project_dir)? else {
+        return Ok(None);
+    };
+    let upload_id = if assets.is_empty() {
+        None
+    } else {
+        let bundle = faasta_interface::StaticAsset::encode_bundle(&assets);
+        Some(upload_in_chunks(client, &bundle, auth_token).await??)
+    };
+    let message = client
+        .publish_static_assets(
+            tarpc::context::current(),
+            function_name.to_string(),
+            upload_id,
+            auth_token.to_string(),
+        )
+        .await??;
+    Ok(Some(message))
+}
+
+/// Set the CORS policy of `function_name` to the project's `[cors]`, removing it
+/// when it allows no origins. Projects without the section are left alone.
+async fn set_cors_policy(
+    client: &faasta_interface::FunctionServiceClient,
+    cors: Option<faasta_interface::CorsPolicy>,
+    function_name: &str,
+    auth_token: &str,
+) -> anyhow::Result<Option<String>> {
+    let Some(cors) = cors else {
+        return Ok(None);
+    };
+    let policy = (!cors.allowed_origins.is_empty()).then_some(cors);
+    let message = client
+        .set_cors_policy(
+            tarpc::context::current(),
+            function_name.to_string(),
+            policy,
+            auth_token.to_string(),
+        )
+        .await??;
+    Ok(Some(message))
+}
+
+/// Require the tokens of the project's `[auth]` on requests to `function_name`,
+/// removing the requirement when it names no issuer. Projects without the section
+/// are left alone.
+async fn set_jwt_auth(
+    client: &faasta_interface::FunctionServiceClient,
+    auth: Option<faasta_interface::JwtAuthPolicy>,
+    function_name: &str,
+    auth_token: &str,
+) -> anyhow::Result<Option<String>> {
+    let Some(auth) = auth else {
+        return Ok(None);
+    };
+    let policy = (!auth.issuer.is_empty()).then_some(auth);
+    let message = client
+        .set_jwt_auth(
+            tarpc::context::current(),
+            function_name.to_string(),
+            policy,
+            auth_token.to_string(),
+        )
+        .await??;
+    Ok(Some(message))
+}
+
+/// Replace the transformation rules of `function_name` with the project's
+/// `[[transforms]]`. Projects without any are left alone.
+async fn set_transforms(
+    client: &faasta_interface::FunctionServiceClient,
+    transforms: Option<Vec<faasta_interface::TransformRule>>,
+    function_name: &str,
+    auth_token: &str,
+) -> anyhow::Result<Option<String>> {
+    let Some(rules) = transforms else {
+        return Ok(None);
+    };
+    let message = client
+        .set_transforms(
+            tarpc::context::current(),
+            function_name.to_string(),
+            rules,
+            auth_token.to_string(),
+        )
+        .await??;
+    Ok(Some(message))
+}
+
+/// Push the project's component, or the one given with --wasm, to a registry
+async fn push_component(args: PushArgs) -> anyhow::Result<()> {
+    let reference: faasta_interface::oci::OciReference = args
+        .reference
+        .parse()
+        .map_err(|e: String| anyhow::anyhow!(e))?;
+
+    let dir = std::env::current_dir()?;
+    let wasm_path = match args.wasm_path {
+        Some(path) => PathBuf::from(path),
+        None => match project::load(&dir)?.function.wasm {
+            Some(wasm) => dir.join(wasm),
+            None => match componentize::detect(&dir)? {
+                Some(project) => project.output_path(),
+                None => {
+                    let (target_directory, package_name, _) = run::get_project_info()?;
+                    compiled_wasm_path(&target_directory, &package_name)
+                }
+            },
+        },
+    };
+    let component = std::fs::read(&wasm_path).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to read {}: {e}. Run 'cargo faasta build' first or pass --wasm",
+            wasm_path.display()
+        )
+    })?;
+    if !is_component(&component) {
+        anyhow::bail!("{} is not a WebAssembly component", wasm_path.display());
+    }
+
+    println!("Pushing {} to {reference}...", wasm_path.display());
+    let digest = registry::push(&reference, &component, registry::credentials_from_env()).await?;
+    println!("✅ Pushed {reference}");
+    println!("Digest: {digest}");
+    println!("Deploy it with: cargo faasta deploy --from-oci {reference}");
+    Ok(())
+}
+
+/// Have the server publish the component of an OCI artifact. The function is named
+/// after the repository's last path segment unless a name is given.
+async fn deploy_from_registry(
+    reference: &str,
+    function_name: Option<String>,
+    team: Option<String>,
+    keep_warm: Option<bool>,
+    server: &str,
+    auth_token: String,
+) -> anyhow::Result<()> {
+    let parsed: faasta_interface::oci::OciReference =
+        reference.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+    let function_name = function_name.unwrap_or_else(|| {
+        parsed
+            .repository
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    });
+    let target = match team {
+        Some(team) => faasta_interface::PublishTarget::Team(team),
+        None => faasta_interface::PublishTarget::Function,
+    };
+
+    let client = run::connect_to_function_service(server)
+        .await
+        .map_err(|e| {
+            errors::failure(
+                &errors::CONNECT_FAILED,
+                format!("Failed to connect to server: {e}"),
+            )
+        })?;
+    println!("Deploying {parsed} as '{function_name}'...");
+    let publish = client.publish_from_registry(
+        publish_context(),
+        parsed.to_string(),
+        function_name.clone(),
+        target,
+        registry::credentials_from_env(),
+        keep_warm,
+        auth_token.clone(),
+    );
+    match with_queue_position(&client, &function_name, &auth_token, publish).await? {
+        Ok(message) => {
+            println!("✅ {message}");
+            println!(
+                "Function URL: {}",
+                format_function_url(&function_name, &extract_server_host(server))
+            );
+            Ok(())
+        }
+        Err(e) => {
+            suggest_smaller_artifact(&e);
+            Err(server_error(e))
+        }
+    }
+}
+
+/// What went wrong in a call the server refused, and what to do about it where
+/// that's clear
+fn describe_error(error: &faasta_interface::FaastaError) -> String {
+    use faasta_interface::FaastaError;
+    match error {
+        FaastaError::AuthFailed(reason) => format!(
+            "Authentication failed: {reason}\n\
+             Run 'cargo faasta login' to sign in again, or check {API_KEY_ENV}."
+        ),
+        FaastaError::QuotaExceeded { quota, limit } => format!(
+            "You already have as many {quota} as this server allows ({limit}). \
+             Remove one first, or ask the server's operator for a higher limit."
+        ),
+        FaastaError::Internal { id } => format!(
+            "The server failed to handle the request. Its operator can look up what went \
+             wrong under reference {id}."
+        ),
+        _ => error.to_string(),
+    }
+}
+
+/// [`describe_error`] as an error to return, keeping the error's code
+fn server_error(error: faasta_interface::FaastaError) -> anyhow::Error {
+    errors::failure(errors::for_server_error(&error), describe_error(&error))
+}
+
+/// Point out how to shrink a component the server refused as too large
+fn suggest_smaller_artifact(error: &faasta_interface::FaastaError) {
+    let faasta_interface::FaastaError::TooLarge { size, limit } = error else {
+        return;
+    };
+    let mb = |bytes: &u64| *bytes as f64 / 1024.0 / 1024.0;
+    eprintln!();
+    eprintln!(
+        "The component is {:.1}MB, but this server accepts at most {:.1}MB. To shrink it:",
+        mb(size),
+        mb(limit)
+    );
+    eprintln!("  1. Deploy a release build ('cargo faasta build' builds in release mode)");
+    eprintln!("  2. Add `strip = true`, `lto = true` and `opt-level = \"z\"` to [profile.release]");
+    eprintln!("  3. Optimize it with wasm-opt: wasm-opt -Oz -o smaller.wasm PATH/TO/FILE.wasm");
+}
+
+/// How long a publish may take, including time spent in the server's deploy queue
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(600);
+/// How long the server may take to write a backup
+const BACKUP_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// How long a garbage collection may take, listing and removing artifacts in storage
+const GC_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// How often the deploy queue position is checked while a publish waits
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Context for calls that upload and compile a function
+fn publish_context() -> tarpc::context::Context {
+    let mut context = tarpc::context::current();
+    context.deadline = std::time::Instant::now() + PUBLISH_TIMEOUT;
+    context
+}
+
+/// Offer a function to another user, or accept one offered to the caller
+async fn transfer_function(
+    client: &faasta_interface::FunctionServiceClient,
+    args: TransferArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let name = match args.name {
+        Some(name) => name,
+        None => current_function_name()?,
+    };
+    let message = match args.to {
+        Some(new_owner) => {
+            client
+                .transfer_function(
+                    tarpc::context::current(),
+                    name.clone(),
+                    new_owner,
+                    auth_token,
+                )
+                .await?
+        }
+        None => {
+            client
+                .accept_transfer(tarpc::context::current(), name.clone(), auth_token)
+                .await?
+        }
+    }
+    .map_err(server_error)?;
+    forget_deploy(&args.server, &name);
+    println!("✅ {message}");
+    Ok(())
+}
+
+/// Add, remove or list the aliases of a function
+async fn manage_aliases(
+    client: &faasta_interface::FunctionServiceClient,
+    args: AliasArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let message = match args.command {
+        AliasCommands::Add {
+            alias,
+            to,
+            redirect,
+        } => {
+            let mode = match redirect {
+                Some(status) => faasta_interface::AliasMode::Redirect {
+                    permanent: status == 301,
+                },
+                None => faasta_interface::AliasMode::Alias,
+            };
+            let alias = faasta_interface::FunctionAlias {
+                name: alias,
+                target: match to {
+                    Some(target) => target,
+                    None => current_function_name()?,
+                },
+                mode,
+            };
+            client
+                .set_alias(tarpc::context::current(), alias, auth_token)
+                .await?
+        }
+        AliasCommands::Remove { alias } => {
+            client
+                .remove_alias(tarpc::context::current(), alias, auth_token)
+                .await?
+        }
+        AliasCommands::List { name } => {
+            let name = match name {
+                Some(name) => name,
+                None => current_function_name()?,
+            };
+            let aliases = client
+                .list_aliases(tarpc::context::current(), name.clone(), auth_token)
+                .await?
+                .map_err(server_error)?;
+            if aliases.is_empty() {
+                println!("'{name}' has no aliases");
+                return Ok(());
+            }
+            let server_host = extract_server_host(&args.server);
+            for alias in aliases {
+                let leads = match alias.mode.redirect_status() {
+                    Some(status) => format!("redirect {status}"),
+                    None => "alias".to_string(),
+                };
+                println!(
+                    "{:<24} {:<13} {}",
+                    alias.name,
+                    leads,
+                    format_function_url(&alias.name, &server_host)
+                );
+            }
+            return Ok(());
+        }
+    }
+    .map_err(server_error)?;
+    println!("✅ {message}");
+    Ok(())
+}
+
+/// Whether `server` still serves the component of `fingerprint` as `name`, with
+/// the saved credentials. Without them, or the server, it's taken not to be.
+async fn is_served(server: &str, name: &str, fingerprint: &fingerprint::Fingerprint) -> bool {
+    let Ok(Some((username, token))) = load_credentials().await else {
+        return false;
+    };
+    let Ok(client) = run::connect_to_function_service(server).await else {
+        return false;
+    };
+    fingerprint::is_served(&client, name, &format!("{username}:{token}"), fingerprint).await
+}
+
+/// Forget the last deploy of `name`, which no longer is what the server serves
+/// under that name
+fn forget_deploy(server: &str, name: &str) {
+    if let Err(e) = fingerprint::forget(server, name) {
+        eprintln!("Warning: failed to forget the last deploy of '{name}': {e:#}");
+    }
 }
 
-#[derive(Args, Debug)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
+/// Unpublish a function, after showing where it's live and asking, unless --yes
+async fn unpublish_function(
+    client: &faasta_interface::FunctionServiceClient,
+    args: UnpublishArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    if !args.yes {
+        let status = client
+            .function_status(
+                tarpc::context::current(),
+                args.name.clone(),
+                auth_token.clone(),
+            )
+            .await?
+            .map_err(|e| unpublish_error(&args.name, e))?;
+        println!("Function '{}' of {}", status.name, status.owner);
+        for url in &status.urls {
+            println!("  Live at:        {url}");
+        }
+        println!("  Last deployed:  {}", status.published_at);
+        if !confirm(&format!("Unpublish '{}'?", args.name))? {
+            println!("Cancelled, nothing was unpublished");
+            return Ok(());
+        }
+    }
+
+    client
+        .unpublish(tarpc::context::current(), args.name.clone(), auth_token)
+        .await?
+        .map_err(|e| unpublish_error(&args.name, e))?;
+    forget_deploy(&args.server, &args.name);
+    println!("✅ Function '{}' unpublished successfully", args.name);
+    println!(
+        "If the server keeps a trash, undo this with 'cargo faasta restore {}'.",
+        args.name
+    );
+    Ok(())
 }
 
-#[derive(Subcommand, Debug)]
-enum Commands {
-    /// Deploys a project to the server
-    Deploy(DeployArgs),
-    /// Invokes a function with the specified name and argument
-    Invoke(InvokeArgs),
-    /// Initialize a new project in the current directory
-    Init,
-    /// Create a new project in a new directory
-    New(NewArgs),
-    /// Build the function (and optionally deploy it)
-    Build(BuildArgs),
-    /// Set up GitHub authentication
-    Login(LoginArgs),
-    /// Get metrics for deployed functions
-    Metrics(ServerArgs),
-    /// List all functions deployed under the current GitHub account
-    List(ServerArgs),
-    /// Run a function locally for testing
-    Run(RunArgs),
-    /// Unpublish a function from the server
-    Unpublish(UnpublishArgs),
+/// [`server_error`], telling a missing function apart from one of someone else
+fn unpublish_error(name: &str, error: faasta_interface::FaastaError) -> anyhow::Error {
+    let code = errors::for_server_error(&error);
+    match error {
+        faasta_interface::FaastaError::NotFound(_) => {
+            errors::failure(code, format!("Function '{name}' not found"))
+        }
+        faasta_interface::FaastaError::PermissionDenied(_) => errors::failure(
+            code,
+            format!("Function '{name}' belongs to another user or team you can't unpublish for"),
+        ),
+        error => server_error(error),
+    }
 }
 
-#[derive(Args, Debug)]
-struct DeployArgs {
-    /// Path to the project to deploy
-    path: Option<String>,
+/// Ask a yes/no `question` on the terminal. Without one to ask on, fails rather
+/// than assuming an answer, so scripts pass --yes.
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    use std::io::IsTerminal;
+    if !std::io::stdin().is_terminal() {
+        return Err(errors::failure(
+            &errors::INVALID_INPUT,
+            "Can't ask for confirmation without a terminal; pass --yes",
+        ));
+    }
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
 
-    /// Skip GitHub authentication
-    #[arg(long)]
-    skip_auth: bool,
+/// Await a publish, showing its place in the server's deploy queue while it waits
+async fn with_queue_position<T>(
+    client: &faasta_interface::FunctionServiceClient,
+    function_name: &str,
+    auth_token: &str,
+    publish: impl std::future::Future<Output = T>,
+) -> T {
+    tokio::pin!(publish);
+    let mut ticker = tokio::time::interval_at(
+        tokio::time::Instant::now() + QUEUE_POLL_INTERVAL,
+        QUEUE_POLL_INTERVAL,
+    );
+    let mut shown = None;
+    loop {
+        tokio::select! {
+            result = &mut publish => return result,
+            _ = ticker.tick() => {
+                let position = client
+                    .deploy_queue_position(
+                        tarpc::context::current(),
+                        function_name.to_string(),
+                        auth_token.to_string(),
+                    )
+                    .await;
+                // Servers without a deploy queue just never report a position
+                if let Ok(Ok(position)) = position {
+                    if let Some(position) = position.filter(|position| Some(*position) != shown) {
+                        println!("Waiting in the deploy queue (position {position})...");
+                    }
+                    shown = position;
+                }
+            }
+        }
+    }
+}
 
-    /// Explicit path to WASM file (overrides automatic detection)
-    #[arg(long)]
-    wasm_path: Option<String>,
+// Create, list or revoke API keys
+async fn manage_api_keys(
+    client: &faasta_interface::FunctionServiceClient,
+    command: TokenCommands,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let context = tarpc::context::current();
+
+    match command {
+        TokenCommands::Create { scopes, name } => {
+            let new_key = client
+                .create_api_key(context, name, scopes, auth_token)
+                .await?
+                .map_err(server_error)?;
+            let scopes: Vec<String> = new_key.info.scopes.iter().map(|s| s.to_string()).collect();
+
+            println!(
+                "✅ Created API key '{}' ({})",
+                new_key.info.id,
+                scopes.join(", ")
+            );
+            println!("\n    {}\n", new_key.key);
+            println!("This key will not be shown again. Use it by setting {API_KEY_ENV}.");
+        }
+        TokenCommands::List => {
+            let keys = client
+                .list_api_keys(context, auth_token)
+                .await?
+                .map_err(server_error)?;
+
+            if keys.is_empty() {
+                println!(
+                    "No API keys. Create one with 'cargo faasta token create --scope deploy'."
+                );
+                return Ok(());
+            }
 
-    /// Function name to use (if different from package name)
-    #[arg(long)]
-    function_name: Option<String>,
+            for key in keys {
+                let scopes: Vec<String> = key.scopes.iter().map(|s| s.to_string()).collect();
+                println!(
+                    "{}  {:<16} {:<20} created {}",
+                    key.id,
+                    key.name,
+                    scopes.join(","),
+                    key.created_at
+                );
+            }
+        }
+        TokenCommands::Revoke { id } => {
+            client
+                .revoke_api_key(context, id.clone(), auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Revoked API key '{id}'");
+        }
+    }
 
-    /// Server address to deploy to (e.g., "faasta.xyz:4433")
-    #[arg(long, default_value = "faasta.xyz:4433")]
-    server: String,
+    Ok(())
 }
 
-#[derive(Args, Debug)]
-struct BuildArgs {
-    /// Deploy the function after building
-    #[arg(short, long)]
-    deploy: bool,
+// Audit and revoke credentials
+async fn manage_sessions(
+    client: &faasta_interface::FunctionServiceClient,
+    command: SessionsCommands,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let context = tarpc::context::current();
 
-    /// Explicit path to WASM file (overrides automatic detection)
-    #[arg(long)]
-    wasm_path: Option<String>,
+    match command {
+        SessionsCommands::List => {
+            let sessions = client
+                .list_sessions(context, auth_token)
+                .await?
+                .map_err(server_error)?;
 
-    /// Function name to use (if different from package name)
-    #[arg(long)]
-    function_name: Option<String>,
+            if sessions.is_empty() {
+                println!("No sessions recorded.");
+                return Ok(());
+            }
 
-    /// Server address to deploy to (e.g., "faasta.xyz:4433")
-    #[arg(long, default_value = "faasta.xyz:4433")]
-    server: String,
-}
+            for session in sessions {
+                println!(
+                    "{:<8}  {:<8} {:<20} created {}  last used {}{}",
+                    session.id,
+                    session.kind,
+                    session.label,
+                    session.created_at,
+                    session.last_used.as_deref().unwrap_or("never"),
+                    if session.current { "  (current)" } else { "" }
+                );
+            }
+        }
+        SessionsCommands::Revoke { id } => {
+            client
+                .revoke_session(context, id.clone(), auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Revoked session '{id}'");
+        }
+        SessionsCommands::Label { label } => {
+            client
+                .label_session(context, label.clone(), auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Labeled this login '{label}'");
+        }
+    }
 
-#[derive(Args, Debug)]
-struct RunArgs {
-    /// Port to run the local server on
-    #[arg(short, long, default_value = "3000")]
-    port: u16,
+    Ok(())
 }
 
-#[derive(Args, Debug)]
-struct InvokeArgs {
-    /// Name of the function to invoke
-    name: String,
-    /// Optional argument to pass to the function
-    #[arg(default_value = "")]
-    arg: String,
-}
+// Print a Terraform starter for the caller's functions
+async fn export_terraform(
+    client: &faasta_interface::FunctionServiceClient,
+    server: &str,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let functions = client
+        .list_functions(
+            tarpc::context::current(),
+            faasta_interface::FunctionQuery::all(),
+            auth_token.clone(),
+        )
+        .await?
+        .map_err(server_error)?
+        .functions;
+
+    let mut exported = Vec::with_capacity(functions.len());
+    for function in functions {
+        let log_level = client
+            .get_log_level(
+                tarpc::context::current(),
+                function.name.clone(),
+                auth_token.clone(),
+            )
+            .await?
+            .map_err(server_error)?;
+        let redaction = client
+            .get_log_redaction(
+                tarpc::context::current(),
+                function.name.clone(),
+                auth_token.clone(),
+            )
+            .await?
+            .map_err(server_error)?;
+        exported.push(export::ExportedFunction {
+            name: function.name,
+            owner: function.owner,
+            log_level,
+            redaction: redaction.function,
+        });
+    }
 
-#[derive(Args, Debug)]
-struct UnpublishArgs {
-    /// Name of the function to unpublish
-    name: String,
-    /// Server address (e.g., "faasta.xyz:4433")
-    #[arg(long, default_value = "faasta.xyz:4433")]
-    server: String,
+    print!(
+        "{}",
+        export::terraform(&extract_server_host(server), &exported)
+    );
+    Ok(())
 }
 
-#[derive(Args, Debug)]
-struct ServerArgs {
-    /// Server address (e.g., "faasta.xyz:4433")
-    #[arg(long, default_value = "faasta.xyz:4433")]
-    server: String,
+/// Read one function definition, or an array of them, and check them
+fn read_definitions(
+    path: &std::path::Path,
+) -> anyhow::Result<Vec<faasta_interface::FunctionDefinition>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", path.display()))?;
+    let value: serde_json::Value = serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("{} is not valid JSON: {e}", path.display()))?;
+    let definitions: Vec<faasta_interface::FunctionDefinition> = if value.is_array() {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_value(value).map(|definition| vec![definition])
+    }
+    .map_err(|e| anyhow::anyhow!("Invalid function definition in {}: {e}", path.display()))?;
+    for definition in &definitions {
+        definition
+            .validate()
+            .map_err(|e| anyhow::anyhow!("{}: {e}", definition.metadata.name))?;
+    }
+    Ok(definitions)
 }
 
-/// Custom styling for the CLI
-pub const CLAP_STYLING: clap::builder::styling::Styles = clap::builder::styling::Styles::styled()
-    .header(clap_cargo::style::HEADER)
-    .usage(clap_cargo::style::USAGE)
-    .literal(clap_cargo::style::LITERAL)
-    .placeholder(clap_cargo::style::PLACEHOLDER)
-    .error(clap_cargo::style::ERROR)
-    .valid(clap_cargo::style::VALID)
-    .invalid(clap_cargo::style::INVALID);
+// Reconcile functions to their definitions
+async fn apply_definitions(
+    client: &faasta_interface::FunctionServiceClient,
+    definitions: Vec<faasta_interface::FunctionDefinition>,
+    dry_run: bool,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    for definition in definitions {
+        let name = definition.metadata.name.clone();
+        let outcome = client
+            .apply(
+                publish_context(),
+                definition,
+                registry::credentials_from_env(),
+                dry_run,
+                auth_token.clone(),
+            )
+            .await?
+            .map_err(|e| anyhow::anyhow!("{name}: {e}"))?;
+
+        if outcome.changes.is_empty() {
+            println!("{name}: up to date");
+            continue;
+        }
+        let verb = if dry_run { "would change" } else { "changed" };
+        println!("{name}: {verb}");
+        for change in &outcome.changes {
+            println!("  {change}");
+        }
+        if let (false, Some(digest)) = (dry_run, &outcome.digest) {
+            println!("  running {digest}");
+        }
+    }
+    Ok(())
+}
 
-/// Formats the function URL based on the server URL
-/// If server is a domain (not localhost or an IP), it uses function_name as a subdomain
-/// Otherwise, it appends function_name as a path
-fn format_function_url(function_name: &str, server: &str) -> String {
-    // Ensure server has a scheme
-    let server_url = if !server.contains("://") {
-        format!("https://{server}")
-    } else {
-        server.to_string()
+// Show the provenance recorded for a function's artifact
+async fn show_provenance(
+    client: &faasta_interface::FunctionServiceClient,
+    args: ProvenanceArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let name = match args.name {
+        Some(name) => name,
+        None => current_function_name()?,
+    };
+    let provenance = client
+        .get_provenance(tarpc::context::current(), name.clone(), auth_token)
+        .await?
+        .map_err(server_error)?;
+
+    let Some(provenance) = provenance else {
+        println!("'{name}' was published without provenance");
+        return Ok(());
     };
+    if args.raw {
+        println!("{}", provenance.document);
+        return Ok(());
+    }
+    println!("Artifact:  {}", provenance.digest);
+    println!("Predicate: {}", provenance.predicate_type);
+    println!(
+        "Builder:   {}",
+        provenance.builder_id.as_deref().unwrap_or("(not named)")
+    );
+    Ok(())
+}
 
-    // Parse the server URL to get the hostname
-    // Format: scheme://host/path
-    let url_parts: Vec<&str> = server_url.split("://").collect();
-    if url_parts.len() != 2 {
-        // If URL doesn't follow the expected format, fall back to a simple approach
-        return format!("https://{server}/{function_name}");
+// Show or change the keys signing a function's responses
+async fn manage_signing_keys(
+    client: &faasta_interface::FunctionServiceClient,
+    args: KeysArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let name = match args.name {
+        Some(name) => name,
+        None => current_function_name()?,
+    };
+    let keys = if args.enable || args.rotate || args.disable {
+        client
+            .set_response_signing(
+                tarpc::context::current(),
+                name.clone(),
+                !args.disable,
+                args.rotate,
+                auth_token,
+            )
+            .await?
+    } else {
+        client
+            .get_signing_keys(tarpc::context::current(), name.clone(), auth_token)
+            .await?
     }
+    .map_err(server_error)?;
 
-    let scheme = url_parts[0];
-    let rest = url_parts[1];
+    let Some(current) = keys.current else {
+        println!("Responses of '{name}' are not signed");
+        return Ok(());
+    };
+    println!("Responses of '{name}' are signed (Standard Webhooks, Ed25519)");
+    println!("Public key: {}", current.public_key);
+    println!("Created:    {}", current.created_at);
+    if let Some(previous) = keys.previous {
+        println!(
+            "Previous:   {} (signing until {})",
+            previous.public_key,
+            keys.previous_expires_at.as_deref().unwrap_or("unknown")
+        );
+    }
+    Ok(())
+}
 
-    // Split host and path
-    let host_path_parts: Vec<&str> = rest.split('/').collect();
-    let host = host_path_parts[0];
+// Show a function's status as a panel
+async fn show_function_status(
+    client: &faasta_interface::FunctionServiceClient,
+    args: StatusArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let name = match args.name {
+        Some(name) => name,
+        None => current_function_name()?,
+    };
+    let status = client
+        .function_status(tarpc::context::current(), name, auth_token)
+        .await?
+        .map_err(server_error)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
 
-    // Check if host is localhost or an IP address
-    if host == "localhost" || host == "127.0.0.1" || is_ip_address(host) {
-        // For localhost or IP, append function_name as a path
-        let base = if server_url.ends_with('/') {
-            server_url
-        } else {
-            format!("{server_url}/")
-        };
-        format!("{base}{function_name}")
-    } else {
-        // For a domain name, use function_name as a subdomain
-        format!("{scheme}://{function_name}.{host}/")
+    println!("╔══════════════════════════════════════════════════════");
+    println!("║ {}", status.name);
+    println!("╠══════════════════════════════════════════════════════");
+    println!("║ Owner       {}", status.owner);
+    println!("║ Deployed    {}", status.published_at);
+    println!(
+        "║ Artifact    {}",
+        status.digest.as_deref().unwrap_or("digest not recorded")
+    );
+    if let Some(bytes) = status.artifact_bytes {
+        println!("║             {:.1} KiB", bytes as f64 / 1024.0);
     }
+    if let Some(weight) = status.canary_weight {
+        println!("║ Canary      receives {weight}% of requests");
+    }
+    for (i, url) in status.urls.iter().enumerate() {
+        let label = if i == 0 { "URLs" } else { "" };
+        println!("║ {label:<11} {url}");
+    }
+    let kept_warm = if status.kept_warm { ", kept warm" } else { "" };
+    println!("║ State       {}{kept_warm}", status.warm);
+    println!(
+        "║ Invocations {} ({} failed)",
+        status.invocations, status.errors
+    );
+    match &status.last_error {
+        Some(failure) => println!("║ Last error  {} at {}", failure.message, failure.at),
+        None => println!("║ Last error  none since the server started"),
+    }
+    for (i, quota) in status.quotas.iter().enumerate() {
+        let label = if i == 0 { "Quotas" } else { "" };
+        println!(
+            "║ {label:<11} {} of {} {}",
+            quota.used, quota.limit, quota.quota
+        );
+    }
+    println!("╚══════════════════════════════════════════════════════");
+    Ok(())
 }
 
-/// Extract the server host from a server address (removing any port)
-fn extract_server_host(server_addr: &str) -> String {
-    // If it already has a scheme, use it as is
-    if server_addr.contains("://") {
-        return server_addr.to_string();
+// Make a function private with a new access key, or public again
+async fn protect_function(
+    client: &faasta_interface::FunctionServiceClient,
+    args: ProtectArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let name = match args.name {
+        Some(name) => name,
+        None => current_function_name()?,
+    };
+    let key = client
+        .protect_function(
+            tarpc::context::current(),
+            name.clone(),
+            !args.remove,
+            auth_token,
+        )
+        .await?
+        .map_err(server_error)?;
+    let Some(key) = key else {
+        println!("✅ '{name}' is public");
+        return Ok(());
+    };
+    println!("✅ '{name}' is private. Its access key, which won't be shown again:");
+    println!();
+    println!("  {key}");
+    println!();
+    println!(
+        "Send it in the {} header, or as the password of HTTP Basic authentication.",
+        faasta_interface::ACCESS_KEY_HEADER
+    );
+    println!("Running this command again replaces the key.");
+    Ok(())
+}
+
+// Show or reset a function's circuit breaker
+async fn manage_breaker(
+    client: &faasta_interface::FunctionServiceClient,
+    args: BreakerArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let name = match args.name {
+        Some(name) => name,
+        None => current_function_name()?,
+    };
+    if args.reset {
+        client
+            .reset_circuit_breaker(tarpc::context::current(), name.clone(), auth_token)
+            .await?
+            .map_err(server_error)?;
+        println!("✅ '{name}' is invoked again");
+        return Ok(());
     }
 
-    // Remove port if present
-    if let Some(host) = server_addr.split(':').next() {
-        format!("https://{host}")
-    } else {
-        format!("https://{server_addr}")
+    let breaker = client
+        .get_circuit_breaker(tarpc::context::current(), name.clone(), auth_token)
+        .await?
+        .map_err(server_error)?;
+    let Some(breaker) = breaker else {
+        println!("'{name}' is invoked normally");
+        return Ok(());
+    };
+    match breaker.state {
+        faasta_interface::BreakerState::Killed => println!(
+            "'{name}' was killed by '{}' and its requests are answered 503",
+            breaker.opened_by.as_deref().unwrap_or("an admin")
+        ),
+        faasta_interface::BreakerState::Tripped => {
+            println!("The circuit breaker of '{name}' tripped and its requests are answered 503")
+        }
     }
+    println!("Reason: {}", breaker.reason);
+    println!("Since:  {}", breaker.opened_at);
+    Ok(())
 }
 
-/// Check if a host string is an IP address
-fn is_ip_address(host: &str) -> bool {
-    host.parse::<std::net::IpAddr>().is_ok()
-}
+// Show, turn on or off, or download a function's debug snapshots
+async fn manage_snapshots(
+    client: &faasta_interface::FunctionServiceClient,
+    args: SnapshotsArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let name = match args.name {
+        Some(name) => name,
+        None => current_function_name()?,
+    };
+    if let Some(id) = args.download {
+        let snapshot = client
+            .get_debug_snapshot(
+                tarpc::context::current(),
+                name.clone(),
+                id.clone(),
+                auth_token.clone(),
+            )
+            .await?
+            .map_err(server_error)?;
+        let dir = args.output.unwrap_or_else(|| PathBuf::from(&id));
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join(run::SNAPSHOT_FILE),
+            serde_json::to_vec_pretty(&snapshot)?,
+        )?;
+        if let Some(size) = snapshot.info.core_dump_size {
+            let mut core_dump = Vec::with_capacity(size as usize);
+            for index in 0.. {
+                let chunk = client
+                    .read_core_dump(
+                        tarpc::context::current(),
+                        name.clone(),
+                        id.clone(),
+                        index,
+                        auth_token.clone(),
+                    )
+                    .await?
+                    .map_err(server_error)?;
+                if chunk.is_empty() {
+                    break;
+                }
+                core_dump.extend_from_slice(&chunk);
+            }
+            fs::write(dir.join(run::CORE_DUMP_FILE), core_dump)?;
+        } else {
+            println!(
+                "The function's memory was over the server's limit, so only the request was kept"
+            );
+        }
+        println!("✅ Downloaded snapshot {id} to {}", dir.display());
+        println!(
+            "Replay it with: cargo faasta run --replay {}",
+            dir.display()
+        );
+        return Ok(());
+    }
 
-async fn invoke_function(name: &str, arg: &str) -> Result<(), reqwest::Error> {
-    let function_url = format_function_url(name, DEFAULT_INVOKE_URL);
-    let invoke_url = if function_url.ends_with('/') {
-        format!("{function_url}{arg}")
+    let snapshots = if args.enable || args.disable {
+        client
+            .set_debug_snapshots(
+                tarpc::context::current(),
+                name.clone(),
+                args.enable,
+                auth_token,
+            )
+            .await?
     } else {
-        format!("{function_url}/{arg}")
-    };
+        client
+            .list_debug_snapshots(tarpc::context::current(), name.clone(), auth_token)
+            .await?
+    }
+    .map_err(server_error)?;
 
-    println!("Invoking function at: {invoke_url}");
+    if !snapshots.enabled {
+        println!("'{name}' is not snapshotted when it traps");
+        return Ok(());
+    }
+    println!("'{name}' is snapshotted when it traps");
+    if snapshots.snapshots.is_empty() {
+        println!("No snapshots yet");
+    }
+    for snapshot in snapshots.snapshots {
+        let memory = snapshot
+            .core_dump_size
+            .map_or("memory left out".to_string(), |size| {
+                format!("{:.1} MiB core dump", size as f64 / 1024.0 / 1024.0)
+            });
+        let trap = snapshot.error.lines().next().unwrap_or_default();
+        println!(
+            "{}  {}  request {}  ({memory})",
+            snapshot.id, snapshot.created_at, snapshot.request_id
+        );
+        println!("    {trap}");
+    }
+    Ok(())
+}
 
-    // Create a client that accepts invalid certificates (for testing)
-    let client = reqwest::Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()?;
+// Show, change or download a function's guest profiles
+async fn manage_profiles(
+    client: &faasta_interface::FunctionServiceClient,
+    args: ProfilesArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let name = match args.name {
+        Some(name) => name,
+        None => current_function_name()?,
+    };
+    if let Some(id) = args.download {
+        let mut profile = Vec::new();
+        for index in 0.. {
+            let chunk = client
+                .read_guest_profile(
+                    tarpc::context::current(),
+                    name.clone(),
+                    id.clone(),
+                    index,
+                    auth_token.clone(),
+                )
+                .await?
+                .map_err(server_error)?;
+            if chunk.is_empty() {
+                break;
+            }
+            profile.extend_from_slice(&chunk);
+        }
+        let path = args
+            .output
+            .unwrap_or_else(|| PathBuf::from(format!("{id}.json")));
+        fs::write(&path, profile)?;
+        println!("✅ Downloaded profile {id} to {}", path.display());
+        println!("Open it at https://profiler.firefox.com to see the flamegraph");
+        return Ok(());
+    }
 
-    // Make sure we're using HTTPS
-    let https_url = if !invoke_url.starts_with("https://") && !invoke_url.starts_with("http://") {
-        format!("https://{invoke_url}")
-    } else if invoke_url.starts_with("http://") {
-        invoke_url.replace("http://", "https://")
+    let sample_rate = if args.disable {
+        Some(0.0)
     } else {
-        invoke_url
+        args.sample_rate
     };
+    let profiles = match sample_rate {
+        Some(sample_rate) => {
+            client
+                .set_guest_profiling(
+                    tarpc::context::current(),
+                    name.clone(),
+                    sample_rate,
+                    auth_token,
+                )
+                .await?
+        }
+        None => {
+            client
+                .list_guest_profiles(tarpc::context::current(), name.clone(), auth_token)
+                .await?
+        }
+    }
+    .map_err(server_error)?;
 
-    let resp = client.get(https_url).send().await?;
-    println!("Response status: {}", resp.status());
-    println!("{}", resp.text().await?);
+    if profiles.sample_rate <= 0.0 {
+        println!("'{name}' is not profiled");
+        return Ok(());
+    }
+    println!(
+        "{:.1}% of the invocations of '{name}''s canary are profiled",
+        profiles.sample_rate * 100.0
+    );
+    if profiles.profiles.is_empty() {
+        println!("No profiles yet. Release a canary with 'cargo faasta release' to collect some");
+    }
+    for profile in profiles.profiles {
+        println!(
+            "{}  {}  request {}  ({} ms, {:.1} KiB)",
+            profile.id,
+            profile.created_at,
+            profile.request_id,
+            profile.duration_ms,
+            profile.size as f64 / 1024.0
+        );
+    }
     Ok(())
 }
 
-// Function to fetch and display metrics
-async fn get_metrics(
+// List a function's webhook deliveries, or queue one again
+async fn show_webhooks(
     client: &faasta_interface::FunctionServiceClient,
-    username: &str,
-    token: &str,
+    args: WebhooksArgs,
+    (username, token): (String, String),
 ) -> anyhow::Result<()> {
-    // Create auth token (username:token format)
     let auth_token = format!("{username}:{token}");
+    let name = match args.name {
+        Some(name) => name,
+        None => current_function_name()?,
+    };
+    if let Some(id) = args.redeliver {
+        let delivery = client
+            .redeliver_webhook(tarpc::context::current(), name, id, auth_token)
+            .await?
+            .map_err(server_error)?;
+        println!("Queued {} to {} again", delivery.id, delivery.url);
+        return Ok(());
+    }
 
-    println!("Fetching metrics from server...");
+    let deliveries = client
+        .list_webhook_deliveries(
+            tarpc::context::current(),
+            name.clone(),
+            args.status,
+            auth_token,
+        )
+        .await?
+        .map_err(server_error)?;
+    if deliveries.is_empty() {
+        println!("No webhook deliveries for '{name}'");
+        return Ok(());
+    }
+    for delivery in deliveries {
+        println!(
+            "{}  {:<9}  {}  {}",
+            delivery.id, delivery.status, delivery.created_at, delivery.url
+        );
+        for attempt in &delivery.attempts {
+            let outcome = match (attempt.status, &attempt.error) {
+                (Some(status), None) => format!("HTTP {status}"),
+                (Some(status), Some(error)) => format!("HTTP {status}: {error}"),
+                (None, Some(error)) => error.clone(),
+                (None, None) => "no answer".to_string(),
+            };
+            println!("    {}  {outcome} ({} ms)", attempt.at, attempt.duration_ms);
+        }
+        if let Some(next) = &delivery.next_attempt_at {
+            println!("    next attempt at {next}");
+        }
+    }
+    Ok(())
+}
 
-    // Call the get_metrics RPC
-    match client
-        .get_metrics(tarpc::context::current(), auth_token)
-        .await
+// Search a function's logs
+async fn show_logs(
+    client: &faasta_interface::FunctionServiceClient,
+    args: LogsArgs,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    if let Some(LogsCommands::Level {
+        level,
+        function,
+        duration,
+        reset,
+    }) = args.command
     {
-        Ok(Ok(metrics)) => {
-            // Print summary
-            println!("\n╔══════════════════════════════════════════════════════");
-            println!("║ FAASTA FUNCTION METRICS");
-            println!("╠══════════════════════════════════════════════════════");
-            println!("║ Total Function Calls: {}", metrics.total_calls);
+        let function = match function {
+            Some(function) => function,
+            None => current_function_name()?,
+        };
+        let context = tarpc::context::current();
+        let setting = if level.is_some() || reset {
+            client
+                .set_log_level(
+                    context,
+                    function.clone(),
+                    level,
+                    duration.unwrap_or(0),
+                    auth_token,
+                )
+                .await?
+        } else {
+            client
+                .get_log_level(context, function.clone(), auth_token)
+                .await?
+        }
+        .map_err(server_error)?;
+
+        match setting.expires_at {
+            Some(expires_at) => println!(
+                "'{function}' stores {} lines and up until {expires_at}",
+                setting.level
+            ),
+            None => println!("'{function}' stores {} lines and up", setting.level),
+        }
+        return Ok(());
+    }
+    if let Some(LogsCommands::Redact {
+        function,
+        headers,
+        patterns,
+        reset,
+    }) = args.command
+    {
+        let function = match function {
+            Some(function) => function,
+            None => current_function_name()?,
+        };
+        let context = tarpc::context::current();
+        let settings = if !headers.is_empty() || !patterns.is_empty() || reset {
+            let rules = faasta_interface::RedactionRules { headers, patterns };
+            client
+                .set_log_redaction(context, function.clone(), rules, auth_token)
+                .await?
+        } else {
+            client
+                .get_log_redaction(context, function.clone(), auth_token)
+                .await?
+        }
+        .map_err(server_error)?;
 
-            // Format total execution time nicely
-            let total_time = if metrics.total_time > 60000 {
-                format!("{:.2} minutes", metrics.total_time as f64 / 60000.0)
-            } else if metrics.total_time > 1000 {
-                format!("{:.2} seconds", metrics.total_time as f64 / 1000.0)
-            } else {
-                format!("{} ms", metrics.total_time)
-            };
+        let print_rules = |title: &str, rules: &faasta_interface::RedactionRules| {
+            println!("{title}:");
+            if rules.headers.is_empty() && rules.patterns.is_empty() {
+                println!("  (none)");
+            }
+            for header in &rules.headers {
+                println!("  header  {header}");
+            }
+            for pattern in &rules.patterns {
+                println!("  pattern {pattern}");
+            }
+        };
+        print_rules("Enforced by the server", &settings.enforced);
+        print_rules(&format!("Added for '{function}'"), &settings.function);
+        return Ok(());
+    }
 
-            println!("║ Total Execution Time: {total_time}");
-            println!("║ Functions Deployed: {}", metrics.function_metrics.len());
-            println!("╠══════════════════════════════════════════════════════");
+    let Some(name) = args.name else {
+        anyhow::bail!("A function name is required");
+    };
+    let mut query = faasta_interface::LogQuery {
+        since: args.since,
+        until: args.until,
+        min_level: args.level,
+        request_id: args.request_id,
+        grep: args.grep,
+        cursor: None,
+        limit: args.limit,
+    };
 
-            // If we have no functions, show a message
-            if metrics.function_metrics.is_empty() {
-                println!("║ No function metrics available.");
-                println!("╚══════════════════════════════════════════════════════");
-                return Ok(());
+    loop {
+        let page = client
+            .function_logs(
+                tarpc::context::current(),
+                name.clone(),
+                query.clone(),
+                auth_token.clone(),
+            )
+            .await?
+            .map_err(server_error)?;
+
+        for entry in &page.entries {
+            if args.jsonl {
+                println!("{}", serde_json::to_string(entry)?);
+            } else {
+                println!(
+                    "{} {:<5} {} {}",
+                    entry.timestamp, entry.level, entry.request_id, entry.message
+                );
             }
+        }
 
-            // Print detailed metrics for each function
-            println!("║ FUNCTION DETAILS");
-            println!("╠══════════════════════════════════════════════════════");
+        match page.next_cursor {
+            Some(cursor) if args.all => query.cursor = Some(cursor),
+            Some(_) => {
+                if !args.jsonl {
+                    eprintln!("More lines match; pass --all to see them all.");
+                }
+                break;
+            }
+            None => break,
+        }
+    }
 
-            for function in metrics.function_metrics {
-                println!("║ Function: {}", function.function_name);
-                println!("║ ├─ Call Count: {}", function.call_count);
+    Ok(())
+}
 
-                // Format execution time nicely
-                let exec_time = if function.total_time_millis > 60000 {
-                    format!("{:.2} minutes", function.total_time_millis as f64 / 60000.0)
-                } else if function.total_time_millis > 1000 {
-                    format!("{:.2} seconds", function.total_time_millis as f64 / 1000.0)
-                } else {
-                    format!("{} ms", function.total_time_millis)
-                };
+// Review flagged activity
+async fn manage_alerts(
+    client: &faasta_interface::FunctionServiceClient,
+    command: AlertsCommands,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let context = tarpc::context::current();
 
-                println!("║ ├─ Total Execution Time: {exec_time}");
+    match command {
+        AlertsCommands::List { all } => {
+            let alerts = client
+                .list_alerts(context, all, auth_token)
+                .await?
+                .map_err(server_error)?;
 
-                // Format average time per call
-                let avg_time = if function.call_count > 0 {
-                    format!(
-                        "{:.2} ms",
-                        function.total_time_millis as f64 / function.call_count as f64
-                    )
-                } else {
-                    "N/A".to_string()
-                };
+            if alerts.is_empty() {
+                println!("No unresolved alerts.");
+                return Ok(());
+            }
 
-                println!("║ ├─ Average Time per Call: {avg_time}");
-                println!("║ └─ Last Called: {}", function.last_called);
-                println!("╟──────────────────────────────────────────────────────");
+            for alert in alerts {
+                println!(
+                    "#{} {} {} ({}) at {}",
+                    alert.id,
+                    alert.kind,
+                    alert.owner,
+                    alert.function.as_deref().unwrap_or("account"),
+                    alert.created_at
+                );
+                println!("  └─ {}", alert.detail);
             }
-            println!("╚══════════════════════════════════════════════════════");
-            Ok(())
         }
-        Ok(Err(e)) => {
-            eprintln!("Server error: {e:?}");
-            Err(anyhow::anyhow!("Server error: {:?}", e))
+        AlertsCommands::Resolve { id } => {
+            client
+                .resolve_alert(context, id, auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Resolved alert #{id}");
         }
-        Err(e) => Err(anyhow::anyhow!("Communication error: {}", e)),
     }
+
+    Ok(())
 }
 
-// Function to fetch and display list of functions
-async fn list_functions(
+// Create and manage teams
+async fn manage_teams(
     client: &faasta_interface::FunctionServiceClient,
-    username: &str,
-    token: &str,
+    command: TeamCommands,
+    (username, token): (String, String),
 ) -> anyhow::Result<()> {
-    // Create auth token (username:token format)
     let auth_token = format!("{username}:{token}");
+    let context = tarpc::context::current();
 
-    println!("Fetching functions for GitHub user: {username}...");
-
-    // Call the list_functions RPC
-    match client
-        .list_functions(tarpc::context::current(), auth_token)
-        .await
-    {
-        Ok(Ok(functions)) => {
-            if functions.is_empty() {
-                println!("\nNo functions deployed under this GitHub account.");
-                println!("Use 'cargo faasta deploy' to deploy a function.");
-                return Ok(());
+    let team = match command {
+        TeamCommands::Create { name, github_org } => {
+            client
+                .create_team(context, name, github_org, auth_token)
+                .await?
+        }
+        TeamCommands::List => {
+            let teams = client
+                .list_teams(context, auth_token)
+                .await?
+                .map_err(server_error)?;
+
+            if teams.is_empty() {
+                println!("You are not a member of any team.");
+            }
+            for team in teams {
+                print_team(&team);
             }
+            return Ok(());
+        }
+        TeamCommands::Add {
+            team,
+            username,
+            role,
+        } => {
+            client
+                .set_team_member(context, team, username, role, auth_token)
+                .await?
+        }
+        TeamCommands::Remove { team, username } => {
+            client
+                .remove_team_member(context, team, username, auth_token)
+                .await?
+        }
+        TeamCommands::Delete { team } => {
+            client
+                .delete_team(context, team.clone(), auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Deleted team '{team}'");
+            return Ok(());
+        }
+    };
 
-            // Print header
-            println!("\n╔══════════════════════════════════════════════════════");
-            println!("║ FUNCTIONS DEPLOYED BY {}", username.to_uppercase());
-            println!("╠══════════════════════════════════════════════════════");
-            println!("║ Total Functions: {}", functions.len());
-            println!("╠══════════════════════════════════════════════════════");
+    let team = team.map_err(server_error)?;
+    println!("✅ Updated team '{}'", team.name);
+    print_team(&team);
+    Ok(())
+}
 
-            // Print functions in alphabetical order
-            let mut sorted_functions = functions.clone();
-            sorted_functions.sort_by(|a, b| a.name.cmp(&b.name));
+// Grant and list platform roles
+async fn manage_roles(
+    client: &faasta_interface::FunctionServiceClient,
+    command: RoleCommands,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let context = tarpc::context::current();
+
+    match command {
+        RoleCommands::Grant { principal, role } => {
+            client
+                .set_role(context, principal.clone(), Some(role), auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Granted the {role} role to '{principal}'");
+        }
+        RoleCommands::Revoke { principal } => {
+            client
+                .set_role(context, principal.clone(), None, auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Cleared the role of '{principal}'");
+        }
+        RoleCommands::List => {
+            let grants = client
+                .list_roles(context, auth_token)
+                .await?
+                .map_err(server_error)?;
+
+            if grants.is_empty() {
+                println!("No roles granted. Everyone has the server's default role.");
+            }
+            for grant in grants {
+                println!("{:<24} {}", grant.principal, grant.role);
+            }
+        }
+    }
 
-            for function in sorted_functions {
-                println!("║ Function: {}", function.name);
+    Ok(())
+}
 
-                // Parse the published_at date for pretty formatting
-                println!("║ ├─ Published: {}", function.published_at);
+// Operator commands for abusive accounts and quotas
+async fn run_admin_command(
+    client: &faasta_interface::FunctionServiceClient,
+    command: AdminCommands,
+    (username, token): (String, String),
+) -> anyhow::Result<()> {
+    let auth_token = format!("{username}:{token}");
+    let context = tarpc::context::current();
+
+    match command {
+        AdminCommands::Suspend { username, reason } => {
+            client
+                .suspend_user(context, username.clone(), reason, auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Suspended '{username}'");
+        }
+        AdminCommands::Unsuspend { username } => {
+            client
+                .unsuspend_user(context, username.clone(), auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Lifted the suspension of '{username}'");
+        }
+        AdminCommands::Kill { name, reason } => {
+            client
+                .kill_function(context, name.clone(), reason, auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!(
+                "✅ Killed function '{name}'; reset it with 'cargo faasta breaker {name} --reset'"
+            );
+        }
+        AdminCommands::Delete { name } => {
+            client
+                .force_delete_function(context, name.clone(), auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!("✅ Deleted function '{name}'");
+        }
+        AdminCommands::Audit {
+            mut after,
+            limit,
+            all,
+            jsonl,
+        } => loop {
+            let events = client
+                .audit_log(tarpc::context::current(), after, limit, auth_token.clone())
+                .await?
+                .map_err(server_error)?;
+
+            for event in &events {
+                if jsonl {
+                    println!("{}", serde_json::to_string(event)?);
+                } else {
+                    println!(
+                        "#{} {} {:<16} {:<15} {:<20} {:<24} {}",
+                        event.id,
+                        event.timestamp,
+                        event.user.as_deref().unwrap_or("-"),
+                        event.source_ip.as_deref().unwrap_or("-"),
+                        event.action,
+                        event.target.as_deref().unwrap_or("-"),
+                        event.error.as_deref().unwrap_or("ok")
+                    );
+                }
+            }
 
-                // URL
-                println!("║ ├─ URL: {}", function.usage);
+            match events.last() {
+                Some(last) if all => after = Some(last.id),
+                _ => break,
+            }
+        },
+        AdminCommands::Events {
+            mut after,
+            limit,
+            all,
+            severity,
+            kind,
+            jsonl,
+        } => loop {
+            let events = client
+                .server_events(
+                    tarpc::context::current(),
+                    after,
+                    limit,
+                    severity,
+                    kind,
+                    auth_token.clone(),
+                )
+                .await?
+                .map_err(server_error)?;
 
-                // Add a command to invoke it
-                println!("║ └─ Invoke: cargo faasta invoke {}", function.name);
-                println!("╟──────────────────────────────────────────────────────");
+            for event in &events {
+                if jsonl {
+                    println!("{}", serde_json::to_string(event)?);
+                } else {
+                    println!(
+                        "#{} {} {:<8} {:<20} {:<24} {}",
+                        event.id,
+                        event.timestamp,
+                        event.severity,
+                        event.kind,
+                        event.subject.as_deref().unwrap_or("-"),
+                        event.detail
+                    );
+                }
             }
-            println!("╚══════════════════════════════════════════════════════");
 
-            Ok(())
+            match events.last() {
+                Some(last) if all => after = Some(last.id),
+                _ => break,
+            }
+        },
+        AdminCommands::SetLimit { username, limit } => {
+            client
+                .set_project_limit(context, username.clone(), limit, auth_token)
+                .await?
+                .map_err(server_error)?;
+            match limit {
+                Some(limit) => println!("✅ '{username}' may now own {limit} functions"),
+                None => println!("✅ Reset the function limit of '{username}'"),
+            }
         }
-        Ok(Err(e)) => Err(anyhow::anyhow!("Server error: {:?}", e)),
-        Err(e) => Err(anyhow::anyhow!("Communication error: {}", e)),
+        AdminCommands::Consistency {
+            check,
+            repair,
+            json,
+        } => {
+            let repair = match repair {
+                Some(kinds) if kinds.is_empty() => faasta_interface::DriftKind::ALL.to_vec(),
+                Some(kinds) => kinds,
+                None => Vec::new(),
+            };
+            let report = if check || !repair.is_empty() {
+                client
+                    .check_consistency(context, repair, auth_token)
+                    .await?
+                    .map_err(server_error)?
+            } else {
+                let Some(report) = client
+                    .consistency_report(context, auth_token)
+                    .await?
+                    .map_err(server_error)?
+                else {
+                    println!("No consistency check has run yet; run one with --check");
+                    return Ok(());
+                };
+                report
+            };
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!(
+                    "Checked {} functions and {} artifacts at {}",
+                    report.functions, report.artifacts, report.checked_at
+                );
+                if report.drift.is_empty() {
+                    println!("✅ No drift found");
+                }
+                for drift in &report.drift {
+                    let repaired = if drift.repaired { " (repaired)" } else { "" };
+                    println!(
+                        "  {} {}: {}{repaired}",
+                        drift.kind, drift.subject, drift.detail
+                    );
+                }
+            }
+        }
+        AdminCommands::Capacity { refresh, json } => {
+            let report = client
+                .capacity_report(context, refresh, auth_token)
+                .await?
+                .map_err(server_error)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{report}");
+            }
+        }
+        AdminCommands::Gc { dry_run, json } => {
+            let mut context = context;
+            context.deadline = std::time::Instant::now() + GC_TIMEOUT;
+            let report = client
+                .collect_garbage(context, dry_run, auth_token)
+                .await?
+                .map_err(server_error)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+                return Ok(());
+            }
+            if report.garbage.is_empty() {
+                println!("✅ No garbage found");
+                return Ok(());
+            }
+            for garbage in &report.garbage {
+                let state = if garbage.removed {
+                    "removed"
+                } else if !garbage.due {
+                    "in grace"
+                } else if report.dry_run {
+                    "due"
+                } else {
+                    "failed"
+                };
+                println!(
+                    "{:<17} {:<9} {:>10}  {}: {}",
+                    garbage.kind, state, garbage.bytes, garbage.subject, garbage.detail
+                );
+            }
+            if report.dry_run {
+                let due: u64 = report
+                    .garbage
+                    .iter()
+                    .filter(|garbage| garbage.due)
+                    .map(|garbage| garbage.bytes)
+                    .sum();
+                println!(
+                    "Dry run: a collection now would free {:.1} MiB",
+                    due as f64 / (1024.0 * 1024.0)
+                );
+            } else {
+                println!(
+                    "✅ Freed {:.1} MiB",
+                    report.freed_bytes() as f64 / (1024.0 * 1024.0)
+                );
+            }
+        }
+        AdminCommands::Nodes => {
+            let nodes = client
+                .list_cluster_nodes(context, auth_token)
+                .await?
+                .map_err(server_error)?;
+            println!(
+                "{:<24} {:<10} {:<8} {:<26} LAST SEEN",
+                "NODE", "VERSION", "STATE", "STARTED"
+            );
+            for node in nodes {
+                println!(
+                    "{:<24} {:<10} {:<8} {:<26} {}",
+                    node.id,
+                    node.version,
+                    if node.alive { "running" } else { "gone" },
+                    node.started_at,
+                    node.last_seen
+                );
+            }
+        }
+        AdminCommands::Backup { output } => {
+            let mut context = context;
+            context.deadline = std::time::Instant::now() + BACKUP_TIMEOUT;
+            let info = client
+                .create_backup(context, auth_token.clone())
+                .await?
+                .map_err(server_error)?;
+            let path = output.unwrap_or_else(|| PathBuf::from(&info.name));
+            // Backups hold secrets, so only their owner may read them
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let mut file = options
+                .open(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to create {}: {e}", path.display()))?;
+            for index in 0.. {
+                let chunk = client
+                    .read_backup(
+                        tarpc::context::current(),
+                        info.name.clone(),
+                        index,
+                        auth_token.clone(),
+                    )
+                    .await?
+                    .map_err(server_error)?;
+                if chunk.is_empty() {
+                    break;
+                }
+                file.write_all(&chunk)?;
+            }
+            println!(
+                "✅ Backed up {} records and {} artifacts to {} ({:.1} MiB)",
+                info.records,
+                info.artifacts,
+                path.display(),
+                info.size_bytes as f64 / (1024.0 * 1024.0)
+            );
+            println!(
+                "Restore it on a stopped server with: server-wasi restore {}",
+                path.display()
+            );
+        }
+        AdminCommands::Usage {
+            period,
+            format,
+            output,
+        } => {
+            let export = client
+                .export_usage(context, period, format, auth_token)
+                .await?
+                .map_err(server_error)?;
+            match output {
+                Some(path) => {
+                    fs::write(&path, export)?;
+                    eprintln!("✅ Wrote usage to {}", path.display());
+                }
+                None => print!("{export}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_team(team: &faasta_interface::TeamInfo) {
+    match &team.github_org {
+        Some(org) => println!("Team: {} (GitHub org: {org})", team.name),
+        None => println!("Team: {}", team.name),
+    }
+    for member in &team.members {
+        println!("  ├─ {} ({})", member.username, member.role);
+    }
+    if team.functions.is_empty() {
+        println!("  └─ No functions");
+    } else {
+        println!("  └─ Functions: {}", team.functions.join(", "));
     }
 }
@@ -0,0 +1,133 @@
+//! Optional size optimization of built components.
+//!
+//! `cargo faasta build --optimize`, or `enabled = true` under `[optimize]` in the
+//! project's `faasta.toml`, builds with size-oriented compiler settings and then
+//! runs `wasm-opt` over the component when it is installed. Smaller components
+//! upload, compile and start faster, so the sizes before and after are reported.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::Command;
+
+/// The `[optimize]` section of `faasta.toml`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct OptimizeSettings {
+    /// Optimize every build, as if `--optimize` was passed
+    pub enabled: bool,
+    /// `opt-level` of the release build
+    pub opt_level: String,
+    /// Link-time optimization of the release build
+    pub lto: bool,
+    /// Strip symbols and debug info from the release build
+    pub strip: bool,
+    /// wasm-opt optimization level (O1-O4, Os or Oz), or "none" to skip wasm-opt
+    pub wasm_opt_level: String,
+    /// wasm-opt binary to run
+    pub wasm_opt: String,
+}
+
+impl Default for OptimizeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            opt_level: "z".to_string(),
+            lto: true,
+            strip: true,
+            wasm_opt_level: "Oz".to_string(),
+            wasm_opt: "wasm-opt".to_string(),
+        }
+    }
+}
+
+impl OptimizeSettings {
+    /// Cargo profile overrides applied to the release build
+    pub fn cargo_env(&self) -> [(&'static str, String); 4] {
+        [
+            ("CARGO_PROFILE_RELEASE_OPT_LEVEL", self.opt_level.clone()),
+            ("CARGO_PROFILE_RELEASE_LTO", self.lto.to_string()),
+            ("CARGO_PROFILE_RELEASE_CODEGEN_UNITS", "1".to_string()),
+            ("CARGO_PROFILE_RELEASE_STRIP", self.strip.to_string()),
+        ]
+    }
+}
+
+/// Size of a component before and after optimization
+pub struct SizeReport {
+    pub before: u64,
+    pub after: u64,
+    /// Why wasm-opt didn't run, if it didn't
+    pub skipped: Option<String>,
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kb = |bytes: u64| bytes as f64 / 1024.0;
+        if self.after == self.before {
+            write!(f, "{:.1}KB", kb(self.after))?;
+        } else {
+            let saved = 100.0 - self.after as f64 * 100.0 / self.before.max(1) as f64;
+            write!(
+                f,
+                "{:.1}KB -> {:.1}KB (-{saved:.0}%)",
+                kb(self.before),
+                kb(self.after)
+            )?;
+        }
+        if let Some(reason) = &self.skipped {
+            write!(f, " ({reason})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Run wasm-opt over the component at `wasm_path`, replacing it if that succeeds.
+/// A missing or failing wasm-opt leaves the component as it is.
+pub fn run_wasm_opt(settings: &OptimizeSettings, wasm_path: &Path) -> Result<SizeReport> {
+    let before = fs::metadata(wasm_path)
+        .with_context(|| format!("Failed to read {}", wasm_path.display()))?
+        .len();
+    let unchanged = |reason: String| SizeReport {
+        before,
+        after: before,
+        skipped: Some(reason),
+    };
+    if settings.wasm_opt_level == "none" {
+        return Ok(unchanged("wasm-opt disabled".to_string()));
+    }
+
+    let optimized = wasm_path.with_extension("opt.wasm");
+    let output = Command::new(&settings.wasm_opt)
+        .arg(format!("-{}", settings.wasm_opt_level))
+        .arg(wasm_path)
+        .arg("-o")
+        .arg(&optimized)
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Ok(unchanged(
+                "wasm-opt not found; install binaryen to shrink it further".to_string(),
+            ))
+        }
+        Err(e) => return Err(e).context("Failed to run wasm-opt"),
+    };
+    if !output.status.success() {
+        let _ = fs::remove_file(&optimized);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().next().unwrap_or("unknown error").trim();
+        return Ok(unchanged(format!("wasm-opt failed: {reason}")));
+    }
+
+    fs::rename(&optimized, wasm_path)
+        .with_context(|| format!("Failed to replace {}", wasm_path.display()))?;
+    Ok(SizeReport {
+        before,
+        after: fs::metadata(wasm_path)?.len(),
+        skipped: None,
+    })
+}
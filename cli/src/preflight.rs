@@ -0,0 +1,134 @@
+//! Checks of a component before it's uploaded.
+//!
+//! The server refuses components that don't fit the `wasi:http/proxy` world or
+//! are larger than it accepts, but only once they're uploaded. Before uploading,
+//! the CLI reads the component's WIT world with wit-component and checks its
+//! exports and imports against the rules the server reports in `server_info`,
+//! naming each one at fault, then compares its size with the server's limit.
+//! Servers that don't report their rules, and components this release can't
+//! read, are left for the server to judge, which still checks everything itself.
+
+use faasta_interface::components::{ComponentRules, Export};
+use faasta_interface::{FaastaError, FunctionResult, ServerInfo, LIMIT_ARTIFACT_BYTES};
+use tracing::debug;
+use wit_component::DecodedWasm;
+
+/// Check `wasm` against the world and the size limit of the server of `info`,
+/// failing as that server would before anything is uploaded
+pub fn check(info: &ServerInfo, wasm: &[u8]) -> FunctionResult<()> {
+    if let Some(rules) = ComponentRules::reported(info) {
+        match world(wasm) {
+            Ok((exports, imports)) => rules
+                .check(&exports, &imports)
+                .map_err(|details| FaastaError::InvalidComponent { details })?,
+            Err(e) => debug!("Leaving the component's world for the server to check: {e}"),
+        }
+    }
+    let size = wasm.len() as u64;
+    match info.limit(LIMIT_ARTIFACT_BYTES) {
+        Some(limit) if size > limit => Err(FaastaError::TooLarge { size, limit }),
+        _ => Ok(()),
+    }
+}
+
+/// Exports and imports of a component
+type World = (Vec<(String, Export)>, Vec<String>);
+
+/// The exports and imports of the component in `wasm`
+fn world(wasm: &[u8]) -> Result<World, String> {
+    let (resolve, world) = match wit_component::decode(wasm) {
+        Ok(DecodedWasm::Component(resolve, world)) => (resolve, world),
+        Ok(DecodedWasm::WitPackage(..)) => {
+            return Err("this is a WIT package, not a component implementing one".to_string())
+        }
+        Err(e) => return Err(format!("failed to read the component: {e:#}")),
+    };
+    let world = &resolve.worlds[world];
+    let exports = world
+        .exports
+        .iter()
+        .map(|(key, item)| {
+            let export = match item {
+                wit_parser::WorldItem::Interface { id, .. } => {
+                    Export::Interface(resolve.interfaces[*id].functions.keys().cloned().collect())
+                }
+                _ => Export::Other,
+            };
+            (resolve.name_world_key(key), export)
+        })
+        .collect();
+    let imports = world
+        .imports
+        .keys()
+        .map(|key| resolve.name_world_key(key))
+        .collect();
+    Ok((exports, imports))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wit_component::{embed_component_metadata, ComponentEncoder, StringEncoding};
+    use wit_parser::{ManglingAndAbi, Resolve};
+
+    /// A component of the world `app` in `wit`, doing nothing
+    fn component(wit: &str) -> Vec<u8> {
+        let mut resolve = Resolve::default();
+        let package = resolve.push_str("app.wit", wit).unwrap();
+        let world = resolve.select_world(&[package], Some("app")).unwrap();
+        let mut module = wit_component::dummy_module(&resolve, world, ManglingAndAbi::Standard32);
+        embed_component_metadata(&mut module, &resolve, world, StringEncoding::UTF8, false)
+            .unwrap();
+        ComponentEncoder::default()
+            .module(&module)
+            .unwrap()
+            .encode()
+            .unwrap()
+    }
+
+    const HTTP: &str = "
+        package wasi:http@0.2.0 {
+            interface incoming-handler { handle: func(); }
+        }
+        package acme:db@1.0.0 {
+            interface store { get: func(); }
+        }
+    ";
+
+    #[test]
+    fn test_components_are_checked_against_the_reported_rules() {
+        let mut info = ServerInfo {
+            protocol_version: faasta_interface::PROTOCOL_VERSION,
+            server_version: "0.0.0".to_string(),
+            limits: [(LIMIT_ARTIFACT_BYTES.to_string(), 1 << 20)].into(),
+            features: ComponentRules::current().features(),
+        };
+        let proxy = component(&format!(
+            "package local:app; {HTTP}
+             world app {{ export wasi:http/incoming-handler@0.2.0; }}"
+        ));
+        assert_eq!(check(&info, &proxy), Ok(()));
+
+        let other = component(&format!(
+            "package local:app; {HTTP}
+             world app {{ import acme:db/store@1.0.0; export run: func(); }}"
+        ));
+        let Err(FaastaError::InvalidComponent { details }) = check(&info, &other) else {
+            panic!("the component should be refused");
+        };
+        assert_eq!(details.len(), 2);
+        assert!(details[0].contains("missing export") && details[0].contains("run"));
+        assert!(details[1].contains("`acme:db/store@1.0.0`"));
+
+        // What can't be read is left to the server, as is everything by servers
+        // that don't report their rules
+        assert_eq!(check(&info, b"not wasm"), Ok(()));
+        info.features.clear();
+        assert_eq!(check(&info, &other), Ok(()));
+        info.limits.insert(LIMIT_ARTIFACT_BYTES.to_string(), 10);
+        assert!(matches!(
+            check(&info, &proxy),
+            Err(FaastaError::TooLarge { limit: 10, .. })
+        ));
+    }
+}
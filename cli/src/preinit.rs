@@ -0,0 +1,179 @@
+//! Optional pre-initialization of built components.
+//!
+//! `cargo faasta build --pre-init`, or `enabled = true` under `[pre-init]` in the
+//! project's `faasta.toml`, runs Wizer over a release build: the component's
+//! initialization function runs once, and the memory and globals it leaves behind
+//! are snapshotted into the component that is deployed. Functions with heavy static
+//! initialization (parsing embedded data, building lookup tables, warming a
+//! language runtime) then start every request from that state instead of
+//! rebuilding it.
+//!
+//! Builds are `wasm32-wasip2` components, which the standalone `wizer` binary
+//! can't read, as it only snapshots core modules. The Wizer that ships with the
+//! Wasmtime CLI, `wasmtime wizer`, handles components and is the default; a build
+//! that doesn't come out of it as a component fails.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The `[pre-init]` section of `faasta.toml`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PreInitSettings {
+    /// Pre-initialize every release build, as if `--pre-init` was passed
+    pub enabled: bool,
+    /// Export of the component that runs the initialization
+    pub init_func: String,
+    /// Let the initialization use WASI, e.g. to read environment variables. Off by
+    /// default, as whatever it reads is frozen into the snapshot.
+    pub allow_wasi: bool,
+    /// Wizer command to run, split on whitespace; it must handle components
+    pub wizer: String,
+}
+
+impl Default for PreInitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            init_func: "wizer.initialize".to_string(),
+            allow_wasi: false,
+            wizer: "wasmtime wizer".to_string(),
+        }
+    }
+}
+
+/// Run the initialization of the component at `wasm_path` and replace it with the
+/// snapshot Wizer takes afterwards. Returns how long that took. Unlike wasm-opt,
+/// a missing or failing Wizer fails the build, as deploying without the snapshot
+/// would silently put the initialization back on every request.
+pub fn run_wizer(settings: &PreInitSettings, wasm_path: &Path) -> Result<Duration> {
+    let started = Instant::now();
+    let initialized = wasm_path.with_extension("init.wasm");
+    let mut words = settings.wizer.split_whitespace();
+    let Some(program) = words.next() else {
+        bail!("No Wizer command is set under [pre-init]");
+    };
+    let mut command = std::process::Command::new(program);
+    command
+        .args(words)
+        .arg(wasm_path)
+        .arg("-o")
+        .arg(&initialized)
+        .arg("--init-func")
+        .arg(&settings.init_func);
+    if settings.allow_wasi {
+        command.arg("--allow-wasi");
+    }
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => bail!(
+            "{} not found; install the Wasmtime CLI, whose 'wasmtime wizer' handles components",
+            program
+        ),
+        Err(e) => return Err(e).context("Failed to run wizer"),
+    };
+    if !output.status.success() {
+        let _ = fs::remove_file(&initialized);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "Pre-initialization with '{}' failed: {}",
+            settings.init_func,
+            stderr.trim()
+        );
+    }
+
+    let snapshot = fs::read(&initialized)
+        .with_context(|| format!("Failed to read {}", initialized.display()))?;
+    if !is_component(&snapshot) {
+        let _ = fs::remove_file(&initialized);
+        bail!(
+            "'{}' didn't produce a component; use a Wizer that handles components, such as 'wasmtime wizer'",
+            settings.wizer
+        );
+    }
+    fs::rename(&initialized, wasm_path)
+        .with_context(|| format!("Failed to replace {}", wasm_path.display()))?;
+    Ok(started.elapsed())
+}
+
+/// Whether `wasm` is a component rather than a core module, going by the layer
+/// in its header
+fn is_component(wasm: &[u8]) -> bool {
+    wasm.starts_with(b"\0asm") && wasm.get(6..8) == Some(&[1, 0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_wizer_fails_and_keeps_the_build() {
+        let dir = std::env::temp_dir().join(format!("faasta-preinit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let wasm_path = dir.join("fn.wasm");
+        fs::write(&wasm_path, b"\0asm").unwrap();
+
+        let settings = PreInitSettings {
+            wizer: "faasta-no-such-wizer".to_string(),
+            ..Default::default()
+        };
+        let error = run_wizer(&settings, &wasm_path).unwrap_err();
+        assert!(error.to_string().contains("not found"));
+        assert_eq!(fs::read(&wasm_path).unwrap(), b"\0asm");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A stand-in for Wizer that writes `output` as the snapshot, after checking
+    /// it was given the init function and no WASI
+    #[cfg(unix)]
+    fn fake_wizer(dir: &Path, output: &[u8]) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let snapshot = dir.join("snapshot.wasm");
+        fs::write(&snapshot, output).unwrap();
+        let script = dir.join("wizer.sh");
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n[ \"$4 $5 $6\" = \"--init-func wizer.initialize \" ] || exit 1\ncp {} \"$3\"\n",
+                snapshot.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        script.display().to_string()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_only_a_component_snapshot_replaces_the_build() {
+        const COMPONENT: &[u8] = b"\0asm\x0d\0\x01\0";
+        const MODULE: &[u8] = b"\0asm\x01\0\0\0";
+        let dir = std::env::temp_dir().join(format!("faasta-preinit-snap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let wasm_path = dir.join("fn.wasm");
+        fs::write(&wasm_path, COMPONENT).unwrap();
+
+        let settings = PreInitSettings {
+            wizer: fake_wizer(&dir, MODULE),
+            ..Default::default()
+        };
+        let error = run_wizer(&settings, &wasm_path).unwrap_err();
+        assert!(error.to_string().contains("didn't produce a component"));
+        assert_eq!(fs::read(&wasm_path).unwrap(), COMPONENT);
+
+        let snapshot = [COMPONENT, b"initialized"].concat();
+        let settings = PreInitSettings {
+            wizer: fake_wizer(&dir, &snapshot),
+            ..Default::default()
+        };
+        run_wizer(&settings, &wasm_path).unwrap();
+        assert_eq!(fs::read(&wasm_path).unwrap(), snapshot);
+        assert!(!wasm_path.with_extension("init.wasm").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
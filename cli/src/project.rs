@@ -0,0 +1,107 @@
+//! Per-project settings in `faasta.toml`.
+//!
+//! The file is optional and sits next to the project's `Cargo.toml`, or in any
+//! directory a prebuilt component is deployed from. Flags given on the command
+//! line take precedence over it.
+
+use anyhow::{bail, Context, Result};
+use faasta_interface::{CorsPolicy, JwtAuthPolicy, TransformRule};
+use serde::Deserialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::optimize::OptimizeSettings;
+use crate::preinit::PreInitSettings;
+
+/// Name of the settings file
+pub const CONFIG_FILE: &str = "faasta.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ProjectConfig {
+    pub function: FunctionSettings,
+    pub optimize: OptimizeSettings,
+    #[serde(rename = "pre-init")]
+    pub pre_init: PreInitSettings,
+    /// Cross-origin requests the server answers for the function; an empty
+    /// `allowed-origins` removes the policy
+    pub cors: Option<CorsPolicy>,
+    /// Tokens requests to the function must carry; an empty `issuer` removes the
+    /// requirement
+    pub auth: Option<JwtAuthPolicy>,
+    /// Changes to the function's requests and responses, by route; an empty list
+    /// removes them
+    pub transforms: Option<Vec<TransformRule>>,
+    /// Components serving routes of the function instead of its main component;
+    /// an empty list removes them
+    pub routes: Option<Vec<RouteSettings>>,
+}
+
+/// A `[[routes]]` entry: the component serving the requests under a route
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RouteSettings {
+    /// Path prefix, such as `/admin`
+    pub route: String,
+    /// Component serving it, relative to the directory of `faasta.toml`
+    pub wasm: String,
+}
+
+/// The `[function]` section: how the project is deployed
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FunctionSettings {
+    /// Function name, instead of the package name or the component's file name
+    pub name: Option<String>,
+    /// Team the function is published for
+    pub team: Option<String>,
+    /// Server to deploy to
+    pub server: Option<String>,
+    /// Component to deploy, relative to the directory of `faasta.toml`
+    pub wasm: Option<String>,
+    /// Have the server keep the function warm (`--keep-warm`, `--no-keep-warm`)
+    pub keep_warm: Option<bool>,
+    /// One line about what the function does, shown by `cargo faasta list`
+    pub description: Option<String>,
+    /// Labels to find the function by with `cargo faasta list --tag`
+    pub tags: Option<Vec<String>>,
+}
+
+impl ProjectConfig {
+    /// Check the policies the settings declare, before anything is deployed
+    pub fn validate(&self) -> Result<()> {
+        if let Some(Err(e)) = self.cors.as_ref().map(CorsPolicy::validate) {
+            bail!("Invalid [cors] in {CONFIG_FILE}: {e}");
+        }
+        if let Some(Err(e)) = self
+            .auth
+            .as_ref()
+            .filter(|auth| !auth.issuer.is_empty())
+            .map(JwtAuthPolicy::validate)
+        {
+            bail!("Invalid [auth] in {CONFIG_FILE}: {e}");
+        }
+        if let Some(Err(e)) = self
+            .transforms
+            .iter()
+            .flatten()
+            .map(TransformRule::validate)
+            .find(Result::is_err)
+        {
+            bail!("Invalid [[transforms]] in {CONFIG_FILE}: {e}");
+        }
+        Ok(())
+    }
+}
+
+/// Load the settings in `dir`, which are all defaults if it has no `faasta.toml`
+pub fn load(dir: &Path) -> Result<ProjectConfig> {
+    let path = dir.join(CONFIG_FILE);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ProjectConfig::default()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    toml::from_str(&contents).with_context(|| format!("Invalid {}", path.display()))
+}
@@ -0,0 +1,138 @@
+//! Pushing components to OCI registries.
+//!
+//! `cargo faasta push` stores a component as a wasm OCI artifact (a config blob,
+//! the component as the single layer, and an image manifest tying them together)
+//! so CI can publish builds to a registry and servers can deploy them from there.
+//! Registry credentials come from `FAASTA_REGISTRY_USERNAME` and
+//! `FAASTA_REGISTRY_PASSWORD`; registries that hand out bearer tokens are asked for
+//! one with them.
+
+use anyhow::{anyhow, bail, Context, Result};
+use faasta_interface::oci::{
+    OciDescriptor, OciManifest, OciReference, RegistryAuth, RegistryCredentials, WasmConfig,
+    CONFIG_MEDIA_TYPE, LAYER_MEDIA_TYPE, MANIFEST_MEDIA_TYPE,
+};
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::{Client, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+pub const USERNAME_ENV: &str = "FAASTA_REGISTRY_USERNAME";
+pub const PASSWORD_ENV: &str = "FAASTA_REGISTRY_PASSWORD";
+
+/// Registry credentials from the environment, if both are set
+pub fn credentials_from_env() -> Option<RegistryCredentials> {
+    Some(RegistryCredentials {
+        username: std::env::var(USERNAME_ENV).ok()?,
+        password: std::env::var(PASSWORD_ENV).ok()?,
+    })
+}
+
+/// `sha256:<hex>` digest of content
+pub fn digest(content: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(content)))
+}
+
+/// Push `component` as a wasm artifact to `reference`, returning the manifest digest
+pub async fn push(
+    reference: &OciReference,
+    component: &[u8],
+    credentials: Option<RegistryCredentials>,
+) -> Result<String> {
+    if reference.is_digest() {
+        bail!("Push to a tag rather than a digest: {reference}");
+    }
+    let client = Client::new();
+    let base = reference.api_base();
+    let mut auth = RegistryAuth::new(credentials);
+
+    let layer_digest = digest(component);
+    let config = serde_json::to_vec(&WasmConfig {
+        created: chrono::Utc::now().to_rfc3339(),
+        architecture: "wasm".to_string(),
+        os: "wasip2".to_string(),
+        layer_digests: vec![layer_digest.clone()],
+    })?;
+    let config_digest = digest(&config);
+
+    push_blob(&client, &mut auth, &base, &config, &config_digest).await?;
+    push_blob(&client, &mut auth, &base, component, &layer_digest).await?;
+
+    let manifest = serde_json::to_vec(&OciManifest {
+        schema_version: 2,
+        media_type: MANIFEST_MEDIA_TYPE.to_string(),
+        config: OciDescriptor {
+            media_type: CONFIG_MEDIA_TYPE.to_string(),
+            digest: config_digest,
+            size: config.len() as u64,
+        },
+        layers: vec![OciDescriptor {
+            media_type: LAYER_MEDIA_TYPE.to_string(),
+            digest: layer_digest,
+            size: component.len() as u64,
+        }],
+    })?;
+    let manifest_url = format!("{base}/manifests/{}", reference.reference);
+    let response = auth
+        .send(&client, || {
+            client
+                .put(&manifest_url)
+                .header(CONTENT_TYPE, MANIFEST_MEDIA_TYPE)
+                .body(manifest.clone())
+        })
+        .await?;
+    check_status(response, "Registry rejected the manifest").await?;
+    Ok(digest(&manifest))
+}
+
+/// Upload a blob unless the registry already has it
+async fn push_blob(
+    client: &Client,
+    auth: &mut RegistryAuth,
+    base: &str,
+    content: &[u8],
+    digest: &str,
+) -> Result<()> {
+    let blob_url = format!("{base}/blobs/{digest}");
+    let response = auth.send(client, || client.head(&blob_url)).await?;
+    if response.status().is_success() {
+        return Ok(());
+    }
+
+    let uploads_url = format!("{base}/blobs/uploads/");
+    let response = auth.send(client, || client.post(&uploads_url)).await?;
+    let response = check_status(response, "Registry refused to start an upload").await?;
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| anyhow!("Registry did not say where to upload"))?;
+    // The location may be relative to the registry
+    let mut upload_url = Url::parse(base)?
+        .join(location)
+        .context("Registry returned an invalid upload location")?;
+    upload_url.query_pairs_mut().append_pair("digest", digest);
+
+    let response = auth
+        .send(client, || {
+            client
+                .put(upload_url.clone())
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .body(content.to_vec())
+        })
+        .await?;
+    check_status(response, "Registry rejected the upload").await?;
+    Ok(())
+}
+
+async fn check_status(response: Response, context: &str) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        bail!("{context} ({status}); set {USERNAME_ENV} and {PASSWORD_ENV} to push to it");
+    }
+    bail!("{context} ({status}): {}", body.trim())
+}
@@ -1,19 +1,32 @@
 use anyhow::{anyhow, Context, Result};
-use faasta_interface::FunctionServiceClient;
+use clap::ValueEnum;
+use faasta_interface::{FunctionServiceClient, ServerInfo, PROTOCOL_VERSION};
 use std::io;
 // futures prelude removed
+use rand::Rng;
 use s2n_quic::client::Connect;
+use s2n_quic::connection;
 use s2n_quic::provider::tls::default::callbacks::VerifyHostNameCallback;
 use s2n_quic::provider::tls::default::Client as TlsClient;
+use s2n_quic::provider::tls::rustls::Client as RustlsClient;
+use s2n_quic::stream::BidirectionalStream;
 use s2n_quic::Client;
 use std::net::SocketAddr;
 use std::path::{Path as StdPath, PathBuf};
-use std::process::exit;
-use tarpc::serde_transport as transport;
+use std::time::Duration;
+use tarpc::serde_transport;
 use tarpc::tokio_serde::formats::Bincode;
 use tarpc::tokio_util::codec::LengthDelimitedCodec;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tracing::debug;
 
+use crate::errors;
+use crate::happy_eyeballs;
+use crate::proxy::Proxy;
+use crate::tls;
+use crate::transport::{self, Transport};
+use crate::{optimize, preinit, project};
+
 /// Compare two file paths in a slightly more robust way.
 /// (On Windows, e.g., backslash vs forward slash).
 fn same_file_path(a: &str, b: &str) -> bool {
@@ -23,14 +36,240 @@ fn same_file_path(a: &str, b: &str) -> bool {
     path_a == path_b
 }
 
+/// Attempts made to connect to the server
+const CONNECT_ATTEMPTS_ENV: &str = "FAASTA_CONNECT_ATTEMPTS";
+/// Milliseconds waited before the first retry of a failed connection
+const CONNECT_BACKOFF_ENV: &str = "FAASTA_CONNECT_BACKOFF_MS";
+
 // Create a connection to the function service
 pub async fn connect_to_function_service(server_addr: &str) -> Result<FunctionServiceClient> {
+    connect_with_info(server_addr)
+        .await
+        .map(|(client, _)| client)
+}
+
+/// Connect to the function service, with what the server said about itself
+pub async fn connect_with_info(server_addr: &str) -> Result<(FunctionServiceClient, ServerInfo)> {
+    let client = connect(server_addr).await?;
+    let info = check_protocol(&client).await?;
+    Ok((client, info))
+}
+
+/// Check that the server speaks the protocol this CLI does, so a mismatch fails
+/// with a message saying so instead of a decode error halfway through a command
+async fn check_protocol(client: &FunctionServiceClient) -> Result<ServerInfo> {
+    let info = client
+        .server_info(tarpc::context::current())
+        .await
+        .map_err(|e| {
+            anyhow!(
+                "The server didn't say which protocol it speaks ({e}). It likely runs a \
+                 release of Faasta this cargo-faasta can't talk to; check the server \
+                 address and upgrade with 'cargo install cargo-faasta'."
+            )
+        })?;
+    debug!(
+        "Server runs Faasta {} with protocol {} and features {:?}",
+        info.server_version, info.protocol_version, info.features
+    );
+    check_protocol_version(info.protocol_version, &info.server_version)?;
+    Ok(info)
+}
+
+/// Fail unless a server speaking protocol `server_protocol` can be used
+fn check_protocol_version(server_protocol: u32, server_version: &str) -> Result<()> {
+    match server_protocol.cmp(&PROTOCOL_VERSION) {
+        std::cmp::Ordering::Equal => Ok(()),
+        std::cmp::Ordering::Greater => Err(anyhow!(
+            "The server runs Faasta {server_version}, speaking protocol version \
+             {server_protocol}, but this cargo-faasta only speaks version \
+             {PROTOCOL_VERSION}. Please upgrade it with 'cargo install cargo-faasta'."
+        )),
+        std::cmp::Ordering::Less => Err(anyhow!(
+            "The server runs Faasta {server_version}, speaking protocol version \
+             {server_protocol}, but this cargo-faasta speaks version {PROTOCOL_VERSION}. \
+             Install cargo-faasta {server_version} to use it, or ask its operator to \
+             upgrade."
+        )),
+    }
+}
+
+/// Open a client of the function service at `server_addr`
+async fn connect(server_addr: &str) -> Result<FunctionServiceClient> {
     // Check if we're connecting to localhost or 127.0.0.1
-    let skip_tls_validation =
-        server_addr.starts_with("localhost:") || server_addr.starts_with("127.0.0.1:");
+    let skip_tls_validation = server_addr.starts_with("localhost:")
+        || server_addr.starts_with("127.0.0.1:")
+        || server_addr.starts_with("[::1]:");
+
+    let server_name = if server_addr.starts_with("localhost:")
+        || server_addr.contains("localhost.localdomain:")
+    {
+        "localhost".to_string()
+    } else {
+        // Extract the hostname from the original server_addr string for SNI
+        server_host(server_addr).to_string()
+    };
+
+    // A dropped packet shouldn't fail a deploy, so transient failures are
+    // retried. Errors that retrying can't fix, such as a rejected certificate, are
+    // reported right away.
+    let retry = ConnectRetry::from_env();
+    let options = transport::options();
+
+    // QUIC can't get through most proxies, so a proxied connection is TLS over TCP.
+    // The proxy resolves the server's name, so it isn't looked up here.
+    if options.transport == Transport::Quic {
+        if options.proxy.is_some() {
+            return Err(anyhow!(
+                "QUIC can't go through a proxy. Use --transport tcp or auto with --proxy."
+            ));
+        }
+    } else if let Some(proxy) = Proxy::for_host(options.proxy.as_ref(), &server_name)? {
+        let port = server_port(server_addr)?;
+        debug!("Connecting through proxy {}:{}", proxy.host, proxy.port);
+        let stream = retry
+            .run(
+                || transport::connect_tcp_via(&proxy, &server_name, port, skip_tls_validation),
+                transport::is_transient_io,
+                transport::tcp_error_message,
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to connect through proxy {}: {}", proxy.host, e))?;
+        debug!("Opened TLS connection to function service");
+        return Ok(rpc_client(stream));
+    }
 
-    // Set up the QUIC client with minimal logging
-    let client = if skip_tls_validation {
+    // Every address the name resolves to is tried, racing them so one that
+    // doesn't answer can't hold up the others
+    let addrs = resolve_server_addrs(server_addr).await?;
+    let connect_tcp = || {
+        happy_eyeballs::race(&addrs, transport::is_transient_io, |addr| {
+            transport::connect_tcp(addr, &server_name, skip_tls_validation)
+        })
+    };
+    match options.transport {
+        Transport::Quic => {
+            let clients = QuicClients::new(skip_tls_validation, &addrs)?;
+            let stream = retry
+                .run(
+                    || open_quic_stream(&clients, &addrs, &server_name),
+                    is_transient,
+                    connect_error_message,
+                )
+                .await
+                .map_err(|e| anyhow!("Failed to connect: {}", e))?;
+            debug!("Opened bidirectional stream to function service");
+            Ok(rpc_client(stream))
+        }
+        Transport::Tcp => {
+            let stream = retry
+                .run(
+                    connect_tcp,
+                    transport::is_transient_io,
+                    transport::tcp_error_message,
+                )
+                .await
+                .map_err(|e| anyhow!("Failed to connect: {}", e))?;
+            debug!("Opened TLS connection to function service");
+            Ok(rpc_client(stream))
+        }
+        Transport::Auto => {
+            // UDP being blocked looks like a lost packet, and waiting out several
+            // handshake timeouts before trying TCP would take too long, so QUIC
+            // gets a single attempt here
+            let clients = QuicClients::new(skip_tls_validation, &addrs)?;
+            let quic_error = match open_quic_stream(&clients, &addrs, &server_name).await {
+                Ok(stream) => {
+                    debug!("Opened bidirectional stream to function service");
+                    return Ok(rpc_client(stream));
+                }
+                Err(e) if is_transient(&e) => connect_error_message(&e),
+                Err(e) => return Err(anyhow!("Failed to connect: {}", connect_error_message(&e))),
+            };
+            eprintln!("QUIC connection failed ({quic_error}); falling back to TLS over TCP...");
+            let stream = retry
+                .run(
+                    connect_tcp,
+                    transport::is_transient_io,
+                    transport::tcp_error_message,
+                )
+                .await
+                .map_err(|e| {
+                    anyhow!(
+                        "Failed to connect over QUIC ({}) or TCP ({}). Use --transport to pick one.",
+                        quic_error,
+                        e
+                    )
+                })?;
+            debug!("Opened TLS connection to function service");
+            Ok(rpc_client(stream))
+        }
+    }
+}
+
+/// QUIC clients for the address families of a server's addresses. A client bound
+/// to `0.0.0.0:0` can't reach IPv6 addresses, so those get one bound to `[::]:0`.
+struct QuicClients {
+    v4: Option<Client>,
+    v6: Option<Client>,
+}
+
+impl QuicClients {
+    fn new(skip_tls_validation: bool, addrs: &[SocketAddr]) -> Result<Self> {
+        let bind = |v6: bool| -> Result<Option<Client>> {
+            if !addrs.iter().any(|addr| addr.is_ipv6() == v6) {
+                return Ok(None);
+            }
+            let local = if v6 { "[::]:0" } else { "0.0.0.0:0" };
+            quic_client(skip_tls_validation, local).map(Some)
+        };
+        let v4 = bind(false)?;
+        // A host without IPv6 can still reach the IPv4 addresses
+        let v6 = match bind(true) {
+            Ok(client) => client,
+            Err(e) if v4.is_some() => {
+                debug!("Skipping IPv6 addresses: {e:#}");
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Self { v4, v6 })
+    }
+
+    fn get(&self, addr: SocketAddr) -> Option<&Client> {
+        if addr.is_ipv6() {
+            self.v6.as_ref()
+        } else {
+            self.v4.as_ref()
+        }
+    }
+
+    /// The addresses one of the clients can reach
+    fn reachable(&self, addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+        addrs
+            .iter()
+            .copied()
+            .filter(|addr| self.get(*addr).is_some())
+            .collect()
+    }
+}
+
+/// Set up a QUIC client bound to `local` with minimal logging
+fn quic_client(skip_tls_validation: bool, local: &str) -> Result<Client> {
+    let tls_options = transport::options().tls;
+    let client = if tls_options.is_custom() {
+        // A private CA or a pinned certificate is checked by rustls, the same way
+        // as on TCP connections
+        let tls_config = tls::client_config(&tls_options, skip_tls_validation, true)
+            .context("Failed to build TLS config")?;
+        Client::builder()
+            .with_tls(RustlsClient::from(tls_config))
+            .context("Failed to set TLS config")?
+            .with_io(local)
+            .context("Failed to set up client IO")?
+            .start()
+            .context("Failed to start client")?
+    } else if skip_tls_validation {
         // Create a struct that implements VerifyHostNameCallback to accept any hostname
         struct AcceptAnyHostname;
         impl VerifyHostNameCallback for AcceptAnyHostname {
@@ -59,7 +298,7 @@ pub async fn connect_to_function_service(server_addr: &str) -> Result<FunctionSe
         Client::builder()
             .with_tls(tls_config)
             .context("Failed to set TLS config")?
-            .with_io("0.0.0.0:0")
+            .with_io(local)
             .context("Failed to set up client IO")?
             .start()
             .context("Failed to start client")?
@@ -67,100 +306,205 @@ pub async fn connect_to_function_service(server_addr: &str) -> Result<FunctionSe
         // Standard client with default TLS settings
         // For non-localhost connections, use the system's PKI
         Client::builder()
-            .with_io("0.0.0.0:0")
+            .with_io(local)
             .context("Failed to set up client IO")?
             .start()
             .context("Failed to start client")?
     };
+    Ok(client)
+}
 
-    // Parse the server address, handling both IP:port and hostname:port formats
-    let addr: SocketAddr = match server_addr.parse() {
-        Ok(addr) => addr,
-        Err(_) => {
-            // Try to resolve the hostname
-            let parts: Vec<&str> = server_addr.split(':').collect();
-            if parts.len() != 2 {
-                return Err(anyhow!(
-                    "Invalid server address format. Expected hostname:port or IP:port"
-                ));
-            }
-
-            let hostname = parts[0];
-            let port = parts[1].parse::<u16>().context("Invalid port number")?;
-
-            // For localhost, use 127.0.0.1
-            if hostname == "localhost" || hostname == "localhost.localdomain" {
-                format!("127.0.0.1:{port}")
-                    .parse()
-                    .context("Failed to parse localhost address")?
-            } else {
-                // For other hostnames, try to resolve using DNS
-                match tokio::net::lookup_host(format!("{hostname}:{port}")).await {
-                    Ok(mut addrs) => {
-                        // Take the first resolved address
-                        if let Some(addr) = addrs.next() {
-                            addr
-                        } else {
-                            return Err(anyhow!(
-                                "Could not resolve hostname: {}. No addresses found.",
-                                hostname
-                            ));
-                        }
-                    }
-                    Err(e) => {
-                        return Err(anyhow!(
-                            "Could not resolve hostname: {}. Error: {}",
-                            hostname,
-                            e
-                        ));
-                    }
-                }
-            }
-        }
-    };
-
-    let server_name = if server_addr.starts_with("localhost:")
-        || server_addr.contains("localhost.localdomain:")
-    {
-        "localhost".to_string()
-    } else {
-        // Extract the hostname from the original server_addr string for SNI
-        let parts: Vec<&str> = server_addr.split(':').collect();
-        parts[0].to_string()
-    };
+/// The port of a `hostname:port` or `IP:port` address
+fn server_port(server_addr: &str) -> Result<u16> {
+    let (_, port) = server_addr.rsplit_once(':').ok_or_else(|| {
+        anyhow!("Invalid server address format. Expected hostname:port or IP:port")
+    })?;
+    port.parse().context("Invalid port number")
+}
 
-    let connect = Connect::new(addr).with_server_name(server_name.as_str());
+/// The host of a `hostname:port`, `IPv4:port` or `[IPv6]:port` address
+fn server_host(server_addr: &str) -> &str {
+    let host = server_addr
+        .rsplit_once(':')
+        .map_or(server_addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
 
-    let mut connection = client
-        .connect(connect)
-        .await
-        .map_err(|e| {
-            // Provide minimal error info for handshake failures
-            if e.to_string().contains("handshake") {
-                if e.to_string().contains("timeout") {
-                    anyhow!("Failed to connect: Handshake timeout. Check your network connection or firewall settings.")
-                } else {
-                    anyhow!("Failed to connect: TLS handshake error. The server may be down or unreachable.")
-                }
-            } else {
-                anyhow!("Failed to connect: {}", e)
-            }
-        })?;
+/// The addresses to try for `server_addr`, with the families interleaved
+async fn resolve_server_addrs(server_addr: &str) -> Result<Vec<SocketAddr>> {
+    if let Ok(addr) = server_addr.parse() {
+        return Ok(vec![addr]);
+    }
+    let hostname = server_host(server_addr);
+    let port = server_port(server_addr)?;
 
-    // Open bidirectional stream
-    let stream = connection
-        .open_bidirectional_stream()
+    // For localhost, use 127.0.0.1
+    if hostname == "localhost" || hostname == "localhost.localdomain" {
+        return Ok(vec![SocketAddr::from(([127, 0, 0, 1], port))]);
+    }
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((hostname, port))
         .await
-        .map_err(|e| anyhow!("Failed to open stream: {}", e))?;
-    debug!("Opened bidirectional stream to function service");
+        .map_err(|e| anyhow!("Could not resolve hostname: {}. Error: {}", hostname, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(anyhow!(
+            "Could not resolve hostname: {}. No addresses found.",
+            hostname
+        ));
+    }
+    Ok(happy_eyeballs::interleave(addrs))
+}
 
+/// Run the RPC client over a stream of either transport
+fn rpc_client<S>(stream: S) -> FunctionServiceClient
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
     let framed = LengthDelimitedCodec::builder().new_framed(stream);
-    let transport = transport::new(framed, Bincode::default());
+    let transport = serde_transport::new(framed, Bincode::default());
 
     // Use default client config
-    let client = FunctionServiceClient::new(Default::default(), transport).spawn();
+    FunctionServiceClient::new(Default::default(), transport).spawn()
+}
 
-    Ok(client)
+/// Open the stream the RPCs run over to the first of `addrs` that answers
+async fn open_quic_stream(
+    clients: &QuicClients,
+    addrs: &[SocketAddr],
+    server_name: &str,
+) -> Result<BidirectionalStream, connection::Error> {
+    let addrs = clients.reachable(addrs);
+    happy_eyeballs::race(&addrs, is_transient, |addr| {
+        let client = clients.get(addr).expect("address of a reachable family");
+        open_stream(client, addr, server_name)
+    })
+    .await
+}
+
+/// Connect to `addr` and open the stream the RPCs run over
+async fn open_stream(
+    client: &Client,
+    addr: SocketAddr,
+    server_name: &str,
+) -> Result<BidirectionalStream, connection::Error> {
+    let connect = Connect::new(addr).with_server_name(server_name);
+    let mut connection = client.connect(connect).await?;
+    connection.open_bidirectional_stream().await
+}
+
+/// Whether a connection failure may go away on its own, like a handshake that
+/// timed out because packets were lost. TLS failures, such as an untrusted
+/// certificate, and local errors aren't retried.
+fn is_transient(error: &connection::Error) -> bool {
+    match error {
+        connection::Error::Transport { code, .. } => !is_crypto_error(code.as_u64()),
+        connection::Error::Closed { .. }
+        | connection::Error::IdleTimerExpired { .. }
+        | connection::Error::MaxHandshakeDurationExceeded { .. }
+        | connection::Error::NoValidPath { .. }
+        | connection::Error::StatelessReset { .. }
+        | connection::Error::Unspecified { .. } => true,
+        _ => false,
+    }
+}
+
+/// QUIC carries TLS alerts as transport error codes 0x100 to 0x1ff
+fn is_crypto_error(code: u64) -> bool {
+    (0x100..=0x1ff).contains(&code)
+}
+
+fn connect_error_message(error: &connection::Error) -> String {
+    match error {
+        connection::Error::MaxHandshakeDurationExceeded { .. }
+        | connection::Error::IdleTimerExpired { .. } => {
+            "Handshake timeout. Check your network connection or firewall settings.".to_string()
+        }
+        connection::Error::Transport { code, .. } if is_crypto_error(code.as_u64()) => {
+            format!("TLS handshake error ({error}). The server's certificate may not be trusted.")
+        }
+        _ => error.to_string(),
+    }
+}
+
+/// How often and how patiently connecting is retried. `FAASTA_CONNECT_ATTEMPTS`
+/// (default 4) and `FAASTA_CONNECT_BACKOFF_MS` (default 250) override the defaults.
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectRetry {
+    /// Attempts in total, at least 1
+    pub attempts: u32,
+    /// Longest wait before the second attempt, doubled for every further attempt
+    pub base_delay: Duration,
+    /// Longest wait between two attempts
+    pub max_delay: Duration,
+}
+
+impl Default for ConnectRetry {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ConnectRetry {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name| std::env::var(name).ok()?.parse::<u64>().ok();
+        Self {
+            attempts: var(CONNECT_ATTEMPTS_ENV).map_or(default.attempts, |attempts| {
+                attempts.clamp(1, u32::MAX as u64) as u32
+            }),
+            base_delay: var(CONNECT_BACKOFF_ENV).map_or(default.base_delay, Duration::from_millis),
+            ..default
+        }
+    }
+
+    /// Wait after failed attempt number `attempt` (starting at 1): exponential
+    /// backoff, jittered between half and all of it so clients that failed together
+    /// don't retry together
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        if ceiling.is_zero() {
+            return ceiling;
+        }
+        rand::thread_rng().gen_range(ceiling / 2..=ceiling)
+    }
+
+    /// Call `connect` until it succeeds, fails for good or runs out of attempts,
+    /// describing failures with `message`
+    async fn run<T, E, F, Fut>(
+        &self,
+        mut connect: F,
+        is_transient: impl Fn(&E) -> bool,
+        message: impl Fn(&E) -> String,
+    ) -> Result<T, String>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match connect().await {
+                Ok(connected) => return Ok(connected),
+                Err(e) if attempt < self.attempts && is_transient(&e) => {
+                    let delay = self.delay(attempt);
+                    eprintln!(
+                        "Connection attempt {attempt} failed ({}); retrying in {}ms...",
+                        message(&e),
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(message(&e)),
+            }
+        }
+    }
 }
 
 /// Get the target directory and package name for the current project
@@ -176,20 +520,20 @@ pub fn get_project_info() -> Result<(PathBuf, String, PathBuf), io::Error> {
         .unwrap_or_else(|e| {
             spinner.finish_and_clear();
             eprintln!("Failed to run cargo metadata: {e}");
-            exit(1);
+            errors::exit(&errors::PROJECT_INVALID);
         });
 
     if !output.status.success() {
         spinner.finish_and_clear();
         eprintln!("Failed to retrieve cargo metadata");
-        exit(1);
+        errors::exit(&errors::PROJECT_INVALID);
     }
 
     // Parse JSON
     let metadata: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_else(|e| {
         spinner.finish_and_clear();
         eprintln!("Failed to parse cargo metadata: {e}");
-        exit(1);
+        errors::exit(&errors::PROJECT_INVALID);
     });
 
     // Extract target_directory
@@ -200,7 +544,7 @@ pub fn get_project_info() -> Result<(PathBuf, String, PathBuf), io::Error> {
         .unwrap_or_else(|| {
             spinner.finish_and_clear();
             eprintln!("No 'target_directory' found in cargo metadata");
-            exit(1);
+            errors::exit(&errors::PROJECT_INVALID);
         });
 
     // Get the package name from the current directory's Cargo.toml
@@ -210,14 +554,14 @@ pub fn get_project_info() -> Result<(PathBuf, String, PathBuf), io::Error> {
         .unwrap_or_else(|| {
             spinner.finish_and_clear();
             eprintln!("No 'packages' found in cargo metadata");
-            exit(1);
+            errors::exit(&errors::PROJECT_INVALID);
         });
 
     // Find the package for the current directory
     let current_dir = std::env::current_dir().unwrap_or_else(|e| {
         spinner.finish_and_clear();
         eprintln!("Failed to get current directory: {e}");
-        exit(1);
+        errors::exit(&errors::PROJECT_INVALID);
     });
 
     let package_name = packages
@@ -235,15 +579,47 @@ pub fn get_project_info() -> Result<(PathBuf, String, PathBuf), io::Error> {
         .unwrap_or_else(|| {
             spinner.finish_and_clear();
             eprintln!("Could not find package for current directory");
-            exit(1);
+            errors::exit(&errors::PROJECT_INVALID);
         });
 
     spinner.finish_and_clear();
     Ok((target_directory, package_name, current_dir))
 }
 
-/// Build the project for wasm32-wasip2 target
-pub fn build_project(package_root: &PathBuf) -> Result<(), io::Error> {
+/// What a build is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildKind {
+    /// Deploying, or running as deployed
+    Release,
+    /// Stepping through under a debugger
+    Debug,
+    /// Profiling, optimized as in release but keeping function names
+    Profile,
+}
+
+impl BuildKind {
+    /// Cargo profile overrides of this kind of build
+    fn cargo_env(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            BuildKind::Release => &[],
+            BuildKind::Debug => &DEBUG_BUILD_ENV,
+            BuildKind::Profile => &PROFILE_BUILD_ENV,
+        }
+    }
+}
+
+/// Build the project for wasm32-wasip2 target. With `optimize`, or when the
+/// project's faasta.toml enables it, a release component at `wasm_path` is also
+/// optimized for size, and with `pre_init` pre-initialized first. Debug and profile
+/// builds keep their symbols and are never size-optimized, since that would strip
+/// them, nor pre-initialized.
+pub fn build_project(
+    package_root: &PathBuf,
+    wasm_path: &StdPath,
+    optimize: bool,
+    pre_init: bool,
+    kind: BuildKind,
+) -> Result<(), io::Error> {
     let spinner = indicatif::ProgressBar::new_spinner();
     spinner.set_message("Building optimized WASI component...");
     spinner.enable_steady_tick(std::time::Duration::from_millis(100));
@@ -253,33 +629,226 @@ pub fn build_project(package_root: &PathBuf) -> Result<(), io::Error> {
         spinner.finish_and_clear();
         eprintln!("Error: src/lib.rs is missing. This file is required for Faasta functions.");
         eprintln!("Hint: Run 'cargo faasta new <n>' to create a new Faasta project.");
-        exit(1);
+        errors::exit(&errors::PROJECT_INVALID);
     }
 
+    let config = project::load(package_root).unwrap_or_else(|e| {
+        spinner.finish_and_clear();
+        eprintln!("{e:#}");
+        errors::exit(&errors::PROJECT_INVALID);
+    });
+    let settings = config.optimize;
+    let optimize = (optimize || settings.enabled) && kind == BuildKind::Release;
+    let pre_init = (pre_init || config.pre_init.enabled) && kind == BuildKind::Release;
+
     // Build with wasm32-wasip2 target
-    let status = std::process::Command::new("cargo")
+    let mut command = std::process::Command::new("cargo");
+    command
         .args(["build", "--release", "--target", "wasm32-wasip2"])
-        .current_dir(package_root)
-        .status()
-        .unwrap_or_else(|e| {
-            spinner.finish_and_clear();
-            eprintln!("Failed to run cargo build: {e}");
-            exit(1);
-        });
+        .current_dir(package_root);
+    if optimize {
+        command.envs(settings.cargo_env());
+    }
+    command.envs(kind.cargo_env().iter().copied());
+    let status = command.status().unwrap_or_else(|e| {
+        spinner.finish_and_clear();
+        eprintln!("Failed to run cargo build: {e}");
+        errors::exit(&errors::BUILD_FAILED);
+    });
 
     if !status.success() {
         spinner.finish_and_clear();
         eprintln!("Build failed");
-        exit(1);
+        errors::exit(&errors::BUILD_FAILED);
+    }
+
+    // Snapshot the initialized state before wasm-opt, so it can shrink the result
+    let pre_initialized = if pre_init {
+        spinner.set_message("Pre-initializing component...");
+        match preinit::run_wizer(&config.pre_init, wasm_path) {
+            Ok(elapsed) => Some(elapsed),
+            Err(e) => {
+                spinner.finish_and_clear();
+                eprintln!("{e:#}");
+                errors::exit(&errors::BUILD_FAILED);
+            }
+        }
+    } else {
+        None
+    };
+
+    if !optimize {
+        spinner.finish_and_clear();
+        println!("✅ Build successful!");
+        if let Some(elapsed) = pre_initialized {
+            println!("⚡ Pre-initialized in {:.1}s", elapsed.as_secs_f64());
+        }
+        return Ok(());
     }
 
+    spinner.set_message("Optimizing component size...");
+    let report = optimize::run_wasm_opt(&settings, wasm_path).map_err(io::Error::other)?;
     spinner.finish_and_clear();
     println!("✅ Build successful!");
+    if let Some(elapsed) = pre_initialized {
+        println!("⚡ Pre-initialized in {:.1}s", elapsed.as_secs_f64());
+    }
+    println!("📦 Component size: {report}");
+    Ok(())
+}
+
+/// Profile overrides of a build meant for stepping through: no optimization, so
+/// every line and variable is there, and full DWARF debug info
+const DEBUG_BUILD_ENV: [(&str, &str); 3] = [
+    ("CARGO_PROFILE_RELEASE_OPT_LEVEL", "0"),
+    ("CARGO_PROFILE_RELEASE_DEBUG", "true"),
+    ("CARGO_PROFILE_RELEASE_STRIP", "none"),
+];
+
+/// Profile overrides of a build meant for profiling: optimized as deployed, but
+/// with the name section kept so the flamegraph shows function names
+const PROFILE_BUILD_ENV: [(&str, &str); 1] = [("CARGO_PROFILE_RELEASE_STRIP", "none")];
+
+/// Directory of a project's target dir that `run --profile` writes profiles to
+pub const PROFILES_DIR: &str = "faasta-profiles";
+
+/// Debug server a `run --debug` runs wasmtime under
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Debugger {
+    /// `lldb-server`, for lldb and editors driving it (CodeLLDB)
+    #[default]
+    Lldb,
+    /// `gdbserver`, for gdb
+    Gdb,
+}
+
+impl Debugger {
+    /// How to attach to the debug server listening on `port`
+    fn attach_hint(self, port: u16) -> String {
+        match self {
+            Debugger::Lldb => format!(
+                "lldb -o 'settings set plugin.jit-loader.gdb.enable on' -o 'gdb-remote 127.0.0.1:{port}'"
+            ),
+            Debugger::Gdb => format!("gdb -ex 'target remote 127.0.0.1:{port}'"),
+        }
+    }
+}
+
+/// Command serving the component at `wasm_path` on `port`. With `debug`, wasmtime
+/// compiles it with its DWARF translated for native debuggers and starts stopped
+/// under the debug server listening on the given port, until a debugger attaches.
+fn serve_command(
+    wasm_path: &StdPath,
+    port: u16,
+    debug: Option<(Debugger, u16)>,
+    profile: Option<&StdPath>,
+) -> std::process::Command {
+    let mut serve = vec!["serve".to_string()];
+    if debug.is_some() {
+        serve.extend(["-D", "debug-info=y", "-O", "opt-level=0"].map(String::from));
+    }
+    if let Some(profile) = profile {
+        serve.push(format!("--profile=guest,{}", profile.display()));
+    }
+    serve.extend([
+        "--addr".to_string(),
+        format!("0.0.0.0:{port}"),
+        wasm_path.to_string_lossy().into_owned(),
+    ]);
+
+    let mut command = match debug {
+        None => std::process::Command::new("wasmtime"),
+        Some((Debugger::Lldb, debug_port)) => {
+            let mut command = std::process::Command::new("lldb-server");
+            command.args(["gdbserver", &format!("127.0.0.1:{debug_port}"), "--"]);
+            command.arg("wasmtime");
+            command
+        }
+        Some((Debugger::Gdb, debug_port)) => {
+            let mut command = std::process::Command::new("gdbserver");
+            command.args([format!("127.0.0.1:{debug_port}"), "wasmtime".to_string()]);
+            command
+        }
+    };
+    command.args(serve);
+    command
+}
+
+/// File of a downloaded debug snapshot holding its details and request
+pub const SNAPSHOT_FILE: &str = "snapshot.json";
+/// File of a downloaded debug snapshot holding the guest's core dump
+pub const CORE_DUMP_FILE: &str = "memory.coredump";
+/// Headers of a snapshot's request that aren't replayed as captured
+const UNREPLAYED_HEADERS: &[&str] = &["host", "content-length", "transfer-encoding"];
+
+/// Read the snapshot downloaded to `path`, a directory or its snapshot file
+fn load_snapshot(path: &StdPath) -> io::Result<faasta_interface::DebugSnapshot> {
+    let file = if path.is_dir() {
+        path.join(SNAPSHOT_FILE)
+    } else {
+        path.to_path_buf()
+    };
+    let contents = std::fs::read(&file)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", file.display())))?;
+    serde_json::from_slice(&contents).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a debug snapshot: {e}", file.display()),
+        )
+    })
+}
+
+/// Send a snapshot's request to the function once it's serving on `port`, waiting
+/// up to `wait` for it to start
+async fn replay_request(
+    port: u16,
+    request: &faasta_interface::CapturedRequest,
+    wait: Duration,
+) -> Result<()> {
+    let address = format!("127.0.0.1:{port}");
+    let started = std::time::Instant::now();
+    while tokio::net::TcpStream::connect(&address).await.is_err() {
+        if started.elapsed() > wait {
+            return Err(anyhow!(
+                "the local server didn't start listening on {address}"
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    let method = reqwest::Method::from_bytes(request.method.as_bytes())?;
+    let mut replayed = reqwest::Client::new()
+        .request(method, format!("http://{address}{}", request.uri))
+        .body(request.body.clone());
+    for (name, value) in &request.headers {
+        if value == faasta_interface::REDACTED {
+            println!("⚠️  Header '{name}' was redacted and is left out");
+        } else if !UNREPLAYED_HEADERS.contains(&name.as_str()) {
+            replayed = replayed.header(name, value);
+        }
+    }
+    if request.body_truncated {
+        println!(
+            "⚠️  Only the first {} bytes of the body were captured",
+            request.body.len()
+        );
+    }
+
+    println!("Replaying {} {}...", request.method, request.uri);
+    let response = replayed.send().await?;
+    println!("Function answered {}", response.status());
     Ok(())
 }
 
 // The function to handle the run command
-pub async fn handle_run(port: u16) -> io::Result<()> {
+pub async fn handle_run(
+    port: u16,
+    replay: Option<&StdPath>,
+    debug: Option<(Debugger, u16)>,
+    profile: bool,
+) -> io::Result<()> {
+    let snapshot = replay.map(load_snapshot).transpose()?;
+
     // Get project information
     let (target_directory, package_name, package_root) = get_project_info()?;
 
@@ -287,9 +856,6 @@ pub async fn handle_run(port: u16) -> io::Result<()> {
     println!("Building project: {package_name}");
     println!("Project root: {}", package_root.display());
 
-    // Build the project first
-    build_project(&package_root)?;
-
     // Get the full WASM file path - use same logic as in deploy
     let rust_compiled_name = package_name.replace('-', "_");
     let wasm_filename = format!("{rust_compiled_name}.wasm");
@@ -298,6 +864,14 @@ pub async fn handle_run(port: u16) -> io::Result<()> {
         .join("release")
         .join(wasm_filename);
 
+    // Build the project first
+    let kind = match (debug, profile) {
+        (Some(_), _) => BuildKind::Debug,
+        (None, true) => BuildKind::Profile,
+        (None, false) => BuildKind::Release,
+    };
+    build_project(&package_root, &wasm_path, false, false, kind)?;
+
     // Ensure the WASM file exists
     if !wasm_path.exists() {
         eprintln!(
@@ -305,23 +879,177 @@ pub async fn handle_run(port: u16) -> io::Result<()> {
             wasm_path.display()
         );
         eprintln!("Build seems to have failed or produced output in a different location.");
-        exit(1);
+        errors::exit(&errors::ARTIFACT_MISSING);
+    }
+
+    // wasmtime writes the guest profile when it stops
+    let profile_path = profile.then(|| {
+        target_directory
+            .join(PROFILES_DIR)
+            .join(format!("{rust_compiled_name}.json"))
+    });
+    if let Some(path) = &profile_path {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
     }
 
     println!("Starting local server on port {port}...");
-    let status = std::process::Command::new("wasmtime")
-        .args(["serve", &wasm_path.to_string_lossy()])
+    let mut server = serve_command(&wasm_path, port, debug, profile_path.as_deref())
         .current_dir(&package_root)
-        .status()
+        .spawn()
         .unwrap_or_else(|e| {
-            eprintln!("Failed to run wasmtime serve: {e}");
-            exit(1);
+            let program = match debug {
+                None => "wasmtime serve",
+                Some((Debugger::Lldb, _)) => "lldb-server",
+                Some((Debugger::Gdb, _)) => "gdbserver",
+            };
+            eprintln!("Failed to run {program}: {e}");
+            errors::exit(&errors::UNCLASSIFIED);
         });
 
-    if !status.success() {
+    // Until a debugger attaches and continues, wasmtime doesn't start serving
+    let mut replay_wait = Duration::from_secs(60);
+    if let Some((debugger, debug_port)) = debug {
+        println!("🐞 Waiting for a debugger on 127.0.0.1:{debug_port}. Attach with:");
+        println!("   {}", debugger.attach_hint(debug_port));
+        println!("   then set breakpoints in your function's code and continue");
+        replay_wait = Duration::from_secs(3600);
+    }
+
+    if let Some(snapshot) = snapshot {
+        println!(
+            "Snapshot {} of request {} trapped with:\n{}",
+            snapshot.info.id, snapshot.info.request_id, snapshot.info.error
+        );
+        if let Err(e) = replay_request(port, &snapshot.request, replay_wait).await {
+            eprintln!("Failed to replay the request: {e}");
+        }
+        println!("The function keeps running for debugging; press Ctrl-C to stop it");
+    }
+
+    if let Some(path) = &profile_path {
+        println!(
+            "🔥 Profiling the function; send it requests, then press Ctrl-C to stop it and write the profile to {}",
+            path.display()
+        );
+        println!("   Open the profile at https://profiler.firefox.com to see the flamegraph");
+    }
+
+    let status = server.wait()?;
+
+    if !status.success() && debug.is_none() {
         eprintln!("wasmtime serve exited with an error");
-        exit(1);
+        errors::exit(&errors::UNCLASSIFIED);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_versions_must_match() {
+        assert!(check_protocol_version(PROTOCOL_VERSION, "0.4.0").is_ok());
+        let newer = check_protocol_version(PROTOCOL_VERSION + 1, "9.0.0").unwrap_err();
+        assert!(newer.to_string().contains("upgrade it"));
+        let older = check_protocol_version(PROTOCOL_VERSION - 1, "0.1.0").unwrap_err();
+        assert!(older.to_string().contains("Install cargo-faasta 0.1.0"));
+    }
+
+    #[test]
+    fn test_connect_retry_delay() {
+        let retry = ConnectRetry::default();
+        for attempt in 1..10 {
+            let delay = retry.delay(attempt);
+            assert!(delay <= retry.max_delay);
+        }
+        let first = retry.delay(1);
+        assert!(first >= retry.base_delay / 2 && first <= retry.base_delay);
+    }
+
+    #[test]
+    fn test_server_host() {
+        assert_eq!(server_host("faasta.xyz:4433"), "faasta.xyz");
+        assert_eq!(server_host("[2001:db8::1]:4433"), "2001:db8::1");
+        assert_eq!(server_port("[2001:db8::1]:4433").unwrap(), 4433);
+    }
+
+    #[test]
+    fn test_debug_runs_wasmtime_under_the_debug_server() {
+        let args = |command: &std::process::Command| {
+            std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        let wasm = StdPath::new("hello.wasm");
+        assert_eq!(
+            args(&serve_command(wasm, 3000, None, None)),
+            ["wasmtime", "serve", "--addr", "0.0.0.0:3000", "hello.wasm"]
+        );
+        assert_eq!(
+            args(&serve_command(
+                wasm,
+                3000,
+                None,
+                Some(StdPath::new("hello.json"))
+            )),
+            [
+                "wasmtime",
+                "serve",
+                "--profile=guest,hello.json",
+                "--addr",
+                "0.0.0.0:3000",
+                "hello.wasm"
+            ]
+        );
+        assert_eq!(
+            args(&serve_command(
+                wasm,
+                3000,
+                Some((Debugger::Gdb, 1234)),
+                None
+            )),
+            [
+                "gdbserver",
+                "127.0.0.1:1234",
+                "wasmtime",
+                "serve",
+                "-D",
+                "debug-info=y",
+                "-O",
+                "opt-level=0",
+                "--addr",
+                "0.0.0.0:3000",
+                "hello.wasm"
+            ]
+        );
+        let lldb = args(&serve_command(
+            wasm,
+            3000,
+            Some((Debugger::Lldb, 1234)),
+            None,
+        ));
+        assert_eq!(
+            lldb[..5],
+            [
+                "lldb-server",
+                "gdbserver",
+                "127.0.0.1:1234",
+                "--",
+                "wasmtime"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tls_alerts_are_crypto_errors() {
+        // certificate_unknown (46) as QUIC carries it
+        assert!(is_crypto_error(0x100 + 46));
+        // CONNECTION_REFUSED
+        assert!(!is_crypto_error(0x2));
+    }
+}
@@ -0,0 +1,382 @@
+//! How the CLI checks the server's certificate on RPC connections.
+//!
+//! Servers with a publicly trusted certificate need no settings. A self-hosted
+//! server with a private CA is trusted with `--ca-cert`, and `--pin-sha256`
+//! accepts only the certificate with the given fingerprint, with or without a CA.
+//! The same checks apply to QUIC and to TLS over TCP. Servers that authenticate
+//! clients by certificate get the one given with `--client-cert` and `--client-key`.
+
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    self, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+/// ALPN the server's QUIC endpoint expects
+const QUIC_ALPN: &[u8] = b"h3";
+
+/// Certificate checks beyond the defaults, from the command line or the config file
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    /// PEM bundle of the CAs trusted instead of the public web PKI
+    pub ca_cert: Option<PathBuf>,
+    /// SHA-256 fingerprint of the only server certificate accepted
+    pub pin_sha256: Option<[u8; 32]>,
+    /// PEM certificate chain and private key presented to servers that ask for one
+    pub client_identity: Option<(PathBuf, PathBuf)>,
+}
+
+impl TlsOptions {
+    /// Whether the options change the default TLS setup
+    pub fn is_custom(&self) -> bool {
+        self.ca_cert.is_some() || self.pin_sha256.is_some() || self.client_identity.is_some()
+    }
+}
+
+/// Parse a certificate fingerprint as `openssl x509 -fingerprint -sha256` prints
+/// it, with or without colons and an optional `sha256:` prefix
+pub fn parse_pin(pin: &str) -> Result<[u8; 32], String> {
+    let hex_digits: String = pin
+        .trim()
+        .trim_start_matches("sha256:")
+        .chars()
+        .filter(|c| *c != ':')
+        .collect();
+    let bytes = hex::decode(&hex_digits)
+        .map_err(|_| format!("'{pin}' is not a hex SHA-256 fingerprint"))?;
+    bytes
+        .try_into()
+        .map_err(|_| format!("'{pin}' is not 32 bytes long, as a SHA-256 fingerprint is"))
+}
+
+/// Client configuration for an RPC connection. With `embedded_cert` and no
+/// custom options, the server must present the development certificate built
+/// into the CLI, as for localhost; otherwise the certificate is checked against
+/// the public web PKI. QUIC needs TLS 1.3 and the server's ALPN.
+pub fn client_config(
+    options: &TlsOptions,
+    embedded_cert: bool,
+    quic: bool,
+) -> io::Result<ClientConfig> {
+    let mut provider = crypto::ring::default_provider();
+    if quic {
+        provider
+            .cipher_suites
+            .retain(|suite| suite.version() == &rustls::version::TLS13);
+    }
+    let provider = Arc::new(provider);
+    let builder = ClientConfig::builder_with_provider(provider.clone());
+    let builder = if quic {
+        builder.with_protocol_versions(&[&rustls::version::TLS13])
+    } else {
+        builder.with_safe_default_protocol_versions()
+    }
+    .map_err(io::Error::other)?;
+
+    let roots = match &options.ca_cert {
+        Some(path) => Some(load_ca_bundle(path)?),
+        None => None,
+    };
+    let builder = match (roots, options.pin_sha256) {
+        (roots, Some(pin)) => {
+            let chain = match roots {
+                Some(roots) => Some(
+                    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                        .build()
+                        .map_err(io::Error::other)?,
+                ),
+                None => None,
+            };
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                    pin,
+                    chain,
+                    provider,
+                }))
+        }
+        (Some(roots), None) => builder.with_root_certificates(roots),
+        (None, None) if embedded_cert => {
+            // Self signed, matches the one in server-wasi, Not for Production use!
+            let cert = CertificateDer::from_pem_slice(include_bytes!("../certs/cert.pem"))
+                .map_err(io::Error::other)?;
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(EmbeddedCertVerifier { cert, provider }))
+        }
+        (None, None) => {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            builder.with_root_certificates(roots)
+        }
+    };
+    let mut config = match &options.client_identity {
+        Some((cert_path, key_path)) => {
+            let (chain, key) = load_identity(cert_path, key_path)?;
+            builder
+                .with_client_auth_cert(chain, key)
+                .map_err(|e| invalid_input(format!("Unusable client certificate: {e}")))?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    if quic {
+        config.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+    }
+    Ok(config)
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Read the client certificate chain and its private key
+fn load_identity(
+    cert_path: &PathBuf,
+    key_path: &PathBuf,
+) -> io::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let chain = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| {
+            invalid_input(format!(
+                "Invalid client certificate {}: {e}",
+                cert_path.display()
+            ))
+        })?;
+    if chain.is_empty() {
+        return Err(invalid_input(format!(
+            "No certificate found in {}",
+            cert_path.display()
+        )));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| invalid_input(format!("Invalid client key {}: {e}", key_path.display())))?;
+    Ok((chain, key))
+}
+
+fn load_ca_bundle(path: &PathBuf) -> io::Result<RootCertStore> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid CA bundle {}: {e}", path.display()),
+        )
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(path).map_err(|e| invalid(&e))? {
+        roots
+            .add(cert.map_err(|e| invalid(&e))?)
+            .map_err(|e| invalid(&e))?;
+    }
+    if roots.is_empty() {
+        return Err(invalid(&"no certificates found"));
+    }
+    Ok(roots)
+}
+
+/// Accepts exactly the embedded development certificate, whatever its names
+/// and validity dates
+#[derive(Debug)]
+struct EmbeddedCertVerifier {
+    cert: CertificateDer<'static>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for EmbeddedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.cert.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::UnknownIssuer,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12(&self.provider, message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13(&self.provider, message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Accepts only the certificate with the pinned fingerprint. With a CA bundle
+/// the certificate must also chain to it and match the server name; without one
+/// the pin alone identifies the server, which suits self-signed certificates.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    pin: [u8; 32],
+    chain: Option<Arc<WebPkiServerVerifier>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(chain) = &self.chain {
+            chain.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        }
+        let fingerprint: [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+        if fingerprint == self.pin {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "server certificate fingerprint {} doesn't match the pinned one",
+                hex::encode(fingerprint)
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12(&self.provider, message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13(&self.provider, message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn verify_tls12(
+    provider: &CryptoProvider,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+) -> Result<HandshakeSignatureValid, rustls::Error> {
+    crypto::verify_tls12_signature(
+        message,
+        cert,
+        dss,
+        &provider.signature_verification_algorithms,
+    )
+}
+
+fn verify_tls13(
+    provider: &CryptoProvider,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+) -> Result<HandshakeSignatureValid, rustls::Error> {
+    crypto::verify_tls13_signature(
+        message,
+        cert,
+        dss,
+        &provider.signature_verification_algorithms,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pin() {
+        let colons = "AB:".repeat(31) + "AB";
+        assert_eq!(parse_pin(&colons), Ok([0xab; 32]));
+        assert_eq!(
+            parse_pin(&format!("sha256:{}", "01".repeat(32))),
+            Ok([1; 32])
+        );
+        assert!(parse_pin("abcd").is_err());
+        assert!(parse_pin(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_client_configs_build() {
+        let pinned = TlsOptions {
+            pin_sha256: Some([0; 32]),
+            ..Default::default()
+        };
+        for options in [TlsOptions::default(), pinned] {
+            for quic in [false, true] {
+                assert!(client_config(&options, true, quic).is_ok());
+                assert!(client_config(&options, false, quic).is_ok());
+            }
+        }
+        let config = client_config(&TlsOptions::default(), false, true).unwrap();
+        assert_eq!(config.alpn_protocols, vec![QUIC_ALPN.to_vec()]);
+    }
+
+    #[test]
+    fn test_ca_bundle_must_hold_certificates() {
+        let path = std::env::temp_dir().join(format!("faasta-empty-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, "not a certificate").unwrap();
+        let options = TlsOptions {
+            ca_cert: Some(path.clone()),
+            ..Default::default()
+        };
+        assert!(client_config(&options, false, false).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        let options = TlsOptions {
+            ca_cert: Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("certs/cert.pem")),
+            ..Default::default()
+        };
+        assert!(client_config(&options, false, false).is_ok());
+    }
+
+    #[test]
+    fn test_client_identity_must_load() {
+        let cert = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("certs/cert.pem");
+        let options = TlsOptions {
+            client_identity: Some((cert.clone(), cert)),
+            ..Default::default()
+        };
+        let error = client_config(&options, false, false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,137 @@
+//! The TLS-over-TCP transport for the management RPCs, for networks that drop
+//! the UDP packets QUIC needs. It carries the same length-delimited Bincode
+//! frames as a QUIC stream, to the same port.
+
+use clap::ValueEnum;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+
+use crate::proxy::Proxy;
+use crate::tls::{self, TlsOptions};
+
+/// Time allowed for the TCP connection and the TLS handshake together
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How the CLI reaches the server's RPC service
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    /// QUIC, falling back to TLS over TCP when QUIC can't connect
+    #[default]
+    Auto,
+    /// QUIC only
+    Quic,
+    /// TLS over TCP only
+    Tcp,
+}
+
+/// Settings shared by every connection this process makes
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+    pub transport: Transport,
+    pub tls: TlsOptions,
+    /// Proxy given with `--proxy` or in the config file, used over the
+    /// environment's
+    pub proxy: Option<Proxy>,
+}
+
+static OPTIONS: OnceLock<ConnectOptions> = OnceLock::new();
+
+pub fn configure(options: ConnectOptions) {
+    let _ = OPTIONS.set(options);
+}
+
+pub fn options() -> ConnectOptions {
+    OPTIONS.get().cloned().unwrap_or_default()
+}
+
+/// Connect to `addr` with TLS over TCP, checking the certificate as
+/// [`tls::client_config`] describes
+pub async fn connect_tcp(
+    addr: SocketAddr,
+    server_name: &str,
+    embedded_cert: bool,
+) -> io::Result<TlsStream<TcpStream>> {
+    let dial = async {
+        let tcp_stream = TcpStream::connect(addr).await?;
+        tcp_stream.set_nodelay(true)?;
+        Ok(tcp_stream)
+    };
+    handshake(dial, server_name, embedded_cert).await
+}
+
+/// Like [`connect_tcp`], through a tunnel `proxy` opens to `server_name:port`
+pub async fn connect_tcp_via(
+    proxy: &Proxy,
+    server_name: &str,
+    port: u16,
+    embedded_cert: bool,
+) -> io::Result<TlsStream<TcpStream>> {
+    handshake(proxy.connect(server_name, port), server_name, embedded_cert).await
+}
+
+/// Run the TLS handshake over the stream `dial` opens
+async fn handshake(
+    dial: impl Future<Output = io::Result<TcpStream>>,
+    server_name: &str,
+    embedded_cert: bool,
+) -> io::Result<TlsStream<TcpStream>> {
+    let config = tls::client_config(&options().tls, embedded_cert, false)?;
+    let connector = TlsConnector::from(Arc::new(config));
+    let server_name = ServerName::try_from(server_name.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let connect = async { connector.connect(server_name, dial.await?).await };
+    tokio::time::timeout(CONNECT_TIMEOUT, connect)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connection timed out"))?
+}
+
+/// Whether a TCP connection failure may go away on its own. TLS failures, which
+/// rustls reports as invalid data, aren't retried.
+pub fn is_transient_io(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::Interrupted
+    )
+}
+
+pub fn tcp_error_message(error: &io::Error) -> String {
+    match error.kind() {
+        io::ErrorKind::TimedOut => {
+            "Connection timeout. Check your network connection or firewall settings.".to_string()
+        }
+        io::ErrorKind::InvalidData => {
+            format!("TLS handshake error ({error}). The server's certificate may not be trusted.")
+        }
+        _ => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_errors_are_not_transient() {
+        let tls_error = io::Error::new(io::ErrorKind::InvalidData, "invalid peer certificate");
+        assert!(!is_transient_io(&tls_error));
+        assert!(is_transient_io(&io::Error::from(
+            io::ErrorKind::ConnectionRefused
+        )));
+    }
+}
@@ -0,0 +1,40 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Why a call to a function failed
+#[derive(Debug, Error)]
+pub enum InvokeError {
+    /// The function (or the server in front of it) answered with an error status
+    #[error("Function returned {status} (request {request_id}): {body}")]
+    Status {
+        status: StatusCode,
+        /// Start of the response body, for context
+        body: String,
+        request_id: String,
+    },
+
+    /// No response arrived before the call's deadline
+    #[error("Function call timed out (request {request_id})")]
+    Timeout { request_id: String },
+
+    /// The connection failed or broke off
+    #[error("Transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// A request or response body wasn't the expected JSON
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+}
+
+impl InvokeError {
+    /// The response status, for errors that have one
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            InvokeError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
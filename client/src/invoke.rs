@@ -0,0 +1,277 @@
+use bytes::Bytes;
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::retry::{is_retryable_error, is_retryable_status};
+use crate::{Client, InvokeError, REQUEST_ID_HEADER, TIMEOUT_HEADER};
+
+/// Most bytes of an error response kept in [`InvokeError::Status`]
+const MAX_ERROR_BODY: usize = 1024;
+
+/// A deployed function, from [`Client::function`]
+#[derive(Clone, Debug)]
+pub struct Function {
+    client: Client,
+    url: Url,
+}
+
+impl Function {
+    pub(crate) fn new(client: Client, name: &str) -> Self {
+        let url = client.function_url(name);
+        Self { client, url }
+    }
+
+    /// URL the function is called at
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Start a call of `path` (relative to the function's root) with `method`
+    pub fn request(&self, method: Method, path: &str) -> InvokeRequest {
+        InvokeRequest {
+            function: self.clone(),
+            method,
+            path: path.to_string(),
+            headers: HeaderMap::new(),
+            body: None,
+            timeout: None,
+            deadline: None,
+            request_id: None,
+            invalid: None,
+        }
+    }
+
+    pub fn get(&self, path: &str) -> InvokeRequest {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> InvokeRequest {
+        self.request(Method::POST, path)
+    }
+
+    /// `GET` `path` and decode the JSON response
+    pub async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, InvokeError> {
+        self.get(path).send().await?.json()
+    }
+
+    /// `POST` `body` as JSON to `path` and decode the JSON response
+    pub async fn post_json<B: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, InvokeError> {
+        self.post(path).json(body).send().await?.json()
+    }
+}
+
+/// A call being configured, sent with [`InvokeRequest::send`]
+#[derive(Debug)]
+pub struct InvokeRequest {
+    function: Function,
+    method: Method,
+    path: String,
+    headers: HeaderMap,
+    body: Option<Bytes>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    request_id: Option<String>,
+    /// First invalid setting, reported by `send`
+    invalid: Option<InvokeError>,
+}
+
+impl InvokeRequest {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        match (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            (Ok(name), Ok(value)) => {
+                self.headers.insert(name, value);
+            }
+            _ => {
+                self.invalid
+                    .get_or_insert(InvokeError::InvalidRequest(format!(
+                        "Invalid header '{name}'"
+                    )));
+            }
+        }
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Send `body` as JSON
+    pub fn json<B: Serialize + ?Sized>(mut self, body: &B) -> Self {
+        match serde_json::to_vec(body) {
+            Ok(body) => {
+                self.headers
+                    .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                self.body = Some(body.into());
+            }
+            Err(e) => {
+                self.invalid.get_or_insert(e.into());
+            }
+        }
+        self
+    }
+
+    /// Time the call may take, including retries, instead of the client's
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Give up at `deadline`, such as the deadline of the request being handled
+    /// that led to this call. The earlier of the deadline and the timeout applies.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Id the function sees as `X-Client-Request-Id`; a random one is sent otherwise
+    pub fn request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    /// Send the call, retrying transient failures. Statuses other than 2xx are
+    /// returned as [`InvokeError::Status`].
+    pub async fn send(self) -> Result<InvokeResponse, InvokeError> {
+        if let Some(invalid) = self.invalid {
+            return Err(invalid);
+        }
+        let inner = &self.function.client.inner;
+        let timeout_deadline = Instant::now() + self.timeout.unwrap_or(inner.timeout);
+        let deadline = self
+            .deadline
+            .map_or(timeout_deadline, |deadline| deadline.min(timeout_deadline));
+        let request_id = self
+            .request_id
+            .unwrap_or_else(|| format!("{:016x}", rand::thread_rng().gen::<u64>()));
+        let url = self
+            .function
+            .url
+            .join(self.path.trim_start_matches('/'))
+            .map_err(|e| InvokeError::InvalidRequest(format!("Invalid path: {e}")))?;
+        let timed_out = || InvokeError::Timeout {
+            request_id: request_id.clone(),
+        };
+
+        let mut retry = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(timed_out());
+            }
+            let mut request = inner
+                .http
+                .request(self.method.clone(), url.clone())
+                .headers(self.headers.clone())
+                .header(REQUEST_ID_HEADER, &request_id)
+                .header(TIMEOUT_HEADER, remaining.as_millis().max(1).to_string())
+                .timeout(remaining);
+            if let Some(body) = &self.body {
+                request = request.body(body.clone());
+            }
+
+            let wait = match request.send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    if retry < inner.retry.max_retries && is_retryable_status(&self.method, status)
+                    {
+                        retry_after(response.headers()).unwrap_or(inner.retry.delay(retry))
+                    } else {
+                        let headers = response.headers().clone();
+                        let body = response.bytes().await.map_err(|e| {
+                            if e.is_timeout() {
+                                timed_out()
+                            } else {
+                                InvokeError::Transport(e)
+                            }
+                        })?;
+                        return into_response(status, headers, body, request_id);
+                    }
+                }
+                Err(e) if e.is_timeout() => return Err(timed_out()),
+                Err(e) => {
+                    if retry < inner.retry.max_retries && is_retryable_error(&self.method, &e) {
+                        inner.retry.delay(retry)
+                    } else {
+                        return Err(InvokeError::Transport(e));
+                    }
+                }
+            };
+
+            // A retry that can't finish before the deadline isn't worth starting
+            if Instant::now() + wait >= deadline {
+                return Err(timed_out());
+            }
+            tokio::time::sleep(wait).await;
+            retry += 1;
+        }
+    }
+}
+
+fn into_response(
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    request_id: String,
+) -> Result<InvokeResponse, InvokeError> {
+    // The server logs the call under an id of its own and returns it
+    let request_id = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map_or(request_id, String::from);
+    if !status.is_success() {
+        let end = body.len().min(MAX_ERROR_BODY);
+        return Err(InvokeError::Status {
+            status,
+            body: String::from_utf8_lossy(&body[..end]).into_owned(),
+            request_id,
+        });
+    }
+    Ok(InvokeResponse {
+        status,
+        headers,
+        body,
+        request_id,
+    })
+}
+
+/// Seconds to wait from a `Retry-After` header (HTTP dates are ignored)
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let secs = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// A function's successful response
+#[derive(Clone, Debug)]
+pub struct InvokeResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// Id of the call in the function's logs
+    pub request_id: String,
+}
+
+impl InvokeResponse {
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, InvokeError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
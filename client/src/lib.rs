@@ -0,0 +1,200 @@
+//! Client for invoking functions deployed on Faasta from other Rust services.
+//!
+//! This crate is for calling functions over HTTP; managing them (publishing,
+//! metrics, logs) is what `faasta-interface` and `cargo faasta` are for.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), faasta_client::InvokeError> {
+//! use std::time::Duration;
+//!
+//! let client = faasta_client::Client::builder("faasta.xyz")
+//!     .timeout(Duration::from_secs(5))
+//!     .build()?;
+//! let greeting: String = client.function("hello").get_json("/greet?name=world").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A [`Client`] is cheap to clone and keeps a pool of connections per function
+//! host, so one client should be shared by the whole service. Transient failures
+//! are retried with backoff (see [`RetryPolicy`]), and every call carries its
+//! remaining time to the server, which stops waiting for the function once the
+//! caller would have given up anyway.
+
+mod error;
+mod invoke;
+mod retry;
+
+pub use error::InvokeError;
+pub use invoke::{Function, InvokeRequest, InvokeResponse};
+pub use retry::RetryPolicy;
+
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Header carrying the milliseconds a caller still waits for a response
+pub const TIMEOUT_HEADER: &str = "x-faasta-timeout-ms";
+/// Header carrying a call's own id out and the id the server logged it under back
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Time a call may take unless the builder or the call sets another
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time allowed to establish a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Idle connections kept open per function host
+const MAX_IDLE_PER_HOST: usize = 32;
+
+/// Handle to a Faasta server's functions
+#[derive(Clone, Debug)]
+pub struct Client {
+    inner: Arc<ClientInner>,
+}
+
+#[derive(Debug)]
+struct ClientInner {
+    http: reqwest::Client,
+    base: Url,
+    timeout: Duration,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    /// A client for functions on `server`, with default settings
+    pub fn new(server: &str) -> Result<Self, InvokeError> {
+        Self::builder(server).build()
+    }
+
+    /// Start configuring a client for `server`: a domain such as `faasta.xyz`,
+    /// whose functions are served from subdomains, or a base URL such as
+    /// `http://localhost:8080`, whose functions are served from paths
+    pub fn builder(server: &str) -> ClientBuilder {
+        ClientBuilder {
+            server: server.to_string(),
+            timeout: DEFAULT_TIMEOUT,
+            retry: RetryPolicy::default(),
+            user_agent: None,
+        }
+    }
+
+    /// A handle for calling the function `name`
+    pub fn function(&self, name: &str) -> Function {
+        Function::new(self.clone(), name)
+    }
+
+    /// URL of the function `name`, ending in a slash
+    pub fn function_url(&self, name: &str) -> Url {
+        function_url(&self.inner.base, name)
+    }
+}
+
+/// Settings of a [`Client`]
+#[derive(Debug)]
+pub struct ClientBuilder {
+    server: String,
+    timeout: Duration,
+    retry: RetryPolicy,
+    user_agent: Option<String>,
+}
+
+impl ClientBuilder {
+    /// Time a call may take, including retries, unless the call sets its own
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How failed calls are retried
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// `User-Agent` sent with every call
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Client, InvokeError> {
+        let base = parse_server(&self.server)?;
+        let http = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .pool_max_idle_per_host(MAX_IDLE_PER_HOST)
+            .tcp_keepalive(Duration::from_secs(60))
+            .user_agent(
+                self.user_agent
+                    .unwrap_or_else(|| format!("faasta-client/{}", env!("CARGO_PKG_VERSION"))),
+            )
+            .build()
+            .map_err(InvokeError::Transport)?;
+        Ok(Client {
+            inner: Arc::new(ClientInner {
+                http,
+                base,
+                timeout: self.timeout,
+                retry: self.retry,
+            }),
+        })
+    }
+}
+
+fn parse_server(server: &str) -> Result<Url, InvokeError> {
+    let server = if server.contains("://") {
+        server.to_string()
+    } else {
+        format!("https://{server}")
+    };
+    let url = Url::parse(&server)
+        .map_err(|e| InvokeError::InvalidRequest(format!("Invalid server '{server}': {e}")))?;
+    if url.host_str().is_none() {
+        return Err(InvokeError::InvalidRequest(format!(
+            "Server '{server}' has no host"
+        )));
+    }
+    Ok(url)
+}
+
+/// Functions live on subdomains of a domain, and under paths of a local server
+/// or an IP address
+fn function_url(base: &Url, name: &str) -> Url {
+    let mut url = base.clone();
+    match base.host() {
+        Some(url::Host::Domain(domain)) if domain != "localhost" => {
+            // The name was checked to be a valid label when the function was published
+            let _ = url.set_host(Some(&format!("{name}.{domain}")));
+            url.set_path("/");
+        }
+        _ => {
+            let path = format!("{}/{name}/", base.path().trim_end_matches('/'));
+            url.set_path(&path);
+        }
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_function_urls() {
+        let client = Client::new("faasta.xyz").unwrap();
+        assert_eq!(
+            client.function_url("hello").as_str(),
+            "https://hello.faasta.xyz/"
+        );
+
+        let client = Client::new("http://localhost:8080").unwrap();
+        assert_eq!(
+            client.function_url("hello").as_str(),
+            "http://localhost:8080/hello/"
+        );
+
+        let client = Client::new("https://10.0.0.5/functions/").unwrap();
+        assert_eq!(
+            client.function_url("hello").as_str(),
+            "https://10.0.0.5/functions/hello/"
+        );
+    }
+}
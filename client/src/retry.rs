@@ -0,0 +1,111 @@
+use rand::Rng;
+use reqwest::{Method, StatusCode};
+use std::time::Duration;
+
+/// How failed calls are retried.
+///
+/// A call is retried when the connection couldn't be established, and, for
+/// idempotent methods, when it broke off or the server answered 429, 502, 503 or
+/// 504. Retries wait with exponential backoff and full jitter, honour a
+/// `Retry-After` the server sent, and never run past the call's deadline.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt (0 disables retrying)
+    pub max_retries: u32,
+    /// Longest wait before the first retry, doubled for every further retry
+    pub base_delay: Duration,
+    /// Longest wait between two attempts
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Wait before retry number `retry` (starting at 0)
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        if ceiling.is_zero() {
+            return ceiling;
+        }
+        rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+    }
+}
+
+/// Methods that may be sent twice without changing the outcome
+pub(crate) fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    )
+}
+
+/// Whether a call may be retried after the response `status`
+pub(crate) fn is_retryable_status(method: &Method, status: StatusCode) -> bool {
+    is_idempotent(method)
+        && matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT
+        )
+}
+
+/// Whether a call may be retried after the transport `error`. A request that
+/// never reached the server can always be sent again.
+pub(crate) fn is_retryable_error(method: &Method, error: &reqwest::Error) -> bool {
+    error.is_connect() || (is_idempotent(method) && (error.is_request() || error.is_body()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_is_bounded() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+        };
+        for retry in 0..10 {
+            assert!(policy.delay(retry) <= Duration::from_millis(500));
+        }
+        assert!(policy.delay(0) <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_only_idempotent_calls_retry_on_status() {
+        assert!(is_retryable_status(
+            &Method::GET,
+            StatusCode::SERVICE_UNAVAILABLE
+        ));
+        assert!(!is_retryable_status(
+            &Method::POST,
+            StatusCode::SERVICE_UNAVAILABLE
+        ));
+        assert!(!is_retryable_status(
+            &Method::GET,
+            StatusCode::INTERNAL_SERVER_ERROR
+        ));
+    }
+}
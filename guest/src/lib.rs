@@ -0,0 +1,127 @@
+//! Helpers for functions running on Faasta.
+//!
+//! The crate has no dependencies and works with any `wasi:http` framework, such as
+//! `spin-sdk` or `waki`.
+//!
+//! ## Initialization ahead of traffic
+//!
+//! Expensive one-time work, such as loading data files or filling caches, is
+//! better done before an instance's first request than during it. Put its result
+//! in a [`Warmup`] and answer readiness probes, requests for [`READY_PATH`], from
+//! your handler:
+//!
+//! ```
+//! use faasta_guest::{probe_status, Warmup, READY_PATH};
+//! # struct Index;
+//! # fn load_index() -> Index { Index }
+//!
+//! faasta_guest::answers_readiness_probes!();
+//!
+//! static INDEX: Warmup<Index> = Warmup::new(load_index);
+//!
+//! fn handle(path: &str) -> u16 {
+//!     if path == READY_PATH {
+//!         INDEX.get();
+//!         return probe_status(INDEX.is_ready());
+//!     }
+//!     let _index = INDEX.get();
+//!     // ...answer the request from the index
+//!     200
+//! }
+//! ```
+//!
+//! [`answers_readiness_probes!`] declares that the function answers probes, in a
+//! custom section of its component; the server sends none to functions that don't.
+//! It probes the instances it creates ahead of traffic, those of kept-warm
+//! functions, and parks them for requests only once a probe is answered `204 No
+//! Content`. A probe answered `503 Service Unavailable` is repeated, with backoff,
+//! so initialization can also be split over several probes. Instances created for
+//! a request aren't probed: [`Warmup::get`] initializes on first use there. Clients
+//! can't reach [`READY_PATH`]; the server answers it with a 404 itself.
+
+use std::sync::OnceLock;
+
+/// Path of the readiness probes the server sends to new instances
+pub const READY_PATH: &str = "/.faasta/ready";
+/// Custom section declaring that a component answers readiness probes, added by
+/// [`answers_readiness_probes!`]
+pub const READINESS_SECTION: &str = "faasta-readiness";
+
+/// Declare that the function answers readiness probes, so the server sends them.
+/// Use it once, in the function's crate.
+#[macro_export]
+macro_rules! answers_readiness_probes {
+    () => {
+        #[cfg_attr(target_family = "wasm", link_section = "faasta-readiness")]
+        #[used]
+        static FAASTA_READINESS: [u8; 14] = *b"/.faasta/ready";
+    };
+}
+
+/// Status answering a readiness probe: `204` once the instance is `ready`, `503`
+/// while it's still initializing
+pub fn probe_status(ready: bool) -> u16 {
+    if ready {
+        204
+    } else {
+        503
+    }
+}
+
+/// A value initialized once per instance, by a readiness probe or on first use
+pub struct Warmup<T> {
+    value: OnceLock<T>,
+    init: fn() -> T,
+}
+
+impl<T> Warmup<T> {
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            value: OnceLock::new(),
+            init,
+        }
+    }
+
+    /// The value, initializing it if no probe did
+    pub fn get(&self) -> &T {
+        self.value.get_or_init(self.init)
+    }
+
+    /// Whether the value is initialized
+    pub fn is_ready(&self) -> bool {
+        self.value.get().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static INITS: AtomicU32 = AtomicU32::new(0);
+
+    fn load() -> Vec<u32> {
+        INITS.fetch_add(1, Ordering::SeqCst);
+        vec![1, 2, 3]
+    }
+
+    answers_readiness_probes!();
+
+    #[test]
+    fn test_declaration_names_the_probe_path() {
+        assert_eq!(FAASTA_READINESS, READY_PATH.as_bytes());
+    }
+
+    #[test]
+    fn test_value_is_initialized_once() {
+        static VALUE: Warmup<Vec<u32>> = Warmup::new(load);
+        assert!(!VALUE.is_ready());
+        assert_eq!(probe_status(VALUE.is_ready()), 503);
+
+        assert_eq!(VALUE.get(), &[1, 2, 3]);
+        assert_eq!(VALUE.get().len(), 3);
+        assert!(VALUE.is_ready());
+        assert_eq!(probe_status(VALUE.is_ready()), 204);
+        assert_eq!(INITS.load(Ordering::SeqCst), 1);
+    }
+}
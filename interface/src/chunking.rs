@@ -0,0 +1,69 @@
+//! Content-defined chunking of artifacts for delta uploads.
+//!
+//! A chunk ends where a gear hash rolling over the bytes before it matches a mask,
+//! so boundaries depend only on the nearby content: an edit changes the chunks
+//! around it while the rest keep their boundaries and digests, even when the edit
+//! shifts everything after it. The server chunks the deployed artifact and the CLI
+//! the new one the same way, and only chunks the server doesn't have are sent.
+
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+/// Smallest chunk, except for the last one
+pub const MIN_CHUNK_SIZE: usize = 2 * 1024;
+/// Largest chunk; a boundary is forced where the content offers none
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024;
+/// Hash bits that must be zero at a boundary, for chunks of 8 KiB on average
+const BOUNDARY_MASK: u64 = (1 << 13) - 1;
+
+/// Random values mixed into the rolling hash, one per byte value
+const GEAR: [u64; 256] = gear_table();
+
+/// Fill the gear table with splitmix64, so it's the same on every build
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Ranges of the chunks of `data`, in order and covering all of it
+pub fn chunks(data: &[u8]) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = chunk_end(&data[start..]) + start;
+        chunks.push(start..end);
+        start = end;
+    }
+    chunks
+}
+
+/// Length of the chunk at the start of `data`
+fn chunk_end(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let limit = data.len().min(MAX_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+    for (i, &byte) in data[..limit].iter().enumerate() {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if i >= MIN_CHUNK_SIZE && hash & BOUNDARY_MASK == 0 {
+            return i + 1;
+        }
+    }
+    limit
+}
+
+/// Hex SHA-256 digest identifying a chunk
+pub fn digest(chunk: &[u8]) -> String {
+    hex::encode(Sha256::digest(chunk))
+}
@@ -0,0 +1,235 @@
+//! What a component must be for a server to run it.
+//!
+//! Functions are components of the `wasi:http/proxy` world. They export
+//! `wasi:http/incoming-handler` and may import WASI and the interfaces the server
+//! implements itself, such as `faasta:webhook/deliveries`. The server checks each
+//! upload against these rules, and reports them in [`ServerInfo::features`] so the
+//! CLI can check a component the same way before uploading it, without knowing
+//! which interfaces that server's release adds.
+
+use crate::ServerInfo;
+
+/// Interface every function must export, without its version
+pub const INCOMING_HANDLER: &str = "wasi:http/incoming-handler";
+/// Function the incoming handler must provide
+pub const HANDLE_FUNC: &str = "handle";
+/// Prefix of the features naming what components may import, followed by an
+/// interface (`wasi` for all of WASI), `@` and the version prefix implemented
+pub const IMPORT_FEATURE_PREFIX: &str = "import:";
+
+/// Version prefix of the WASI interfaces servers of this release implement
+const WASI_VERSION: &str = "0.2.";
+/// Platform interfaces servers of this release implement, with their version prefix
+const HOST_INTERFACES: &[(&str, &str)] = &[("faasta:webhook/deliveries", "0.1.")];
+
+/// An export of a component, as far as the rules are concerned
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Export {
+    /// An interface, with the names of its functions
+    Interface(Vec<String>),
+    /// A function or anything else
+    Other,
+}
+
+/// The versions of WASI and the platform interfaces a server implements
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComponentRules {
+    /// Version prefix of the WASI interfaces, such as `0.2.`
+    pub wasi_version: String,
+    /// Platform interfaces, such as `faasta:webhook/deliveries`, with their version
+    /// prefix
+    pub host_interfaces: Vec<(String, String)>,
+}
+
+impl ComponentRules {
+    /// The rules of servers of this release
+    pub fn current() -> Self {
+        Self {
+            wasi_version: WASI_VERSION.to_string(),
+            host_interfaces: HOST_INTERFACES
+                .iter()
+                .map(|(interface, version)| (interface.to_string(), version.to_string()))
+                .collect(),
+        }
+    }
+
+    /// The rules as [`ServerInfo::features`]
+    pub fn features(&self) -> Vec<String> {
+        std::iter::once(format!("{IMPORT_FEATURE_PREFIX}wasi@{}", self.wasi_version))
+            .chain(self.host_interfaces.iter().map(|(interface, version)| {
+                format!("{IMPORT_FEATURE_PREFIX}{interface}@{version}")
+            }))
+            .collect()
+    }
+
+    /// The rules the server of `info` reports, none for servers that don't
+    pub fn reported(info: &ServerInfo) -> Option<Self> {
+        let mut wasi_version = None;
+        let mut host_interfaces = Vec::new();
+        for feature in &info.features {
+            let Some((interface, version)) = feature
+                .strip_prefix(IMPORT_FEATURE_PREFIX)
+                .and_then(|import| import.split_once('@'))
+            else {
+                continue;
+            };
+            if interface == "wasi" {
+                wasi_version = Some(version.to_string());
+            } else {
+                host_interfaces.push((interface.to_string(), version.to_string()));
+            }
+        }
+        Some(Self {
+            wasi_version: wasi_version?,
+            host_interfaces,
+        })
+    }
+
+    /// Check a component exporting `exports` and importing `imports` against the
+    /// `wasi:http/proxy` world, returning every problem found
+    pub fn check(
+        &self,
+        exports: &[(String, Export)],
+        imports: &[String],
+    ) -> Result<(), Vec<String>> {
+        let wasi_version = &self.wasi_version;
+        let mut problems = Vec::new();
+
+        match exports
+            .iter()
+            .find(|(name, _)| interface_name(name) == INCOMING_HANDLER)
+        {
+            None => {
+                let names: Vec<&str> = exports.iter().map(|(name, _)| name.as_str()).collect();
+                problems.push(format!(
+                    "missing export `{INCOMING_HANDLER}@{wasi_version}x` (the component exports {})",
+                    if names.is_empty() {
+                        "nothing".to_string()
+                    } else {
+                        names.join(", ")
+                    }
+                ));
+            }
+            Some((name, Export::Interface(functions))) => {
+                if !has_version(name, wasi_version) {
+                    problems.push(format!(
+                        "export `{name}` targets an unsupported version (the server implements {wasi_version}x)"
+                    ));
+                }
+                if !functions.iter().any(|func| func == HANDLE_FUNC) {
+                    problems.push(format!(
+                        "export `{name}` is missing the `{HANDLE_FUNC}` function"
+                    ));
+                }
+            }
+            Some((name, Export::Other)) => {
+                problems.push(format!("export `{name}` is not an interface"))
+            }
+        }
+
+        for name in imports {
+            if let Some((_, version)) = self
+                .host_interfaces
+                .iter()
+                .find(|(interface, _)| interface_name(name) == interface)
+            {
+                if !name
+                    .split_once('@')
+                    .is_some_and(|(_, v)| v.starts_with(version.as_str()))
+                {
+                    problems.push(format!(
+                        "import `{name}` targets an unsupported version (the server implements {version}x)"
+                    ));
+                }
+            } else if !name.starts_with("wasi:") {
+                let available: Vec<&str> = std::iter::once("WASI interfaces")
+                    .chain(
+                        self.host_interfaces
+                            .iter()
+                            .map(|(interface, _)| interface.as_str()),
+                    )
+                    .collect();
+                problems.push(format!(
+                    "unsupported import `{name}` (only {} are available)",
+                    available.join(", ")
+                ));
+            } else if !has_version(name, wasi_version) {
+                problems.push(format!(
+                    "import `{name}` targets an unsupported version (the server implements {wasi_version}x)"
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+/// `wasi:http/incoming-handler@0.2.0` -> `wasi:http/incoming-handler`
+fn interface_name(name: &str) -> &str {
+    name.split('@').next().unwrap_or(name)
+}
+
+/// Whether `name` has no version or one starting with `prefix`
+fn has_version(name: &str, prefix: &str) -> bool {
+    name.split_once('@')
+        .is_none_or(|(_, version)| version.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_rules_round_trip_through_server_info() {
+        let rules = ComponentRules::current();
+        let mut info = ServerInfo {
+            protocol_version: crate::PROTOCOL_VERSION,
+            server_version: "0.0.0".to_string(),
+            limits: BTreeMap::new(),
+            features: vec!["trash".to_string()],
+        };
+        assert_eq!(ComponentRules::reported(&info), None);
+        info.features.extend(rules.features());
+        assert_eq!(ComponentRules::reported(&info), Some(rules));
+    }
+
+    #[test]
+    fn test_problems_name_the_culprit() {
+        let rules = ComponentRules::current();
+        let handler = (
+            "wasi:http/incoming-handler@0.2.0".to_string(),
+            Export::Interface(vec!["handle".to_string()]),
+        );
+        let imports = ["wasi:io/streams@0.2.1", "faasta:webhook/deliveries@0.1.0"];
+        let imports: Vec<String> = imports.iter().map(|name| name.to_string()).collect();
+        assert_eq!(
+            rules.check(std::slice::from_ref(&handler), &imports),
+            Ok(())
+        );
+
+        let problems = rules
+            .check(
+                &[("run".to_string(), Export::Other)],
+                &["acme:db/store@1.0.0".to_string()],
+            )
+            .unwrap_err();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("missing export") && problems[0].contains("run"));
+        assert!(problems[1].contains("`acme:db/store@1.0.0`"));
+
+        // An interface a newer server adds is fine once it reports it
+        let mut newer = rules.clone();
+        newer
+            .host_interfaces
+            .push(("acme:db/store".to_string(), "1.".to_string()));
+        assert_eq!(
+            newer.check(&[handler], &["acme:db/store@1.0.0".to_string()]),
+            Ok(())
+        );
+    }
+}
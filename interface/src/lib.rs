@@ -1,95 +1,2814 @@
 use bincode::{Decode, Encode};
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, UNIX_EPOCH};
 use thiserror::Error;
 use tokio::sync::Mutex;
 
+pub mod chunking;
+pub mod components;
+pub mod oci;
+
+/// Largest artifact a server accepts unless configured otherwise
 pub const MAX_WASM_SIZE: usize = 30 * 1024 * 1024;
+/// Largest chunk of an upload sent in one `upload_chunk` call
+pub const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Errors returned across the RPC boundary. They are serialized as they are, so
+/// clients can branch on the kind of failure instead of matching message text.
+#[derive(Debug, Error, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum FaastaError {
+    /// The token or API key was missing, invalid, expired or revoked
+    #[error("Authentication failed: {0}")]
+    AuthFailed(String),
+
+    /// The caller already has as many of `quota` as they may, e.g. `projects`
+    #[error("Quota exceeded: at most {limit} {quota} allowed")]
+    QuotaExceeded { quota: String, limit: u64 },
+
+    /// The upload isn't a usable `wasi:http/proxy` component, for each of `details`
+    #[error("Not a valid wasi:http/proxy component:\n  - {}", details.join("\n  - "))]
+    InvalidComponent { details: Vec<String> },
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
+    /// The server failed. What went wrong is only in the server's log, under `id`.
+    #[error("Internal server error (reference {id})")]
+    Internal { id: String },
+
+    #[error("Artifact too large: {size} bytes, but the server accepts at most {limit} bytes")]
+    TooLarge { size: u64, limit: u64 },
+}
+
+impl FaastaError {
+    /// Stable name of the kind of error, as used in JSON error bodies
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FaastaError::AuthFailed(_) => "auth-failed",
+            FaastaError::QuotaExceeded { .. } => "quota-exceeded",
+            FaastaError::InvalidComponent { .. } => "invalid-component",
+            FaastaError::NotFound(_) => "not-found",
+            FaastaError::PermissionDenied(_) => "permission-denied",
+            FaastaError::InvalidInput(_) => "invalid-input",
+            FaastaError::Internal { .. } => "internal",
+            FaastaError::TooLarge { .. } => "too-large",
+        }
+    }
+}
+
+// Type alias for Result with our custom error
+pub type FunctionResult<T> = std::result::Result<T, FaastaError>;
+
+// Define the data structures for our service
+
+/// Represents a published function
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct FunctionInfo {
+    /// Name of the function
+    pub name: String,
+    /// Owner's GitHub username
+    pub owner: String,
+    /// When the function was published
+    pub published_at: String,
+    /// Usage information
+    pub usage: String,
+    /// What its project says about it
+    pub metadata: FunctionMetadata,
+}
+
+/// Most tags a function may have
+pub const MAX_FUNCTION_TAGS: usize = 16;
+/// Longest description a function may have, in characters
+pub const MAX_DESCRIPTION_CHARS: usize = 280;
+
+/// What a project declares about its function in `faasta.toml`, shown when
+/// functions are listed
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct FunctionMetadata {
+    /// One line about what the function does
+    pub description: String,
+    /// Labels to find the function by, such as `api`
+    pub tags: Vec<String>,
+    /// Routes the function serves through handlers of their own, such as `/admin`.
+    /// Listed from the handlers the server stores, whatever a publish sends.
+    pub routes: Vec<String>,
+}
+
+impl FunctionMetadata {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.description.chars().count() > MAX_DESCRIPTION_CHARS {
+            return Err(format!(
+                "the description is longer than {MAX_DESCRIPTION_CHARS} characters"
+            ));
+        }
+        if self.description.chars().any(char::is_control) {
+            return Err("the description must be a single line".to_string());
+        }
+        if self.tags.len() > MAX_FUNCTION_TAGS {
+            return Err(format!(
+                "{} tags, more than the {MAX_FUNCTION_TAGS} allowed",
+                self.tags.len()
+            ));
+        }
+        for tag in &self.tags {
+            let valid = !tag.is_empty()
+                && tag.len() <= 32
+                && tag
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid {
+                return Err(format!(
+                    "tag '{tag}' must be 1-32 lowercase letters, digits or dashes"
+                ));
+            }
+        }
+        validate_routes(self.routes.iter().map(String::as_str))
+    }
+
+    /// Whether the function is tagged `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+/// Order functions are listed in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FunctionSort {
+    /// By name, A to Z
+    #[default]
+    Name,
+    /// Most recently published first
+    LastDeploy,
+    /// Most invoked first
+    Invocations,
+}
+
+impl fmt::Display for FunctionSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FunctionSort::Name => "name",
+            FunctionSort::LastDeploy => "last-deploy",
+            FunctionSort::Invocations => "invocations",
+        })
+    }
+}
+
+impl FromStr for FunctionSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(FunctionSort::Name),
+            "last-deploy" => Ok(FunctionSort::LastDeploy),
+            "invocations" => Ok(FunctionSort::Invocations),
+            other => Err(format!(
+                "unknown sort '{other}' (expected name, last-deploy or invocations)"
+            )),
+        }
+    }
+}
+
+/// Which of their functions a user lists, in what order
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunctionQuery {
+    /// Only functions whose name contains this, ignoring case
+    pub name_filter: Option<String>,
+    /// Only functions with this tag
+    pub tag: Option<String>,
+    pub sort: FunctionSort,
+    /// List in the opposite order
+    pub reverse: bool,
+    /// Page to list, from 1
+    pub page: u32,
+    /// Functions per page; 0 lists them all on one page
+    pub limit: u32,
+}
+
+impl FunctionQuery {
+    /// Every function, by name
+    pub fn all() -> Self {
+        Self {
+            name_filter: None,
+            tag: None,
+            sort: FunctionSort::Name,
+            reverse: false,
+            page: 1,
+            limit: 0,
+        }
+    }
+}
+
+/// A page of a user's functions
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunctionPage {
+    pub functions: Vec<FunctionInfo>,
+    /// Page listed, from 1
+    pub page: u32,
+    /// Functions matching the filter, on every page
+    pub matching: u64,
+    /// Functions the user can list, filtered or not
+    pub total: u64,
+}
+
+/// Function metrics information
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunctionMetricsResponse {
+    /// Name of the function
+    pub function_name: String,
+    /// Total execution time in milliseconds
+    pub total_time_millis: u64,
+    /// Number of times the function was called
+    pub call_count: u64,
+    /// Last time the function was called (ISO 8601 format)
+    pub last_called: String,
+}
+
+/// Invocation statistics of one function
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FunctionStats {
+    /// Name of the function
+    pub function_name: String,
+    /// Invocations since the function was published
+    pub invocations: u64,
+    /// Invocations that trapped, timed out or never set a response
+    pub errors: u64,
+    /// Median latency in milliseconds over the recent invocations
+    pub p50_ms: Option<u64>,
+    /// 95th percentile latency in milliseconds over the recent invocations
+    pub p95_ms: Option<u64>,
+    /// 99th percentile latency in milliseconds over the recent invocations
+    pub p99_ms: Option<u64>,
+    /// How many of the latest invocations the percentiles cover
+    pub latency_samples: u32,
+    /// When the function was last invoked (RFC 3339), if ever
+    pub last_invoked: Option<String>,
+    /// Linear memory of the recent invocations, if any were measured
+    pub memory: Option<MemoryStats>,
+}
+
+/// Linear memory a function's instances grew to over its recent invocations
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MemoryStats {
+    /// How many of the latest invocations were measured
+    pub samples: u32,
+    /// Memory of the latest invocation, in bytes
+    pub latest_bytes: u64,
+    /// Smallest memory over the measured invocations, in bytes
+    pub min_bytes: u64,
+    /// Largest memory over the measured invocations, in bytes
+    pub max_bytes: u64,
+    /// Memory of the latest invocation minus that of the oldest measured one, of
+    /// the instance that served the latest invocation
+    pub growth_bytes: i64,
+    /// Whether that instance's memory only ever grew over a full window of its
+    /// invocations, as it does when a function leaks
+    pub only_grows: bool,
+}
+
+/// Whether a function is ready for its next request
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WarmState {
+    /// Not loaded on the server answering; its next request loads it first
+    Cold,
+    /// Loaded, each request creating a fresh instance
+    Loaded,
+    /// Loaded, with instances waiting for the next requests
+    Warm { idle_instances: u32 },
+}
+
+impl fmt::Display for WarmState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarmState::Cold => f.write_str("cold"),
+            WarmState::Loaded => f.write_str("loaded"),
+            WarmState::Warm { idle_instances } => {
+                write!(f, "warm ({idle_instances} idle instances)")
+            }
+        }
+    }
+}
+
+/// The latest failed invocation of a function
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InvocationFailure {
+    /// When it failed (RFC 3339)
+    pub at: String,
+    /// Why: a trap, a timeout or no response set
+    pub message: String,
+}
+
+/// How much of one of its quotas a function's owner uses
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    /// What's counted, such as `projects`
+    pub quota: String,
+    pub used: u64,
+    pub limit: u64,
+}
+
+/// Where a function stands, at a glance
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunctionStatus {
+    pub name: String,
+    /// Owner's GitHub username, or the team's `FunctionInfo::owner` value
+    pub owner: String,
+    /// When the version it runs was published
+    pub published_at: String,
+    /// Digest of the WebAssembly it runs, `sha256:<hex>`, unless it was
+    /// published before digests were recorded
+    pub digest: Option<String>,
+    /// Size of the WebAssembly it runs, in bytes
+    pub artifact_bytes: Option<u64>,
+    /// Percent of requests its canary receives, if it has one
+    pub canary_weight: Option<u8>,
+    /// Addresses it's served at
+    pub urls: Vec<String>,
+    pub warm: WarmState,
+    /// Whether it's kept warm, by `--keep-warm` or a schedule
+    pub kept_warm: bool,
+    /// Invocations since it was published
+    pub invocations: u64,
+    /// Invocations that failed
+    pub errors: u64,
+    /// The latest failure since the server started, if any
+    pub last_error: Option<InvocationFailure>,
+    /// The owner's use of the quotas the function counts against
+    pub quotas: Vec<QuotaUsage>,
+}
+
+/// Overall metrics information
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Total execution time across all functions in milliseconds
+    pub total_time: u64,
+    /// Total number of function calls
+    pub total_calls: u64,
+    /// Metrics for individual functions
+    pub function_metrics: Vec<FunctionMetricsResponse>,
+}
+
+/// What a server-issued API key is allowed to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum ApiKeyScope {
+    /// Publish new functions and new versions of existing ones
+    Deploy,
+    /// List functions and read metrics
+    Read,
+    /// Unpublish and otherwise manage existing functions
+    Manage,
+}
+
+impl fmt::Display for ApiKeyScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ApiKeyScope::Deploy => "deploy",
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::Manage => "manage",
+        })
+    }
+}
+
+impl FromStr for ApiKeyScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deploy" => Ok(ApiKeyScope::Deploy),
+            "read" => Ok(ApiKeyScope::Read),
+            "manage" => Ok(ApiKeyScope::Manage),
+            other => Err(format!(
+                "unknown scope '{other}' (expected deploy, read or manage)"
+            )),
+        }
+    }
+}
+
+/// Metadata about an API key (never includes the key itself)
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct ApiKeyInfo {
+    /// Short identifier used to revoke the key
+    pub id: String,
+    /// Human readable label chosen when the key was created
+    pub name: String,
+    /// Operations the key is allowed to perform
+    pub scopes: Vec<ApiKeyScope>,
+    /// When the key was created (RFC 3339)
+    pub created_at: String,
+}
+
+/// A freshly created API key; the secret is only ever returned once
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NewApiKey {
+    pub info: ApiKeyInfo,
+    /// The key to send in place of a GitHub token
+    pub key: String,
+}
+
+/// How a session authenticates
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum SessionKind {
+    /// A provider login, such as the GitHub token stored by `cargo faasta login`
+    Login,
+    /// A server-issued API key
+    ApiKey,
+}
+
+impl fmt::Display for SessionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SessionKind::Login => "login",
+            SessionKind::ApiKey => "api-key",
+        })
+    }
+}
+
+/// A credential that has been used with the server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// Short identifier used to revoke the session (the key id for API keys)
+    pub id: String,
+    pub kind: SessionKind,
+    /// Device label for logins, key name for API keys
+    pub label: String,
+    /// When the credential was first seen (RFC 3339)
+    pub created_at: String,
+    /// When the credential was last used (RFC 3339), if ever
+    pub last_used: Option<String>,
+    /// Whether this is the credential making the request
+    pub current: bool,
+}
+
+/// Kind of unusual activity the server flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum AlertKind {
+    /// A deploy came from a network the account hasn't deployed from before
+    NewDeployNetwork,
+    /// A function suddenly received far more requests than usual
+    TrafficSpike,
+    /// A function suddenly sent far more response bytes than usual
+    EgressSpike,
+    /// The memory of one of a function's instances only ever grew over its recent
+    /// invocations
+    MemoryGrowth,
+    /// A function trapped so often that its circuit breaker stopped invoking it
+    BreakerTripped,
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AlertKind::NewDeployNetwork => "new-deploy-network",
+            AlertKind::TrafficSpike => "traffic-spike",
+            AlertKind::EgressSpike => "egress-spike",
+            AlertKind::MemoryGrowth => "memory-growth",
+            AlertKind::BreakerTripped => "breaker-tripped",
+        })
+    }
+}
+
+/// Unusual activity waiting for review by the owner or an admin
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct AnomalyAlert {
+    /// Identifier used to resolve the alert
+    pub id: u64,
+    pub kind: AlertKind,
+    /// Account or team owner (`team:<name>`) the alert is about
+    pub owner: String,
+    /// Function the alert is about, if any
+    pub function: Option<String>,
+    /// Human readable description of what was observed
+    pub detail: String,
+    /// When the activity was flagged (RFC 3339)
+    pub created_at: String,
+}
+
+/// Why a function's requests are answered 503 instead of invoking it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum BreakerState {
+    /// An admin stopped the function with its kill switch
+    Killed,
+    /// The function trapped too often and its circuit breaker tripped
+    Tripped,
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BreakerState::Killed => "killed",
+            BreakerState::Tripped => "tripped",
+        })
+    }
+}
+
+/// A function that is no longer invoked until its breaker is reset
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct CircuitBreaker {
+    pub state: BreakerState,
+    /// The admin's reason, or the trap rate that tripped the breaker
+    pub reason: String,
+    /// Admin who killed the function, `None` when the breaker tripped by itself
+    pub opened_by: Option<String>,
+    /// When the function stopped being invoked (RFC 3339)
+    pub opened_at: String,
+}
+
+/// Ways the server's records can disagree with each other, e.g. after a crash
+/// halfway through a publish, rename or delete
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum DriftKind {
+    /// A function's metadata has no WebAssembly in artifact storage
+    MissingArtifact,
+    /// WebAssembly in artifact storage belongs to no function
+    OrphanedArtifact,
+    /// A function is missing from its owner's list of projects
+    UnlistedFunction,
+    /// An account lists a project that isn't a function, in the trash or reserved
+    StaleProject,
+    /// An account lists a function owned by someone else
+    ForeignProject,
+    /// Per-function settings or data are kept for a name that isn't a function
+    OrphanedData,
+    /// A renamed function's old name redirects to a function that no longer exists
+    StaleRedirect,
+}
+
+impl fmt::Display for DriftKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DriftKind::MissingArtifact => "missing-artifact",
+            DriftKind::OrphanedArtifact => "orphaned-artifact",
+            DriftKind::UnlistedFunction => "unlisted-function",
+            DriftKind::StaleProject => "stale-project",
+            DriftKind::ForeignProject => "foreign-project",
+            DriftKind::OrphanedData => "orphaned-data",
+            DriftKind::StaleRedirect => "stale-redirect",
+        })
+    }
+}
+
+impl DriftKind {
+    pub const ALL: [DriftKind; 7] = [
+        DriftKind::MissingArtifact,
+        DriftKind::OrphanedArtifact,
+        DriftKind::UnlistedFunction,
+        DriftKind::StaleProject,
+        DriftKind::ForeignProject,
+        DriftKind::OrphanedData,
+        DriftKind::StaleRedirect,
+    ];
+}
+
+impl FromStr for DriftKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DriftKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| format!("unknown drift kind '{s}'"))
+    }
+}
+
+/// One disagreement found by a consistency check
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+pub struct Drift {
+    pub kind: DriftKind,
+    /// Function, project or key concerned
+    pub subject: String,
+    /// What disagrees, and with what
+    pub detail: String,
+    /// Whether the check repaired it
+    pub repaired: bool,
+}
+
+/// Outcome of cross-checking function metadata, artifact storage, redirects and
+/// the accounts' project lists
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct ConsistencyReport {
+    /// When the check ran (RFC 3339)
+    pub checked_at: String,
+    /// Functions checked
+    pub functions: u64,
+    /// Artifacts in storage checked
+    pub artifacts: u64,
+    pub drift: Vec<Drift>,
+}
+
+/// What garbage collection removes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum GarbageKind {
+    /// WebAssembly in artifact storage no function, project or trashed function owns
+    OrphanedArtifact,
+    /// A canary or route handler version its function no longer uses
+    StaleVersion,
+    /// A function whose time in the trash ran out
+    ExpiredTrash,
+    /// Function log lines older than their retention period
+    ExpiredLogs,
+    /// A precompiled artifact on local disk for a version that isn't served
+    StaleCache,
+    /// Per-function records kept for a name that isn't a function
+    StaleMetadata,
+}
+
+impl fmt::Display for GarbageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GarbageKind::OrphanedArtifact => "orphaned-artifact",
+            GarbageKind::StaleVersion => "stale-version",
+            GarbageKind::ExpiredTrash => "expired-trash",
+            GarbageKind::ExpiredLogs => "expired-logs",
+            GarbageKind::StaleCache => "stale-cache",
+            GarbageKind::StaleMetadata => "stale-metadata",
+        })
+    }
+}
+
+/// One piece of garbage found by a collection
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+pub struct Garbage {
+    pub kind: GarbageKind,
+    /// Function, version or key concerned
+    pub subject: String,
+    /// Bytes it takes up, where known
+    pub bytes: u64,
+    pub detail: String,
+    /// Whether it's been garbage long enough to be removed
+    pub due: bool,
+    /// Whether the collection removed it
+    pub removed: bool,
+}
+
+/// Outcome of a garbage collection, or of a dry run of one
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct GarbageReport {
+    /// When the collection ran (RFC 3339)
+    pub collected_at: String,
+    /// Whether garbage was only reported, not removed
+    pub dry_run: bool,
+    pub garbage: Vec<Garbage>,
+}
+
+impl GarbageReport {
+    /// Bytes the removed garbage took up
+    pub fn freed_bytes(&self) -> u64 {
+        self.garbage
+            .iter()
+            .filter(|garbage| garbage.removed)
+            .map(|garbage| garbage.bytes)
+            .sum()
+    }
+}
+
+/// Resource use of one function, as ranked in a capacity report
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+pub struct ResourceConsumer {
+    pub function_name: String,
+    /// Invocations since the function was published
+    pub invocations: u64,
+    /// Wall-clock time its invocations took since it was published, in
+    /// milliseconds, waiting on I/O included
+    #[serde(alias = "cpu_millis")]
+    pub invocation_millis: u64,
+    /// Largest linear memory over its recent invocations, in bytes
+    pub peak_memory_bytes: u64,
+}
+
+/// How fast a server's load and disk use grew between two capacity reports
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+pub struct CapacityGrowth {
+    /// Days between the earlier report and this one
+    pub over_days: f64,
+    pub invocations_per_day: f64,
+    #[serde(alias = "cpu_millis_per_day")]
+    pub invocation_millis_per_day: f64,
+    /// Change per day of the local disk used by functions and the database, in bytes
+    pub disk_bytes_per_day: f64,
+    /// Days until the disk is full at this rate, if it's filling up
+    pub days_until_disk_full: Option<f64>,
+}
+
+/// Periodic summary of where a server's time, memory and disk go, for planning
+/// hardware before a limit is hit
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct CapacityReport {
+    /// When the report was made (RFC 3339)
+    pub generated_at: String,
+    /// Functions with metadata
+    pub functions: u64,
+    /// Invocations of all functions since they were published
+    pub invocations: u64,
+    /// Wall-clock time invocations of all functions took, in milliseconds
+    #[serde(alias = "cpu_millis")]
+    pub invocation_millis: u64,
+    /// Functions whose invocations took the most time, most first
+    #[serde(alias = "top_cpu")]
+    pub top_invocation_time: Vec<ResourceConsumer>,
+    /// Functions whose instances grew the largest, largest first
+    pub top_memory: Vec<ResourceConsumer>,
+    /// Compiled components loaded in memory
+    pub cached_components: u64,
+    /// Estimated memory of the loaded components, in bytes
+    pub component_cache_bytes: u64,
+    /// Precompiled artifacts in the functions directory, in bytes
+    pub compiled_bytes: u64,
+    /// Published WebAssembly in artifact storage, in bytes
+    pub artifact_bytes: u64,
+    /// Local disk used by the functions directory and the database, in bytes
+    pub disk_used_bytes: u64,
+    /// Free space on the disk of the functions directory, if it could be read
+    pub disk_free_bytes: Option<u64>,
+    /// Growth since the oldest report kept, if one is old enough to compare with
+    pub growth: Option<CapacityGrowth>,
+}
+
+/// A backup of a server's database and function artifacts, kept on the server
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct BackupInfo {
+    /// File name of the backup, read in chunks of [`UPLOAD_CHUNK_SIZE`] bytes
+    pub name: String,
+    /// When the backup was taken (RFC 3339)
+    pub created_at: String,
+    /// Size of the backup, in bytes
+    pub size_bytes: u64,
+    /// Database records in the backup
+    pub records: u64,
+    /// Function artifacts in the backup
+    pub artifacts: u64,
+}
+
+/// A node of a cluster sharing function metadata and artifacts
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct ClusterNode {
+    /// Id the node was started with
+    pub id: String,
+    /// Version of server-wasi the node runs
+    pub version: String,
+    /// When the node started (RFC 3339)
+    pub started_at: String,
+    /// When the node last reported in (RFC 3339)
+    pub last_seen: String,
+    /// Whether the node reported in recently enough to be taken as running
+    pub alive: bool,
+}
+
+/// Days ahead the growth of a capacity report is projected to
+pub const CAPACITY_PROJECTION_DAYS: f64 = 30.0;
+
+impl fmt::Display for CapacityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |bytes: u64| format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0));
+        writeln!(f, "Capacity report of {}", self.generated_at)?;
+        writeln!(
+            f,
+            "  {} functions, {} invocations, {:.1} hours in invocations",
+            self.functions,
+            self.invocations,
+            self.invocation_millis as f64 / 3_600_000.0
+        )?;
+        writeln!(f, "Top consumers of invocation time:")?;
+        for consumer in &self.top_invocation_time {
+            writeln!(
+                f,
+                "  {:<32} {:>10.1} s  {:>10} calls",
+                consumer.function_name,
+                consumer.invocation_millis as f64 / 1000.0,
+                consumer.invocations
+            )?;
+        }
+        writeln!(f, "Top memory consumers:")?;
+        for consumer in &self.top_memory {
+            writeln!(
+                f,
+                "  {:<32} {:>12}",
+                consumer.function_name,
+                mib(consumer.peak_memory_bytes)
+            )?;
+        }
+        writeln!(f, "Compilation cache:")?;
+        writeln!(
+            f,
+            "  {} components loaded, {}",
+            self.cached_components,
+            mib(self.component_cache_bytes)
+        )?;
+        writeln!(f, "  {} precompiled on disk", mib(self.compiled_bytes))?;
+        writeln!(f, "Disk:")?;
+        writeln!(f, "  {} in artifact storage", mib(self.artifact_bytes))?;
+        match self.disk_free_bytes {
+            Some(free) => writeln!(
+                f,
+                "  {} used locally, {} free",
+                mib(self.disk_used_bytes),
+                mib(free)
+            )?,
+            None => writeln!(f, "  {} used locally", mib(self.disk_used_bytes))?,
+        }
+        let Some(growth) = &self.growth else {
+            return writeln!(f, "Growth: not enough history yet");
+        };
+        let days = CAPACITY_PROJECTION_DAYS;
+        writeln!(f, "Growth over the last {:.1} days:", growth.over_days)?;
+        writeln!(
+            f,
+            "  {:.0} invocations/day, {:.0} more in {days} days",
+            growth.invocations_per_day,
+            growth.invocations_per_day * days
+        )?;
+        writeln!(
+            f,
+            "  {:.2} hours in invocations/day, {:.1} more in {days} days",
+            growth.invocation_millis_per_day / 3_600_000.0,
+            growth.invocation_millis_per_day * days / 3_600_000.0
+        )?;
+        let disk_per_day = growth.disk_bytes_per_day;
+        let projected = (self.disk_used_bytes as f64 + disk_per_day * days).max(0.0);
+        writeln!(
+            f,
+            "  {:+.1} MiB/day of disk, {} used in {days} days",
+            disk_per_day / (1024.0 * 1024.0),
+            mib(projected as u64)
+        )?;
+        if let Some(full) = growth.days_until_disk_full {
+            writeln!(f, "  Disk full in about {full:.0} days")?;
+        }
+        Ok(())
+    }
+}
+
+/// Usage of one account in one billing period (calendar month, UTC)
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Encode, Decode)]
+pub struct UsageRecord {
+    pub username: String,
+    /// Billing period, formatted as `YYYY-MM`
+    pub period: String,
+    /// Requests served by the account's functions
+    pub call_count: u64,
+    /// Wall-clock time the account's functions ran, in milliseconds
+    pub compute_millis: u64,
+    /// Response body bytes sent by the account's functions
+    pub egress_bytes: u64,
+    /// Most bytes of artifacts the account's functions took up in storage
+    pub storage_bytes: u64,
+}
+
+/// Format usage records are exported in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UsageFormat {
+    /// One line per account, with a header row
+    #[default]
+    Csv,
+    /// An array of [`UsageRecord`]s
+    Json,
+}
+
+impl fmt::Display for UsageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UsageFormat::Csv => "csv",
+            UsageFormat::Json => "json",
+        })
+    }
+}
+
+impl FromStr for UsageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(UsageFormat::Csv),
+            "json" => Ok(UsageFormat::Json),
+            other => Err(format!(
+                "unknown usage format '{other}' (expected csv or json)"
+            )),
+        }
+    }
+}
+
+/// How serious a server event is. Severities are ordered, so filtering by one
+/// includes everything more serious.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Encode, Decode,
+)]
+pub enum EventSeverity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for EventSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EventSeverity::Info => "info",
+            EventSeverity::Warning => "warning",
+            EventSeverity::Error => "error",
+        })
+    }
+}
+
+impl FromStr for EventSeverity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "info" => Ok(EventSeverity::Info),
+            "warning" => Ok(EventSeverity::Warning),
+            "error" => Ok(EventSeverity::Error),
+            other => Err(format!(
+                "unknown severity '{other}' (expected info, warning or error)"
+            )),
+        }
+    }
+}
+
+/// Kind of significant platform event recorded in the server's journal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum ServerEventKind {
+    /// The server process started
+    ServerStarted,
+    /// The server process drained its connections and stopped
+    ServerStopped,
+    /// A new TLS certificate was installed
+    CertificateRenewed,
+    /// A canary replaced its function's stable version
+    CanaryPromoted,
+    /// A canary was removed without being promoted
+    CanaryAborted,
+    /// A published component was refused by the server's policy checks
+    UploadRejected,
+    /// A function was deleted for good once its trash retention ran out
+    TrashPurged,
+    /// An admin suspended an account
+    AccountSuspended,
+    /// An admin lifted an account's suspension
+    AccountReinstated,
+    /// A function's outgoing webhook was given up on after its last retry
+    WebhookFailed,
+    /// An admin stopped invoking a function with its kill switch
+    FunctionKilled,
+    /// A function's circuit breaker tripped on too many traps
+    BreakerTripped,
+    /// A killed or tripped function was invoked again
+    BreakerReset,
+    /// A consistency check found records disagreeing with each other
+    ConsistencyDrift,
+    /// An account was refused for having used up one of its quotas
+    QuotaExceeded,
+}
+
+impl ServerEventKind {
+    pub const ALL: [ServerEventKind; 15] = [
+        ServerEventKind::ServerStarted,
+        ServerEventKind::ServerStopped,
+        ServerEventKind::CertificateRenewed,
+        ServerEventKind::CanaryPromoted,
+        ServerEventKind::CanaryAborted,
+        ServerEventKind::UploadRejected,
+        ServerEventKind::TrashPurged,
+        ServerEventKind::AccountSuspended,
+        ServerEventKind::AccountReinstated,
+        ServerEventKind::WebhookFailed,
+        ServerEventKind::FunctionKilled,
+        ServerEventKind::BreakerTripped,
+        ServerEventKind::BreakerReset,
+        ServerEventKind::ConsistencyDrift,
+        ServerEventKind::QuotaExceeded,
+    ];
+}
+
+impl fmt::Display for ServerEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ServerEventKind::ServerStarted => "server-started",
+            ServerEventKind::ServerStopped => "server-stopped",
+            ServerEventKind::CertificateRenewed => "certificate-renewed",
+            ServerEventKind::CanaryPromoted => "canary-promoted",
+            ServerEventKind::CanaryAborted => "canary-aborted",
+            ServerEventKind::UploadRejected => "upload-rejected",
+            ServerEventKind::TrashPurged => "trash-purged",
+            ServerEventKind::AccountSuspended => "account-suspended",
+            ServerEventKind::AccountReinstated => "account-reinstated",
+            ServerEventKind::WebhookFailed => "webhook-failed",
+            ServerEventKind::FunctionKilled => "function-killed",
+            ServerEventKind::BreakerTripped => "breaker-tripped",
+            ServerEventKind::BreakerReset => "breaker-reset",
+            ServerEventKind::ConsistencyDrift => "consistency-drift",
+            ServerEventKind::QuotaExceeded => "quota-exceeded",
+        })
+    }
+}
+
+impl FromStr for ServerEventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ServerEventKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| format!("unknown event kind '{s}'"))
+    }
+}
+
+/// A significant platform event, as recorded in the server's journal
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct ServerEvent {
+    /// Increasing sequence number, used to page through the journal
+    pub id: u64,
+    /// When the event happened (RFC 3339)
+    pub timestamp: String,
+    pub severity: EventSeverity,
+    pub kind: ServerEventKind,
+    /// Function, account or domain the event concerns
+    pub subject: Option<String>,
+    /// Human readable description of what happened
+    pub detail: String,
+}
+
+/// One control-plane call, as recorded in the server's audit log
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct AuditEvent {
+    /// Increasing sequence number, used to page through the log
+    pub id: u64,
+    /// When the call was made (RFC 3339)
+    pub timestamp: String,
+    /// User the call authenticated as, or claimed to be if authentication failed
+    pub user: Option<String>,
+    /// Client address, when the transport knows it
+    pub source_ip: Option<String>,
+    /// RPC that was called, e.g. `publish`
+    pub action: String,
+    /// Function, team, key or user the call acted on
+    pub target: Option<String>,
+    /// Why the call failed, or `None` if it succeeded
+    pub error: Option<String>,
+}
+
+/// Level of a line a function logged. Levels are ordered, so filtering by one
+/// includes everything more severe.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Encode, Decode,
+)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        })
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" | "trace" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            other => Err(format!(
+                "unknown log level '{other}' (expected debug, info, warn or error)"
+            )),
+        }
+    }
+}
+
+/// One line a function wrote to stdout or stderr while handling a request
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct LogEntry {
+    /// When the request that logged the line finished (RFC 3339)
+    pub timestamp: String,
+    pub level: LogLevel,
+    /// Id of the request the line was logged during
+    pub request_id: String,
+    pub message: String,
+}
+
+/// The least severe level of a function's lines that gets stored
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogLevelSetting {
+    pub level: LogLevel,
+    /// When the level reverts to the server's default (RFC 3339), if it does
+    pub expires_at: Option<String>,
+}
+
+/// Rules scrubbing a function's log lines before they are stored
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct RedactionRules {
+    /// Headers whose values are replaced wherever a line shows `name: value` or
+    /// `name=value` (case-insensitive)
+    pub headers: Vec<String>,
+    /// Regular expressions whose matches are replaced
+    pub patterns: Vec<String>,
+}
+
+/// The redaction rules of a function, applied on top of those the server enforces
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RedactionSettings {
+    pub function: RedactionRules,
+    pub enforced: RedactionRules,
+}
+
+/// Filters of a function log query. Every filter is optional.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LogQuery {
+    /// Only lines logged at or after this time (Unix milliseconds)
+    pub since: Option<i64>,
+    /// Only lines logged at or before this time (Unix milliseconds)
+    pub until: Option<i64>,
+    /// Only lines at least this severe
+    pub min_level: Option<LogLevel>,
+    pub request_id: Option<String>,
+    /// Only lines containing this text
+    pub grep: Option<String>,
+    /// Continue where a previous page ended
+    pub cursor: Option<String>,
+    /// Most lines returned; the server caps this
+    pub limit: u32,
+}
+
+/// A page of log lines, oldest first
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogPage {
+    pub entries: Vec<LogEntry>,
+    /// Pass as `cursor` to fetch the next page; `None` once the query is exhausted
+    pub next_cursor: Option<String>,
+}
+
+/// Change to a function's canary release
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CanaryUpdate {
+    /// Send this percentage of requests to the canary
+    Weight(u8),
+    /// Replace the stable version with the canary
+    Promote,
+    /// Delete the canary, keeping the stable version
+    Abort,
+}
+
+/// Platform-wide role granted by a server admin.
+/// Roles are ordered, so a role includes everything below it.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Encode, Decode,
+)]
+pub enum PlatformRole {
+    /// List functions and read metrics and logs
+    Viewer,
+    /// Also publish, rename and delete functions
+    Deployer,
+    /// Also grant roles to other users and teams
+    Admin,
+}
+
+impl fmt::Display for PlatformRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PlatformRole::Viewer => "viewer",
+            PlatformRole::Deployer => "deployer",
+            PlatformRole::Admin => "admin",
+        })
+    }
+}
+
+impl FromStr for PlatformRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(PlatformRole::Viewer),
+            "deployer" => Ok(PlatformRole::Deployer),
+            "admin" => Ok(PlatformRole::Admin),
+            other => Err(format!(
+                "unknown role '{other}' (expected viewer, deployer or admin)"
+            )),
+        }
+    }
+}
+
+/// A role granted to a user, or to a team (as `team:<name>`)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoleGrant {
+    pub principal: String,
+    pub role: PlatformRole,
+}
+
+/// Owners starting with this prefix are teams rather than individual users
+pub const TEAM_OWNER_PREFIX: &str = "team:";
+
+/// The `FunctionInfo::owner` value for functions owned by `team`
+pub fn team_owner(team: &str) -> String {
+    format!("{TEAM_OWNER_PREFIX}{team}")
+}
+
+/// What a team member may do with the team's functions.
+/// Roles are ordered, so a role includes everything below it.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Encode, Decode,
+)]
+pub enum TeamRole {
+    /// List functions and read metrics
+    Viewer,
+    /// Also publish new functions and versions
+    Developer,
+    /// Also unpublish functions and manage members
+    Owner,
+}
+
+impl fmt::Display for TeamRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TeamRole::Viewer => "viewer",
+            TeamRole::Developer => "developer",
+            TeamRole::Owner => "owner",
+        })
+    }
+}
+
+impl FromStr for TeamRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(TeamRole::Viewer),
+            "developer" => Ok(TeamRole::Developer),
+            "owner" => Ok(TeamRole::Owner),
+            other => Err(format!(
+                "unknown role '{other}' (expected viewer, developer or owner)"
+            )),
+        }
+    }
+}
+
+/// A member of a team
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct TeamMember {
+    pub username: String,
+    pub role: TeamRole,
+}
+
+/// A team that can own functions
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct TeamInfo {
+    pub name: String,
+    /// GitHub organization whose members are also treated as team members
+    pub github_org: Option<String>,
+    pub members: Vec<TeamMember>,
+    /// Functions owned by the team
+    pub functions: Vec<String>,
+}
+
+/// What a finished upload is published as
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum PublishTarget {
+    /// The caller's function (or an update of one they may deploy)
+    Function,
+    /// A function owned by the team (requires the developer role)
+    Team(String),
+    /// A canary of an existing function taking `weight` percent of its requests
+    Canary { weight: u8 },
+}
+
+/// An upload started on top of a function's deployed artifact
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeltaUpload {
+    pub upload_id: String,
+    /// Digests of the deployed artifact's chunks (see `chunking`), which can be
+    /// sent with `upload_known_chunks` instead of their bytes. Empty when the
+    /// function doesn't exist yet or the caller may not deploy it.
+    pub base_chunks: Vec<String>,
+}
+
+/// Most aliases and redirects one function may have
+pub const MAX_FUNCTION_ALIASES: usize = 8;
+
+/// How requests for an alias reach its function
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum AliasMode {
+    /// The function answers under the alias as under its own name
+    Alias,
+    /// Requests are redirected to the function's own URL, with a 301 when
+    /// `permanent` and a 302 otherwise
+    Redirect { permanent: bool },
+}
+
+impl AliasMode {
+    /// Status of the redirect, if this is one
+    pub fn redirect_status(self) -> Option<u16> {
+        match self {
+            Self::Alias => None,
+            Self::Redirect { permanent: true } => Some(301),
+            Self::Redirect { permanent: false } => Some(302),
+        }
+    }
+}
+
+/// Another name a function is reachable under, on its own subdomain and path
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct FunctionAlias {
+    pub name: String,
+    /// The function the alias leads to
+    pub target: String,
+    pub mode: AliasMode,
+}
+
+/// Most route handlers a function may have
+pub const MAX_ROUTE_HANDLERS: usize = 16;
+
+/// A finished upload to publish as the handler of a function's route
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RouteUpload {
+    /// Path prefix the handler serves, such as `/admin`
+    pub route: String,
+    pub upload_id: String,
+}
+
+/// A component serving the requests under a route of a function, instead of the
+/// function's main component
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct RouteHandler {
+    /// Path prefix the handler serves, such as `/admin`
+    pub route: String,
+    /// Digest of the handler's WebAssembly, `sha256:<hex>`
+    pub digest: String,
+}
+
+/// Check the routes of a function's handlers: distinct path prefixes other than
+/// `/`, which the main component serves
+pub fn validate_routes<'a>(routes: impl IntoIterator<Item = &'a str>) -> Result<(), String> {
+    let mut seen = std::collections::HashSet::new();
+    for route in routes {
+        let trimmed = route.trim_end_matches('/');
+        if !route.starts_with('/') || route.chars().any(|c| c.is_control() || c == '?') {
+            return Err(format!("route '{route}' must be a path like /admin"));
+        }
+        if trimmed.is_empty() {
+            return Err("route '/' is served by the main component".to_string());
+        }
+        if !seen.insert(trimmed) {
+            return Err(format!("route '{route}' has more than one handler"));
+        }
+    }
+    if seen.len() > MAX_ROUTE_HANDLERS {
+        return Err(format!(
+            "{} routes, more than the {MAX_ROUTE_HANDLERS} allowed",
+            seen.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Largest provenance document attached to an upload
+pub const MAX_PROVENANCE_SIZE: usize = 1024 * 1024;
+
+/// SLSA provenance recorded for the artifact a function runs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProvenanceInfo {
+    /// Digest of the artifact, which the attestation's subject matches
+    pub digest: String,
+    /// Such as `https://slsa.dev/provenance/v1`
+    pub predicate_type: String,
+    /// Builder the attestation names, if it names one
+    pub builder_id: Option<String>,
+    /// The document as uploaded: an in-toto statement or a DSSE envelope holding one
+    pub document: String,
+}
+
+/// Largest response body a function with response signing may send, since the
+/// body is buffered to be signed
+pub const MAX_SIGNED_RESPONSE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Public half of a key a function's responses are signed with
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SigningKey {
+    /// Ed25519 public key as the Standard Webhooks spec writes it: `whpk_` and
+    /// the key in base64
+    pub public_key: String,
+    /// When the key was generated (RFC 3339)
+    pub created_at: String,
+}
+
+/// The keys signing a function's responses, if response signing is on
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SigningKeys {
+    pub current: Option<SigningKey>,
+    /// Key replaced by a rotation, which keeps signing alongside the current one
+    /// until `previous_expires_at`, so consumers can switch without a gap
+    pub previous: Option<SigningKey>,
+    pub previous_expires_at: Option<String>,
+}
+
+/// Largest payload a function may queue as a webhook delivery
+pub const MAX_WEBHOOK_PAYLOAD: usize = 1024 * 1024;
+
+/// Where a queued webhook delivery stands
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum DeliveryStatus {
+    /// Waiting for its first or next attempt
+    Pending,
+    /// A receiver answered with a 2xx status
+    Delivered,
+    /// Every attempt failed
+    Failed,
+}
+
+impl fmt::Display for DeliveryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        })
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(DeliveryStatus::Pending),
+            "delivered" => Ok(DeliveryStatus::Delivered),
+            "failed" => Ok(DeliveryStatus::Failed),
+            _ => Err(format!(
+                "unknown delivery status '{s}' (expected pending, delivered or failed)"
+            )),
+        }
+    }
+}
+
+/// One try at delivering a webhook
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct DeliveryAttempt {
+    /// When the attempt started (RFC 3339)
+    pub at: String,
+    /// Status the receiver answered with, if it answered
+    pub status: Option<u16>,
+    /// Why the attempt failed, when it did
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// A webhook a function queued, with the log of its delivery attempts
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    /// Also sent to the receiver as `webhook-id`
+    pub id: String,
+    pub url: String,
+    pub status: DeliveryStatus,
+    /// When the function queued it (RFC 3339)
+    pub created_at: String,
+    /// When the next attempt is due, while the delivery is pending (RFC 3339)
+    pub next_attempt_at: Option<String>,
+    pub attempts: Vec<DeliveryAttempt>,
+}
+
+/// What redacted log text and header values are replaced with
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Most of a request's body kept with a debug snapshot
+pub const MAX_SNAPSHOT_BODY: usize = 64 * 1024;
+
+/// The request a trapped invocation was handling
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct CapturedRequest {
+    pub method: String,
+    /// Path and query
+    pub uri: String,
+    /// Headers in the order received, the values of redacted ones replaced
+    pub headers: Vec<(String, String)>,
+    /// The start of the body, up to [`MAX_SNAPSHOT_BODY`] bytes
+    pub body: Vec<u8>,
+    /// Whether the guest read more of the body than was kept
+    pub body_truncated: bool,
+}
+
+/// A debug snapshot taken when an invocation trapped
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct SnapshotInfo {
+    pub id: String,
+    /// When the invocation trapped (RFC 3339)
+    pub created_at: String,
+    /// The invocation's `X-Request-Id`
+    pub request_id: String,
+    /// The trap, with the guest's backtrace
+    pub error: String,
+    /// Size of the core dump; `None` when the guest's memory was over the
+    /// server's limit and only the request was kept
+    pub core_dump_size: Option<u64>,
+}
+
+/// Whether a function is snapshotted when it traps, and the snapshots kept
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DebugSnapshots {
+    pub enabled: bool,
+    /// Newest first
+    pub snapshots: Vec<SnapshotInfo>,
+}
+
+/// A snapshot with the request it captured. The core dump is read separately, in
+/// chunks of [`UPLOAD_CHUNK_SIZE`] bytes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DebugSnapshot {
+    pub info: SnapshotInfo,
+    pub request: CapturedRequest,
+}
+
+/// A guest profile of one sampled invocation, in the Firefox Profiler's format
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct ProfileInfo {
+    pub id: String,
+    /// When the invocation finished (RFC 3339)
+    pub created_at: String,
+    /// The invocation's `X-Request-Id`
+    pub request_id: String,
+    /// How long the guest ran, in milliseconds
+    pub duration_ms: u64,
+    /// Size of the profile, read in chunks of [`UPLOAD_CHUNK_SIZE`] bytes
+    pub size: u64,
+}
+
+/// How often a function's canary is profiled, and the profiles kept
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GuestProfiles {
+    /// Share of canary invocations profiled, 0 when profiling is off
+    pub sample_rate: f64,
+    /// Newest first
+    pub profiles: Vec<ProfileInfo>,
+}
+
+/// `apiVersion` of function definitions
+pub const FUNCTION_API_VERSION: &str = "faasta.xyz/v1";
+/// `kind` of function definitions
+pub const FUNCTION_KIND: &str = "Function";
+/// Most memory a function definition may allow, the most an instance can have
+pub const MAX_FUNCTION_MEMORY_MB: u32 = 2048;
+/// Longest a function definition may let a request run
+pub const MAX_FUNCTION_TIMEOUT_MS: u64 = 600_000;
+/// Longest a function definition may let a response stream, e.g. Server-Sent Events
+pub const MAX_FUNCTION_STREAM_SECS: u64 = 24 * 60 * 60;
+/// Most instances a function's keep-warm schedule may keep warm
+pub const MAX_SCHEDULED_WARM_INSTANCES: u32 = 50;
+
+/// Declarative definition of a function, shaped like a Kubernetes custom resource
+/// so operators and GitOps tools can keep it in a repository and `apply` it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FunctionDefinition {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: DefinitionMetadata,
+    pub spec: FunctionSpec,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefinitionMetadata {
+    /// Name of the function
+    pub name: String,
+}
+
+/// The state a function is reconciled to
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FunctionSpec {
+    /// OCI reference of the component (such as `ghcr.io/user/fn@sha256:...`)
+    pub artifact: String,
+    /// Team owning the function, when it isn't the caller's own
+    #[serde(default)]
+    pub team: Option<String>,
+    /// Environment variables the function sees
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Path prefixes the function serves, such as `/api`; empty for every path
+    #[serde(default)]
+    pub routes: Vec<String>,
+    #[serde(default)]
+    pub limits: FunctionLimits,
+    /// Windows in which the function keeps instances warm ahead of its requests
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keep_warm: Vec<WarmSchedule>,
+}
+
+/// Warm instances a function keeps during a recurring window of the week
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct WarmSchedule {
+    /// Days the window starts on, such as `mon-fri` or `sat,sun`; every day if unset
+    #[serde(default)]
+    pub days: Option<String>,
+    /// Start of the window, `HH:MM`
+    pub from: String,
+    /// End of the window, `HH:MM`; at or before `from` for one ending the next day
+    pub to: String,
+    /// Offset from UTC the times are in, such as `+05:00`; UTC if unset
+    #[serde(default)]
+    pub utc_offset: Option<String>,
+    /// Instances kept warm during the window
+    pub instances: u32,
+}
+
+/// Days of the week, Monday first, as written in a [`WarmSchedule`]
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl WarmSchedule {
+    /// Days the window starts on, Monday first
+    fn parse_days(&self) -> Result<[bool; 7], String> {
+        let Some(days) = &self.days else {
+            return Ok([true; 7]);
+        };
+        let day = |name: &str| {
+            WEEKDAYS
+                .iter()
+                .position(|day| name.trim().eq_ignore_ascii_case(day))
+                .ok_or_else(|| format!("'{name}' is not a day such as mon or sat"))
+        };
+        let mut parsed = [false; 7];
+        for part in days.split(',') {
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (day(first)?, day(last)?);
+                    // A range such as sat-mon wraps around the week
+                    let mut current = first;
+                    loop {
+                        parsed[current] = true;
+                        if current == last {
+                            break;
+                        }
+                        current = (current + 1) % 7;
+                    }
+                }
+                None => parsed[day(part)?] = true,
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Minutes since midnight of a `HH:MM` time
+    fn parse_time(time: &str) -> Result<u32, String> {
+        let parsed = time.split_once(':').and_then(|(hours, minutes)| {
+            if minutes.len() != 2 {
+                return None;
+            }
+            let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+            (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+        });
+        parsed.ok_or_else(|| format!("'{time}' is not a time such as 09:00"))
+    }
+
+    /// Seconds east of UTC of the window's times
+    fn parse_offset(&self) -> Result<i32, String> {
+        let Some(offset) = &self.utc_offset else {
+            return Ok(0);
+        };
+        let invalid = || format!("'{offset}' is not a UTC offset such as +05:00");
+        let (sign, rest) = match offset.split_at_checked(1) {
+            Some(("+", rest)) => (1, rest),
+            Some(("-", rest)) => (-1, rest),
+            _ => return Err(invalid()),
+        };
+        let minutes = Self::parse_time(rest).map_err(|_| invalid())?;
+        if minutes > 14 * 60 {
+            return Err(invalid());
+        }
+        Ok(sign * minutes as i32 * 60)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.parse_days()?;
+        Self::parse_time(&self.from)?;
+        Self::parse_time(&self.to)?;
+        self.parse_offset()?;
+        if self.instances > MAX_SCHEDULED_WARM_INSTANCES {
+            return Err(format!(
+                "keepWarm instances must be at most {MAX_SCHEDULED_WARM_INSTANCES}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Instances to keep warm at `now`, if it falls in the window
+    pub fn instances_at(&self, now: chrono::DateTime<chrono::Utc>) -> Option<u32> {
+        use chrono::{Datelike, Timelike};
+        let days = self.parse_days().ok()?;
+        let (from, to) = (
+            Self::parse_time(&self.from).ok()?,
+            Self::parse_time(&self.to).ok()?,
+        );
+        let offset = chrono::FixedOffset::east_opt(self.parse_offset().ok()?)?;
+        let local = now.with_timezone(&offset);
+        let today = local.weekday().num_days_from_monday() as usize;
+        let yesterday = (today + 6) % 7;
+        let minute = local.hour() * 60 + local.minute();
+
+        let active = if from < to {
+            days[today] && (from..to).contains(&minute)
+        } else {
+            // The window runs past midnight into the next day
+            (days[today] && minute >= from) || (days[yesterday] && minute < to)
+        };
+        active.then_some(self.instances)
+    }
+}
+
+/// Limits on each request a function handles. Unset limits are the server's.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FunctionLimits {
+    /// Longest a request may run, in milliseconds
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Most linear memory an instance may grow to, in MiB
+    #[serde(default)]
+    pub memory_mb: Option<u32>,
+    /// Longest a response body may stream, in seconds, after which it is ended
+    #[serde(default)]
+    pub max_stream_secs: Option<u64>,
+}
+
+impl FunctionDefinition {
+    /// Check what can be checked without the server: the kind, the routes, the
+    /// environment, the limits and the keep-warm schedule
+    pub fn validate(&self) -> Result<(), String> {
+        if self.api_version != FUNCTION_API_VERSION || self.kind != FUNCTION_KIND {
+            return Err(format!(
+                "Expected apiVersion '{FUNCTION_API_VERSION}' and kind '{FUNCTION_KIND}', got '{}' and '{}'",
+                self.api_version, self.kind
+            ));
+        }
+        let spec = &self.spec;
+        for key in spec.env.keys() {
+            if key.is_empty() || key.contains('=') || key.contains('\0') {
+                return Err(format!("'{key}' is not a valid environment variable name"));
+            }
+            if key == "FUNCTION_NAME" {
+                return Err("FUNCTION_NAME is set by the server".to_string());
+            }
+        }
+        if let Some(route) = spec.routes.iter().find(|route| !route.starts_with('/')) {
+            return Err(format!("Route '{route}' must start with '/'"));
+        }
+        if let Some(timeout) = spec.limits.timeout_ms {
+            if timeout == 0 || timeout > MAX_FUNCTION_TIMEOUT_MS {
+                return Err(format!(
+                    "timeoutMs must be between 1 and {MAX_FUNCTION_TIMEOUT_MS}"
+                ));
+            }
+        }
+        if let Some(memory) = spec.limits.memory_mb {
+            if memory == 0 || memory > MAX_FUNCTION_MEMORY_MB {
+                return Err(format!(
+                    "memoryMb must be between 1 and {MAX_FUNCTION_MEMORY_MB}"
+                ));
+            }
+        }
+        if let Some(stream) = spec.limits.max_stream_secs {
+            if stream == 0 || stream > MAX_FUNCTION_STREAM_SECS {
+                return Err(format!(
+                    "maxStreamSecs must be between 1 and {MAX_FUNCTION_STREAM_SECS}"
+                ));
+            }
+        }
+        for schedule in &spec.keep_warm {
+            schedule.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Directory of a project uploaded as its function's static assets
+pub const STATIC_ASSETS_DIR: &str = "static";
+/// Path prefix under which a function's static assets are served
+pub const STATIC_PATH_PREFIX: &str = "/static/";
+/// Most files a function's static assets may have
+pub const MAX_STATIC_ASSETS: usize = 10_000;
+
+/// A file served by the platform for a function, under [`STATIC_PATH_PREFIX`]
+#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
+pub struct StaticAsset {
+    /// Path relative to the static directory, with `/` separators
+    pub path: String,
+    pub content: Vec<u8>,
+}
+
+impl StaticAsset {
+    /// Encode `assets` for upload with `publish_static_assets`
+    pub fn encode_bundle(assets: &[StaticAsset]) -> Vec<u8> {
+        bincode::encode_to_vec(assets, bincode::config::standard())
+            .expect("static assets always encode")
+    }
+
+    /// Decode assets uploaded with `publish_static_assets`
+    pub fn decode_bundle(bundle: &[u8]) -> Result<Vec<StaticAsset>, String> {
+        bincode::decode_from_slice(bundle, bincode::config::standard())
+            .map(|(assets, _)| assets)
+            .map_err(|e| format!("invalid static asset bundle: {e}"))
+    }
+}
+
+/// Check that `path` is a relative path to a static asset, such as `css/site.css`
+pub fn validate_static_path(path: &str) -> Result<(), String> {
+    if path.is_empty() || path.len() > 1024 {
+        return Err(format!("invalid static asset path '{path}'"));
+    }
+    if path.contains('\\') || path.chars().any(char::is_control) {
+        return Err(format!(
+            "static asset path '{path}' must use '/' and no control characters"
+        ));
+    }
+    if path
+        .split('/')
+        .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+    {
+        return Err(format!(
+            "static asset path '{path}' must be relative, without '.' or '..'"
+        ));
+    }
+    Ok(())
+}
+
+/// Most origins, methods or headers a CORS policy may list each
+pub const MAX_CORS_ENTRIES: usize = 100;
+
+/// Cross-origin requests a function accepts from browsers, the `[cors]` section of
+/// a project's `faasta.toml`. The server answers preflight requests and adds the
+/// CORS headers to the function's responses.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CorsPolicy {
+    /// Origins allowed, such as `https://app.example.com`, `https://*.example.com`
+    /// for any subdomain, or `*` for any origin
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed beyond `GET`, `HEAD` and `POST`, which always are
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed beyond the CORS-safelisted ones, or `*` for any
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+    /// Response headers scripts may read beyond the CORS-safelisted ones
+    #[serde(default)]
+    pub expose_headers: Vec<String>,
+    /// Whether requests may carry cookies and credentials
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// Seconds browsers may cache a preflight's result
+    #[serde(default)]
+    pub max_age: Option<u32>,
+}
+
+impl CorsPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        for list in [
+            &self.allowed_origins,
+            &self.allowed_methods,
+            &self.allowed_headers,
+            &self.expose_headers,
+        ] {
+            if list.len() > MAX_CORS_ENTRIES {
+                return Err(format!(
+                    "a CORS policy may list at most {MAX_CORS_ENTRIES} of each setting"
+                ));
+            }
+        }
+        for origin in &self.allowed_origins {
+            if origin == "*" {
+                if self.allow_credentials {
+                    return Err("origin '*' can't be allowed with credentials".to_string());
+                }
+                continue;
+            }
+            let host = origin
+                .strip_prefix("https://")
+                .or_else(|| origin.strip_prefix("http://"))
+                .ok_or_else(|| format!("origin '{origin}' must start with https:// or http://"))?;
+            let host = host.strip_prefix("*.").unwrap_or(host);
+            let valid = !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'));
+            if !valid {
+                return Err(format!(
+                    "origin '{origin}' must be a scheme and host, like https://app.example.com"
+                ));
+            }
+        }
+        let token = |value: &String| {
+            !value.is_empty()
+                && value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+        };
+        for value in self
+            .allowed_methods
+            .iter()
+            .chain(&self.allowed_headers)
+            .chain(&self.expose_headers)
+        {
+            if !token(value) {
+                return Err(format!(
+                    "'{value}' is not a valid HTTP method or header name"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Header carrying the access key of a private function. The key can also be sent
+/// as the password of HTTP Basic authentication.
+pub const ACCESS_KEY_HEADER: &str = "x-faasta-access-key";
+
+/// Requests a function accepts only with a valid Bearer JWT, the `[auth]` section
+/// of a project's `faasta.toml`. The server checks the token's signature against the
+/// issuer's JWKS, and its issuer, audience and expiry, before invoking the function.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct JwtAuthPolicy {
+    /// Issuer tokens must name in `iss`, such as `https://accounts.example.com`
+    pub issuer: String,
+    /// JWKS holding the issuer's keys; discovered from the issuer's OpenID
+    /// configuration if unset
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+    /// Audiences of which tokens must name one in `aud`; any if empty
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// Path prefixes served without a token, such as `/health`
+    #[serde(default)]
+    pub public_routes: Vec<String>,
+}
+
+impl JwtAuthPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        for url in std::iter::once(&self.issuer).chain(&self.jwks_url) {
+            if !url.starts_with("https://") || url.len() > 2048 {
+                return Err(format!("'{url}' must be an https:// URL"));
+            }
+        }
+        if let Some(route) = self
+            .public_routes
+            .iter()
+            .find(|route| !route.starts_with('/'))
+        {
+            return Err(format!(
+                "public route '{route}' must be a path like /health"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether requests for `path` are served without a token. Both `path` and its
+    /// normalized form must be under a public route, so `/health/../admin` and
+    /// `/health%2F..%2Fadmin` are not, however the function reads them.
+    pub fn is_public(&self, path: &str) -> bool {
+        let under_public_route = |path: &str| {
+            self.public_routes.iter().any(|route| {
+                let route = route.trim_end_matches('/');
+                path.strip_prefix(route)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+        };
+        under_public_route(path) && under_public_route(&normalize_path(path))
+    }
+}
+
+/// `path` percent-decoded, with empty and `.` segments dropped and `..` segments
+/// resolved
+fn normalize_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    let decoded = String::from_utf8_lossy(&decoded).replace('\\', "/");
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+/// Most transformation rules a function may have
+pub const MAX_TRANSFORM_RULES: usize = 50;
+
+/// Headers transformation rules may not set or remove, as they frame the message
+const FRAMING_HEADERS: [&str; 5] = [
+    "content-length",
+    "transfer-encoding",
+    "connection",
+    "upgrade",
+    "te",
+];
+
+/// Changes to the requests and responses of a function under a route, a
+/// `[[transforms]]` entry of a project's `faasta.toml`. Request changes are made
+/// before the function is invoked, response changes after it answered.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TransformRule {
+    /// Path prefix the rule applies under, such as `/legacy`; every path if unset
+    #[serde(default)]
+    pub route: Option<String>,
+    /// Headers set on the request, replacing any the client sent
+    #[serde(default)]
+    pub add_request_headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub remove_request_headers: Vec<String>,
+    /// `Host` the function sees instead of the client's
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Query parameters removed, by name, or ending in `*` for a prefix such as
+    /// `utm_*`
+    #[serde(default)]
+    pub strip_query: Vec<String>,
+    /// Headers set on the response, replacing any the function sent
+    #[serde(default)]
+    pub add_response_headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub remove_response_headers: Vec<String>,
+    /// Statuses of the function's responses replaced by others
+    #[serde(default)]
+    pub map_status: Vec<StatusMapping>,
+}
+
+/// A response status replaced by another
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(deny_unknown_fields)]
+pub struct StatusMapping {
+    pub from: u16,
+    pub to: u16,
+}
+
+impl TransformRule {
+    /// Whether the rule applies to requests for `path`
+    pub fn matches(&self, path: &str) -> bool {
+        let Some(route) = &self.route else {
+            return true;
+        };
+        let route = route.trim_end_matches('/');
+        path.strip_prefix(route)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(route) = &self.route {
+            if !route.starts_with('/') || route.chars().any(|c| c.is_control() || c == '?') {
+                return Err(format!(
+                    "transform route '{route}' must be a path like /api"
+                ));
+            }
+        }
+        let header_name = |name: &String| {
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+            if !valid {
+                return Err(format!("'{name}' is not a valid header name"));
+            }
+            if FRAMING_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                return Err(format!("header '{name}' can't be transformed"));
+            }
+            Ok(())
+        };
+        let header_value = |value: &String| {
+            if value.chars().any(|c| c.is_control() && c != '\t') {
+                return Err(format!("header value '{value}' has control characters"));
+            }
+            Ok(())
+        };
+        for (name, value) in self
+            .add_request_headers
+            .iter()
+            .chain(&self.add_response_headers)
+        {
+            header_name(name)?;
+            header_value(value)?;
+        }
+        for name in self
+            .remove_request_headers
+            .iter()
+            .chain(&self.remove_response_headers)
+        {
+            header_name(name)?;
+        }
+        if let Some(host) = &self.host {
+            let valid = !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':'));
+            if !valid {
+                return Err(format!("'{host}' is not a valid host"));
+            }
+        }
+        if let Some(param) = self
+            .strip_query
+            .iter()
+            .find(|param| param.is_empty() || param.contains(['&', '=']))
+        {
+            return Err(format!("'{param}' is not a query parameter name"));
+        }
+        for mapping in &self.map_status {
+            if !(100..=599).contains(&mapping.from) || !(200..=599).contains(&mapping.to) {
+                return Err(format!(
+                    "can't map status {} to {}: statuses are 100-599, and 200-599 to map to",
+                    mapping.from, mapping.to
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What applying a definition changed, or would change on a dry run
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ApplyOutcome {
+    /// One line per changed part of the function; empty when it already matched
+    pub changes: Vec<String>,
+    /// Digest of the WebAssembly the function runs afterwards, when known
+    pub digest: Option<String>,
+}
+
+/// Version of the RPC protocol, raised whenever a change to `FunctionService` or
+/// the types it carries would keep an older client or server from decoding it
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// `ServerInfo::limits` key of the largest artifact the server accepts, in bytes
+pub const LIMIT_ARTIFACT_BYTES: &str = "artifact-bytes";
+/// `ServerInfo::limits` key of the most route handlers a function may have
+pub const LIMIT_ROUTE_HANDLERS: &str = "route-handlers";
+/// `ServerInfo::limits` key of the most static assets a function may have
+pub const LIMIT_STATIC_ASSETS: &str = "static-assets";
+/// `ServerInfo::limits` key of the most bytes a function's static assets may take up
+pub const LIMIT_STATIC_ASSET_BYTES: &str = "static-asset-bytes";
+/// `ServerInfo::limits` key of the most transformation rules a function may have
+pub const LIMIT_TRANSFORM_RULES: &str = "transform-rules";
+/// `ServerInfo::limits` key of the most tags a function may have
+pub const LIMIT_FUNCTION_TAGS: &str = "function-tags";
+/// `ServerInfo::limits` key of the most aliases and redirects a function may have
+pub const LIMIT_FUNCTION_ALIASES: &str = "function-aliases";
+
+/// What a server tells clients about itself before anything else. Its shape never
+/// changes, so clients of any version can read it: new limits and features are
+/// new keys.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServerInfo {
+    /// `PROTOCOL_VERSION` of the server
+    pub protocol_version: u32,
+    /// Release of the server, such as `0.4.0`
+    pub server_version: String,
+    /// Limits the server enforces, by `LIMIT_*` key
+    pub limits: BTreeMap<String, u64>,
+    /// Optional features enabled on the server, such as `trash` or `cluster`
+    pub features: Vec<String>,
+}
+
+impl ServerInfo {
+    /// The limit named `key`, if the server reports it
+    pub fn limit(&self, key: &str) -> Option<u64> {
+        self.limits.get(key).copied()
+    }
+
+    /// Whether `feature` is enabled on the server
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// Service interface for managing functions
+#[tarpc::service]
+pub trait FunctionService {
+    /// Protocol version, limits and features of the server, which clients ask for
+    /// first. Stays the first method, so it's decoded the same by every version.
+    async fn server_info() -> ServerInfo;
+
+    /// Publish a new function
+    async fn publish(
+        wasm_file: Vec<u8>,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// A page of the functions of the authenticated user and their teams
+    async fn list_functions(
+        query: FunctionQuery,
+        github_auth_token: String,
+    ) -> FunctionResult<FunctionPage>;
+
+    /// Unpublish a function. Servers with a trash keep it restorable for a while.
+    async fn unpublish(name: String, github_auth_token: String) -> FunctionResult<()>;
+
+    /// Publish a function owned by `team` (requires the developer role)
+    async fn publish_to_team(
+        wasm_file: Vec<u8>,
+        name: String,
+        team: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// 1-based position of the caller's publish of `name` in the deploy queue, or
+    /// `None` once it is no longer waiting
+    async fn deploy_queue_position(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<u32>>;
+
+    /// Upload a canary version of an existing function and send `weight` percent of
+    /// its requests to it, replacing any previous canary
+    async fn publish_canary(
+        wasm_file: Vec<u8>,
+        name: String,
+        weight: u8,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Start uploading an artifact of `size` bytes, returning the upload's id. The
+    /// size is checked against the server's limit before anything is sent.
+    async fn begin_upload(size: u64, github_auth_token: String) -> FunctionResult<String>;
+
+    /// Send the next chunk (at most `UPLOAD_CHUNK_SIZE` bytes) of an upload
+    async fn upload_chunk(
+        upload_id: String,
+        chunk: Vec<u8>,
+        github_auth_token: String,
+    ) -> FunctionResult<()>;
+
+    /// Attach an in-toto/SLSA provenance document (a statement, or a DSSE envelope
+    /// holding one) to an upload. Its subject must match the artifact's digest when
+    /// the upload is published.
+    async fn attach_provenance(
+        upload_id: String,
+        document: Vec<u8>,
+        github_auth_token: String,
+    ) -> FunctionResult<()>;
+
+    /// Publish a finished upload as the function `name`. `keep_warm` marks the
+    /// function to be pre-instantiated at startup and after deploys, or clears the
+    /// mark; `None` leaves it as it is. `metadata` replaces the function's
+    /// description, tags and routes; `None` keeps them.
+    #[allow(clippy::too_many_arguments)]
+    async fn publish_upload(
+        upload_id: String,
+        name: String,
+        target: PublishTarget,
+        keep_warm: Option<bool>,
+        metadata: Option<FunctionMetadata>,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Replace the route handlers of the function `name` with the components of
+    /// finished uploads, all together, or remove them when `routes` is empty.
+    /// Requests under a handler's route go to it instead of the main component.
+    /// Deploys stage them with `stage_routes` instead, to swap them in with the
+    /// main component. Requires the developer role for the function.
+    async fn publish_routes(
+        name: String,
+        routes: Vec<RouteUpload>,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Replace the static assets of the function `name` with those in a finished
+    /// upload of a bincode-encoded `Vec<StaticAsset>`, or remove them when no
+    /// upload is given. Requires the developer role for the function.
+    async fn publish_static_assets(
+        name: String,
+        upload_id: Option<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Set the CORS policy of the function `name`, or remove it so the function
+    /// handles cross-origin requests itself. Requires the developer role for the
+    /// function.
+    async fn set_cors_policy(
+        name: String,
+        policy: Option<CorsPolicy>,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Require a valid Bearer JWT for requests to the function `name`, or remove the
+    /// requirement. Requires the developer role for the function.
+    async fn set_jwt_auth(
+        name: String,
+        policy: Option<JwtAuthPolicy>,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Replace the transformation rules of the function `name`, removing them when
+    /// `rules` is empty. Requires the developer role for the function.
+    async fn set_transforms(
+        name: String,
+        rules: Vec<TransformRule>,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Publish the component of the OCI artifact at `reference` (such as
+    /// `ghcr.io/user/fn:tag`) as the function `name`. The server pulls it from the
+    /// registry, which must be one the server allows.
+    #[allow(clippy::too_many_arguments)]
+    async fn publish_from_registry(
+        reference: String,
+        name: String,
+        target: PublishTarget,
+        credentials: Option<oci::RegistryCredentials>,
+        keep_warm: Option<bool>,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Change the weight of a function's canary, promote it or abort it
+    async fn update_canary(
+        name: String,
+        update: CanaryUpdate,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Rename a function, moving its artifacts, metadata and metrics.
+    /// The old URL redirects to the new one for `redirect_hours` (0 for no redirect).
+    async fn rename_function(
+        name: String,
+        new_name: String,
+        redirect_hours: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
 
-// Define a custom error type that can be serialized
-#[derive(Debug, Error, Serialize, Deserialize, Clone)]
-pub enum FunctionError {
-    #[error("Authentication error: {0}")]
-    AuthError(String),
+    /// Copy a function's live artifact into a new function owned by the caller.
+    /// With `with_data`, the function's per-function settings and data are copied too.
+    async fn clone_function(
+        name: String,
+        new_name: String,
+        with_data: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
 
-    #[error("Function not found: {0}")]
-    NotFound(String),
+    /// Restore a function from the trash
+    async fn restore(name: String, github_auth_token: String) -> FunctionResult<String>;
 
-    #[error("Permission denied: {0}")]
-    PermissionDenied(String),
+    /// Lines a function logged, filtered by `query`. Requires the viewer role for
+    /// the function.
+    async fn function_logs(
+        name: String,
+        query: LogQuery,
+        github_auth_token: String,
+    ) -> FunctionResult<LogPage>;
 
-    #[error("Invalid input: {0}")]
-    InvalidInput(String),
+    /// Provenance of the artifact a function runs, if it was published with one.
+    /// Requires the viewer role for the function.
+    async fn get_provenance(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<ProvenanceInfo>>;
 
-    #[error("Internal error: {0}")]
-    InternalError(String),
-}
+    /// The keys a function's responses are signed with. Requires the viewer role
+    /// for the function.
+    async fn get_signing_keys(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<SigningKeys>;
 
-// Type alias for Result with our custom error
-pub type FunctionResult<T> = std::result::Result<T, FunctionError>;
+    /// Turn response signing on or off. Turning it on generates a key unless the
+    /// function has one; `rotate` replaces an existing key. Turning it off deletes
+    /// the keys. Requires the developer role for the function.
+    async fn set_response_signing(
+        name: String,
+        enabled: bool,
+        rotate: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<SigningKeys>;
 
-// Define the data structures for our service
+    /// Webhooks a function queued, newest first, optionally only those in
+    /// `status`. Requires the viewer role for the function.
+    async fn list_webhook_deliveries(
+        name: String,
+        status: Option<DeliveryStatus>,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<WebhookDelivery>>;
 
-/// Represents a published function
-#[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode)]
-pub struct FunctionInfo {
-    /// Name of the function
-    pub name: String,
-    /// Owner's GitHub username
-    pub owner: String,
-    /// When the function was published
-    pub published_at: String,
-    /// Usage information
-    pub usage: String,
-}
+    /// Queue a delivered or failed webhook to be sent again from its first attempt.
+    /// Requires the developer role for the function.
+    async fn redeliver_webhook(
+        name: String,
+        id: String,
+        github_auth_token: String,
+    ) -> FunctionResult<WebhookDelivery>;
 
-/// Function metrics information
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct FunctionMetricsResponse {
-    /// Name of the function
-    pub function_name: String,
-    /// Total execution time in milliseconds
-    pub total_time_millis: u64,
-    /// Number of times the function was called
-    pub call_count: u64,
-    /// Last time the function was called (ISO 8601 format)
-    pub last_called: String,
-}
+    /// Whether a function is snapshotted when it traps, and its snapshots. Requires
+    /// the viewer role for the function.
+    async fn list_debug_snapshots(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<DebugSnapshots>;
 
-/// Overall metrics information
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Metrics {
-    /// Total execution time across all functions in milliseconds
-    pub total_time: u64,
-    /// Total number of function calls
-    pub total_calls: u64,
-    /// Metrics for individual functions
-    pub function_metrics: Vec<FunctionMetricsResponse>,
-}
+    /// Start or stop snapshotting a function's memory and request when it traps.
+    /// Stopping deletes the function's snapshots. Requires the developer role for it.
+    async fn set_debug_snapshots(
+        name: String,
+        enabled: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<DebugSnapshots>;
 
-/// Service interface for managing functions
-#[tarpc::service]
-pub trait FunctionService {
-    /// Publish a new function
-    async fn publish(
-        wasm_file: Vec<u8>,
+    /// A snapshot and the request it captured. Requires the developer role for the
+    /// function, as snapshots hold whatever the function had in memory.
+    async fn get_debug_snapshot(
         name: String,
+        id: String,
         github_auth_token: String,
-    ) -> FunctionResult<String>;
+    ) -> FunctionResult<DebugSnapshot>;
 
-    /// List all functions for the authenticated user
-    async fn list_functions(github_auth_token: String) -> FunctionResult<Vec<FunctionInfo>>;
+    /// Chunk `index` of a snapshot's core dump, empty past its end. Requires the
+    /// developer role for the function.
+    async fn read_core_dump(
+        name: String,
+        id: String,
+        index: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<u8>>;
 
-    /// Unpublish a function
-    async fn unpublish(name: String, github_auth_token: String) -> FunctionResult<()>;
+    /// How often a function's canary is profiled, and its profiles. Requires the
+    /// viewer role for the function.
+    async fn list_guest_profiles(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<GuestProfiles>;
+
+    /// Profile `sample_rate` (0 to 1) of the invocations of a function's canary.
+    /// A rate of 0 stops profiling and deletes the function's profiles. Requires the
+    /// developer role for it.
+    async fn set_guest_profiling(
+        name: String,
+        sample_rate: f64,
+        github_auth_token: String,
+    ) -> FunctionResult<GuestProfiles>;
+
+    /// Chunk `index` of one of a function's profiles, empty past its end. Requires
+    /// the viewer role for the function.
+    async fn read_guest_profile(
+        name: String,
+        id: String,
+        index: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<u8>>;
+
+    /// The level of lines stored from a function. Requires the viewer role for it.
+    async fn get_log_level(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<LogLevelSetting>;
+
+    /// Store a function's lines from `level` up, or with `None` go back to the
+    /// server's default. After `duration_secs` (0 for no limit) the level reverts to
+    /// the default. A level more verbose than the default always reverts: after an
+    /// hour if no duration is given, and at the latest after the server's limit.
+    /// Requires the developer role for the function.
+    async fn set_log_level(
+        name: String,
+        level: Option<LogLevel>,
+        duration_secs: u64,
+        github_auth_token: String,
+    ) -> FunctionResult<LogLevelSetting>;
+
+    /// The rules redacting a function's log lines. Requires the viewer role for it.
+    async fn get_log_redaction(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<RedactionSettings>;
+
+    /// Replace the redaction rules a function adds to those the server enforces,
+    /// which can't be removed. Applies to lines stored from then on. Requires the
+    /// developer role for the function.
+    async fn set_log_redaction(
+        name: String,
+        rules: RedactionRules,
+        github_auth_token: String,
+    ) -> FunctionResult<RedactionSettings>;
 
     /// Get metrics for all functions
     async fn get_metrics(github_auth_token: String) -> FunctionResult<Metrics>;
+
+    /// Invocation count, errors and latency percentiles of one function
+    async fn get_function_metrics(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<FunctionStats>;
+
+    /// Create a long-lived API key limited to `scopes`.
+    /// Requires a provider (GitHub) token; API keys cannot create other keys.
+    async fn create_api_key(
+        name: String,
+        scopes: Vec<ApiKeyScope>,
+        github_auth_token: String,
+    ) -> FunctionResult<NewApiKey>;
+
+    /// List the API keys belonging to the authenticated user
+    async fn list_api_keys(github_auth_token: String) -> FunctionResult<Vec<ApiKeyInfo>>;
+
+    /// Revoke one of the authenticated user's API keys
+    async fn revoke_api_key(id: String, github_auth_token: String) -> FunctionResult<()>;
+
+    /// List the logins and API keys used with the authenticated account
+    async fn list_sessions(github_auth_token: String) -> FunctionResult<Vec<SessionInfo>>;
+
+    /// Revoke a session by id. A revoked login is rejected by this server even
+    /// while the provider still accepts its token.
+    async fn revoke_session(id: String, github_auth_token: String) -> FunctionResult<()>;
+
+    /// Name the device the current login is used from
+    async fn label_session(label: String, github_auth_token: String) -> FunctionResult<()>;
+
+    /// Unresolved alerts about the caller's account, functions and teams, or with
+    /// `all` (admin only) the whole review queue
+    async fn list_alerts(all: bool, github_auth_token: String)
+        -> FunctionResult<Vec<AnomalyAlert>>;
+
+    /// Mark an alert as reviewed, removing it from the queue
+    async fn resolve_alert(id: u64, github_auth_token: String) -> FunctionResult<()>;
+
+    /// Grant `role` to a user or team (`team:<name>`), or clear their grant with `None`.
+    /// Admin only.
+    async fn set_role(
+        principal: String,
+        role: Option<PlatformRole>,
+        github_auth_token: String,
+    ) -> FunctionResult<()>;
+
+    /// List granted roles. Admin only.
+    async fn list_roles(github_auth_token: String) -> FunctionResult<Vec<RoleGrant>>;
+
+    /// Suspend an account: it can no longer authenticate and its functions stop
+    /// being served. Admin only.
+    async fn suspend_user(
+        username: String,
+        reason: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()>;
+
+    /// Lift an account's suspension. Admin only.
+    async fn unsuspend_user(username: String, github_auth_token: String) -> FunctionResult<()>;
+
+    /// Stop invoking a function at once: its requests are answered 503 until its
+    /// breaker is reset. Admin only.
+    async fn kill_function(
+        name: String,
+        reason: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()>;
+
+    /// Whether a function was killed or its circuit breaker tripped. Requires the
+    /// viewer role for the function.
+    async fn get_circuit_breaker(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<CircuitBreaker>>;
+
+    /// Invoke a function again after its breaker tripped. Requires the developer
+    /// role for the function; a killed function is reset by admins only.
+    async fn reset_circuit_breaker(name: String, github_auth_token: String) -> FunctionResult<()>;
+
+    /// Make the function `name` private with a new access key, replacing any
+    /// previous one, or public again when `private` is false. Returns the new key,
+    /// of which the server only keeps a hash. Requires the developer role for the
+    /// function.
+    async fn protect_function(
+        name: String,
+        private: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<String>>;
+
+    /// Up to `limit` journal events with ids above `after`, oldest first, that are at
+    /// least `min_severity` and of `kind` if given. Admin only.
+    async fn server_events(
+        after: Option<u64>,
+        limit: u32,
+        min_severity: EventSeverity,
+        kind: Option<ServerEventKind>,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<ServerEvent>>;
+
+    /// The report of the last consistency check, if one ran. Admin only.
+    async fn consistency_report(
+        github_auth_token: String,
+    ) -> FunctionResult<Option<ConsistencyReport>>;
+
+    /// Cross-check the server's records now, repairing the drift of the kinds in
+    /// `repair` that can be. Admin only.
+    async fn check_consistency(
+        repair: Vec<DriftKind>,
+        github_auth_token: String,
+    ) -> FunctionResult<ConsistencyReport>;
+
+    /// Collect garbage now, or with `dry_run` only report what would be removed.
+    /// Admin only.
+    async fn collect_garbage(
+        dry_run: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<GarbageReport>;
+
+    /// The latest capacity report, made now if `refresh` is set or none was made
+    /// yet. Admin only.
+    async fn capacity_report(
+        refresh: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<CapacityReport>;
+
+    /// Take a consistent backup of the database and function artifacts, kept on the
+    /// server until newer ones replace it. Admin only.
+    async fn create_backup(github_auth_token: String) -> FunctionResult<BackupInfo>;
+
+    /// Chunk `index` of the backup `name`, empty past its end. Admin only.
+    async fn read_backup(
+        name: String,
+        index: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<u8>>;
+
+    /// Nodes of the cluster this server belongs to, itself included. Admin only.
+    async fn list_cluster_nodes(github_auth_token: String) -> FunctionResult<Vec<ClusterNode>>;
+
+    /// Up to `limit` audit log entries with ids above `after`, oldest first. Admin only.
+    async fn audit_log(
+        after: Option<u64>,
+        limit: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<AuditEvent>>;
+
+    /// Permanently delete any user's function, skipping the trash. Admin only.
+    async fn force_delete_function(name: String, github_auth_token: String) -> FunctionResult<()>;
+
+    /// Override how many functions a user may own, or reset it to the server
+    /// default with `None`. Admin only.
+    async fn set_project_limit(
+        username: String,
+        limit: Option<u32>,
+        github_auth_token: String,
+    ) -> FunctionResult<()>;
+
+    /// Every account's usage in `period` (`YYYY-MM`, the current one if `None`),
+    /// rendered as `format`. Admin only.
+    async fn export_usage(
+        period: Option<String>,
+        format: UsageFormat,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Create a team with the caller as its owner, optionally linked to a GitHub organization
+    async fn create_team(
+        name: String,
+        github_org: Option<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<TeamInfo>;
+
+    /// List the teams the caller belongs to
+    async fn list_teams(github_auth_token: String) -> FunctionResult<Vec<TeamInfo>>;
+
+    /// Add a member to a team, or change their role
+    async fn set_team_member(
+        team: String,
+        username: String,
+        role: TeamRole,
+        github_auth_token: String,
+    ) -> FunctionResult<TeamInfo>;
+
+    /// Remove a member from a team
+    async fn remove_team_member(
+        team: String,
+        username: String,
+        github_auth_token: String,
+    ) -> FunctionResult<TeamInfo>;
+
+    /// Delete a team that no longer owns any functions
+    async fn delete_team(team: String, github_auth_token: String) -> FunctionResult<()>;
+
+    /// Reconcile a function to `definition`: pull and publish its artifact when the
+    /// reference changed or the function runs something else, then store its
+    /// environment, routes and limits. `credentials` are passed on to the registry.
+    /// With `dry_run`, only report the changes.
+    async fn apply(
+        definition: FunctionDefinition,
+        credentials: Option<oci::RegistryCredentials>,
+        dry_run: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<ApplyOutcome>;
+
+    /// Start uploading an artifact of `size` bytes to publish as the function
+    /// `name`, returning the chunks of its deployed artifact the upload may reuse
+    async fn begin_delta_upload(
+        size: u64,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<DeltaUpload>;
+
+    /// Append chunks of the deployed artifact a delta upload started from, by
+    /// digest, as if their bytes were sent with `upload_chunk`
+    async fn upload_known_chunks(
+        upload_id: String,
+        digests: Vec<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<()>;
+
+    /// Offer the function `name` to the user `new_owner`, who takes it over with
+    /// `accept_transfer` within a week. Requires the owner role for the function;
+    /// platform admins move it at once.
+    async fn transfer_function(
+        name: String,
+        new_owner: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Take over the function `name`, offered to the caller with `transfer_function`
+    async fn accept_transfer(name: String, github_auth_token: String) -> FunctionResult<String>;
+
+    /// Make `alias.name` another name of `alias.target`, or change what an alias of
+    /// it does. The name must not be taken by a function or another function's
+    /// alias. Requires the developer role for the target.
+    async fn set_alias(alias: FunctionAlias, github_auth_token: String) -> FunctionResult<String>;
+
+    /// Remove the alias `name`, freeing the name. Requires the developer role for
+    /// the function it leads to.
+    async fn remove_alias(name: String, github_auth_token: String) -> FunctionResult<String>;
+
+    /// The aliases and redirects of the function `name`
+    async fn list_aliases(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<FunctionAlias>>;
+
+    /// Deploy, artifact, routing, warmth, errors and quota usage of one function,
+    /// for quick debugging
+    async fn function_status(
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<FunctionStatus>;
+
+    /// Check and store the components of finished uploads as the route handlers of
+    /// the function `name`, to be swapped in by the caller's next publish of it, in
+    /// one step with its main component. Staging again replaces them, and an
+    /// empty `routes` stages removing them. Requires the developer role for an
+    /// existing function.
+    async fn stage_routes(
+        name: String,
+        routes: Vec<RouteUpload>,
+        github_auth_token: String,
+    ) -> FunctionResult<String>;
+
+    /// Like `upload_chunk`, for the chunk starting at byte `offset` of the upload.
+    /// A chunk sent again after it was stored is skipped, so a call whose reply
+    /// was lost can be retried.
+    async fn upload_chunk_at(
+        upload_id: String,
+        offset: u64,
+        chunk: Vec<u8>,
+        github_auth_token: String,
+    ) -> FunctionResult<()>;
+
+    /// Like `upload_known_chunks`, for chunks starting at byte `offset` of the
+    /// upload, which may be retried the same way as `upload_chunk_at`
+    async fn upload_known_chunks_at(
+        upload_id: String,
+        offset: u64,
+        digests: Vec<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<()>;
 }
 
 /// Type alias for the auth validator function type
@@ -144,33 +2863,72 @@ impl FunctionServiceImpl {
         // For now, we'll assume the token is in the format "username:token"
         let parts: Vec<&str> = token.split(':').collect();
         if parts.len() != 2 {
-            return Err(FunctionError::AuthError("Invalid token format".to_string()));
+            return Err(FaastaError::AuthFailed("Invalid token format".to_string()));
         }
         Ok(parts[0].to_string())
     }
-}
 
-impl FunctionService for FunctionServiceImpl {
-    async fn publish(
-        self,
-        _: tarpc::context::Context,
-        wasm_file: Vec<u8>,
-        name: String,
-        github_auth_token: String,
-    ) -> FunctionResult<String> {
+    /// Username of a valid `token`
+    async fn authenticate(&self, token: &str) -> FunctionResult<String> {
         // Extract username from token
-        let username = self.get_username_from_token(&github_auth_token).await?;
+        let username = self.get_username_from_token(token).await?;
 
         // Validate token
         if !self
-            .validate_auth(&username, &github_auth_token)
+            .validate_auth(&username, token)
             .await
-            .map_err(|e| FunctionError::AuthError(e.to_string()))?
+            .map_err(|e| FaastaError::AuthFailed(e.to_string()))?
         {
-            return Err(FunctionError::AuthError(
+            return Err(FaastaError::AuthFailed(
                 "Invalid GitHub authentication token".to_string(),
             ));
         }
+        Ok(username)
+    }
+}
+
+/// Error of the calls this in-memory service doesn't implement
+fn unsupported(method: &str) -> FaastaError {
+    FaastaError::InvalidInput(format!("{method} is not supported by this service"))
+}
+
+/// Error of a failed write to the functions directory
+fn io_error(e: std::io::Error) -> FaastaError {
+    FaastaError::Internal {
+        id: e.kind().to_string(),
+    }
+}
+
+/// Implement the calls of `FunctionService` this service doesn't support
+macro_rules! unsupported_calls {
+    ($($method:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;)*) => {
+        $(
+            #[allow(clippy::too_many_arguments)]
+            async fn $method(self, _: tarpc::context::Context, $(_: $ty),*) -> $ret {
+                Err(unsupported(stringify!($method)))
+            }
+        )*
+    };
+}
+
+impl FunctionService for FunctionServiceImpl {
+    async fn server_info(self, _: tarpc::context::Context) -> ServerInfo {
+        ServerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            limits: BTreeMap::from([(LIMIT_ARTIFACT_BYTES.to_string(), MAX_WASM_SIZE as u64)]),
+            features: Vec::new(),
+        }
+    }
+
+    async fn publish(
+        self,
+        _: tarpc::context::Context,
+        wasm_file: Vec<u8>,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = self.authenticate(&github_auth_token).await?;
 
         // Check if function name is valid
         if name.is_empty()
@@ -178,16 +2936,14 @@ impl FunctionService for FunctionServiceImpl {
                 .chars()
                 .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
         {
-            return Err(FunctionError::InvalidInput(
+            return Err(FaastaError::InvalidInput(
                 "Invalid function name. Use only alphanumeric characters, underscores, and hyphens.".to_string()
             ));
         }
-
         // Check if function already exists
-        if self.functions_db.contains_key(&name) {
-            let existing = self.functions_db.get(&name).unwrap();
+        if let Some(existing) = self.functions_db.get(&name) {
             if existing.owner != username {
-                return Err(FunctionError::PermissionDenied(
+                return Err(FaastaError::PermissionDenied(
                     "A function with this name already exists and belongs to another user"
                         .to_string(),
                 ));
@@ -196,10 +2952,8 @@ impl FunctionService for FunctionServiceImpl {
 
         // Save the WASM file
         let wasm_path = self.functions_dir.join(format!("{name}.wasm"));
-        let mut file = fs::File::create(&wasm_path)
-            .map_err(|e| FunctionError::InternalError(format!("Failed to create file: {e}")))?;
-        file.write_all(&wasm_file)
-            .map_err(|e| FunctionError::InternalError(format!("Failed to write file: {e}")))?;
+        let mut file = fs::File::create(&wasm_path).map_err(io_error)?;
+        file.write_all(&wasm_file).map_err(io_error)?;
 
         // Create function info
         let now = chrono::Utc::now().to_rfc3339();
@@ -208,11 +2962,11 @@ impl FunctionService for FunctionServiceImpl {
             owner: username,
             published_at: now,
             usage: format!("https://faasta.xyz/{name}"),
+            metadata: FunctionMetadata::default(),
         };
 
         // Save function metadata
-        self.functions_db
-            .insert(name.clone(), function_info.clone());
+        self.functions_db.insert(name.clone(), function_info);
 
         // TODO: Save metadata to a file or database
 
@@ -222,21 +2976,10 @@ impl FunctionService for FunctionServiceImpl {
     async fn list_functions(
         self,
         _: tarpc::context::Context,
+        query: FunctionQuery,
         github_auth_token: String,
-    ) -> FunctionResult<Vec<FunctionInfo>> {
-        // Extract username from token
-        let username = self.get_username_from_token(&github_auth_token).await?;
-
-        // Validate token
-        if !self
-            .validate_auth(&username, &github_auth_token)
-            .await
-            .map_err(|e| FunctionError::AuthError(e.to_string()))?
-        {
-            return Err(FunctionError::AuthError(
-                "Invalid GitHub authentication token".to_string(),
-            ));
-        }
+    ) -> FunctionResult<FunctionPage> {
+        let username = self.authenticate(&github_auth_token).await?;
 
         // Filter functions by owner
         let user_functions: Vec<FunctionInfo> = self
@@ -245,8 +2988,23 @@ impl FunctionService for FunctionServiceImpl {
             .filter(|entry| entry.owner == username)
             .map(|entry| entry.clone())
             .collect();
+        let total = user_functions.len() as u64;
+        let functions: Vec<FunctionInfo> = user_functions
+            .into_iter()
+            .filter(|info| {
+                query
+                    .name_filter
+                    .as_ref()
+                    .is_none_or(|filter| info.name.to_lowercase().contains(&filter.to_lowercase()))
+            })
+            .collect();
 
-        Ok(user_functions)
+        Ok(FunctionPage {
+            matching: functions.len() as u64,
+            functions,
+            page: 1,
+            total,
+        })
     }
 
     async fn unpublish(
@@ -255,48 +3013,37 @@ impl FunctionService for FunctionServiceImpl {
         name: String,
         github_auth_token: String,
     ) -> FunctionResult<()> {
-        // Extract username from token
-        let username = self.get_username_from_token(&github_auth_token).await?;
+        let username = self.authenticate(&github_auth_token).await?;
 
-        // Validate token
-        if !self
-            .validate_auth(&username, &github_auth_token)
-            .await
-            .map_err(|e| FunctionError::AuthError(e.to_string()))?
-        {
-            return Err(FunctionError::AuthError(
-                "Invalid GitHub authentication token".to_string(),
+        // Check if function exists
+        let Some(owner) = self
+            .functions_db
+            .get(&name)
+            .map(|entry| entry.owner.clone())
+        else {
+            return Err(FaastaError::NotFound(format!(
+                "Function '{name}' not found"
+            )));
+        };
+        // Check if user owns the function
+        if owner != username {
+            return Err(FaastaError::PermissionDenied(
+                "You don't have permission to unpublish this function".to_string(),
             ));
         }
 
-        // Check if function exists
-        if let Some(entry) = self.functions_db.get(&name) {
-            // Check if user owns the function
-            if entry.owner != username {
-                return Err(FunctionError::PermissionDenied(
-                    "You don't have permission to unpublish this function".to_string(),
-                ));
-            }
-
-            // Remove function from database
-            self.functions_db.remove(&name);
+        // Remove function from database
+        self.functions_db.remove(&name);
 
-            // Remove WASM file
-            let wasm_path = self.functions_dir.join(format!("{name}.wasm"));
-            if wasm_path.exists() {
-                fs::remove_file(wasm_path).map_err(|e| {
-                    FunctionError::InternalError(format!("Failed to remove file: {e}"))
-                })?;
-            }
+        // Remove WASM file
+        let wasm_path = self.functions_dir.join(format!("{name}.wasm"));
+        if wasm_path.exists() {
+            fs::remove_file(wasm_path).map_err(io_error)?;
+        }
 
-            // TODO: Remove metadata file
+        // TODO: Remove metadata file
 
-            Ok(())
-        } else {
-            Err(FunctionError::NotFound(format!(
-                "Function '{name}' not found"
-            )))
-        }
+        Ok(())
     }
 
     async fn get_metrics(
@@ -304,19 +3051,7 @@ impl FunctionService for FunctionServiceImpl {
         _: tarpc::context::Context,
         github_auth_token: String,
     ) -> FunctionResult<Metrics> {
-        // Extract username from token
-        let username = self.get_username_from_token(&github_auth_token).await?;
-
-        // Validate token
-        if !self
-            .validate_auth(&username, &github_auth_token)
-            .await
-            .map_err(|e| FunctionError::AuthError(e.to_string()))?
-        {
-            return Err(FunctionError::AuthError(
-                "Invalid GitHub authentication token".to_string(),
-            ));
-        }
+        self.authenticate(&github_auth_token).await?;
 
         // Collect metrics from all functions
         let mut function_metrics = Vec::new();
@@ -348,6 +3083,309 @@ impl FunctionService for FunctionServiceImpl {
             function_metrics,
         })
     }
+
+    unsupported_calls! {
+        publish_to_team(
+            wasm_file: Vec<u8>,
+            name: String,
+            team: String,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        deploy_queue_position(
+            name: String,
+            github_auth_token: String,
+        ) -> FunctionResult<Option<u32>>;
+        publish_canary(
+            wasm_file: Vec<u8>,
+            name: String,
+            weight: u8,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        begin_upload(size: u64, github_auth_token: String) -> FunctionResult<String>;
+        upload_chunk(
+            upload_id: String,
+            chunk: Vec<u8>,
+            github_auth_token: String,
+        ) -> FunctionResult<()>;
+        attach_provenance(
+            upload_id: String,
+            document: Vec<u8>,
+            github_auth_token: String,
+        ) -> FunctionResult<()>;
+        publish_upload(
+            upload_id: String,
+            name: String,
+            target: PublishTarget,
+            keep_warm: Option<bool>,
+            metadata: Option<FunctionMetadata>,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        publish_routes(
+            name: String,
+            routes: Vec<RouteUpload>,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        publish_static_assets(
+            name: String,
+            upload_id: Option<String>,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        set_cors_policy(
+            name: String,
+            policy: Option<CorsPolicy>,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        set_jwt_auth(
+            name: String,
+            policy: Option<JwtAuthPolicy>,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        set_transforms(
+            name: String,
+            rules: Vec<TransformRule>,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        publish_from_registry(
+            reference: String,
+            name: String,
+            target: PublishTarget,
+            credentials: Option<oci::RegistryCredentials>,
+            keep_warm: Option<bool>,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        update_canary(
+            name: String,
+            update: CanaryUpdate,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        rename_function(
+            name: String,
+            new_name: String,
+            redirect_hours: u32,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        clone_function(
+            name: String,
+            new_name: String,
+            with_data: bool,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        restore(name: String, github_auth_token: String) -> FunctionResult<String>;
+        function_logs(
+            name: String,
+            query: LogQuery,
+            github_auth_token: String,
+        ) -> FunctionResult<LogPage>;
+        get_provenance(
+            name: String,
+            github_auth_token: String,
+        ) -> FunctionResult<Option<ProvenanceInfo>>;
+        get_signing_keys(name: String, github_auth_token: String) -> FunctionResult<SigningKeys>;
+        set_response_signing(
+            name: String,
+            enabled: bool,
+            rotate: bool,
+            github_auth_token: String,
+        ) -> FunctionResult<SigningKeys>;
+        list_webhook_deliveries(
+            name: String,
+            status: Option<DeliveryStatus>,
+            github_auth_token: String,
+        ) -> FunctionResult<Vec<WebhookDelivery>>;
+        redeliver_webhook(
+            name: String,
+            id: String,
+            github_auth_token: String,
+        ) -> FunctionResult<WebhookDelivery>;
+        list_debug_snapshots(
+            name: String,
+            github_auth_token: String,
+        ) -> FunctionResult<DebugSnapshots>;
+        set_debug_snapshots(
+            name: String,
+            enabled: bool,
+            github_auth_token: String,
+        ) -> FunctionResult<DebugSnapshots>;
+        get_debug_snapshot(
+            name: String,
+            id: String,
+            github_auth_token: String,
+        ) -> FunctionResult<DebugSnapshot>;
+        read_core_dump(
+            name: String,
+            id: String,
+            index: u32,
+            github_auth_token: String,
+        ) -> FunctionResult<Vec<u8>>;
+        list_guest_profiles(
+            name: String,
+            github_auth_token: String,
+        ) -> FunctionResult<GuestProfiles>;
+        set_guest_profiling(
+            name: String,
+            sample_rate: f64,
+            github_auth_token: String,
+        ) -> FunctionResult<GuestProfiles>;
+        read_guest_profile(
+            name: String,
+            id: String,
+            index: u32,
+            github_auth_token: String,
+        ) -> FunctionResult<Vec<u8>>;
+        get_log_level(name: String, github_auth_token: String) -> FunctionResult<LogLevelSetting>;
+        set_log_level(
+            name: String,
+            level: Option<LogLevel>,
+            duration_secs: u64,
+            github_auth_token: String,
+        ) -> FunctionResult<LogLevelSetting>;
+        get_log_redaction(
+            name: String,
+            github_auth_token: String,
+        ) -> FunctionResult<RedactionSettings>;
+        set_log_redaction(
+            name: String,
+            rules: RedactionRules,
+            github_auth_token: String,
+        ) -> FunctionResult<RedactionSettings>;
+        get_function_metrics(
+            name: String,
+            github_auth_token: String,
+        ) -> FunctionResult<FunctionStats>;
+        create_api_key(
+            name: String,
+            scopes: Vec<ApiKeyScope>,
+            github_auth_token: String,
+        ) -> FunctionResult<NewApiKey>;
+        list_api_keys(github_auth_token: String) -> FunctionResult<Vec<ApiKeyInfo>>;
+        revoke_api_key(id: String, github_auth_token: String) -> FunctionResult<()>;
+        list_sessions(github_auth_token: String) -> FunctionResult<Vec<SessionInfo>>;
+        revoke_session(id: String, github_auth_token: String) -> FunctionResult<()>;
+        label_session(label: String, github_auth_token: String) -> FunctionResult<()>;
+        list_alerts(all: bool, github_auth_token: String) -> FunctionResult<Vec<AnomalyAlert>>;
+        resolve_alert(id: u64, github_auth_token: String) -> FunctionResult<()>;
+        set_role(
+            principal: String,
+            role: Option<PlatformRole>,
+            github_auth_token: String,
+        ) -> FunctionResult<()>;
+        list_roles(github_auth_token: String) -> FunctionResult<Vec<RoleGrant>>;
+        suspend_user(
+            username: String,
+            reason: String,
+            github_auth_token: String,
+        ) -> FunctionResult<()>;
+        unsuspend_user(username: String, github_auth_token: String) -> FunctionResult<()>;
+        kill_function(
+            name: String,
+            reason: String,
+            github_auth_token: String,
+        ) -> FunctionResult<()>;
+        get_circuit_breaker(
+            name: String,
+            github_auth_token: String,
+        ) -> FunctionResult<Option<CircuitBreaker>>;
+        reset_circuit_breaker(name: String, github_auth_token: String) -> FunctionResult<()>;
+        protect_function(
+            name: String,
+            private: bool,
+            github_auth_token: String,
+        ) -> FunctionResult<Option<String>>;
+        server_events(
+            after: Option<u64>,
+            limit: u32,
+            min_severity: EventSeverity,
+            kind: Option<ServerEventKind>,
+            github_auth_token: String,
+        ) -> FunctionResult<Vec<ServerEvent>>;
+        consistency_report(github_auth_token: String) -> FunctionResult<Option<ConsistencyReport>>;
+        check_consistency(
+            repair: Vec<DriftKind>,
+            github_auth_token: String,
+        ) -> FunctionResult<ConsistencyReport>;
+        collect_garbage(dry_run: bool, github_auth_token: String) -> FunctionResult<GarbageReport>;
+        capacity_report(refresh: bool, github_auth_token: String) -> FunctionResult<CapacityReport>;
+        create_backup(github_auth_token: String) -> FunctionResult<BackupInfo>;
+        read_backup(name: String, index: u32, github_auth_token: String) -> FunctionResult<Vec<u8>>;
+        list_cluster_nodes(github_auth_token: String) -> FunctionResult<Vec<ClusterNode>>;
+        audit_log(
+            after: Option<u64>,
+            limit: u32,
+            github_auth_token: String,
+        ) -> FunctionResult<Vec<AuditEvent>>;
+        force_delete_function(name: String, github_auth_token: String) -> FunctionResult<()>;
+        set_project_limit(
+            username: String,
+            limit: Option<u32>,
+            github_auth_token: String,
+        ) -> FunctionResult<()>;
+        export_usage(
+            period: Option<String>,
+            format: UsageFormat,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        create_team(
+            name: String,
+            github_org: Option<String>,
+            github_auth_token: String,
+        ) -> FunctionResult<TeamInfo>;
+        list_teams(github_auth_token: String) -> FunctionResult<Vec<TeamInfo>>;
+        set_team_member(
+            team: String,
+            username: String,
+            role: TeamRole,
+            github_auth_token: String,
+        ) -> FunctionResult<TeamInfo>;
+        remove_team_member(
+            team: String,
+            username: String,
+            github_auth_token: String,
+        ) -> FunctionResult<TeamInfo>;
+        delete_team(team: String, github_auth_token: String) -> FunctionResult<()>;
+        apply(
+            definition: FunctionDefinition,
+            credentials: Option<oci::RegistryCredentials>,
+            dry_run: bool,
+            github_auth_token: String,
+        ) -> FunctionResult<ApplyOutcome>;
+        begin_delta_upload(
+            size: u64,
+            name: String,
+            github_auth_token: String,
+        ) -> FunctionResult<DeltaUpload>;
+        upload_known_chunks(
+            upload_id: String,
+            digests: Vec<String>,
+            github_auth_token: String,
+        ) -> FunctionResult<()>;
+        transfer_function(
+            name: String,
+            new_owner: String,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        accept_transfer(name: String, github_auth_token: String) -> FunctionResult<String>;
+        set_alias(alias: FunctionAlias, github_auth_token: String) -> FunctionResult<String>;
+        remove_alias(name: String, github_auth_token: String) -> FunctionResult<String>;
+        list_aliases(name: String, github_auth_token: String) -> FunctionResult<Vec<FunctionAlias>>;
+        function_status(name: String, github_auth_token: String) -> FunctionResult<FunctionStatus>;
+        stage_routes(
+            name: String,
+            routes: Vec<RouteUpload>,
+            github_auth_token: String,
+        ) -> FunctionResult<String>;
+        upload_chunk_at(
+            upload_id: String,
+            offset: u64,
+            chunk: Vec<u8>,
+            github_auth_token: String,
+        ) -> FunctionResult<()>;
+        upload_known_chunks_at(
+            upload_id: String,
+            offset: u64,
+            digests: Vec<String>,
+            github_auth_token: String,
+        ) -> FunctionResult<()>;
+    }
 }
 
 /// Helper function to create a service implementation with GitHub auth
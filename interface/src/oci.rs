@@ -0,0 +1,316 @@
+//! Function components as OCI artifacts.
+//!
+//! Components are stored in OCI registries following the CNCF "Wasm OCI Artifact"
+//! layout: an image manifest whose config has the wasm config media type and whose
+//! single layer is the component itself. The CLI pushes artifacts and the server
+//! pulls them; both share the reference syntax, the manifest types and the registry
+//! authentication defined here.
+
+use anyhow::{anyhow, bail, Result};
+use reqwest::header::WWW_AUTHENTICATE;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+pub const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const CONFIG_MEDIA_TYPE: &str = "application/vnd.wasm.config.v0+json";
+pub const LAYER_MEDIA_TYPE: &str = "application/wasm";
+
+/// Registry used when a reference names none, as Docker does
+const DEFAULT_REGISTRY: &str = "docker.io";
+
+/// A reference such as `ghcr.io/user/fn:tag` or `ghcr.io/user/fn@sha256:...`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OciReference {
+    /// Registry host, with a port if it has one
+    pub registry: String,
+    pub repository: String,
+    /// Tag or digest of the manifest
+    pub reference: String,
+}
+
+impl OciReference {
+    /// Base URL of the registry's distribution API
+    pub fn api_base(&self) -> String {
+        let host = match self.registry.as_str() {
+            DEFAULT_REGISTRY => "registry-1.docker.io",
+            host => host,
+        };
+        // Local registries usually don't serve TLS
+        let name = host.rsplit_once(':').map_or(host, |(name, _)| name);
+        let scheme = if matches!(name, "localhost" | "127.0.0.1") {
+            "http"
+        } else {
+            "https"
+        };
+        format!("{scheme}://{host}/v2/{}", self.repository)
+    }
+
+    /// Whether the reference pins a digest rather than a tag
+    pub fn is_digest(&self) -> bool {
+        self.reference.contains(':')
+    }
+}
+
+impl fmt::Display for OciReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.is_digest() { '@' } else { ':' };
+        write!(
+            f,
+            "{}/{}{separator}{}",
+            self.registry, self.repository, self.reference
+        )
+    }
+}
+
+impl FromStr for OciReference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_start_matches("oci://");
+        let (name, reference) = match s.split_once('@') {
+            Some((name, digest)) => (name, digest.to_string()),
+            None => match s.rsplit_once(':') {
+                // A colon before the last slash belongs to the registry's port
+                Some((name, tag)) if !tag.contains('/') => (name, tag.to_string()),
+                _ => (s, "latest".to_string()),
+            },
+        };
+
+        // The first component is a registry if it looks like a host name
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, rest))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host.to_string(), rest.to_string())
+            }
+            _ => (DEFAULT_REGISTRY.to_string(), name.to_string()),
+        };
+        let repository = if registry == DEFAULT_REGISTRY && !repository.contains('/') {
+            format!("library/{repository}")
+        } else {
+            repository
+        };
+
+        let valid_repository = !repository.is_empty()
+            && repository.split('/').all(|part| {
+                !part.is_empty()
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+            });
+        if !valid_repository {
+            return Err(format!("invalid repository in OCI reference '{s}'"));
+        }
+        if reference.is_empty() {
+            return Err(format!("missing tag or digest in OCI reference '{s}'"));
+        }
+        Ok(OciReference {
+            registry,
+            repository,
+            reference,
+        })
+    }
+}
+
+/// Content descriptor of a manifest's config or layer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OciDescriptor {
+    pub media_type: String,
+    /// `sha256:<hex>` of the content
+    pub digest: String,
+    pub size: u64,
+}
+
+/// OCI image manifest
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OciManifest {
+    pub schema_version: u32,
+    pub media_type: String,
+    pub config: OciDescriptor,
+    pub layers: Vec<OciDescriptor>,
+}
+
+impl OciManifest {
+    /// The component layer of a wasm artifact
+    pub fn component_layer(&self) -> Option<&OciDescriptor> {
+        self.layers
+            .iter()
+            .find(|layer| layer.media_type == LAYER_MEDIA_TYPE)
+    }
+}
+
+/// Config blob of a wasm artifact
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WasmConfig {
+    /// When the artifact was built (RFC 3339)
+    pub created: String,
+    pub architecture: String,
+    pub os: String,
+    pub layer_digests: Vec<String>,
+}
+
+/// Login for a private registry. A password may also be a personal access token.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegistryCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Parameters of a `WWW-Authenticate: Bearer realm="..",service="..",scope=".."`
+/// challenge, from which a registry token is requested
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BearerChallenge {
+    pub realm: String,
+    pub service: Option<String>,
+    pub scope: Option<String>,
+}
+
+impl BearerChallenge {
+    /// Parse a challenge, or `None` for other schemes such as `Basic`
+    pub fn parse(header: &str) -> Option<Self> {
+        let params = header.strip_prefix("Bearer ")?;
+        let mut challenge = BearerChallenge::default();
+        let mut rest = params.trim();
+        while let Some((key, after)) = rest.split_once('=') {
+            let key = key.trim().trim_start_matches(',').trim();
+            let after = after.trim_start();
+            let (value, remaining) = match after.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"')?,
+                None => after.split_once(',').unwrap_or((after, "")),
+            };
+            match key {
+                "realm" => challenge.realm = value.to_string(),
+                "service" => challenge.service = Some(value.to_string()),
+                "scope" => challenge.scope = Some(value.to_string()),
+                _ => {}
+            }
+            rest = remaining;
+        }
+        (!challenge.realm.is_empty()).then_some(challenge)
+    }
+}
+
+/// Credentials for the requests of one push or pull, upgraded to a bearer token
+/// when the registry asks
+pub struct RegistryAuth {
+    credentials: Option<RegistryCredentials>,
+    token: Option<String>,
+}
+
+impl RegistryAuth {
+    pub fn new(credentials: Option<RegistryCredentials>) -> Self {
+        Self {
+            credentials,
+            token: None,
+        }
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match (&self.token, &self.credentials) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some(credentials)) => {
+                request.basic_auth(&credentials.username, Some(&credentials.password))
+            }
+            (None, None) => request,
+        }
+    }
+
+    /// Send a request, answering a bearer challenge and retrying once on a 401.
+    /// A new challenge (such as for another scope) replaces the token.
+    pub async fn send(
+        &mut self,
+        client: &Client,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Result<Response> {
+        let response = self.authorize(request()).send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        let challenge = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(BearerChallenge::parse);
+        let Some(challenge) = challenge else {
+            return Ok(response);
+        };
+
+        let mut token_request = client.get(&challenge.realm);
+        if let Some(service) = &challenge.service {
+            token_request = token_request.query(&[("service", service)]);
+        }
+        if let Some(scope) = &challenge.scope {
+            token_request = token_request.query(&[("scope", scope)]);
+        }
+        if let Some(credentials) = &self.credentials {
+            token_request =
+                token_request.basic_auth(&credentials.username, Some(&credentials.password));
+        }
+        let token_response = token_request.send().await?;
+        if !token_response.status().is_success() {
+            bail!(
+                "Registry refused a token ({}); check the registry credentials",
+                token_response.status()
+            );
+        }
+        let body: serde_json::Value = token_response.json().await?;
+        let token = body
+            .get("token")
+            .or_else(|| body.get("access_token"))
+            .and_then(|token| token.as_str())
+            .ok_or_else(|| anyhow!("Registry token response has no token"))?;
+        self.token = Some(token.to_string());
+
+        Ok(self.authorize(request()).send().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_references() {
+        let reference: OciReference = "ghcr.io/octocat/hello:v1".parse().unwrap();
+        assert_eq!(reference.registry, "ghcr.io");
+        assert_eq!(reference.repository, "octocat/hello");
+        assert_eq!(reference.reference, "v1");
+
+        let reference: OciReference = "localhost:5000/hello".parse().unwrap();
+        assert_eq!(reference.registry, "localhost:5000");
+        assert_eq!(reference.reference, "latest");
+        assert_eq!(reference.api_base(), "http://localhost:5000/v2/hello");
+        // Only the local host itself is spoken to without TLS
+        let reference: OciReference = "localhost.example.com/hello".parse().unwrap();
+        assert_eq!(
+            reference.api_base(),
+            "https://localhost.example.com/v2/hello"
+        );
+
+        let reference: OciReference = "hello@sha256:abcd".parse().unwrap();
+        assert_eq!(reference.repository, "library/hello");
+        assert_eq!(reference.to_string(), "docker.io/library/hello@sha256:abcd");
+
+        assert!("ghcr.io/Octocat/hello".parse::<OciReference>().is_err());
+    }
+
+    #[test]
+    fn test_parse_bearer_challenge() {
+        let challenge = BearerChallenge::parse(
+            r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:octocat/hello:pull""#,
+        )
+        .unwrap();
+        assert_eq!(challenge.realm, "https://ghcr.io/token");
+        assert_eq!(challenge.service.as_deref(), Some("ghcr.io"));
+        assert_eq!(
+            challenge.scope.as_deref(),
+            Some("repository:octocat/hello:pull")
+        );
+        assert!(BearerChallenge::parse(r#"Basic realm="registry""#).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+//! Access keys of private functions.
+//!
+//! `cargo faasta protect` makes a function private: the server mints a random
+//! access key, returns it once and keeps only its SHA-256 hash. Requests to the
+//! function must then carry the key, in `X-Faasta-Access-Key` or as the password
+//! of HTTP Basic authentication (any user name), or get a 401 without the function
+//! running. Protecting the function again rotates the key, invalidating the old
+//! one at once.
+//!
+//! The key is removed from requests to private functions before the function sees
+//! them, so it doesn't end up in the function's logs or upstream calls. Requests to
+//! public functions are passed on as they are, Basic authentication included.
+
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use faasta_interface::ACCESS_KEY_HEADER;
+use hyper::header::AUTHORIZATION;
+use hyper::Request;
+use once_cell::sync::OnceCell;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::metadata_store::{Cached, MetadataStore};
+
+/// Collection of the metadata store holding the hash of each private function's
+/// key, keyed by function name
+pub const ACCESS_KEYS_TREE: &str = "function_access_keys";
+
+/// Global access keys, set at startup
+pub static ACCESS_KEYS: OnceCell<AccessKeys> = OnceCell::new();
+
+/// Prefix that tells access keys apart from other secrets
+const ACCESS_KEY_PREFIX: &str = "fak_";
+const ACCESS_KEY_SECRET_LEN: usize = 40;
+
+pub struct AccessKeys {
+    hashes: Cached<Vec<u8>>,
+}
+
+impl AccessKeys {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Result<Self> {
+        Ok(Self {
+            hashes: Cached::new(store, ACCESS_KEYS_TREE, |hash| Some(hash.to_vec()))?,
+        })
+    }
+
+    /// Make `name` private with a new key, returning the key
+    pub fn rotate(&self, name: &str) -> Result<String> {
+        let secret: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(ACCESS_KEY_SECRET_LEN)
+            .map(char::from)
+            .collect();
+        let key = format!("{ACCESS_KEY_PREFIX}{secret}");
+        let hash = hash_key(&key);
+        self.hashes.insert(name, hash.clone(), hash)?;
+        Ok(key)
+    }
+
+    /// Make `name` public again, returning whether it was private
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        self.hashes.remove(name)
+    }
+
+    /// Read `name`'s key again, after its record was moved or copied
+    pub fn refresh(&self, name: &str) -> Result<()> {
+        self.hashes.refresh(name)
+    }
+
+    /// Read every key again, for changes made by other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.hashes.reload()
+    }
+
+    /// Whether `req` may invoke `name`: it's public, or `req` carries its key. The
+    /// key of a private function is taken out of the request either way; requests
+    /// to public functions keep their headers, which may be the function's own.
+    pub fn admit<B>(&self, name: &str, req: &mut Request<B>) -> bool {
+        let Some(hash) = self.hashes.get(name) else {
+            return true;
+        };
+        take_key(req).is_some_and(|key| hash_key(&key) == hash)
+    }
+}
+
+/// The access key `req` carries, removed from its headers
+fn take_key<B>(req: &mut Request<B>) -> Option<String> {
+    let headers = req.headers_mut();
+    let header = headers
+        .remove(ACCESS_KEY_HEADER)
+        .and_then(|value| value.to_str().ok().map(str::to_string));
+    if header.is_some() {
+        return header;
+    }
+    let password = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|credentials| STANDARD.decode(credentials.trim()).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| {
+            credentials
+                .split_once(':')
+                .map(|(_, password)| password.to_string())
+        })?;
+    headers.remove(AUTHORIZATION);
+    Some(password)
+}
+
+fn hash_key(key: &str) -> Vec<u8> {
+    Sha256::digest(key.as_bytes()).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_store::SledStore;
+
+    fn request(headers: &[(&str, String)]) -> Request<()> {
+        let mut builder = Request::builder().uri("/");
+        for (name, value) in headers {
+            builder = builder.header(*name, value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_private_functions_need_their_current_key() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let keys = AccessKeys::new(Arc::new(SledStore::new(&db))).unwrap();
+        assert!(keys.admit("api", &mut request(&[])));
+        // Public functions get the credentials meant for them
+        let basic = format!("Basic {}", STANDARD.encode("user:password"));
+        let mut req = request(&[("authorization", basic)]);
+        assert!(keys.admit("api", &mut req));
+        assert!(req.headers().contains_key(AUTHORIZATION));
+
+        let old = keys.rotate("api").unwrap();
+        let key = keys.rotate("api").unwrap();
+        assert!(key.starts_with(ACCESS_KEY_PREFIX));
+        assert!(!keys.admit("api", &mut request(&[])));
+        assert!(!keys.admit("api", &mut request(&[(ACCESS_KEY_HEADER, old)])));
+
+        let mut req = request(&[(ACCESS_KEY_HEADER, key.clone())]);
+        assert!(keys.admit("api", &mut req));
+        assert!(!req.headers().contains_key(ACCESS_KEY_HEADER));
+
+        let basic = format!("Basic {}", STANDARD.encode(format!("ci:{key}")));
+        let mut req = request(&[("authorization", basic)]);
+        assert!(keys.admit("api", &mut req));
+        assert!(!req.headers().contains_key(AUTHORIZATION));
+
+        // Bearer tokens are left for the function
+        let mut req = request(&[("authorization", "Bearer abc".to_string())]);
+        assert!(!keys.admit("api", &mut req));
+        assert!(req.headers().contains_key(AUTHORIZATION));
+
+        assert!(keys.remove("api").unwrap());
+        assert!(keys.admit("api", &mut request(&[])));
+    }
+}
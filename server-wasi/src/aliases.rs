@@ -0,0 +1,178 @@
+//! Other names of functions.
+//!
+//! An alias such as `api-v2` makes a function answer under a second name, on its
+//! subdomain and path alike, while a redirect answers requests for the name with a
+//! 301 or 302 to the function's own URL. Both are looked up only when no function
+//! has the requested name, so they cost nothing on the way to a function. An alias
+//! keeps its name taken until it's removed, and counts toward the project limit of
+//! the function's owner; renaming the function carries its aliases along in the
+//! same batch, and deleting it removes them. Aliases are kept in memory, as they're
+//! read on requests.
+
+use anyhow::Result;
+use faasta_interface::FunctionAlias;
+use once_cell::sync::OnceCell;
+use std::sync::Arc;
+
+use crate::metadata_store::{Cached, Change, MetadataStore};
+
+/// Collection of the metadata store holding aliases, keyed by the alias name
+pub const ALIASES_TREE: &str = "function_aliases";
+
+/// Global aliases, set at startup
+pub static ALIASES: OnceCell<Aliases> = OnceCell::new();
+
+pub struct Aliases {
+    store: Arc<dyn MetadataStore>,
+    aliases: Cached<FunctionAlias>,
+}
+
+impl Aliases {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Result<Self> {
+        Ok(Self {
+            aliases: Cached::new(store.clone(), ALIASES_TREE, decode)?,
+            store,
+        })
+    }
+
+    /// The alias called `name`, if there is one
+    pub fn get(&self, name: &str) -> Option<FunctionAlias> {
+        self.aliases.get(name)
+    }
+
+    /// Add `alias`, or replace the alias of the same name
+    pub fn set(&self, alias: &FunctionAlias) -> Result<()> {
+        self.aliases
+            .insert(&alias.name, alias.clone(), encode(alias)?)
+    }
+
+    /// Remove the alias `name`. Returns whether there was one.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        self.aliases.remove(name)
+    }
+
+    /// The aliases leading to `target`, sorted by name
+    pub fn of(&self, target: &str) -> Result<Vec<FunctionAlias>> {
+        let mut aliases: Vec<FunctionAlias> = self
+            .aliases
+            .all()
+            .into_iter()
+            .map(|(_, alias)| alias)
+            .filter(|alias| alias.target == target)
+            .collect();
+        aliases.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(aliases)
+    }
+
+    /// How many aliases lead to any of `targets`
+    pub fn count_of(&self, targets: &[String]) -> usize {
+        self.aliases
+            .all()
+            .iter()
+            .filter(|(_, alias)| targets.contains(&alias.target))
+            .count()
+    }
+
+    /// Changes pointing the aliases of `from` to `to`, to apply with the rename,
+    /// followed by a [`reload`](Self::reload)
+    pub fn retarget(&self, from: &str, to: &str) -> Result<Vec<Change>> {
+        self.of(from)?
+            .into_iter()
+            .map(|alias| {
+                let alias = FunctionAlias {
+                    target: to.to_string(),
+                    ..alias
+                };
+                Ok(Change::Insert {
+                    collection: ALIASES_TREE,
+                    key: alias.name.clone(),
+                    value: encode(&alias)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Remove every alias of `target`
+    pub fn remove_all(&self, target: &str) -> Result<()> {
+        let names: Vec<String> = self
+            .of(target)?
+            .into_iter()
+            .map(|alias| alias.name)
+            .collect();
+        let changes: Vec<Change> = names
+            .iter()
+            .map(|name| Change::Remove {
+                collection: ALIASES_TREE,
+                key: name.clone(),
+            })
+            .collect();
+        self.store.apply(&changes)?;
+        names.iter().try_for_each(|name| self.aliases.refresh(name))
+    }
+
+    /// Read every alias again, after a rename or for changes made by other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.aliases.reload()
+    }
+}
+
+fn encode(alias: &FunctionAlias) -> Result<Vec<u8>> {
+    Ok(bincode::encode_to_vec(alias, bincode::config::standard())?)
+}
+
+fn decode(bytes: &[u8]) -> Option<FunctionAlias> {
+    bincode::decode_from_slice(bytes, bincode::config::standard())
+        .ok()
+        .map(|(alias, _)| alias)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_store::SledStore;
+    use faasta_interface::AliasMode;
+
+    fn alias(name: &str, target: &str, mode: AliasMode) -> FunctionAlias {
+        FunctionAlias {
+            name: name.to_string(),
+            target: target.to_string(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn test_aliases_follow_renames_and_deletes() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store: Arc<dyn MetadataStore> = Arc::new(SledStore::new(&db));
+        let aliases = Aliases::new(store.clone()).unwrap();
+        let v2 = alias("api-v2", "api", AliasMode::Alias);
+        let old = alias("old-api", "api", AliasMode::Redirect { permanent: true });
+        aliases.set(&v2).unwrap();
+        aliases.set(&old).unwrap();
+        aliases
+            .set(&alias("docs", "site", AliasMode::Alias))
+            .unwrap();
+        assert_eq!(aliases.get("api-v2"), Some(v2));
+        assert_eq!(aliases.of("api").unwrap().len(), 2);
+
+        store
+            .apply(&aliases.retarget("api", "backend").unwrap())
+            .unwrap();
+        aliases.reload().unwrap();
+        assert!(aliases.of("api").unwrap().is_empty());
+        assert_eq!(
+            aliases.count_of(&["backend".to_string(), "site".to_string()]),
+            3
+        );
+        assert_eq!(aliases.get("old-api").unwrap().target, "backend");
+        assert_eq!(
+            aliases.get("old-api").unwrap().mode.redirect_status(),
+            Some(301)
+        );
+
+        aliases.remove_all("backend").unwrap();
+        assert!(aliases.get("api-v2").is_none());
+        assert!(aliases.remove("docs").unwrap());
+        assert!(!aliases.remove("docs").unwrap());
+    }
+}
@@ -0,0 +1,308 @@
+//! Heuristics that flag unusual activity.
+//!
+//! Deploys are compared against the networks an account has deployed from before,
+//! and each function's request count and response bytes per interval against a
+//! moving baseline. Flagged activity becomes an [`AnomalyAlert`], which shows up
+//! for the owner in `cargo faasta alerts` and for admins in the review queue until
+//! someone resolves it. Traffic baselines live in memory and are rebuilt after a
+//! restart.
+
+use anyhow::Result;
+use dashmap::DashMap;
+use faasta_interface::{AlertKind, AnomalyAlert};
+use once_cell::sync::OnceCell;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{error, warn};
+
+use crate::events::{PlatformEvent, Subscriber};
+use crate::wasi_server::SERVER;
+
+/// Sled tree holding unresolved alerts, keyed by big-endian alert id
+const ALERTS_TREE: &str = "anomaly_alerts";
+/// Sled tree holding the networks each account has deployed from, keyed by username
+const DEPLOY_NETWORKS_TREE: &str = "deploy_networks";
+/// Most networks remembered per account
+const MAX_KNOWN_NETWORKS: usize = 32;
+/// Intervals with fewer requests than this are never flagged
+const MIN_SPIKE_REQUESTS: u64 = 1000;
+/// Intervals with fewer response bytes than this are never flagged
+const MIN_SPIKE_BYTES: u64 = 100 * 1024 * 1024;
+/// Weight of the latest interval in the moving baseline
+const BASELINE_WEIGHT: f64 = 0.2;
+
+/// Global detector, set at startup
+pub static ANOMALIES: OnceCell<AnomalyDetector> = OnceCell::new();
+
+#[derive(Default)]
+struct Window {
+    requests: AtomicU64,
+    bytes: AtomicU64,
+}
+
+#[derive(Clone, Copy)]
+struct Baseline {
+    requests: f64,
+    bytes: f64,
+}
+
+pub struct AnomalyDetector {
+    db: sled::Db,
+    alerts: sled::Tree,
+    networks: sled::Tree,
+    /// An interval this many times above the baseline is a spike
+    spike_factor: f64,
+    current: DashMap<String, Window>,
+    baselines: DashMap<String, Baseline>,
+}
+
+impl AnomalyDetector {
+    pub fn new(db: &sled::Db, spike_factor: f64) -> Result<Self> {
+        Ok(Self {
+            db: db.clone(),
+            alerts: db.open_tree(ALERTS_TREE)?,
+            networks: db.open_tree(DEPLOY_NETWORKS_TREE)?,
+            spike_factor,
+            current: DashMap::new(),
+            baselines: DashMap::new(),
+        })
+    }
+
+    /// Count a served request and the size of its response body
+    pub fn record_request(&self, function_name: &str, response_bytes: u64) {
+        let window = self.current.entry(function_name.to_string()).or_default();
+        window.requests.fetch_add(1, Ordering::Relaxed);
+        window.bytes.fetch_add(response_bytes, Ordering::Relaxed);
+    }
+
+    /// Remember the network a deploy came from, flagging it if the account has
+    /// deployed before but never from there
+    pub fn record_deploy(&self, username: &str, function_name: &str, peer: IpAddr) -> Result<()> {
+        let network = network_of(peer);
+        let mut known: Vec<String> = match self.networks.get(username.as_bytes())? {
+            Some(value) => bincode::decode_from_slice(&value, bincode::config::standard())
+                .map(|(known, _)| known)
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        if known.contains(&network) {
+            return Ok(());
+        }
+
+        if !known.is_empty() {
+            self.raise(
+                AlertKind::NewDeployNetwork,
+                username,
+                Some(function_name),
+                format!(
+                    "Deployed from {network}, previously only from {}",
+                    known.join(", ")
+                ),
+            )?;
+        }
+
+        known.push(network);
+        if known.len() > MAX_KNOWN_NETWORKS {
+            known.remove(0);
+        }
+        let encoded = bincode::encode_to_vec(&known, bincode::config::standard())?;
+        self.networks.insert(username.as_bytes(), encoded)?;
+        Ok(())
+    }
+
+    /// Close the current interval, flagging functions far above their baseline
+    pub fn check_traffic(&self) {
+        let functions: Vec<String> = self
+            .current
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        for function_name in functions {
+            let Some((_, window)) = self.current.remove(&function_name) else {
+                continue;
+            };
+            let requests = window.requests.into_inner();
+            let bytes = window.bytes.into_inner();
+
+            if let Some(baseline) = self.baselines.get(&function_name).map(|b| *b) {
+                let spikes = [
+                    (
+                        AlertKind::TrafficSpike,
+                        requests,
+                        baseline.requests,
+                        MIN_SPIKE_REQUESTS,
+                        "requests",
+                    ),
+                    (
+                        AlertKind::EgressSpike,
+                        bytes,
+                        baseline.bytes,
+                        MIN_SPIKE_BYTES,
+                        "response bytes",
+                    ),
+                ];
+                for (kind, observed, usual, floor, unit) in spikes {
+                    if observed >= floor && observed as f64 >= usual.max(1.0) * self.spike_factor {
+                        self.raise_for_function(
+                            kind,
+                            &function_name,
+                            format!("{observed} {unit} in the last interval, usually {usual:.0}"),
+                        );
+                    }
+                }
+            }
+
+            let updated = match self.baselines.get(&function_name).map(|b| *b) {
+                Some(baseline) => Baseline {
+                    requests: baseline.requests * (1.0 - BASELINE_WEIGHT)
+                        + requests as f64 * BASELINE_WEIGHT,
+                    bytes: baseline.bytes * (1.0 - BASELINE_WEIGHT)
+                        + bytes as f64 * BASELINE_WEIGHT,
+                },
+                None => Baseline {
+                    requests: requests as f64,
+                    bytes: bytes as f64,
+                },
+            };
+            self.baselines.insert(function_name, updated);
+        }
+    }
+
+    /// Every unresolved alert, oldest first
+    pub fn alerts(&self) -> Vec<AnomalyAlert> {
+        self.alerts
+            .iter()
+            .flatten()
+            .filter_map(|(_, value)| {
+                bincode::decode_from_slice::<AnomalyAlert, _>(&value, bincode::config::standard())
+                    .ok()
+                    .map(|(alert, _)| alert)
+            })
+            .collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<AnomalyAlert> {
+        let value = match self.alerts.get(id.to_be_bytes()) {
+            Ok(value) => value?,
+            Err(e) => {
+                error!("Failed to read anomaly alert {}: {}", id, e);
+                return None;
+            }
+        };
+        bincode::decode_from_slice::<AnomalyAlert, _>(&value, bincode::config::standard())
+            .ok()
+            .map(|(alert, _)| alert)
+    }
+
+    pub fn resolve(&self, id: u64) -> Result<()> {
+        self.alerts.remove(id.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Drop a function's traffic history, e.g. after it was renamed or deleted
+    pub fn forget_function(&self, function_name: &str) {
+        self.current.remove(function_name);
+        self.baselines.remove(function_name);
+    }
+
+    /// Raise an alert about a function for its owner, unless one of `kind` is open
+    pub fn raise_for_function(&self, kind: AlertKind, function_name: &str, detail: String) {
+        // One open alert per function and kind is enough
+        if self
+            .alerts()
+            .iter()
+            .any(|alert| alert.kind == kind && alert.function.as_deref() == Some(function_name))
+        {
+            return;
+        }
+
+        let owner = match SERVER
+            .get()
+            .map(|server| server.github_auth.project_owner(function_name))
+        {
+            Some(Ok(Some(owner))) => owner,
+            Some(Err(e)) => {
+                error!("Failed to find the owner of '{}': {}", function_name, e);
+                return;
+            }
+            _ => return,
+        };
+        if let Err(e) = self.raise(kind, &owner, Some(function_name), detail) {
+            error!("Failed to record alert for '{}': {}", function_name, e);
+        }
+    }
+
+    fn raise(
+        &self,
+        kind: AlertKind,
+        owner: &str,
+        function_name: Option<&str>,
+        detail: String,
+    ) -> Result<()> {
+        let alert = AnomalyAlert {
+            id: self.db.generate_id()?,
+            kind,
+            owner: owner.to_string(),
+            function: function_name.map(String::from),
+            detail,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        warn!(
+            "Anomaly {} for '{}' ({}): {}",
+            alert.kind,
+            alert.owner,
+            alert.function.as_deref().unwrap_or("-"),
+            alert.detail
+        );
+        let encoded = bincode::encode_to_vec(&alert, bincode::config::standard())?;
+        self.alerts.insert(alert.id.to_be_bytes(), encoded)?;
+        Ok(())
+    }
+}
+
+impl Subscriber for AnomalyDetector {
+    fn on_event(&self, event: &PlatformEvent) {
+        if let PlatformEvent::Deployed {
+            function,
+            username,
+            peer: Some(peer),
+            ..
+        } = event
+        {
+            if let Err(e) = self.record_deploy(username, function, *peer) {
+                error!("Failed to record deploy network for '{function}': {e}");
+            }
+        }
+    }
+}
+
+/// The network an address belongs to: its /16 for IPv4, its /32 for IPv6
+fn network_of(peer: IpAddr) -> String {
+    match peer.to_canonical() {
+        IpAddr::V4(v4) => {
+            let [a, b, _, _] = v4.octets();
+            format!("{a}.{b}.0.0/16")
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            format!("{:x}:{:x}::/32", segments[0], segments[1])
+        }
+    }
+}
+
+/// Spawn a task that closes a traffic interval every `interval_secs`
+pub fn spawn_periodic_check(interval_secs: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_secs));
+        // The first tick completes immediately
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if let Some(anomalies) = ANOMALIES.get() {
+                anomalies.check_traffic();
+            }
+        }
+    });
+}
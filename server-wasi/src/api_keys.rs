@@ -0,0 +1,216 @@
+use anyhow::Result;
+use bincode::{Decode, Encode};
+use faasta_interface::{ApiKeyInfo, ApiKeyScope};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::metadata_store::{hex_encode_keys, MetadataStore};
+
+/// Collection of the metadata store holding API key records, keyed by the hex
+/// SHA-256 hash of the key
+const API_KEYS_TREE: &str = "api_keys";
+/// Prefix that distinguishes server-issued keys from provider tokens
+pub const API_KEY_PREFIX: &str = "fst_";
+const API_KEY_SECRET_LEN: usize = 40;
+/// Random bytes of a key's public id, which is hex-encoded
+const API_KEY_ID_BYTES: usize = 8;
+
+/// A stored API key. Only the hash of the key is persisted.
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct ApiKeyRecord {
+    pub username: String,
+    pub info: ApiKeyInfo,
+}
+
+/// Long-lived keys minted by the server and validated locally,
+/// so automated deploys don't need a round trip to the auth provider
+pub struct ApiKeyStore {
+    store: Arc<dyn MetadataStore>,
+}
+
+impl ApiKeyStore {
+    pub fn new(store: Arc<dyn MetadataStore>, db: &sled::Db) -> Result<Self> {
+        hex_encode_keys(db, API_KEYS_TREE)?;
+        let store = Self { store };
+        store.reassign_legacy_ids()?;
+        Ok(store)
+    }
+
+    /// Keys minted before ids were random had the first characters of their secret
+    /// as id, which is listed and logged. Give them random ids instead.
+    fn reassign_legacy_ids(&self) -> Result<()> {
+        let legacy: Vec<_> = self
+            .records()?
+            .into_iter()
+            .filter(|(_, record)| record.info.id.len() != API_KEY_ID_BYTES * 2)
+            .collect();
+        for (hash, mut record) in legacy {
+            record.info.id = new_key_id();
+            let encoded = bincode::encode_to_vec(&record, bincode::config::standard())?;
+            self.store.insert(API_KEYS_TREE, &hash, encoded)?;
+        }
+        Ok(())
+    }
+
+    /// Mint a new key for `username`. Returns the plaintext key and its metadata.
+    pub fn create(
+        &self,
+        username: &str,
+        name: &str,
+        scopes: Vec<ApiKeyScope>,
+    ) -> Result<(String, ApiKeyInfo)> {
+        let secret: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(API_KEY_SECRET_LEN)
+            .map(char::from)
+            .collect();
+        let key = format!("{API_KEY_PREFIX}{secret}");
+
+        let info = ApiKeyInfo {
+            id: new_key_id(),
+            name: name.to_string(),
+            scopes,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        let record = ApiKeyRecord {
+            username: username.to_string(),
+            info: info.clone(),
+        };
+
+        let encoded = bincode::encode_to_vec(&record, bincode::config::standard())?;
+        self.store.insert(API_KEYS_TREE, &hash_key(&key), encoded)?;
+
+        Ok((key, info))
+    }
+
+    /// Look up the record for a plaintext key
+    pub fn validate(&self, key: &str) -> Result<Option<ApiKeyRecord>> {
+        let Some(value) = self.store.get(API_KEYS_TREE, &hash_key(key))? else {
+            return Ok(None);
+        };
+        Ok(
+            bincode::decode_from_slice::<ApiKeyRecord, _>(&value, bincode::config::standard())
+                .ok()
+                .map(|(record, _)| record),
+        )
+    }
+
+    /// All keys belonging to `username`
+    pub fn list(&self, username: &str) -> Result<Vec<ApiKeyInfo>> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .filter(|(_, record)| record.username == username)
+            .map(|(_, record)| record.info)
+            .collect())
+    }
+
+    /// Revoke the key with `id` if it belongs to `username`.
+    /// Returns whether a key was removed.
+    pub fn revoke(&self, username: &str, id: &str) -> Result<bool> {
+        let Some((hash, _)) = self
+            .records()?
+            .into_iter()
+            .find(|(_, record)| record.username == username && record.info.id == id)
+        else {
+            return Ok(false);
+        };
+
+        self.store.remove(API_KEYS_TREE, &hash)?;
+        Ok(true)
+    }
+
+    fn records(&self) -> Result<Vec<(String, ApiKeyRecord)>> {
+        Ok(self
+            .store
+            .scan(API_KEYS_TREE)?
+            .into_iter()
+            .filter_map(|(hash, value)| {
+                bincode::decode_from_slice::<ApiKeyRecord, _>(&value, bincode::config::standard())
+                    .ok()
+                    .map(|(record, _)| (hash, record))
+            })
+            .collect())
+    }
+}
+
+/// If `token` is a server-issued API key (optionally sent as `username:key`),
+/// return the username the client claimed and the key itself
+pub fn parse_api_key(token: &str) -> Option<(Option<&str>, &str)> {
+    let (username, key) = match token.split_once(':') {
+        Some((username, key)) => (Some(username).filter(|u| !u.is_empty()), key),
+        None => (None, token),
+    };
+    let key = key.strip_prefix("Bearer ").unwrap_or(key).trim();
+    key.starts_with(API_KEY_PREFIX).then_some((username, key))
+}
+
+/// Public id of a key, unrelated to its secret
+fn new_key_id() -> String {
+    hex::encode(rand::random::<[u8; API_KEY_ID_BYTES]>())
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_store::SledStore;
+
+    #[test]
+    fn test_keys_validate_until_revoked_and_ids_reveal_nothing() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store = ApiKeyStore::new(Arc::new(SledStore::new(&db)), &db).unwrap();
+        let (key, info) = store
+            .create("alice", "ci", vec![ApiKeyScope::Deploy])
+            .unwrap();
+        let secret = key.strip_prefix(API_KEY_PREFIX).unwrap();
+        assert_eq!(info.id.len(), API_KEY_ID_BYTES * 2);
+        assert!(!secret.contains(&info.id));
+
+        assert_eq!(store.validate(&key).unwrap().unwrap().username, "alice");
+        assert!(store.validate(&format!("{key}x")).unwrap().is_none());
+        assert_eq!(store.list("alice").unwrap().len(), 1);
+        assert!(store.list("bob").unwrap().is_empty());
+
+        assert!(!store.revoke("bob", &info.id).unwrap());
+        assert!(store.revoke("alice", &info.id).unwrap());
+        assert!(store.validate(&key).unwrap().is_none());
+
+        assert_eq!(
+            parse_api_key(&format!("alice:{key}")),
+            Some((Some("alice"), key.as_str()))
+        );
+        assert_eq!(
+            parse_api_key(&format!("Bearer {key}")),
+            Some((None, key.as_str()))
+        );
+        assert_eq!(parse_api_key("alice:gho_token"), None);
+    }
+
+    #[test]
+    fn test_legacy_ids_and_keys_are_replaced() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let shared: Arc<dyn MetadataStore> = Arc::new(SledStore::new(&db));
+        let store = ApiKeyStore::new(shared.clone(), &db).unwrap();
+        let (key, _) = store.create("alice", "ci", vec![]).unwrap();
+        let mut record = store.validate(&key).unwrap().unwrap();
+        record.info.id = key[API_KEY_PREFIX.len()..][..8].to_string();
+        let encoded = bincode::encode_to_vec(&record, bincode::config::standard()).unwrap();
+        // Kept under the raw hash, as before keys moved to the metadata store
+        db.open_tree(API_KEYS_TREE)
+            .unwrap()
+            .insert(Sha256::digest(key.as_bytes()).as_slice(), encoded)
+            .unwrap();
+        shared.remove(API_KEYS_TREE, &hash_key(&key)).unwrap();
+
+        let store = ApiKeyStore::new(shared, &db).unwrap();
+        let id = store.validate(&key).unwrap().unwrap().info.id;
+        assert_eq!(id.len(), API_KEY_ID_BYTES * 2);
+        assert!(!key.contains(&id));
+    }
+}
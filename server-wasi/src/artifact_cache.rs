@@ -0,0 +1,263 @@
+//! Local cache of precompiled functions.
+//!
+//! With a cache size configured, the `.cwasm` files in the functions directory are
+//! only a cache in front of artifact storage. A function that isn't cached is
+//! fetched and compiled on its first request, and the least recently used ones are
+//! evicted to stay under the size, so a node can host far more rarely-used
+//! functions than its disk holds. Functions named as prefetch hints, followed by
+//! the most recently used ones, are hydrated in the background at startup.
+//! Canaries and kept-warm functions are always kept.
+//!
+//! The digest of the WebAssembly each artifact was compiled from is recorded with
+//! it, so in a cluster a node can tell an artifact of an older version apart even
+//! when storage served it one.
+
+use anyhow::{anyhow, Result};
+use dashmap::DashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info};
+use wasmtime::Engine;
+
+use crate::canary::CANARY_SUFFIX;
+use crate::compiler;
+use crate::function_data::artifact_digest;
+use crate::keep_warm::is_kept_warm;
+use crate::metadata_store::MetadataStore;
+use crate::rpc_service::FUNCTIONS_DB_TREE;
+use crate::storage::{wasm_key, write_atomically, ArtifactStorage};
+
+/// Sled tree holding when each function was last requested, keyed by function name
+const LAST_USED_TREE: &str = "artifact_last_used";
+/// Sled tree holding the digest of the WebAssembly each precompiled artifact was
+/// compiled from, keyed by function name
+const COMPILED_DIGESTS_TREE: &str = "compiled_artifact_digests";
+/// Uses are recorded at most this often per function, in seconds
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+pub struct ArtifactCache {
+    functions_dir: PathBuf,
+    storage: Arc<dyn ArtifactStorage>,
+    /// Function metadata, telling published functions apart
+    metadata: Arc<dyn MetadataStore>,
+    last_used: sled::Tree,
+    compiled: sled::Tree,
+    /// Most bytes of precompiled functions kept locally (0 keeps everything)
+    max_bytes: u64,
+    /// Functions being hydrated, so concurrent requests fetch them only once
+    hydrating: DashMap<String, Arc<Mutex<()>>>,
+    /// When each function's use was last recorded
+    touched: DashMap<String, i64>,
+}
+
+impl ArtifactCache {
+    pub fn new(
+        db: &sled::Db,
+        metadata: Arc<dyn MetadataStore>,
+        functions_dir: &Path,
+        storage: Arc<dyn ArtifactStorage>,
+        max_bytes: u64,
+    ) -> Result<Self> {
+        Ok(Self {
+            functions_dir: functions_dir.to_path_buf(),
+            storage,
+            metadata,
+            last_used: db.open_tree(LAST_USED_TREE)?,
+            compiled: db.open_tree(COMPILED_DIGESTS_TREE)?,
+            max_bytes,
+            hydrating: DashMap::new(),
+            touched: DashMap::new(),
+        })
+    }
+
+    /// Whether functions are fetched on demand rather than all kept locally
+    pub fn is_bounded(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    fn cwasm_path(&self, name: &str) -> PathBuf {
+        self.functions_dir.join(format!("{name}.cwasm"))
+    }
+
+    /// Path of `name`'s precompiled artifact, fetching and compiling it first if it
+    /// isn't cached. `None` if no function by that name is published.
+    pub async fn hydrate(&self, engine: &Engine, name: &str) -> Result<Option<PathBuf>> {
+        let path = self.cwasm_path(name);
+        if path.exists() {
+            self.touch(name);
+            return Ok(Some(path));
+        }
+        if !self.metadata.contains(FUNCTIONS_DB_TREE, name)? {
+            return Ok(None);
+        }
+
+        let lock = self.hydrating.entry(name.to_string()).or_default().clone();
+        let _guard = lock.lock().await;
+        // Another request may have hydrated it while we waited
+        if !path.exists() {
+            let wasm = self
+                .storage
+                .get(&wasm_key(name))
+                .await?
+                .ok_or_else(|| anyhow!("'{name}' is published but missing from storage"))?;
+            let cwasm = compiler::precompile(engine, &wasm).await?;
+            write_atomically(&path, &cwasm)?;
+            self.record_compiled(name, &artifact_digest(&wasm))?;
+            info!("Hydrated '{}' from {} storage", name, self.storage.name());
+        }
+        self.hydrating.remove(name);
+        self.touch(name);
+        Ok(Some(path))
+    }
+
+    /// Remember that `name`'s precompiled artifact was compiled from the
+    /// WebAssembly with `digest`
+    pub fn record_compiled(&self, name: &str, digest: &str) -> Result<()> {
+        self.compiled.insert(name.as_bytes(), digest.as_bytes())?;
+        Ok(())
+    }
+
+    /// Digest of the WebAssembly `name`'s precompiled artifact was compiled from,
+    /// if it was recorded
+    pub fn compiled_digest(&self, name: &str) -> Result<Option<String>> {
+        Ok(self
+            .compiled
+            .get(name.as_bytes())?
+            .map(|digest| String::from_utf8_lossy(&digest).into_owned()))
+    }
+
+    /// Remember that `name` was just used, for eviction and prefetching
+    pub fn touch(&self, name: &str) {
+        if !self.is_bounded() {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp();
+        if self
+            .touched
+            .get(name)
+            .is_some_and(|last| now - *last < TOUCH_INTERVAL_SECS)
+        {
+            return;
+        }
+        self.touched.insert(name.to_string(), now);
+        if let Err(e) = self.last_used.insert(name.as_bytes(), &now.to_be_bytes()) {
+            error!("Failed to record use of '{}': {}", name, e);
+        }
+    }
+
+    /// Evict the least recently used functions until the cache fits its size,
+    /// never evicting `keep` or kept-warm functions. Returns the evicted functions.
+    pub fn evict(&self, keep: &str) -> Vec<String> {
+        if !self.is_bounded() {
+            return Vec::new();
+        }
+        let mut cached = self.cached();
+        let mut total: u64 = cached.iter().map(|(_, size)| size).sum();
+        if total <= self.max_bytes {
+            return Vec::new();
+        }
+
+        cached.sort_by_key(|(name, _)| self.last_used(name));
+        let mut evicted = Vec::new();
+        for (name, size) in cached {
+            if total <= self.max_bytes {
+                break;
+            }
+            if name == keep || is_kept_warm(&name) {
+                continue;
+            }
+            match fs::remove_file(self.cwasm_path(&name)) {
+                Ok(()) => {
+                    debug!("Evicted '{}' from the artifact cache", name);
+                    total -= size;
+                    evicted.push(name);
+                }
+                Err(e) => error!("Failed to evict '{}': {}", name, e),
+            }
+        }
+        evicted
+    }
+
+    /// Hydrate `hints`, then the most recently used functions, while they fit
+    pub async fn prefetch(&self, engine: &Engine, hints: &[String]) {
+        let mut recent: Vec<(String, i64)> = self
+            .last_used
+            .iter()
+            .flatten()
+            .filter_map(|(name, value)| {
+                let name = String::from_utf8(name.to_vec()).ok()?;
+                Some((name, i64::from_be_bytes(value.as_ref().try_into().ok()?)))
+            })
+            .collect();
+        recent.sort_by_key(|(_, used)| std::cmp::Reverse(*used));
+
+        let mut total: u64 = self.cached().iter().map(|(_, size)| size).sum();
+        let candidates = hints
+            .iter()
+            .cloned()
+            .chain(recent.into_iter().map(|(name, _)| name));
+        for name in candidates {
+            if total >= self.max_bytes {
+                break;
+            }
+            if self.cwasm_path(&name).exists() {
+                continue;
+            }
+            match self.hydrate(engine, &name).await {
+                Ok(Some(path)) => total += fs::metadata(path).map_or(0, |meta| meta.len()),
+                Ok(None) => {}
+                Err(e) => error!("Failed to prefetch '{}': {}", name, e),
+            }
+        }
+    }
+
+    /// Functions whose use is recorded
+    pub fn used(&self) -> Vec<String> {
+        self.last_used
+            .iter()
+            .keys()
+            .flatten()
+            .map(|name| String::from_utf8_lossy(&name).into_owned())
+            .collect()
+    }
+
+    /// Forget a function's usage, e.g. after it was renamed or deleted
+    pub fn forget_function(&self, name: &str) {
+        self.touched.remove(name);
+        if let Err(e) = self.last_used.remove(name.as_bytes()) {
+            error!("Failed to forget use of '{}': {}", name, e);
+        }
+        if let Err(e) = self.compiled.remove(name.as_bytes()) {
+            error!("Failed to forget what '{}' was compiled from: {}", name, e);
+        }
+    }
+
+    /// Cached stable versions and their sizes
+    fn cached(&self) -> Vec<(String, u64)> {
+        let Ok(entries) = fs::read_dir(&self.functions_dir) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name().into_string().ok()?;
+                let name = file_name.strip_suffix(".cwasm")?;
+                if name.starts_with('.') || name.ends_with(CANARY_SUFFIX) {
+                    return None;
+                }
+                Some((name.to_string(), entry.metadata().ok()?.len()))
+            })
+            .collect()
+    }
+
+    fn last_used(&self, name: &str) -> i64 {
+        self.last_used
+            .get(name.as_bytes())
+            .ok()
+            .flatten()
+            .and_then(|value| value.as_ref().try_into().ok())
+            .map_or(0, i64::from_be_bytes)
+    }
+}
@@ -0,0 +1,325 @@
+//! Operator policies every published component must pass.
+//!
+//! Once an upload has been compiled and checked against the `wasi:http/proxy`
+//! world, and before it's stored or served, it goes through the policies the
+//! operator enabled, in order, until one rejects it:
+//!
+//! - `size` refuses components whose precompiled form is larger than
+//!   `--max-compiled-mb`, as a small component can compile to a much larger one
+//! - `banned-imports` refuses components importing an interface or package listed
+//!   in `--banned-imports`, such as `wasi:sockets` on a public platform
+//! - `scanner` POSTs the component to the `--artifact-scanner-url` webhook, an
+//!   external malware or abuse scanner, which answers whether to allow it
+//!
+//! Other checks implement [`ArtifactPolicy`]. A scanner that can't be reached or
+//! answers with an error fails the publish rather than letting the component
+//! through unscanned.
+
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Global artifact policies, set at startup
+pub static POLICIES: OnceCell<ArtifactPolicies> = OnceCell::new();
+
+/// A component being published, as policies see it
+pub struct Artifact<'a> {
+    /// Name of the function it's published as
+    pub function: &'a str,
+    /// User publishing it
+    pub uploader: &'a str,
+    /// The component as uploaded
+    pub wasm: &'a [u8],
+    /// The component precompiled for this server
+    pub cwasm: &'a [u8],
+    /// Interfaces the component imports, with their versions
+    pub imports: &'a [String],
+}
+
+/// What a policy decided about an artifact
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Refuse the artifact, for the reason given to the uploader
+    Reject(String),
+}
+
+/// A check components must pass to be published
+pub trait ArtifactPolicy: Send + Sync {
+    /// Short policy name used in refusals and logs
+    fn name(&self) -> &'static str;
+
+    /// Decide on `artifact`. Errors fail the publish without blaming the artifact.
+    fn check<'a>(&'a self, artifact: &'a Artifact<'a>) -> BoxFuture<'a, Result<Verdict>>;
+}
+
+/// Settings needed to build the [`ArtifactPolicies`]
+#[derive(Clone, Debug, Default)]
+pub struct PolicyConfig {
+    /// Largest precompiled component accepted, in bytes (0 for no limit)
+    pub max_compiled_bytes: u64,
+    /// Interfaces (`wasi:sockets/tcp`) or packages (`wasi:sockets`) components may not import
+    pub banned_imports: Vec<String>,
+    pub scanner_url: Option<String>,
+    /// Bearer token sent to the scanner
+    pub scanner_token: Option<String>,
+    pub scanner_timeout: Duration,
+}
+
+/// The policies enabled on this server, run in order
+pub struct ArtifactPolicies {
+    policies: Vec<Arc<dyn ArtifactPolicy>>,
+}
+
+impl ArtifactPolicies {
+    pub fn new(policies: Vec<Arc<dyn ArtifactPolicy>>) -> Self {
+        Self { policies }
+    }
+
+    /// Build the policies enabled in `config`
+    pub fn from_config(config: &PolicyConfig) -> Result<Self> {
+        let mut policies: Vec<Arc<dyn ArtifactPolicy>> = Vec::new();
+        if config.max_compiled_bytes > 0 {
+            policies.push(Arc::new(SizePolicy {
+                max_compiled_bytes: config.max_compiled_bytes,
+            }));
+        }
+        if !config.banned_imports.is_empty() {
+            policies.push(Arc::new(BannedImports {
+                banned: config.banned_imports.clone(),
+            }));
+        }
+        if let Some(url) = &config.scanner_url {
+            policies.push(Arc::new(Scanner {
+                client: reqwest::Client::builder()
+                    .timeout(config.scanner_timeout)
+                    .build()?,
+                url: url.clone(),
+                token: config.scanner_token.clone(),
+            }));
+        }
+        Ok(Self::new(policies))
+    }
+
+    /// Names of the policies enabled, in the order they run
+    pub fn names(&self) -> Vec<&'static str> {
+        self.policies.iter().map(|policy| policy.name()).collect()
+    }
+
+    /// Run the policies on `artifact`, returning the first rejection as the
+    /// policy's name and its reason
+    pub async fn check(&self, artifact: &Artifact<'_>) -> Result<Option<(&'static str, String)>> {
+        for policy in &self.policies {
+            let verdict = policy
+                .check(artifact)
+                .await
+                .with_context(|| format!("{} policy failed", policy.name()))?;
+            if let Verdict::Reject(reason) = verdict {
+                return Ok(Some((policy.name(), reason)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Limit on the size of precompiled components
+struct SizePolicy {
+    max_compiled_bytes: u64,
+}
+
+impl ArtifactPolicy for SizePolicy {
+    fn name(&self) -> &'static str {
+        "size"
+    }
+
+    fn check<'a>(&'a self, artifact: &'a Artifact<'a>) -> BoxFuture<'a, Result<Verdict>> {
+        let size = artifact.cwasm.len() as u64;
+        let verdict = if size > self.max_compiled_bytes {
+            Verdict::Reject(format!(
+                "the component compiles to {size} bytes, over the limit of {} bytes",
+                self.max_compiled_bytes
+            ))
+        } else {
+            Verdict::Allow
+        };
+        Box::pin(async move { Ok(verdict) })
+    }
+}
+
+/// Interfaces and packages components may not import
+struct BannedImports {
+    banned: Vec<String>,
+}
+
+impl BannedImports {
+    /// Whether `import` is a banned interface or in a banned package
+    fn bans(&self, import: &str) -> bool {
+        let interface = import.split('@').next().unwrap_or(import);
+        self.banned.iter().any(|banned| {
+            interface == banned.as_str()
+                || interface
+                    .strip_prefix(banned.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
+
+impl ArtifactPolicy for BannedImports {
+    fn name(&self) -> &'static str {
+        "banned-imports"
+    }
+
+    fn check<'a>(&'a self, artifact: &'a Artifact<'a>) -> BoxFuture<'a, Result<Verdict>> {
+        let banned: Vec<&str> = artifact
+            .imports
+            .iter()
+            .filter(|import| self.bans(import))
+            .map(String::as_str)
+            .collect();
+        let verdict = if banned.is_empty() {
+            Verdict::Allow
+        } else {
+            Verdict::Reject(format!(
+                "the component imports {}, which this server doesn't allow",
+                banned.join(", ")
+            ))
+        };
+        Box::pin(async move { Ok(verdict) })
+    }
+}
+
+/// External scanner asked about every component
+struct Scanner {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+/// Answer of the scanner webhook
+#[derive(Deserialize)]
+struct ScanResult {
+    allow: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+impl ScanResult {
+    fn verdict(self) -> Verdict {
+        if self.allow {
+            Verdict::Allow
+        } else {
+            Verdict::Reject(
+                self.reason
+                    .unwrap_or_else(|| "the artifact scanner refused it".to_string()),
+            )
+        }
+    }
+}
+
+impl ArtifactPolicy for Scanner {
+    fn name(&self) -> &'static str {
+        "scanner"
+    }
+
+    fn check<'a>(&'a self, artifact: &'a Artifact<'a>) -> BoxFuture<'a, Result<Verdict>> {
+        Box::pin(async move {
+            let mut request = self
+                .client
+                .post(&self.url)
+                .header("content-type", "application/wasm")
+                .header("x-faasta-function", artifact.function)
+                .header("x-faasta-uploader", artifact.uploader)
+                .header(
+                    "x-faasta-digest",
+                    crate::function_data::artifact_digest(artifact.wasm),
+                )
+                .body(artifact.wasm.to_vec());
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            let response = request.send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                bail!("the scanner answered {status}");
+            }
+            let result: ScanResult = response
+                .json()
+                .await
+                .context("the scanner's answer is not a scan result")?;
+            Ok(result.verdict())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifact<'a>(cwasm: &'a [u8], imports: &'a [String]) -> Artifact<'a> {
+        Artifact {
+            function: "api",
+            uploader: "alice",
+            wasm: b"wasm",
+            cwasm,
+            imports,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_policies_reject_large_components_and_banned_imports() {
+        let policies = ArtifactPolicies::from_config(&PolicyConfig {
+            max_compiled_bytes: 8,
+            banned_imports: vec![
+                "wasi:sockets".to_string(),
+                "wasi:http/outgoing-handler".to_string(),
+            ],
+            ..PolicyConfig::default()
+        })
+        .unwrap();
+        assert_eq!(policies.names(), ["size", "banned-imports"]);
+
+        let allowed = vec![
+            "wasi:http/types@0.2.0".to_string(),
+            "wasi:socketsx/tcp@0.2.0".to_string(),
+        ];
+        assert_eq!(
+            policies.check(&artifact(b"small", &allowed)).await.unwrap(),
+            None
+        );
+
+        let (policy, _) = policies
+            .check(&artifact(b"much too large", &allowed))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(policy, "size");
+
+        let banned = vec![
+            "wasi:sockets/tcp@0.2.0".to_string(),
+            "wasi:http/outgoing-handler@0.2.0".to_string(),
+            "wasi:http/types@0.2.0".to_string(),
+        ];
+        let (policy, reason) = policies
+            .check(&artifact(b"small", &banned))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(policy, "banned-imports");
+        assert!(reason.contains("wasi:sockets/tcp@0.2.0, wasi:http/outgoing-handler@0.2.0,"));
+        assert!(!reason.contains("types"));
+    }
+
+    #[test]
+    fn test_scanner_answers_become_verdicts() {
+        let parse = |body: &str| serde_json::from_str::<ScanResult>(body).unwrap().verdict();
+        assert_eq!(parse(r#"{"allow": true}"#), Verdict::Allow);
+        assert_eq!(
+            parse(r#"{"allow": false, "reason": "cryptominer"}"#),
+            Verdict::Reject("cryptominer".to_string())
+        );
+        assert!(matches!(parse(r#"{"allow": false}"#), Verdict::Reject(_)));
+    }
+}
@@ -0,0 +1,114 @@
+//! Append-only record of control-plane calls.
+//!
+//! Every RPC is recorded once it finishes, with who made it, from where, what it
+//! acted on and whether it succeeded. Entries are never modified or removed by
+//! the server; admins page through them with `cargo faasta admin audit`, which can
+//! print them as JSONL for compliance tooling.
+
+use anyhow::Result;
+use faasta_interface::{AuditEvent, FaastaError, FunctionResult};
+use once_cell::sync::OnceCell;
+use std::cell::RefCell;
+use std::future::Future;
+use std::net::IpAddr;
+use tracing::Instrument;
+
+use crate::events::{self, PlatformEvent};
+
+/// Sled tree holding audit events, keyed by big-endian event id
+const AUDIT_TREE: &str = "audit_log";
+/// Most events returned by one query
+pub const MAX_AUDIT_PAGE: u32 = 1000;
+
+/// Global audit log, set at startup
+pub static AUDIT: OnceCell<AuditLog> = OnceCell::new();
+
+tokio::task_local! {
+    /// User the current call has authenticated as (or claims to be)
+    static CALLER: RefCell<Option<String>>;
+}
+
+pub struct AuditLog {
+    db: sled::Db,
+    tree: sled::Tree,
+}
+
+impl AuditLog {
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            db: db.clone(),
+            tree: db.open_tree(AUDIT_TREE)?,
+        })
+    }
+
+    pub fn append(&self, mut event: AuditEvent) -> Result<()> {
+        event.id = self.db.generate_id()?;
+        let encoded = bincode::encode_to_vec(&event, bincode::config::standard())?;
+        self.tree.insert(event.id.to_be_bytes(), encoded)?;
+        Ok(())
+    }
+
+    /// Up to `limit` events with ids above `after`, oldest first
+    pub fn events(&self, after: Option<u64>, limit: usize) -> Vec<AuditEvent> {
+        let start = after.map_or(0, |after| after.saturating_add(1));
+        self.tree
+            .range(start.to_be_bytes()..)
+            .flatten()
+            .filter_map(|(_, value)| {
+                bincode::decode_from_slice::<AuditEvent, _>(&value, bincode::config::standard())
+                    .ok()
+                    .map(|(event, _)| event)
+            })
+            .take(limit)
+            .collect()
+    }
+}
+
+/// Remember who the current call is made by, for its audit event
+pub fn set_caller(username: &str) {
+    let _ = CALLER.try_with(|caller| *caller.borrow_mut() = Some(username.to_string()));
+}
+
+/// Run an RPC and record it in the audit log once it finishes, publishing the
+/// quotas it was refused for
+pub async fn audited<T>(
+    action: &str,
+    target: Option<String>,
+    peer: Option<IpAddr>,
+    call: impl Future<Output = FunctionResult<T>>,
+) -> FunctionResult<T> {
+    let (result, user) = CALLER
+        .scope(RefCell::new(None), async {
+            let result = call.await;
+            (result, CALLER.with(|caller| caller.borrow().clone()))
+        })
+        .instrument(tracing::info_span!(
+            "rpc",
+            rpc.method = action,
+            function = target.as_deref()
+        ))
+        .await;
+
+    if let Err(FaastaError::QuotaExceeded { quota, limit }) = &result {
+        events::publish(PlatformEvent::QuotaExceeded {
+            account: user.clone(),
+            quota: quota.clone(),
+            limit: *limit,
+        });
+    }
+    if let Some(audit) = AUDIT.get() {
+        let event = AuditEvent {
+            id: 0,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            user,
+            source_ip: peer.map(|peer| peer.to_canonical().to_string()),
+            action: action.to_string(),
+            target,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        if let Err(e) = audit.append(event) {
+            tracing::error!("Failed to record audit event for '{action}': {e}");
+        }
+    }
+    result
+}
@@ -0,0 +1,365 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use faasta_interface::TeamRole;
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::{error, warn};
+
+/// Identity providers the server can validate deploy tokens against
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AuthProviderKind {
+    Github,
+    Gitlab,
+    Bitbucket,
+    Oidc,
+}
+
+/// Settings needed to build an [`AuthProvider`]
+#[derive(Clone, Debug)]
+pub struct AuthProviderConfig {
+    pub kind: AuthProviderKind,
+    /// Base URL of the GitLab instance (for self-managed GitLab)
+    pub gitlab_url: String,
+    /// OIDC issuer URL, used to discover the userinfo endpoint
+    pub oidc_issuer: Option<String>,
+    /// Claim in the OIDC userinfo response that holds the username
+    pub oidc_username_claim: String,
+}
+
+/// A source of truth for "which user does this token belong to".
+///
+/// Implementations receive the raw token sent by the CLI, which is either a bare
+/// token or `username:token`, and return `(username, is_valid)`. Network or
+/// provider failures are reported as an invalid token rather than an error.
+pub trait AuthProvider: Send + Sync {
+    /// Short provider name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Resolve the username for `token`
+    fn authenticate<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<(String, bool)>>;
+
+    /// Check the provider can be reached, for readiness probes. Any response counts,
+    /// no token is checked.
+    fn probe(&self) -> BoxFuture<'_, Result<()>>;
+
+    /// Role of the token's user in the organization `org`, if they belong to it.
+    /// Providers without organizations never report a role.
+    fn org_role<'a>(
+        &'a self,
+        _token: &'a str,
+        _org: &'a str,
+    ) -> BoxFuture<'a, Result<Option<TeamRole>>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+/// Build the provider selected in the server configuration
+pub fn build_provider(config: &AuthProviderConfig) -> Result<Arc<dyn AuthProvider>> {
+    let client = Client::builder().timeout(Duration::from_secs(3)).build()?;
+
+    let provider: Arc<dyn AuthProvider> = match config.kind {
+        AuthProviderKind::Github => Arc::new(GitHubProvider { client }),
+        AuthProviderKind::Gitlab => Arc::new(GitLabProvider {
+            client,
+            base_url: config.gitlab_url.trim_end_matches('/').to_string(),
+        }),
+        AuthProviderKind::Bitbucket => Arc::new(BitbucketProvider { client }),
+        AuthProviderKind::Oidc => {
+            let issuer = config
+                .oidc_issuer
+                .clone()
+                .ok_or_else(|| anyhow!("--oidc-issuer is required when using the oidc provider"))?;
+            Arc::new(OidcProvider {
+                client,
+                issuer: issuer.trim_end_matches('/').to_string(),
+                username_claim: config.oidc_username_claim.clone(),
+                userinfo_endpoint: OnceCell::new(),
+            })
+        }
+    };
+
+    Ok(provider)
+}
+
+/// Split a `username:token` pair, stripping any `Bearer ` prefix from the token
+fn split_token(token: &str) -> (Option<&str>, &str) {
+    if let Some((username, token_part)) = token.split_once(':') {
+        (
+            Some(username),
+            token_part
+                .strip_prefix("Bearer ")
+                .unwrap_or(token_part)
+                .trim(),
+        )
+    } else {
+        (None, token.strip_prefix("Bearer ").unwrap_or(token).trim())
+    }
+}
+
+/// Call a "who am I" endpoint and pull the username out of `field`
+async fn fetch_username(
+    client: &Client,
+    provider: &str,
+    url: &str,
+    token: &str,
+    field: &str,
+) -> Option<String> {
+    let response = match client
+        .get(url)
+        .header("User-Agent", "faasta-server")
+        .header("Authorization", format!("Bearer {token}"))
+        .send()
+        .await
+    {
+        Ok(resp) => resp,
+        Err(e) => {
+            error!("{provider} API request failed: {e}");
+            return None;
+        }
+    };
+
+    if !response.status().is_success() {
+        warn!(
+            "{provider} API returned error status: {}",
+            response.status()
+        );
+        return None;
+    }
+
+    let user: Value = match response.json().await {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to parse {provider} response: {e}");
+            return None;
+        }
+    };
+
+    user[field].as_str().map(str::to_string)
+}
+
+/// Whether anything answers at `url`, whatever the status
+async fn answers(client: &Client, url: &str) -> Result<()> {
+    client
+        .get(url)
+        .header("User-Agent", "faasta-server")
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Check a username reported by the provider against the one the CLI sent
+fn verify_username(
+    provider: &str,
+    provided: Option<&str>,
+    api_username: Option<String>,
+) -> (String, bool) {
+    let Some(api_username) = api_username.filter(|name| !name.is_empty()) else {
+        return (String::new(), false);
+    };
+
+    if let Some(provided) = provided {
+        if provided != api_username {
+            warn!(
+                "Username mismatch: provided '{}', {} returned '{}'",
+                provided, provider, api_username
+            );
+            return (api_username, false);
+        }
+    }
+
+    (api_username, true)
+}
+
+/// Validates GitHub personal access tokens and OAuth tokens
+pub struct GitHubProvider {
+    client: Client,
+}
+
+impl AuthProvider for GitHubProvider {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn authenticate<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<(String, bool)>> {
+        Box::pin(async move {
+            let (provided, token_value) = split_token(token);
+            let username = fetch_username(
+                &self.client,
+                self.name(),
+                "https://api.github.com/user",
+                token_value,
+                "login",
+            )
+            .await;
+            Ok(verify_username(self.name(), provided, username))
+        })
+    }
+
+    fn probe(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(answers(&self.client, "https://api.github.com"))
+    }
+
+    fn org_role<'a>(
+        &'a self,
+        token: &'a str,
+        org: &'a str,
+    ) -> BoxFuture<'a, Result<Option<TeamRole>>> {
+        Box::pin(async move {
+            let (_, token_value) = split_token(token);
+            let response = self
+                .client
+                .get(format!(
+                    "https://api.github.com/user/memberships/orgs/{org}"
+                ))
+                .header("User-Agent", "faasta-server")
+                .header("Authorization", format!("Bearer {token_value}"))
+                .send()
+                .await?;
+
+            // 404 and 403 mean "not a member" or "token can't read org membership"
+            if !response.status().is_success() {
+                return Ok(None);
+            }
+
+            let membership: Value = response.json().await?;
+            if membership["state"].as_str() != Some("active") {
+                return Ok(None);
+            }
+            Ok(Some(match membership["role"].as_str() {
+                Some("admin") => TeamRole::Owner,
+                _ => TeamRole::Developer,
+            }))
+        })
+    }
+}
+
+/// Validates GitLab personal access tokens against gitlab.com or a self-managed instance
+pub struct GitLabProvider {
+    client: Client,
+    base_url: String,
+}
+
+impl AuthProvider for GitLabProvider {
+    fn name(&self) -> &'static str {
+        "GitLab"
+    }
+
+    fn authenticate<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<(String, bool)>> {
+        Box::pin(async move {
+            let (provided, token_value) = split_token(token);
+            let url = format!("{}/api/v4/user", self.base_url);
+            let username =
+                fetch_username(&self.client, self.name(), &url, token_value, "username").await;
+            Ok(verify_username(self.name(), provided, username))
+        })
+    }
+
+    fn probe(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let url = format!("{}/api/v4/version", self.base_url);
+            answers(&self.client, &url).await
+        })
+    }
+}
+
+/// Validates Bitbucket Cloud OAuth access tokens
+pub struct BitbucketProvider {
+    client: Client,
+}
+
+impl AuthProvider for BitbucketProvider {
+    fn name(&self) -> &'static str {
+        "Bitbucket"
+    }
+
+    fn authenticate<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<(String, bool)>> {
+        Box::pin(async move {
+            let (provided, token_value) = split_token(token);
+            let username = fetch_username(
+                &self.client,
+                self.name(),
+                "https://api.bitbucket.org/2.0/user",
+                token_value,
+                "username",
+            )
+            .await;
+            Ok(verify_username(self.name(), provided, username))
+        })
+    }
+
+    fn probe(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(answers(&self.client, "https://api.bitbucket.org/2.0/"))
+    }
+}
+
+/// Validates access tokens issued by any OpenID Connect provider via its userinfo endpoint
+pub struct OidcProvider {
+    client: Client,
+    issuer: String,
+    username_claim: String,
+    userinfo_endpoint: OnceCell<String>,
+}
+
+impl OidcProvider {
+    /// Look up the userinfo endpoint from the issuer's discovery document (once)
+    async fn userinfo_endpoint(&self) -> Result<&str> {
+        let endpoint = self
+            .userinfo_endpoint
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer);
+                let discovery: Value = self
+                    .client
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                discovery["userinfo_endpoint"]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow!("No userinfo_endpoint in discovery document at {url}"))
+            })
+            .await?;
+        Ok(endpoint.as_str())
+    }
+}
+
+impl AuthProvider for OidcProvider {
+    fn name(&self) -> &'static str {
+        "OIDC"
+    }
+
+    fn authenticate<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<(String, bool)>> {
+        Box::pin(async move {
+            let (provided, token_value) = split_token(token);
+            let endpoint = match self.userinfo_endpoint().await {
+                Ok(endpoint) => endpoint,
+                Err(e) => {
+                    error!("OIDC discovery failed for {}: {}", self.issuer, e);
+                    return Ok((String::new(), false));
+                }
+            };
+            let username = fetch_username(
+                &self.client,
+                self.name(),
+                endpoint,
+                token_value,
+                &self.username_claim,
+            )
+            .await;
+            Ok(verify_username(self.name(), provided, username))
+        })
+    }
+
+    fn probe(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let url = format!("{}/.well-known/openid-configuration", self.issuer);
+            answers(&self.client, &url).await
+        })
+    }
+}
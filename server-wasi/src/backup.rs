@@ -0,0 +1,632 @@
+//! Backups of the server's state, for recovering from disk loss.
+//!
+//! A backup is a tar file holding every sled tree of the metadata database (users,
+//! function metadata, specs with their environment variables, and everything else
+//! kept there), the WebAssembly of every function in artifact storage, trashed ones
+//! included, and a `manifest.json` listing them with the artifacts' digests. As it
+//! holds secrets, it's only readable by its owner.
+//!
+//! It isn't a point-in-time snapshot: trees are copied one after another, so a
+//! write made meanwhile can be in some of them and not others. The artifact of each
+//! function is checked against the digest the backed up database records for it,
+//! though, and a backup a publish raced with is taken again.
+//!
+//! `cargo faasta admin backup` takes one on a running server, in `--backup-dir`,
+//! and downloads it. `server-wasi backup` and `server-wasi restore` work on the
+//! database of a stopped server directly. Restoring checks the whole file before
+//! writing anything, and every function's WebAssembly against the server's
+//! artifact policies, then writes the artifacts and replaces the trees in a single
+//! transaction, so a failed restore leaves the database as it was. The metrics
+//! database isn't included. Servers keeping metadata in Postgres refuse to take
+//! backups, which would miss it: the database is backed up with its own tools.
+
+use anyhow::{anyhow, bail, Context, Result};
+use faasta_interface::{BackupInfo, UPLOAD_CHUNK_SIZE};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionResult, TransactionError, Transactional};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::function_data::{artifact_digest, ARTIFACT_DIGESTS_TREE};
+use crate::storage::{wasm_key, ArtifactStorage};
+use crate::trash::{trashed_key, TRASH_TREE};
+
+/// Backups taken over RPC, only set on a running server
+pub static BACKUPS: OnceCell<Backups> = OnceCell::new();
+
+/// Version of the backup format written, and the newest one restored
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+/// Directory of the tar holding one file per sled tree
+const TREES_DIR: &str = "db/";
+/// Directory of the tar holding artifacts, by storage key
+const ARTIFACTS_DIR: &str = "artifacts/";
+/// Times a backup is taken before giving up on publishes racing with it
+const ATTEMPTS: usize = 3;
+
+/// A backup being written
+type TarFile = tar::Builder<BufWriter<File>>;
+
+/// What a backup holds, written last
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    created_at: String,
+    trees: Vec<TreeEntry>,
+    artifacts: Vec<ArtifactEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TreeEntry {
+    name: String,
+    records: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArtifactEntry {
+    key: String,
+    size: u64,
+    digest: String,
+}
+
+impl Manifest {
+    fn info(&self, name: String, size_bytes: u64) -> BackupInfo {
+        BackupInfo {
+            name,
+            created_at: self.created_at.clone(),
+            size_bytes,
+            records: self.trees.iter().map(|tree| tree.records).sum(),
+            artifacts: self.artifacts.len() as u64,
+        }
+    }
+}
+
+/// Backups kept on the server, newest `kept` of them
+pub struct Backups {
+    dir: PathBuf,
+    kept: usize,
+    /// Held while a backup is taken, one at a time
+    taking: Mutex<()>,
+}
+
+impl Backups {
+    pub fn new(dir: &Path, kept: usize) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            kept: kept.max(1),
+            taking: Mutex::new(()),
+        })
+    }
+
+    /// Take a backup now, deleting the oldest ones beyond the number kept
+    pub async fn take(&self, db: &sled::Db, storage: &dyn ArtifactStorage) -> Result<BackupInfo> {
+        let _taking = self.taking.lock().await;
+        // Backups are taken one at a time and take longer than a millisecond, so
+        // names to the millisecond don't repeat
+        let name = format!(
+            "faasta-backup-{}.tar",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        let info = write(db, storage, &self.dir.join(&name)).await?;
+
+        let mut backups = self.names()?;
+        backups.sort();
+        let expired = backups.len().saturating_sub(self.kept);
+        for old in &backups[..expired] {
+            fs::remove_file(self.dir.join(old))?;
+        }
+        Ok(info)
+    }
+
+    /// Chunk `index` of the backup `name`, empty past its end
+    pub fn chunk(&self, name: &str, index: u32) -> Result<Option<Vec<u8>>> {
+        if !self.names()?.iter().any(|backup| backup == name) {
+            return Ok(None);
+        }
+        let mut file = File::open(self.dir.join(name))?;
+        file.seek(SeekFrom::Start(index as u64 * UPLOAD_CHUNK_SIZE as u64))?;
+        let mut chunk = Vec::with_capacity(UPLOAD_CHUNK_SIZE);
+        file.take(UPLOAD_CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)?;
+        Ok(Some(chunk))
+    }
+
+    fn names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.starts_with("faasta-backup-") && name.ends_with(".tar") {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+}
+
+/// Write a backup of `db` and `storage` to `path`, taking it again if a publish
+/// changed an artifact meanwhile
+pub async fn write(
+    db: &sled::Db,
+    storage: &dyn ArtifactStorage,
+    path: &Path,
+) -> Result<BackupInfo> {
+    let temp_path = path.with_extension("tar.tmp");
+    for attempt in 1..=ATTEMPTS {
+        let written = write_once(db, storage, &temp_path).await;
+        match written {
+            Ok(Some(manifest)) => {
+                fs::rename(&temp_path, path)?;
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                return Ok(manifest.info(name, fs::metadata(path)?.len()));
+            }
+            Ok(None) => info!(
+                "An artifact changed during backup attempt {}, taking it again",
+                attempt
+            ),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                return Err(e);
+            }
+        }
+    }
+    let _ = fs::remove_file(&temp_path);
+    bail!("Artifacts kept changing while the backup was taken, try again when fewer functions are being published")
+}
+
+/// The manifest of the backup written to `path`, or `None` if an artifact doesn't
+/// match the database written before it. The database and the file are read and
+/// written on blocking threads.
+async fn write_once(
+    db: &sled::Db,
+    storage: &dyn ArtifactStorage,
+    path: &Path,
+) -> Result<Option<Manifest>> {
+    let (db, path) = (db.clone(), path.to_path_buf());
+    let (mut tar, mut manifest, digests, trashed) =
+        tokio::task::spawn_blocking(move || write_trees(&db, &path)).await??;
+
+    // Precompiled artifacts are left out, they're compiled again from the WebAssembly
+    let mut keys: Vec<String> = storage
+        .list()
+        .await?
+        .into_iter()
+        .filter(|key| key.ends_with(".wasm") && !key.starts_with('.'))
+        .collect();
+    keys.extend(trashed);
+    for key in keys {
+        // Trashed functions can be purged meanwhile; they're gone either way
+        let Some(wasm) = storage.get(&key).await? else {
+            continue;
+        };
+        let digest = artifact_digest(&wasm);
+        if digests
+            .get(&key)
+            .is_some_and(|recorded| recorded != digest.as_bytes())
+        {
+            return Ok(None);
+        }
+        manifest.artifacts.push(ArtifactEntry {
+            key: key.clone(),
+            size: wasm.len() as u64,
+            digest,
+        });
+        tar = tokio::task::spawn_blocking(move || {
+            append(&mut tar, &format!("{ARTIFACTS_DIR}{key}"), &wasm)?;
+            Ok::<_, anyhow::Error>(tar)
+        })
+        .await??;
+    }
+
+    let encoded = serde_json::to_vec_pretty(&manifest)?;
+    tokio::task::spawn_blocking(move || {
+        append(&mut tar, MANIFEST, &encoded)?;
+        let out = tar.into_inner()?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+    Ok(Some(manifest))
+}
+
+/// Start a backup at `path` with every tree of `db`. Returns it with its manifest
+/// so far, the artifact digests the database records, by storage key, and the
+/// storage keys of trashed artifacts.
+#[allow(clippy::type_complexity)]
+fn write_trees(
+    db: &sled::Db,
+    path: &Path,
+) -> Result<(TarFile, Manifest, HashMap<String, Vec<u8>>, Vec<String>)> {
+    let mut tar = tar::Builder::new(BufWriter::new(create_private(path)?));
+    let mut manifest = Manifest {
+        version: FORMAT_VERSION,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        trees: Vec::new(),
+        artifacts: Vec::new(),
+    };
+
+    let mut digests = HashMap::new();
+    let mut trashed = Vec::new();
+    for name in db.tree_names() {
+        let name = String::from_utf8(name.to_vec()).context("Tree name isn't UTF-8")?;
+        let tree = db.open_tree(&name)?;
+        let mut data = Vec::new();
+        let mut records = 0;
+        for entry in tree.iter() {
+            let (key, value) = entry?;
+            if name == ARTIFACT_DIGESTS_TREE {
+                digests.insert(wasm_key(&String::from_utf8_lossy(&key)), value.to_vec());
+            } else if name == TRASH_TREE {
+                trashed.push(trashed_key(&String::from_utf8_lossy(&key)));
+            }
+            for field in [&key, &value] {
+                data.extend_from_slice(&(field.len() as u32).to_be_bytes());
+                data.extend_from_slice(field);
+            }
+            records += 1;
+        }
+        append(&mut tar, &format!("{TREES_DIR}{name}"), &data)?;
+        manifest.trees.push(TreeEntry { name, records });
+    }
+    Ok((tar, manifest, digests, trashed))
+}
+
+/// Create the file at `path`, replacing any, readable and writable by its owner
+/// only
+fn create_private(path: &Path) -> Result<File> {
+    let _ = fs::remove_file(path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))
+}
+
+/// Add a regular file holding `data` to `tar`
+fn append(tar: &mut TarFile, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    tar.append_data(&mut header, path, data)
+        .with_context(|| format!("Failed to add {path} to the backup"))
+}
+
+/// Restore the backup at `path` into `db` and `storage`. Unless `force` is set,
+/// trees of the backup that already have records in `db` are refused; with it,
+/// they're replaced. The WebAssembly of every function must pass `check`, given
+/// its name, before anything is written.
+pub async fn restore<C, F>(
+    db: &sled::Db,
+    storage: &dyn ArtifactStorage,
+    path: &Path,
+    force: bool,
+    check: C,
+) -> Result<BackupInfo>
+where
+    C: Fn(String, Vec<u8>) -> F,
+    F: Future<Output = Result<()>>,
+{
+    let manifest = {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || verify(&path)).await??
+    };
+    if !force {
+        for tree in &manifest.trees {
+            if tree.records > 0 && !db.open_tree(&tree.name)?.is_empty() {
+                bail!(
+                    "The database already has records in '{}'; restore with --force to replace them",
+                    tree.name
+                );
+            }
+        }
+    }
+    // Trashed functions are checked when they're taken out of the trash
+    let check = &check;
+    for_each_entry(path, |name, data| async move {
+        let function = name
+            .strip_prefix(ARTIFACTS_DIR)
+            .filter(|key| !key.contains('/'))
+            .and_then(|key| key.strip_suffix(".wasm"));
+        match function {
+            Some(function) => check(function.to_string(), data)
+                .await
+                .with_context(|| format!("Function '{function}' can't be restored")),
+            None => Ok(()),
+        }
+    })
+    .await?;
+
+    // Artifacts are written as they're read, the trees kept for the transaction
+    let mut trees = Vec::new();
+    for_each_entry(path, |name, data| {
+        let artifact = match name.strip_prefix(TREES_DIR) {
+            Some(tree) => {
+                trees.push((tree.to_string(), data));
+                None
+            }
+            None => name
+                .strip_prefix(ARTIFACTS_DIR)
+                .map(|key| (key.to_string(), data)),
+        };
+        async move {
+            match artifact {
+                Some((key, data)) => storage.put(&key, &data).await,
+                None => Ok(()),
+            }
+        }
+    })
+    .await?;
+    let db = db.clone();
+    tokio::task::spawn_blocking(move || restore_trees(&db, &trees)).await??;
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(manifest.info(name, fs::metadata(path)?.len()))
+}
+
+/// Call `each` with the path and contents of every regular file of the backup at
+/// `path`, read off the runtime
+async fn for_each_entry<E, F>(path: &Path, mut each: E) -> Result<()>
+where
+    E: FnMut(String, Vec<u8>) -> F,
+    F: Future<Output = Result<()>>,
+{
+    let (sender, mut entries) = tokio::sync::mpsc::channel(1);
+    let reader = {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            read_entries(&path, |name, data| {
+                sender
+                    .blocking_send((name, data))
+                    .map_err(|_| anyhow!("The restore stopped"))
+            })
+        })
+    };
+    while let Some((name, data)) = entries.recv().await {
+        each(name, data).await?;
+    }
+    reader.await?
+}
+
+/// Replace the records of each of `trees`, by name, with those of its file, all in
+/// one transaction
+fn restore_trees(db: &sled::Db, trees: &[(String, Vec<u8>)]) -> Result<()> {
+    if trees.is_empty() {
+        return Ok(());
+    }
+    let opened = trees
+        .iter()
+        .map(|(name, _)| db.open_tree(name))
+        .collect::<sled::Result<Vec<_>>>()?;
+    // Records there now are removed, unless the backup has them too
+    let existing = opened
+        .iter()
+        .map(|tree| tree.iter().keys().collect::<sled::Result<Vec<_>>>())
+        .collect::<sled::Result<Vec<_>>>()?;
+    let restored = trees
+        .iter()
+        .map(|(_, data)| records(data))
+        .collect::<Result<Vec<_>>>()?;
+    opened
+        .as_slice()
+        .transaction(|views| -> ConflictableTransactionResult<(), ()> {
+            for ((view, existing), restored) in views.iter().zip(&existing).zip(&restored) {
+                for key in existing {
+                    view.remove(key)?;
+                }
+                for &(key, value) in restored {
+                    view.insert(key, value)?;
+                }
+            }
+            Ok(())
+        })
+        .map_err(|e: TransactionError<()>| anyhow!("Failed to restore the database: {e:?}"))?;
+    db.flush()?;
+    Ok(())
+}
+
+/// The manifest of the backup at `path`, once everything it lists is checked to be
+/// there and intact
+fn verify(path: &Path) -> Result<Manifest> {
+    let mut trees = HashMap::new();
+    let mut artifacts = HashMap::new();
+    let mut manifest = None;
+    read_entries(path, |name, data| {
+        if let Some(tree) = name.strip_prefix(TREES_DIR) {
+            trees.insert(tree.to_string(), records(&data)?.len() as u64);
+        } else if let Some(key) = name.strip_prefix(ARTIFACTS_DIR) {
+            artifacts.insert(key.to_string(), artifact_digest(&data));
+        } else if name == MANIFEST {
+            manifest = Some(serde_json::from_slice::<Manifest>(&data)?);
+        }
+        Ok(())
+    })?;
+
+    let manifest = manifest.ok_or_else(|| anyhow!("Not a backup, it has no {MANIFEST}"))?;
+    if manifest.version > FORMAT_VERSION {
+        bail!(
+            "The backup has format version {}, this server reads up to {FORMAT_VERSION}",
+            manifest.version
+        );
+    }
+    for tree in &manifest.trees {
+        if trees.get(&tree.name) != Some(&tree.records) {
+            bail!(
+                "The backup of tree '{}' is missing or incomplete",
+                tree.name
+            );
+        }
+    }
+    for artifact in &manifest.artifacts {
+        if artifacts.get(&artifact.key) != Some(&artifact.digest) {
+            bail!("Artifact '{}' is missing or corrupt", artifact.key);
+        }
+    }
+    Ok(manifest)
+}
+
+/// Call `each` with the path and contents of every regular file of the backup at
+/// `path`, in order
+fn read_entries(path: &Path, mut each: impl FnMut(String, Vec<u8>) -> Result<()>) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut archive = tar::Archive::new(BufReader::new(file));
+    for entry in archive.entries().context("Corrupt backup")? {
+        let mut entry = entry.context("Corrupt backup")?;
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data).context("Truncated backup")?;
+        each(name, data)?;
+    }
+    Ok(())
+}
+
+/// The key-value pairs of a tree's file
+fn records(mut data: &[u8]) -> Result<Vec<(&[u8], &[u8])>> {
+    fn field<'a>(data: &mut &'a [u8]) -> Result<&'a [u8]> {
+        let (len, rest) = data
+            .split_first_chunk::<4>()
+            .ok_or_else(|| anyhow!("Truncated tree record"))?;
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len {
+            bail!("Truncated tree record");
+        }
+        let (field, rest) = rest.split_at(len);
+        *data = rest;
+        Ok(field)
+    }
+    let mut records = Vec::new();
+    while !data.is_empty() {
+        let key = field(&mut data)?;
+        let value = field(&mut data)?;
+        records.push((key, value));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function_data::record_artifact;
+    use crate::metadata_store::SledStore;
+    use crate::storage::{build_storage, StorageConfig, StorageKind};
+
+    fn storage(dir: &Path) -> std::sync::Arc<dyn ArtifactStorage> {
+        build_storage(&StorageConfig {
+            kind: StorageKind::Filesystem,
+            functions_dir: dir.to_path_buf(),
+            s3_bucket: None,
+            s3_region: String::new(),
+            s3_endpoint: None,
+            s3_prefix: String::new(),
+            s3_access_key_id: None,
+            s3_secret_access_key: None,
+            s3_session_token: None,
+        })
+        .unwrap()
+    }
+
+    async fn allow(_: String, _: Vec<u8>) -> Result<()> {
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backups_restore_records_and_artifacts() {
+        let dir = std::env::temp_dir().join(format!("faasta-backup-{}", rand::random::<u64>()));
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let functions = storage(&dir.join("functions"));
+        db.open_tree("user_data")
+            .unwrap()
+            .insert("alice", "profile")
+            .unwrap();
+        let long_name = "f".repeat(90);
+        for name in ["hello", long_name.as_str()] {
+            functions
+                .put(&wasm_key(name), name.as_bytes())
+                .await
+                .unwrap();
+            record_artifact(&SledStore::new(&db), name, name.as_bytes()).unwrap();
+        }
+
+        let path = dir.join("backup.tar");
+        let info = write(&db, functions.as_ref(), &path).await.unwrap();
+        assert_eq!(info.artifacts, 2);
+        assert_eq!(info.records, 3);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let restored_db = sled::Config::new().temporary(true).open().unwrap();
+        let restored = storage(&dir.join("restored"));
+        restore(&restored_db, restored.as_ref(), &path, false, allow)
+            .await
+            .unwrap();
+        let user = restored_db.open_tree("user_data").unwrap().get("alice");
+        assert_eq!(user.unwrap().unwrap(), "profile");
+        let wasm = restored.get(&wasm_key(&long_name)).await.unwrap();
+        assert_eq!(wasm.unwrap(), long_name.as_bytes());
+
+        // Records already there are only replaced on request
+        assert!(
+            restore(&restored_db, restored.as_ref(), &path, false, allow)
+                .await
+                .is_err()
+        );
+        // Records the backup doesn't have are dropped along with the rest
+        let users = restored_db.open_tree("user_data").unwrap();
+        users.insert("mallory", "profile").unwrap();
+        // but not when a function fails the check, which leaves everything as it was
+        let refuse_hello = |name: String, _| async move {
+            match name.as_str() {
+                "hello" => Err(anyhow!("refused")),
+                _ => Ok(()),
+            }
+        };
+        assert!(
+            restore(&restored_db, restored.as_ref(), &path, true, refuse_hello)
+                .await
+                .is_err()
+        );
+        assert!(users.get("mallory").unwrap().is_some());
+        restore(&restored_db, restored.as_ref(), &path, true, allow)
+            .await
+            .unwrap();
+        assert!(users.get("mallory").unwrap().is_none());
+        assert!(users.get("alice").unwrap().is_some());
+
+        // An artifact that no longer matches its recorded digest isn't backed up
+        functions.put(&wasm_key("hello"), b"changed").await.unwrap();
+        assert!(write(&db, functions.as_ref(), &path).await.is_err());
+
+        // Nor is a corrupt backup restored
+        let mut tar = fs::read(&path).unwrap();
+        tar[0] ^= 1;
+        fs::write(&path, tar).unwrap();
+        assert!(verify(&path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
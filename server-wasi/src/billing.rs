@@ -0,0 +1,573 @@
+//! Optional billing integration.
+//!
+//! The usage accounted per user and billing period by [`crate::usage`] is
+//! periodically pushed to the configured [`BillingProvider`]. Providers also parse
+//! subscription webhooks, whose plan changes are applied to the user's project
+//! limit. With the default `none` provider nothing in this module runs.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use clap::ValueEnum;
+use faasta_interface::UsageRecord;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{HeaderMap, Request, Response};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{error, info, warn};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+
+use crate::usage::USAGE;
+use crate::wasi_server::{text_response, SERVER};
+
+/// Sled tree mapping usernames to the provider's customer id
+const CUSTOMER_TREE: &str = "billing_customers";
+/// Sled tree holding the request counts already reported to Stripe, keyed by `period/username`
+const STRIPE_SENT_TREE: &str = "billing_stripe_sent";
+/// Largest webhook body we accept
+const MAX_WEBHOOK_BODY: usize = 64 * 1024;
+/// Allowed clock skew for signed webhook timestamps
+const WEBHOOK_TOLERANCE_SECS: i64 = 300;
+
+/// Global billing service, only set when a provider is configured
+pub static BILLING: OnceCell<Billing> = OnceCell::new();
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Billing backends selectable in server config
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BillingProviderKind {
+    None,
+    Webhook,
+    Stripe,
+}
+
+/// Subscription change reported by a billing provider webhook
+#[derive(Clone, Debug, Deserialize)]
+pub struct SubscriptionEvent {
+    pub username: String,
+    #[serde(default)]
+    pub customer_id: Option<String>,
+    /// Plan tier name, matched against the configured plans
+    #[serde(default)]
+    pub plan: Option<String>,
+    /// Whether the subscription is currently paid up
+    pub active: bool,
+}
+
+/// A billing backend that receives usage and reports subscription changes
+pub trait BillingProvider: Send + Sync {
+    /// Short provider name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Verify and decode a subscription webhook.
+    /// Returns `Ok(None)` for authentic events that don't concern subscriptions.
+    fn parse_webhook(&self, headers: &HeaderMap, body: &[u8]) -> Result<Option<SubscriptionEvent>>;
+
+    /// Push usage records; `customers` maps usernames to provider customer ids
+    fn export_usage<'a>(
+        &'a self,
+        records: &'a [UsageRecord],
+        customers: &'a HashMap<String, String>,
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Settings needed to build a [`BillingProvider`]
+#[derive(Clone, Debug)]
+pub struct BillingConfig {
+    pub kind: BillingProviderKind,
+    pub webhook_secret: Option<String>,
+    pub export_url: Option<String>,
+    pub stripe_api_key: Option<String>,
+    pub stripe_meter_event: String,
+    /// Plan tiers as `name=max_projects` pairs
+    pub plans: String,
+}
+
+/// Build the billing service selected in server config, or `None` if billing is disabled
+pub fn build_billing(db: &sled::Db, config: &BillingConfig) -> Result<Option<Billing>> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let secret = || {
+        config
+            .webhook_secret
+            .clone()
+            .ok_or_else(|| anyhow!("--billing-webhook-secret is required for billing"))
+    };
+
+    let provider: Arc<dyn BillingProvider> = match config.kind {
+        BillingProviderKind::None => return Ok(None),
+        BillingProviderKind::Webhook => Arc::new(WebhookBilling {
+            client,
+            secret: secret()?,
+            export_url: config.export_url.clone(),
+        }),
+        BillingProviderKind::Stripe => Arc::new(StripeBilling {
+            client,
+            webhook_secret: secret()?,
+            api_key: config
+                .stripe_api_key
+                .clone()
+                .ok_or_else(|| anyhow!("--stripe-api-key is required for Stripe billing"))?,
+            meter_event: config.stripe_meter_event.clone(),
+            sent_tree: db.open_tree(STRIPE_SENT_TREE)?,
+        }),
+    };
+
+    Ok(Some(Billing {
+        provider,
+        plans: parse_plans(&config.plans)?,
+        customer_tree: db.open_tree(CUSTOMER_TREE)?,
+    }))
+}
+
+/// Parse `free=10,pro=50` into a plan name -> project limit map
+pub fn parse_plans(spec: &str) -> Result<HashMap<String, usize>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, limit) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid plan '{pair}', expected name=max_projects"))?;
+            let limit = limit
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid project limit in plan '{pair}'"))?;
+            Ok((name.trim().to_string(), limit))
+        })
+        .collect()
+}
+
+pub struct Billing {
+    provider: Arc<dyn BillingProvider>,
+    plans: HashMap<String, usize>,
+    customer_tree: sled::Tree,
+}
+
+impl Billing {
+    pub fn provider_name(&self) -> &'static str {
+        self.provider.name()
+    }
+
+    /// Apply a verified subscription webhook to the user's limits
+    pub fn handle_webhook(&self, headers: &HeaderMap, body: &[u8]) -> Result<()> {
+        let Some(event) = self.provider.parse_webhook(headers, body)? else {
+            return Ok(());
+        };
+        let server = SERVER
+            .get()
+            .ok_or_else(|| anyhow!("Server not initialised"))?;
+
+        if let Some(customer_id) = &event.customer_id {
+            self.customer_tree
+                .insert(event.username.as_bytes(), customer_id.as_bytes())?;
+        }
+
+        // Inactive subscriptions and unknown plans fall back to the default limit
+        let limit = event
+            .plan
+            .as_deref()
+            .filter(|_| event.active)
+            .and_then(|plan| self.plans.get(plan).copied());
+        server
+            .github_auth
+            .set_project_limit(&event.username, limit)?;
+
+        info!(
+            "Applied {} subscription update for '{}': plan={:?}, active={}, limit={}",
+            self.provider.name(),
+            event.username,
+            event.plan,
+            event.active,
+            server.github_auth.project_limit(&event.username)
+        );
+        Ok(())
+    }
+
+    /// Accumulate and push the current period's usage to the provider
+    pub async fn export(&self) -> Result<()> {
+        let records = USAGE
+            .get()
+            .ok_or_else(|| anyhow!("Usage accounting not initialised"))?
+            .accumulate()
+            .await?;
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let customers = self
+            .customer_tree
+            .iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                Some((
+                    String::from_utf8(key.to_vec()).ok()?,
+                    String::from_utf8(value.to_vec()).ok()?,
+                ))
+            })
+            .collect();
+
+        self.provider.export_usage(&records, &customers).await?;
+        info!(
+            "Exported {} usage records to {}",
+            records.len(),
+            self.provider.name()
+        );
+        Ok(())
+    }
+}
+
+/// Spawn a Tokio task that exports usage every `interval_secs` seconds
+pub fn spawn_periodic_export(interval_secs: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            if let Some(billing) = BILLING.get() {
+                if let Err(e) = billing.export().await {
+                    error!("Failed to export usage: {}", e);
+                }
+            }
+        }
+    });
+}
+
+/// HTTP handler for `POST /v1/billing/webhook`
+pub async fn handle_webhook_request(
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<HyperOutgoingBody>> {
+    let Some(billing) = BILLING.get() else {
+        return text_response(404, "Billing is not enabled on this server");
+    };
+
+    let (parts, body) = req.into_parts();
+    let body = match Limited::new(body, MAX_WEBHOOK_BODY).collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => return text_response(413, "Webhook body too large"),
+    };
+
+    match billing.handle_webhook(&parts.headers, &body) {
+        Ok(()) => Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(HyperOutgoingBody::new(
+                Full::new(Bytes::from_static(b"{\"received\":true}"))
+                    .map_err(|_| ErrorCode::InternalError(None))
+                    .boxed(),
+            ))?),
+        Err(e) => {
+            warn!("Rejected billing webhook: {}", e);
+            text_response(400, "Invalid webhook")
+        }
+    }
+}
+
+fn verify_hmac(secret: &str, message: &[u8], signature_hex: &str) -> Result<()> {
+    let signature = hex::decode(signature_hex).context("Signature is not valid hex")?;
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())?;
+    mac.update(message);
+    mac.verify_slice(&signature)
+        .map_err(|_| anyhow!("Signature mismatch"))
+}
+
+fn sign_hmac(secret: &str, message: &[u8]) -> Result<String> {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())?;
+    mac.update(message);
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Generic provider for self-built billing systems.
+///
+/// Webhooks are JSON [`SubscriptionEvent`]s signed with
+/// `X-Faasta-Signature: sha256=<hex hmac of the body>`; usage records are POSTed
+/// as a JSON array to the export URL with the same signature scheme.
+pub struct WebhookBilling {
+    client: reqwest::Client,
+    secret: String,
+    export_url: Option<String>,
+}
+
+impl BillingProvider for WebhookBilling {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn parse_webhook(&self, headers: &HeaderMap, body: &[u8]) -> Result<Option<SubscriptionEvent>> {
+        let signature = headers
+            .get("X-Faasta-Signature")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("sha256="))
+            .ok_or_else(|| anyhow!("Missing X-Faasta-Signature header"))?;
+        verify_hmac(&self.secret, body, signature)?;
+        Ok(Some(serde_json::from_slice(body)?))
+    }
+
+    fn export_usage<'a>(
+        &'a self,
+        records: &'a [UsageRecord],
+        _customers: &'a HashMap<String, String>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let Some(url) = &self.export_url else {
+                return Ok(());
+            };
+            let body = serde_json::to_vec(records)?;
+            let signature = sign_hmac(&self.secret, &body)?;
+            self.client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .header("X-Faasta-Signature", format!("sha256={signature}"))
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Stripe subscriptions and usage-based billing meters.
+///
+/// Subscriptions must carry a `faasta_username` metadata entry, and the price's
+/// lookup key (or id) is used as the plan name. Usage is reported as meter
+/// events carrying the request count delta.
+pub struct StripeBilling {
+    client: reqwest::Client,
+    webhook_secret: String,
+    api_key: String,
+    meter_event: String,
+    sent_tree: sled::Tree,
+}
+
+impl StripeBilling {
+    /// Check a `Stripe-Signature: t=...,v1=...` header against the raw body
+    fn verify_signature(&self, headers: &HeaderMap, body: &[u8]) -> Result<()> {
+        let header = headers
+            .get("Stripe-Signature")
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| anyhow!("Missing Stripe-Signature header"))?;
+
+        let mut timestamp = None;
+        let mut signatures = Vec::new();
+        for part in header.split(',') {
+            match part.split_once('=') {
+                Some(("t", value)) => timestamp = Some(value),
+                Some(("v1", value)) => signatures.push(value),
+                _ => {}
+            }
+        }
+        let timestamp = timestamp.ok_or_else(|| anyhow!("Missing timestamp in signature"))?;
+
+        let age = chrono::Utc::now().timestamp() - timestamp.parse::<i64>()?;
+        if age.abs() > WEBHOOK_TOLERANCE_SECS {
+            bail!("Webhook timestamp outside tolerance");
+        }
+
+        let mut message = format!("{timestamp}.").into_bytes();
+        message.extend_from_slice(body);
+        if signatures
+            .iter()
+            .any(|signature| verify_hmac(&self.webhook_secret, &message, signature).is_ok())
+        {
+            Ok(())
+        } else {
+            bail!("No matching v1 signature")
+        }
+    }
+}
+
+impl BillingProvider for StripeBilling {
+    fn name(&self) -> &'static str {
+        "Stripe"
+    }
+
+    fn parse_webhook(&self, headers: &HeaderMap, body: &[u8]) -> Result<Option<SubscriptionEvent>> {
+        self.verify_signature(headers, body)?;
+
+        let event: Value = serde_json::from_slice(body)?;
+        let event_type = event["type"].as_str().unwrap_or_default();
+        if !event_type.starts_with("customer.subscription.") {
+            return Ok(None);
+        }
+
+        let subscription = &event["data"]["object"];
+        let Some(username) = subscription["metadata"]["faasta_username"].as_str() else {
+            warn!("Stripe subscription without faasta_username metadata, ignoring");
+            return Ok(None);
+        };
+        let price = &subscription["items"]["data"][0]["price"];
+        let status = subscription["status"].as_str().unwrap_or_default();
+
+        Ok(Some(SubscriptionEvent {
+            username: username.to_string(),
+            customer_id: subscription["customer"].as_str().map(str::to_string),
+            plan: price["lookup_key"]
+                .as_str()
+                .or(price["id"].as_str())
+                .map(str::to_string),
+            active: event_type != "customer.subscription.deleted"
+                && matches!(status, "active" | "trialing"),
+        }))
+    }
+
+    fn export_usage<'a>(
+        &'a self,
+        records: &'a [UsageRecord],
+        customers: &'a HashMap<String, String>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            // Meter events are deltas, so only send what changed since the last push
+            for record in records {
+                let Some(customer) = customers.get(&record.username) else {
+                    continue;
+                };
+                let sent_key = format!("{}/{}", record.period, record.username);
+                let already_sent = self
+                    .sent_tree
+                    .get(sent_key.as_bytes())?
+                    .and_then(|bytes| {
+                        bincode::decode_from_slice::<u64, _>(&bytes, bincode::config::standard())
+                            .ok()
+                    })
+                    .map(|(sent, _)| sent)
+                    .unwrap_or(0);
+                let delta = record.call_count.saturating_sub(already_sent);
+                if delta == 0 {
+                    continue;
+                }
+
+                let value = delta.to_string();
+                let identifier = format!(
+                    "faasta-{}-{}-{}",
+                    record.period, record.username, record.call_count
+                );
+                self.client
+                    .post("https://api.stripe.com/v1/billing/meter_events")
+                    .bearer_auth(&self.api_key)
+                    .form(&[
+                        ("event_name", self.meter_event.as_str()),
+                        ("identifier", identifier.as_str()),
+                        ("payload[stripe_customer_id]", customer.as_str()),
+                        ("payload[value]", value.as_str()),
+                    ])
+                    .send()
+                    .await?
+                    .error_for_status()?;
+
+                self.sent_tree.insert(
+                    sent_key.as_bytes(),
+                    bincode::encode_to_vec(record.call_count, bincode::config::standard())?,
+                )?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_signatures_round_trip_and_match_known_values() {
+        let message = b"The quick brown fox jumps over the lazy dog";
+        let signature = sign_hmac("key", message).unwrap();
+        assert_eq!(
+            signature,
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        assert!(verify_hmac("key", message, &signature).is_ok());
+        assert!(verify_hmac("other", message, &signature).is_err());
+        assert!(verify_hmac("key", b"tampered", &signature).is_err());
+        assert!(verify_hmac("key", message, "not hex").is_err());
+    }
+
+    #[test]
+    fn test_webhooks_must_be_signed_with_the_secret() {
+        let billing = WebhookBilling {
+            client: reqwest::Client::new(),
+            secret: "s3cret".to_string(),
+            export_url: None,
+        };
+        let body = br#"{"username":"alice","plan":"pro","active":true}"#;
+        let signed = |signature: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Faasta-Signature", signature.parse().unwrap());
+            headers
+        };
+
+        let signature = format!("sha256={}", sign_hmac("s3cret", body).unwrap());
+        let event = billing
+            .parse_webhook(&signed(&signature), body)
+            .unwrap()
+            .unwrap();
+        assert_eq!((event.username.as_str(), event.active), ("alice", true));
+
+        let forged = format!("sha256={}", sign_hmac("guess", body).unwrap());
+        assert!(billing.parse_webhook(&signed(&forged), body).is_err());
+        assert!(billing.parse_webhook(&HeaderMap::new(), body).is_err());
+    }
+
+    #[test]
+    fn test_stripe_signatures_are_checked_with_their_timestamp() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let stripe = StripeBilling {
+            client: reqwest::Client::new(),
+            webhook_secret: "whsec_test".to_string(),
+            api_key: String::new(),
+            meter_event: "faasta_requests".to_string(),
+            sent_tree: db.open_tree(STRIPE_SENT_TREE).unwrap(),
+        };
+        let body = br#"{"type":"invoice.paid"}"#;
+        let header = |timestamp: i64, secret: &str| {
+            let mut message = format!("{timestamp}.").into_bytes();
+            message.extend_from_slice(body);
+            let signature = sign_hmac(secret, &message).unwrap();
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "Stripe-Signature",
+                format!("t={timestamp},v1=0000,v1={signature}")
+                    .parse()
+                    .unwrap(),
+            );
+            headers
+        };
+        let now = chrono::Utc::now().timestamp();
+
+        assert!(stripe
+            .verify_signature(&header(now, "whsec_test"), body)
+            .is_ok());
+        assert!(stripe
+            .verify_signature(&header(now, "whsec_other"), body)
+            .is_err());
+        // A replay of an old event is refused even with a valid signature
+        let stale = now - WEBHOOK_TOLERANCE_SECS - 1;
+        assert!(stripe
+            .verify_signature(&header(stale, "whsec_test"), body)
+            .is_err());
+        // Events other than subscription changes are ignored once verified
+        assert!(stripe
+            .parse_webhook(&header(now, "whsec_test"), body)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_plans_parse_into_project_limits() {
+        let plans = parse_plans("free=10, pro = 50,").unwrap();
+        assert_eq!(plans.get("free"), Some(&10));
+        assert_eq!(plans.get("pro"), Some(&50));
+        assert!(parse_plans("free").is_err());
+        assert!(parse_plans("free=many").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Client fingerprints and bot signals.
+//!
+//! Each HTTPS connection's TLS ClientHello is fingerprinted when it arrives: a
+//! digest of the cipher suites, signature schemes and ALPN protocols the client
+//! offered, in its order and without GREASE values, so requests from the same
+//! client software share a fingerprint whatever their headers claim. Each request
+//! is then scored from 0 to 100 by cheap heuristics: a missing or automation
+//! `User-Agent`, and a browser `User-Agent` without the headers, or the HTTP/2
+//! offer, every browser sends.
+//!
+//! Functions get the fingerprint, score and the signals behind it as request
+//! headers, replacing any the client sent, so they can rate-limit or challenge
+//! likely bots without parsing anything themselves. The score is a hint: crawlers
+//! and API clients score high by design, and a careful bot can score 0.
+
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, USER_AGENT};
+use hyper::http::request::Parts;
+use hyper::Version;
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls::server::ClientHello;
+
+/// Header carrying the TLS fingerprint of the client's connection
+pub const FINGERPRINT_HEADER: &str = "x-faasta-client-fingerprint";
+/// Header carrying the request's bot score, 0 to 100
+pub const BOT_SCORE_HEADER: &str = "x-faasta-bot-score";
+/// Header carrying the comma-separated signals behind the score
+pub const BOT_SIGNALS_HEADER: &str = "x-faasta-bot-signals";
+
+/// Lowercase `User-Agent` fragments of HTTP libraries, headless browsers and crawlers
+const AUTOMATION_AGENTS: &[&str] = &[
+    "curl/",
+    "wget/",
+    "python-requests",
+    "python-urllib",
+    "aiohttp",
+    "httpx",
+    "go-http-client",
+    "java/",
+    "okhttp",
+    "apache-httpclient",
+    "libwww-perl",
+    "node-fetch",
+    "axios/",
+    "scrapy",
+    "headlesschrome",
+    "phantomjs",
+    "selenium",
+    "puppeteer",
+    "playwright",
+    "bot",
+    "crawler",
+    "spider",
+];
+
+/// What the TLS ClientHello of a connection revealed, kept with its requests
+#[derive(Clone, Debug)]
+pub struct TlsHello {
+    /// Hex digest of the offered parameters
+    pub fingerprint: String,
+    /// Whether the client offered HTTP/2
+    pub offers_h2: bool,
+}
+
+impl TlsHello {
+    pub fn new(hello: &ClientHello) -> Self {
+        let mut offered = Vec::new();
+        let ciphers: Vec<u16> = hello.cipher_suites().iter().map(|&c| c.into()).collect();
+        let schemes: Vec<u16> = hello
+            .signature_schemes()
+            .iter()
+            .map(|&s| s.into())
+            .collect();
+        for values in [ciphers, schemes] {
+            let values: Vec<String> = values
+                .into_iter()
+                .filter(|&value| !is_grease(value))
+                .map(|value| value.to_string())
+                .collect();
+            offered.push(values.join("-"));
+        }
+        let protocols: Vec<&[u8]> = hello.alpn().map(Iterator::collect).unwrap_or_default();
+        offered.push(
+            protocols
+                .iter()
+                .map(|protocol| String::from_utf8_lossy(protocol))
+                .collect::<Vec<_>>()
+                .join("-"),
+        );
+        let digest = Sha256::digest(offered.join(",").as_bytes());
+        Self {
+            fingerprint: hex::encode(&digest[..16]),
+            offers_h2: protocols.contains(&b"h2".as_slice()),
+        }
+    }
+}
+
+/// GREASE values (RFC 8701), which clients pick at random
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// Signals a request gave of coming from a bot
+#[derive(Debug)]
+pub struct ClientSignals {
+    pub fingerprint: Option<String>,
+    pub score: u8,
+    pub signals: Vec<&'static str>,
+}
+
+impl ClientSignals {
+    pub fn of(parts: &Parts) -> Self {
+        let hello = parts.extensions.get::<TlsHello>();
+        let mut signals = Vec::new();
+        let mut score: u32 = 0;
+        let mut signal = |name, weight| {
+            signals.push(name);
+            score += weight;
+        };
+
+        let agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_ascii_lowercase)
+            .filter(|agent| !agent.trim().is_empty());
+        match &agent {
+            None => signal("no-user-agent", 40),
+            Some(agent) if AUTOMATION_AGENTS.iter().any(|tool| agent.contains(tool)) => {
+                signal("automation-user-agent", 60)
+            }
+            Some(agent) if agent.starts_with("mozilla/") => {
+                let headers = &parts.headers;
+                if !headers.contains_key(ACCEPT_LANGUAGE) {
+                    signal("browser-without-accept-language", 25);
+                }
+                if !headers.contains_key(ACCEPT) || !headers.contains_key(ACCEPT_ENCODING) {
+                    signal("browser-without-accept", 15);
+                }
+                // Chromium and Firefox have sent fetch metadata for years
+                let modern = agent.contains("chrome/") || agent.contains("firefox/");
+                if modern && !headers.contains_key("sec-fetch-mode") {
+                    signal("browser-without-fetch-metadata", 15);
+                }
+                if hello.is_some_and(|hello| !hello.offers_h2) {
+                    signal("browser-without-h2", 30);
+                }
+            }
+            Some(_) => {}
+        }
+        if parts.version == Version::HTTP_10 {
+            signal("http-1.0", 10);
+        }
+
+        Self {
+            fingerprint: hello.map(|hello| hello.fingerprint.clone()),
+            score: score.min(100) as u8,
+            signals,
+        }
+    }
+
+    /// Set the signal headers a function sees, dropping any the client sent
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.remove(FINGERPRINT_HEADER);
+        if let Some(fingerprint) = &self.fingerprint {
+            if let Ok(value) = HeaderValue::from_str(fingerprint) {
+                headers.insert(FINGERPRINT_HEADER, value);
+            }
+        }
+        headers.insert(BOT_SCORE_HEADER, HeaderValue::from(u16::from(self.score)));
+        headers.remove(BOT_SIGNALS_HEADER);
+        if !self.signals.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&self.signals.join(",")) {
+                headers.insert(BOT_SIGNALS_HEADER, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Request;
+
+    fn parts(headers: &[(&str, &str)], hello: Option<TlsHello>) -> Parts {
+        let mut builder = Request::builder().uri("/");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let (mut parts, ()) = builder.body(()).unwrap().into_parts();
+        if let Some(hello) = hello {
+            parts.extensions.insert(hello);
+        }
+        parts
+    }
+
+    #[test]
+    fn test_requests_are_scored_by_their_headers_and_hello() {
+        let browser = [
+            (
+                "user-agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Chrome/129.0 Safari/537.36",
+            ),
+            ("accept", "text/html"),
+            ("accept-encoding", "gzip, br"),
+            ("accept-language", "uz,en;q=0.8"),
+            ("sec-fetch-mode", "navigate"),
+        ];
+        let hello = |offers_h2| TlsHello {
+            fingerprint: "abc".to_string(),
+            offers_h2,
+        };
+
+        let signals = ClientSignals::of(&parts(&browser, Some(hello(true))));
+        assert_eq!(signals.score, 0);
+        assert_eq!(signals.fingerprint.as_deref(), Some("abc"));
+
+        // A script claiming to be Chrome, over a TLS stack without HTTP/2
+        let signals = ClientSignals::of(&parts(&browser[..1], Some(hello(false))));
+        assert_eq!(
+            signals.signals,
+            [
+                "browser-without-accept-language",
+                "browser-without-accept",
+                "browser-without-fetch-metadata",
+                "browser-without-h2"
+            ]
+        );
+        assert_eq!(signals.score, 85);
+
+        let signals = ClientSignals::of(&parts(&[("user-agent", "curl/8.5.0")], None));
+        assert_eq!(signals.signals, ["automation-user-agent"]);
+        assert_eq!(ClientSignals::of(&parts(&[], None)).score, 40);
+
+        // What the client sent in the signal headers doesn't reach the function
+        let mut spoofed = parts(&[(BOT_SCORE_HEADER, "0"), (FINGERPRINT_HEADER, "x")], None);
+        let signals = ClientSignals::of(&spoofed);
+        signals.apply(&mut spoofed.headers);
+        assert_eq!(spoofed.headers[BOT_SCORE_HEADER], "40");
+        assert!(!spoofed.headers.contains_key(FINGERPRINT_HEADER));
+        assert_eq!(spoofed.headers[BOT_SIGNALS_HEADER], "no-user-agent");
+    }
+
+    #[test]
+    fn test_grease_values_are_recognized() {
+        assert!(is_grease(0x0a0a));
+        assert!(is_grease(0xfafa));
+        assert!(!is_grease(0x1301));
+        assert!(!is_grease(0x0a1a));
+    }
+}
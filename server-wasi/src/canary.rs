@@ -0,0 +1,118 @@
+//! Canary releases.
+//!
+//! A function can have a second, canary version next to its stable one, stored as
+//! `<name>@canary.wasm` (in artifact storage) and `<name>@canary.cwasm`. The router
+//! sends the canary's weight (a percentage) of requests to it, and its calls are
+//! recorded under `<name>@canary` so metrics can be compared per version. Promoting
+//! the canary replaces the stable version; aborting it deletes it.
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::error;
+
+use crate::storage::{wasm_key, write_atomically, ArtifactStorage};
+
+/// Sled tree holding canary weights, keyed by function name
+const CANARIES_TREE: &str = "canaries";
+/// Suffix distinguishing a canary's artifacts, cache entry and metrics from the stable version
+pub const CANARY_SUFFIX: &str = "@canary";
+
+pub struct Canaries {
+    tree: sled::Tree,
+    functions_dir: PathBuf,
+    storage: Arc<dyn ArtifactStorage>,
+}
+
+impl Canaries {
+    pub fn new(
+        db: &sled::Db,
+        functions_dir: &Path,
+        storage: Arc<dyn ArtifactStorage>,
+    ) -> Result<Self> {
+        Ok(Self {
+            tree: db.open_tree(CANARIES_TREE)?,
+            functions_dir: functions_dir.to_path_buf(),
+            storage,
+        })
+    }
+
+    /// Percentage of `name`'s requests going to its canary, if it has one
+    pub fn weight(&self, name: &str) -> Option<u8> {
+        match self.tree.get(name.as_bytes()) {
+            Ok(value) => value?.first().copied(),
+            Err(e) => {
+                error!("Failed to read the canary weight of '{}': {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Functions with a canary
+    pub fn names(&self) -> Vec<String> {
+        self.tree
+            .iter()
+            .keys()
+            .flatten()
+            .map(|key| String::from_utf8_lossy(&key).into_owned())
+            .collect()
+    }
+
+    pub fn set_weight(&self, name: &str, weight: u8) -> Result<()> {
+        self.tree.insert(name.as_bytes(), &[weight.min(100)])?;
+        Ok(())
+    }
+
+    /// Path of `name`'s precompiled canary
+    pub fn cwasm_path(&self, name: &str) -> PathBuf {
+        self.functions_dir
+            .join(format!("{name}{CANARY_SUFFIX}.cwasm"))
+    }
+
+    /// Store a new canary of `name`
+    pub async fn save(&self, name: &str, wasm: &[u8], cwasm: &[u8]) -> Result<()> {
+        self.storage
+            .put(&wasm_key(&format!("{name}{CANARY_SUFFIX}")), wasm)
+            .await?;
+        write_atomically(&self.cwasm_path(name), cwasm)
+    }
+
+    /// Decide whether a request for `name` goes to its canary
+    pub fn pick(&self, name: &str) -> bool {
+        match self.weight(name) {
+            Some(0) | None => false,
+            Some(weight) => rand::thread_rng().gen_range(0..100) < weight,
+        }
+    }
+
+    /// Make the canary the stable version
+    pub async fn promote(&self, name: &str) -> Result<()> {
+        self.storage
+            .rename(
+                &wasm_key(&format!("{name}{CANARY_SUFFIX}")),
+                &wasm_key(name),
+            )
+            .await?;
+        let canary = self.cwasm_path(name);
+        fs::rename(&canary, self.functions_dir.join(format!("{name}.cwasm")))
+            .with_context(|| format!("Failed to promote {}", canary.display()))?;
+        self.tree.remove(name.as_bytes())?;
+        Ok(())
+    }
+
+    /// Delete the canary, leaving the stable version untouched
+    pub async fn abort(&self, name: &str) -> Result<()> {
+        self.tree.remove(name.as_bytes())?;
+        self.storage
+            .delete(&wasm_key(&format!("{name}{CANARY_SUFFIX}")))
+            .await?;
+        let canary = self.cwasm_path(name);
+        if canary.exists() {
+            fs::remove_file(&canary)
+                .with_context(|| format!("Failed to remove {}", canary.display()))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! Capacity reserved for management.
+//!
+//! Functions are served from a bounded share of the server's connection slots,
+//! and at most `--max-concurrent-invocations` of them run at once; requests
+//! beyond that are answered 503. The remaining slots are reserved for
+//! management: RPC connections (deploy, disable, logs, ...) and HTTPS connections
+//! to the management API on the root domain. The RPC listeners also run on their
+//! own small runtime (`--management-threads`), so guests keeping every request
+//! worker busy don't starve them. An overloaded function therefore can't lock
+//! operators and owners out of the very APIs they need to fix it.
+
+use anyhow::Result;
+use once_cell::sync::OnceCell;
+use std::sync::Arc;
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Global capacity limits, set at startup
+pub static CAPACITY: OnceCell<Capacity> = OnceCell::new();
+
+/// Runtime the management RPCs run on, when they have their own
+static MANAGEMENT_RUNTIME: OnceCell<Runtime> = OnceCell::new();
+
+pub struct Capacity {
+    /// Connection slots functions are served from
+    function_connections: Arc<Semaphore>,
+    /// Connection slots only management connections may take
+    management_connections: Arc<Semaphore>,
+    /// Function invocations that may run at once
+    invocations: Arc<Semaphore>,
+}
+
+impl Capacity {
+    /// Limits with `reserved_connections` of `max_connections` kept for
+    /// management, and at most `max_invocations` invocations at once (0 for no limit)
+    pub fn new(
+        max_connections: usize,
+        reserved_connections: usize,
+        max_invocations: usize,
+    ) -> Self {
+        let reserved = reserved_connections.min(max_connections);
+        let max_invocations = match max_invocations {
+            0 => Semaphore::MAX_PERMITS,
+            max => max,
+        };
+        Self {
+            function_connections: Arc::new(Semaphore::new(max_connections - reserved)),
+            management_connections: Arc::new(Semaphore::new(reserved)),
+            invocations: Arc::new(Semaphore::new(max_invocations)),
+        }
+    }
+
+    /// A slot for a connection serving functions, unless all are taken. The
+    /// slot is freed when the permit is dropped.
+    pub fn function_connection(&self) -> Option<OwnedSemaphorePermit> {
+        self.function_connections.clone().try_acquire_owned().ok()
+    }
+
+    /// A slot for a management connection: a reserved one, or one of the
+    /// functions' while they have some to spare
+    pub fn management_connection(&self) -> Option<OwnedSemaphorePermit> {
+        self.management_connections
+            .clone()
+            .try_acquire_owned()
+            .ok()
+            .or_else(|| self.function_connection())
+    }
+
+    /// A permit to run a function invocation, unless too many already run
+    pub fn invocation(&self) -> Option<OwnedSemaphorePermit> {
+        self.invocations.clone().try_acquire_owned().ok()
+    }
+}
+
+/// Runtime to run the management listeners on: one of their own with `threads`
+/// workers, or the current one when `threads` is 0
+pub fn management_runtime(threads: usize) -> Result<Handle> {
+    if threads == 0 {
+        return Ok(Handle::current());
+    }
+    let runtime = MANAGEMENT_RUNTIME.get_or_try_init(|| {
+        Builder::new_multi_thread()
+            .worker_threads(threads)
+            .thread_name("faasta-management")
+            .enable_all()
+            .build()
+    })?;
+    Ok(runtime.handle().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_management_keeps_its_reserve_when_functions_are_full() {
+        let capacity = Capacity::new(3, 1, 1);
+        let functions: Vec<_> = std::iter::from_fn(|| capacity.function_connection()).collect();
+        assert_eq!(functions.len(), 2);
+
+        let management = capacity.management_connection();
+        assert!(management.is_some());
+        assert!(capacity.management_connection().is_none());
+
+        // Management may borrow a function slot once one is free
+        drop(functions);
+        assert!(capacity.management_connection().is_some());
+
+        let invocation = capacity.invocation();
+        assert!(invocation.is_some());
+        assert!(capacity.invocation().is_none());
+    }
+}
@@ -0,0 +1,316 @@
+//! Capacity planning reports.
+//!
+//! Every `--capacity-report-interval` hours the server sums up where its resources
+//! go: the functions whose invocations take the most wall-clock time and memory,
+//! the size of the compilation cache, artifact storage and local disk, and how fast
+//! all of that grew since the oldest report kept. Reports are kept for [`HISTORY_DAYS`] days, so
+//! growth is measured over up to a month, and the latest is also written as JSON
+//! to `--capacity-report-path`. `cargo faasta admin capacity` reads it over RPC;
+//! `server-wasi report` prints the file on the server itself, without the RPC
+//! credentials and while the server holds its database.
+
+use anyhow::{anyhow, Context, Result};
+use faasta_interface::{CapacityGrowth, CapacityReport, ResourceConsumer};
+use futures::StreamExt;
+use once_cell::sync::OnceCell;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{error, info};
+
+use crate::metrics::{function_stats, get_metrics};
+use crate::rpc_service::FUNCTIONS_DB_TREE;
+use crate::storage::write_atomically;
+use crate::wasi_server::{FaastaServer, SERVER};
+
+/// Sled tree holding the reports, keyed by when they were made in big-endian
+/// milliseconds
+pub const CAPACITY_TREE: &str = "capacity_reports";
+/// Where the latest report is written as JSON, if configured
+pub static CAPACITY_REPORT_PATH: OnceCell<PathBuf> = OnceCell::new();
+
+/// Days reports are kept, and so the longest growth is measured over
+const HISTORY_DAYS: u64 = 30;
+/// Reports closer together than this aren't compared, the rates would be noise
+const MIN_GROWTH_DAYS: f64 = 1.0 / 24.0;
+/// Functions listed as top consumers of each resource
+const TOP_CONSUMERS: usize = 10;
+/// Artifacts whose size is asked of storage at once
+const SIZE_CONCURRENCY: usize = 16;
+const DAY_MILLIS: f64 = 86_400_000.0;
+
+/// The latest report, if one was made
+pub fn latest(db: &sled::Db) -> Result<Option<CapacityReport>> {
+    let Some((_, value)) = db.open_tree(CAPACITY_TREE)?.last()? else {
+        return Ok(None);
+    };
+    let (report, _) = bincode::decode_from_slice(&value, bincode::config::standard())?;
+    Ok(Some(report))
+}
+
+/// Make a report of the server's resources now, keep it and write it out
+pub async fn generate() -> Result<CapacityReport> {
+    let server = SERVER
+        .get()
+        .ok_or_else(|| anyhow!("Server not initialised"))?;
+    let mut report = measure(server).await?;
+
+    let tree = server.metadata_db.open_tree(CAPACITY_TREE)?;
+    let now = chrono::Utc::now().timestamp_millis();
+    let expired = now - (HISTORY_DAYS * 86_400_000) as i64;
+    for key in tree.range(..expired.to_be_bytes()).keys() {
+        tree.remove(key?)?;
+    }
+    if let Some((key, value)) = tree.first()? {
+        let then = i64::from_be_bytes(key.as_ref().try_into()?);
+        let (earlier, _) = bincode::decode_from_slice(&value, bincode::config::standard())?;
+        report.growth = growth(&earlier, &report, (now - then) as f64 / DAY_MILLIS);
+    }
+    tree.insert(
+        now.to_be_bytes(),
+        bincode::encode_to_vec(&report, bincode::config::standard())?,
+    )?;
+
+    if let Some(path) = CAPACITY_REPORT_PATH.get() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomically(path, &serde_json::to_vec_pretty(&report)?)?;
+    }
+    Ok(report)
+}
+
+/// Resource use of the server right now, without growth
+async fn measure(server: &FaastaServer) -> Result<CapacityReport> {
+    let metrics = get_metrics();
+    let consumers: Vec<ResourceConsumer> = metrics
+        .function_metrics
+        .iter()
+        .map(|metric| ResourceConsumer {
+            function_name: metric.function_name.clone(),
+            invocations: metric.call_count,
+            invocation_millis: metric.total_time_millis,
+            peak_memory_bytes: function_stats(&metric.function_name)
+                .and_then(|stats| stats.memory)
+                .map(|memory| memory.max_bytes)
+                .unwrap_or(0),
+        })
+        .collect();
+
+    let mut sizes = futures::stream::iter(server.storage.list().await?)
+        .map(|key| async move { server.storage.size(&key).await })
+        .buffer_unordered(SIZE_CONCURRENCY);
+    let mut artifact_bytes = 0;
+    while let Some(size) = sizes.next().await {
+        artifact_bytes += size?.unwrap_or(0);
+    }
+    // Walking the functions directory blocks on the disk
+    let functions_dir = server.functions_dir.clone();
+    let ((functions_dir_bytes, compiled_bytes), disk_free_bytes) =
+        tokio::task::spawn_blocking(move || -> Result<_> {
+            Ok((directory_usage(&functions_dir)?, disk_free(&functions_dir)))
+        })
+        .await??;
+    let (cached_components, component_cache_bytes) = server.component_cache_usage();
+
+    Ok(CapacityReport {
+        generated_at: chrono::Utc::now().to_rfc3339(),
+        functions: server.metadata.count(FUNCTIONS_DB_TREE)? as u64,
+        invocations: metrics.total_calls,
+        invocation_millis: metrics.total_time,
+        top_invocation_time: top(&consumers, |consumer| consumer.invocation_millis),
+        top_memory: top(&consumers, |consumer| consumer.peak_memory_bytes),
+        cached_components: cached_components as u64,
+        component_cache_bytes,
+        compiled_bytes,
+        artifact_bytes,
+        disk_used_bytes: functions_dir_bytes + server.metadata_db.size_on_disk()?,
+        disk_free_bytes,
+        growth: None,
+    })
+}
+
+/// The [`TOP_CONSUMERS`] functions using the most of `resource`, most first
+fn top(
+    consumers: &[ResourceConsumer],
+    resource: impl Fn(&ResourceConsumer) -> u64,
+) -> Vec<ResourceConsumer> {
+    let mut ranked: Vec<&ResourceConsumer> = consumers
+        .iter()
+        .filter(|consumer| resource(consumer) > 0)
+        .collect();
+    ranked.sort_by(|a, b| {
+        resource(b)
+            .cmp(&resource(a))
+            .then_with(|| a.function_name.cmp(&b.function_name))
+    });
+    ranked.into_iter().take(TOP_CONSUMERS).cloned().collect()
+}
+
+/// Growth per day from `earlier` to `current`, made `days` apart
+fn growth(earlier: &CapacityReport, current: &CapacityReport, days: f64) -> Option<CapacityGrowth> {
+    if days < MIN_GROWTH_DAYS {
+        return None;
+    }
+    let per_day = |from: u64, to: u64| (to as f64 - from as f64) / days;
+    // Counters restart from what was last flushed after a crash, so they only grow
+    let invocations_per_day = per_day(earlier.invocations, current.invocations).max(0.0);
+    let invocation_millis_per_day =
+        per_day(earlier.invocation_millis, current.invocation_millis).max(0.0);
+    let disk_bytes_per_day = per_day(earlier.disk_used_bytes, current.disk_used_bytes);
+    let days_until_disk_full = current
+        .disk_free_bytes
+        .filter(|_| disk_bytes_per_day > 0.0)
+        .map(|free| free as f64 / disk_bytes_per_day);
+    Some(CapacityGrowth {
+        over_days: days,
+        invocations_per_day,
+        invocation_millis_per_day,
+        disk_bytes_per_day,
+        days_until_disk_full,
+    })
+}
+
+/// Bytes of all files under `dir`, and of the precompiled artifacts among them
+fn directory_usage(dir: &Path) -> Result<(u64, u64)> {
+    let (mut total, mut compiled) = (0, 0);
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries =
+            fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                pending.push(entry.path());
+                continue;
+            }
+            total += metadata.len();
+            if entry.path().extension().is_some_and(|ext| ext == "cwasm") {
+                compiled += metadata.len();
+            }
+        }
+    }
+    Ok((total, compiled))
+}
+
+/// Bytes unprivileged users may still write to the filesystem holding `path`
+#[allow(clippy::unnecessary_cast)]
+fn disk_free(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Print the report last written to `path`, for `server-wasi report`
+pub fn print_saved(path: &Path, json: bool) -> Result<()> {
+    let saved = match fs::read_to_string(path) {
+        Ok(saved) => saved,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow!(
+                "No capacity report at {} yet; the server writes one every \
+                 --capacity-report-interval hours",
+                path.display()
+            ))
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    if json {
+        println!("{saved}");
+    } else {
+        let report: CapacityReport = serde_json::from_str(&saved)
+            .with_context(|| format!("{} isn't a capacity report", path.display()))?;
+        print!("{report}");
+    }
+    Ok(())
+}
+
+pub fn spawn_periodic_report(interval_hours: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_hours * 3600));
+        loop {
+            ticker.tick().await;
+            match generate().await {
+                Ok(report) => info!(
+                    "Capacity report: {} functions, {} bytes of local disk used",
+                    report.functions, report.disk_used_bytes
+                ),
+                Err(e) => error!("Capacity report failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn consumer(name: &str, invocation_millis: u64, peak_memory_bytes: u64) -> ResourceConsumer {
+        ResourceConsumer {
+            function_name: name.to_string(),
+            invocations: 1,
+            invocation_millis,
+            peak_memory_bytes,
+        }
+    }
+
+    fn report(invocations: u64, disk_used_bytes: u64) -> CapacityReport {
+        CapacityReport {
+            generated_at: String::new(),
+            functions: 1,
+            invocations,
+            invocation_millis: invocations * 10,
+            top_invocation_time: Vec::new(),
+            top_memory: Vec::new(),
+            cached_components: 0,
+            component_cache_bytes: 0,
+            compiled_bytes: 0,
+            artifact_bytes: 0,
+            disk_used_bytes,
+            disk_free_bytes: Some(9_000),
+            growth: None,
+        }
+    }
+
+    #[test]
+    fn test_consumers_are_ranked_and_growth_projected() {
+        let consumers = [
+            consumer("idle", 0, 0),
+            consumer("api", 500, 64),
+            consumer("etl", 9_000, 32),
+            consumer("cron", 500, 128),
+        ];
+        let names = |ranked: Vec<ResourceConsumer>| {
+            ranked
+                .into_iter()
+                .map(|consumer| consumer.function_name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(top(&consumers, |c| c.invocation_millis)),
+            ["etl", "api", "cron"]
+        );
+        assert_eq!(
+            names(top(&consumers, |c| c.peak_memory_bytes)),
+            ["cron", "api", "etl"]
+        );
+
+        let growing = growth(&report(100, 1_000), &report(400, 4_000), 3.0).unwrap();
+        assert_eq!(growing.invocations_per_day, 100.0);
+        assert_eq!(growing.invocation_millis_per_day, 1_000.0);
+        assert_eq!(growing.disk_bytes_per_day, 1_000.0);
+        assert_eq!(growing.days_until_disk_full, Some(9.0));
+
+        // A shrinking disk never fills, and reports too close together aren't compared
+        let shrinking = growth(&report(100, 4_000), &report(100, 1_000), 3.0).unwrap();
+        assert_eq!(shrinking.days_until_disk_full, None);
+        assert!(growth(&report(100, 1_000), &report(400, 4_000), 0.01).is_none());
+    }
+}
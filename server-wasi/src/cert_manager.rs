@@ -7,7 +7,13 @@ use std::path::PathBuf;
 use std::time::{Duration, SystemTime};
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
-use tracing::{info, warn};
+use tokio::time::{interval_at, Instant};
+use tracing::{error, info, warn};
+
+use crate::events::{self, PlatformEvent};
+
+/// How often the certificate's expiry is checked
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 3600);
 
 // Porkbun API response structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -193,8 +199,8 @@ impl CertManager {
         Ok(response_json)
     }
 
-    // Obtain or renew the certificate
-    pub async fn obtain_or_renew_certificate(&self) -> Result<()> {
+    // Obtain or renew the certificate, returning whether a new one was installed
+    pub async fn obtain_or_renew_certificate(&self) -> Result<bool> {
         info!(
             "Checking if certificate needs renewal for domain: {}",
             self.domain
@@ -205,7 +211,7 @@ impl CertManager {
 
         if !needs_renewal {
             info!("Certificate is still valid for more than 30 days, skipping renewal");
-            return Ok(());
+            return Ok(false);
         }
 
         // Get certificates from Porkbun API
@@ -249,6 +255,27 @@ impl CertManager {
             "Successfully downloaded certificates for domain: {}",
             self.domain
         );
-        Ok(())
+        Ok(true)
     }
 }
+
+/// Renew the certificate once a day when it nears expiry, for the listeners to
+/// pick up from its files
+pub fn spawn_periodic_renewal(cert_manager: CertManager) {
+    tokio::spawn(async move {
+        let mut ticker = interval_at(
+            Instant::now() + RENEWAL_CHECK_INTERVAL,
+            RENEWAL_CHECK_INTERVAL,
+        );
+        loop {
+            ticker.tick().await;
+            match cert_manager.obtain_or_renew_certificate().await {
+                Ok(true) => events::publish(PlatformEvent::CertRenewed {
+                    domain: cert_manager.domain.clone(),
+                }),
+                Ok(false) => {}
+                Err(e) => error!("Failed to renew the TLS certificate: {:#}", e),
+            }
+        }
+    });
+}
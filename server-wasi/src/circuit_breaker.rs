@@ -0,0 +1,239 @@
+//! Kill switch and circuit breakers of functions.
+//!
+//! An admin can stop invoking a function at once with its kill switch, e.g. when
+//! it's being abused or takes the server down with it. Independently, each
+//! function has a circuit breaker: when more than `--breaker-trap-rate` of its
+//! invocations in a `--breaker-window` trap, once it has had at least
+//! `--breaker-min-requests` of them, the breaker trips. Either way the function's
+//! requests are answered 503 without running it, the event is journaled, and a
+//! tripped breaker raises an alert for the owner. Breakers stay open across
+//! restarts until they are reset: a tripped one by the function's developers, a
+//! killed one only by an admin. Open breakers are kept in the metadata store, so
+//! in a cluster every node stops invoking the function once it syncs.
+
+use anyhow::Result;
+use dashmap::DashMap;
+use faasta_interface::{AlertKind, BreakerState, CircuitBreaker, EventSeverity, ServerEventKind};
+use once_cell::sync::OnceCell;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+use crate::anomalies::ANOMALIES;
+use crate::events::{PlatformEvent, Subscriber};
+use crate::journal;
+use crate::metadata_store::{Cached, MetadataStore};
+
+/// Collection of the metadata store holding open breakers, keyed by function name
+pub const BREAKERS_TREE: &str = "circuit_breakers";
+
+/// Global breakers, set at startup
+pub static BREAKERS: OnceCell<CircuitBreakers> = OnceCell::new();
+
+/// Invocations and traps of a function since its window started
+struct Window {
+    started: Instant,
+    calls: u64,
+    traps: u64,
+}
+
+pub struct CircuitBreakers {
+    breakers: Cached<CircuitBreaker>,
+    /// Share of trapping invocations that trips a breaker (0 never trips one)
+    trap_rate: f64,
+    /// Invocations a window needs before it can trip a breaker
+    min_requests: u64,
+    window: Duration,
+    windows: DashMap<String, Window>,
+}
+
+impl CircuitBreakers {
+    pub fn new(
+        store: Arc<dyn MetadataStore>,
+        trap_rate: f64,
+        min_requests: u64,
+        window: Duration,
+    ) -> Result<Self> {
+        Ok(Self {
+            breakers: Cached::new(store, BREAKERS_TREE, |value| {
+                bincode::decode_from_slice(value, bincode::config::standard())
+                    .ok()
+                    .map(|(breaker, _)| breaker)
+            })?,
+            trap_rate,
+            min_requests: min_requests.max(1),
+            window,
+            windows: DashMap::new(),
+        })
+    }
+
+    /// The open breaker of `name`, if it was killed or tripped
+    pub fn get(&self, name: &str) -> Option<CircuitBreaker> {
+        self.breakers.get(name)
+    }
+
+    /// Stop invoking `name` on an admin's order, replacing a tripped breaker
+    pub fn kill(&self, name: &str, reason: String, admin: &str) -> Result<()> {
+        self.open(
+            name,
+            &CircuitBreaker {
+                state: BreakerState::Killed,
+                reason,
+                opened_by: Some(admin.to_string()),
+                opened_at: chrono::Utc::now().to_rfc3339(),
+            },
+        )
+    }
+
+    /// Invoke `name` again, returning the breaker that was open
+    pub fn reset(&self, name: &str) -> Result<Option<CircuitBreaker>> {
+        let breaker = self.get(name);
+        self.breakers.remove(name)?;
+        self.windows.remove(name);
+        Ok(breaker)
+    }
+
+    /// Read the breaker of `name` again, after it was moved or deleted
+    pub fn refresh(&self, name: &str) -> Result<()> {
+        self.breakers.refresh(name)
+    }
+
+    /// Read every breaker again, for those other nodes opened or reset
+    pub fn reload(&self) -> Result<()> {
+        self.breakers.reload()
+    }
+
+    /// Count an invocation of `name`, tripping its breaker if it traps too often
+    pub fn record(&self, name: &str, trapped: bool) {
+        if self.trap_rate <= 0.0 {
+            return;
+        }
+        let rate = {
+            let mut window = self
+                .windows
+                .entry(name.to_string())
+                .or_insert_with(|| Window {
+                    started: Instant::now(),
+                    calls: 0,
+                    traps: 0,
+                });
+            if window.started.elapsed() >= self.window {
+                *window = Window {
+                    started: Instant::now(),
+                    calls: 0,
+                    traps: 0,
+                };
+            }
+            window.calls += 1;
+            window.traps += u64::from(trapped);
+            if !trapped || window.calls < self.min_requests {
+                return;
+            }
+            window.traps as f64 / window.calls as f64
+        };
+        if rate > self.trap_rate {
+            self.trip(name, rate);
+        }
+    }
+
+    fn trip(&self, name: &str, rate: f64) {
+        // Invocations already running may still report traps
+        if self.get(name).is_some() {
+            return;
+        }
+        let reason = format!(
+            "{:.0}% of invocations trapped in the last {}s",
+            rate * 100.0,
+            self.window.as_secs()
+        );
+        let breaker = CircuitBreaker {
+            state: BreakerState::Tripped,
+            reason: reason.clone(),
+            opened_by: None,
+            opened_at: chrono::Utc::now().to_rfc3339(),
+        };
+        if let Err(e) = self.open(name, &breaker) {
+            error!("Failed to trip the circuit breaker of '{}': {}", name, e);
+            return;
+        }
+        self.windows.remove(name);
+
+        warn!("Circuit breaker of '{}' tripped: {}", name, reason);
+        journal::record(
+            EventSeverity::Warning,
+            ServerEventKind::BreakerTripped,
+            Some(name),
+            reason.clone(),
+        );
+        if let Some(anomalies) = ANOMALIES.get() {
+            anomalies.raise_for_function(
+                AlertKind::BreakerTripped,
+                name,
+                format!("{reason}; requests are answered 503 until the breaker is reset"),
+            );
+        }
+    }
+
+    fn open(&self, name: &str, breaker: &CircuitBreaker) -> Result<()> {
+        let encoded = bincode::encode_to_vec(breaker, bincode::config::standard())?;
+        self.breakers.insert(name, breaker.clone(), encoded)
+    }
+}
+
+impl Subscriber for CircuitBreakers {
+    fn on_event(&self, event: &PlatformEvent) {
+        // A trapping canary is aborted rather than taking its function down
+        if let PlatformEvent::InvokeCompleted {
+            function,
+            version,
+            trapped,
+            ..
+        } = event
+        {
+            if version == function {
+                self.record(function, *trapped);
+            }
+        }
+    }
+}
+
+/// The open breaker of `name`, if its requests are answered 503
+pub fn open_breaker(name: &str) -> Option<CircuitBreaker> {
+    BREAKERS.get()?.get(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_store::SledStore;
+
+    #[test]
+    fn test_breaker_trips_past_the_trap_rate_and_resets() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store = Arc::new(SledStore::new(&db));
+        let breakers = CircuitBreakers::new(store, 0.5, 4, Duration::from_secs(60)).unwrap();
+
+        // Too few invocations to judge
+        for _ in 0..3 {
+            breakers.record("api", true);
+        }
+        assert!(breakers.get("api").is_none());
+
+        breakers.record("api", true);
+        let breaker = breakers.get("api").unwrap();
+        assert_eq!(breaker.state, BreakerState::Tripped);
+        assert!(breaker.opened_by.is_none());
+
+        assert!(breakers.reset("api").unwrap().is_some());
+        assert!(breakers.get("api").is_none());
+
+        // Half of the invocations trapping doesn't exceed the rate
+        for trapped in [false, true, false, true] {
+            breakers.record("api", trapped);
+        }
+        assert!(breakers.get("api").is_none());
+
+        breakers.kill("api", "abuse".to_string(), "admin").unwrap();
+        assert_eq!(breakers.get("api").unwrap().state, BreakerState::Killed);
+    }
+}
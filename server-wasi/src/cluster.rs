@@ -0,0 +1,387 @@
+//! Several nodes serving the same functions.
+//!
+//! With `--cluster`, nodes behind a load balancer share users and function metadata
+//! through the Postgres metadata store and WebAssembly through S3 artifact storage,
+//! so any of them can serve any function and take any publish. Accounts, teams,
+//! roles, API keys, sessions and suspensions, and the settings of functions from
+//! their specs to their access keys, redirects, kill switches and circuit breakers,
+//! keep-warm marks, response signing keys and log settings, are in the metadata
+//! store too.
+//!
+//! What each node keeps locally is either its own, like usage, log lines, metrics,
+//! canaries, the trash and static assets, or a cache. Every `--cluster-sync-interval` seconds a node reads
+//! the settings requests use again, and compares the digest each of its
+//! precompiled artifacts was compiled from with the digest published, dropping
+//! artifacts and loaded components of functions published or deleted on another
+//! node. The next request compiles them again from storage.
+//!
+//! Each node also reports in under its `--node-id`, listed by
+//! `cargo faasta admin nodes`, along with the canaries and trash it keeps for
+//! garbage collection, and tags the intents of its publishes and deletes with it,
+//! so a node restarting recovers only the operations it was running. A node unseen
+//! for a day is taken as gone: the running node with the smallest id recovers its
+//! intents and drops its records.
+
+use anyhow::{anyhow, bail, Result};
+use bincode::{Decode, Encode};
+use chrono::{DateTime, Utc};
+use faasta_interface::ClusterNode;
+use once_cell::sync::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, error, info};
+
+use crate::canary::CANARY_SUFFIX;
+use crate::function_data::{self, ARTIFACT_DIGESTS_TREE};
+use crate::gc;
+use crate::intents::{self, Intents};
+use crate::metadata_store::MetadataStore;
+use crate::route_handlers::ROUTE_SUFFIX;
+use crate::rpc_service::FUNCTIONS_DB_TREE;
+use crate::wasi_server::{FaastaServer, SERVER};
+
+/// Collection of the metadata store holding the nodes of the cluster, keyed by id
+pub const NODES_TREE: &str = "cluster_nodes";
+/// Syncs a node may miss before it's no longer taken as running
+const MISSED_SYNCS: i64 = 3;
+/// Seconds after which a node that stopped reporting in is taken as gone
+const GONE_AFTER_SECS: i64 = 24 * 3600;
+
+/// Cluster membership of this node, set at startup in cluster mode
+pub static CLUSTER: OnceCell<Cluster> = OnceCell::new();
+
+/// What a node reports about itself
+#[derive(Encode, Decode)]
+struct NodeRecord {
+    version: String,
+    started_at: i64,
+    seen_at: i64,
+}
+
+pub struct Cluster {
+    node_id: String,
+    store: Arc<dyn MetadataStore>,
+    started_at: i64,
+    /// Seconds between syncs
+    sync_interval: u64,
+    /// Digest of each function's published WebAssembly, if it was recorded, as of
+    /// the last sync; `None` before the first
+    published: Mutex<Option<HashMap<String, Option<String>>>>,
+}
+
+impl Cluster {
+    pub fn new(node_id: String, store: Arc<dyn MetadataStore>, sync_interval: u64) -> Self {
+        Self {
+            node_id,
+            store,
+            started_at: Utc::now().timestamp(),
+            sync_interval: sync_interval.max(1),
+            published: Mutex::new(None),
+        }
+    }
+
+    /// Id of this node
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Record that this node is running
+    fn report_in(&self) -> Result<()> {
+        let record = NodeRecord {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: self.started_at,
+            seen_at: Utc::now().timestamp(),
+        };
+        let encoded = bincode::encode_to_vec(&record, bincode::config::standard())?;
+        self.store.insert(NODES_TREE, &self.node_id, encoded)
+    }
+
+    /// Every node that reported in, by id
+    fn records(&self) -> Result<Vec<(String, NodeRecord)>> {
+        Ok(self
+            .store
+            .scan(NODES_TREE)?
+            .into_iter()
+            .filter_map(|(id, value)| {
+                let (record, _) = bincode::decode_from_slice::<NodeRecord, _>(
+                    &value,
+                    bincode::config::standard(),
+                )
+                .ok()?;
+                Some((id, record))
+            })
+            .collect())
+    }
+
+    /// Whether a node last seen at `seen_at` is taken as running at `now`
+    fn is_alive(&self, seen_at: i64, now: i64) -> bool {
+        now - seen_at <= MISSED_SYNCS * self.sync_interval as i64
+    }
+
+    /// Every node that reported in and wasn't forgotten, running ones first
+    pub fn nodes(&self) -> Result<Vec<ClusterNode>> {
+        let now = Utc::now().timestamp();
+        let mut nodes: Vec<ClusterNode> = self
+            .records()?
+            .into_iter()
+            .map(|(id, record)| ClusterNode {
+                id,
+                version: record.version,
+                started_at: rfc3339(record.started_at),
+                last_seen: rfc3339(record.seen_at),
+                alive: self.is_alive(record.seen_at, now),
+            })
+            .collect();
+        nodes.sort_by(|a, b| b.alive.cmp(&a.alive).then_with(|| a.id.cmp(&b.id)));
+        Ok(nodes)
+    }
+
+    /// Recover the intents of nodes gone for good and drop their records. Only the
+    /// running node with the smallest id does, so no intent is recovered twice.
+    async fn forget_gone_nodes(&self) -> Result<()> {
+        let now = Utc::now().timestamp();
+        let records = self.records()?;
+        let first_alive = records
+            .iter()
+            .filter(|(_, record)| self.is_alive(record.seen_at, now))
+            .map(|(id, _)| id)
+            .min();
+        if first_alive != Some(&self.node_id) {
+            return Ok(());
+        }
+
+        for (id, record) in records {
+            if now - record.seen_at <= GONE_AFTER_SECS {
+                continue;
+            }
+            let gone = Intents::new(self.store.clone(), Some(id.clone()));
+            intents::recover_intents(&gone).await;
+            if gone.pending().is_empty() {
+                self.store.remove(NODES_TREE, &id)?;
+                self.store.remove(gc::INVENTORIES_TREE, &id)?;
+                info!(
+                    "Forgot node '{}', unseen since {}",
+                    id,
+                    rfc3339(record.seen_at)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Functions published or deleted since the last sync, given the digest each
+    /// one is published with now; every function on the first sync
+    fn changes(&self, now: HashMap<String, Option<String>>) -> Vec<String> {
+        let mut published = self.published.lock().unwrap();
+        let changed = match published.as_ref() {
+            Some(before) => changed(before, &now),
+            None => now.keys().cloned().collect(),
+        };
+        *published = Some(now);
+        changed
+    }
+}
+
+/// Refuse cluster mode unless function metadata and artifacts are kept where every
+/// node sees them
+pub fn check_shared(metadata_store: &str, storage: &str) -> Result<()> {
+    if metadata_store == "sled" {
+        bail!("Cluster mode needs a shared metadata store, e.g. --metadata-store postgres");
+    }
+    if storage == "filesystem" {
+        bail!("Cluster mode needs shared artifact storage, e.g. --storage s3");
+    }
+    Ok(())
+}
+
+/// Id of this node: `configured`, else its hostname
+pub fn node_id(configured: Option<String>) -> Result<String> {
+    let node_id = configured
+        .or_else(hostname)
+        .ok_or_else(|| anyhow!("The hostname is unavailable, set --node-id"))?;
+    if node_id.is_empty() || node_id.contains('/') {
+        bail!("Node id '{node_id}' must be non-empty and can't contain '/'");
+    }
+    Ok(node_id)
+}
+
+fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    let name = String::from_utf8_lossy(&buffer[..len]).into_owned();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Functions published differently in `before` and `now`, deleted ones included
+fn changed<V: PartialEq>(before: &HashMap<String, V>, now: &HashMap<String, V>) -> Vec<String> {
+    let mut changed: Vec<String> = now
+        .iter()
+        .filter(|(name, published)| before.get(*name) != Some(*published))
+        .map(|(name, _)| name.clone())
+        .collect();
+    changed.extend(
+        before
+            .keys()
+            .filter(|name| !now.contains_key(*name))
+            .cloned(),
+    );
+    changed
+}
+
+/// Whether an artifact compiled from the WebAssembly with digest `compiled` is of
+/// another version than the one `published`: `None` for a deleted function,
+/// `Some(None)` for one published before digests were recorded
+fn is_stale(compiled: Option<&str>, published: Option<&Option<String>>) -> bool {
+    match published {
+        None => true,
+        Some(None) => false,
+        Some(Some(published)) => compiled != Some(published.as_str()),
+    }
+}
+
+fn rfc3339(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+/// Report in, with the canaries and trash this node keeps, and drop what it
+/// caches of functions changed elsewhere
+pub async fn sync() -> Result<()> {
+    let (Some(cluster), Some(server)) = (CLUSTER.get(), SERVER.get()) else {
+        return Ok(());
+    };
+    cluster.report_in()?;
+    gc::report_inventory(server, cluster)?;
+    function_data::reload_cached()?;
+
+    let mut published: HashMap<String, Option<String>> = server
+        .metadata
+        .scan(FUNCTIONS_DB_TREE)?
+        .into_iter()
+        .map(|(name, _)| (name, None))
+        .collect();
+    for (name, digest) in server.metadata.scan(ARTIFACT_DIGESTS_TREE)? {
+        if let Some(published) = published.get_mut(&name) {
+            *published = Some(String::from_utf8_lossy(&digest).into_owned());
+        }
+    }
+    let mut candidates: HashSet<String> = cluster.changes(published.clone()).into_iter().collect();
+    // Storage may have served a version other than the published one, e.g. while
+    // a publish on another node was half done, so every cached artifact is checked
+    candidates.extend(cached(server));
+
+    for name in candidates {
+        if drop_if_stale(server, &name, published.get(&name))? {
+            info!("Dropped the cached '{}', changed on another node", name);
+        }
+    }
+
+    cluster.forget_gone_nodes().await
+}
+
+/// Drop what's cached of `name` unless it was compiled from the version
+/// `published`. Returns whether anything was dropped.
+fn drop_if_stale(
+    server: &FaastaServer,
+    name: &str,
+    published: Option<&Option<String>>,
+) -> Result<bool> {
+    let path = server.functions_dir.join(format!("{name}.cwasm"));
+    let compiled = server.artifacts.compiled_digest(name)?;
+    let stale = path.exists() && is_stale(compiled.as_deref(), published);
+    if stale {
+        fs::remove_file(&path)?;
+    }
+    if stale || published.is_none() {
+        server.remove_from_cache(name);
+    }
+    Ok(stale)
+}
+
+/// Functions with a precompiled artifact on this node
+fn cached(server: &FaastaServer) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(&server.functions_dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            let name = file_name.strip_suffix(".cwasm")?;
+            if name.starts_with('.') || name.contains(CANARY_SUFFIX) || name.contains(ROUTE_SUFFIX)
+            {
+                return None;
+            }
+            Some(name.to_string())
+        })
+        .collect()
+}
+
+/// Sync every `interval_secs` seconds
+pub fn spawn_periodic_sync(interval_secs: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_secs.max(1)));
+        loop {
+            ticker.tick().await;
+            match sync().await {
+                Ok(()) => debug!("Synced with the cluster"),
+                Err(e) => error!("Failed to sync with the cluster: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_cover_publishes_and_deletes_elsewhere() {
+        let before = HashMap::from([
+            ("kept".to_string(), "2026-01-01T00:00:00+00:00".to_string()),
+            (
+                "updated".to_string(),
+                "2026-01-01T00:00:00+00:00".to_string(),
+            ),
+            (
+                "deleted".to_string(),
+                "2026-01-01T00:00:00+00:00".to_string(),
+            ),
+        ]);
+        let now = HashMap::from([
+            ("kept".to_string(), "2026-01-01T00:00:00+00:00".to_string()),
+            (
+                "updated".to_string(),
+                "2026-02-01T00:00:00+00:00".to_string(),
+            ),
+            (
+                "created".to_string(),
+                "2026-02-01T00:00:00+00:00".to_string(),
+            ),
+        ]);
+        let mut changed = changed(&before, &now);
+        changed.sort();
+        assert_eq!(changed, ["created", "deleted", "updated"]);
+    }
+
+    #[test]
+    fn test_artifacts_of_other_versions_are_stale() {
+        let published = Some("sha256:new".to_string());
+        assert!(!is_stale(Some("sha256:new"), Some(&published)));
+        assert!(is_stale(Some("sha256:old"), Some(&published)));
+        // Compiled before digests were recorded with artifacts
+        assert!(is_stale(None, Some(&published)));
+        // Deleted
+        assert!(is_stale(Some("sha256:new"), None));
+        // Published before digests were recorded
+        assert!(!is_stale(None, Some(&None)));
+    }
+}
@@ -0,0 +1,87 @@
+//! Cranelift compilation kept apart from request serving.
+//!
+//! Compiling a component is CPU-heavy and, with parallel compilation, fans out over
+//! every thread of the pool it runs in. Compilations therefore run in a dedicated
+//! thread pool rather than on the runtime serving requests, its threads run at a
+//! lower scheduling priority, and only a few compilations run at once. A burst of
+//! deploys or cold hydrations then slows down compilation, not live traffic.
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+use tokio::sync::{oneshot, Semaphore};
+use wasmtime::Engine;
+
+/// Global compiler, set at startup
+pub static COMPILER: OnceCell<Compiler> = OnceCell::new();
+
+#[cfg(target_os = "linux")]
+/// Nice value of compile threads; request serving keeps the default of 0
+const COMPILE_NICENESS: i32 = 10;
+
+/// Precompile `wasm` with the global compiler, or on a blocking thread if it
+/// isn't set up
+pub async fn precompile(engine: &Engine, wasm: &[u8]) -> Result<Vec<u8>> {
+    match COMPILER.get() {
+        Some(compiler) => compiler.precompile(engine, wasm).await,
+        None => {
+            let engine = engine.clone();
+            let wasm = wasm.to_vec();
+            tokio::task::spawn_blocking(move || engine.precompile_component(&wasm)).await?
+        }
+    }
+}
+
+pub struct Compiler {
+    pool: rayon::ThreadPool,
+    /// Limits how many compilations run at once
+    permits: Semaphore,
+}
+
+impl Compiler {
+    /// `threads` of 0 uses half the available cores
+    pub fn new(threads: usize, max_concurrent: usize) -> Result<Self> {
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get() / 2),
+            n => n,
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .thread_name(|index| format!("compile-{index}"))
+            .start_handler(|_| lower_thread_priority())
+            .build()?;
+        Ok(Self {
+            pool,
+            permits: Semaphore::new(max_concurrent.max(1)),
+        })
+    }
+
+    /// Precompile `wasm` once a compilation slot is free
+    pub async fn precompile(&self, engine: &Engine, wasm: &[u8]) -> Result<Vec<u8>> {
+        let _permit = self.permits.acquire().await?;
+        let (done, result) = oneshot::channel();
+        let engine = engine.clone();
+        let wasm = wasm.to_vec();
+        // Wasmtime's parallel compilation runs in the pool it is called from
+        self.pool.spawn(move || {
+            let _ = done.send(engine.precompile_component(&wasm));
+        });
+        result
+            .await
+            .map_err(|_| anyhow!("compilation was abandoned"))?
+    }
+}
+
+/// Make the calling thread yield to request serving when the CPU is contended
+fn lower_thread_priority() {
+    // On Linux every thread has its own nice value, and `who` of 0 is the caller
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: setpriority only changes the scheduling priority of this thread
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, COMPILE_NICENESS) } != 0 {
+            tracing::warn!(
+                "Failed to lower compile thread priority: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Compiled components kept in memory.
+//!
+//! Every function served is deserialized from its `.cwasm` file and linked into a
+//! pre-instantiated component once, then kept for its next requests. With
+//! `--component-cache-mb` set, the components kept are bounded by the size of
+//! their `.cwasm` files, which is about the code they map: the least recently used
+//! ones are dropped, with their warm instances, to stay under the budget, and are
+//! loaded again from the artifact cache on their next request. Canaries and
+//! kept-warm functions are never dropped, so they may take the cache over budget.
+
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::debug;
+use wasmtime_wasi_http::bindings::ProxyPre;
+
+use crate::canary::CANARY_SUFFIX;
+use crate::instance_pool::INSTANCE_POOL;
+use crate::keep_warm::is_kept_warm;
+use crate::wasi_server::FaastaClientState;
+
+struct Entry<T> {
+    component: T,
+    /// Size of the component's `.cwasm` file
+    bytes: u64,
+    /// Value of the cache's clock when the component was last used
+    last_used: AtomicU64,
+}
+
+pub struct ComponentCache<T = ProxyPre<FaastaClientState>> {
+    entries: DashMap<String, Entry<T>>,
+    /// Most bytes of components kept (0 keeps everything)
+    max_bytes: u64,
+    /// Bytes of the components kept
+    bytes: AtomicU64,
+    /// Ticks on every use, ordering uses without reading the time
+    clock: AtomicU64,
+}
+
+impl<T: Clone> ComponentCache<T> {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            entries: DashMap::new(),
+            max_bytes,
+            bytes: AtomicU64::new(0),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// The component of `version`, if it's loaded
+    pub fn get(&self, version: &str) -> Option<T> {
+        let entry = self.entries.get(version)?;
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(entry.component.clone())
+    }
+
+    /// Whether the component of `version` is loaded, without counting as a use
+    pub fn contains(&self, version: &str) -> bool {
+        self.entries.contains_key(version)
+    }
+
+    /// Keep the component of `version`, `bytes` large, dropping the least recently
+    /// used others to make room
+    pub fn insert(&self, version: &str, component: T, bytes: u64) {
+        let entry = Entry {
+            component,
+            bytes,
+            last_used: AtomicU64::new(self.tick()),
+        };
+        if let Some(previous) = self.entries.insert(version.to_string(), entry) {
+            self.bytes.fetch_sub(previous.bytes, Ordering::Relaxed);
+        }
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.evict_to_budget(version);
+    }
+
+    /// Drop the component of `version`, returning whether it was loaded
+    pub fn remove(&self, version: &str) -> bool {
+        match self.entries.remove(version) {
+            Some((_, entry)) => {
+                self.bytes.fetch_sub(entry.bytes, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Components loaded, and their bytes
+    pub fn usage(&self) -> (usize, u64) {
+        (self.entries.len(), self.bytes.load(Ordering::Relaxed))
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Drop the least recently used components until the cache fits its budget,
+    /// keeping `keep`, which was just loaded
+    fn evict_to_budget(&self, keep: &str) {
+        if self.max_bytes == 0 {
+            return;
+        }
+        while self.bytes.load(Ordering::Relaxed) > self.max_bytes {
+            let victim = self
+                .entries
+                .iter()
+                .filter(|entry| entry.key() != keep && evictable(entry.key()))
+                .min_by_key(|entry| entry.last_used.load(Ordering::Relaxed))
+                .map(|entry| entry.key().clone());
+            let Some(victim) = victim else {
+                return;
+            };
+            self.remove(&victim);
+            if let Some(pool) = INSTANCE_POOL.get() {
+                pool.evict(&victim);
+            }
+            debug!("Dropped the component of '{}' from memory", victim);
+        }
+    }
+}
+
+/// Whether the component of `version` may be dropped to make room
+fn evictable(version: &str) -> bool {
+    !version.ends_with(CANARY_SUFFIX) && !is_kept_warm(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_recently_used_components_are_dropped() {
+        let cache = ComponentCache::new(300);
+        cache.insert("a", 1, 100);
+        cache.insert("b", 2, 100);
+        cache.insert("c", 3, 100);
+        assert_eq!(cache.get("a"), Some(1));
+
+        // "b" is the least recently used now
+        cache.insert("d", 4, 100);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.usage(), (3, 300));
+
+        // Canaries stay even when the cache is over budget
+        cache.insert("e@canary", 5, 150);
+        assert_eq!(cache.usage(), (2, 250));
+        cache.insert("f", 6, 250);
+        assert_eq!(cache.get("d"), None);
+        assert_eq!(cache.get("e@canary"), Some(5));
+        assert_eq!(cache.usage(), (2, 400));
+        assert!(cache.remove("e@canary"));
+        assert_eq!(cache.usage(), (1, 250));
+    }
+}
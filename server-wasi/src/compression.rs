@@ -0,0 +1,440 @@
+//! Response compression.
+//!
+//! Responses are compressed with Brotli or gzip, whichever the client's
+//! `Accept-Encoding` prefers (Brotli on a tie), so functions don't each compress
+//! inside the sandbox. A response is compressed when its `Content-Type` is in
+//! `--compression-types` and its `Content-Length`, if it has one, is at least
+//! `--compression-min-bytes`. Responses already encoded, marked `no-transform`,
+//! partial, bodiless or event streams are sent as they are.
+//!
+//! Bodies are compressed as they stream: what the function wrote is flushed to the
+//! client whenever it has nothing more ready, so long polls and chunked responses
+//! aren't held back. A compressed response loses its `Content-Length`, gets
+//! `Vary: Accept-Encoding`, and its `ETag` is made weak, as its bytes differ from
+//! the uncompressed response's.
+//!
+//! The encoders are the `flate2` and `brotli` crates.
+
+use brotli::CompressorWriter;
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use hyper::body::{Body, Frame};
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_RANGE, CONTENT_TYPE, ETAG, VARY,
+};
+use hyper::{Method, Request, Response};
+use once_cell::sync::OnceCell;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+
+use crate::streaming;
+
+/// Global compression settings, set at startup unless compression is off
+pub static COMPRESSION: OnceCell<Compression> = OnceCell::new();
+
+/// gzip level, zlib's default
+const GZIP_LEVEL: flate2::Compression = flate2::Compression::new(6);
+/// Brotli quality, fast enough to compress responses as they're sent
+const BROTLI_QUALITY: u32 = 5;
+/// Base-2 logarithm of Brotli's window, the encoder's default
+const BROTLI_WINDOW: u32 = 22;
+/// Size of the Brotli encoder's output buffer
+const OUTPUT_CHUNK: usize = 16 * 1024;
+
+/// A content coding the server compresses with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+pub struct Compression {
+    /// Smallest `Content-Length` compressed
+    min_bytes: u64,
+    /// Media types compressed: exact, or `type/*`
+    types: Vec<String>,
+}
+
+impl Compression {
+    /// Settings from `--compression-min-bytes` and the comma-separated
+    /// `--compression-types`
+    pub fn new(min_bytes: u64, types: &str) -> Self {
+        Self {
+            min_bytes,
+            types: types
+                .split(',')
+                .map(|media_type| media_type.trim().to_ascii_lowercase())
+                .filter(|media_type| !media_type.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Whether any media type is compressed
+    pub fn enabled(&self) -> bool {
+        !self.types.is_empty()
+    }
+
+    /// Whether a response with `headers` is worth compressing
+    fn compressible(&self, headers: &HeaderMap) -> bool {
+        if headers.contains_key(CONTENT_ENCODING)
+            || headers.contains_key(CONTENT_RANGE)
+            || streaming::is_event_stream(headers)
+        {
+            return false;
+        }
+        let no_transform = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+        if no_transform {
+            return false;
+        }
+        let too_small = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+            .is_some_and(|length| length < self.min_bytes);
+        if too_small {
+            return false;
+        }
+        let Some(media_type) = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|media_type| media_type.trim().to_ascii_lowercase())
+        else {
+            return false;
+        };
+        self.types
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => prefix.ends_with('/') && media_type.starts_with(prefix),
+                None => *pattern == media_type,
+            })
+    }
+}
+
+/// The encoding to compress the response to `req` with, if the client accepts one
+pub fn negotiate<B>(req: &Request<B>) -> Option<Encoding> {
+    if req.method() == Method::HEAD {
+        return None;
+    }
+    let accept = req.headers().get(ACCEPT_ENCODING)?.to_str().ok()?;
+    preferred_encoding(accept)
+}
+
+/// The encoding an `Accept-Encoding` value prefers, Brotli on a tie
+fn preferred_encoding(accept: &str) -> Option<Encoding> {
+    let (mut brotli, mut gzip, mut any) = (None, None, None);
+    for coding in accept.split(',') {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match name.as_str() {
+            "br" => brotli = Some(quality),
+            "gzip" | "x-gzip" => gzip = Some(quality),
+            "*" => any = Some(quality),
+            _ => {}
+        }
+    }
+    let brotli = brotli.or(any).unwrap_or(0.0);
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    if brotli > 0.0 && brotli >= gzip {
+        Some(Encoding::Brotli)
+    } else if gzip > 0.0 {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    }
+}
+
+/// Compress `resp` with `encoding`, the client's choice, if it's eligible.
+/// Eligible responses vary by `Accept-Encoding` even when the client accepts
+/// neither encoding.
+pub fn compress(
+    resp: Response<HyperOutgoingBody>,
+    encoding: Option<Encoding>,
+) -> Response<HyperOutgoingBody> {
+    let Some(compression) = COMPRESSION.get() else {
+        return resp;
+    };
+    let status = resp.status().as_u16();
+    if status < 200 || status == 204 || status == 206 || status == 304 {
+        return resp;
+    }
+    if !compression.compressible(resp.headers()) {
+        return resp;
+    }
+    let (mut parts, body) = resp.into_parts();
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    let Some(encoding) = encoding else {
+        return Response::from_parts(parts, body);
+    };
+    let encoder = Encoder::new(encoding);
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    if let Some(etag) = parts.headers.get(ETAG).and_then(|tag| tag.to_str().ok()) {
+        if !etag.starts_with("W/") {
+            if let Ok(weak) = HeaderValue::from_str(&format!("W/{etag}")) {
+                parts.headers.insert(ETAG, weak);
+            }
+        }
+    }
+    let body = CompressedBody {
+        inner: body,
+        encoder,
+        unflushed: false,
+        trailers: None,
+        finished: false,
+    };
+    Response::from_parts(parts, HyperOutgoingBody::new(body))
+}
+
+/// A body compressed as it streams
+struct CompressedBody {
+    inner: HyperOutgoingBody,
+    encoder: Encoder,
+    /// Whether input was written since the last flush
+    unflushed: bool,
+    /// Trailers of the inner body, sent once the compressed data ended
+    trailers: Option<HeaderMap>,
+    finished: bool,
+}
+
+impl Body for CompressedBody {
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, ErrorCode>>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(
+                this.trailers
+                    .take()
+                    .map(|trailers| Ok(Frame::trailers(trailers))),
+            );
+        }
+        loop {
+            let output = match Pin::new(&mut this.inner).poll_frame(cx) {
+                // Send what's compressed so far while the function writes more
+                Poll::Pending if this.unflushed => {
+                    this.unflushed = false;
+                    this.encoder.write(&[], Flush::Sync)
+                }
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+                    Ok(data) => {
+                        this.unflushed = true;
+                        this.encoder.write(&data, Flush::None)
+                    }
+                    Err(frame) => {
+                        this.trailers = frame.into_trailers().ok();
+                        this.finished = true;
+                        this.encoder.write(&[], Flush::Finish)
+                    }
+                },
+                Poll::Ready(None) => {
+                    this.finished = true;
+                    this.encoder.write(&[], Flush::Finish)
+                }
+            };
+            let output = output.map_err(|e| ErrorCode::InternalError(Some(e.to_string())))?;
+            if !output.is_empty() {
+                return Poll::Ready(Some(Ok(Frame::data(Bytes::from(output)))));
+            }
+            if this.finished {
+                return Poll::Ready(
+                    this.trailers
+                        .take()
+                        .map(|trailers| Ok(Frame::trailers(trailers))),
+                );
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Flush {
+    None,
+    /// Output everything written so far
+    Sync,
+    /// End the stream
+    Finish,
+}
+
+/// A gzip or Brotli compression stream, writing to a buffer that's drained after
+/// each write
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    /// Taken once the stream is finished
+    Brotli(Option<Box<CompressorWriter<Vec<u8>>>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), GZIP_LEVEL)),
+            Encoding::Brotli => Encoder::Brotli(Some(Box::new(CompressorWriter::new(
+                Vec::new(),
+                OUTPUT_CHUNK,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            )))),
+        }
+    }
+
+    /// Compress `input`, returning the output the encoder has ready
+    fn write(&mut self, input: &[u8], flush: Flush) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(input)?;
+                match flush {
+                    Flush::None => {}
+                    Flush::Sync => encoder.flush()?,
+                    Flush::Finish => encoder.try_finish()?,
+                }
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Encoder::Brotli(writer) => {
+                let Some(encoder) = writer else {
+                    return Err(io::Error::other("Brotli stream already finished"));
+                };
+                encoder.write_all(input)?;
+                match flush {
+                    Flush::None => Ok(std::mem::take(encoder.get_mut())),
+                    Flush::Sync => {
+                        encoder.flush()?;
+                        Ok(std::mem::take(encoder.get_mut()))
+                    }
+                    Flush::Finish => Ok(writer.take().map(|w| w.into_inner()).unwrap_or_default()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Full};
+    use std::io::Read;
+
+    fn decode(encoding: Encoding, data: &[u8]) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        match encoding {
+            Encoding::Gzip => flate2::read::GzDecoder::new(data).read_to_end(&mut decoded),
+            Encoding::Brotli => brotli::Decompressor::new(data, 4096).read_to_end(&mut decoded),
+        }
+        .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn test_accept_encoding_prefers_brotli_unless_weighted_lower() {
+        assert_eq!(
+            preferred_encoding("gzip, deflate, br"),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(preferred_encoding("br;q=0.5, gzip"), Some(Encoding::Gzip));
+        assert_eq!(preferred_encoding("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(preferred_encoding("*;q=0.1"), Some(Encoding::Brotli));
+        assert_eq!(preferred_encoding("identity, deflate"), None);
+        assert_eq!(preferred_encoding("gzip;q=0"), None);
+    }
+
+    #[test]
+    fn test_only_eligible_responses_are_compressible() {
+        let compression = Compression::new(100, "text/*, application/json");
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            headers
+        };
+        assert!(compression.compressible(&headers(&[("content-type", "text/html")])));
+        assert!(compression.compressible(&headers(&[
+            ("content-type", "Application/JSON; charset=utf-8"),
+            ("content-length", "100"),
+        ])));
+        assert!(!compression.compressible(&headers(&[
+            ("content-type", "text/html"),
+            ("content-length", "99"),
+        ])));
+        assert!(!compression.compressible(&headers(&[("content-type", "image/png")])));
+        assert!(!compression.compressible(&headers(&[])));
+        assert!(!compression.compressible(&headers(&[("content-type", "text/event-stream")])));
+        assert!(!compression.compressible(&headers(&[
+            ("content-type", "text/html"),
+            ("content-encoding", "gzip"),
+        ])));
+        assert!(!compression.compressible(&headers(&[
+            ("content-type", "text/html"),
+            ("cache-control", "public, no-transform"),
+        ])));
+    }
+
+    #[tokio::test]
+    async fn test_bodies_round_trip_through_both_encoders() {
+        let text = "Hello, compressed world! ".repeat(2000);
+        for encoding in [Encoding::Gzip, Encoding::Brotli] {
+            let mut encoder = Encoder::new(encoding);
+            let mut compressed = encoder.write(text.as_bytes(), Flush::None).unwrap();
+            let flushed = encoder.write(&[], Flush::Sync).unwrap();
+            // What was written so far decodes once flushed
+            assert!(!flushed.is_empty());
+            compressed.extend(flushed);
+            compressed.extend(encoder.write(b"tail", Flush::Finish).unwrap());
+            assert!(compressed.len() < text.len() / 10);
+            assert_eq!(
+                decode(encoding, &compressed),
+                format!("{text}tail").as_bytes()
+            );
+        }
+
+        // Responses get compressed bodies and adjusted headers
+        let _ = COMPRESSION.set(Compression::new(10, "text/*"));
+        let body = Full::new(Bytes::from(text.clone()))
+            .map_err(|_| ErrorCode::InternalError(None))
+            .boxed();
+        let resp = Response::builder()
+            .header(CONTENT_TYPE, "text/plain")
+            .header(CONTENT_LENGTH, text.len())
+            .header(ETAG, "\"abc\"")
+            .body(HyperOutgoingBody::new(body))
+            .unwrap();
+        let resp = compress(resp, Some(Encoding::Gzip));
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(resp.headers()[VARY], "accept-encoding");
+        assert_eq!(resp.headers()[ETAG], "W/\"abc\"");
+        assert!(!resp.headers().contains_key(CONTENT_LENGTH));
+        let compressed = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(decode(Encoding::Gzip, &compressed), text.as_bytes());
+    }
+}
@@ -0,0 +1,431 @@
+//! Consistency checks of the server's records.
+//!
+//! A function is spread over records written one after the other: its owner's
+//! list of projects, its WebAssembly in artifact storage, its metadata and
+//! per-function data in sled, and the redirects its renames left behind. A crash
+//! halfway through a publish, rename or delete leaves them disagreeing. Every
+//! `--consistency-check-interval` hours they are cross-checked; the report is kept
+//! for `cargo faasta admin consistency` and any drift is journaled.
+//!
+//! With `--consistency-repair`, the scheduled check also repairs the drift that can
+//! be repaired without losing anything, once two checks in a row found it, so a
+//! publish or rename in progress isn't mistaken for drift. Artifacts missing from
+//! storage, and artifacts no account lists, are only reported: repairing them would
+//! mean deleting a function or WebAssembly someone may still want.
+
+use anyhow::{anyhow, Result};
+use faasta_interface::{
+    ConsistencyReport, Drift, DriftKind, EventSeverity, FunctionInfo, FunctionMetadata,
+    ServerEventKind,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{error, info, warn};
+
+use crate::canary::CANARY_SUFFIX;
+use crate::function_data;
+use crate::journal;
+use crate::route_handlers::ROUTE_SUFFIX;
+use crate::rpc_service::{decode_function_info, function_usage, FUNCTIONS_DB_TREE};
+use crate::trash::TRASH;
+use crate::wasi_server::{FaastaServer, SERVER};
+
+/// Sled tree holding the report of the last check
+pub const CONSISTENCY_TREE: &str = "consistency_report";
+const LATEST_KEY: &[u8] = b"latest";
+
+/// Which drift a check repairs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Repair {
+    /// None, the drift is only reported
+    Off,
+    /// Drift the previous check found too
+    SeenTwice,
+    /// Drift of these kinds, where it can be repaired
+    Kinds(Vec<DriftKind>),
+}
+
+/// What the server's records say, gathered for a check
+#[derive(Default)]
+pub struct Records {
+    /// Owner of each function with metadata
+    pub functions: BTreeMap<String, String>,
+    /// Functions, canaries and route handlers with WebAssembly in artifact storage
+    pub artifacts: BTreeSet<String>,
+    /// Projects each account and team lists
+    pub projects: BTreeMap<String, Vec<String>>,
+    /// Names with per-function data
+    pub data: BTreeSet<String>,
+    /// Target of each redirect, by old name
+    pub redirects: BTreeMap<String, String>,
+    /// Functions in the trash
+    pub trashed: BTreeSet<String>,
+}
+
+impl Records {
+    async fn gather(server: &FaastaServer) -> Result<Self> {
+        let functions = server
+            .metadata
+            .scan(FUNCTIONS_DB_TREE)?
+            .into_iter()
+            .filter_map(|(_, value)| decode_function_info(&value).ok())
+            .map(|info| (info.name, info.owner))
+            .collect();
+        let artifacts = server
+            .storage
+            .list()
+            .await?
+            .iter()
+            .filter_map(|key| key.strip_suffix(".wasm"))
+            .map(String::from)
+            .collect();
+        let projects = server
+            .github_auth
+            .all_users()?
+            .into_iter()
+            .map(|user| (user.github_username, user.projects))
+            .collect();
+        let redirects = server
+            .redirects
+            .all()
+            .into_iter()
+            .map(|(from, redirect)| (from, redirect.target))
+            .collect();
+        Ok(Self {
+            functions,
+            artifacts,
+            projects,
+            data: function_data::names(server.metadata.as_ref(), &server.metadata_db)?,
+            redirects,
+            trashed: TRASH
+                .get()
+                .map(|trash| trash.names().into_iter().collect())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Whether `name` is, or was until recently, a function
+    fn known(&self, name: &str) -> bool {
+        let name = base_name(name);
+        self.functions.contains_key(name) || self.trashed.contains(name)
+    }
+
+    /// Account listing `name` among its projects, if one does
+    fn lister(&self, name: &str) -> Option<&str> {
+        self.projects
+            .iter()
+            .find(|(_, projects)| projects.iter().any(|project| project == name))
+            .map(|(account, _)| account.as_str())
+    }
+
+    /// Every disagreement between the records
+    pub fn drift(&self) -> Vec<Drift> {
+        let mut drift = Vec::new();
+        let mut found = |kind, subject: &str, detail: String| {
+            drift.push(Drift {
+                kind,
+                subject: subject.to_string(),
+                detail,
+                repaired: false,
+            })
+        };
+
+        for (name, owner) in &self.functions {
+            if !self.artifacts.contains(name) {
+                found(
+                    DriftKind::MissingArtifact,
+                    name,
+                    format!("No {name}.wasm in artifact storage; it must be published again"),
+                );
+            }
+            let listed = self
+                .projects
+                .get(owner)
+                .is_some_and(|projects| projects.contains(name));
+            if !listed {
+                found(
+                    DriftKind::UnlistedFunction,
+                    name,
+                    format!("Owned by '{owner}' but missing from their projects"),
+                );
+            }
+        }
+
+        for name in &self.artifacts {
+            if self.known(name) {
+                continue;
+            }
+            let detail = match self.lister(name) {
+                Some(account) => format!("Listed by '{account}' but has no metadata"),
+                None => "No function or project owns it".to_string(),
+            };
+            found(DriftKind::OrphanedArtifact, name, detail);
+        }
+
+        for (account, projects) in &self.projects {
+            for project in projects {
+                match self.functions.get(project) {
+                    Some(owner) if owner != account => found(
+                        DriftKind::ForeignProject,
+                        project,
+                        format!("Listed by '{account}' but owned by '{owner}'"),
+                    ),
+                    Some(_) => {}
+                    // An artifact without metadata is reported as orphaned instead
+                    None if self.trashed.contains(project) || self.artifacts.contains(project) => {}
+                    None => found(
+                        DriftKind::StaleProject,
+                        project,
+                        format!("Listed by '{account}' but not a function"),
+                    ),
+                }
+            }
+        }
+
+        for name in &self.data {
+            if !self.known(name) {
+                found(
+                    DriftKind::OrphanedData,
+                    name,
+                    "Per-function data kept for a name that isn't a function".to_string(),
+                );
+            }
+        }
+
+        for (from, target) in &self.redirects {
+            if !self.known(target) {
+                found(
+                    DriftKind::StaleRedirect,
+                    from,
+                    format!("Redirects to '{target}', which isn't a function"),
+                );
+            }
+        }
+        drift
+    }
+}
+
+/// The function a version belongs to
+fn base_name(name: &str) -> &str {
+    name.strip_suffix(CANARY_SUFFIX)
+        .or_else(|| name.split_once(ROUTE_SUFFIX).map(|(base, _)| base))
+        .unwrap_or(name)
+}
+
+/// The report of the last check, if one ran
+pub fn latest(db: &sled::Db) -> Result<Option<ConsistencyReport>> {
+    let Some(value) = db.open_tree(CONSISTENCY_TREE)?.get(LATEST_KEY)? else {
+        return Ok(None);
+    };
+    let (report, _) = bincode::decode_from_slice(&value, bincode::config::standard())?;
+    Ok(Some(report))
+}
+
+/// Cross-check the server's records, repair the drift `repair` allows, and keep
+/// and journal the report
+pub async fn check(repair: Repair) -> Result<ConsistencyReport> {
+    let server = SERVER
+        .get()
+        .ok_or_else(|| anyhow!("Server not initialised"))?;
+    let records = Records::gather(server).await?;
+    let mut drift = records.drift();
+
+    let previous = latest(&server.metadata_db)?.map(|report| report.drift);
+    for found in drift.iter_mut() {
+        let allowed = match &repair {
+            Repair::Off => false,
+            Repair::SeenTwice => previous.as_ref().is_some_and(|previous| {
+                previous
+                    .iter()
+                    .any(|seen| seen.kind == found.kind && seen.subject == found.subject)
+            }),
+            Repair::Kinds(kinds) => kinds.contains(&found.kind),
+        };
+        if !allowed {
+            continue;
+        }
+        match repair_drift(server, &records, found).await {
+            Ok(repaired) => found.repaired = repaired,
+            Err(e) => error!(
+                "Failed to repair {} of '{}': {}",
+                found.kind, found.subject, e
+            ),
+        }
+    }
+
+    let report = ConsistencyReport {
+        checked_at: chrono::Utc::now().to_rfc3339(),
+        functions: records.functions.len() as u64,
+        artifacts: records.artifacts.len() as u64,
+        drift,
+    };
+    server.metadata_db.open_tree(CONSISTENCY_TREE)?.insert(
+        LATEST_KEY,
+        bincode::encode_to_vec(&report, bincode::config::standard())?,
+    )?;
+
+    if !report.drift.is_empty() {
+        let repaired = report.drift.iter().filter(|drift| drift.repaired).count();
+        let mut kinds: Vec<String> = Vec::new();
+        for drift in &report.drift {
+            let kind = drift.kind.to_string();
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        warn!(
+            "Consistency check found {} drifted records, repaired {}",
+            report.drift.len(),
+            repaired
+        );
+        journal::record(
+            EventSeverity::Warning,
+            ServerEventKind::ConsistencyDrift,
+            None,
+            format!(
+                "{} drifted records ({}), {} repaired",
+                report.drift.len(),
+                kinds.join(", "),
+                repaired
+            ),
+        );
+    }
+    Ok(report)
+}
+
+/// Repair `drift`, returning whether it could be
+async fn repair_drift(server: &FaastaServer, records: &Records, drift: &Drift) -> Result<bool> {
+    let name = drift.subject.as_str();
+    match drift.kind {
+        DriftKind::MissingArtifact => Ok(false),
+        DriftKind::OrphanedArtifact => {
+            // The publish stored the artifact and registered the project, but
+            // crashed before writing the metadata
+            let Some(owner) = records.lister(name) else {
+                return Ok(false);
+            };
+            let info = FunctionInfo {
+                name: name.to_string(),
+                owner: owner.to_string(),
+                published_at: chrono::Utc::now().to_rfc3339(),
+                usage: function_usage(name),
+                metadata: FunctionMetadata::default(),
+            };
+            server.metadata.insert(
+                FUNCTIONS_DB_TREE,
+                name,
+                bincode::encode_to_vec(&info, bincode::config::standard())?,
+            )?;
+            Ok(true)
+        }
+        DriftKind::UnlistedFunction => {
+            let owner = &records.functions[name];
+            server.github_auth.add_project(owner, name).await?;
+            Ok(true)
+        }
+        DriftKind::StaleProject | DriftKind::ForeignProject => {
+            for (account, projects) in &records.projects {
+                let owns = records.functions.get(name) == Some(account);
+                if !owns && projects.iter().any(|project| project == name) {
+                    server.github_auth.remove_project(account, name).await?;
+                }
+            }
+            Ok(true)
+        }
+        DriftKind::OrphanedData => {
+            function_data::remove(server.metadata.as_ref(), &server.metadata_db, name)?;
+            Ok(true)
+        }
+        DriftKind::StaleRedirect => {
+            server.redirects.remove(name)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Spawn a task checking the records every `interval_hours`, repairing the drift
+/// found twice in a row if `repair` is set
+pub fn spawn_periodic_check(interval_hours: u64, repair: bool) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_hours * 3600));
+        loop {
+            ticker.tick().await;
+            let repair = if repair {
+                Repair::SeenTwice
+            } else {
+                Repair::Off
+            };
+            match check(repair).await {
+                Ok(report) if report.drift.is_empty() => info!(
+                    "Consistency check found no drift across {} functions",
+                    report.functions
+                ),
+                Ok(_) => {}
+                Err(e) => error!("Consistency check failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_drift_between_records() {
+        let records = Records {
+            functions: [("api", "alice"), ("web", "bob"), ("gone", "bob")]
+                .into_iter()
+                .map(|(name, owner)| (name.to_string(), owner.to_string()))
+                .collect(),
+            artifacts: ["api", "web", "half-published", "stray", "old"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            projects: [
+                (
+                    "alice".to_string(),
+                    strings(&["api", "half-published", "web"]),
+                ),
+                ("bob".to_string(), strings(&["gone", "crashed"])),
+            ]
+            .into_iter()
+            .collect(),
+            data: ["api", "api@canary", "deleted", "old"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            redirects: [("before", "api"), ("older", "deleted")]
+                .into_iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+            trashed: ["old".to_string()].into_iter().collect(),
+        };
+
+        let drift = records.drift();
+        let found: Vec<(DriftKind, &str)> = drift
+            .iter()
+            .map(|drift| (drift.kind, drift.subject.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (DriftKind::MissingArtifact, "gone"),
+                (DriftKind::UnlistedFunction, "web"),
+                (DriftKind::OrphanedArtifact, "half-published"),
+                (DriftKind::OrphanedArtifact, "stray"),
+                (DriftKind::ForeignProject, "web"),
+                (DriftKind::StaleProject, "crashed"),
+                (DriftKind::OrphanedData, "deleted"),
+                (DriftKind::StaleRedirect, "older"),
+            ]
+        );
+        assert_eq!(records.lister("half-published"), Some("alice"));
+        assert_eq!(records.lister("stray"), None);
+    }
+}
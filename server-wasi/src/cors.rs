@@ -0,0 +1,318 @@
+//! CORS policies of functions.
+//!
+//! A function with a policy, set from the `[cors]` section of its project's
+//! `faasta.toml` on deploy, doesn't handle cross-origin requests itself: the server
+//! answers its preflight requests (`OPTIONS` with `Access-Control-Request-Method`)
+//! without invoking it, and adds `Access-Control-Allow-Origin` and the related
+//! headers to its responses to allowed origins. Responses the function gave CORS
+//! headers itself are left as they are.
+//!
+//! A refused preflight is answered without CORS headers, so the browser doesn't
+//! send the request.
+
+use anyhow::Result;
+use faasta_interface::CorsPolicy;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+    VARY,
+};
+use hyper::{Method, Request, Response};
+use once_cell::sync::OnceCell;
+use std::sync::Arc;
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+
+use crate::metadata_store::{Cached, MetadataStore};
+
+/// Collection of the metadata store holding each function's CORS policy, keyed by
+/// function name
+pub const CORS_POLICIES_TREE: &str = "function_cors_policies";
+
+/// Global CORS policies, set at startup
+pub static CORS: OnceCell<CorsPolicies> = OnceCell::new();
+
+/// Methods allowed without being listed, the CORS-safelisted ones
+const SAFELISTED_METHODS: [&str; 3] = ["GET", "HEAD", "POST"];
+
+pub struct CorsPolicies {
+    policies: Cached<CorsPolicy>,
+}
+
+impl CorsPolicies {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Result<Self> {
+        Ok(Self {
+            policies: Cached::new(store, CORS_POLICIES_TREE, |value| {
+                bincode::decode_from_slice(value, bincode::config::standard())
+                    .ok()
+                    .map(|(policy, _)| policy)
+            })?,
+        })
+    }
+
+    /// Set the policy of `name`, or remove it
+    pub fn set(&self, name: &str, policy: Option<&CorsPolicy>) -> Result<()> {
+        match policy {
+            Some(policy) => {
+                let encoded = bincode::encode_to_vec(policy, bincode::config::standard())?;
+                self.policies.insert(name, policy.clone(), encoded)
+            }
+            None => self.policies.remove(name).map(|_| ()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<CorsPolicy> {
+        self.policies.get(name)
+    }
+
+    /// Read `name`'s policy again, after its record was moved or copied
+    pub fn refresh(&self, name: &str) -> Result<()> {
+        self.policies.refresh(name)
+    }
+
+    /// Read every policy again, for changes made by other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.policies.reload()
+    }
+}
+
+/// The answer to `req` if it's a preflight request: allowing it when `policy`
+/// allows its origin, method and headers
+pub fn preflight<B>(
+    policy: &CorsPolicy,
+    req: &Request<B>,
+) -> Option<Result<Response<HyperOutgoingBody>>> {
+    let headers = req.headers();
+    let method = headers.get(ACCESS_CONTROL_REQUEST_METHOD)?;
+    if req.method() != Method::OPTIONS {
+        return None;
+    }
+    let mut builder = Response::builder().status(204).header(
+        VARY,
+        "origin, access-control-request-method, access-control-request-headers",
+    );
+
+    let origin = headers.get(ORIGIN).and_then(|origin| origin.to_str().ok());
+    let method = method.to_str().unwrap_or("");
+    let requested_headers: Vec<&str> = headers
+        .get_all(ACCESS_CONTROL_REQUEST_HEADERS)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|header| !header.is_empty())
+        .collect();
+    let allowed = origin.is_some_and(|origin| origin_allowed(policy, origin))
+        && method_allowed(policy, method)
+        && requested_headers
+            .iter()
+            .all(|header| header_allowed(policy, header));
+
+    if let (true, Some(origin)) = (allowed, origin) {
+        builder = builder
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin(policy, origin))
+            .header(ACCESS_CONTROL_ALLOW_METHODS, method);
+        if !requested_headers.is_empty() {
+            builder = builder.header(ACCESS_CONTROL_ALLOW_HEADERS, requested_headers.join(", "));
+        }
+        if policy.allow_credentials {
+            builder = builder.header(ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+        }
+        if let Some(max_age) = policy.max_age {
+            builder = builder.header(ACCESS_CONTROL_MAX_AGE, max_age);
+        }
+    }
+    let body = Empty::new()
+        .map_err(|_| ErrorCode::InternalError(None))
+        .boxed();
+    Some(
+        builder
+            .body(HyperOutgoingBody::new(body))
+            .map_err(Into::into),
+    )
+}
+
+/// Add the CORS headers for a request from `origin` to its response's `headers`
+pub fn apply(policy: &CorsPolicy, origin: Option<&HeaderValue>, headers: &mut HeaderMap) {
+    if headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN) {
+        return;
+    }
+    let any_origin = policy.allowed_origins.iter().any(|allowed| allowed == "*");
+    if !any_origin || policy.allow_credentials {
+        // Responses differ by origin
+        headers.append(VARY, HeaderValue::from_static("origin"));
+    }
+    let Some(origin) = origin.and_then(|origin| origin.to_str().ok()) else {
+        return;
+    };
+    if !origin_allowed(policy, origin) {
+        return;
+    }
+    if let Ok(value) = HeaderValue::from_str(allow_origin(policy, origin)) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, value);
+    }
+    if policy.allow_credentials {
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+    if !policy.expose_headers.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&policy.expose_headers.join(", ")) {
+            headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, value);
+        }
+    }
+}
+
+/// `Access-Control-Allow-Origin` for an allowed `origin`
+fn allow_origin<'a>(policy: &CorsPolicy, origin: &'a str) -> &'a str {
+    let any_origin = policy.allowed_origins.iter().any(|allowed| allowed == "*");
+    if any_origin && !policy.allow_credentials {
+        "*"
+    } else {
+        origin
+    }
+}
+
+fn origin_allowed(policy: &CorsPolicy, origin: &str) -> bool {
+    policy.allowed_origins.iter().any(|allowed| {
+        if allowed == "*" || allowed.eq_ignore_ascii_case(origin) {
+            return true;
+        }
+        // `https://*.example.com` matches subdomains of example.com only
+        let Some((scheme, domain)) = allowed.split_once("://*.") else {
+            return false;
+        };
+        let Some(host) = origin
+            .get(..scheme.len() + 3)
+            .filter(|prefix| prefix.eq_ignore_ascii_case(&format!("{scheme}://")))
+            .and_then(|_| origin.get(scheme.len() + 3..))
+        else {
+            return false;
+        };
+        host.len() > domain.len() + 1
+            && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+    })
+}
+
+fn method_allowed(policy: &CorsPolicy, method: &str) -> bool {
+    SAFELISTED_METHODS.contains(&method)
+        || policy
+            .allowed_methods
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(method))
+}
+
+fn header_allowed(policy: &CorsPolicy, header: &str) -> bool {
+    policy
+        .allowed_headers
+        .iter()
+        .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> CorsPolicy {
+        CorsPolicy {
+            allowed_origins: vec![
+                "https://app.example.com".to_string(),
+                "https://*.example.org".to_string(),
+            ],
+            allowed_methods: vec!["PUT".to_string()],
+            allowed_headers: vec!["Content-Type".to_string()],
+            expose_headers: vec!["x-total".to_string()],
+            allow_credentials: true,
+            max_age: Some(600),
+        }
+    }
+
+    fn preflight_request(origin: &str, method: &str, headers: &str) -> Request<()> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/items")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(ACCESS_CONTROL_REQUEST_HEADERS, headers)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_preflights_are_answered_from_the_policy() {
+        let policy = policy();
+        let resp = preflight(
+            &policy,
+            &preflight_request("https://app.example.com", "PUT", "content-type"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(resp.status(), 204);
+        let headers = resp.headers();
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "PUT");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_HEADERS], "content-type");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "600");
+
+        for refused in [
+            preflight_request("https://evil.example.com", "PUT", ""),
+            preflight_request("https://app.example.com", "DELETE", ""),
+            preflight_request("https://app.example.com", "GET", "authorization"),
+        ] {
+            let resp = preflight(&policy, &refused).unwrap().unwrap();
+            assert!(!resp.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        }
+
+        // Other requests go to the function
+        let get = Request::builder()
+            .header(ORIGIN, "https://app.example.com")
+            .body(())
+            .unwrap();
+        assert!(preflight(&policy, &get).is_none());
+    }
+
+    #[test]
+    fn test_responses_get_headers_for_allowed_origins() {
+        let policy = policy();
+        let origin = |origin: &str| {
+            let mut headers = HeaderMap::new();
+            apply(
+                &policy,
+                Some(&HeaderValue::from_str(origin).unwrap()),
+                &mut headers,
+            );
+            headers
+        };
+        let headers = origin("https://api.example.org");
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://api.example.org"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_EXPOSE_HEADERS], "x-total");
+        assert_eq!(headers[VARY], "origin");
+        assert!(!origin("https://example.org").contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!origin("http://api.example.org").contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!origin("https://apiexample.org").contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let public = CorsPolicy {
+            allowed_origins: vec!["*".to_string()],
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        apply(
+            &public,
+            Some(&HeaderValue::from_static("https://any.site")),
+            &mut headers,
+        );
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!headers.contains_key(VARY));
+    }
+}
@@ -0,0 +1,169 @@
+//! Fair queueing of publishes.
+//!
+//! Only a few deploys are validated, compiled and stored at once, so a burst of
+//! them (say, everyone redeploying after an incident) can't starve request
+//! serving. The rest wait in per-user queues that are served round-robin, so one
+//! user pushing many functions doesn't hold up everyone else. Waiting deploys can
+//! be asked for their position, which the CLI shows while it waits.
+
+use once_cell::sync::OnceCell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+/// Global deploy queue, set at startup
+pub static DEPLOY_QUEUE: OnceCell<DeployQueue> = OnceCell::new();
+
+struct Waiter {
+    id: u64,
+    function_name: String,
+    ready: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    running: usize,
+    /// Users with waiting deploys, in the order they are served next
+    users: VecDeque<(String, VecDeque<Waiter>)>,
+}
+
+impl QueueState {
+    /// Waiting deploys in the order they will run
+    fn order(&self) -> impl Iterator<Item = (&str, &Waiter)> {
+        let rounds = self.users.iter().map(|(_, waiters)| waiters.len()).max();
+        (0..rounds.unwrap_or(0)).flat_map(move |round| {
+            self.users.iter().filter_map(move |(user, waiters)| {
+                waiters.get(round).map(|waiter| (user.as_str(), waiter))
+            })
+        })
+    }
+
+    fn remove(&mut self, id: u64) -> bool {
+        for (_, waiters) in self.users.iter_mut() {
+            if let Some(index) = waiters.iter().position(|waiter| waiter.id == id) {
+                waiters.remove(index);
+                self.users.retain(|(_, waiters)| !waiters.is_empty());
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Hand a free slot to the next waiting deploy, or give it up if none is waiting
+    fn release(&mut self) {
+        while let Some((user, mut waiters)) = self.users.pop_front() {
+            let Some(waiter) = waiters.pop_front() else {
+                continue;
+            };
+            if !waiters.is_empty() {
+                self.users.push_back((user, waiters));
+            }
+            // A deploy whose client went away no longer wants the slot
+            if waiter.ready.send(()).is_ok() {
+                return;
+            }
+        }
+        self.running -= 1;
+    }
+}
+
+/// Wait for a deploy slot in the global queue
+pub async fn wait_for_turn(username: &str, function_name: &str) -> Option<DeployPermit<'static>> {
+    match DEPLOY_QUEUE.get() {
+        Some(queue) => Some(queue.enter(username, function_name).await),
+        None => None,
+    }
+}
+
+pub struct DeployQueue {
+    /// Most deploys processed at once
+    slots: usize,
+    next_id: AtomicU64,
+    state: Mutex<QueueState>,
+}
+
+/// A deploy's turn to run; the next one in line starts when it is dropped
+pub struct DeployPermit<'a> {
+    queue: &'a DeployQueue,
+}
+
+impl Drop for DeployPermit<'_> {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().release();
+    }
+}
+
+/// Takes a waiting deploy out of the queue if it is abandoned
+struct WaitGuard<'a> {
+    queue: &'a DeployQueue,
+    id: u64,
+    done: bool,
+}
+
+impl Drop for WaitGuard<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut state = self.queue.state.lock().unwrap();
+        // Not in the queue any more means it was handed a slot it will never use
+        if !state.remove(self.id) {
+            state.release();
+        }
+    }
+}
+
+impl DeployQueue {
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots: slots.max(1),
+            next_id: AtomicU64::new(0),
+            state: Mutex::new(QueueState::default()),
+        }
+    }
+
+    /// Wait for `username`'s turn to deploy `function_name`
+    pub async fn enter(&self, username: &str, function_name: &str) -> DeployPermit<'_> {
+        let (ready, wait) = oneshot::channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.running < self.slots && state.users.is_empty() {
+                state.running += 1;
+                return DeployPermit { queue: self };
+            }
+
+            let waiter = Waiter {
+                id,
+                function_name: function_name.to_string(),
+                ready,
+            };
+            match state.users.iter_mut().find(|(user, _)| user == username) {
+                Some((_, waiters)) => waiters.push_back(waiter),
+                None => state
+                    .users
+                    .push_back((username.to_string(), VecDeque::from([waiter]))),
+            }
+        }
+
+        let mut guard = WaitGuard {
+            queue: self,
+            id,
+            done: false,
+        };
+        // The sender is only dropped unused when the queue itself goes away
+        let _ = wait.await;
+        guard.done = true;
+        DeployPermit { queue: self }
+    }
+
+    /// 1-based position of `username`'s waiting deploy of `function_name`
+    pub fn position(&self, username: &str, function_name: &str) -> Option<u32> {
+        let state = self.state.lock().unwrap();
+        let position = state
+            .order()
+            .position(|(user, waiter)| user == username && waiter.function_name == function_name)?;
+        Some(position as u32 + 1)
+    }
+}
@@ -0,0 +1,118 @@
+//! Internal bus of platform events.
+//!
+//! The invocation path, publishes and certificate management publish what
+//! happened (a function deployed, an invocation completed, an account over its
+//! quota, a certificate renewed) instead of calling every subsystem interested in
+//! it. Subsystems such as metrics, circuit breakers, anomaly alerts and the
+//! journal subscribe at startup, so an integration is added by subscribing rather
+//! than by touching the code publishing the event.
+//!
+//! Subscribers are called in the order they subscribed, on the publisher's task,
+//! some of them on every invocation: they must return quickly, spawning anything
+//! slow.
+
+use once_cell::sync::Lazy;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+/// Global event bus
+pub static EVENT_BUS: Lazy<EventBus> = Lazy::new(EventBus::default);
+
+/// Something that happened on the platform
+#[derive(Clone, Debug)]
+pub enum PlatformEvent {
+    /// A new version of `function` was published
+    Deployed {
+        function: String,
+        /// User who published it, on its owner's behalf for a team
+        username: String,
+        /// Address the publish came from, if known
+        peer: Option<IpAddr>,
+    },
+    /// An invocation of `version` of `function` finished
+    InvokeCompleted {
+        function: String,
+        /// Function name, or that of its canary
+        version: String,
+        trapped: bool,
+        /// Id of the instance that served it, reused instances serving many
+        instance: u64,
+        /// Linear memory the instance grew to
+        memory_bytes: u64,
+    },
+    /// `account` was refused for having `limit` of `quota` already
+    QuotaExceeded {
+        account: Option<String>,
+        quota: String,
+        limit: u64,
+    },
+    /// A new TLS certificate for `domain` was installed
+    CertRenewed { domain: String },
+}
+
+/// Something interested in platform events
+pub trait Subscriber: Send + Sync {
+    fn on_event(&self, event: &PlatformEvent);
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: RwLock<Vec<&'static dyn Subscriber>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self, subscriber: &'static dyn Subscriber) {
+        self.subscribers.write().unwrap().push(subscriber);
+    }
+
+    pub fn publish(&self, event: &PlatformEvent) {
+        for subscriber in self.subscribers.read().unwrap().iter() {
+            subscriber.on_event(event);
+        }
+    }
+}
+
+/// Call `subscriber` with every event published from now on
+pub fn subscribe(subscriber: &'static dyn Subscriber) {
+    EVENT_BUS.subscribe(subscriber);
+}
+
+/// Hand `event` to every subscriber
+pub fn publish(event: PlatformEvent) {
+    EVENT_BUS.publish(&event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Subscriber for Recorder {
+        fn on_event(&self, event: &PlatformEvent) {
+            if let PlatformEvent::CertRenewed { domain } = event {
+                self.0.lock().unwrap().push(domain.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn test_subscribers_see_events_published_after_subscribing() {
+        let bus = EventBus::default();
+        let early: &'static Recorder = Box::leak(Box::default());
+        let late: &'static Recorder = Box::leak(Box::default());
+        let renewed = |domain: &str| PlatformEvent::CertRenewed {
+            domain: domain.to_string(),
+        };
+
+        bus.subscribe(early);
+        bus.publish(&renewed("a.example"));
+        bus.subscribe(late);
+        bus.publish(&renewed("b.example"));
+
+        assert_eq!(*early.0.lock().unwrap(), ["a.example", "b.example"]);
+        assert_eq!(*late.0.lock().unwrap(), ["b.example"]);
+    }
+}
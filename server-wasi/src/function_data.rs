@@ -0,0 +1,265 @@
+//! Per-function records kept outside the function metadata.
+//!
+//! Features that store settings or data for a single function keep them in their
+//! own collection keyed by function name and list it here, so renames, clones and
+//! permanent deletes carry the data along without knowing about it. Settings are
+//! kept in the metadata store, so every node of a cluster applies them, and only
+//! indexes of what a node keeps itself in its own sled. Webhook deliveries, keyed by function name and delivery, are
+//! moved and deleted here too, but not copied to clones, and static assets, whose
+//! contents are counted by reference, are copied, moved and deleted through
+//! their store.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+
+use crate::access_keys::ACCESS_KEYS;
+use crate::aliases::ALIASES;
+use crate::circuit_breaker::BREAKERS;
+use crate::cors::CORS;
+use crate::jwt_auth::JWT_AUTH;
+use crate::keep_warm::KEEP_WARM;
+use crate::logs::LOGS;
+use crate::metadata_store::{Change, MetadataStore};
+use crate::static_assets::STATIC_ASSETS;
+use crate::transforms::TRANSFORMS;
+use crate::wasi_server::SERVER;
+use crate::webhooks::WEBHOOKS;
+
+/// Collection of the metadata store holding the digest of each function's
+/// published WebAssembly
+pub const ARTIFACT_DIGESTS_TREE: &str = "function_artifact_digests";
+
+/// Collections of the metadata store holding per-function records, keyed by
+/// function name
+pub const SHARED_DATA_TREES: &[&str] = &[
+    crate::access_keys::ACCESS_KEYS_TREE,
+    ARTIFACT_DIGESTS_TREE,
+    crate::cors::CORS_POLICIES_TREE,
+    crate::jwt_auth::JWT_AUTH_TREE,
+    crate::logs::LOG_LEVELS_TREE,
+    crate::logs::REDACTION_TREE,
+    crate::readiness::READINESS_TREE,
+    crate::specs::FUNCTION_SPECS_TREE,
+    crate::transforms::TRANSFORMS_TREE,
+];
+
+/// Collections of the metadata store holding per-function records that follow
+/// renames and permanent deletes but aren't copied to clones, since they would let
+/// a clone pass for the original or count against a limit of the clone's owner
+const UNCOPIED_SHARED_TREES: &[&str] = &[
+    crate::circuit_breaker::BREAKERS_TREE,
+    crate::keep_warm::KEEP_WARM_TREE,
+    crate::signing::SIGNING_KEYS_TREE,
+];
+
+/// Sled trees indexing what the node keeps of a function, which follow renames and
+/// permanent deletes but aren't copied to clones
+const UNCOPIED_TREES: &[&str] = &[
+    crate::snapshots::SNAPSHOT_INDEX_TREE,
+    crate::profiling::PROFILE_INDEX_TREE,
+];
+
+/// `sha256:<hex>` digest of a WebAssembly component
+pub fn artifact_digest(wasm: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(wasm)))
+}
+
+/// Remember that `name` now runs `wasm`, and whether it answers readiness probes
+pub fn record_artifact(store: &dyn MetadataStore, name: &str, wasm: &[u8]) -> Result<()> {
+    store.apply(&[
+        Change::Insert {
+            collection: ARTIFACT_DIGESTS_TREE,
+            key: name.to_string(),
+            value: artifact_digest(wasm).into_bytes(),
+        },
+        crate::readiness::record(name, wasm),
+    ])
+}
+
+/// Digest of the WebAssembly `name` runs, unless it was published before digests
+/// were recorded
+pub fn published_digest(store: &dyn MetadataStore, name: &str) -> Result<Option<String>> {
+    Ok(store
+        .get(ARTIFACT_DIGESTS_TREE, name)?
+        .map(|digest| String::from_utf8_lossy(&digest).into_owned()))
+}
+
+/// Copy `from`'s records to `to`, replacing any `to` already had
+pub fn copy(store: &dyn MetadataStore, from: &str, to: &str) -> Result<()> {
+    let mut changes = Vec::new();
+    for collection in SHARED_DATA_TREES {
+        if let Some(value) = store.get(collection, from)? {
+            changes.push(Change::Insert {
+                collection,
+                key: to.to_string(),
+                value,
+            });
+        }
+    }
+    store.apply(&changes)?;
+    refresh_cached(to)?;
+
+    if let Some(assets) = STATIC_ASSETS.get() {
+        assets.copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Move `from`'s records to `to`
+pub fn rename(store: &dyn MetadataStore, db: &sled::Db, from: &str, to: &str) -> Result<()> {
+    let mut changes = Vec::new();
+    for collection in SHARED_DATA_TREES.iter().chain(UNCOPIED_SHARED_TREES) {
+        if let Some(value) = store.get(collection, from)? {
+            changes.push(Change::Remove {
+                collection,
+                key: from.to_string(),
+            });
+            changes.push(Change::Insert {
+                collection,
+                key: to.to_string(),
+                value,
+            });
+        }
+    }
+    store.apply(&changes)?;
+    refresh_cached(from)?;
+    refresh_cached(to)?;
+
+    for tree in UNCOPIED_TREES {
+        let tree = db.open_tree(tree)?;
+        if let Some(value) = tree.remove(from.as_bytes())? {
+            tree.insert(to.as_bytes(), value)?;
+        }
+    }
+    forget_cached(from);
+    forget_cached(to);
+    if let Some(webhooks) = WEBHOOKS.get() {
+        webhooks.rename_function(from, to)?;
+    }
+    if let Some(assets) = STATIC_ASSETS.get() {
+        assets.rename(from, to)?;
+    }
+    Ok(())
+}
+
+/// Names that have records in any of the per-function collections
+pub fn names(store: &dyn MetadataStore, db: &sled::Db) -> Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    for collection in SHARED_DATA_TREES.iter().chain(UNCOPIED_SHARED_TREES) {
+        names.extend(store.scan(collection)?.into_iter().map(|(name, _)| name));
+    }
+    for tree in UNCOPIED_TREES {
+        for key in db.open_tree(tree)?.iter().keys() {
+            names.insert(String::from_utf8_lossy(&key?).into_owned());
+        }
+    }
+    if let Some(webhooks) = WEBHOOKS.get() {
+        names.extend(webhooks.functions()?);
+    }
+    if let Some(assets) = STATIC_ASSETS.get() {
+        names.extend(assets.functions()?);
+    }
+    Ok(names)
+}
+
+/// Delete all of `name`'s records
+pub fn remove(store: &dyn MetadataStore, db: &sled::Db, name: &str) -> Result<()> {
+    let changes: Vec<Change> = SHARED_DATA_TREES
+        .iter()
+        .chain(UNCOPIED_SHARED_TREES)
+        .map(|collection| Change::Remove {
+            collection,
+            key: name.to_string(),
+        })
+        .collect();
+    store.apply(&changes)?;
+    refresh_cached(name)?;
+
+    for tree in UNCOPIED_TREES {
+        db.open_tree(tree)?.remove(name.as_bytes())?;
+    }
+    forget_cached(name);
+    if let Some(webhooks) = WEBHOOKS.get() {
+        webhooks.remove_function(name)?;
+    }
+    if let Some(assets) = STATIC_ASSETS.get() {
+        assets.remove(name)?;
+    }
+    Ok(())
+}
+
+/// Read `name`'s settings again into the caches requests read them from, after
+/// they were changed around them
+fn refresh_cached(name: &str) -> Result<()> {
+    if let Some(access_keys) = ACCESS_KEYS.get() {
+        access_keys.refresh(name)?;
+    }
+    if let Some(breakers) = BREAKERS.get() {
+        breakers.refresh(name)?;
+    }
+    if let Some(cors) = CORS.get() {
+        cors.refresh(name)?;
+    }
+    if let Some(jwt_auth) = JWT_AUTH.get() {
+        jwt_auth.refresh(name)?;
+    }
+    if let Some(keep_warm) = KEEP_WARM.get() {
+        keep_warm.refresh(name)?;
+    }
+    if let Some(logs) = LOGS.get() {
+        logs.refresh(name)?;
+    }
+    if let Some(transforms) = TRANSFORMS.get() {
+        transforms.refresh(name)?;
+    }
+    if let Some(server) = SERVER.get() {
+        server.specs.refresh(name)?;
+        server.signing.refresh(name)?;
+    }
+    Ok(())
+}
+
+/// Drop what's cached of `name`'s local records, after they were moved or deleted
+fn forget_cached(name: &str) {
+    if let Some(profiling) = crate::profiling::PROFILING.get() {
+        profiling.forget(name);
+    }
+}
+
+/// Read every function's settings again into the caches requests read them from,
+/// for changes made by other nodes
+pub fn reload_cached() -> Result<()> {
+    if let Some(access_keys) = ACCESS_KEYS.get() {
+        access_keys.reload()?;
+    }
+    if let Some(breakers) = BREAKERS.get() {
+        breakers.reload()?;
+    }
+    if let Some(cors) = CORS.get() {
+        cors.reload()?;
+    }
+    if let Some(jwt_auth) = JWT_AUTH.get() {
+        jwt_auth.reload()?;
+    }
+    if let Some(keep_warm) = KEEP_WARM.get() {
+        keep_warm.reload()?;
+    }
+    if let Some(logs) = LOGS.get() {
+        logs.reload()?;
+    }
+    if let Some(transforms) = TRANSFORMS.get() {
+        transforms.reload()?;
+    }
+    if let Some(aliases) = ALIASES.get() {
+        aliases.reload()?;
+    }
+    if let Some(server) = SERVER.get() {
+        server.specs.reload()?;
+        server.signing.reload()?;
+        server.redirects.reload()?;
+        server.routes.reload()?;
+        server.suspensions.reload(&server.github_auth)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! Per-function write locks.
+//!
+//! Writes that check a function's state before changing it, such as publishes,
+//! unpublishes and the management API's conditional requests, hold the function's
+//! lock, so two of them can't both pass the same check. Writes to other functions
+//! go ahead meanwhile. A task running under [`locked`] already holds the lock, so
+//! the RPC calls the management API makes don't wait for it again.
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Lock of each function that is being written, removed once nobody holds it
+static LOCKS: Lazy<DashMap<String, Arc<Mutex<()>>>> = Lazy::new(DashMap::new);
+
+tokio::task_local! {
+    /// Function whose lock the current task holds through [`locked`]
+    static HELD: String;
+}
+
+/// A function's lock, released when dropped
+pub struct FunctionLock {
+    name: String,
+    /// `None` if the task already held the lock
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for FunctionLock {
+    fn drop(&mut self) {
+        if self.guard.take().is_some() {
+            LOCKS.remove_if(&self.name, |_, lock| Arc::strong_count(lock) == 1);
+        }
+    }
+}
+
+/// Wait for the lock of the function `name`
+pub async fn lock(name: &str) -> FunctionLock {
+    if HELD.try_with(|held| held == name).unwrap_or(false) {
+        return FunctionLock {
+            name: name.to_string(),
+            guard: None,
+        };
+    }
+    let lock = LOCKS.entry(name.to_string()).or_default().clone();
+    FunctionLock {
+        name: name.to_string(),
+        guard: Some(lock.lock_owned().await),
+    }
+}
+
+/// Run `f` holding the lock of the function `name`, including the writes it makes
+pub async fn locked<F: Future>(name: &str, f: F) -> F::Output {
+    let _lock = lock(name).await;
+    HELD.scope(name.to_string(), f).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_locks_are_per_function_and_held_through_locked() {
+        let first = lock("locks-hello").await;
+        // Another function isn't held up
+        drop(lock("locks-world").await);
+        let waiting = tokio::spawn(async { drop(lock("locks-hello").await) });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        waiting.await.unwrap();
+
+        // Taking the lock again under `locked` doesn't wait for itself
+        let nested = locked("locks-hello", async {
+            let _again = lock("locks-hello").await;
+            LOCKS.contains_key("locks-hello")
+        });
+        assert!(tokio::time::timeout(Duration::from_secs(1), nested)
+            .await
+            .unwrap());
+        assert!(!LOCKS.contains_key("locks-hello"));
+    }
+}
@@ -0,0 +1,626 @@
+//! Garbage collection.
+//!
+//! Crashes, aborted deploys and features turned off leave things behind that
+//! nothing uses any more: WebAssembly in artifact storage no function owns, canary
+//! and route handler versions their function dropped, precompiled artifacts of
+//! versions that aren't served and half-written temporary files on local disk, and
+//! per-function records of names that aren't functions. Every `--gc-interval` hours
+//! they are collected, along with functions whose time in the trash ran out and log
+//! lines past their retention, which are otherwise purged hourly.
+//!
+//! Garbage is only removed once it's been found for `--gc-grace` hours, so a
+//! publish, deploy or rename halfway through isn't mistaken for it.
+//! `cargo faasta admin gc --dry-run` reports what a collection would remove.
+//!
+//! In a cluster, canaries and the trash are kept by each node. Every node reports
+//! which it keeps on each cluster sync, and the canaries and trashed functions in
+//! the shared artifact storage that no node keeps are collected like the rest. Until
+//! every node has reported, they're left alone.
+
+use anyhow::{anyhow, Result};
+use bincode::{Decode, Encode};
+use chrono::Utc;
+use faasta_interface::{Garbage, GarbageKind, GarbageReport};
+use once_cell::sync::OnceCell;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::interval;
+use tracing::{error, info};
+
+use crate::canary::CANARY_SUFFIX;
+use crate::cluster::{Cluster, CLUSTER};
+use crate::function_data;
+use crate::logs::LOGS;
+use crate::route_handlers::ROUTE_SUFFIX;
+use crate::rpc_service::FUNCTIONS_DB_TREE;
+use crate::trash::{self, trashed_key, TRASH, TRASH_DIR};
+use crate::wasi_server::{FaastaServer, SERVER};
+
+/// Sled tree holding when each piece of garbage was first found, keyed by
+/// `<kind>/<subject>`
+const GC_TREE: &str = "gc_candidates";
+
+/// Collection of the metadata store holding the canaries and trashed functions
+/// each node of a cluster keeps, keyed by node id
+pub const INVENTORIES_TREE: &str = "gc_inventories";
+
+/// Global garbage collector, set at startup
+pub static GC: OnceCell<GarbageCollector> = OnceCell::new();
+
+pub struct GarbageCollector {
+    candidates: sled::Tree,
+    /// How long garbage is found before it's removed
+    grace: Duration,
+    /// Held while collecting, so collections don't overlap
+    running: Mutex<()>,
+}
+
+/// What the server keeps, to tell garbage apart
+#[derive(Default)]
+struct Inventory {
+    /// Functions with metadata
+    functions: BTreeSet<String>,
+    /// Projects the accounts list
+    listed: BTreeSet<String>,
+    /// Functions in the trash
+    trashed: BTreeSet<String>,
+    /// Functions with a canary
+    canaries: BTreeSet<String>,
+    /// Versions of the functions' current and staged route handlers
+    routes: BTreeSet<String>,
+    /// Keys of the WebAssembly in artifact storage
+    artifacts: Vec<String>,
+    /// Files in the functions directory and its trash, relative to it
+    cached: Vec<String>,
+    /// Names with per-function records or recorded use
+    records: BTreeSet<String>,
+    /// Whether artifact storage is shared with nodes keeping their own canaries
+    /// and trash
+    shared_storage: bool,
+    /// What the nodes sharing it keep, unless some node hasn't reported yet
+    kept_by_nodes: Option<NodeInventory>,
+}
+
+/// The canaries and trashed functions a node keeps
+#[derive(Default, Encode, Decode)]
+pub struct NodeInventory {
+    canaries: BTreeSet<String>,
+    trashed: BTreeSet<String>,
+}
+
+/// Report the canaries and trashed functions this node keeps to the rest of the
+/// cluster
+pub fn report_inventory(server: &FaastaServer, cluster: &Cluster) -> Result<()> {
+    let inventory = NodeInventory {
+        canaries: server.canaries.names().into_iter().collect(),
+        trashed: TRASH
+            .get()
+            .map(|trash| trash.names().into_iter().collect())
+            .unwrap_or_default(),
+    };
+    let encoded = bincode::encode_to_vec(&inventory, bincode::config::standard())?;
+    server
+        .metadata
+        .insert(INVENTORIES_TREE, cluster.node_id(), encoded)
+}
+
+/// What every node of the cluster keeps, `None` if a node hasn't reported it
+fn cluster_inventory(server: &FaastaServer, cluster: &Cluster) -> Result<Option<NodeInventory>> {
+    let mut reported = std::collections::HashMap::new();
+    for (node, value) in server.metadata.scan(INVENTORIES_TREE)? {
+        if let Ok((inventory, _)) =
+            bincode::decode_from_slice::<NodeInventory, _>(&value, bincode::config::standard())
+        {
+            reported.insert(node, inventory);
+        }
+    }
+    let mut kept = NodeInventory::default();
+    for node in cluster.nodes()? {
+        let Some(inventory) = reported.remove(&node.id) else {
+            return Ok(None);
+        };
+        kept.canaries.extend(inventory.canaries);
+        kept.trashed.extend(inventory.trashed);
+    }
+    Ok(Some(kept))
+}
+
+impl Inventory {
+    async fn gather(server: &FaastaServer) -> Result<Self> {
+        let functions = server
+            .metadata
+            .scan(FUNCTIONS_DB_TREE)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<BTreeSet<_>>();
+        let listed = server
+            .github_auth
+            .all_users()?
+            .into_iter()
+            .flat_map(|user| user.projects)
+            .collect();
+        let trashed = TRASH
+            .get()
+            .map(|trash| trash.names().into_iter().collect())
+            .unwrap_or_default();
+        let canaries = functions
+            .iter()
+            .filter(|name| server.canaries.weight(name).is_some())
+            .cloned()
+            .collect();
+        // Handlers staged for a publish aren't routed to yet
+        let mut routes: BTreeSet<String> = functions
+            .iter()
+            .flat_map(|name| server.routes.versions(name))
+            .collect();
+        routes.extend(server.routes.staged_versions()?);
+        let artifacts = server
+            .storage
+            .list()
+            .await?
+            .into_iter()
+            .filter(|key| key.ends_with(".wasm"))
+            .collect();
+        let mut cached = files(&server.functions_dir, "");
+        cached.extend(files(&server.functions_dir.join(TRASH_DIR), TRASH_DIR));
+        let mut records = function_data::names(server.metadata.as_ref(), &server.metadata_db)?;
+        records.extend(server.artifacts.used());
+        let kept_by_nodes = match CLUSTER.get() {
+            Some(cluster) => cluster_inventory(server, cluster)?,
+            None => None,
+        };
+        Ok(Self {
+            functions,
+            listed,
+            trashed,
+            canaries,
+            routes,
+            artifacts,
+            cached,
+            records,
+            shared_storage: CLUSTER.get().is_some(),
+            kept_by_nodes,
+        })
+    }
+
+    /// Why the version `version` isn't served, unless it is
+    fn unused_version(&self, version: &str) -> Option<(GarbageKind, String)> {
+        if let Some(name) = version.strip_suffix(CANARY_SUFFIX) {
+            return (!self.canaries.contains(name)).then(|| {
+                (
+                    GarbageKind::StaleVersion,
+                    format!("Canary of '{name}', which has none"),
+                )
+            });
+        }
+        if let Some((name, _)) = version.split_once(ROUTE_SUFFIX) {
+            return (!self.routes.contains(version)).then(|| {
+                (
+                    GarbageKind::StaleVersion,
+                    format!("Route handler '{name}' no longer uses"),
+                )
+            });
+        }
+        let owned = self.functions.contains(version)
+            || self.listed.contains(version)
+            || self.trashed.contains(version);
+        (!owned).then(|| {
+            (
+                GarbageKind::OrphanedArtifact,
+                "No function or project owns it".to_string(),
+            )
+        })
+    }
+
+    /// Everything nothing uses, as its kind, subject and why it's garbage
+    fn garbage(&self) -> Vec<(GarbageKind, String, String)> {
+        let mut garbage = Vec::new();
+
+        for key in &self.artifacts {
+            let Some(version) = key.strip_suffix(".wasm") else {
+                continue;
+            };
+            let found = match version.strip_prefix(&format!("{TRASH_DIR}/")) {
+                Some(name) if self.shared_storage => {
+                    let kept = self.kept_by_nodes.as_ref().is_none_or(|kept| {
+                        kept.trashed.contains(name) || self.trashed.contains(name)
+                    });
+                    (!kept).then(|| {
+                        (
+                            GarbageKind::OrphanedArtifact,
+                            format!("Trashed '{name}' is in no node's trash"),
+                        )
+                    })
+                }
+                Some(name) => (!self.trashed.contains(name)).then(|| {
+                    (
+                        GarbageKind::OrphanedArtifact,
+                        format!("Trashed '{name}' is no longer in the trash"),
+                    )
+                }),
+                None if self.shared_storage && version.ends_with(CANARY_SUFFIX) => {
+                    let name = version.trim_end_matches(CANARY_SUFFIX);
+                    let kept = self.kept_by_nodes.as_ref().is_none_or(|kept| {
+                        kept.canaries.contains(name) || self.canaries.contains(name)
+                    });
+                    (!kept).then(|| {
+                        (
+                            GarbageKind::StaleVersion,
+                            format!("Canary of '{name}', which no node has"),
+                        )
+                    })
+                }
+                None if self.shared_storage && !version.contains(ROUTE_SUFFIX) => {
+                    let owned = self.functions.contains(version)
+                        || self.listed.contains(version)
+                        || version.contains(CANARY_SUFFIX);
+                    (!owned).then(|| {
+                        (
+                            GarbageKind::OrphanedArtifact,
+                            "No function or project owns it".to_string(),
+                        )
+                    })
+                }
+                None => self.unused_version(version),
+            };
+            if let Some((kind, detail)) = found {
+                garbage.push((kind, key.clone(), detail));
+            }
+        }
+
+        for path in &self.cached {
+            let (dir, file) = match path.rsplit_once('/') {
+                Some((dir, file)) => (Some(dir), file),
+                None => (None, path.as_str()),
+            };
+            let detail = if file.starts_with('.') && file.ends_with(".tmp") {
+                Some("Left behind by an interrupted write".to_string())
+            } else if let Some(version) = file.strip_suffix(".cwasm") {
+                match dir {
+                    Some(_) => (!self.trashed.contains(version))
+                        .then(|| format!("Trashed '{version}' is no longer in the trash")),
+                    None => self
+                        .unused_version(version)
+                        .map(|_| format!("'{version}' isn't served")),
+                }
+            } else {
+                None
+            };
+            if let Some(detail) = detail {
+                garbage.push((GarbageKind::StaleCache, path.clone(), detail));
+            }
+        }
+
+        for name in &self.records {
+            if !self.functions.contains(name) && !self.trashed.contains(name) {
+                garbage.push((
+                    GarbageKind::StaleMetadata,
+                    name.clone(),
+                    "Per-function records kept for a name that isn't a function".to_string(),
+                ));
+            }
+        }
+        garbage
+    }
+}
+
+/// Names of the files directly in `dir`, prefixed with `prefix/`
+fn files(dir: &Path, prefix: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .map(|file| match prefix {
+            "" => file,
+            prefix => format!("{prefix}/{file}"),
+        })
+        .collect()
+}
+
+impl GarbageCollector {
+    pub fn new(db: &sled::Db, grace: Duration) -> Result<Self> {
+        Ok(Self {
+            candidates: db.open_tree(GC_TREE)?,
+            grace,
+            running: Mutex::new(()),
+        })
+    }
+
+    /// Collect garbage, or with `dry_run` only report it
+    pub async fn collect(&self, dry_run: bool) -> Result<GarbageReport> {
+        let _running = self.running.lock().await;
+        let server = SERVER
+            .get()
+            .ok_or_else(|| anyhow!("Server not initialised"))?;
+        let now = Utc::now().timestamp();
+
+        let mut garbage = Vec::new();
+        let mut found = HashSet::new();
+        for (kind, subject, detail) in Inventory::gather(server).await?.garbage() {
+            let key = format!("{kind}/{subject}");
+            let first_found = match self.candidates.get(&key)? {
+                Some(value) => i64::from_be_bytes(value.as_ref().try_into()?),
+                None => {
+                    if !dry_run {
+                        self.candidates.insert(key.as_bytes(), &now.to_be_bytes())?;
+                    }
+                    now
+                }
+            };
+            found.insert(key);
+            garbage.push(Garbage {
+                kind,
+                bytes: size(server, kind, &subject).await,
+                subject,
+                detail,
+                due: now - first_found >= self.grace.as_secs() as i64,
+                removed: false,
+            });
+        }
+
+        if !dry_run {
+            // What's no longer garbage starts its grace period over if it becomes so
+            for key in self.candidates.iter().keys() {
+                let key = key?;
+                if !found.contains(String::from_utf8_lossy(&key).as_ref()) {
+                    self.candidates.remove(key)?;
+                }
+            }
+            for item in garbage.iter_mut().filter(|item| item.due) {
+                match remove(server, item.kind, &item.subject).await {
+                    Ok(()) => {
+                        item.removed = true;
+                        self.candidates
+                            .remove(format!("{}/{}", item.kind, item.subject))?;
+                    }
+                    Err(e) => error!("Failed to remove {} '{}': {}", item.kind, item.subject, e),
+                }
+            }
+        }
+
+        garbage.extend(expired_trash(server, dry_run).await);
+        garbage.extend(expired_logs(dry_run)?);
+
+        let report = GarbageReport {
+            collected_at: Utc::now().to_rfc3339(),
+            dry_run,
+            garbage,
+        };
+        if !dry_run {
+            let removed = report.garbage.iter().filter(|item| item.removed).count();
+            if removed > 0 {
+                info!(
+                    "Garbage collection removed {} items, freeing {} bytes",
+                    removed,
+                    report.freed_bytes()
+                );
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Bytes `subject` takes up, where known
+async fn size(server: &FaastaServer, kind: GarbageKind, subject: &str) -> u64 {
+    match kind {
+        GarbageKind::OrphanedArtifact | GarbageKind::StaleVersion => {
+            server.storage.size(subject).await.ok().flatten()
+        }
+        GarbageKind::StaleCache => fs::metadata(server.functions_dir.join(subject))
+            .ok()
+            .map(|file| file.len()),
+        _ => None,
+    }
+    .unwrap_or(0)
+}
+
+async fn remove(server: &FaastaServer, kind: GarbageKind, subject: &str) -> Result<()> {
+    match kind {
+        GarbageKind::OrphanedArtifact | GarbageKind::StaleVersion => {
+            server.storage.delete(subject).await?;
+        }
+        GarbageKind::StaleCache => {
+            let path = server.functions_dir.join(subject);
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+            if let Some(version) = subject.strip_suffix(".cwasm") {
+                server.remove_from_cache(version);
+            }
+        }
+        GarbageKind::StaleMetadata => {
+            function_data::remove(server.metadata.as_ref(), &server.metadata_db, subject)?;
+            server.artifacts.forget_function(subject);
+        }
+        GarbageKind::ExpiredTrash | GarbageKind::ExpiredLogs => {}
+    }
+    Ok(())
+}
+
+/// Functions whose time in the trash ran out, purged unless `dry_run`
+async fn expired_trash(server: &FaastaServer, dry_run: bool) -> Vec<Garbage> {
+    let Some(trash) = TRASH.get() else {
+        return Vec::new();
+    };
+    let mut garbage = Vec::new();
+    for trashed in trash.expired() {
+        let name = trashed.info.name;
+        garbage.push(Garbage {
+            kind: GarbageKind::ExpiredTrash,
+            bytes: server
+                .storage
+                .size(&trashed_key(&name))
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or(0),
+            subject: name,
+            detail: format!(
+                "In the trash for more than {}h",
+                trash.retention().as_secs() / 3600
+            ),
+            due: true,
+            removed: false,
+        });
+    }
+    if !dry_run && !garbage.is_empty() {
+        let purged: HashSet<String> = trash::purge()
+            .await
+            .into_iter()
+            .map(|trashed| trashed.info.name)
+            .collect();
+        for item in &mut garbage {
+            item.removed = purged.contains(&item.subject);
+        }
+    }
+    garbage
+}
+
+/// Log lines past their retention, purged unless `dry_run`
+fn expired_logs(dry_run: bool) -> Result<Option<Garbage>> {
+    let Some(logs) = LOGS.get() else {
+        return Ok(None);
+    };
+    // Purging counts what it removes, so it's one pass over the lines either way
+    let (lines, bytes) = if dry_run {
+        logs.expired()?
+    } else {
+        logs.purge_expired()?
+    };
+    if lines == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Garbage {
+        kind: GarbageKind::ExpiredLogs,
+        subject: "function logs".to_string(),
+        bytes,
+        detail: format!("{lines} lines past their retention"),
+        due: true,
+        removed: !dry_run,
+    }))
+}
+
+/// Spawn a task collecting garbage every `interval_hours`
+pub fn spawn_periodic_collection(interval_hours: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_hours * 3600));
+        loop {
+            ticker.tick().await;
+            let Some(gc) = GC.get() else {
+                continue;
+            };
+            if let Err(e) = gc.collect(false).await {
+                error!("Garbage collection failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_garbage_is_what_nothing_uses() {
+        let mut inventory = Inventory {
+            functions: names(&["api", "web"]),
+            listed: names(&["api", "web", "half-published"]),
+            trashed: names(&["old"]),
+            canaries: names(&["api"]),
+            routes: names(&["web@route-0123456789abcdef"]),
+            artifacts: [
+                "api.wasm",
+                "api@canary.wasm",
+                "web.wasm",
+                "web@canary.wasm",
+                "web@route-0123456789abcdef.wasm",
+                "web@route-fedcba9876543210.wasm",
+                "half-published.wasm",
+                "stray.wasm",
+                ".trash/old.wasm",
+                ".trash/purged.wasm",
+                "hot-functions.json",
+            ]
+            .map(String::from)
+            .to_vec(),
+            cached: [
+                "api.cwasm",
+                "gone.cwasm",
+                ".api.cwasm.0badf00d.tmp",
+                ".trash/old.cwasm",
+                ".trash/purged.cwasm",
+            ]
+            .map(String::from)
+            .to_vec(),
+            records: names(&["api", "old", "deleted"]),
+            shared_storage: false,
+            kept_by_nodes: None,
+        };
+        let found = |inventory: &Inventory| -> Vec<(GarbageKind, String)> {
+            inventory
+                .garbage()
+                .into_iter()
+                .map(|(kind, subject, _)| (kind, subject))
+                .collect()
+        };
+        let garbage = |kind, subject: &str| (kind, subject.to_string());
+        assert_eq!(
+            found(&inventory),
+            [
+                garbage(GarbageKind::StaleVersion, "web@canary.wasm"),
+                garbage(GarbageKind::StaleVersion, "web@route-fedcba9876543210.wasm"),
+                garbage(GarbageKind::OrphanedArtifact, "stray.wasm"),
+                garbage(GarbageKind::OrphanedArtifact, ".trash/purged.wasm"),
+                garbage(GarbageKind::StaleCache, "gone.cwasm"),
+                garbage(GarbageKind::StaleCache, ".api.cwasm.0badf00d.tmp"),
+                garbage(GarbageKind::StaleCache, ".trash/purged.cwasm"),
+                garbage(GarbageKind::StaleMetadata, "deleted"),
+            ]
+        );
+
+        // Other nodes of a cluster keep canaries and trash of their own, so they're
+        // left alone until every node reported which, but route handlers are shared
+        inventory.shared_storage = true;
+        let garbage_kinds: Vec<(GarbageKind, String)> = found(&inventory)
+            .into_iter()
+            .filter(|(kind, _)| *kind != GarbageKind::StaleCache)
+            .collect();
+        assert_eq!(
+            garbage_kinds,
+            [
+                garbage(GarbageKind::StaleVersion, "web@route-fedcba9876543210.wasm"),
+                garbage(GarbageKind::OrphanedArtifact, "stray.wasm"),
+                garbage(GarbageKind::StaleMetadata, "deleted"),
+            ]
+        );
+
+        // Another node has the canary of web
+        inventory.kept_by_nodes = Some(NodeInventory {
+            canaries: names(&["web"]),
+            trashed: BTreeSet::new(),
+        });
+        let garbage_kinds: Vec<(GarbageKind, String)> = found(&inventory)
+            .into_iter()
+            .filter(|(kind, _)| *kind != GarbageKind::StaleCache)
+            .collect();
+        assert_eq!(
+            garbage_kinds,
+            [
+                garbage(GarbageKind::StaleVersion, "web@route-fedcba9876543210.wasm"),
+                garbage(GarbageKind::OrphanedArtifact, "stray.wasm"),
+                garbage(GarbageKind::OrphanedArtifact, ".trash/purged.wasm"),
+                garbage(GarbageKind::StaleMetadata, "deleted"),
+            ]
+        );
+    }
+}
@@ -1,177 +1,484 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bincode::{Decode, Encode};
-use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::time::Duration;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::api_keys::{parse_api_key, ApiKeyStore};
+use crate::auth_provider::AuthProvider;
+use crate::metadata_store::{Change, MetadataStore};
+use crate::teams::TeamStore;
+use faasta_interface::TeamRole;
 
 const USER_DB_TREE: &str = "user_data";
-const MAX_PROJECTS_PER_USER: usize = 10;
+/// Owner of each project listed in a user record, keyed by project name
+const PROJECT_OWNERS_TREE: &str = "project_owners";
+/// Per-user overrides of the project limit, keyed by username
+const USER_LIMITS_TREE: &str = "user_limits";
+pub const MAX_PROJECTS_PER_USER: usize = 10;
 
+/// Tracks which user owns which projects and validates tokens through an [`AuthProvider`]
 pub struct GitHubAuth {
-    user_projects: DashMap<String, UserData>,
-    db: sled::Db,
+    /// Users and their limits, read through so nodes sharing the store agree
+    store: Arc<dyn MetadataStore>,
+    provider: Arc<dyn AuthProvider>,
+    /// Server-issued API keys, validated without calling the provider
+    pub api_keys: ApiKeyStore,
+    /// Teams that can own functions
+    pub teams: TeamStore,
 }
 #[derive(Serialize, Deserialize, Clone, Debug, Encode, Decode)]
 pub struct UserData {
     pub github_username: String,
     pub projects: Vec<String>,
+    /// Teams the user is an explicit member of
+    pub teams: Vec<String>,
 }
 
-impl GitHubAuth {
-    pub async fn new(db: sled::Db) -> Result<Self> {
-        // Load existing user data
-        let user_projects = DashMap::new();
-
-        // Create or get the user data tree
-        let user_tree = db.open_tree(USER_DB_TREE)?;
-
-        // Iterate through all items in the tree
-        for item in user_tree.iter().flatten() {
-            if let Ok(username) = std::str::from_utf8(&item.0) {
-                // Try to decode using bincode
-                if let Ok((user_data, _)) =
-                    bincode::decode_from_slice::<UserData, _>(&item.1, bincode::config::standard())
-                {
-                    user_projects.insert(username.to_string(), user_data);
-                }
-            }
+/// `UserData` as stored before team membership was tracked
+#[derive(Decode)]
+struct LegacyUserData {
+    github_username: String,
+    projects: Vec<String>,
+}
+
+impl UserData {
+    fn new(username: &str) -> Self {
+        Self {
+            github_username: username.to_string(),
+            projects: Vec::new(),
+            teams: Vec::new(),
         }
+    }
 
-        Ok(Self { user_projects, db })
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let config = bincode::config::standard();
+        if let Ok((user_data, _)) = bincode::decode_from_slice::<UserData, _>(bytes, config) {
+            return Some(user_data);
+        }
+        bincode::decode_from_slice::<LegacyUserData, _>(bytes, config)
+            .ok()
+            .map(|(legacy, _)| Self {
+                github_username: legacy.github_username,
+                projects: legacy.projects,
+                teams: Vec::new(),
+            })
     }
+}
 
-    /// Authenticate and extract username from GitHub token in a single API call
-    /// Returns (username, is_valid) tuple
-    pub async fn authenticate_github(&self, token: &str) -> Result<(String, bool)> {
-        // Check if the token is in the format "username:token"
-        let (provided_username, token_value) =
-            if let Some((username, token_part)) = token.split_once(':') {
-                (
-                    Some(username.to_string()),
-                    token_part
-                        .strip_prefix("Bearer ")
-                        .unwrap_or(token_part)
-                        .trim(),
-                )
-            } else {
-                (None, token.strip_prefix("Bearer ").unwrap_or(token).trim())
-            };
+impl GitHubAuth {
+    pub async fn new(
+        store: Arc<dyn MetadataStore>,
+        db: &sled::Db,
+        provider: Arc<dyn AuthProvider>,
+    ) -> Result<Self> {
+        let api_keys = ApiKeyStore::new(store.clone(), db)?;
+        let teams = TeamStore::new(store.clone());
 
-        // Create client with timeout to verify with GitHub API
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(3))
-            .build()?;
-
-        // Make a single API call to GitHub
-        let response = match client
-            .get("https://api.github.com/user")
-            .header("User-Agent", "faasta-server")
-            .header("Authorization", format!("Bearer {token_value}"))
-            .send()
-            .await
-        {
-            Ok(resp) => resp,
-            Err(e) => {
-                tracing::error!("GitHub API request failed: {}", e);
-                return Ok(("".to_string(), false));
-            }
+        let auth = Self {
+            store,
+            provider,
+            api_keys,
+            teams,
         };
+        auth.index_project_owners()?;
+        Ok(auth)
+    }
 
-        if !response.status().is_success() {
-            tracing::warn!("GitHub API returned error status: {}", response.status());
-            return Ok(("".to_string(), false));
+    /// Fill the index of project owners from the user records, if it's empty, as
+    /// on the first start of a server that kept none
+    fn index_project_owners(&self) -> Result<()> {
+        if self.store.count(PROJECT_OWNERS_TREE)? > 0 {
+            return Ok(());
         }
+        let changes: Vec<Change> = self
+            .users()?
+            .into_iter()
+            .flat_map(|(username, user_data)| {
+                user_data
+                    .projects
+                    .into_iter()
+                    .map(move |project| owner_change(&project, &username))
+            })
+            .collect();
+        self.store.apply(&changes)
+    }
+
+    /// A user's record, if they have one
+    fn user(&self, username: &str) -> Result<Option<UserData>> {
+        self.store
+            .get(USER_DB_TREE, username)?
+            .map(|bytes| decode_user(username, &bytes))
+            .transpose()
+    }
+
+    /// Change `username`'s record with `change`, starting from an empty one if
+    /// they have none, without losing changes made to it meanwhile. Returns the
+    /// record as saved, `None` if `create` is false and there was none.
+    fn update_user(
+        &self,
+        username: &str,
+        create: bool,
+        change: impl Fn(&mut UserData) + Sync,
+    ) -> Result<Option<UserData>> {
+        let updated = self.store.update(USER_DB_TREE, username, &mut |current| {
+            let mut user_data = match current {
+                Some(bytes) => decode_user(username, bytes)?,
+                None if create => UserData::new(username),
+                None => return Ok(None),
+            };
+            change(&mut user_data);
+            Ok(Some(bincode::encode_to_vec(
+                &user_data,
+                bincode::config::standard(),
+            )?))
+        })?;
+        updated
+            .map(|bytes| decode_user(username, &bytes))
+            .transpose()
+    }
+
+    /// Every user record, with its key
+    fn users(&self) -> Result<Vec<(String, UserData)>> {
+        self.store
+            .scan(USER_DB_TREE)?
+            .into_iter()
+            .map(|(username, bytes)| {
+                let user_data = decode_user(&username, &bytes)?;
+                Ok((username, user_data))
+            })
+            .collect()
+    }
 
-        // Parse response and extract username
-        let github_user: Value = match response.json().await {
-            Ok(json) => json,
+    /// Authenticate and extract the username from a token using the configured provider
+    /// Returns (username, is_valid) tuple
+    pub async fn authenticate(&self, token: &str) -> Result<(String, bool)> {
+        self.provider.authenticate(token).await
+    }
+
+    /// Name of the configured auth provider
+    pub fn provider_name(&self) -> &'static str {
+        self.provider.name()
+    }
+
+    /// Check the configured auth provider can be reached
+    pub async fn probe_provider(&self) -> Result<()> {
+        self.provider.probe().await
+    }
+
+    /// Maximum number of projects a user may own
+    /// (their override if one is set, otherwise MAX_PROJECTS_PER_USER)
+    pub fn project_limit(&self, username: &str) -> usize {
+        let limit = match self.store.get(USER_LIMITS_TREE, username) {
+            Ok(limit) => limit,
             Err(e) => {
-                tracing::error!("Failed to parse GitHub response: {}", e);
-                return Ok(("".to_string(), false));
+                warn!("Failed to read the project limit of '{}': {}", username, e);
+                None
             }
         };
+        limit
+            .and_then(|value| {
+                bincode::decode_from_slice::<u64, _>(&value, bincode::config::standard()).ok()
+            })
+            .map(|(limit, _)| limit as usize)
+            .unwrap_or(MAX_PROJECTS_PER_USER)
+    }
 
-        let api_username = github_user["login"].as_str().unwrap_or("");
-
-        // If username was provided in token, verify it matches
-        if let Some(provided) = provided_username {
-            if provided != api_username {
-                tracing::warn!(
-                    "Username mismatch: provided '{}', GitHub returned '{}'",
-                    provided,
-                    api_username
-                );
-                return Ok((api_username.to_string(), false));
+    /// Set or clear (`None`) a user's project limit override
+    pub fn set_project_limit(&self, username: &str, limit: Option<usize>) -> Result<()> {
+        match limit {
+            Some(limit) => {
+                let encoded = bincode::encode_to_vec(limit as u64, bincode::config::standard())?;
+                self.store.insert(USER_LIMITS_TREE, username, encoded)
             }
+            None => self.store.remove(USER_LIMITS_TREE, username),
         }
-
-        Ok((api_username.to_string(), true))
     }
 
-    /// Check if a user can upload more projects (limit is MAX_PROJECTS_PER_USER)
-    pub fn can_upload_project(&self, username: &str, project_name: &str) -> bool {
-        if let Some(user_data) = self.user_projects.get(username) {
-            // Check if they're already at the limit
-            if user_data.projects.len() >= MAX_PROJECTS_PER_USER
-                && !user_data.projects.contains(&project_name.to_string())
-            {
-                return false;
-            }
+    /// Give `username` a record on their first login, so they're known to the
+    /// platform before they publish anything
+    pub fn register_user(&self, username: &str) -> Result<()> {
+        if self.user(username)?.is_none() {
+            self.update_user(username, true, |_| {})?;
         }
-        true
+        Ok(())
+    }
+
+    /// Whether `username` has logged in or owned a project
+    pub fn user_exists(&self, username: &str) -> Result<bool> {
+        Ok(self.user(username)?.is_some())
     }
 
     /// Add a project to a user's list
     pub async fn add_project(&self, username: &str, project_name: &str) -> Result<()> {
-        // Get or create user data
-        let mut user_data = if let Some(data) = self.user_projects.get(username) {
-            data.clone()
-        } else {
-            UserData {
-                github_username: username.to_string(),
-                projects: Vec::new(),
+        self.update_user(username, true, |user_data| {
+            if !user_data.projects.iter().any(|p| p == project_name) {
+                user_data.projects.push(project_name.to_string());
             }
+        })?;
+        self.store.apply(&[owner_change(project_name, username)])
+    }
+
+    /// Remove a project from a user's list
+    pub async fn remove_project(&self, username: &str, project_name: &str) -> Result<()> {
+        self.update_user(username, false, |user_data| {
+            user_data.projects.retain(|p| p != project_name);
+        })?;
+        // Unless the project has been listed for someone else since
+        self.store
+            .update(PROJECT_OWNERS_TREE, project_name, &mut |owner| {
+                Ok(owner
+                    .filter(|owner| *owner != username.as_bytes())
+                    .map(<[u8]>::to_vec))
+            })?;
+        Ok(())
+    }
+
+    /// Changes renaming one of `owner`'s projects, keeping its place in their
+    /// list, to apply in one batch with the function's metadata. The owner must
+    /// have a record.
+    pub fn rename_project(
+        &self,
+        owner: &str,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<Vec<Change>> {
+        let Some(mut user_data) = self.user(owner)? else {
+            anyhow::bail!("User '{owner}' doesn't exist");
         };
+        for project in user_data.projects.iter_mut().filter(|p| *p == old_name) {
+            *project = new_name.to_string();
+        }
+        Ok(vec![
+            Change::Insert {
+                collection: USER_DB_TREE,
+                key: owner.to_string(),
+                value: bincode::encode_to_vec(&user_data, bincode::config::standard())?,
+            },
+            Change::Remove {
+                collection: PROJECT_OWNERS_TREE,
+                key: old_name.to_string(),
+            },
+            owner_change(new_name, owner),
+        ])
+    }
 
-        // Add or update the project
-        if !user_data.projects.contains(&project_name.to_string()) {
-            user_data.projects.push(project_name.to_string());
+    /// Changes moving `project_name` from `from`'s list of projects to `to`'s, to
+    /// apply in one batch with the change of the function's owner. Both users
+    /// must have a record.
+    pub fn transfer_project(
+        &self,
+        from: &str,
+        to: &str,
+        project_name: &str,
+    ) -> Result<Vec<Change>> {
+        let Some(mut from_data) = self.user(from)? else {
+            anyhow::bail!("User '{from}' doesn't exist");
+        };
+        from_data.projects.retain(|p| p != project_name);
+        let Some(mut to_data) = self.user(to)? else {
+            anyhow::bail!("User '{to}' doesn't exist");
+        };
+        if !to_data.projects.iter().any(|p| p == project_name) {
+            to_data.projects.push(project_name.to_string());
         }
+        let config = bincode::config::standard();
+        Ok(vec![
+            Change::Insert {
+                collection: USER_DB_TREE,
+                key: from.to_string(),
+                value: bincode::encode_to_vec(&from_data, config)?,
+            },
+            Change::Insert {
+                collection: USER_DB_TREE,
+                key: to.to_string(),
+                value: bincode::encode_to_vec(&to_data, config)?,
+            },
+            owner_change(project_name, to),
+        ])
+    }
 
-        // Update the map
-        self.user_projects
-            .insert(username.to_string(), user_data.clone());
+    /// Get the list of projects owned by a user
+    pub fn get_user_projects(&self, username: &str) -> Result<Vec<String>> {
+        Ok(self
+            .user(username)?
+            .map(|user_data| user_data.projects)
+            .unwrap_or_default())
+    }
+
+    /// Role of `username` in `team`: their explicit membership, or for teams linked to a
+    /// GitHub organization, their role in the organization (provider tokens only)
+    pub async fn team_role(&self, team: &str, username: &str, token: &str) -> Option<TeamRole> {
+        let team = match self.teams.get(team) {
+            Ok(team) => team?,
+            Err(e) => {
+                warn!("Failed to read team '{}': {}", team, e);
+                return None;
+            }
+        };
+        if let Some(role) = team.member_role(username) {
+            return Some(role);
+        }
 
-        // Save to database
-        let user_tree = self.db.open_tree(USER_DB_TREE)?;
-        let encoded = bincode::encode_to_vec(&user_data, bincode::config::standard())?;
-        user_tree.insert(username.as_bytes(), encoded)?;
+        let org = team.github_org.as_deref()?;
+        if parse_api_key(token).is_some() {
+            return None;
+        }
+        self.provider
+            .org_role(token, org)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to check {} membership of {}: {}", org, username, e);
+                None
+            })
+    }
 
+    /// Record whether `username` is an explicit member of `team`
+    pub fn set_team_membership(&self, username: &str, team: &str, member: bool) -> Result<()> {
+        self.update_user(username, true, |user_data| {
+            user_data.teams.retain(|t| t != team);
+            if member {
+                user_data.teams.push(team.to_string());
+            }
+        })?;
         Ok(())
     }
 
-    /// Remove a project from a user's list
-    pub async fn remove_project(&self, username: &str, project_name: &str) -> Result<()> {
-        // Get user data
-        if let Some(mut user_data) = self.user_projects.get_mut(username) {
-            // Remove the project
-            user_data.projects.retain(|p| p != project_name);
+    /// Account or team owner of `project_name`
+    pub fn project_owner(&self, project_name: &str) -> Result<Option<String>> {
+        Ok(self
+            .store
+            .get(PROJECT_OWNERS_TREE, project_name)?
+            .map(|owner| String::from_utf8_lossy(&owner).into_owned()))
+    }
 
-            // Save to database
-            let user_tree = self.db.open_tree(USER_DB_TREE)?;
-            let user_data_clone = user_data.clone();
-            let encoded = bincode::encode_to_vec(&user_data_clone, bincode::config::standard())?;
-            user_tree.insert(username.as_bytes(), encoded)?;
-        }
+    /// Teams `username` is an explicit member of
+    pub fn get_user_teams(&self, username: &str) -> Result<Vec<String>> {
+        Ok(self
+            .user(username)?
+            .map(|user_data| user_data.teams)
+            .unwrap_or_default())
+    }
 
-        Ok(())
+    /// Snapshot of every known user and their projects
+    pub fn all_users(&self) -> Result<Vec<UserData>> {
+        Ok(self
+            .users()?
+            .into_iter()
+            .map(|(_, user_data)| user_data)
+            .collect())
     }
+}
 
-    /// Get the list of projects owned by a user
-    pub fn get_user_projects(&self, username: &str) -> Option<Vec<String>> {
-        self.user_projects
-            .get(username)
-            .map(|user_data| user_data.projects.clone())
+fn decode_user(username: &str, bytes: &[u8]) -> Result<UserData> {
+    UserData::decode(bytes).ok_or_else(|| anyhow!("The record of user '{username}' is unreadable"))
+}
+
+/// Change recording `owner` as the owner of `project_name`
+fn owner_change(project_name: &str, owner: &str) -> Change {
+    Change::Insert {
+        collection: PROJECT_OWNERS_TREE,
+        key: project_name.to_string(),
+        value: owner.as_bytes().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth_provider::{build_provider, AuthProviderConfig, AuthProviderKind};
+    use crate::metadata_store::SledStore;
+
+    async fn auth(db: &sled::Db) -> GitHubAuth {
+        let provider = build_provider(&AuthProviderConfig {
+            kind: AuthProviderKind::Github,
+            gitlab_url: String::new(),
+            oidc_issuer: None,
+            oidc_username_claim: String::new(),
+        })
+        .unwrap();
+        GitHubAuth::new(Arc::new(SledStore::new(db)), db, provider)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_projects_keep_their_owner_indexed() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let auth = auth(&db).await;
+        auth.add_project("alice", "api").await.unwrap();
+        auth.set_team_membership("alice", "web", true).unwrap();
+        assert_eq!(auth.get_user_projects("alice").unwrap(), ["api"]);
+        assert_eq!(auth.get_user_teams("alice").unwrap(), ["web"]);
+        assert_eq!(auth.project_owner("api").unwrap().as_deref(), Some("alice"));
+
+        // Only to someone the platform knows
+        assert!(auth.transfer_project("alice", "bob", "api").is_err());
+        assert!(!auth.user_exists("bob").unwrap());
+        auth.register_user("bob").unwrap();
+        assert!(auth.user_exists("bob").unwrap());
+        auth.store
+            .apply(&auth.transfer_project("alice", "bob", "api").unwrap())
+            .unwrap();
+        assert_eq!(auth.project_owner("api").unwrap().as_deref(), Some("bob"));
+        auth.store
+            .apply(&auth.rename_project("bob", "api", "edge").unwrap())
+            .unwrap();
+        assert_eq!(auth.project_owner("api").unwrap(), None);
+        assert_eq!(auth.project_owner("edge").unwrap().as_deref(), Some("bob"));
+        assert!(auth.rename_project("dave", "edge", "api").is_err());
+
+        // Releasing a project someone else now owns leaves their entry alone
+        auth.remove_project("alice", "edge").await.unwrap();
+        assert_eq!(auth.project_owner("edge").unwrap().as_deref(), Some("bob"));
+        auth.remove_project("bob", "edge").await.unwrap();
+        assert_eq!(auth.project_owner("edge").unwrap(), None);
+        assert!(auth.get_user_projects("bob").unwrap().is_empty());
+
+        // An index missing from an older database is built on start
+        db.drop_tree(PROJECT_OWNERS_TREE).unwrap();
+        auth.add_project("carol", "blog").await.unwrap();
+        db.drop_tree(PROJECT_OWNERS_TREE).unwrap();
+        let auth = self::auth(&db).await;
+        assert_eq!(
+            auth.project_owner("blog").unwrap().as_deref(),
+            Some("carol")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_user_records_are_not_overwritten() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let auth = auth(&db).await;
+        auth.store
+            .insert(USER_DB_TREE, "alice", b"garbage".to_vec())
+            .unwrap();
+        assert!(auth.add_project("alice", "api").await.is_err());
+        assert!(auth.set_team_membership("alice", "web", true).is_err());
+        assert!(auth.all_users().is_err());
+        assert_eq!(
+            auth.store.get(USER_DB_TREE, "alice").unwrap().unwrap(),
+            b"garbage"
+        );
+    }
+
+    #[derive(Encode)]
+    struct OldUserData {
+        github_username: String,
+        projects: Vec<String>,
+    }
+
+    #[test]
+    fn test_decode_user_data_without_teams() {
+        let old = OldUserData {
+            github_username: "octocat".to_string(),
+            projects: vec!["hello".to_string()],
+        };
+        let bytes = bincode::encode_to_vec(&old, bincode::config::standard()).unwrap();
+
+        let user_data = UserData::decode(&bytes).unwrap();
+        assert_eq!(user_data.github_username, "octocat");
+        assert_eq!(user_data.projects, vec!["hello".to_string()]);
+        assert!(user_data.teams.is_empty());
     }
 }
@@ -0,0 +1,215 @@
+//! Health and readiness endpoints for orchestrators.
+//!
+//! Served over plain HTTP on `--admin-listen-addr`, apart from function traffic:
+//!
+//! - `GET /healthz` answers `200` as long as the process serves requests at all,
+//!   for liveness probes that restart a hung server
+//! - `GET /readyz` answers `200` once the database is open, a valid TLS certificate
+//!   loaded, the HTTPS listener bound and the hottest functions warmed, and `503`
+//!   before that and while the server drains, for load balancers and readiness
+//!   probes that should only send traffic to a server able to take it
+//!
+//! Both answer JSON; `/readyz` names the failing checks. It also reports whether the
+//! auth provider is reachable, as an advisory that doesn't make the server unready:
+//! all nodes share the provider, so its outage would take every one of them out of
+//! the load balancer at once. The provider is probed in the background at most
+//! every [`AUTH_PROBE_TTL`], so frequent probes neither hammer nor wait for it.
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Map, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tracing::{error, info};
+
+use crate::hot_functions;
+use crate::rpc_service::FUNCTIONS_DB_TREE;
+use crate::shutdown::SHUTDOWN;
+use crate::tls::CERTIFICATES;
+use crate::wasi_server::SERVER;
+
+/// How long a probe of the auth provider is trusted
+const AUTH_PROBE_TTL: Duration = Duration::from_secs(30);
+/// Key read to check the database answers
+const PROBE_KEY: &str = "__readyz";
+
+/// Whether the HTTPS listener is bound
+static LISTENING: AtomicBool = AtomicBool::new(false);
+/// The last probe of the auth provider, and when it was made
+static AUTH_PROBE: Mutex<Option<(Instant, Result<(), String>)>> = Mutex::new(None);
+/// Whether a probe of the auth provider is under way
+static PROBING: AtomicBool = AtomicBool::new(false);
+
+/// Record that the HTTPS listener is bound
+pub fn mark_listening() {
+    LISTENING.store(true, Ordering::SeqCst);
+}
+
+/// Serve the endpoints on `listener` for the life of the process, draining included
+pub async fn run_admin_server(listener: TcpListener) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("Health endpoints listening on http://{}", addr);
+    }
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+    if let Err(e) = axum::serve(listener, app).await {
+        error!("Health endpoints failed: {}", e);
+    }
+}
+
+async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn readyz() -> Response {
+    let checks = [
+        ("database", database()),
+        ("tls", tls()),
+        ("listener", listener()),
+        ("warming", warming()),
+        ("draining", draining()),
+    ];
+    let advisories = [("auth_provider", auth_provider())];
+    let (status, body) = report(&checks, &advisories);
+    (status, Json(body)).into_response()
+}
+
+/// The status and body answering a readiness probe with `checks`, all of which
+/// must pass, and `advisories`, which are only reported
+fn report(
+    checks: &[(&str, Result<(), String>)],
+    advisories: &[(&str, Result<(), String>)],
+) -> (StatusCode, Value) {
+    let ready = checks.iter().all(|(_, result)| result.is_ok());
+    let outcomes = |results: &[(&str, Result<(), String>)]| -> Map<String, Value> {
+        results
+            .iter()
+            .map(|(name, result)| {
+                let outcome = match result {
+                    Ok(()) => "ok".to_string(),
+                    Err(e) => e.clone(),
+                };
+                (name.to_string(), Value::String(outcome))
+            })
+            .collect()
+    };
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = json!({
+        "ready": ready,
+        "checks": outcomes(checks),
+        "advisories": outcomes(advisories),
+    });
+    (status, body)
+}
+
+fn database() -> Result<(), String> {
+    let server = SERVER.get().ok_or("not open yet")?;
+    server
+        .metadata_db
+        .contains_key(PROBE_KEY)
+        .map_err(|e| e.to_string())?;
+    server
+        .metadata
+        .contains(FUNCTIONS_DB_TREE, PROBE_KEY)
+        .map(|_| ())
+        .map_err(|e| format!("{} metadata store: {e}", server.metadata.name()))
+}
+
+fn tls() -> Result<(), String> {
+    CERTIFICATES
+        .get()
+        .ok_or("certificate not loaded yet")?
+        .check_validity()
+}
+
+fn listener() -> Result<(), String> {
+    if LISTENING.load(Ordering::SeqCst) {
+        Ok(())
+    } else {
+        Err("HTTPS listener not bound yet".to_string())
+    }
+}
+
+fn warming() -> Result<(), String> {
+    if hot_functions::is_warming() {
+        Err("still warming the hottest functions".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// The last probe of the auth provider, starting another in the background once
+/// it's stale
+fn auth_provider() -> Result<(), String> {
+    let server = SERVER.get().ok_or("not set up yet")?;
+    let last = AUTH_PROBE.lock().unwrap().clone();
+    let stale = last
+        .as_ref()
+        .is_none_or(|(at, _)| at.elapsed() >= AUTH_PROBE_TTL);
+    if stale && !PROBING.swap(true, Ordering::SeqCst) {
+        tokio::spawn(async move {
+            let result =
+                server.github_auth.probe_provider().await.map_err(|e| {
+                    format!("{} unreachable: {e}", server.github_auth.provider_name())
+                });
+            *AUTH_PROBE.lock().unwrap() = Some((Instant::now(), result));
+            PROBING.store(false, Ordering::SeqCst);
+        });
+    }
+    match last {
+        Some((_, result)) => result,
+        None => Err("not probed yet".to_string()),
+    }
+}
+
+fn draining() -> Result<(), String> {
+    if SHUTDOWN.is_draining() {
+        Err("shutting down".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness_needs_every_check() {
+        let provider_down = [(
+            "auth_provider",
+            Err("GitHub unreachable: timed out".to_string()),
+        )];
+        // The auth provider being down is reported without making the server unready
+        let (status, body) = report(&[("database", Ok(())), ("tls", Ok(()))], &provider_down);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+        assert_eq!(body["checks"]["tls"], "ok");
+        assert_eq!(
+            body["advisories"]["auth_provider"],
+            "GitHub unreachable: timed out"
+        );
+
+        let (status, body) = report(
+            &[
+                ("database", Ok(())),
+                ("tls", Err("certificate expired".to_string())),
+            ],
+            &[],
+        );
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["checks"]["database"], "ok");
+        assert_eq!(body["checks"]["tls"], "certificate expired");
+    }
+}
@@ -0,0 +1,287 @@
+//! Hottest functions, shared through artifact storage.
+//!
+//! Every node scores functions by their recent invocations, decaying older
+//! intervals, and publishes its hottest to artifact storage as
+//! `hot-functions.<node>.json`, or `hot-functions.json` outside a cluster. A node
+//! that starts, e.g. after a scale-out, adds up the lists of the nodes that
+//! published lately and hydrates, compiles and pre-instantiates the hottest
+//! functions a few at a time, so the traffic routed to it doesn't begin with a
+//! storm of cold starts. It listens meanwhile, but `/readyz` only reports it ready
+//! once they're warm or `--prefetch-timeout` passes.
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use dashmap::DashMap;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, error, info, warn};
+
+use crate::canary::CANARY_SUFFIX;
+use crate::cluster::CLUSTER;
+use crate::metrics::get_metrics;
+use crate::wasi_server::SERVER;
+
+/// Storage keys of the lists of hottest functions start with this
+const HOT_FUNCTIONS_PREFIX: &str = "hot-functions.";
+/// Weight an interval's score keeps in the next one
+const DECAY: f64 = 0.5;
+/// Seconds after which a node's list is taken as left behind by a node that's
+/// gone, and deleted
+const MAX_LIST_AGE_SECS: i64 = 24 * 3600;
+/// Functions warmed at once at startup
+const PREFETCH_CONCURRENCY: usize = 4;
+
+/// Whether the hottest functions are being warmed at startup
+static WARMING: AtomicBool = AtomicBool::new(false);
+
+/// A function and how hot it is
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HotFunction {
+    pub name: String,
+    /// Invocations per interval, recent intervals weighing most
+    pub score: f64,
+}
+
+/// The hottest functions of one node, as published
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HotList {
+    /// Unix time of the publish, in seconds
+    pub published_at: i64,
+    pub functions: Vec<HotFunction>,
+}
+
+/// Scores of the functions invoked on this node
+#[derive(Default)]
+pub struct HotScores {
+    scores: DashMap<String, f64>,
+    /// Invocations of each function seen so far
+    seen: DashMap<String, u64>,
+}
+
+impl HotScores {
+    /// Fold the invocations since the last interval, given as cumulative counts,
+    /// into the scores. Returns the `limit` hottest functions, hottest first.
+    pub fn update(&self, call_counts: &[(String, u64)], limit: usize) -> Vec<HotFunction> {
+        for (name, calls) in call_counts {
+            // Counts are cumulative since the function was published, so the
+            // first one only sets the baseline
+            let seen = self.seen.insert(name.clone(), *calls).unwrap_or(*calls);
+            let new_calls = calls.saturating_sub(seen);
+            let mut score = self.scores.entry(name.clone()).or_default();
+            *score = *score * DECAY + new_calls as f64;
+        }
+        self.scores.retain(|_, score| *score >= 0.5);
+
+        let mut hottest: Vec<HotFunction> = self
+            .scores
+            .iter()
+            .filter(|entry| !entry.key().ends_with(CANARY_SUFFIX))
+            .map(|entry| HotFunction {
+                name: entry.key().clone(),
+                score: *entry.value(),
+            })
+            .collect();
+        hottest.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hottest.truncate(limit);
+        hottest
+    }
+}
+
+/// Storage key of this node's list
+fn list_key() -> String {
+    match CLUSTER.get() {
+        Some(cluster) => format!("{HOT_FUNCTIONS_PREFIX}{}.json", cluster.node_id()),
+        None => format!("{HOT_FUNCTIONS_PREFIX}json"),
+    }
+}
+
+/// The `limit` hottest functions across `lists`, adding up each function's score
+/// over the nodes, ignoring lists published more than [`MAX_LIST_AGE_SECS`]
+/// before `now`
+pub fn merge(lists: &[HotList], now: i64, limit: usize) -> Vec<HotFunction> {
+    let mut scores: HashMap<&str, f64> = HashMap::new();
+    for list in lists {
+        if now - list.published_at > MAX_LIST_AGE_SECS {
+            continue;
+        }
+        for function in &list.functions {
+            *scores.entry(&function.name).or_default() += function.score;
+        }
+    }
+    let mut hottest: Vec<HotFunction> = scores
+        .into_iter()
+        .map(|(name, score)| HotFunction {
+            name: name.to_string(),
+            score,
+        })
+        .collect();
+    hottest.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.name.cmp(&b.name)));
+    hottest.truncate(limit);
+    hottest
+}
+
+/// Spawn a task publishing the `limit` hottest functions every `interval_secs`
+pub fn spawn_periodic_publish(interval_secs: u64, limit: usize) {
+    tokio::spawn(async move {
+        let scores = HotScores::default();
+        let mut ticker = interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            let call_counts: Vec<(String, u64)> = get_metrics()
+                .function_metrics
+                .into_iter()
+                .map(|metric| (metric.function_name, metric.call_count))
+                .collect();
+            let hottest = scores.update(&call_counts, limit);
+            if hottest.is_empty() {
+                continue;
+            }
+            if let Err(e) = publish(&hottest).await {
+                error!("Failed to publish the hottest functions: {}", e);
+            }
+        }
+    });
+}
+
+async fn publish(hottest: &[HotFunction]) -> Result<()> {
+    let server = SERVER
+        .get()
+        .ok_or_else(|| anyhow!("Server not initialised"))?;
+    let list = HotList {
+        published_at: Utc::now().timestamp(),
+        functions: hottest.to_vec(),
+    };
+    server
+        .storage
+        .put(&list_key(), &serde_json::to_vec(&list)?)
+        .await?;
+    debug!("Published the {} hottest functions", hottest.len());
+    Ok(())
+}
+
+/// Whether the hottest functions are still being warmed, keeping the node from
+/// reporting ready
+pub fn is_warming() -> bool {
+    WARMING.load(Ordering::SeqCst)
+}
+
+/// Spawn a task hydrating and pre-instantiating up to `limit` of the hottest
+/// functions published to storage, giving up after `timeout`
+pub fn spawn_prefetch(limit: usize, timeout: Duration) {
+    WARMING.store(true, Ordering::SeqCst);
+    tokio::spawn(async move {
+        match tokio::time::timeout(timeout, prefetch_hottest(limit)).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(warmed)) => info!("Warmed the {} hottest functions", warmed),
+            Ok(Err(e)) => error!("Failed to warm the hottest functions: {}", e),
+            Err(_) => warn!("Stopped warming the hottest functions after {:?}", timeout),
+        }
+        WARMING.store(false, Ordering::SeqCst);
+    });
+}
+
+async fn prefetch_hottest(limit: usize) -> Result<usize> {
+    let server = SERVER
+        .get()
+        .ok_or_else(|| anyhow!("Server not initialised"))?;
+    let now = Utc::now().timestamp();
+    let mut lists = Vec::new();
+    for key in server.storage.list().await? {
+        if !(key.starts_with(HOT_FUNCTIONS_PREFIX) && key.ends_with(".json")) {
+            continue;
+        }
+        let Some(data) = server.storage.get(&key).await? else {
+            continue;
+        };
+        match serde_json::from_slice::<HotList>(&data) {
+            Ok(list) if now - list.published_at > MAX_LIST_AGE_SECS => {
+                if let Err(e) = server.storage.delete(&key).await {
+                    debug!("Failed to delete the stale list {}: {}", key, e);
+                }
+            }
+            Ok(list) => lists.push(list),
+            Err(e) => debug!("Skipped the unreadable list {}: {}", key, e),
+        }
+    }
+
+    let warmed = futures::stream::iter(merge(&lists, now, limit))
+        .map(|function| async move {
+            // Functions deleted since the list was published are skipped
+            match server.prewarm(&function.name).await {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!("Skipped warming '{}': {}", function.name, e);
+                    false
+                }
+            }
+        })
+        .buffer_unordered(PREFETCH_CONCURRENCY)
+        .filter(|warmed| std::future::ready(*warmed))
+        .count()
+        .await;
+    Ok(warmed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_invocations_weigh_most() {
+        let scores = HotScores::default();
+        let counts = |api: u64, cron: u64| {
+            vec![
+                ("api".to_string(), api),
+                ("cron".to_string(), cron),
+                ("api@canary".to_string(), 1000),
+            ]
+        };
+
+        assert!(scores.update(&counts(5000, 0), 10).is_empty());
+        let hottest = scores.update(&counts(5010, 100), 10);
+        let names: Vec<_> = hottest.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["cron", "api"]);
+
+        // cron went quiet while api got busy
+        let hottest = scores.update(&counts(5200, 100), 1);
+        assert_eq!(
+            hottest,
+            [HotFunction {
+                name: "api".to_string(),
+                score: 195.0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_lists_of_nodes_add_up_unless_stale() {
+        let hot = |name: &str, score: f64| HotFunction {
+            name: name.to_string(),
+            score,
+        };
+        let now = 1_000_000;
+        let lists = [
+            HotList {
+                published_at: now - 60,
+                functions: vec![hot("api", 10.0), hot("cron", 8.0)],
+            },
+            HotList {
+                published_at: now - 120,
+                functions: vec![hot("cron", 5.0)],
+            },
+            HotList {
+                published_at: now - MAX_LIST_AGE_SECS - 1,
+                functions: vec![hot("gone", 100.0)],
+            },
+        ];
+        assert_eq!(
+            merge(&lists, now, 10),
+            [hot("cron", 13.0), hot("api", 10.0)]
+        );
+        assert_eq!(merge(&lists, now, 1), [hot("cron", 13.0)]);
+    }
+}
@@ -9,24 +9,134 @@ use bytes::Bytes;
 use http::Response;
 use http_body_util::{BodyExt, Full};
 use hyper::body::Incoming;
-use hyper::server::conn::http1;
+use hyper::header::{HeaderValue, HOST};
 use hyper::service::service_fn;
 use hyper::Request;
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use once_cell::sync::OnceCell;
 use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio_rustls::TlsAcceptor;
-use tracing::{error, info};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio_rustls::rustls::server::Acceptor;
+use tokio_rustls::{LazyConfigAcceptor, TlsAcceptor};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
+use crate::bot_signals::TlsHello;
+use crate::capacity::CAPACITY;
+use crate::server_config::Reloadable;
+use crate::shutdown::SHUTDOWN;
 use crate::wasi_server::text_response;
 use crate::wasi_server::SERVER;
 
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
+/// Global limits on reading requests, set at startup
+pub static REQUEST_LIMITS: OnceCell<Reloadable<RequestLimits>> = OnceCell::new();
+
+/// Smallest read buffer hyper accepts, and so the smallest head limit
+const MIN_HEAD_BYTES: usize = 8192;
+
+/// Limits on what a client may send before its request reaches a function. A
+/// client breaking them gets a `431 Request Header Fields Too Large` (or its HTTP/2
+/// stream reset when the head is too large), or its connection closed when it's too
+/// slow, so slow or oversized requests can't pin connection slots or memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestLimits {
+    /// Most header fields in a request
+    pub max_headers: usize,
+    /// Most bytes of a request's head, its request line and headers (at least 8 KiB)
+    pub max_head_bytes: usize,
+    /// Longest a client may take for its TLS handshake, and then for each request
+    /// head, or to answer a ping on HTTP/2
+    pub header_read_timeout: Duration,
+    /// Most requests at once on an HTTP/2 connection
+    pub max_concurrent_streams: u32,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_headers: 100,
+            max_head_bytes: 64 * 1024,
+            header_read_timeout: Duration::from_secs(30),
+            max_concurrent_streams: 100,
+        }
+    }
+}
+
+impl RequestLimits {
+    /// Builder of HTTP/1.1 and HTTP/2 connections enforcing the limits, the protocol
+    /// told apart by the client's preface
+    fn builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(self.header_read_timeout)
+            .max_headers(self.max_headers)
+            .max_buf_size(self.max_head_bytes.max(MIN_HEAD_BYTES));
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_header_list_size(self.max_head_bytes.max(MIN_HEAD_BYTES) as u32)
+            .max_concurrent_streams(self.max_concurrent_streams)
+            .keep_alive_interval(self.header_read_timeout)
+            .keep_alive_timeout(self.header_read_timeout);
+        builder
+    }
+}
+
+/// A request in flight on a connection. The connection's slot covers one request
+/// at a time; each HTTP/2 stream in flight beside it holds a slot of its own, so
+/// multiplexing doesn't get around `--max-connections`.
+struct InFlight {
+    streams: Arc<AtomicUsize>,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl InFlight {
+    /// Count a request on the connection with `streams` in flight, unless it needs
+    /// a slot and none is free
+    fn start(streams: &Arc<AtomicUsize>, root_domain: bool) -> Option<Self> {
+        let beside_others = streams.fetch_add(1, Ordering::AcqRel) > 0;
+        // Uncounted again when dropped, also when no slot is free
+        let mut in_flight = Self {
+            streams: streams.clone(),
+            _slot: None,
+        };
+        let Some(capacity) = CAPACITY.get().filter(|_| beside_others) else {
+            return Some(in_flight);
+        };
+        let slot = if root_domain {
+            capacity.management_connection()
+        } else {
+            capacity.function_connection()
+        };
+        in_flight._slot = Some(slot?);
+        Some(in_flight)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.streams.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
 // Note: text_response and redirect_to_website functions have been moved to wasi_server module
 
-/// Runs the HTTP server that redirects HTTP requests to HTTPS
-pub async fn run_http_server(http_listener: TcpListener) {
+/// Runs the HTTP server that redirects HTTP requests to HTTPS, moving to each
+/// listener received from `rebinds`
+pub async fn run_http_server(
+    mut http_listener: TcpListener,
+    mut rebinds: UnboundedReceiver<TcpListener>,
+) {
     info!("HTTP redirect server listening for connections");
 
     // Create a function to convert HTTP URLs to HTTPS
@@ -55,9 +165,6 @@ pub async fn run_http_server(http_listener: TcpListener) {
         Ok(Uri::from_parts(parts)?)
     };
 
-    // Get the local port this listener is bound to
-    let listener_addr = http_listener.local_addr().unwrap();
-
     // Determine HTTPS port (default to 443)
     let https_port = 443;
 
@@ -75,23 +182,57 @@ pub async fn run_http_server(http_listener: TcpListener) {
     // Create Axum router with the redirect handler
     let app = Router::new().fallback(redirect);
 
-    // Start the Axum HTTP server
-    info!(
-        "HTTP redirect service listening on http://{}",
-        listener_addr
-    );
+    loop {
+        // Start the Axum HTTP server
+        info!(
+            "HTTP redirect service listening on http://{}",
+            http_listener.local_addr().unwrap()
+        );
 
-    // Serve with the existing TcpListener
-    axum::serve(http_listener, app).await.unwrap();
+        // Serve with the listener until the server drains or moves to another one
+        let rebound = CancellationToken::new();
+        let stopped = rebound.clone();
+        let server = axum::serve(http_listener, app.clone()).with_graceful_shutdown(async move {
+            tokio::select! {
+                _ = SHUTDOWN.draining() => {}
+                _ = stopped.cancelled() => {}
+            }
+        });
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("HTTP redirect server failed: {}", e);
+            }
+        });
+        match rebinds.recv().await {
+            Some(listener) => {
+                rebound.cancel();
+                http_listener = listener;
+            }
+            None => return,
+        }
+    }
 }
 
-/// Runs the HTTPS server
-pub async fn run_https_server(listener: TcpListener, tls_acceptor: TlsAcceptor) {
+/// Runs the HTTPS server, moving to each listener received from `rebinds`, and
+/// returning once the server drains
+pub async fn run_https_server(
+    mut listener: TcpListener,
+    tls_acceptor: TlsAcceptor,
+    mut rebinds: UnboundedReceiver<TcpListener>,
+) {
     info!("HTTPS server listening for connections");
 
     loop {
         // Accept incoming connection
-        let (stream, peer_addr) = match listener.accept().await {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(rebound) = rebinds.recv() => {
+                listener = rebound;
+                continue;
+            }
+            _ = SHUTDOWN.draining() => break,
+        };
+        let (stream, peer_addr) = match accepted {
             Ok(conn) => conn,
             Err(e) => {
                 error!("Failed to accept connection: {}", e);
@@ -100,24 +241,97 @@ pub async fn run_https_server(listener: TcpListener, tls_acceptor: TlsAcceptor)
         };
         info!("Accepted connection from {}", peer_addr);
 
-        // Clone acceptor for this connection
-        let tls_acceptor = tls_acceptor.clone();
+        // Clone the TLS configuration for this connection
+        let tls_config = tls_acceptor.config().clone();
+        let limits = REQUEST_LIMITS
+            .get_or_init(|| Reloadable::new(RequestLimits::default()))
+            .load();
 
         // Handle connection in a new task
+        let in_flight = SHUTDOWN.track();
         tokio::spawn(async move {
-            // Perform TLS handshake
-            match tls_acceptor.accept(stream).await {
-                Ok(tls_stream) => {
+            let _in_flight = in_flight;
+            // Perform TLS handshake, fingerprinting the client by its ClientHello
+            let handshake = async {
+                let start = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+                let hello = TlsHello::new(&start.client_hello());
+                let tls_stream = start.into_stream(tls_config).await?;
+                Ok::<_, std::io::Error>((tls_stream, hello))
+            };
+            let handshake = tokio::time::timeout(limits.header_read_timeout, handshake);
+            match handshake.await {
+                Ok(Ok((tls_stream, hello))) => {
                     info!("TLS handshake successful with {}", peer_addr);
 
+                    // The management API lives on the root domain, whose
+                    // connections may use the slots reserved for management
+                    let base_domain = SERVER.get().unwrap().base_domain.load();
+                    let root_domain =
+                        tls_stream.get_ref().1.server_name() == Some(base_domain.as_str());
+                    let _slot = match CAPACITY.get() {
+                        Some(capacity) => {
+                            let slot = if root_domain {
+                                capacity.management_connection()
+                            } else {
+                                capacity.function_connection()
+                            };
+                            if slot.is_none() {
+                                warn!(
+                                    "Out of connection slots, closing connection from {}",
+                                    peer_addr
+                                );
+                                return;
+                            }
+                            slot
+                        }
+                        None => None,
+                    };
+
                     // Create a service function for handling HTTP requests
-                    let service = service_fn(move |req: Request<Incoming>| {
+                    let streams = Arc::new(AtomicUsize::new(0));
+                    let max_headers = limits.max_headers;
+                    let service = service_fn(move |mut req: Request<Incoming>| {
+                        // hyper only bounds the header count of HTTP/1.1 requests
+                        let too_many_headers = req.headers().len() > max_headers;
+                        let in_flight = InFlight::start(&streams, root_domain);
+                        req.extensions_mut().insert(hello.clone());
+                        req.extensions_mut().insert(peer_addr);
+                        // HTTP/2 carries the host in the URI, where routing doesn't look
+                        if !req.headers().contains_key(HOST) {
+                            let authority = req.uri().authority().map(|a| a.as_str().to_string());
+                            if let Some(value) =
+                                authority.and_then(|a| HeaderValue::from_str(&a).ok())
+                            {
+                                req.headers_mut().insert(HOST, value);
+                            }
+                        }
                         async move {
-                            match SERVER.get().unwrap().handle_request(req).await {
+                            if too_many_headers {
+                                return text_response(431, "Request Header Fields Too Large");
+                            }
+                            let Some(in_flight) = in_flight else {
+                                warn!(
+                                    "Out of connection slots, refusing a stream from {}",
+                                    peer_addr
+                                );
+                                let mut response =
+                                    text_response(503, "Server is at capacity, retry shortly")?;
+                                response.headers_mut().insert(
+                                    hyper::header::RETRY_AFTER,
+                                    HeaderValue::from_static("1"),
+                                );
+                                return Ok(response);
+                            };
+                            match SERVER.get().unwrap().serve_request(req).await {
                                 Ok(response) => {
-                                    // Just return the response directly - we'll handle any conversion issues
-                                    // at a different layer if needed
-                                    Ok::<_, anyhow::Error>(response)
+                                    // The request counts until its body is sent
+                                    Ok::<_, anyhow::Error>(response.map(|body| {
+                                        body.map_frame(move |frame| {
+                                            let _ = &in_flight;
+                                            frame
+                                        })
+                                        .boxed()
+                                    }))
                                 }
                                 Err(e) => {
                                     error!("Error handling request: {}", e);
@@ -144,20 +358,34 @@ pub async fn run_https_server(listener: TcpListener, tls_acceptor: TlsAcceptor)
                         }
                     });
 
-                    // Serve the HTTP connection directly with hyper
-                    if let Err(err) = http1::Builder::new()
-                        .serve_connection(TokioIo::new(tls_stream), service)
-                        .await
-                    {
+                    // Serve the HTTP connection directly with hyper, closing it after
+                    // the requests in progress once the server drains
+                    let builder = limits.builder();
+                    let connection = builder.serve_connection(TokioIo::new(tls_stream), service);
+                    tokio::pin!(connection);
+                    let served = tokio::select! {
+                        served = connection.as_mut() => served,
+                        _ = SHUTDOWN.draining() => {
+                            connection.as_mut().graceful_shutdown();
+                            connection.await
+                        }
+                    };
+                    if let Err(err) = served {
                         // Only log errors that aren't from client disconnects
-                        if !err.is_closed() && !err.is_canceled() {
+                        let disconnected = err
+                            .downcast_ref::<hyper::Error>()
+                            .is_some_and(|err| err.is_closed() || err.is_canceled());
+                        if !disconnected {
                             error!("Error serving connection from {}: {}", peer_addr, err);
                         }
                     }
                 }
-                Err(e) => {
+                Ok(Err(e)) => {
                     error!("TLS handshake failed with {}: {}", peer_addr, e);
                 }
+                Err(_) => {
+                    warn!("TLS handshake with {} timed out", peer_addr);
+                }
             }
         });
     }
@@ -0,0 +1,268 @@
+//! Warm instances of hot functions.
+//!
+//! Instantiating a function for every request costs far more than calling into
+//! an instance that already exists. With `--warm-instances` set, an instance
+//! that answered its request without trapping is parked here, with its store and
+//! output captures, and the function's next request calls it again instead of
+//! instantiating. Only functions called again within [`IDLE_TIMEOUT`] keep their
+//! instances, so the pool holds hot functions. An instance is recycled after
+//! `--instance-max-requests` requests, which bounds what a leaking guest can
+//! accumulate, and instances of a version are dropped whenever its component is
+//! evicted from the pre-instantiation cache (deploys, renames, spec changes).
+//!
+//! A reused instance keeps its globals between requests, which is why reuse is
+//! off unless the operator turns it on. Instances of kept-warm functions are
+//! created ahead of their first request and never time out, and a function's
+//! keep-warm schedule may keep more of them than `--warm-instances` while it's in
+//! one of its windows.
+
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+use tracing::debug;
+use wasmtime::Store;
+use wasmtime_wasi_http::bindings::Proxy;
+
+use crate::keep_warm::{is_kept_warm, scheduled_instances};
+use crate::logs::OutputCapture;
+use crate::wasi_server::FaastaClientState;
+
+/// How long an instance stays parked without being called again
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Global pool, set at startup when instance reuse is turned on
+pub static INSTANCE_POOL: OnceCell<InstancePool> = OnceCell::new();
+
+/// Id of the next instance created
+static NEXT_INSTANCE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// An instantiated function, with the store and output captures it runs with
+pub struct WarmInstance {
+    /// Tells the instance apart from others of its function while it lives
+    pub id: u64,
+    pub store: Store<FaastaClientState>,
+    pub proxy: Proxy,
+    pub stdout: OutputCapture,
+    pub stderr: OutputCapture,
+    /// Requests the instance has served
+    pub requests: u32,
+    parked_at: Instant,
+}
+
+impl WarmInstance {
+    pub fn new(
+        store: Store<FaastaClientState>,
+        proxy: Proxy,
+        stdout: OutputCapture,
+        stderr: OutputCapture,
+    ) -> Self {
+        Self {
+            id: NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed),
+            store,
+            proxy,
+            stdout,
+            stderr,
+            requests: 0,
+            parked_at: Instant::now(),
+        }
+    }
+}
+
+pub struct InstancePool {
+    /// Most instances parked per function version
+    per_function: usize,
+    /// Most instances parked in all, as each holds slots of the pooling allocator
+    max_total: usize,
+    /// Requests after which an instance is dropped instead of parked
+    max_requests: u32,
+    /// Parked instances by function version, most recently parked last.
+    /// Stores aren't `Sync`, so they sit behind a lock rather than in a `DashMap`.
+    idle: Mutex<HashMap<String, Vec<WarmInstance>>>,
+}
+
+impl InstancePool {
+    pub fn new(per_function: usize, max_total: usize, max_requests: u32) -> Self {
+        Self {
+            per_function,
+            max_total,
+            max_requests,
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A parked instance of `version` to serve a request with, its output
+    /// captures cleared
+    pub fn take(&self, version: &str) -> Option<WarmInstance> {
+        let mut idle = self.idle.lock().unwrap();
+        let instances = idle.get_mut(version)?;
+        while let Some(instance) = instances.pop() {
+            if !expired(version, &instance) {
+                instance.stdout.clear();
+                instance.stderr.clear();
+                return Some(instance);
+            }
+        }
+        None
+    }
+
+    /// Park an instance of `version` that served a request without trapping,
+    /// unless it's due for recycling or the pool is full
+    pub fn park(&self, version: &str, mut instance: WarmInstance) {
+        instance.requests += 1;
+        if instance.requests >= self.max_requests {
+            debug!(
+                "Recycling an instance of '{}' after {} requests",
+                version, instance.requests
+            );
+            return;
+        }
+        self.put(version, instance);
+    }
+
+    /// Park an instance of `version`, unless the pool is full. Returns whether it
+    /// was parked.
+    pub fn put(&self, version: &str, mut instance: WarmInstance) -> bool {
+        // A keep-warm schedule may keep more instances than the pool's default
+        let per_function = self.per_function.max(scheduled_instances(version) as usize);
+        let mut idle = self.idle.lock().unwrap();
+        let total: usize = idle.values().map(Vec::len).sum();
+        let instances = idle.entry(version.to_string()).or_default();
+        if instances.len() >= per_function || total >= self.max_total {
+            return false;
+        }
+        instance.parked_at = Instant::now();
+        instances.push(instance);
+        true
+    }
+
+    /// Instances of `version` parked
+    pub fn idle_count(&self, version: &str) -> usize {
+        self.idle.lock().unwrap().get(version).map_or(0, Vec::len)
+    }
+
+    /// Drop the parked instances of `version`, e.g. when it was redeployed
+    pub fn evict(&self, version: &str) {
+        self.idle.lock().unwrap().remove(version);
+    }
+
+    /// Drop instances parked for longer than [`IDLE_TIMEOUT`]
+    pub fn sweep(&self) {
+        self.idle.lock().unwrap().retain(|version, instances| {
+            instances.retain(|instance| !expired(version, instance));
+            !instances.is_empty()
+        });
+    }
+}
+
+/// Whether a parked instance is dropped instead of called again
+fn expired(version: &str, instance: &WarmInstance) -> bool {
+    instance.parked_at.elapsed() >= IDLE_TIMEOUT && !is_kept_warm(version)
+}
+
+/// Spawn a task dropping idle instances, so cold functions free their slots
+pub fn spawn_periodic_sweep() {
+    tokio::spawn(async move {
+        let mut ticker = interval(IDLE_TIMEOUT / 2);
+        loop {
+            ticker.tick().await;
+            if let Some(pool) = INSTANCE_POOL.get() {
+                pool.sweep();
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasi_server::{build_linker, client_state};
+    use wasmtime::component::Component;
+    use wasmtime::{Config, Engine};
+    use wasmtime_wasi::OutputStream;
+    use wasmtime_wasi_http::bindings::ProxyPre;
+
+    /// The smallest component of the `wasi:http/proxy` world, answering nothing
+    const HANDLER: &str = r#"(component
+        (import "wasi:http/types@0.2.0" (instance $types
+            (export "incoming-request" (type (sub resource)))
+            (export "response-outparam" (type (sub resource)))
+        ))
+        (alias export $types "incoming-request" (type $request))
+        (alias export $types "response-outparam" (type $response_out))
+        (core module $m (func (export "handle") (param i32 i32)))
+        (core instance $i (instantiate $m))
+        (func $handle (param "request" (own $request)) (param "response-out" (own $response_out))
+            (canon lift (core func $i "handle")))
+        (instance $handler (export "handle" (func $handle)))
+        (export "wasi:http/incoming-handler@0.2.0" (instance $handler))
+    )"#;
+
+    async fn instances(count: usize) -> Vec<WarmInstance> {
+        let mut config = Config::new();
+        config.async_support(true);
+        let engine = Engine::new(&config).unwrap();
+        let component = Component::new(&engine, HANDLER).unwrap();
+        let linker = build_linker(&engine);
+        let pre = ProxyPre::new(linker.instantiate_pre(&component).unwrap()).unwrap();
+        let mut instances = Vec::new();
+        for _ in 0..count {
+            let (state, stdout, stderr) = client_state("pool-test", None);
+            let mut store = Store::new(&engine, state);
+            let proxy = pre.instantiate_async(&mut store).await.unwrap();
+            instances.push(WarmInstance::new(store, proxy, stdout, stderr));
+        }
+        instances
+    }
+
+    #[tokio::test]
+    async fn test_parked_instances_are_reused_until_recycled() {
+        let pool = InstancePool::new(2, 8, 2);
+        let mut instance = instances(1).await.pop().unwrap();
+        let id = instance.id;
+        instance.stdout.write("hello".into()).unwrap();
+
+        pool.park("api", instance);
+        assert_eq!(pool.idle_count("api"), 1);
+        assert!(pool.take("web").is_none());
+        let instance = pool.take("api").unwrap();
+        assert_eq!((instance.id, instance.requests), (id, 1));
+        // The next request doesn't see the output of the last one
+        assert!(instance.stdout.contents().is_empty());
+
+        // Recycled once it served `--instance-max-requests` requests
+        pool.park("api", instance);
+        assert_eq!(pool.idle_count("api"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_the_pool_is_bounded_per_function_and_in_all() {
+        let pool = InstancePool::new(2, 3, 100);
+        let mut instances = instances(5).await.into_iter();
+        assert!(pool.put("api", instances.next().unwrap()));
+        assert!(pool.put("api", instances.next().unwrap()));
+        assert!(!pool.put("api", instances.next().unwrap()));
+        assert!(pool.put("web", instances.next().unwrap()));
+        assert!(!pool.put("docs", instances.next().unwrap()));
+
+        pool.evict("api");
+        assert_eq!(pool.idle_count("api"), 0);
+        assert_eq!(pool.idle_count("web"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_idle_instances_are_swept() {
+        let pool = InstancePool::new(2, 8, 100);
+        let mut instances = instances(2).await.into_iter();
+        pool.put("api", instances.next().unwrap());
+        pool.put("web", instances.next().unwrap());
+        let idle_since = Instant::now().checked_sub(IDLE_TIMEOUT).unwrap();
+        pool.idle.lock().unwrap().get_mut("api").unwrap()[0].parked_at = idle_since;
+
+        pool.sweep();
+        assert_eq!(pool.idle_count("api"), 0);
+        assert_eq!(pool.idle_count("web"), 1);
+    }
+}
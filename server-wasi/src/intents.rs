@@ -0,0 +1,376 @@
+//! Intent log of publishes, deletes and restores.
+//!
+//! Publishing, deleting and restoring a function touch its owner's list of projects,
+//! artifact storage, the local precompiled file and several sled trees in turn,
+//! and artifact storage can't join a transaction. So an intent naming the
+//! operation is written before its first step, and removed in the same batch of
+//! the metadata store that writes or removes the function's metadata, its last step.
+//!
+//! Intents still there at startup belong to operations a crash interrupted, and
+//! are recovered before requests are served: a publish of a new function is rolled
+//! back, one over an existing function is rolled forward to the artifact in
+//! storage, and a delete or a restore from the trash is finished. In a cluster, intents are keyed by the node
+//! running the operation too, so a node only recovers its own, until a node is
+//! gone for good and another one recovers its intents.
+
+use anyhow::{anyhow, Result};
+use bincode::{Decode, Encode};
+use faasta_interface::FunctionInfo;
+use once_cell::sync::OnceCell;
+use std::fs;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::function_data;
+use crate::metadata_store::{Change, MetadataStore};
+use crate::rpc_service::{FunctionServiceImpl, LegacyFunctionInfo, FUNCTIONS_DB_TREE};
+use crate::storage::wasm_key;
+use crate::trash::TRASH;
+use crate::wasi_server::{FaastaServer, SERVER};
+
+/// Collection of the metadata store holding the operations in progress, keyed by
+/// function name
+pub const INTENTS_TREE: &str = "intents";
+
+/// Global intent log, set at startup
+pub static INTENTS: OnceCell<Intents> = OnceCell::new();
+
+/// An operation on a function that hasn't finished
+#[derive(Clone, Debug, Encode, Decode)]
+pub enum Intent {
+    Publish {
+        owner: String,
+        /// Whether the function had no metadata before the publish
+        new: bool,
+    },
+    Delete {
+        info: FunctionInfo,
+        /// Whether the function goes to the trash rather than being deleted for good
+        trash: bool,
+    },
+    Restore {
+        info: FunctionInfo,
+    },
+}
+
+/// `Intent` as recorded before functions carried metadata
+#[derive(Decode)]
+enum LegacyIntent {
+    Publish {
+        owner: String,
+        new: bool,
+    },
+    Delete {
+        info: LegacyFunctionInfo,
+        trash: bool,
+    },
+}
+
+impl From<LegacyIntent> for Intent {
+    fn from(legacy: LegacyIntent) -> Self {
+        match legacy {
+            LegacyIntent::Publish { owner, new } => Intent::Publish { owner, new },
+            LegacyIntent::Delete { info, trash } => Intent::Delete {
+                info: info.into(),
+                trash,
+            },
+        }
+    }
+}
+
+/// Decode a recorded intent, including ones recorded before functions carried
+/// metadata
+fn decode_intent(bytes: &[u8]) -> Result<Intent, bincode::error::DecodeError> {
+    let config = bincode::config::standard();
+    match bincode::decode_from_slice::<Intent, _>(bytes, config) {
+        Ok((intent, _)) => Ok(intent),
+        Err(e) => bincode::decode_from_slice::<LegacyIntent, _>(bytes, config)
+            .map(|(legacy, _)| legacy.into())
+            .map_err(|_| e),
+    }
+}
+
+pub struct Intents {
+    store: Arc<dyn MetadataStore>,
+    /// Id of this node, in a cluster
+    node_id: Option<String>,
+}
+
+impl Intents {
+    pub fn new(store: Arc<dyn MetadataStore>, node_id: Option<String>) -> Self {
+        Self { store, node_id }
+    }
+
+    /// Key of the intent on `name`
+    fn key(&self, name: &str) -> String {
+        match &self.node_id {
+            Some(node_id) => format!("{node_id}/{name}"),
+            None => name.to_string(),
+        }
+    }
+
+    /// Function named by the intent under `key`, if this node recorded it
+    fn function<'a>(&self, key: &'a str) -> Option<&'a str> {
+        match &self.node_id {
+            Some(node_id) => key.strip_prefix(node_id.as_str())?.strip_prefix('/'),
+            None => (!key.contains('/')).then_some(key),
+        }
+    }
+
+    /// Record that `intent` on `name` is starting
+    pub fn begin(&self, name: &str, intent: &Intent) -> Result<()> {
+        let encoded = bincode::encode_to_vec(intent, bincode::config::standard())?;
+        self.store.insert(INTENTS_TREE, &self.key(name), encoded)?;
+        self.store.flush()
+    }
+
+    /// Finish the operation on `name` by setting its metadata to `metadata`, or
+    /// removing it, in one batch with removing the intent
+    pub fn commit(&self, name: &str, metadata: Option<Vec<u8>>) -> Result<()> {
+        self.commit_with(name, metadata, Vec::new())
+    }
+
+    /// [`commit`](Self::commit), applying `changes` in the same batch
+    pub fn commit_with(
+        &self,
+        name: &str,
+        metadata: Option<Vec<u8>>,
+        mut changes: Vec<Change>,
+    ) -> Result<()> {
+        let key = name.to_string();
+        let function = match metadata {
+            Some(value) => Change::Insert {
+                collection: FUNCTIONS_DB_TREE,
+                key,
+                value,
+            },
+            None => Change::Remove {
+                collection: FUNCTIONS_DB_TREE,
+                key,
+            },
+        };
+        changes.extend([
+            function,
+            Change::Remove {
+                collection: INTENTS_TREE,
+                key: self.key(name),
+            },
+        ]);
+        self.store.apply(&changes)
+    }
+
+    /// Operations a crash interrupted, or still running
+    pub fn pending(&self) -> Vec<(String, Intent)> {
+        let intents = match self.store.scan(INTENTS_TREE) {
+            Ok(intents) => intents,
+            Err(e) => {
+                error!("Failed to read the intent log: {}", e);
+                return Vec::new();
+            }
+        };
+        intents
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let name = self.function(&key)?.to_string();
+                match decode_intent(&value) {
+                    Ok(intent) => Some((name, intent)),
+                    Err(e) => {
+                        error!("Failed to decode the intent on '{}': {}", name, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Undo or finish `intent` on `name` the way a restart would, and forget it.
+    /// Also how an operation that failed part way cleans up after itself.
+    pub async fn recover(&self, name: &str, intent: &Intent) -> Result<()> {
+        let server = SERVER
+            .get()
+            .ok_or_else(|| anyhow!("Server is not initialized"))?;
+        match intent {
+            Intent::Publish { owner, new: true } => roll_back_publish(server, name, owner).await?,
+            Intent::Publish { new: false, .. } => roll_forward_publish(server, name).await?,
+            Intent::Delete { info, trash } => finish_delete(info.clone(), *trash).await?,
+            Intent::Restore { info } => finish_restore(server, info).await?,
+        }
+        self.forget(name)
+    }
+
+    fn forget(&self, name: &str) -> Result<()> {
+        self.store.remove(INTENTS_TREE, &self.key(name))
+    }
+}
+
+/// Recover the operations a crash interrupted. Runs at startup, before anything
+/// else can publish or delete.
+pub async fn recover() {
+    if let Some(intents) = INTENTS.get() {
+        recover_intents(intents).await;
+    }
+}
+
+/// Recover the operations recorded in `intents`, this node's or in a cluster
+/// those of a node that is gone
+pub async fn recover_intents(intents: &Intents) {
+    if SERVER.get().is_none() {
+        return;
+    }
+    for (name, intent) in intents.pending() {
+        match intents.recover(&name, &intent).await {
+            Ok(()) => warn!("Recovered an interrupted {:?} of '{}'", intent, name),
+            Err(e) => error!(
+                "Failed to recover the interrupted operation on '{}': {}",
+                name, e
+            ),
+        }
+    }
+}
+
+/// Undo what an interrupted publish of a new function did
+async fn roll_back_publish(server: &FaastaServer, name: &str, owner: &str) -> Result<()> {
+    // The metadata is written last, with the intent removed, so it's there only if
+    // a concurrent publish finished since
+    if server.metadata.contains(FUNCTIONS_DB_TREE, name)? {
+        return Ok(());
+    }
+    server.storage.delete(&wasm_key(name)).await?;
+    let cwasm_path = server.functions_dir.join(format!("{name}.cwasm"));
+    if cwasm_path.exists() {
+        fs::remove_file(&cwasm_path)?;
+    }
+
+    // A trashed function, or a renamed one's old name, keeps its name and data
+    let trashed = TRASH.get().and_then(|trash| trash.get(name)).is_some();
+    if !trashed && server.redirects.lookup(name)?.is_none() {
+        function_data::remove(server.metadata.as_ref(), &server.metadata_db, name)?;
+        server.github_auth.remove_project(owner, name).await?;
+    }
+    info!("Rolled back the interrupted publish of '{}'", name);
+    Ok(())
+}
+
+/// Bring a function an interrupted publish replaced in part up to the artifact
+/// in storage, whichever version that is
+async fn roll_forward_publish(server: &FaastaServer, name: &str) -> Result<()> {
+    let cwasm_path = server.functions_dir.join(format!("{name}.cwasm"));
+    if cwasm_path.exists() {
+        // Compiled again from storage on the next request
+        fs::remove_file(&cwasm_path)?;
+    }
+    server.remove_from_cache(name);
+    if let Some(wasm) = server.storage.get(&wasm_key(name)).await? {
+        function_data::record_artifact(server.metadata.as_ref(), name, &wasm)?;
+    }
+    info!("Rolled the interrupted publish of '{}' forward", name);
+    Ok(())
+}
+
+/// Run an interrupted delete again, each of its steps tolerating being done already
+async fn finish_delete(info: FunctionInfo, trash: bool) -> Result<()> {
+    let name = info.name.clone();
+    let trash = if trash {
+        Some(
+            TRASH
+                .get()
+                .ok_or_else(|| anyhow!("'{name}' was being trashed, but the trash is off"))?,
+        )
+    } else {
+        None
+    };
+    FunctionServiceImpl::new(None)?
+        .remove_function(info, trash)
+        .await
+        .map_err(|e| anyhow!("{e}"))?;
+    info!("Finished the interrupted delete of '{}'", name);
+    Ok(())
+}
+
+/// Run an interrupted restore from the trash again, moving the artifacts back
+/// unless they were already and writing the metadata
+async fn finish_restore(server: &FaastaServer, info: &FunctionInfo) -> Result<()> {
+    let name = &info.name;
+    if server.metadata.contains(FUNCTIONS_DB_TREE, name)? {
+        return Ok(());
+    }
+    if let Some(trash) = TRASH.get() {
+        trash.restore(name).await?;
+    }
+    let encoded = bincode::encode_to_vec(info, bincode::config::standard())?;
+    server.metadata.insert(FUNCTIONS_DB_TREE, name, encoded)?;
+    info!("Finished the interrupted restore of '{}'", name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_store::SledStore;
+
+    #[test]
+    fn test_commit_writes_metadata_and_clears_the_intent() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let intents = Intents::new(Arc::new(SledStore::new(&db)), None);
+        let functions = db.open_tree(FUNCTIONS_DB_TREE).unwrap();
+
+        let publish = Intent::Publish {
+            owner: "alice".to_string(),
+            new: true,
+        };
+        intents.begin("api", &publish).unwrap();
+        intents.begin("web", &publish).unwrap();
+        assert_eq!(intents.pending().len(), 2);
+
+        intents.commit("api", Some(b"metadata".to_vec())).unwrap();
+        assert_eq!(functions.get("api").unwrap().unwrap(), b"metadata");
+        let pending: Vec<String> = intents
+            .pending()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(pending, ["web"]);
+
+        intents.commit("api", None).unwrap();
+        assert!(functions.get("api").unwrap().is_none());
+
+        // In a cluster, each node sees only its own intents
+        let store: Arc<dyn MetadataStore> = Arc::new(SledStore::new(&db));
+        let node_a = Intents::new(store.clone(), Some("node-a".to_string()));
+        let node_b = Intents::new(store, Some("node-b".to_string()));
+        node_a.begin("api", &publish).unwrap();
+        assert_eq!(node_a.pending().len(), 1);
+        assert!(node_b.pending().is_empty());
+        assert_eq!(intents.pending().len(), 1);
+        node_a.commit("api", None).unwrap();
+        assert!(node_a.pending().is_empty());
+    }
+
+    #[test]
+    fn test_deletes_recorded_before_function_metadata_still_decode() {
+        let config = bincode::config::standard();
+        // The `Delete` variant, its `FunctionInfo` without metadata, and `trash`
+        let legacy = bincode::encode_to_vec(
+            (
+                1u32,
+                ("api", "alice", "2025-01-01T00:00:00+00:00", "usage"),
+                true,
+            ),
+            config,
+        )
+        .unwrap();
+        let Intent::Delete { info, trash } = decode_intent(&legacy).unwrap() else {
+            panic!("not a delete");
+        };
+        assert_eq!((info.name.as_str(), info.owner.as_str()), ("api", "alice"));
+        assert_eq!(info.metadata, Default::default());
+        assert!(trash);
+
+        let current = Intent::Delete { info, trash: false };
+        let encoded = bincode::encode_to_vec(&current, config).unwrap();
+        assert!(matches!(
+            decode_intent(&encoded).unwrap(),
+            Intent::Delete { trash: false, .. }
+        ));
+    }
+}
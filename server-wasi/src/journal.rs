@@ -0,0 +1,156 @@
+//! Journal of significant platform events for operators.
+//!
+//! Where the audit log records who called what, the journal records what happened
+//! to the platform itself: certificates renewed, canaries promoted, uploads refused
+//! by policy, functions purged from the trash and so on. Each event has a severity,
+//! and admins query the journal with `cargo faasta admin events`. Events can also be
+//! mirrored to the server log, so log shippers pick them up.
+
+use anyhow::Result;
+use faasta_interface::{EventSeverity, ServerEvent, ServerEventKind};
+use once_cell::sync::OnceCell;
+use tracing::{error, info, warn};
+
+use crate::events::{PlatformEvent, Subscriber};
+
+/// Sled tree holding journal events, keyed by big-endian event id
+const JOURNAL_TREE: &str = "server_events";
+/// Most events returned by one query
+pub const MAX_JOURNAL_PAGE: u32 = 1000;
+
+/// Global journal, set at startup
+pub static JOURNAL: OnceCell<Journal> = OnceCell::new();
+
+pub struct Journal {
+    db: sled::Db,
+    tree: sled::Tree,
+    /// Whether events are also written to the server log
+    mirror_to_log: bool,
+}
+
+impl Journal {
+    pub fn new(db: &sled::Db, mirror_to_log: bool) -> Result<Self> {
+        Ok(Self {
+            db: db.clone(),
+            tree: db.open_tree(JOURNAL_TREE)?,
+            mirror_to_log,
+        })
+    }
+
+    pub fn append(&self, mut event: ServerEvent) -> Result<()> {
+        event.id = self.db.generate_id()?;
+        let encoded = bincode::encode_to_vec(&event, bincode::config::standard())?;
+        self.tree.insert(event.id.to_be_bytes(), encoded)?;
+        if self.mirror_to_log {
+            mirror(&event);
+        }
+        Ok(())
+    }
+
+    pub fn record(
+        &self,
+        severity: EventSeverity,
+        kind: ServerEventKind,
+        subject: Option<&str>,
+        detail: impl Into<String>,
+    ) {
+        let event = ServerEvent {
+            id: 0,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            severity,
+            kind,
+            subject: subject.map(String::from),
+            detail: detail.into(),
+        };
+        if let Err(e) = self.append(event) {
+            error!("Failed to record {} event: {}", kind, e);
+        }
+    }
+
+    /// Up to `limit` matching events with ids above `after`, oldest first
+    pub fn events(
+        &self,
+        after: Option<u64>,
+        limit: usize,
+        min_severity: EventSeverity,
+        kind: Option<ServerEventKind>,
+    ) -> Vec<ServerEvent> {
+        let start = after.map_or(0, |after| after.saturating_add(1));
+        self.tree
+            .range(start.to_be_bytes()..)
+            .flatten()
+            .filter_map(|(_, value)| {
+                bincode::decode_from_slice::<ServerEvent, _>(&value, bincode::config::standard())
+                    .ok()
+                    .map(|(event, _)| event)
+            })
+            .filter(|event| event.severity >= min_severity)
+            .filter(|event| kind.is_none_or(|kind| event.kind == kind))
+            .take(limit)
+            .collect()
+    }
+}
+
+/// Record an event in the global journal
+pub fn record(
+    severity: EventSeverity,
+    kind: ServerEventKind,
+    subject: Option<&str>,
+    detail: impl Into<String>,
+) {
+    if let Some(journal) = JOURNAL.get() {
+        journal.record(severity, kind, subject, detail);
+    }
+}
+
+/// Journals the bus events operators should see
+impl Subscriber for Journal {
+    fn on_event(&self, event: &PlatformEvent) {
+        match event {
+            PlatformEvent::QuotaExceeded {
+                account,
+                quota,
+                limit,
+            } => self.record(
+                EventSeverity::Warning,
+                ServerEventKind::QuotaExceeded,
+                account.as_deref(),
+                format!("Refused for having {limit} {quota} already"),
+            ),
+            PlatformEvent::CertRenewed { domain } => self.record(
+                EventSeverity::Info,
+                ServerEventKind::CertificateRenewed,
+                Some(domain),
+                "Installed a new TLS certificate",
+            ),
+            PlatformEvent::Deployed { .. } | PlatformEvent::InvokeCompleted { .. } => {}
+        }
+    }
+}
+
+fn mirror(event: &ServerEvent) {
+    let subject = event.subject.as_deref().unwrap_or("-");
+    match event.severity {
+        EventSeverity::Info => info!(
+            target: "faasta::journal",
+            kind = %event.kind,
+            subject,
+            "{}",
+            event.detail
+        ),
+        EventSeverity::Warning => warn!(
+            target: "faasta::journal",
+            kind = %event.kind,
+            subject,
+            "{}",
+            event.detail
+        ),
+        EventSeverity::Error => error!(
+            target: "faasta::journal",
+            kind = %event.kind,
+            subject,
+            "{}",
+            event.detail
+        ),
+    }
+}
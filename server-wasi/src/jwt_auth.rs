@@ -0,0 +1,590 @@
+//! JWT request authentication of functions.
+//!
+//! A function with a JWT policy, set from the `[auth]` section of its project's
+//! `faasta.toml` on deploy, is only invoked for requests carrying a valid
+//! `Authorization: Bearer` token, outside its public routes. The token must be
+//! signed with an asymmetric key from the issuer's JWKS, name the issuer and one of
+//! the audiences, and not be expired. Other requests get a 401 without the function
+//! running.
+//!
+//! The function sees the token's verified claims in headers: `sub` in
+//! `X-Faasta-Auth-Subject` and all of them, as base64url JSON, in
+//! `X-Faasta-Auth-Claims`. Headers of those names from the client are dropped.
+//!
+//! JWKS are discovered from the issuer's OpenID configuration unless given, and
+//! cached for ten minutes. A token signed with a key not in the cache fetches the
+//! JWKS again, at most once a minute, so rotated keys are picked up.
+//!
+//! As anyone deploying a function picks the URLs fetched, they must be https on
+//! public addresses, redirects aren't followed and documents are read up to 256
+//! KiB. A failed fetch isn't retried for 30 seconds, and at most 1024 issuers and
+//! JWKS are cached.
+
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use dashmap::DashMap;
+use faasta_interface::JwtAuthPolicy;
+use hyper::header::{HeaderValue, AUTHORIZATION};
+use hyper::Request;
+use jsonwebtoken::jwk::Jwk;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use once_cell::sync::OnceCell;
+use serde_json::{Map, Value};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::metadata_store::{Cached, MetadataStore};
+
+/// Collection of the metadata store holding each function's JWT policy, keyed by
+/// function name
+pub const JWT_AUTH_TREE: &str = "function_jwt_auth";
+
+/// Header carrying the verified token's `sub` claim
+pub const SUBJECT_HEADER: &str = "x-faasta-auth-subject";
+/// Header carrying the verified token's claims, as base64url JSON
+pub const CLAIMS_HEADER: &str = "x-faasta-auth-claims";
+
+/// Global JWT policies, set at startup
+pub static JWT_AUTH: OnceCell<JwtAuth> = OnceCell::new();
+
+/// How long a fetched JWKS is used
+const JWKS_TTL: Duration = Duration::from_secs(10 * 60);
+/// Least time between fetches of a JWKS for tokens signed with unknown keys
+const JWKS_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a failed fetch is answered from the cache instead of retried
+const FETCH_FAILURE_TTL: Duration = Duration::from_secs(30);
+/// Largest discovery document or JWKS read
+const MAX_DOCUMENT_BYTES: usize = 256 * 1024;
+/// Most entries in each cache of issuers, JWKS and failed fetches
+const MAX_CACHED: usize = 1024;
+/// Largest claims passed on to the function, once encoded
+const MAX_CLAIMS_HEADER: usize = 8 * 1024;
+
+/// Signature algorithms accepted: only asymmetric ones, as JWKS are public
+const ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Why a request was refused
+#[derive(Debug)]
+pub enum AuthFailure {
+    /// No token, or one that doesn't verify
+    Unauthorized(String),
+    /// The issuer's keys couldn't be fetched
+    KeysUnavailable(String),
+}
+
+struct CachedJwks {
+    keys: Vec<Jwk>,
+    fetched: Instant,
+}
+
+pub struct JwtAuth {
+    policies: Cached<JwtAuthPolicy>,
+    client: reqwest::Client,
+    /// JWKS by URL
+    jwks: DashMap<String, CachedJwks>,
+    /// JWKS URLs discovered, by issuer
+    discovered: DashMap<String, String>,
+    /// When and why fetching each URL last failed
+    failures: DashMap<String, (Instant, String)>,
+}
+
+impl JwtAuth {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Result<Self> {
+        Ok(Self {
+            policies: Cached::new(store, JWT_AUTH_TREE, |value| {
+                bincode::decode_from_slice(value, bincode::config::standard())
+                    .ok()
+                    .map(|(policy, _)| policy)
+            })?,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .https_only(true)
+                .redirect(reqwest::redirect::Policy::none())
+                .dns_resolver(Arc::new(PublicResolver))
+                .build()?,
+            jwks: DashMap::new(),
+            discovered: DashMap::new(),
+            failures: DashMap::new(),
+        })
+    }
+
+    /// Set the policy of `name`, or remove it
+    pub fn set(&self, name: &str, policy: Option<&JwtAuthPolicy>) -> Result<()> {
+        match policy {
+            Some(policy) => {
+                let encoded = bincode::encode_to_vec(policy, bincode::config::standard())?;
+                self.policies.insert(name, policy.clone(), encoded)
+            }
+            None => self.policies.remove(name).map(|_| ()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<JwtAuthPolicy> {
+        self.policies.get(name)
+    }
+
+    /// Read `name`'s policy again, after its record was moved or copied
+    pub fn refresh(&self, name: &str) -> Result<()> {
+        self.policies.refresh(name)
+    }
+
+    /// Read every policy again, for changes made by other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.policies.reload()
+    }
+
+    /// Check `req` against `policy`, passing the verified claims on in its headers
+    pub async fn authenticate<B>(
+        &self,
+        policy: &JwtAuthPolicy,
+        req: &mut Request<B>,
+    ) -> Result<(), AuthFailure> {
+        strip_claim_headers(req);
+        if policy.is_public(req.uri().path()) {
+            return Ok(());
+        }
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                value
+                    .strip_prefix("Bearer ")
+                    .or_else(|| value.strip_prefix("bearer "))
+            })
+            .map(str::trim)
+            .ok_or_else(|| AuthFailure::Unauthorized("missing bearer token".to_string()))?;
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| AuthFailure::Unauthorized(format!("malformed token: {e}")))?;
+
+        let jwks_url = self
+            .jwks_url(policy)
+            .await
+            .map_err(|e| AuthFailure::KeysUnavailable(e.to_string()))?;
+        let mut keys = self
+            .keys(&jwks_url, false)
+            .await
+            .map_err(|e| AuthFailure::KeysUnavailable(e.to_string()))?;
+        // A key missing from the cache may have been added since it was fetched
+        if find_key(&keys, header.kid.as_deref()).is_none() {
+            if let Ok(refetched) = self.keys(&jwks_url, true).await {
+                keys = refetched;
+            }
+        }
+        let claims = verify(policy, &keys, token).map_err(AuthFailure::Unauthorized)?;
+        set_claim_headers(req, &claims);
+        Ok(())
+    }
+
+    /// JWKS URL of `policy`'s issuer
+    async fn jwks_url(&self, policy: &JwtAuthPolicy) -> Result<String> {
+        if let Some(url) = &policy.jwks_url {
+            return Ok(url.clone());
+        }
+        if let Some(url) = self.discovered.get(&policy.issuer) {
+            return Ok(url.clone());
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            policy.issuer.trim_end_matches('/')
+        );
+        let discovery = self.fetch(&url).await?;
+        let jwks_url = discovery["jwks_uri"]
+            .as_str()
+            .filter(|jwks_url| jwks_url.starts_with("https://"))
+            .ok_or_else(|| anyhow!("No https jwks_uri in the discovery document at {url}"))?
+            .to_string();
+        insert_bounded(&self.discovered, policy.issuer.clone(), jwks_url.clone());
+        Ok(jwks_url)
+    }
+
+    /// Keys of the JWKS at `url`, fetched when the cached ones are stale, or when
+    /// `refetch` is set and they weren't fetched within the last minute
+    async fn keys(&self, url: &str, refetch: bool) -> Result<Vec<Jwk>> {
+        if let Some(cached) = self.jwks.get(url) {
+            let age = cached.fetched.elapsed();
+            if age < JWKS_TTL && (!refetch || age < JWKS_REFETCH_INTERVAL) {
+                return Ok(cached.keys.clone());
+            }
+        }
+        let jwks = self.fetch(url).await?;
+        // Keys for other uses, or of types this server can't read, are skipped
+        let keys: Vec<Jwk> = jwks["keys"]
+            .as_array()
+            .ok_or_else(|| anyhow!("No keys in the JWKS at {url}"))?
+            .iter()
+            .filter_map(|key| serde_json::from_value(key.clone()).ok())
+            .collect();
+        if keys.is_empty() {
+            warn!("The JWKS at {} has no usable keys", url);
+        }
+        insert_bounded(
+            &self.jwks,
+            url.to_string(),
+            CachedJwks {
+                keys: keys.clone(),
+                fetched: Instant::now(),
+            },
+        );
+        Ok(keys)
+    }
+
+    /// The JSON document at `url`. A failure is returned again, without a request,
+    /// until it's older than [`FETCH_FAILURE_TTL`].
+    async fn fetch(&self, url: &str) -> Result<Value> {
+        if let Some(failure) = self.failures.get(url) {
+            if failure.0.elapsed() < FETCH_FAILURE_TTL {
+                bail!("{}", failure.1);
+            }
+        }
+        let fetched = self.fetch_uncached(url).await;
+        match &fetched {
+            Ok(_) => {
+                self.failures.remove(url);
+            }
+            Err(e) => insert_bounded(
+                &self.failures,
+                url.to_string(),
+                (Instant::now(), e.to_string()),
+            ),
+        }
+        fetched
+    }
+
+    async fn fetch_uncached(&self, url: &str) -> Result<Value> {
+        check_url(url)?;
+        let mut response = self.client.get(url).send().await?.error_for_status()?;
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > MAX_DOCUMENT_BYTES {
+                bail!("{url} is larger than {MAX_DOCUMENT_BYTES} bytes");
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+/// Resolves host names to their public addresses only, so the URLs of a policy
+/// can't reach the server's own network
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_address(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Refuse `url` unless it's https, and public when its host is an address, as
+/// addresses aren't resolved
+fn check_url(url: &str) -> Result<()> {
+    let parsed = url::Url::parse(url)?;
+    if parsed.scheme() != "https" {
+        bail!("{url} isn't an https URL");
+    }
+    let ip = match parsed.host() {
+        Some(url::Host::Domain(_)) => return Ok(()),
+        Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+        None => bail!("{url} has no host"),
+    };
+    if !is_public_address(ip) {
+        bail!("{url} isn't on a public address");
+    }
+    Ok(())
+}
+
+/// Whether `ip` is reachable on the internet, rather than loopback, private,
+/// link-local or otherwise reserved
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                // Shared address space, benchmarking and IETF protocol assignments
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (18..20).contains(&b))
+                || (a == 192 && b == 0 && c == 0))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_address(IpAddr::V4(ip));
+            }
+            let [first, second, ..] = ip.segments();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local, link-local and documentation
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+                || (first == 0x2001 && second == 0x0db8))
+        }
+    }
+}
+
+/// Insert into a cache of at most [`MAX_CACHED`] entries, dropping any other entry
+/// to make room
+fn insert_bounded<V>(cache: &DashMap<String, V>, key: String, value: V) {
+    if cache.len() >= MAX_CACHED && !cache.contains_key(&key) {
+        let evicted = cache.iter().next().map(|entry| entry.key().clone());
+        if let Some(evicted) = evicted {
+            cache.remove(&evicted);
+        }
+    }
+    cache.insert(key, value);
+}
+
+/// The key of `keys` with id `kid`, or the only key when the token names none
+fn find_key<'a>(keys: &'a [Jwk], kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => keys
+            .iter()
+            .find(|key| key.common.key_id.as_deref() == Some(kid)),
+        None if keys.len() == 1 => keys.first(),
+        None => None,
+    }
+}
+
+/// Claims of `token` once verified against `policy` and `keys`
+fn verify(policy: &JwtAuthPolicy, keys: &[Jwk], token: &str) -> Result<Map<String, Value>, String> {
+    let header = jsonwebtoken::decode_header(token).map_err(|e| format!("malformed token: {e}"))?;
+    if !ALGORITHMS.contains(&header.alg) {
+        return Err(format!("algorithm {:?} isn't accepted", header.alg));
+    }
+    let jwk = find_key(keys, header.kid.as_deref())
+        .ok_or_else(|| "token signed with an unknown key".to_string())?;
+    if jwk.common.algorithm.is_some_and(|alg| alg != header.alg) {
+        return Err("token algorithm doesn't match its key".to_string());
+    }
+    let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("unusable key: {e}"))?;
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&policy.issuer]);
+    if !policy.audiences.is_empty() {
+        validation.set_audience(&policy.audiences);
+    }
+    jsonwebtoken::decode::<Map<String, Value>>(token, &key, &validation)
+        .map(|data| data.claims)
+        .map_err(|e| format!("invalid token: {e}"))
+}
+
+fn strip_claim_headers<B>(req: &mut Request<B>) {
+    req.headers_mut().remove(SUBJECT_HEADER);
+    req.headers_mut().remove(CLAIMS_HEADER);
+}
+
+fn set_claim_headers<B>(req: &mut Request<B>, claims: &Map<String, Value>) {
+    let headers = req.headers_mut();
+    if let Some(subject) = claims.get("sub").and_then(Value::as_str) {
+        if let Ok(value) = HeaderValue::from_str(subject) {
+            headers.insert(SUBJECT_HEADER, value);
+        }
+    }
+    let encoded = URL_SAFE_NO_PAD.encode(Value::Object(claims.clone()).to_string());
+    if encoded.len() <= MAX_CLAIMS_HEADER {
+        if let Ok(value) = HeaderValue::from_str(&encoded) {
+            headers.insert(CLAIMS_HEADER, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_store::SledStore;
+    use jsonwebtoken::{EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::json;
+
+    const ISSUER: &str = "https://accounts.example.com";
+    const JWKS_URL: &str = "https://accounts.example.com/jwks";
+
+    /// A signing key and its JWK
+    fn key_pair(kid: &str) -> (EncodingKey, Jwk) {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        // An uncompressed point: 0x04, then x and y
+        let point = pair.public_key().as_ref();
+        let jwk = serde_json::from_value(json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": kid,
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+        }))
+        .unwrap();
+        (EncodingKey::from_ec_der(pkcs8.as_ref()), jwk)
+    }
+
+    fn token(key: &EncodingKey, kid: &str, claims: Value) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(kid.to_string());
+        jsonwebtoken::encode(&header, &claims, key).unwrap()
+    }
+
+    fn request(token: Option<&str>, path: &str) -> Request<()> {
+        let mut builder = Request::builder()
+            .uri(path)
+            .header(SUBJECT_HEADER, "forged");
+        if let Some(token) = token {
+            builder = builder.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_only_valid_tokens_reach_the_function() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let auth = JwtAuth::new(Arc::new(SledStore::new(&db))).unwrap();
+        let (key, jwk) = key_pair("k1");
+        let (other_key, _) = key_pair("k1");
+        auth.jwks.insert(
+            JWKS_URL.to_string(),
+            CachedJwks {
+                keys: vec![jwk],
+                fetched: Instant::now(),
+            },
+        );
+        let policy = JwtAuthPolicy {
+            issuer: ISSUER.to_string(),
+            jwks_url: Some(JWKS_URL.to_string()),
+            audiences: vec!["api".to_string()],
+            public_routes: vec!["/health".to_string()],
+        };
+        let exp = chrono::Utc::now().timestamp() + 600;
+        let claims = json!({"iss": ISSUER, "aud": "api", "sub": "user-1", "exp": exp});
+
+        let mut req = request(Some(&token(&key, "k1", claims.clone())), "/items");
+        auth.authenticate(&policy, &mut req).await.unwrap();
+        assert_eq!(req.headers()[SUBJECT_HEADER], "user-1");
+        let passed = URL_SAFE_NO_PAD
+            .decode(req.headers()[CLAIMS_HEADER].as_bytes())
+            .unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&passed).unwrap(), claims);
+
+        // Public routes need no token, but still can't be given forged claims
+        let mut req = request(None, "/health/live");
+        auth.authenticate(&policy, &mut req).await.unwrap();
+        assert!(!req.headers().contains_key(SUBJECT_HEADER));
+
+        let refused = [
+            request(None, "/items"),
+            request(None, "/health/../items"),
+            request(None, "/health/%2E%2E/items"),
+            request(Some(&token(&other_key, "k1", claims.clone())), "/items"),
+            request(
+                Some(&token(
+                    &key,
+                    "k1",
+                    json!({"iss": "https://evil.example.com", "aud": "api", "exp": exp}),
+                )),
+                "/items",
+            ),
+            request(
+                Some(&token(
+                    &key,
+                    "k1",
+                    json!({"iss": ISSUER, "aud": "web", "exp": exp}),
+                )),
+                "/items",
+            ),
+            request(
+                Some(&token(
+                    &key,
+                    "k1",
+                    json!({"iss": ISSUER, "aud": "api", "exp": 1}),
+                )),
+                "/items",
+            ),
+        ];
+        for mut req in refused {
+            assert!(matches!(
+                auth.authenticate(&policy, &mut req).await,
+                Err(AuthFailure::Unauthorized(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_only_https_urls_on_public_addresses_are_fetched() {
+        for url in [
+            "http://accounts.example.com/jwks",
+            "https://127.0.0.1/jwks",
+            "https://10.0.0.8/jwks",
+            "https://169.254.169.254/latest",
+            "https://[::1]/jwks",
+            "https://[::ffff:192.168.0.1]/jwks",
+            "https://[fd00::1]/jwks",
+        ] {
+            assert!(check_url(url).is_err(), "{url}");
+        }
+        assert!(check_url("https://8.8.8.8/jwks").is_ok());
+        assert!(check_url(JWKS_URL).is_ok());
+        assert!(is_public_address("2606:4700::1111".parse().unwrap()));
+
+        // A host name that resolves to loopback isn't connected to, and the
+        // failure is kept
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let auth = JwtAuth::new(Arc::new(SledStore::new(&db))).unwrap();
+        let url = "https://localhost/jwks";
+        assert!(auth.fetch(url).await.is_err());
+        assert!(auth.failures.contains_key(url));
+    }
+
+    #[test]
+    fn test_symmetric_and_unsigned_tokens_are_refused() {
+        let policy = JwtAuthPolicy {
+            issuer: ISSUER.to_string(),
+            ..Default::default()
+        };
+        let secret: Jwk = serde_json::from_value(json!({"kty": "oct", "k": "c2VjcmV0"})).unwrap();
+        let claims = json!({"iss": ISSUER, "exp": chrono::Utc::now().timestamp() + 600});
+        let hs256 = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(verify(&policy, &[secret], &hs256)
+            .unwrap_err()
+            .contains("isn't accepted"));
+        let unsigned = format!(
+            "{}.{}.",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        assert!(verify(&policy, &[], &unsigned).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! Functions kept warm.
+//!
+//! Owners mark latency-sensitive functions "keep warm" when publishing them. The
+//! server hydrates and pre-instantiates those functions at startup and again after
+//! every deploy, so their first request doesn't pay for loading the component.
+//! With instance reuse on (`--warm-instances`), an instance is also created ahead
+//! of that request and stays parked past the idle timeout. Kept-warm functions
+//! are never evicted from the artifact cache. Each owner may keep at most
+//! `--max-keep-warm` functions warm. The marks are kept in the metadata store, so
+//! every node of a cluster keeps the same functions warm.
+//!
+//! A function definition can also declare a keep-warm schedule, such as 5
+//! instances from 09:00 to 18:00 on weekdays. Every minute the scheduler works out
+//! how many instances each function's schedule wants and pre-instantiates up to
+//! that many; a function in one of its windows is kept warm like a marked one, and
+//! outside them its instances time out as usual, so nights and weekends cost
+//! nothing. A function with a schedule counts against `--max-keep-warm` like a
+//! marked one, and schedules need instance reuse to be on.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use faasta_interface::{FaastaError, FunctionResult, FunctionSpec};
+use once_cell::sync::{Lazy, OnceCell};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, error, info};
+
+use crate::events::{PlatformEvent, Subscriber};
+use crate::instance_pool::INSTANCE_POOL;
+use crate::metadata_store::{Cached, MetadataStore};
+use crate::rpc_service::internal_error;
+use crate::wasi_server::SERVER;
+
+/// Collection of the metadata store holding the functions kept warm, keyed by function name
+pub const KEEP_WARM_TREE: &str = "function_keep_warm";
+
+/// Global set of kept-warm functions, set at startup
+pub static KEEP_WARM: OnceCell<KeepWarm> = OnceCell::new();
+
+/// Instances the keep-warm schedules of functions in one of their windows want
+static SCHEDULED: Lazy<DashMap<String, u32>> = Lazy::new(DashMap::new);
+/// How often keep-warm schedules are evaluated
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct KeepWarm {
+    marked: Cached<()>,
+    /// Most functions each owner may keep warm
+    max_per_owner: usize,
+}
+
+impl KeepWarm {
+    pub fn new(store: Arc<dyn MetadataStore>, max_per_owner: usize) -> Result<Self> {
+        Ok(Self {
+            marked: Cached::new(store, KEEP_WARM_TREE, |_| Some(()))?,
+            max_per_owner,
+        })
+    }
+
+    pub fn is_warm(&self, name: &str) -> bool {
+        self.marked.get(name).is_some()
+    }
+
+    /// Every function kept warm, in name order
+    pub fn functions(&self) -> Vec<String> {
+        let mut functions: Vec<String> = self
+            .marked
+            .all()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        functions.sort();
+        functions
+    }
+
+    /// Check that `owner` may keep `name` warm on top of the functions it
+    /// already keeps warm
+    pub fn check_limit(&self, owner: &str, name: &str) -> FunctionResult<()> {
+        if self.is_warm(name) || has_schedule(name) {
+            return Ok(());
+        }
+        if self.used(owner)? >= self.max_per_owner {
+            return Err(FaastaError::QuotaExceeded {
+                quota: "functions kept warm".to_string(),
+                limit: self.max_per_owner as u64,
+            });
+        }
+        Ok(())
+    }
+
+    /// Functions of `owner` kept warm, marked or on a schedule
+    pub fn used(&self, owner: &str) -> FunctionResult<usize> {
+        let projects = SERVER
+            .get()
+            .unwrap()
+            .github_auth
+            .get_user_projects(owner)
+            .map_err(|e| internal_error(format!("Failed to read projects: {e}")))?;
+        Ok(projects
+            .iter()
+            .filter(|project| self.is_warm(project) || has_schedule(project))
+            .count())
+    }
+
+    /// Most functions an owner may keep warm
+    pub fn max_per_owner(&self) -> usize {
+        self.max_per_owner
+    }
+
+    pub fn set(&self, name: &str, enabled: bool) -> Result<()> {
+        if enabled {
+            self.marked.insert(name, (), Vec::new())?;
+        } else {
+            self.marked.remove(name)?;
+        }
+        Ok(())
+    }
+
+    /// Read the mark of `name` again, after it was moved or deleted
+    pub fn refresh(&self, name: &str) -> Result<()> {
+        self.marked.refresh(name)
+    }
+
+    /// Read every mark again, for functions other nodes marked or unmarked
+    pub fn reload(&self) -> Result<()> {
+        self.marked.reload()
+    }
+}
+
+/// Whether `version`, a function or its canary, is kept warm
+pub fn is_kept_warm(version: &str) -> bool {
+    KEEP_WARM
+        .get()
+        .is_some_and(|keep_warm| keep_warm.is_warm(version))
+        || scheduled_instances(version) > 0
+}
+
+/// Whether the spec applied to `name` declares a keep-warm schedule
+fn has_schedule(name: &str) -> bool {
+    SERVER
+        .get()
+        .and_then(|server| server.specs.get(name))
+        .is_some_and(|applied| !applied.spec.keep_warm.is_empty())
+}
+
+/// Check that `owner` may give `name` a keep-warm schedule
+pub fn check_schedule(owner: &str, name: &str) -> FunctionResult<()> {
+    if INSTANCE_POOL.get().is_none() {
+        return Err(FaastaError::InvalidInput(
+            "This server doesn't reuse instances, so it can't keep them warm".to_string(),
+        ));
+    }
+    KEEP_WARM
+        .get()
+        .ok_or_else(|| {
+            FaastaError::InvalidInput("This server doesn't keep functions warm".to_string())
+        })?
+        .check_limit(owner, name)
+}
+
+/// Instances the keep-warm schedule of `version` wants warm now (0 outside its
+/// windows)
+pub fn scheduled_instances(version: &str) -> u32 {
+    SCHEDULED.get(version).map_or(0, |instances| *instances)
+}
+
+/// Instances `spec`'s keep-warm schedule wants warm at `now`, the most any of its
+/// windows wants
+pub fn instances_at(spec: &FunctionSpec, now: DateTime<Utc>) -> u32 {
+    spec.keep_warm
+        .iter()
+        .filter_map(|schedule| schedule.instances_at(now))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Spawn the task scaling functions' warm instances to their schedules
+pub fn spawn_scheduler() {
+    tokio::spawn(async move {
+        let mut ticker = interval(SCHEDULE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let Some(server) = SERVER.get() else {
+                continue;
+            };
+            let now = Utc::now();
+            let wanted: Vec<(String, u32)> = server
+                .specs
+                .all()
+                .into_iter()
+                .map(|(name, applied)| {
+                    let instances = instances_at(&applied.spec, now);
+                    (name, instances)
+                })
+                .filter(|(_, instances)| *instances > 0)
+                .collect();
+
+            // Functions whose windows ended go back to timing out
+            SCHEDULED.retain(|name, _| wanted.iter().any(|(wanted, _)| wanted == name));
+            for (name, instances) in wanted {
+                if SCHEDULED.insert(name.clone(), instances) != Some(instances) {
+                    info!("Keeping {} instances of '{}' warm", instances, name);
+                }
+                if let Err(e) = server.prewarm_instances(&name, instances as usize).await {
+                    debug!("Failed to warm '{}' to its schedule: {}", name, e);
+                }
+            }
+        }
+    });
+}
+
+/// Hydrate and pre-instantiate `name` in the background
+pub fn spawn_warm(name: String) {
+    tokio::spawn(async move {
+        if let Err(e) = warm(&name).await {
+            error!("Failed to warm '{}': {}", name, e);
+        }
+    });
+}
+
+/// Warms kept-warm functions again after they are deployed
+pub struct WarmOnDeploy;
+
+impl Subscriber for WarmOnDeploy {
+    fn on_event(&self, event: &PlatformEvent) {
+        if let PlatformEvent::Deployed { function, .. } = event {
+            if is_kept_warm(function) {
+                spawn_warm(function.clone());
+            }
+        }
+    }
+}
+
+/// Warm every kept-warm function in the background, e.g. at startup
+pub fn spawn_warm_all() {
+    let Some(keep_warm) = KEEP_WARM.get() else {
+        return;
+    };
+    let functions = keep_warm.functions();
+    if functions.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        for name in &functions {
+            if let Err(e) = warm(name).await {
+                error!("Failed to warm '{}': {}", name, e);
+            }
+        }
+        info!("Warmed {} kept-warm functions", functions.len());
+    });
+}
+
+async fn warm(name: &str) -> Result<()> {
+    SERVER
+        .get()
+        .ok_or_else(|| anyhow!("Server not initialised"))?
+        .prewarm(name)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_store::SledStore;
+    use faasta_interface::WarmSchedule;
+
+    #[test]
+    fn test_flag_is_set_and_cleared() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let keep_warm = KeepWarm::new(Arc::new(SledStore::new(&db)), 2).unwrap();
+        keep_warm.set("api", true).unwrap();
+        keep_warm.set("cron", true).unwrap();
+        assert!(keep_warm.is_warm("api"));
+        assert_eq!(keep_warm.functions(), vec!["api", "cron"]);
+
+        keep_warm.set("api", false).unwrap();
+        assert!(!keep_warm.is_warm("api"));
+        assert_eq!(keep_warm.functions(), vec!["cron"]);
+    }
+
+    #[test]
+    fn test_schedules_keep_instances_warm_in_their_windows() {
+        let window = |days: Option<&str>, from: &str, to: &str, instances| WarmSchedule {
+            days: days.map(String::from),
+            from: from.to_string(),
+            to: to.to_string(),
+            utc_offset: Some("+05:00".to_string()),
+            instances,
+        };
+        let spec = FunctionSpec {
+            keep_warm: vec![
+                window(Some("mon-fri"), "09:00", "18:00", 5),
+                // Overnight batch on Friday night, into Saturday
+                window(Some("fri"), "22:00", "02:00", 2),
+            ],
+            ..Default::default()
+        };
+        // Times are UTC, five hours behind the schedule's
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+
+        // Monday 2026-10-12
+        assert_eq!(instances_at(&spec, at("2026-10-12T04:00:00Z")), 5);
+        assert_eq!(instances_at(&spec, at("2026-10-12T12:59:00Z")), 5);
+        assert_eq!(instances_at(&spec, at("2026-10-12T13:00:00Z")), 0);
+        assert_eq!(instances_at(&spec, at("2026-10-12T03:59:00Z")), 0);
+        // Friday 23:00 and Saturday 01:00 local, but not Saturday 02:00
+        assert_eq!(instances_at(&spec, at("2026-10-16T18:00:00Z")), 2);
+        assert_eq!(instances_at(&spec, at("2026-10-16T20:00:00Z")), 2);
+        assert_eq!(instances_at(&spec, at("2026-10-16T21:00:00Z")), 0);
+        assert_eq!(
+            instances_at(&FunctionSpec::default(), at("2026-10-12T06:00:00Z")),
+            0
+        );
+
+        assert!(window(Some("sat-mon"), "00:00", "00:00", 1)
+            .validate()
+            .is_ok());
+        assert!(window(Some("weekdays"), "09:00", "18:00", 1)
+            .validate()
+            .is_err());
+        assert!(window(None, "9:0", "18:00", 1).validate().is_err());
+        assert!(window(None, "09:00", "24:00", 1).validate().is_err());
+    }
+}
@@ -0,0 +1,501 @@
+//! What functions write to stdout and stderr, kept for querying.
+//!
+//! Each request's output is captured in memory (up to a limit, past which it is
+//! dropped rather than failing the function's writes) and stored line by line once
+//! the request is done. Lines are keyed by function, time and sequence number, so a
+//! query for a time range only reads that range. Queries filter by level, request
+//! id and substring, return bounded pages and hand out a cursor for the next one;
+//! they power `cargo faasta logs` and `GET /v1/logs/{function}`. Lines older than
+//! the retention period are purged.
+//!
+//! Lines below the server's default level are dropped. Owners can change the level
+//! of a function at runtime, say to `debug` while chasing a bug; a level more
+//! verbose than the default reverts after a time box, so it can't be forgotten and
+//! fill the disk.
+//!
+//! Lines are redacted before they are stored (see [`crate::redaction`]), with the
+//! rules the operator enforces and those the function's owner added.
+//!
+//! Lines stay on the node that served the request, but levels and redaction rules
+//! are kept in the metadata store, so every node of a cluster applies them.
+
+use anyhow::{anyhow, Result};
+use bincode::{Decode, Encode};
+use bytes::Bytes;
+use dashmap::DashMap;
+use faasta_interface::{
+    LogEntry, LogLevel, LogLevelSetting, LogPage, LogQuery, RedactionRules, RedactionSettings,
+};
+use once_cell::sync::OnceCell;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, error};
+use wasmtime_wasi::{OutputStream, Pollable, StdoutStream, StreamResult};
+
+use crate::metadata_store::{Cached, MetadataStore};
+use crate::redaction::Redactor;
+
+/// Sled tree holding log lines, keyed by function name, 0, timestamp (ms) and id
+const LOGS_TREE: &str = "function_logs";
+/// Collection of the metadata store holding log level overrides, keyed by function
+/// name
+pub const LOG_LEVELS_TREE: &str = "function_log_levels";
+/// Collection of the metadata store holding the redaction rules owners added, keyed
+/// by function name
+pub const REDACTION_TREE: &str = "function_log_redaction";
+/// Longest a function may log more verbosely than the default, in seconds
+pub const MAX_VERBOSE_SECS: u64 = 24 * 60 * 60;
+/// How long a more verbose level lasts if no duration is given, in seconds
+const DEFAULT_VERBOSE_SECS: u64 = 60 * 60;
+/// Most lines returned by one query
+pub const MAX_LOG_PAGE: u32 = 1000;
+/// Most stored lines one query reads before handing out a cursor to go on from
+const MAX_SCANNED_LINES: usize = 50_000;
+/// Output kept per stream and request; the rest is dropped
+const MAX_CAPTURED_BYTES: usize = 64 * 1024;
+/// Longer lines are cut to this many bytes
+const MAX_LINE_BYTES: usize = 4096;
+
+/// Global log store, set at startup
+pub static LOGS: OnceCell<LogStore> = OnceCell::new();
+
+/// A function's stdout or stderr during one request
+#[derive(Clone, Default)]
+pub struct OutputCapture {
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+impl OutputCapture {
+    pub fn contents(&self) -> Vec<u8> {
+        self.buffer.lock().unwrap().clone()
+    }
+
+    /// Forget what was captured, before a warm instance serves its next request
+    pub fn clear(&self) {
+        self.buffer.lock().unwrap().clear();
+    }
+}
+
+impl StdoutStream for OutputCapture {
+    fn stream(&self) -> Box<dyn OutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+impl OutputStream for OutputCapture {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let room = MAX_CAPTURED_BYTES.saturating_sub(buffer.len());
+        buffer.extend_from_slice(&bytes[..bytes.len().min(room)]);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(MAX_CAPTURED_BYTES)
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Pollable for OutputCapture {
+    async fn ready(&mut self) {}
+}
+
+/// A function's log level, set by its owner
+#[derive(Encode, Decode)]
+struct LevelOverride {
+    level: LogLevel,
+    /// Unix timestamp (seconds) the override ends, or 0 if it doesn't
+    expires_at: i64,
+}
+
+pub struct LogStore {
+    db: sled::Db,
+    tree: sled::Tree,
+    store: Arc<dyn MetadataStore>,
+    levels: Cached<Vec<u8>>,
+    /// How long lines are kept, in milliseconds
+    retention_ms: i64,
+    /// Level of functions without an override
+    default_level: LogLevel,
+    redaction: Cached<Vec<u8>>,
+    /// Rules applied to every function
+    enforced: RedactionRules,
+    enforced_redactor: Arc<Redactor>,
+    /// Compiled rules of functions with rules of their own, next to the stored
+    /// rules they were compiled from
+    redactors: DashMap<String, (Vec<u8>, Arc<Redactor>)>,
+}
+
+impl LogStore {
+    pub fn new(
+        db: &sled::Db,
+        store: Arc<dyn MetadataStore>,
+        retention: Duration,
+        default_level: LogLevel,
+        enforced: RedactionRules,
+    ) -> Result<Self> {
+        let enforced_redactor =
+            Arc::new(Redactor::new(&enforced).map_err(|e| anyhow!("Invalid redaction rule: {e}"))?);
+        Ok(Self {
+            db: db.clone(),
+            tree: db.open_tree(LOGS_TREE)?,
+            levels: Cached::new(store.clone(), LOG_LEVELS_TREE, |value| Some(value.to_vec()))?,
+            retention_ms: retention.as_millis() as i64,
+            default_level,
+            redaction: Cached::new(store.clone(), REDACTION_TREE, |value| Some(value.to_vec()))?,
+            store,
+            enforced,
+            enforced_redactor,
+            redactors: DashMap::new(),
+        })
+    }
+
+    /// The redaction rules of `function` and those enforced on every function
+    pub fn redaction(&self, function: &str) -> Result<RedactionSettings> {
+        let rules = match self.redaction.get(function) {
+            Some(value) => bincode::decode_from_slice(&value, bincode::config::standard())?.0,
+            None => RedactionRules::default(),
+        };
+        Ok(RedactionSettings {
+            function: rules,
+            enforced: self.enforced.clone(),
+        })
+    }
+
+    /// Replace the redaction rules `function` adds to the enforced ones, which must
+    /// have passed [`crate::redaction::validate`]
+    pub fn set_redaction(
+        &self,
+        function: &str,
+        rules: RedactionRules,
+    ) -> Result<RedactionSettings> {
+        if rules.headers.is_empty() && rules.patterns.is_empty() {
+            self.redaction.remove(function)?;
+        } else {
+            let encoded = bincode::encode_to_vec(&rules, bincode::config::standard())?;
+            self.redaction.insert(function, encoded.clone(), encoded)?;
+        }
+        self.redaction(function)
+    }
+
+    /// Compiled rules for `function`'s lines. They are looked up on every write, so
+    /// a rename or clone that moves the stored rules is picked up right away.
+    fn redactor(&self, function: &str) -> Result<Arc<Redactor>> {
+        let Some(value) = self.redaction.get(function) else {
+            self.redactors.remove(function);
+            return Ok(self.enforced_redactor.clone());
+        };
+        if let Some(cached) = self.redactors.get(function) {
+            if cached.0 == value {
+                return Ok(cached.1.clone());
+            }
+        }
+        let (rules, _): (RedactionRules, _) =
+            bincode::decode_from_slice(&value, bincode::config::standard())?;
+        let redactor = Arc::new(Redactor::with_rules(&self.enforced, &rules)?);
+        self.redactors
+            .insert(function.to_string(), (value, redactor.clone()));
+        Ok(redactor)
+    }
+
+    /// Least severe level stored for `function`, dropping its override once expired
+    pub fn level(&self, function: &str) -> Result<LogLevelSetting> {
+        let default = LogLevelSetting {
+            level: self.default_level,
+            expires_at: None,
+        };
+        let Some(value) = self.levels.get(function) else {
+            return Ok(default);
+        };
+        let (level_override, _): (LevelOverride, _) =
+            bincode::decode_from_slice(&value, bincode::config::standard())?;
+        let expires_at = (level_override.expires_at != 0)
+            .then(|| chrono::DateTime::from_timestamp(level_override.expires_at, 0))
+            .flatten();
+        if expires_at.is_some_and(|expires_at| expires_at <= chrono::Utc::now()) {
+            debug!("Log level of '{}' reverted to the default", function);
+            // Keep an override set meanwhile
+            self.store
+                .update(LOG_LEVELS_TREE, function, &mut |current| {
+                    Ok(current
+                        .filter(|current| *current != value)
+                        .map(<[u8]>::to_vec))
+                })?;
+            self.levels.refresh(function)?;
+            return Ok(default);
+        }
+        Ok(LogLevelSetting {
+            level: level_override.level,
+            expires_at: expires_at.map(|expires_at| expires_at.to_rfc3339()),
+        })
+    }
+
+    /// Store `function`'s lines from `level` up for `duration_secs` (0 for good), or
+    /// with `None` go back to the default. Levels more verbose than the default last
+    /// an hour unless told otherwise, and at most [`MAX_VERBOSE_SECS`].
+    pub fn set_level(
+        &self,
+        function: &str,
+        level: Option<LogLevel>,
+        duration_secs: u64,
+    ) -> Result<LogLevelSetting> {
+        let Some(level) = level else {
+            self.levels.remove(function)?;
+            return self.level(function);
+        };
+        let duration_secs = if level < self.default_level {
+            match duration_secs {
+                0 => DEFAULT_VERBOSE_SECS,
+                secs => secs.min(MAX_VERBOSE_SECS),
+            }
+        } else {
+            duration_secs
+        };
+        let expires_at = match duration_secs {
+            0 => 0,
+            secs => expiry(secs).ok_or_else(|| anyhow!("Duration of {secs}s is too long"))?,
+        };
+        let encoded = bincode::encode_to_vec(
+            LevelOverride { level, expires_at },
+            bincode::config::standard(),
+        )?;
+        self.levels.insert(function, encoded.clone(), encoded)?;
+        self.level(function)
+    }
+
+    /// Read the level and redaction rules of `function` again, after they were
+    /// moved or deleted
+    pub fn refresh(&self, function: &str) -> Result<()> {
+        self.levels.refresh(function)?;
+        self.redaction.refresh(function)
+    }
+
+    /// Read every function's level and redaction rules again, for those changed on
+    /// other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.levels.reload()?;
+        self.redaction.reload()
+    }
+
+    /// Store what a request of `function` wrote to stdout and stderr, followed by
+    /// `failure` if the request failed
+    pub fn append(
+        &self,
+        function: &str,
+        request_id: &str,
+        stdout: &[u8],
+        stderr: &[u8],
+        failure: Option<String>,
+    ) -> Result<()> {
+        let min_level = self.level(function)?.level;
+        let redactor = self.redactor(function)?;
+        // Redact before truncating, so a cut can't leave part of a secret behind
+        let clean = |line: &str| truncate(&redactor.redact(line));
+        let now = chrono::Utc::now();
+        let timestamp_ms = now.timestamp_millis();
+        let timestamp = now.to_rfc3339();
+
+        let stdout = String::from_utf8_lossy(stdout);
+        let stderr = String::from_utf8_lossy(stderr);
+        let lines = stdout
+            .lines()
+            .map(|line| (line_level(line, LogLevel::Info), clean(line)))
+            .chain(
+                stderr
+                    .lines()
+                    .map(|line| (line_level(line, LogLevel::Error), clean(line))),
+            )
+            .chain(failure.map(|failure| (LogLevel::Error, clean(&failure))))
+            .filter(|(level, message)| *level >= min_level && !message.trim().is_empty());
+
+        let mut batch = sled::Batch::default();
+        let mut count = 0;
+        for (level, message) in lines {
+            let entry = LogEntry {
+                timestamp: timestamp.clone(),
+                level,
+                request_id: request_id.to_string(),
+                message,
+            };
+            let key = log_key(function, timestamp_ms, self.db.generate_id()?);
+            batch.insert(
+                key,
+                bincode::encode_to_vec(&entry, bincode::config::standard())?,
+            );
+            count += 1;
+        }
+        if count > 0 {
+            self.tree.apply_batch(batch)?;
+        }
+        Ok(())
+    }
+
+    /// Lines `function` logged that match `query`, oldest first
+    pub fn query(&self, function: &str, query: &LogQuery) -> Result<LogPage> {
+        let prefix = function_prefix(function);
+        let start = match &query.cursor {
+            Some(cursor) => {
+                let mut key = hex::decode(cursor).map_err(|_| anyhow!("Invalid cursor"))?;
+                if !key.starts_with(&prefix) {
+                    return Err(anyhow!("Cursor belongs to another function"));
+                }
+                // Continue just after the last key already seen
+                key.push(0);
+                key
+            }
+            None => log_key(function, query.since.unwrap_or(0), 0),
+        };
+        let end = log_key(function, query.until.unwrap_or(i64::MAX), u64::MAX);
+        if start > end {
+            return Ok(LogPage {
+                entries: Vec::new(),
+                next_cursor: None,
+            });
+        }
+        let limit = query.limit.clamp(1, MAX_LOG_PAGE) as usize;
+        let grep = query.grep.as_deref().filter(|grep| !grep.is_empty());
+
+        let mut entries = Vec::new();
+        let mut last_key = None;
+        let mut scanned = 0;
+        for item in self.tree.range(start..=end) {
+            let (key, value) = item?;
+            scanned += 1;
+            last_key = Some(key);
+            let Ok((entry, _)) =
+                bincode::decode_from_slice::<LogEntry, _>(&value, bincode::config::standard())
+            else {
+                continue;
+            };
+            let matches = query.min_level.is_none_or(|level| entry.level >= level)
+                && query
+                    .request_id
+                    .as_ref()
+                    .is_none_or(|id| entry.request_id == *id)
+                && grep.is_none_or(|grep| entry.message.contains(grep));
+            if matches {
+                entries.push(entry);
+            }
+            if entries.len() == limit || scanned == MAX_SCANNED_LINES {
+                break;
+            }
+        }
+
+        // A page cut short by either bound may have more lines after it
+        let exhausted = entries.len() < limit && scanned < MAX_SCANNED_LINES;
+        Ok(LogPage {
+            entries,
+            next_cursor: last_key.filter(|_| !exhausted).map(hex::encode),
+        })
+    }
+
+    /// Lines older than the retention period, and the bytes they take up
+    pub fn expired(&self) -> Result<(usize, u64)> {
+        let cutoff = chrono::Utc::now().timestamp_millis() - self.retention_ms;
+        let mut expired = (0, 0);
+        for entry in self.tree.iter() {
+            let (key, value) = entry?;
+            if key_timestamp(&key).is_some_and(|timestamp| timestamp < cutoff) {
+                expired.0 += 1;
+                expired.1 += (key.len() + value.len()) as u64;
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Delete lines older than the retention period, returning how many went and
+    /// the bytes they took
+    pub fn purge_expired(&self) -> Result<(usize, u64)> {
+        let cutoff = chrono::Utc::now().timestamp_millis() - self.retention_ms;
+        let mut purged = (0, 0);
+        for entry in self.tree.iter() {
+            let (key, value) = entry?;
+            if key_timestamp(&key).is_some_and(|timestamp| timestamp < cutoff) {
+                self.tree.remove(&key)?;
+                purged.0 += 1;
+                purged.1 += (key.len() + value.len()) as u64;
+            }
+        }
+        Ok(purged)
+    }
+}
+
+/// Spawn a Tokio task that purges expired lines every `interval_secs` seconds
+pub fn spawn_periodic_purge(interval_secs: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            let Some(logs) = LOGS.get() else {
+                continue;
+            };
+            match logs.purge_expired() {
+                Ok((0, _)) => {}
+                Ok((purged, _)) => debug!("Purged {} expired log lines", purged),
+                Err(e) => error!("Failed to purge expired log lines: {}", e),
+            }
+        }
+    });
+}
+
+fn function_prefix(function: &str) -> Vec<u8> {
+    // Function names never contain a 0 byte, so one prefix can't contain another
+    let mut prefix = function.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+fn log_key(function: &str, timestamp_ms: i64, id: u64) -> Vec<u8> {
+    let mut key = function_prefix(function);
+    key.extend_from_slice(&(timestamp_ms.max(0) as u64).to_be_bytes());
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn key_timestamp(key: &[u8]) -> Option<i64> {
+    let timestamp = key
+        .len()
+        .checked_sub(16)
+        .map(|start| &key[start..start + 8])?;
+    Some(u64::from_be_bytes(timestamp.try_into().ok()?) as i64)
+}
+
+/// Level named at the start of a line, like `ERROR ...` or `[warn] ...`, or
+/// `default` if it doesn't name one
+fn line_level(line: &str, default: LogLevel) -> LogLevel {
+    let word: String = line
+        .trim_start()
+        .trim_start_matches('[')
+        .chars()
+        .take_while(char::is_ascii_alphabetic)
+        .collect();
+    word.parse().unwrap_or(default)
+}
+
+fn truncate(line: &str) -> String {
+    if line.len() <= MAX_LINE_BYTES {
+        return line.to_string();
+    }
+    let mut end = MAX_LINE_BYTES;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &line[..end])
+}
+
+/// Unix time `duration_secs` from now, none if that's past what a timestamp holds
+pub fn expiry(duration_secs: u64) -> Option<i64> {
+    i64::try_from(duration_secs)
+        .ok()
+        .and_then(|secs| chrono::Utc::now().timestamp().checked_add(secs))
+        .filter(|&time| chrono::DateTime::from_timestamp(time, 0).is_some())
+}
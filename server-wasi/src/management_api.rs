@@ -0,0 +1,531 @@
+//! JSON management API for infrastructure-as-code tools (Terraform/OpenTofu
+//! providers and the like), served under `/v1/functions` on the root domain.
+//!
+//! Every resource carries a strong `ETag`. Writes honour `If-Match` and
+//! `If-None-Match`, so a provider can refuse to overwrite changes it hasn't
+//! seen, and repeating a `PUT` is harmless: publishing WebAssembly with the
+//! digest that is already live, or settings that are already in effect, changes
+//! nothing. The operations go through the RPC service, so the same
+//! authentication, roles and audit log apply as for `cargo faasta`.
+//!
+//! - `GET /v1/functions` lists the caller's functions
+//! - `GET /v1/functions/{name}` reads one
+//! - `PUT /v1/functions/{name}[?team=...]` publishes the WebAssembly in the body
+//! - `DELETE /v1/functions/{name}` unpublishes it
+//! - `PUT /v1/functions/{name}/settings` replaces its log level and redaction rules
+//!
+//! The API shares the HTTPS listener with functions, which can't ask for client
+//! certificates, so it is turned off when the RPC listeners require one.
+
+use anyhow::Result;
+use bytes::Bytes;
+use faasta_interface::{
+    FaastaError, FunctionInfo, FunctionQuery, FunctionService, LogLevel, RedactionRules,
+};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::header::{ETAG, IF_MATCH, IF_NONE_MATCH};
+use hyper::{HeaderMap, Method, Request, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::error;
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+
+use crate::function_data;
+use crate::function_locks;
+use crate::rpc_service::{self, FunctionServiceImpl};
+use crate::wasi_server::{
+    authorization_token, error_response, peer_ip, read_artifact_body, text_response, SERVER,
+};
+
+/// Largest settings document accepted
+const MAX_SETTINGS_BODY: usize = 64 * 1024;
+
+/// Set when RPC clients must present a certificate, which HTTPS clients can't be
+/// asked for
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Refuse management requests, so a valid token alone never gets past a required
+/// client certificate
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// A function as the management API shows it
+#[derive(Clone, Debug, Serialize)]
+struct FunctionResource {
+    name: String,
+    owner: String,
+    published_at: String,
+    usage: String,
+    /// `sha256:<hex>` of the published WebAssembly, unknown for functions last
+    /// published before digests were recorded
+    digest: Option<String>,
+    log_level: String,
+    /// When a temporarily more verbose log level reverts (RFC 3339)
+    log_level_expires_at: Option<String>,
+    redaction: RedactionRules,
+}
+
+impl FunctionResource {
+    /// Strong validator of this representation
+    fn etag(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        format!("\"{}\"", &hex::encode(Sha256::digest(json))[..32])
+    }
+}
+
+/// Body of `PUT /v1/functions/{name}/settings`. Fields left out reset to the
+/// server's defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FunctionSettings {
+    #[serde(default)]
+    log_level: Option<String>,
+    #[serde(default)]
+    redaction: RedactionRules,
+}
+
+/// Entry point for `/v1/functions/...`, with `resource` the remaining path segments
+pub async fn handle_request(
+    req: Request<hyper::body::Incoming>,
+    resource: &[String],
+) -> Result<Response<HyperOutgoingBody>> {
+    if DISABLED.load(Ordering::Relaxed) {
+        return text_response(
+            403,
+            "The management API is disabled because this server requires client certificates; use the RPC service",
+        );
+    }
+    let token = match authorization_token(&req) {
+        Ok(token) => token,
+        Err(message) => return text_response(401, message),
+    };
+    let service = match rpc_service::create_service(peer_ip(&req)) {
+        Ok(service) => service,
+        Err(e) => {
+            error!("Failed to create function service: {}", e);
+            return text_response(500, "Internal server error");
+        }
+    };
+
+    let method = req.method().clone();
+    match (resource, &method) {
+        ([], &Method::GET) => list(&service, token).await,
+        ([name], &Method::GET) => get(&service, name, req.headers(), token).await,
+        // Writes hold the function's lock from checking their preconditions on, so
+        // two can't both pass the same one, nor an RPC call change it in between.
+        // Publishes take it once their body is in, so a slow upload holds up nobody.
+        ([name], &Method::PUT) => publish(&service, name, req, token).await,
+        ([name], &Method::DELETE) => {
+            let headers = req.headers();
+            function_locks::locked(name, delete(&service, name, headers, token)).await
+        }
+        ([name, settings], &Method::PUT) if settings == "settings" => {
+            function_locks::locked(name, update_settings(&service, name, req, token)).await
+        }
+        ([], _) | ([_], _) => text_response(405, "Method not allowed"),
+        ([_, settings], _) if settings == "settings" => text_response(405, "Method not allowed"),
+        _ => text_response(404, "Not found"),
+    }
+}
+
+async fn list(service: &FunctionServiceImpl, token: String) -> Result<Response<HyperOutgoingBody>> {
+    let functions = match service
+        .clone()
+        .list_functions(
+            tarpc::context::current(),
+            FunctionQuery::all(),
+            token.clone(),
+        )
+        .await
+    {
+        Ok(page) => page.functions,
+        Err(e) => return error_response(&e),
+    };
+    let mut resources = Vec::with_capacity(functions.len());
+    for info in functions {
+        match describe(service, info, &token).await {
+            Ok(resource) => resources.push(resource),
+            Err(e) => return error_response(&e),
+        }
+    }
+    json_response(200, &serde_json::json!({ "functions": resources }), None)
+}
+
+async fn get(
+    service: &FunctionServiceImpl,
+    name: &str,
+    headers: &HeaderMap,
+    token: String,
+) -> Result<Response<HyperOutgoingBody>> {
+    let resource = match load(service, name, &token).await {
+        Ok(Some(resource)) => resource,
+        Ok(None) => return error_response(&not_found(name)),
+        Err(e) => return error_response(&e),
+    };
+    let etag = resource.etag();
+    if header_str(headers, IF_NONE_MATCH).is_some_and(|value| etag_matches(value, &etag, true)) {
+        return Ok(Response::builder()
+            .status(304)
+            .header(ETAG, etag)
+            .body(empty_body())?);
+    }
+    json_response(200, &resource, Some(&etag))
+}
+
+async fn publish(
+    service: &FunctionServiceImpl,
+    name: &str,
+    req: Request<hyper::body::Incoming>,
+    token: String,
+) -> Result<Response<HyperOutgoingBody>> {
+    let team = req.uri().query().and_then(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "team")
+            .map(|(_, team)| team.into_owned())
+    });
+
+    let headers = req.headers().clone();
+    let wasm = match read_artifact_body(req).await {
+        Ok(wasm) => wasm,
+        Err(e) => return error_response(&e),
+    };
+    function_locks::locked(
+        name,
+        publish_locked(service, name, team, &headers, wasm, token),
+    )
+    .await
+}
+
+async fn publish_locked(
+    service: &FunctionServiceImpl,
+    name: &str,
+    team: Option<String>,
+    headers: &HeaderMap,
+    wasm: Vec<u8>,
+    token: String,
+) -> Result<Response<HyperOutgoingBody>> {
+    let current = match load(service, name, &token).await {
+        Ok(current) => current,
+        Err(e) => return error_response(&e),
+    };
+    if let Err(status) = check_preconditions(headers, current.as_ref()) {
+        return precondition_failed(status, current.as_ref());
+    }
+
+    if let Some(current) = &current {
+        if current.digest.as_deref() == Some(&function_data::artifact_digest(&wasm)) {
+            return json_response(200, current, Some(&current.etag()));
+        }
+    }
+
+    let service_impl = service.clone();
+    let context = tarpc::context::current();
+    let name = name.to_string();
+    let result = match team {
+        Some(team) => {
+            service_impl
+                .publish_to_team(context, wasm, name.clone(), team, token.clone())
+                .await
+        }
+        None => {
+            service_impl
+                .publish(context, wasm, name.clone(), token.clone())
+                .await
+        }
+    };
+    if let Err(e) = result {
+        return error_response(&e);
+    }
+
+    let status = if current.is_some() { 200 } else { 201 };
+    respond_with(service, &name, &token, status).await
+}
+
+async fn delete(
+    service: &FunctionServiceImpl,
+    name: &str,
+    headers: &HeaderMap,
+    token: String,
+) -> Result<Response<HyperOutgoingBody>> {
+    let current = match load(service, name, &token).await {
+        Ok(Some(current)) => current,
+        Ok(None) => return error_response(&not_found(name)),
+        Err(e) => return error_response(&e),
+    };
+    if let Err(status) = check_preconditions(headers, Some(&current)) {
+        return precondition_failed(status, Some(&current));
+    }
+
+    if let Err(e) = service
+        .clone()
+        .unpublish(tarpc::context::current(), name.to_string(), token)
+        .await
+    {
+        return error_response(&e);
+    }
+    Ok(Response::builder().status(204).body(empty_body())?)
+}
+
+async fn update_settings(
+    service: &FunctionServiceImpl,
+    name: &str,
+    req: Request<hyper::body::Incoming>,
+    token: String,
+) -> Result<Response<HyperOutgoingBody>> {
+    let current = match load(service, name, &token).await {
+        Ok(Some(current)) => current,
+        Ok(None) => return error_response(&not_found(name)),
+        Err(e) => return error_response(&e),
+    };
+    if let Err(status) = check_preconditions(req.headers(), Some(&current)) {
+        return precondition_failed(status, Some(&current));
+    }
+
+    let body = match Limited::new(req.into_body(), MAX_SETTINGS_BODY)
+        .collect()
+        .await
+    {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => return text_response(413, "Settings document too large"),
+    };
+    let settings: FunctionSettings = match serde_json::from_slice(&body) {
+        Ok(settings) => settings,
+        Err(e) => {
+            return error_response(&FaastaError::InvalidInput(format!("Invalid settings: {e}")))
+        }
+    };
+    let level = match settings.log_level.as_deref().map(str::parse::<LogLevel>) {
+        None => None,
+        Some(Ok(level)) => Some(level),
+        Some(Err(e)) => return error_response(&FaastaError::InvalidInput(e)),
+    };
+
+    // Setting the level again would restart the window of a verbose one
+    let level_unchanged = level.is_some_and(|level| {
+        level.to_string() == current.log_level && current.log_level_expires_at.is_none()
+    });
+    if !level_unchanged {
+        if let Err(e) = service
+            .clone()
+            .set_log_level(
+                tarpc::context::current(),
+                name.to_string(),
+                level,
+                0,
+                token.clone(),
+            )
+            .await
+        {
+            return error_response(&e);
+        }
+    }
+    if settings.redaction != current.redaction {
+        if let Err(e) = service
+            .clone()
+            .set_log_redaction(
+                tarpc::context::current(),
+                name.to_string(),
+                settings.redaction,
+                token.clone(),
+            )
+            .await
+        {
+            return error_response(&e);
+        }
+    }
+
+    respond_with(service, name, &token, 200).await
+}
+
+/// The current representation of `name`, or `None` if there is no such function
+async fn load(
+    service: &FunctionServiceImpl,
+    name: &str,
+    token: &str,
+) -> Result<Option<FunctionResource>, FaastaError> {
+    // Reading the log level checks the function exists and the caller may see it
+    match service
+        .clone()
+        .get_log_level(
+            tarpc::context::current(),
+            name.to_string(),
+            token.to_string(),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(FaastaError::NotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let info = service
+        .clone()
+        .list_functions(
+            tarpc::context::current(),
+            FunctionQuery::all(),
+            token.to_string(),
+        )
+        .await?
+        .functions
+        .into_iter()
+        .find(|info| info.name == name);
+    match info {
+        Some(info) => describe(service, info, token).await.map(Some),
+        None => Err(FaastaError::PermissionDenied(format!(
+            "You don't have permission to manage '{name}'"
+        ))),
+    }
+}
+
+async fn describe(
+    service: &FunctionServiceImpl,
+    info: FunctionInfo,
+    token: &str,
+) -> Result<FunctionResource, FaastaError> {
+    let level = service
+        .clone()
+        .get_log_level(
+            tarpc::context::current(),
+            info.name.clone(),
+            token.to_string(),
+        )
+        .await?;
+    let redaction = service
+        .clone()
+        .get_log_redaction(
+            tarpc::context::current(),
+            info.name.clone(),
+            token.to_string(),
+        )
+        .await?;
+    let digest =
+        function_data::published_digest(SERVER.get().unwrap().metadata.as_ref(), &info.name)
+            .map_err(|e| rpc_service::internal_error(format!("Failed to read digest: {e}")))?;
+    Ok(FunctionResource {
+        name: info.name,
+        owner: info.owner,
+        published_at: info.published_at,
+        usage: info.usage,
+        digest,
+        log_level: level.level.to_string(),
+        log_level_expires_at: level.expires_at,
+        redaction: redaction.function,
+    })
+}
+
+async fn respond_with(
+    service: &FunctionServiceImpl,
+    name: &str,
+    token: &str,
+    status: u16,
+) -> Result<Response<HyperOutgoingBody>> {
+    match load(service, name, token).await {
+        Ok(Some(resource)) => json_response(status, &resource, Some(&resource.etag())),
+        Ok(None) => error_response(&not_found(name)),
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Check `If-Match` and `If-None-Match` against the current representation,
+/// returning the status to fail with
+fn check_preconditions(headers: &HeaderMap, current: Option<&FunctionResource>) -> Result<(), u16> {
+    let etag = current.map(FunctionResource::etag);
+    if let Some(if_match) = header_str(headers, IF_MATCH) {
+        let matched = etag
+            .as_deref()
+            .is_some_and(|etag| etag_matches(if_match, etag, false));
+        if !matched {
+            return Err(412);
+        }
+    }
+    if let Some(if_none_match) = header_str(headers, IF_NONE_MATCH) {
+        let matched = etag
+            .as_deref()
+            .is_some_and(|etag| etag_matches(if_none_match, etag, true));
+        if matched {
+            return Err(412);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the `If-Match`/`If-None-Match` list `header` covers `etag`. Weak
+/// validators only match with the weak comparison `If-None-Match` uses.
+fn etag_matches(header: &str, etag: &str, weak: bool) -> bool {
+    header.split(',').map(str::trim).any(|candidate| {
+        if candidate == "*" {
+            return true;
+        }
+        match candidate.strip_prefix("W/") {
+            Some(candidate) => weak && candidate == etag,
+            None => candidate == etag,
+        }
+    })
+}
+
+fn precondition_failed(
+    status: u16,
+    current: Option<&FunctionResource>,
+) -> Result<Response<HyperOutgoingBody>> {
+    let json = serde_json::json!({
+        "success": false,
+        "error": "The function changed since it was last read",
+    });
+    json_response(
+        status,
+        &json,
+        current.map(FunctionResource::etag).as_deref(),
+    )
+}
+
+fn not_found(name: &str) -> FaastaError {
+    FaastaError::NotFound(format!("Function '{name}' not found"))
+}
+
+fn header_str(headers: &HeaderMap, name: hyper::header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn json_response<T: Serialize>(
+    status: u16,
+    value: &T,
+    etag: Option<&str>,
+) -> Result<Response<HyperOutgoingBody>> {
+    let body = Full::new(Bytes::from(serde_json::to_vec(value)?))
+        .map_err(|_| ErrorCode::InternalError(None))
+        .boxed();
+    let mut builder = Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json");
+    if let Some(etag) = etag {
+        builder = builder.header(ETAG, etag);
+    }
+    Ok(builder.body(HyperOutgoingBody::new(body))?)
+}
+
+fn empty_body() -> HyperOutgoingBody {
+    HyperOutgoingBody::new(
+        Full::new(Bytes::new())
+            .map_err(|_| ErrorCode::InternalError(None))
+            .boxed(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_etag_matching() {
+        let etag = "\"0123abcd\"";
+        assert!(etag_matches("\"0123abcd\"", etag, false));
+        assert!(etag_matches("\"ffff\", \"0123abcd\"", etag, false));
+        assert!(etag_matches("*", etag, false));
+        assert!(!etag_matches("\"ffff\"", etag, false));
+        // Weak validators never satisfy If-Match
+        assert!(!etag_matches("W/\"0123abcd\"", etag, false));
+        assert!(etag_matches("W/\"0123abcd\"", etag, true));
+    }
+}
@@ -1,28 +1,70 @@
 use dashmap::DashMap;
-use faasta_interface::{FunctionMetricsResponse, Metrics};
+use faasta_interface::{
+    AlertKind, FunctionMetricsResponse, FunctionStats, InvocationFailure, MemoryStats, Metrics,
+};
 use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
 use std::path::Path;
 use std::str;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::time::{interval, Duration as TokioDuration};
 use tracing::{debug, error, info};
 
+use crate::anomalies::ANOMALIES;
+use crate::events::{PlatformEvent, Subscriber};
+
 // Global metrics storage using DashMap for lock-free concurrent access
 pub static FUNCTION_METRICS: Lazy<DashMap<String, FunctionMetric>> = Lazy::new(DashMap::new);
 
 // Sled database for persistent storage
 pub static METRICS_DB: Lazy<sled::Db> = Lazy::new(|| {
+    // Tests never leave a database behind in the crate directory
+    if cfg!(test) {
+        return sled::Config::new()
+            .temporary(true)
+            .open()
+            .expect("Failed to open metrics database");
+    }
     let db_path = std::env::var("METRICS_DB_PATH").unwrap_or_else(|_| "./data/metrics".to_string());
     sled::open(db_path).expect("Failed to open metrics database")
 });
 
+/// Latest invocations of a function its latency percentiles are taken over
+pub const LATENCY_SAMPLES: usize = 1000;
+
+/// Latest invocations of a function, and of each of its instances, its memory
+/// trend is taken over
+pub const MEMORY_SAMPLES: usize = 100;
+
+/// Instances of a function whose memory is followed at once; the one measured
+/// least makes way for a new one
+const MEMORY_TRACKED_INSTANCES: usize = 16;
+
+/// Memory growth over a full window below which it's never flagged, as instances
+/// commonly grow by a page or two as they warm up
+const MIN_LEAK_GROWTH: u64 = 1024 * 1024;
+
+/// Tree of the metrics database counting each function's failed invocations
+const ERRORS_TREE: &str = "function_errors";
+
 #[derive(Debug)]
 pub struct FunctionMetric {
     pub function_name: String,
     pub total_time: AtomicU64,
     pub call_count: AtomicU64,
     pub last_called: AtomicU64,
+    /// Failed invocations since the last flush
+    pub errors: AtomicU64,
+    /// Durations in milliseconds of the latest [`LATENCY_SAMPLES`] invocations
+    latencies: Mutex<VecDeque<u32>>,
+    /// Linear memory in bytes the latest invocations grew to
+    memory: Mutex<MemorySamples>,
+    /// Whether the function's memory growth was flagged since the server started
+    memory_flagged: AtomicBool,
+    /// The latest failed invocation since the server started
+    last_error: Mutex<Option<InvocationFailure>>,
 }
 
 // Manual implementation of Clone for FunctionMetric
@@ -33,6 +75,11 @@ impl Clone for FunctionMetric {
             total_time: AtomicU64::new(self.total_time.load(Ordering::Relaxed)),
             call_count: AtomicU64::new(self.call_count.load(Ordering::Relaxed)),
             last_called: AtomicU64::new(self.last_called.load(Ordering::Relaxed)),
+            errors: AtomicU64::new(self.errors.load(Ordering::Relaxed)),
+            latencies: Mutex::new(self.latencies.lock().unwrap().clone()),
+            memory: Mutex::new(self.memory.lock().unwrap().clone()),
+            memory_flagged: AtomicBool::new(self.memory_flagged.load(Ordering::Relaxed)),
+            last_error: Mutex::new(self.last_error.lock().unwrap().clone()),
         }
     }
 }
@@ -45,20 +92,15 @@ impl FunctionMetric {
             .unwrap_or(Duration::from_secs(0))
             .as_millis() as u64;
 
-        // Try to load from sled if it exists
+        // Try to load from sled if it exists. The counters only hold calls since the
+        // last flush, which are added to the persisted totals.
         let metric = if let Ok(Some(data)) = METRICS_DB.get(function_name.as_bytes()) {
-            if let Ok(((total_time, call_count, last_called), _)) =
-                bincode::decode_from_slice::<(u64, u64, u64), bincode::config::Configuration>(
-                    &data,
-                    bincode::config::standard(),
-                )
+            if let Ok(((_, _, last_called), _)) = bincode::decode_from_slice::<
+                (u64, u64, u64),
+                bincode::config::Configuration,
+            >(&data, bincode::config::standard())
             {
-                Self {
-                    function_name,
-                    total_time: AtomicU64::new(total_time),
-                    call_count: AtomicU64::new(call_count),
-                    last_called: AtomicU64::new(last_called),
-                }
+                Self::default(function_name, last_called)
             } else {
                 Self::default(function_name, now)
             }
@@ -79,6 +121,11 @@ impl FunctionMetric {
             total_time: AtomicU64::new(0),
             call_count: AtomicU64::new(0),
             last_called: AtomicU64::new(now),
+            errors: AtomicU64::new(0),
+            latencies: Mutex::new(VecDeque::with_capacity(LATENCY_SAMPLES)),
+            memory: Mutex::new(MemorySamples::default()),
+            memory_flagged: AtomicBool::new(false),
+            last_error: Mutex::new(None),
         }
     }
 
@@ -94,6 +141,13 @@ impl FunctionMetric {
             .as_millis() as u64;
         self.last_called.store(now, Ordering::Relaxed);
 
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == LATENCY_SAMPLES {
+            latencies.pop_front();
+        }
+        latencies.push_back(duration_ms.min(u32::MAX as u64) as u32);
+        drop(latencies);
+
         // Log the metrics update with more detailed information
         debug!(
             "Recorded metrics for function '{}': duration={}ms, prev_total={}ms, new_total={}ms, prev_calls={}, new_calls={}",
@@ -108,86 +162,322 @@ impl FunctionMetric {
         // No immediate persistence; metrics will be flushed periodically
     }
 
-    // Method to flush this individual function's metrics to the database
-    pub fn flush_to_db(&self) {
-        // Load existing DB values
-        let existing = METRICS_DB
-            .get(self.function_name.as_bytes())
-            .unwrap_or(None);
-        let (db_total, db_calls, db_last) = if let Some(db_bytes) = existing {
-            if let Ok(((t, c, l), _)) = bincode::decode_from_slice::<
-                (u64, u64, u64),
-                bincode::config::Configuration,
-            >(&db_bytes, bincode::config::standard())
-            {
-                info!(
-                    "Found existing DB metrics for '{}': total={}ms, calls={}, last={}",
-                    self.function_name, t, c, l
+    /// Record the linear memory `instance` grew to serving an invocation, flagging
+    /// the function once the memory of one of its instances only ever grows
+    pub fn record_memory(&self, instance: u64, bytes: u64) {
+        let mut memory = self.memory.lock().unwrap();
+        memory.record(instance, bytes);
+        let stats = memory.stats();
+        drop(memory);
+
+        if stats.only_grows && !self.memory_flagged.swap(true, Ordering::Relaxed) {
+            if let Some(anomalies) = ANOMALIES.get() {
+                anomalies.raise_for_function(
+                    AlertKind::MemoryGrowth,
+                    &self.function_name,
+                    format!(
+                        "An instance's memory grew by {} bytes over {} invocations without ever shrinking",
+                        stats.growth_bytes, MEMORY_SAMPLES
+                    ),
                 );
-                (t, c, l)
-            } else {
-                info!(
-                    "Failed to decode existing DB metrics for '{}', using zeros",
-                    self.function_name
-                );
-                (0, 0, 0)
             }
-        } else {
-            info!(
-                "No existing DB metrics for '{}', using zeros",
-                self.function_name
-            );
-            (0, 0, 0)
-        };
+        }
+    }
 
-        // Add current in-memory values
-        let mem_total = self.total_time.load(Ordering::Relaxed);
-        let mem_calls = self.call_count.load(Ordering::Relaxed);
+    /// Latency percentiles (p50, p95, p99) over the latest invocations, and how
+    /// many invocations they cover
+    fn latency_percentiles(&self) -> ([Option<u64>; 3], u32) {
+        let mut sorted: Vec<u32> = self.latencies.lock().unwrap().iter().copied().collect();
+        sorted.sort_unstable();
+        (
+            [50.0, 95.0, 99.0].map(|p| percentile(&sorted, p)),
+            sorted.len() as u32,
+        )
+    }
+
+    /// Add this function's counters to the database and reset them. Each counter
+    /// is taken and zeroed in one step and merged into the stored value in
+    /// another, so calls recorded meanwhile and concurrent flushes aren't counted
+    /// twice or lost.
+    pub fn flush_to_db(&self) {
+        let mem_total = self.total_time.swap(0, Ordering::Relaxed);
+        let mem_calls = self.call_count.swap(0, Ordering::Relaxed);
         let mem_last = self.last_called.load(Ordering::Relaxed);
+        let mem_errors = self.errors.swap(0, Ordering::Relaxed);
 
         info!(
             "In-memory metrics for '{}': total={}ms, calls={}, last={}",
             self.function_name, mem_total, mem_calls, mem_last
         );
 
-        // Calculate combined values
-        let combined_total = db_total + mem_total;
-        let combined_calls = db_calls + mem_calls;
-        let combined_last = std::cmp::max(db_last, mem_last);
-
-        info!(
-            "Combined metrics for '{}': total={}ms, calls={}, last={}",
-            self.function_name, combined_total, combined_calls, combined_last
-        );
-
-        // Combine and persist
-        if let Ok(data) = bincode::encode_to_vec(
-            (combined_total, combined_calls, combined_last),
-            bincode::config::standard(),
-        ) {
-            match METRICS_DB.insert(self.function_name.as_bytes(), data) {
-                Ok(_) => info!(
-                    "Successfully persisted metrics for '{}'",
-                    self.function_name
-                ),
-                Err(e) => error!(
+        let merged = METRICS_DB.fetch_and_update(self.function_name.as_bytes(), |existing| {
+            let (db_total, db_calls, db_last) = existing
+                .and_then(|db_bytes| {
+                    bincode::decode_from_slice::<(u64, u64, u64), _>(
+                        db_bytes,
+                        bincode::config::standard(),
+                    )
+                    .ok()
+                })
+                .map_or((0, 0, 0), |(metrics, _)| metrics);
+            let combined = (
+                db_total.saturating_add(mem_total),
+                db_calls.saturating_add(mem_calls),
+                db_last.max(mem_last),
+            );
+            bincode::encode_to_vec(combined, bincode::config::standard()).ok()
+        });
+        match merged {
+            Ok(_) => info!(
+                "Successfully persisted metrics for '{}'",
+                self.function_name
+            ),
+            Err(e) => {
+                error!(
                     "Failed to persist metrics for '{}': {}",
                     self.function_name, e
-                ),
+                );
+                // Keep them for the next flush
+                self.total_time.fetch_add(mem_total, Ordering::Relaxed);
+                self.call_count.fetch_add(mem_calls, Ordering::Relaxed);
+            }
+        }
+
+        if mem_errors > 0 {
+            let merged = errors_tree().and_then(|tree| {
+                tree.fetch_and_update(self.function_name.as_bytes(), |existing| {
+                    let persisted = existing
+                        .and_then(|value| value.try_into().ok())
+                        .map_or(0, u64::from_be_bytes);
+                    Some(persisted.saturating_add(mem_errors).to_be_bytes().to_vec())
+                })
+            });
+            if let Err(e) = merged {
+                error!(
+                    "Failed to persist error count of '{}': {}",
+                    self.function_name, e
+                );
+                self.errors.fetch_add(mem_errors, Ordering::Relaxed);
             }
-        } else {
-            error!("Failed to encode metrics for '{}'", self.function_name);
         }
     }
 }
 
+fn errors_tree() -> sled::Result<sled::Tree> {
+    METRICS_DB.open_tree(ERRORS_TREE)
+}
+
+/// Failed invocations of a function counted up to the last flush
+fn persisted_errors(function_name: &str) -> u64 {
+    errors_tree()
+        .and_then(|tree| tree.get(function_name.as_bytes()))
+        .ok()
+        .flatten()
+        .and_then(|value| Some(u64::from_be_bytes(value.as_ref().try_into().ok()?)))
+        .unwrap_or(0)
+}
+
+/// Nearest-rank percentile `p` of ascending `sorted`, `None` when it's empty
+fn percentile(sorted: &[u32], p: f64) -> Option<u64> {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted
+        .get(rank.saturating_sub(1).min(sorted.len().saturating_sub(1)))
+        .map(|&ms| ms as u64)
+}
+
+/// Memory trend of `samples`, oldest first. It only grows when a full window of
+/// invocations never shrank and grew by more than warming up explains.
+/// Memory of the latest invocations, overall and per instance
+#[derive(Clone, Debug, Default)]
+struct MemorySamples {
+    /// Latest [`MEMORY_SAMPLES`] invocations, whichever instance served them
+    recent: VecDeque<u64>,
+    /// Latest [`MEMORY_SAMPLES`] invocations of each followed instance, by id
+    instances: HashMap<u64, VecDeque<u64>>,
+    /// Instance that served the latest invocation
+    latest: Option<u64>,
+}
+
+impl MemorySamples {
+    fn record(&mut self, instance: u64, bytes: u64) {
+        push_bounded(&mut self.recent, bytes);
+        if !self.instances.contains_key(&instance)
+            && self.instances.len() >= MEMORY_TRACKED_INSTANCES
+        {
+            let least = self
+                .instances
+                .iter()
+                .min_by_key(|(_, samples)| samples.len())
+                .map(|(id, _)| *id);
+            if let Some(least) = least {
+                self.instances.remove(&least);
+            }
+        }
+        push_bounded(self.instances.entry(instance).or_default(), bytes);
+        self.latest = Some(instance);
+    }
+
+    fn stats(&self) -> MemoryStats {
+        let recent: Vec<u64> = self.recent.iter().copied().collect();
+        let instance: Vec<u64> = self
+            .latest
+            .and_then(|latest| self.instances.get(&latest))
+            .map(|samples| samples.iter().copied().collect())
+            .unwrap_or_default();
+        memory_stats(&recent, &instance)
+    }
+}
+
+fn push_bounded(samples: &mut VecDeque<u64>, bytes: u64) {
+    if samples.len() == MEMORY_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(bytes);
+}
+
+/// Stats of the `recent` invocations, with the trend of those one `instance`
+/// served, as only memory an instance keeps across invocations can leak
+fn memory_stats(recent: &[u64], instance: &[u64]) -> MemoryStats {
+    let Some(&latest) = recent.last() else {
+        return MemoryStats::default();
+    };
+    let growth = match (instance.first(), instance.last()) {
+        (Some(&first), Some(&last)) => last as i64 - first as i64,
+        _ => 0,
+    };
+    MemoryStats {
+        samples: recent.len() as u32,
+        latest_bytes: latest,
+        min_bytes: recent.iter().copied().min().unwrap_or(0),
+        max_bytes: recent.iter().copied().max().unwrap_or(0),
+        growth_bytes: growth,
+        only_grows: instance.len() == MEMORY_SAMPLES
+            && growth > MIN_LEAK_GROWTH as i64
+            && instance.windows(2).all(|pair| pair[0] <= pair[1]),
+    }
+}
+
+/// Count a failed invocation of `function_name`: one that trapped, timed out or
+/// never set a response
+pub fn record_error(function_name: &str, error: impl std::fmt::Display) {
+    if let Some(metric) = get_or_create_metric(function_name) {
+        metric.errors.fetch_add(1, Ordering::Relaxed);
+        *metric.last_error.lock().unwrap() = Some(InvocationFailure {
+            at: chrono::Utc::now().to_rfc3339(),
+            message: error.to_string(),
+        });
+    }
+}
+
+/// The latest failed invocation of `function_name` since the server started
+pub fn last_error(function_name: &str) -> Option<InvocationFailure> {
+    FUNCTION_METRICS
+        .get(function_name)?
+        .last_error
+        .lock()
+        .unwrap()
+        .clone()
+}
+
+/// Record the linear memory `instance` of `function_name` grew to serving an
+/// invocation
+pub fn record_memory(function_name: &str, instance: u64, bytes: u64) {
+    if let Some(metric) = get_or_create_metric(function_name) {
+        metric.record_memory(instance, bytes);
+    }
+}
+
+/// Records the memory of completed invocations
+pub struct MemorySubscriber;
+
+impl Subscriber for MemorySubscriber {
+    fn on_event(&self, event: &PlatformEvent) {
+        if let PlatformEvent::InvokeCompleted {
+            version,
+            instance,
+            memory_bytes,
+            ..
+        } = event
+        {
+            record_memory(version, *instance, *memory_bytes);
+        }
+    }
+}
+
+/// Counts an invocation as failed at most once, however many of the ways it can
+/// fail are seen
+pub struct InvocationErrors {
+    function_name: String,
+    counted: std::sync::atomic::AtomicBool,
+}
+
+impl InvocationErrors {
+    pub fn new(function_name: String) -> Self {
+        Self {
+            function_name,
+            counted: Default::default(),
+        }
+    }
+
+    pub fn record(&self, error: impl std::fmt::Display) {
+        if !self.counted.swap(true, Ordering::Relaxed) {
+            record_error(&self.function_name, error);
+        }
+    }
+}
+
+/// Invocation statistics of one function, combining what was flushed with the
+/// calls since. `None` if nothing was ever recorded for it.
+pub fn function_stats(function_name: &str) -> Option<FunctionStats> {
+    let persisted = METRICS_DB
+        .get(function_name.as_bytes())
+        .ok()
+        .flatten()
+        .and_then(|data| {
+            bincode::decode_from_slice::<(u64, u64, u64), _>(&data, bincode::config::standard())
+                .ok()
+        })
+        .map(|(metrics, _)| metrics);
+    let memory = FUNCTION_METRICS.get(function_name);
+    if persisted.is_none() && memory.is_none() {
+        return None;
+    }
+
+    let (_, db_calls, db_last) = persisted.unwrap_or((0, 0, 0));
+    let mut stats = FunctionStats {
+        function_name: function_name.to_string(),
+        invocations: db_calls,
+        errors: persisted_errors(function_name),
+        ..Default::default()
+    };
+    let mut last_called = db_last;
+    if let Some(metric) = memory {
+        stats.invocations += metric.call_count.load(Ordering::Relaxed);
+        stats.errors += metric.errors.load(Ordering::Relaxed);
+        last_called = last_called.max(metric.last_called.load(Ordering::Relaxed));
+        let ([p50, p95, p99], samples) = metric.latency_percentiles();
+        (stats.p50_ms, stats.p95_ms, stats.p99_ms) = (p50, p95, p99);
+        stats.latency_samples = samples;
+        let memory = metric.memory.lock().unwrap();
+        stats.memory = (!memory.recent.is_empty()).then(|| memory.stats());
+    }
+    // A function is timestamped when its metrics are created, so only one that was
+    // called has been invoked
+    if stats.invocations > 0 {
+        let at = UNIX_EPOCH + Duration::from_millis(last_called);
+        stats.last_invoked = Some(chrono::DateTime::<chrono::Utc>::from(at).to_rfc3339());
+    }
+    Some(stats)
+}
+
 // Function to check if a function's WASM file exists
 fn function_wasm_exists(function_name: &str) -> bool {
     // Get the functions directory from environment or use default
     let functions_dir =
         std::env::var("FUNCTIONS_PATH").unwrap_or_else(|_| "./functions".to_string());
 
-    let wasm_filename = format!("{function_name}.wasm");
+    // The precompiled artifact is local even when the WASM itself is in object storage
+    let wasm_filename = format!("{function_name}.cwasm");
     let wasm_path = Path::new(&functions_dir).join(&wasm_filename);
 
     wasm_path.exists()
@@ -295,14 +585,16 @@ pub fn get_metrics() -> Metrics {
 }
 
 // Helper function to get or create a function metric
-pub fn get_or_create_metric(function_name: &str) -> Option<FunctionMetric> {
+pub fn get_or_create_metric(
+    function_name: &str,
+) -> Option<dashmap::mapref::one::Ref<'static, String, FunctionMetric>> {
     // Use entry API to reduce lock contention
     let entry = FUNCTION_METRICS.entry(function_name.to_string());
 
     match entry {
         dashmap::mapref::entry::Entry::Occupied(occupied) => {
-            // Return a clone of the existing metric
-            Some(FunctionMetric::new(occupied.key().clone()))
+            // Return the existing metric, so calls are recorded on it
+            Some(occupied.into_ref().downgrade())
         }
         dashmap::mapref::entry::Entry::Vacant(vacant) => {
             // First check if the function's WASM file exists
@@ -315,9 +607,6 @@ pub fn get_or_create_metric(function_name: &str) -> Option<FunctionMetric> {
             // Create the new metric
             let metric = FunctionMetric::new(function_name.to_string());
 
-            // Insert it into the map
-            vacant.insert(metric.clone());
-
             // New function added - ensure it's recorded in Sled DB even if no calls happen
             if !METRICS_DB
                 .contains_key(function_name.as_bytes())
@@ -345,11 +634,29 @@ pub fn get_or_create_metric(function_name: &str) -> Option<FunctionMetric> {
                 debug!("Added new function '{}' to metrics database", function_name);
             }
 
-            Some(metric)
+            // Insert it into the map
+            Some(vacant.insert(metric).downgrade())
         }
     }
 }
 
+/// Move a function's metrics to a new name, e.g. after a rename
+pub fn rename_function_metrics(old_name: &str, new_name: &str) -> sled::Result<()> {
+    if let Some((_, metric)) = FUNCTION_METRICS.remove(old_name) {
+        metric.flush_to_db();
+    }
+    FUNCTION_METRICS.remove(new_name);
+
+    if let Some(data) = METRICS_DB.remove(old_name.as_bytes())? {
+        METRICS_DB.insert(new_name.as_bytes(), data)?;
+    }
+    let errors = errors_tree()?;
+    if let Some(count) = errors.remove(old_name.as_bytes())? {
+        errors.insert(new_name.as_bytes(), count)?;
+    }
+    Ok(())
+}
+
 // Timer utility to measure function execution time
 pub struct Timer {
     start: SystemTime,
@@ -393,9 +700,10 @@ pub fn flush_metrics_to_db() {
         let function_name = &metric.function_name;
         let call_count = metric.call_count.load(Ordering::Relaxed);
         let total_time = metric.total_time.load(Ordering::Relaxed);
+        let errors = metric.errors.load(Ordering::Relaxed);
 
         // Skip if no calls were made since last flush
-        if call_count == 0 {
+        if call_count == 0 && errors == 0 {
             debug!(
                 "Skipping flush for function '{}' - no calls since last flush",
                 function_name
@@ -408,14 +716,9 @@ pub fn flush_metrics_to_db() {
             function_name, call_count, total_time
         );
 
-        // First flush this function's current metrics to the database
-        // using our helper method
+        // Persists the counters and resets them
         metric.flush_to_db();
 
-        // Then reset the in-memory counters
-        metric.total_time.store(0, Ordering::Relaxed);
-        metric.call_count.store(0, Ordering::Relaxed);
-
         // Don't reset last_called timestamp
         // This preserves when the function was last used even after resetting counters
 
@@ -448,3 +751,92 @@ pub fn spawn_periodic_flush(interval_secs: u64) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_use_the_nearest_rank() {
+        let sorted: Vec<u32> = (1..=100).collect();
+        assert_eq!(percentile(&sorted, 50.0), Some(50));
+        assert_eq!(percentile(&sorted, 95.0), Some(95));
+        assert_eq!(percentile(&sorted, 99.0), Some(99));
+        assert_eq!(percentile(&[7], 99.0), Some(7));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn test_memory_that_only_grows_is_flagged() {
+        let page = 64 * 1024;
+        let growing: Vec<u64> = (0..MEMORY_SAMPLES as u64).map(|i| (i + 1) * page).collect();
+        let stats = memory_stats(&growing, &growing);
+        assert!(stats.only_grows);
+        assert_eq!(
+            stats.growth_bytes,
+            (MEMORY_SAMPLES as i64 - 1) * page as i64
+        );
+
+        // A partial window, an instance that shrank once, or growth by warming up
+        // alone are not leaks
+        let partial = &growing[..MEMORY_SAMPLES - 1];
+        assert!(!memory_stats(partial, partial).only_grows);
+        let mut shrank = growing.clone();
+        shrank[50] = page;
+        assert!(!memory_stats(&shrank, &shrank).only_grows);
+        let warming: Vec<u64> = (0..MEMORY_SAMPLES as u64)
+            .map(|i| page * (1 + i / 50))
+            .collect();
+        assert!(!memory_stats(&warming, &warming).only_grows);
+        assert_eq!(memory_stats(&[], &[]).samples, 0);
+    }
+
+    #[test]
+    fn test_memory_is_followed_per_instance() {
+        let page = 64 * 1024;
+        // Two instances whose invocations interleave: one steady, one leaking
+        let mut samples = MemorySamples::default();
+        for i in 0..MEMORY_SAMPLES as u64 {
+            samples.record(1, 4 * page);
+            samples.record(2, (i + 1) * page);
+        }
+        let stats = samples.stats();
+        assert!(stats.only_grows);
+        assert_eq!(stats.samples, MEMORY_SAMPLES as u32);
+
+        // Fresh instances that each start small, but grow to more than the last one
+        // did, don't leak
+        let mut fresh = MemorySamples::default();
+        for i in 0..2 * MEMORY_SAMPLES as u64 {
+            fresh.record(100 + i, (i + 1) * page);
+        }
+        assert!(!fresh.stats().only_grows);
+        assert_eq!(fresh.instances.len(), MEMORY_TRACKED_INSTANCES);
+        // The trend shown is that of the instance serving the latest invocation
+        samples.record(1, 4 * page);
+        assert!(!samples.stats().only_grows);
+    }
+
+    #[test]
+    fn test_the_latest_failure_is_kept() {
+        let name = "test-latest-failure";
+        FUNCTION_METRICS.insert(
+            name.to_string(),
+            FunctionMetric::default(name.to_string(), 0),
+        );
+        assert!(last_error(name).is_none());
+
+        record_error(name, "Trapped: integer divide by zero");
+        record_error(name, "Timed out after 30s");
+        assert_eq!(last_error(name).unwrap().message, "Timed out after 30s");
+        assert_eq!(
+            FUNCTION_METRICS
+                .get(name)
+                .unwrap()
+                .errors
+                .load(Ordering::Relaxed),
+            2
+        );
+        FUNCTION_METRICS.remove(name);
+    }
+}
@@ -0,0 +1,467 @@
+//! Optional push of platform metrics to an external time-series database.
+//!
+//! Operators without a scraping setup can ship metrics off-box with Prometheus
+//! remote write or InfluxDB's line protocol. Every interval the function counters
+//! that changed since the last push are sent in batches, retried with backoff. A
+//! batch that still fails stays queued (up to a bound) for the next interval, so a
+//! short outage of the database loses no samples. With the default `none` sink
+//! nothing in this module runs.
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use futures::future::BoxFuture;
+use once_cell::sync::OnceCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, error, warn};
+
+use crate::metrics::get_metrics;
+
+/// Most samples kept queued while the database is unreachable; the oldest are dropped
+const MAX_PENDING_SAMPLES: usize = 100_000;
+/// Delay before the first retry of a failed batch, doubled for every further retry
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Global metrics exporter, only set when a sink is configured
+pub static METRICS_EXPORT: OnceCell<MetricsExporter> = OnceCell::new();
+
+/// Time-series databases selectable in server config
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MetricsSinkKind {
+    None,
+    /// Prometheus remote write (also Mimir, Thanos, VictoriaMetrics, ...)
+    Prometheus,
+    /// InfluxDB line protocol
+    Influxdb,
+}
+
+/// One value of one series
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub name: &'static str,
+    /// Labels other than the name, sorted by label name
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: i64,
+}
+
+/// A time-series database samples are pushed to
+pub trait MetricsSink: Send + Sync {
+    /// Short sink name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Write a batch of samples
+    fn push<'a>(&'a self, samples: &'a [Sample]) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Settings needed to build a [`MetricsSink`]
+#[derive(Clone, Debug)]
+pub struct MetricsExportConfig {
+    pub kind: MetricsSinkKind,
+    /// Remote write endpoint, or InfluxDB write URL including the database or bucket
+    pub url: Option<String>,
+    /// Username for basic auth; without one the token is sent as a bearer token
+    pub username: Option<String>,
+    pub token: Option<String>,
+    /// Most samples sent in one request
+    pub batch_size: usize,
+    /// Retries of a failed batch before it is left for the next interval
+    pub retries: u32,
+}
+
+/// Build the exporter selected in server config, or `None` if export is disabled
+pub fn build_exporter(config: &MetricsExportConfig) -> Result<Option<MetricsExporter>> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let url = || {
+        config
+            .url
+            .clone()
+            .ok_or_else(|| anyhow!("--metrics-export-url is required for metrics export"))
+    };
+    let auth = Auth {
+        username: config.username.clone(),
+        token: config.token.clone(),
+    };
+
+    let sink: Arc<dyn MetricsSink> = match config.kind {
+        MetricsSinkKind::None => return Ok(None),
+        MetricsSinkKind::Prometheus => Arc::new(PrometheusRemoteWrite {
+            client,
+            url: url()?,
+            auth,
+        }),
+        MetricsSinkKind::Influxdb => Arc::new(InfluxLineProtocol {
+            client,
+            url: url()?,
+            auth,
+        }),
+    };
+
+    Ok(Some(MetricsExporter {
+        sink,
+        batch_size: config.batch_size.max(1),
+        retries: config.retries,
+        pending: Mutex::new(VecDeque::new()),
+        exported: Mutex::new(HashMap::new()),
+    }))
+}
+
+pub struct MetricsExporter {
+    sink: Arc<dyn MetricsSink>,
+    batch_size: usize,
+    retries: u32,
+    /// Samples collected but not yet written
+    pending: Mutex<VecDeque<Sample>>,
+    /// Counters last queued per function, as (calls, compute milliseconds)
+    exported: Mutex<HashMap<String, (u64, u64)>>,
+}
+
+impl MetricsExporter {
+    pub fn sink_name(&self) -> &'static str {
+        self.sink.name()
+    }
+
+    /// Queue samples for every function whose counters changed since the last push
+    fn collect(&self) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut exported = self.exported.lock().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+
+        for function in get_metrics().function_metrics {
+            let counters = (function.call_count, function.total_time_millis);
+            if exported.get(&function.function_name) == Some(&counters) {
+                continue;
+            }
+            let labels = vec![("function", function.function_name.clone())];
+            pending.push_back(Sample {
+                name: "faasta_function_calls_total",
+                labels: labels.clone(),
+                value: counters.0 as f64,
+                timestamp_ms: now,
+            });
+            pending.push_back(Sample {
+                name: "faasta_function_compute_milliseconds_total",
+                labels,
+                value: counters.1 as f64,
+                timestamp_ms: now,
+            });
+            exported.insert(function.function_name, counters);
+        }
+
+        let overflow = pending.len().saturating_sub(MAX_PENDING_SAMPLES);
+        if overflow > 0 {
+            warn!("Dropping {} unsent metric samples", overflow);
+            pending.drain(..overflow);
+        }
+    }
+
+    /// Collect changed counters and write everything queued, batch by batch
+    pub async fn export(&self) -> Result<()> {
+        self.collect();
+        let mut sent = 0;
+        loop {
+            let batch: Vec<Sample> = {
+                let pending = self.pending.lock().unwrap();
+                pending.iter().take(self.batch_size).cloned().collect()
+            };
+            if batch.is_empty() {
+                break;
+            }
+            self.push_with_retry(&batch).await?;
+            self.pending.lock().unwrap().drain(..batch.len());
+            sent += batch.len();
+        }
+        if sent > 0 {
+            debug!("Exported {} metric samples to {}", sent, self.sink.name());
+        }
+        Ok(())
+    }
+
+    async fn push_with_retry(&self, batch: &[Sample]) -> Result<()> {
+        let mut delay = RETRY_BASE_DELAY;
+        for _ in 0..self.retries {
+            match self.sink.push(batch).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!(
+                        "Failed to push metrics to {}, retrying in {:?}: {}",
+                        self.sink.name(),
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+        self.sink.push(batch).await
+    }
+}
+
+/// Spawn a Tokio task that exports metrics every `interval_secs` seconds
+pub fn spawn_periodic_export(interval_secs: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            if let Some(exporter) = METRICS_EXPORT.get() {
+                if let Err(e) = exporter.export().await {
+                    error!(
+                        "Failed to export metrics, keeping them for the next attempt: {}",
+                        e
+                    );
+                }
+            }
+        }
+    });
+}
+
+/// Credentials sent with every write
+#[derive(Clone, Debug)]
+struct Auth {
+    username: Option<String>,
+    token: Option<String>,
+}
+
+impl Auth {
+    fn apply(&self, request: reqwest::RequestBuilder, scheme: &str) -> reqwest::RequestBuilder {
+        match (&self.username, &self.token) {
+            (Some(username), password) => request.basic_auth(username, password.as_ref()),
+            (None, Some(token)) => request.header("Authorization", format!("{scheme} {token}")),
+            (None, None) => request,
+        }
+    }
+}
+
+/// Prometheus remote write 1.0: a snappy-compressed protobuf `WriteRequest`
+pub struct PrometheusRemoteWrite {
+    client: reqwest::Client,
+    url: String,
+    auth: Auth,
+}
+
+impl MetricsSink for PrometheusRemoteWrite {
+    fn name(&self) -> &'static str {
+        "prometheus"
+    }
+
+    fn push<'a>(&'a self, samples: &'a [Sample]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let body = snap::raw::Encoder::new().compress_vec(&encode_write_request(samples))?;
+            let request = self
+                .client
+                .post(&self.url)
+                .header("Content-Type", "application/x-protobuf")
+                .header("Content-Encoding", "snappy")
+                .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                .body(body);
+            self.auth
+                .apply(request, "Bearer")
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Encode samples as a `prometheus.WriteRequest`, one time series per sample
+fn encode_write_request(samples: &[Sample]) -> Vec<u8> {
+    let mut request = Vec::new();
+    for sample in samples {
+        let mut series = Vec::new();
+        // `__name__` sorts before every other label
+        let name = std::iter::once(("__name__", sample.name));
+        let labels = sample
+            .labels
+            .iter()
+            .map(|(label, value)| (*label, value.as_str()));
+        for (label, value) in name.chain(labels) {
+            let mut encoded = Vec::new();
+            protobuf_bytes(&mut encoded, 1, label.as_bytes());
+            protobuf_bytes(&mut encoded, 2, value.as_bytes());
+            protobuf_bytes(&mut series, 1, &encoded);
+        }
+        let mut encoded = Vec::new();
+        // Sample.value is a double (field 1), Sample.timestamp an int64 (field 2)
+        protobuf_varint(&mut encoded, (1 << 3) | 1);
+        encoded.extend_from_slice(&sample.value.to_le_bytes());
+        protobuf_varint(&mut encoded, 2 << 3);
+        protobuf_varint(&mut encoded, sample.timestamp_ms as u64);
+        protobuf_bytes(&mut series, 2, &encoded);
+
+        protobuf_bytes(&mut request, 1, &series);
+    }
+    request
+}
+
+/// Append a length-delimited protobuf field
+fn protobuf_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    protobuf_varint(out, (field << 3) | 2);
+    protobuf_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn protobuf_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// InfluxDB line protocol, written to a 1.x `/write?db=` or 2.x `/api/v2/write?bucket=` URL
+pub struct InfluxLineProtocol {
+    client: reqwest::Client,
+    url: String,
+    auth: Auth,
+}
+
+impl MetricsSink for InfluxLineProtocol {
+    fn name(&self) -> &'static str {
+        "influxdb"
+    }
+
+    fn push<'a>(&'a self, samples: &'a [Sample]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let request = self
+                .client
+                .post(&self.url)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(encode_lines(samples));
+            self.auth
+                .apply(request, "Token")
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Encode samples as `name,label=value value=<f64> <nanoseconds>` lines
+fn encode_lines(samples: &[Sample]) -> String {
+    let mut lines = String::new();
+    for sample in samples {
+        lines.push_str(sample.name);
+        for (label, value) in &sample.labels {
+            lines.push(',');
+            lines.push_str(label);
+            lines.push('=');
+            lines.push_str(&escape_tag(value));
+        }
+        lines.push_str(&format!(
+            " value={} {}\n",
+            sample.value,
+            sample.timestamp_ms * 1_000_000
+        ));
+    }
+    lines
+}
+
+/// Escape the characters line protocol gives meaning to in tag values
+fn escape_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    fn sample(function: &str, value: f64) -> Sample {
+        Sample {
+            name: "faasta_function_calls_total",
+            labels: vec![("function", function.to_string())],
+            value,
+            timestamp_ms: 1_700_000_000_000,
+        }
+    }
+
+    #[test]
+    fn test_line_protocol_escapes_tag_values() {
+        assert_eq!(
+            encode_lines(&[sample("a b,c=d", 3.0)]),
+            "faasta_function_calls_total,function=a\\ b\\,c\\=d value=3 1700000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn test_remote_write_requests_are_protobuf() {
+        let mut varint = Vec::new();
+        protobuf_varint(&mut varint, 300);
+        assert_eq!(varint, [0xac, 0x02]);
+
+        let encoded = encode_write_request(&[sample("api", 1.0)]);
+        let name = b"\x0a\x08__name__\x12\x1bfaasta_function_calls_total";
+        let function = b"\x0a\x08function\x12\x03api";
+        let mut value = vec![0x09];
+        value.extend_from_slice(&1.0f64.to_le_bytes());
+        value.push(0x10);
+        protobuf_varint(&mut value, 1_700_000_000_000);
+
+        let mut series = vec![0x0a, name.len() as u8];
+        series.extend_from_slice(name);
+        series.extend_from_slice(&[0x0a, function.len() as u8]);
+        series.extend_from_slice(function);
+        series.extend_from_slice(&[0x12, value.len() as u8]);
+        series.extend_from_slice(&value);
+        let mut expected = vec![0x0a, series.len() as u8];
+        expected.extend_from_slice(&series);
+        assert_eq!(encoded, expected);
+    }
+
+    /// A sink failing while `down` is set, counting the samples it took
+    #[derive(Default)]
+    struct FlakySink {
+        down: AtomicBool,
+        written: AtomicUsize,
+    }
+
+    impl MetricsSink for FlakySink {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn push<'a>(&'a self, samples: &'a [Sample]) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                if self.down.load(Ordering::SeqCst) {
+                    anyhow::bail!("connection refused");
+                }
+                self.written.fetch_add(samples.len(), Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_samples_are_kept_until_written() {
+        let sink = Arc::new(FlakySink::default());
+        let exporter = MetricsExporter {
+            sink: sink.clone(),
+            batch_size: 2,
+            retries: 0,
+            pending: Mutex::new((0..5).map(|i| sample("api", i as f64)).collect()),
+            exported: Mutex::new(HashMap::new()),
+        };
+
+        sink.down.store(true, Ordering::SeqCst);
+        assert!(exporter.export().await.is_err());
+        let queued = exporter.pending.lock().unwrap().len();
+        assert!(queued >= 5);
+
+        sink.down.store(false, Ordering::SeqCst);
+        exporter.export().await.unwrap();
+        assert!(exporter.pending.lock().unwrap().is_empty());
+        assert!(sink.written.load(Ordering::SeqCst) >= queued);
+    }
+}
@@ -0,0 +1,495 @@
+//! Sampled guest profiling of canary versions.
+//!
+//! Owners opt a function in with `cargo faasta profiles NAME --sample-rate 0.05`.
+//! That share of the invocations of its canary (the version being previewed before
+//! promotion) then runs on a separate engine compiled with epoch interruption, and
+//! wasmtime's guest profiler samples the guest's stack every
+//! [`SAMPLE_INTERVAL`]. The profile, in the Firefox Profiler's format, is kept for
+//! download and shows as a flamegraph at <https://profiler.firefox.com>.
+//!
+//! Stable versions are never profiled: the main engine is compiled without epoch
+//! interruption so ordinary invocations pay nothing for it, and the profiling
+//! engine's epoch only ticks while a sampled invocation runs. A function keeps its
+//! latest few profiles, profiles over the server's size limit are dropped, and
+//! turning profiling off deletes them. Sample rates and the canaries compiled for
+//! the profiling engine are cached, the latter for a few functions at most.
+
+use anyhow::{Context, Result};
+use bincode::{Decode, Encode};
+use dashmap::DashMap;
+use faasta_interface::{GuestProfiles, ProfileInfo, UPLOAD_CHUNK_SIZE};
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::Thread;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, info};
+use wasmtime::component::{Component, Linker};
+use wasmtime::{Config, Engine, GuestProfiler, InstanceAllocationStrategy, Store, UpdateDeadline};
+use wasmtime_wasi_http::bindings::ProxyPre;
+
+use crate::storage::{wasm_key, ArtifactStorage};
+use crate::wasi_server::{build_linker, FaastaClientState};
+
+/// Sled tree holding each function's profiling settings and profile list, keyed by
+/// function name. A function has an entry while profiling is on.
+pub const PROFILE_INDEX_TREE: &str = "guest_profiles";
+/// Sled tree holding profile chunks, keyed by profile key and chunk index
+const PROFILE_DATA_TREE: &str = "guest_profile_data";
+
+/// Profiles kept per function; older ones are deleted as new ones are taken
+const MAX_PROFILES_PER_FUNCTION: usize = 5;
+/// Canaries kept compiled for the profiling engine; the one compiled first makes
+/// way for another
+const MAX_COMPILED: usize = 8;
+/// How often the guest's stack is sampled
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Global profiler, set at startup unless profiling is turned off
+pub static PROFILING: OnceCell<Profiling> = OnceCell::new();
+
+#[derive(Default, Encode, Decode)]
+struct Index {
+    sample_rate: f64,
+    /// Newest first
+    profiles: Vec<ProfileInfo>,
+}
+
+/// A canary compiled for the profiling engine
+struct Compiled {
+    /// Modification time of the canary's precompiled artifact it was compiled
+    /// alongside, so a new canary is compiled again
+    modified: SystemTime,
+    compiled_at: Instant,
+    component: Component,
+    pre: ProxyPre<FaastaClientState>,
+}
+
+/// The thread advancing the profiling engine's epoch, and how many sampled
+/// invocations it's advancing it for; it sleeps while there are none
+struct Ticker {
+    running: Arc<AtomicUsize>,
+    thread: Thread,
+}
+
+/// A profiler sampling a running invocation, which keeps the epoch ticking until
+/// it's dropped
+pub struct Sampler {
+    profiler: GuestProfiler,
+    _running: Running,
+}
+
+/// One of the sampled invocations the ticker counts
+struct Running(Arc<AtomicUsize>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct Profiling {
+    engine: Engine,
+    linker: Linker<FaastaClientState>,
+    storage: Arc<dyn ArtifactStorage>,
+    compiled: DashMap<String, Arc<Compiled>>,
+    ticker: Ticker,
+    /// Sample rate of each function that was asked for one, 0 when it's off
+    sample_rates: DashMap<String, f64>,
+    index: sled::Tree,
+    data: sled::Tree,
+    /// Largest profile kept, in bytes
+    max_profile: u64,
+    /// Serializes changes to the index
+    lock: Mutex<()>,
+}
+
+impl Profiling {
+    /// Set up profiling with an engine configured like `config`, plus the epoch
+    /// interruption sampling needs
+    pub fn new(
+        db: &sled::Db,
+        config: &Config,
+        storage: Arc<dyn ArtifactStorage>,
+        max_profile: u64,
+    ) -> Result<Self> {
+        let mut config = config.clone();
+        config.epoch_interruption(true);
+        // Profiled instances are few, so they don't take slots of the main pool
+        config.allocation_strategy(InstanceAllocationStrategy::OnDemand);
+        let engine = Engine::new(&config)?;
+
+        // Epochs only advance for this engine, as its stores are the ones sampled,
+        // and only while one of them runs
+        let weak = engine.weak();
+        let running = Arc::new(AtomicUsize::new(0));
+        let sampled = running.clone();
+        let thread = std::thread::Builder::new()
+            .name("profile-ticker".to_string())
+            .spawn(move || {
+                while let Some(engine) = weak.upgrade() {
+                    if sampled.load(Ordering::Relaxed) == 0 {
+                        drop(engine);
+                        std::thread::park();
+                        continue;
+                    }
+                    engine.increment_epoch();
+                    drop(engine);
+                    std::thread::sleep(SAMPLE_INTERVAL);
+                }
+            })?
+            .thread()
+            .clone();
+
+        Ok(Self {
+            linker: build_linker(&engine),
+            engine,
+            storage,
+            compiled: DashMap::new(),
+            ticker: Ticker { running, thread },
+            sample_rates: DashMap::new(),
+            index: db.open_tree(PROFILE_INDEX_TREE)?,
+            data: db.open_tree(PROFILE_DATA_TREE)?,
+            max_profile,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn load_index(&self, function: &str) -> Result<Option<Index>> {
+        self.index
+            .get(function.as_bytes())?
+            .map(|value| Ok(bincode::decode_from_slice(&value, bincode::config::standard())?.0))
+            .transpose()
+    }
+
+    fn save_index(&self, function: &str, index: &Index) -> Result<()> {
+        self.index.insert(
+            function.as_bytes(),
+            bincode::encode_to_vec(index, bincode::config::standard())?,
+        )?;
+        Ok(())
+    }
+
+    /// Whether this invocation of `function`'s canary should be profiled
+    pub fn sample(&self, function: &str) -> bool {
+        let sample_rate = match self.sample_rates.get(function) {
+            Some(sample_rate) => *sample_rate,
+            None => {
+                let sample_rate = match self.load_index(function) {
+                    Ok(index) => index.map_or(0.0, |index| index.sample_rate),
+                    Err(e) => {
+                        error!(
+                            "Failed to read the profiling settings of '{}': {}",
+                            function, e
+                        );
+                        return false;
+                    }
+                };
+                self.sample_rates.insert(function.to_string(), sample_rate);
+                sample_rate
+            }
+        };
+        sample_rate > 0.0 && rand::thread_rng().gen_bool(sample_rate.min(1.0))
+    }
+
+    /// Drop what's cached of `function`, after its settings were moved or deleted
+    pub fn forget(&self, function: &str) {
+        self.sample_rates.remove(function);
+        self.compiled.remove(function);
+    }
+
+    pub fn list(&self, function: &str) -> Result<GuestProfiles> {
+        Ok(match self.load_index(function)? {
+            Some(index) => GuestProfiles {
+                sample_rate: index.sample_rate,
+                profiles: index.profiles,
+            },
+            None => GuestProfiles::default(),
+        })
+    }
+
+    /// Profile `sample_rate` of `function`'s canary invocations, or stop profiling
+    /// it and delete its profiles at 0
+    pub fn set_sample_rate(&self, function: &str, sample_rate: f64) -> Result<GuestProfiles> {
+        {
+            let _lock = self.lock.lock().unwrap();
+            match self.load_index(function)? {
+                Some(index) if sample_rate <= 0.0 => {
+                    for info in &index.profiles {
+                        self.remove_data(&info.id)?;
+                    }
+                    self.index.remove(function.as_bytes())?;
+                    self.compiled.remove(function);
+                }
+                None if sample_rate <= 0.0 => {}
+                index => {
+                    let mut index = index.unwrap_or_default();
+                    index.sample_rate = sample_rate.min(1.0);
+                    self.save_index(function, &index)?;
+                }
+            }
+            self.sample_rates.remove(function);
+        }
+        self.list(function)
+    }
+
+    /// Chunk `index` of `function`'s profile `id`, empty past its end. `None` if
+    /// the function has no such profile.
+    pub fn chunk(&self, function: &str, id: &str, index: u32) -> Result<Option<Vec<u8>>> {
+        let known = self
+            .load_index(function)?
+            .is_some_and(|list| list.profiles.iter().any(|info| info.id == id));
+        let (true, Some(key)) = (known, parse_profile_id(id)) else {
+            return Ok(None);
+        };
+        Ok(Some(
+            self.data
+                .get(chunk_key(&key, index))?
+                .map(|chunk| chunk.to_vec())
+                .unwrap_or_default(),
+        ))
+    }
+
+    /// The canary of `function`, precompiled at `cwasm_path`, compiled for the
+    /// profiling engine, with a sampler for a store to sample it
+    pub async fn prepare(
+        &self,
+        function: &str,
+        version: &str,
+        cwasm_path: &Path,
+    ) -> Result<(ProxyPre<FaastaClientState>, Sampler)> {
+        let modified = std::fs::metadata(cwasm_path)?.modified()?;
+        let compiled = match self.compiled.get(function) {
+            Some(compiled) if compiled.modified == modified => compiled.clone(),
+            _ => {
+                let wasm = self
+                    .storage
+                    .get(&wasm_key(version))
+                    .await?
+                    .with_context(|| format!("No WebAssembly stored for '{version}'"))?;
+                let engine = self.engine.clone();
+                let component =
+                    tokio::task::spawn_blocking(move || Component::new(&engine, wasm)).await??;
+                let pre = ProxyPre::new(self.linker.instantiate_pre(&component)?)?;
+                let compiled = Arc::new(Compiled {
+                    modified,
+                    compiled_at: Instant::now(),
+                    component,
+                    pre,
+                });
+                if self.compiled.len() >= MAX_COMPILED && !self.compiled.contains_key(function) {
+                    let oldest = self
+                        .compiled
+                        .iter()
+                        .min_by_key(|entry| entry.compiled_at)
+                        .map(|entry| entry.key().clone());
+                    if let Some(oldest) = oldest {
+                        self.compiled.remove(&oldest);
+                    }
+                }
+                self.compiled.insert(function.to_string(), compiled.clone());
+                compiled
+            }
+        };
+        let profiler =
+            GuestProfiler::new_component(version, SAMPLE_INTERVAL, compiled.component.clone(), []);
+        Ok((compiled.pre.clone(), self.sampler(profiler)))
+    }
+
+    /// A sampler for `profiler`, waking the ticker if it's the only one
+    fn sampler(&self, profiler: GuestProfiler) -> Sampler {
+        if self.ticker.running.fetch_add(1, Ordering::Relaxed) == 0 {
+            self.ticker.thread.unpark();
+        }
+        Sampler {
+            profiler,
+            _running: Running(self.ticker.running.clone()),
+        }
+    }
+
+    /// Keep the profile of an invocation of `function`, if it's still profiled
+    pub fn record(
+        &self,
+        function: &str,
+        request_id: &str,
+        profiler: GuestProfiler,
+        duration: Duration,
+    ) -> Result<()> {
+        let mut profile = Vec::new();
+        profiler.finish(&mut profile)?;
+        if profile.len() as u64 > self.max_profile {
+            info!(
+                "Dropped a guest profile of '{}' of {} bytes, over the limit",
+                function,
+                profile.len()
+            );
+            return Ok(());
+        }
+
+        let now = chrono::Utc::now();
+        let key = profile_key(now.timestamp_millis());
+        let info = ProfileInfo {
+            id: profile_id(&key),
+            created_at: now.to_rfc3339(),
+            request_id: request_id.to_string(),
+            duration_ms: duration.as_millis() as u64,
+            size: profile.len() as u64,
+        };
+        for (index, chunk) in profile.chunks(UPLOAD_CHUNK_SIZE).enumerate() {
+            self.data.insert(chunk_key(&key, index as u32), chunk)?;
+        }
+
+        let _lock = self.lock.lock().unwrap();
+        let Some(mut index) = self.load_index(function)? else {
+            // Profiling was turned off while this invocation ran
+            return self.remove_data(&info.id);
+        };
+        info!("Took guest profile {} of '{}'", info.id, function);
+        index.profiles.insert(0, info);
+        for dropped in index
+            .profiles
+            .split_off(MAX_PROFILES_PER_FUNCTION.min(index.profiles.len()))
+        {
+            self.remove_data(&dropped.id)?;
+        }
+        self.save_index(function, &index)
+    }
+
+    fn remove_data(&self, id: &str) -> Result<()> {
+        let Some(key) = parse_profile_id(id) else {
+            return Ok(());
+        };
+        for entry in self.data.scan_prefix(key) {
+            let (key, _) = entry?;
+            self.data.remove(key)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Profiling {
+    fn drop(&mut self) {
+        // Lets the ticker find the engine gone and stop
+        self.ticker.thread.unpark();
+    }
+}
+
+/// Have `store`, made for the profiling engine, sampled by `sampler` until it's
+/// taken back out of the store's state
+pub fn start_sampling(store: &mut Store<FaastaClientState>, sampler: Sampler) {
+    store.data_mut().profiler = Some(sampler);
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(|mut store| {
+        if let Some(mut sampler) = store.data_mut().profiler.take() {
+            sampler.profiler.sample(&store, Duration::ZERO);
+            store.data_mut().profiler = Some(sampler);
+        }
+        Ok(UpdateDeadline::Continue(1))
+    });
+}
+
+/// Store the profile `store` was sampled into, if it was
+pub fn finish_sampling(
+    store: &mut Store<FaastaClientState>,
+    function: &str,
+    request_id: &str,
+    duration: Duration,
+) {
+    let (Some(sampler), Some(profiling)) = (store.data_mut().profiler.take(), PROFILING.get())
+    else {
+        return;
+    };
+    let Sampler { profiler, .. } = sampler;
+    if let Err(e) = profiling.record(function, request_id, profiler, duration) {
+        error!("Failed to store a guest profile of '{}': {}", function, e);
+    }
+}
+
+fn profile_key(created_ms: i64) -> Vec<u8> {
+    let mut key = created_ms.max(0).to_be_bytes().to_vec();
+    key.extend_from_slice(&rand::random::<u64>().to_be_bytes());
+    key
+}
+
+fn profile_id(key: &[u8]) -> String {
+    format!("prof_{}", hex::encode(key))
+}
+
+fn parse_profile_id(id: &str) -> Option<Vec<u8>> {
+    let key = hex::decode(id.strip_prefix("prof_")?).ok()?;
+    (key.len() == 16).then_some(key)
+}
+
+fn chunk_key(key: &[u8], index: u32) -> Vec<u8> {
+    let mut chunk_key = key.to_vec();
+    chunk_key.extend_from_slice(&index.to_be_bytes());
+    chunk_key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_profiling() -> Profiling {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let dir = std::env::temp_dir().join(format!("faasta-profiles-{}", rand::random::<u64>()));
+        let storage = crate::storage::build_storage(&crate::storage::StorageConfig {
+            kind: crate::storage::StorageKind::Filesystem,
+            functions_dir: dir,
+            s3_bucket: None,
+            s3_region: String::new(),
+            s3_endpoint: None,
+            s3_prefix: String::new(),
+            s3_access_key_id: None,
+            s3_secret_access_key: None,
+            s3_session_token: None,
+        })
+        .unwrap();
+        let mut config = Config::new();
+        config.async_support(true);
+        Profiling::new(&db, &config, storage, 1 << 20).unwrap()
+    }
+
+    #[test]
+    fn test_turning_profiling_off_deletes_profiles() {
+        let profiling = temp_profiling();
+        assert!(!profiling.sample("hello"));
+        profiling.set_sample_rate("hello", 1.0).unwrap();
+        assert!(profiling.sample("hello"));
+
+        let component = Component::new(&profiling.engine, "(component)").unwrap();
+        let profiler = GuestProfiler::new_component("hello", SAMPLE_INTERVAL, component, []);
+        profiling
+            .record("hello", "req_1", profiler, Duration::from_millis(3))
+            .unwrap();
+        let profiles = profiling.list("hello").unwrap().profiles;
+        assert_eq!(profiles.len(), 1);
+        let chunk = profiling
+            .chunk("hello", &profiles[0].id, 0)
+            .unwrap()
+            .unwrap();
+        assert!(serde_json::from_slice::<serde_json::Value>(&chunk).is_ok());
+
+        let off = profiling.set_sample_rate("hello", 0.0).unwrap();
+        assert_eq!(off.sample_rate, 0.0);
+        assert!(off.profiles.is_empty());
+        assert!(profiling
+            .chunk("hello", &profiles[0].id, 0)
+            .unwrap()
+            .is_none());
+        assert!(!profiling.sample("hello"));
+    }
+
+    #[test]
+    fn test_the_epoch_only_ticks_while_sampling() {
+        let profiling = temp_profiling();
+        let component = Component::new(&profiling.engine, "(component)").unwrap();
+        let profiler = GuestProfiler::new_component("hello", SAMPLE_INTERVAL, component, []);
+        let sampler = profiling.sampler(profiler);
+        assert_eq!(profiling.ticker.running.load(Ordering::Relaxed), 1);
+        drop(sampler);
+        assert_eq!(profiling.ticker.running.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,223 @@
+//! SLSA provenance of published artifacts.
+//!
+//! A client may attach an in-toto statement with a SLSA provenance predicate to an
+//! upload, bare or in a DSSE envelope (as `cosign attest` and the SLSA GitHub
+//! generator write it, alone or inside a Sigstore bundle). At publish time one of
+//! the statement's subjects must carry the artifact's SHA-256 digest. The document
+//! is then stored as uploaded, keyed by that digest, so it follows the artifact
+//! through canary promotions, renames and clones. Envelope signatures are kept
+//! for auditors but not checked here.
+//!
+//! Servers started with `--require-provenance` refuse artifacts without it.
+
+use anyhow::Result;
+use base64::Engine;
+use faasta_interface::ProvenanceInfo;
+use once_cell::sync::OnceCell;
+use serde_json::Value;
+
+/// Global provenance store, set at startup
+pub static PROVENANCE: OnceCell<ProvenanceStore> = OnceCell::new();
+
+/// Sled tree holding provenance documents, keyed by artifact digest
+const PROVENANCE_TREE: &str = "artifact_provenance";
+
+const DSSE_PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+const STATEMENT_TYPES: &[&str] = &[
+    "https://in-toto.io/Statement/v0.1",
+    "https://in-toto.io/Statement/v1",
+];
+const SLSA_PREDICATE_PREFIX: &str = "https://slsa.dev/provenance/";
+
+/// What a provenance document says, once it has been read
+#[derive(Debug)]
+pub struct Statement {
+    pub predicate_type: String,
+    pub builder_id: Option<String>,
+    /// SHA-256 digests (hex) of the statement's subjects
+    subjects: Vec<String>,
+}
+
+impl Statement {
+    /// Read a statement, bare or in a DSSE envelope or Sigstore bundle
+    pub fn parse(document: &[u8]) -> Result<Self, String> {
+        let value: Value = serde_json::from_slice(document)
+            .map_err(|e| format!("provenance is not valid JSON: {e}"))?;
+        let statement = unwrap_envelope(value)?;
+
+        let statement_type = statement["_type"].as_str().unwrap_or_default();
+        if !STATEMENT_TYPES.contains(&statement_type) {
+            return Err(format!(
+                "provenance is not an in-toto statement (_type '{statement_type}')"
+            ));
+        }
+        let predicate_type = statement["predicateType"].as_str().unwrap_or_default();
+        if !predicate_type.starts_with(SLSA_PREDICATE_PREFIX) {
+            return Err(format!(
+                "provenance predicate '{predicate_type}' is not SLSA provenance"
+            ));
+        }
+        let predicate = &statement["predicate"];
+        // SLSA v1 moved the builder under runDetails
+        let builder_id = predicate["runDetails"]["builder"]["id"]
+            .as_str()
+            .or_else(|| predicate["builder"]["id"].as_str())
+            .map(str::to_string);
+        let subjects = statement["subject"]
+            .as_array()
+            .map(|subjects| {
+                subjects
+                    .iter()
+                    .filter_map(|subject| subject["digest"]["sha256"].as_str())
+                    .map(str::to_ascii_lowercase)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            predicate_type: predicate_type.to_string(),
+            builder_id,
+            subjects,
+        })
+    }
+
+    /// Whether one of the subjects is the artifact with `digest` (`sha256:<hex>`)
+    pub fn covers(&self, digest: &str) -> bool {
+        digest
+            .strip_prefix("sha256:")
+            .is_some_and(|hex| self.subjects.iter().any(|subject| subject == hex))
+    }
+}
+
+/// The statement inside a DSSE envelope, or inside the envelope of a Sigstore
+/// bundle; anything else is taken to be the statement itself
+fn unwrap_envelope(value: Value) -> Result<Value, String> {
+    let envelope = match value.get("dsseEnvelope") {
+        Some(envelope) => envelope,
+        None if value.get("payloadType").is_some() => &value,
+        None => return Ok(value),
+    };
+    let payload_type = envelope["payloadType"].as_str().unwrap_or_default();
+    if payload_type != DSSE_PAYLOAD_TYPE {
+        return Err(format!("unexpected DSSE payload type '{payload_type}'"));
+    }
+    let payload = base64::engine::general_purpose::STANDARD
+        .decode(envelope["payload"].as_str().unwrap_or_default())
+        .map_err(|e| format!("DSSE payload is not base64: {e}"))?;
+    serde_json::from_slice(&payload).map_err(|e| format!("DSSE payload is not valid JSON: {e}"))
+}
+
+pub struct ProvenanceStore {
+    tree: sled::Tree,
+    /// Whether artifacts without provenance are refused
+    required: bool,
+}
+
+impl ProvenanceStore {
+    pub fn new(db: &sled::Db, required: bool) -> Result<Self> {
+        Ok(Self {
+            tree: db.open_tree(PROVENANCE_TREE)?,
+            required,
+        })
+    }
+
+    /// Check `document` against the artifact with `digest`, before it is published.
+    /// `Ok(None)` when there is no document and none is required.
+    pub fn verify(
+        &self,
+        digest: &str,
+        document: Option<&[u8]>,
+    ) -> Result<Option<Statement>, String> {
+        let Some(document) = document else {
+            return if self.required {
+                Err("This server only accepts artifacts with SLSA provenance".to_string())
+            } else {
+                Ok(None)
+            };
+        };
+        let statement = Statement::parse(document)?;
+        if !statement.covers(digest) {
+            return Err(format!(
+                "The provenance's subjects don't include the artifact ({digest})"
+            ));
+        }
+        Ok(Some(statement))
+    }
+
+    /// Keep `document` as the provenance of the artifact with `digest`
+    pub fn record(&self, digest: &str, document: &[u8]) -> Result<()> {
+        self.tree.insert(digest.as_bytes(), document)?;
+        Ok(())
+    }
+
+    /// Provenance recorded for the artifact with `digest`
+    pub fn get(&self, digest: &str) -> Result<Option<ProvenanceInfo>> {
+        let Some(document) = self.tree.get(digest.as_bytes())? else {
+            return Ok(None);
+        };
+        let statement = Statement::parse(&document).map_err(anyhow::Error::msg)?;
+        Ok(Some(ProvenanceInfo {
+            digest: digest.to_string(),
+            predicate_type: statement.predicate_type,
+            builder_id: statement.builder_id,
+            document: String::from_utf8_lossy(&document).into_owned(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:4f2a9c1e0b7d4a654f2a9c1e0b7d4a654f2a9c1e0b7d4a654f2a9c1e0b7d4a65";
+
+    fn statement(digest_hex: &str) -> String {
+        serde_json::json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{ "name": "fn.wasm", "digest": { "sha256": digest_hex } }],
+            "predicateType": "https://slsa.dev/provenance/v1",
+            "predicate": {
+                "runDetails": { "builder": { "id": "https://github.com/slsa-framework/slsa-github-generator" } }
+            }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_statement_must_cover_the_artifact() {
+        let hex = DIGEST.trim_start_matches("sha256:");
+        let parsed = Statement::parse(statement(hex).as_bytes()).unwrap();
+        assert!(parsed.covers(DIGEST));
+        assert_eq!(
+            parsed.builder_id.as_deref(),
+            Some("https://github.com/slsa-framework/slsa-github-generator")
+        );
+
+        let other = Statement::parse(statement(&"00".repeat(32)).as_bytes()).unwrap();
+        assert!(!other.covers(DIGEST));
+    }
+
+    #[test]
+    fn test_envelopes_are_unwrapped() {
+        let hex = DIGEST.trim_start_matches("sha256:");
+        let payload = base64::engine::general_purpose::STANDARD.encode(statement(hex));
+        let envelope = serde_json::json!({
+            "payloadType": DSSE_PAYLOAD_TYPE,
+            "payload": payload,
+            "signatures": [],
+        });
+        let bundle = serde_json::json!({ "dsseEnvelope": envelope.clone() });
+        for document in [envelope, bundle] {
+            let parsed = Statement::parse(document.to_string().as_bytes()).unwrap();
+            assert!(parsed.covers(DIGEST));
+        }
+    }
+
+    #[test]
+    fn test_only_slsa_statements_are_accepted() {
+        let mut value: Value = serde_json::from_str(&statement("00")).unwrap();
+        value["predicateType"] = "https://spdx.dev/Document".into();
+        assert!(Statement::parse(value.to_string().as_bytes()).is_err());
+        assert!(Statement::parse(b"{}").is_err());
+    }
+}
@@ -1,30 +1,103 @@
-use anyhow::{anyhow, Result};
+//! Listeners for the management RPCs.
+//!
+//! Clients normally connect over QUIC. Networks that drop UDP can use the same
+//! RPCs over TLS on TCP, which the server accepts on the same port number; both
+//! carry the same length-delimited Bincode frames.
+//!
+//! Operators of private deployments can also require a client certificate signed
+//! by their own CA on both listeners, on top of the token each call carries.
+
+use anyhow::{anyhow, bail, Result};
 use futures::StreamExt;
-use std::path::PathBuf;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use s2n_quic::provider::tls::rustls::Server as RustlsServer;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use tarpc::tokio_serde::formats::Bincode;
 use tarpc::{
     serde_transport as transport,
     server::{BaseChannel, Channel},
 };
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
 use tokio_util::codec::LengthDelimitedCodec;
-use tracing::{debug, info};
+use tokio_util::task::TaskTracker;
+use tracing::{debug, info, warn};
+
+/// Time a TCP client has to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time a draining RPC stream is given to write its last responses
+const RESPONSE_FLUSH_TIMEOUT: Duration = Duration::from_millis(200);
 
+use crate::capacity::CAPACITY;
 use crate::rpc_service;
+use crate::shutdown::SHUTDOWN;
+use crate::tls::CertResolver;
 use faasta_interface::FunctionService;
 
-/// Configures and starts a QUIC server for RPC communication
-pub async fn setup_quic_server(
-    tls_cert_path: PathBuf,
-    tls_key_path: PathBuf,
-    rpc_address: &str,
-) -> Result<()> {
+/// ALPN of the QUIC listener
+const QUIC_ALPN: &[u8] = b"h3";
+
+/// Server configuration for an RPC listener serving the certificates of
+/// `certificates`. With `client_ca`, it only accepts clients with a certificate
+/// chaining to a CA in that bundle. QUIC needs TLS 1.3 and the listener's ALPN.
+pub fn rpc_tls_config(
+    certificates: Arc<CertResolver>,
+    client_ca: Option<&Path>,
+    quic: bool,
+) -> Result<Arc<ServerConfig>> {
+    let mut provider = rustls::crypto::ring::default_provider();
+    if quic {
+        provider
+            .cipher_suites
+            .retain(|suite| suite.version() == &rustls::version::TLS13);
+    }
+    let provider = Arc::new(provider);
+    let builder = ServerConfig::builder_with_provider(provider.clone());
+    let builder = if quic {
+        builder.with_protocol_versions(&[&rustls::version::TLS13])?
+    } else {
+        builder.with_safe_default_protocol_versions()?
+    };
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(client_ca)
+                .map_err(|e| anyhow!("Failed to read client CA {}: {e}", client_ca.display()))?
+            {
+                roots.add(cert?)?;
+            }
+            if roots.is_empty() {
+                bail!("No certificates found in client CA {}", client_ca.display());
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(certificates);
+    if quic {
+        config.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+    }
+    Ok(Arc::new(config))
+}
+
+/// Configures and starts a QUIC server for RPC communication with `tls_config`
+pub async fn setup_quic_server(tls_config: Arc<ServerConfig>, rpc_address: &str) -> Result<()> {
     let addr = rpc_address
         .parse::<std::net::SocketAddr>()
         .map_err(|e| anyhow!("Invalid RPC address: {}", e))?;
 
     // Configure server with the TLS certs
     let quic_server = s2n_quic::Server::builder()
-        .with_tls((tls_cert_path.as_path(), tls_key_path.as_path()))
+        .with_tls(RustlsServer::from(tls_config))
         .map_err(|e| anyhow!("Failed to set up TLS: {:?}", e))?
         .with_io(addr)
         .map_err(|e| anyhow!("Failed to set up IO: {:?}", e))?
@@ -41,33 +114,128 @@ pub async fn setup_quic_server(
 
 /// Runs the RPC server that handles QUIC connections
 pub async fn run_rpc_server(mut quic_server: s2n_quic::Server) {
-    while let Some(mut connection) = quic_server.accept().await {
+    loop {
+        let mut connection = tokio::select! {
+            Some(connection) = quic_server.accept() => connection,
+            _ = SHUTDOWN.draining() => break,
+            else => break,
+        };
+        let Some(slot) = management_slot() else {
+            warn!("Out of connection slots, closing RPC connection");
+            continue;
+        };
+        let in_flight = SHUTDOWN.track();
         tokio::spawn(async move {
+            let (_slot, _in_flight) = (slot, in_flight);
             debug!("Accepted new connection");
+            let peer = connection.remote_addr().ok().map(|addr| addr.ip());
+
+            // The connection counts as in flight until its streams are closed too
+            let streams = TaskTracker::new();
+            loop {
+                let stream = tokio::select! {
+                    stream = connection.accept_bidirectional_stream() => stream,
+                    _ = SHUTDOWN.draining() => break,
+                };
+                let Ok(Some(stream)) = stream else {
+                    break;
+                };
+                debug!("Accepted new stream");
+                streams.spawn(serve_stream(stream, peer));
+            }
+            streams.close();
+            streams.wait().await;
+        });
+    }
+}
 
-            while let Ok(Some(stream)) = connection.accept_bidirectional_stream().await {
-                tokio::spawn(async move {
-                    debug!("Accepted new stream");
-                    let framed = LengthDelimitedCodec::builder().new_framed(stream);
-                    let transport = transport::new(framed, Bincode::default());
-
-                    let service =
-                        rpc_service::create_service().expect("Failed to create function service");
-
-                    // Process this connection
-                    // Use default configuration but with a longer context deadline
-                    let server_channel = BaseChannel::with_defaults(transport);
-
-                    // Use a reference to the service to call serve()
-                    server_channel
-                        .execute(service.serve())
-                        .for_each(|fut| {
-                            tokio::spawn(fut);
-                            async {}
-                        })
-                        .await;
-                });
+/// Runs the RPC server for clients that connect with TLS over TCP
+pub async fn run_tcp_rpc_server(listener: TcpListener, tls_acceptor: TlsAcceptor) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = SHUTDOWN.draining() => break,
+        };
+        let (tcp_stream, remote_addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept RPC connection: {}", e);
+                continue;
             }
+        };
+        let Some(slot) = management_slot() else {
+            warn!(
+                "Out of connection slots, closing RPC connection from {}",
+                remote_addr
+            );
+            continue;
+        };
+        let tls_acceptor = tls_acceptor.clone();
+        let in_flight = SHUTDOWN.track();
+        tokio::spawn(async move {
+            let (_slot, _in_flight) = (slot, in_flight);
+            let tls_stream =
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls_acceptor.accept(tcp_stream))
+                    .await
+                {
+                    Ok(Ok(tls_stream)) => tls_stream,
+                    Ok(Err(e)) => {
+                        debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("TLS handshake with {} timed out", remote_addr);
+                        return;
+                    }
+                };
+            debug!("Accepted new TCP connection");
+            serve_stream(tls_stream, Some(remote_addr.ip())).await;
         });
     }
 }
+
+/// A connection slot for an RPC client, `Some(None)` when connections aren't
+/// limited and `None` when none is free
+fn management_slot() -> Option<Option<tokio::sync::OwnedSemaphorePermit>> {
+    match CAPACITY.get() {
+        Some(capacity) => capacity.management_connection().map(Some),
+        None => Some(None),
+    }
+}
+
+/// Serve the RPCs sent over one stream. Once the server starts draining, the
+/// stream is closed as soon as the requests in flight on it are answered, so
+/// idle clients don't hold up the shutdown.
+async fn serve_stream<S>(stream: S, peer: Option<IpAddr>)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let framed = LengthDelimitedCodec::builder().new_framed(stream);
+    let transport = transport::new(framed, Bincode::default());
+
+    let service = rpc_service::create_service(peer).expect("Failed to create function service");
+
+    // Process this connection
+    // Use default configuration but with a longer context deadline
+    let server_channel = BaseChannel::with_defaults(transport);
+
+    let requests = TaskTracker::new();
+    let serving = server_channel.execute(service.serve()).for_each(|fut| {
+        requests.spawn(fut);
+        async {}
+    });
+    tokio::pin!(serving);
+    tokio::select! {
+        _ = &mut serving => return,
+        _ = SHUTDOWN.draining() => {}
+    }
+
+    requests.close();
+    tokio::select! {
+        _ = &mut serving => return,
+        _ = requests.wait() => {}
+    }
+    // The last responses are written as the channel is polled
+    let _ = tokio::time::timeout(RESPONSE_FLUSH_TIMEOUT, &mut serving).await;
+    debug!("Closed an RPC stream for the shutdown");
+}
@@ -0,0 +1,183 @@
+//! Readiness of instances created ahead of traffic.
+//!
+//! A function can do its expensive one-time initialization before its first
+//! request, with the `faasta-guest` crate. Components declaring so, with the
+//! crate's `faasta-readiness` custom section, are recorded at publish; instances
+//! created ahead of traffic for them, when they're kept warm, are sent readiness
+//! probes (`GET /.faasta/ready`) and parked for requests only once one is answered
+//! `204`. A probe answered `503` is sent again with backoff, for up to
+//! [`READY_TIMEOUT`]; an instance that isn't ready by then is dropped. Any other
+//! status counts as ready. Functions without the declaration are never probed.
+//!
+//! What a function logs while answering probes is stored under the request id
+//! `readiness-probe`. Clients' requests for the probe path are refused, so only the
+//! server can trigger initialization.
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::Request;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::time::{sleep, timeout, Instant};
+use tracing::error;
+use wasmparser::{Parser, Payload};
+use wasmtime_wasi_http::WasiHttpView;
+
+use crate::instance_pool::WarmInstance;
+use crate::logs::LOGS;
+use crate::metadata_store::{Change, MetadataStore};
+use crate::streaming::{self, BodyFaults};
+
+use faasta_guest::READINESS_SECTION;
+pub use faasta_guest::READY_PATH;
+
+/// Collection of the metadata store naming the functions whose component answers
+/// readiness probes, keyed by function name
+pub const READINESS_TREE: &str = "function_readiness_probes";
+
+/// Longest an instance may take to become ready
+pub const READY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Wait before the first repeated probe, doubled up to [`MAX_PROBE_INTERVAL`]
+const PROBE_INTERVAL: Duration = Duration::from_millis(50);
+const MAX_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+/// Request id a function's logs from probes are stored under
+const PROBE_REQUEST_ID: &str = "readiness-probe";
+
+/// What an instance answered a readiness probe with
+#[derive(Debug, PartialEq, Eq)]
+pub enum Probe {
+    /// Ready, or not answering probes
+    Ready,
+    /// Still initializing
+    Initializing,
+}
+
+impl Probe {
+    fn of(status: u16) -> Self {
+        if status == 503 {
+            Self::Initializing
+        } else {
+            Self::Ready
+        }
+    }
+}
+
+/// Whether the component `wasm` declares that it answers readiness probes
+pub fn declares_probes(wasm: &[u8]) -> bool {
+    Parser::new(0).parse_all(wasm).any(|payload| {
+        matches!(payload, Ok(Payload::CustomSection(section)) if section.name() == READINESS_SECTION)
+    })
+}
+
+/// Change recording whether `name` now runs a component answering probes
+pub fn record(name: &str, wasm: &[u8]) -> Change {
+    let key = name.to_string();
+    if declares_probes(wasm) {
+        Change::Insert {
+            collection: READINESS_TREE,
+            key,
+            value: Vec::new(),
+        }
+    } else {
+        Change::Remove {
+            collection: READINESS_TREE,
+            key,
+        }
+    }
+}
+
+/// Whether instances of `name` are probed before they're parked
+pub fn answers_probes(store: &dyn MetadataStore, name: &str) -> bool {
+    store.contains(READINESS_TREE, name).unwrap_or_else(|e| {
+        error!("Failed to read whether '{}' answers probes: {}", name, e);
+        false
+    })
+}
+
+/// Probe `instance` of `name` until it's ready, returning whether it became ready
+/// in time
+pub async fn wait_until_ready(name: &str, instance: &mut WarmInstance) -> Result<bool> {
+    let deadline = Instant::now() + READY_TIMEOUT;
+    let mut interval = PROBE_INTERVAL;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let probe = timeout(remaining, probe(instance))
+            .await
+            .map_err(|_| anyhow!("'{name}' didn't answer its readiness probe in time"));
+        store_logs(name, instance);
+        match probe?? {
+            Probe::Ready => return Ok(true),
+            Probe::Initializing if Instant::now() + interval >= deadline => return Ok(false),
+            Probe::Initializing => {
+                sleep(interval).await;
+                interval = (interval * 2).min(MAX_PROBE_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Send `instance` one readiness probe
+async fn probe(instance: &mut WarmInstance) -> Result<Probe> {
+    let body = Empty::<Bytes>::new().map_err(|never: Infallible| match never {});
+    let req = Request::get(READY_PATH).body(body)?;
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    let state = instance.store.data_mut();
+    let wasi_req = streaming::incoming_request(state, req, BodyFaults::default())?;
+    let wasi_resp_out = state.new_response_outparam(sender)?;
+    instance
+        .proxy
+        .wasi_http_incoming_handler()
+        .call_handle(&mut instance.store, wasi_req, wasi_resp_out)
+        .await?;
+    let resp = receiver
+        .await
+        .map_err(|_| anyhow!("Readiness probe got no response"))?
+        .map_err(|e| anyhow!("Readiness probe failed: {e:?}"))?;
+    Ok(Probe::of(resp.status().as_u16()))
+}
+
+/// Store what the instance logged during a probe, so the next request's logs
+/// start empty
+fn store_logs(name: &str, instance: &WarmInstance) {
+    let (stdout, stderr) = (instance.stdout.contents(), instance.stderr.contents());
+    if let (Some(logs), false) = (LOGS.get(), stdout.is_empty() && stderr.is_empty()) {
+        if let Err(e) = logs.append(name, PROBE_REQUEST_ID, &stdout, &stderr, None) {
+            error!("Failed to store logs of '{}': {}", name, e);
+        }
+    }
+    instance.stdout.clear();
+    instance.stderr.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_declared_components_are_probed() {
+        // A module with a `faasta-readiness` custom section, and one without
+        let mut declared = b"\0asm\x01\0\0\0".to_vec();
+        let payload = [
+            &[READINESS_SECTION.len() as u8][..],
+            READINESS_SECTION.as_bytes(),
+            READY_PATH.as_bytes(),
+        ]
+        .concat();
+        declared.push(0);
+        declared.push(payload.len() as u8);
+        declared.extend(payload);
+        assert!(declares_probes(&declared));
+        assert!(!declares_probes(b"\0asm\x01\0\0\0"));
+        assert!(!declares_probes(b"not wasm"));
+    }
+
+    #[test]
+    fn test_only_503_means_initializing() {
+        assert_eq!(Probe::of(204), Probe::Ready);
+        assert_eq!(Probe::of(503), Probe::Initializing);
+        // Functions without the guest crate answer probes like any request
+        assert_eq!(Probe::of(404), Probe::Ready);
+        assert_eq!(Probe::of(200), Probe::Ready);
+    }
+}
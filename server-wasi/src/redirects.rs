@@ -0,0 +1,82 @@
+use anyhow::Result;
+use bincode::{Decode, Encode};
+use std::sync::Arc;
+
+use crate::metadata_store::{Cached, MetadataStore};
+
+/// Collection of the metadata store holding redirects left behind by renamed
+/// functions, keyed by the old name
+const REDIRECTS_TREE: &str = "redirects";
+
+/// Longest redirect a rename may leave behind
+pub const MAX_REDIRECT_HOURS: u32 = 24 * 90;
+
+/// Requests for a renamed function's old name are redirected to its new name
+/// until `expires_at`. The old name stays reserved for the owner meanwhile.
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct Redirect {
+    pub target: String,
+    pub owner: String,
+    /// Unix timestamp (seconds) after which the redirect is dropped
+    pub expires_at: i64,
+}
+
+pub struct Redirects {
+    redirects: Cached<Redirect>,
+}
+
+impl Redirects {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Result<Self> {
+        Ok(Self {
+            redirects: Cached::new(store, REDIRECTS_TREE, |value| {
+                bincode::decode_from_slice(value, bincode::config::standard())
+                    .ok()
+                    .map(|(redirect, _)| redirect)
+            })?,
+        })
+    }
+
+    /// Redirect `from` to `target` for `hours`
+    pub fn add(&self, from: &str, target: &str, owner: &str, hours: u32) -> Result<()> {
+        let redirect = Redirect {
+            target: target.to_string(),
+            owner: owner.to_string(),
+            expires_at: chrono::Utc::now().timestamp() + i64::from(hours) * 3600,
+        };
+        let encoded = bincode::encode_to_vec(&redirect, bincode::config::standard())?;
+        self.redirects.insert(from, redirect, encoded)
+    }
+
+    /// The active redirect for `name`, for requests
+    pub fn active(&self, name: &str) -> Option<Redirect> {
+        self.redirects
+            .get(name)
+            .filter(|redirect| redirect.expires_at > chrono::Utc::now().timestamp())
+    }
+
+    /// The active redirect for `name`, dropping it if it has expired
+    pub fn lookup(&self, name: &str) -> Result<Option<Redirect>> {
+        let Some(redirect) = self.redirects.get(name) else {
+            return Ok(None);
+        };
+        if redirect.expires_at <= chrono::Utc::now().timestamp() {
+            self.redirects.remove(name)?;
+            return Ok(None);
+        }
+        Ok(Some(redirect))
+    }
+
+    /// Every redirect, keyed by old name, including expired ones not dropped yet
+    pub fn all(&self) -> Vec<(String, Redirect)> {
+        self.redirects.all()
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.redirects.remove(name).map(|_| ())
+    }
+
+    /// Read every redirect again, for changes made by other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.redirects.reload()
+    }
+}
@@ -0,0 +1,231 @@
+//! Pulling function components from OCI registries.
+//!
+//! Instead of uploading a component, a client may name an OCI artifact for the
+//! server to fetch, so builds can be promoted through a registry. Only registries
+//! on the server's allowlist are contacted, and redirects are only followed to
+//! hosts on it too, which keeps the server from being used to reach arbitrary
+//! hosts. The manifest's component layer is checked
+//! against the artifact size limit before it is downloaded, the download stops at
+//! the size the manifest announced, and the content must match its digest.
+
+use anyhow::{anyhow, bail, Result};
+use faasta_interface::oci::{
+    OciManifest, OciReference, RegistryAuth, RegistryCredentials, MANIFEST_MEDIA_TYPE,
+};
+use once_cell::sync::OnceCell;
+use reqwest::header::ACCEPT;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Response, Url};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tracing::debug;
+
+/// Global registry client, set at startup
+pub static REGISTRIES: OnceCell<RegistryClient> = OnceCell::new();
+
+/// Largest manifest read, in bytes
+const MAX_MANIFEST_BYTES: usize = 64 * 1024;
+/// Most redirects followed for one request
+const MAX_REDIRECTS: usize = 5;
+
+pub struct RegistryClient {
+    client: Client,
+    /// Registry hosts that may be pulled from; `*` allows any, and `*.example.com`
+    /// any host under example.com
+    allowed: Vec<String>,
+}
+
+impl RegistryClient {
+    /// A client for the comma-separated registry hosts in `allowed`
+    pub fn new(allowed: &str) -> Result<Self> {
+        let allowed: Vec<String> = allowed
+            .split(',')
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        // Registries send blob downloads to their CDN, which must be allowed too
+        let redirects = allowed.clone();
+        let client = Client::builder()
+            .timeout(Duration::from_secs(120))
+            .redirect(Policy::custom(move |attempt| {
+                let host = host_of(attempt.url());
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if allows(&redirects, &host) {
+                    attempt.follow()
+                } else {
+                    attempt.error(format!(
+                        "redirected to {host}, which is not an allowed registry"
+                    ))
+                }
+            }))
+            .build()?;
+        Ok(Self { client, allowed })
+    }
+
+    pub fn is_allowed(&self, registry: &str) -> bool {
+        allows(&self.allowed, registry)
+    }
+
+    /// Fetch the component of the artifact at `reference`. Fails without downloading
+    /// it if the component is larger than `max_bytes`.
+    pub async fn pull(
+        &self,
+        reference: &OciReference,
+        credentials: Option<&RegistryCredentials>,
+        max_bytes: u64,
+    ) -> Result<Vec<u8>> {
+        if !self.is_allowed(&reference.registry) {
+            bail!(
+                "Registry '{}' is not allowed on this server",
+                reference.registry
+            );
+        }
+        let base = reference.api_base();
+        let mut auth = RegistryAuth::new(credentials.cloned());
+
+        let manifest_url = format!("{base}/manifests/{}", reference.reference);
+        let response = auth
+            .send(&self.client, || {
+                self.client
+                    .get(&manifest_url)
+                    .header(ACCEPT, MANIFEST_MEDIA_TYPE)
+            })
+            .await?;
+        if !response.status().is_success() {
+            bail!(
+                "Registry returned {} for manifest {reference}",
+                response.status()
+            );
+        }
+        let manifest_bytes = read_limited(response, MAX_MANIFEST_BYTES as u64).await?;
+        if reference.is_digest() {
+            verify_digest(&manifest_bytes, &reference.reference)?;
+        }
+        let manifest: OciManifest = serde_json::from_slice(&manifest_bytes)
+            .map_err(|e| anyhow!("Invalid manifest for {reference}: {e}"))?;
+        let layer = manifest.component_layer().ok_or_else(|| {
+            anyhow!("{reference} is not a wasm artifact (no application/wasm layer)")
+        })?;
+        if layer.size > max_bytes {
+            return Err(SizeExceeded {
+                size: layer.size,
+                limit: max_bytes,
+            }
+            .into());
+        }
+
+        debug!(
+            "Pulling {} ({} bytes) from {reference}",
+            layer.digest, layer.size
+        );
+        let blob_url = format!("{base}/blobs/{}", layer.digest);
+        let response = auth
+            .send(&self.client, || self.client.get(&blob_url))
+            .await?;
+        if !response.status().is_success() {
+            bail!(
+                "Registry returned {} for blob {}",
+                response.status(),
+                layer.digest
+            );
+        }
+        let component = read_limited(response, layer.size).await?;
+        if component.len() as u64 != layer.size {
+            bail!(
+                "Layer {} is {} bytes, but the manifest announced {}",
+                layer.digest,
+                component.len(),
+                layer.size
+            );
+        }
+        verify_digest(&component, &layer.digest)?;
+        Ok(component)
+    }
+}
+
+/// The artifact's component is over the size limit
+#[derive(Debug)]
+pub struct SizeExceeded {
+    pub size: u64,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for SizeExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "component is {} bytes, limit is {}",
+            self.size, self.limit
+        )
+    }
+}
+
+impl std::error::Error for SizeExceeded {}
+
+/// Whether `host`, with its port if it has one, is on the allowlist `allowed`
+fn allows(allowed: &[String], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    allowed.iter().any(|allowed| {
+        allowed == "*"
+            || *allowed == host
+            || allowed
+                .strip_prefix('*')
+                .is_some_and(|domain| domain.starts_with('.') && host.ends_with(domain))
+    })
+}
+
+/// `host` or `host:port` of `url`
+fn host_of(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
+}
+
+/// Read a response body, failing once it exceeds `limit` bytes
+async fn read_limited(mut response: Response, limit: u64) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > limit {
+            bail!("Registry sent more than the expected {limit} bytes");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Check content against a `sha256:<hex>` digest
+fn verify_digest(content: &[u8], digest: &str) -> Result<()> {
+    let expected = digest
+        .strip_prefix("sha256:")
+        .ok_or_else(|| anyhow!("Unsupported digest algorithm in '{digest}'"))?;
+    let actual = hex::encode(Sha256::digest(content));
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!("Content does not match its digest {digest} (got sha256:{actual})");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_allowed_hosts_are_contacted() {
+        let client = RegistryClient::new("ghcr.io, *.docker.com,localhost:5000").unwrap();
+        assert!(client.is_allowed("ghcr.io"));
+        assert!(client.is_allowed("GHCR.io"));
+        assert!(client.is_allowed("production.cloudflare.docker.com"));
+        assert!(!client.is_allowed("docker.com"));
+        assert!(!client.is_allowed("evil-docker.com"));
+        assert!(client.is_allowed("localhost:5000"));
+        assert!(!client.is_allowed("localhost:6000"));
+        assert!(!client.is_allowed("ghcr.io.evil.com"));
+
+        let url = Url::parse("http://localhost:5000/v2/hello").unwrap();
+        assert_eq!(host_of(&url), "localhost:5000");
+        assert!(RegistryClient::new("*").unwrap().is_allowed("example.com"));
+    }
+}
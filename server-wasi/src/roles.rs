@@ -0,0 +1,137 @@
+//! Platform-wide roles.
+//!
+//! Admins named in the server config can grant [`PlatformRole`]s to users and
+//! teams. A user's effective role is their own grant, else the highest grant of
+//! any team they belong to, else the configured default. Ownership checks still
+//! apply on top: a deployer can only publish or delete functions they may manage.
+
+use anyhow::Result;
+use faasta_interface::{team_owner, PlatformRole, RoleGrant};
+use once_cell::sync::OnceCell;
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+use crate::metadata_store::MetadataStore;
+use crate::wasi_server::SERVER;
+
+/// Collection of the metadata store holding role grants, keyed by username or team
+/// owner (`team:<name>`)
+const ROLES_TREE: &str = "roles";
+
+/// Global role registry, set at startup
+pub static ROLES: OnceCell<Roles> = OnceCell::new();
+
+pub struct Roles {
+    store: Arc<dyn MetadataStore>,
+    /// Users who are always admins, and the role of users without any grant, from
+    /// the server config
+    configured: RwLock<(Vec<String>, PlatformRole)>,
+}
+
+impl Roles {
+    pub fn new(
+        store: Arc<dyn MetadataStore>,
+        admins: Vec<String>,
+        default_role: PlatformRole,
+    ) -> Self {
+        Self {
+            store,
+            configured: RwLock::new((admins, default_role)),
+        }
+    }
+
+    /// Replace the admins and default role from the server config
+    pub fn reconfigure(&self, admins: Vec<String>, default_role: PlatformRole) {
+        *self.configured.write().unwrap() = (admins, default_role);
+    }
+
+    /// The role explicitly granted to `principal`
+    pub fn grant(&self, principal: &str) -> Result<Option<PlatformRole>> {
+        let Some(value) = self.store.get(ROLES_TREE, principal)? else {
+            return Ok(None);
+        };
+        Ok(
+            bincode::decode_from_slice::<PlatformRole, _>(&value, bincode::config::standard())
+                .ok()
+                .map(|(role, _)| role),
+        )
+    }
+
+    /// Grant `role` to `principal`, or clear their grant
+    pub fn set_grant(&self, principal: &str, role: Option<PlatformRole>) -> Result<()> {
+        match role {
+            Some(role) => {
+                let encoded = bincode::encode_to_vec(role, bincode::config::standard())?;
+                self.store.insert(ROLES_TREE, principal, encoded)
+            }
+            None => self.store.remove(ROLES_TREE, principal),
+        }
+    }
+
+    /// Every explicit grant, plus the admins from the server config
+    pub fn grants(&self) -> Result<Vec<RoleGrant>> {
+        let admins = self.configured.read().unwrap().0.clone();
+        let configured = admins.into_iter().map(|admin| RoleGrant {
+            principal: admin,
+            role: PlatformRole::Admin,
+        });
+        let granted = self
+            .store
+            .scan(ROLES_TREE)?
+            .into_iter()
+            .filter_map(|(principal, value)| {
+                let (role, _) = bincode::decode_from_slice::<PlatformRole, _>(
+                    &value,
+                    bincode::config::standard(),
+                )
+                .ok()?;
+                Some(RoleGrant { principal, role })
+            });
+        Ok(configured.chain(granted).collect())
+    }
+
+    /// The role `username` acts with
+    pub fn effective_role(&self, username: &str) -> PlatformRole {
+        let default_role = {
+            let (admins, default_role) = &*self.configured.read().unwrap();
+            if admins.iter().any(|admin| admin == username) {
+                return PlatformRole::Admin;
+            }
+            *default_role
+        };
+        // Without their grants, they act with the least role
+        let grant = |principal: &str| {
+            self.grant(principal).unwrap_or_else(|e| {
+                warn!("Failed to read the role of '{}': {}", principal, e);
+                Some(PlatformRole::Viewer)
+            })
+        };
+        if let Some(role) = grant(username) {
+            return role;
+        }
+
+        // Without their teams, they act with no more than their own role
+        let teams = SERVER
+            .get()
+            .map(|server| server.github_auth.get_user_teams(username))
+            .transpose()
+            .unwrap_or_else(|e| {
+                warn!("Failed to read the teams of '{}': {}", username, e);
+                None
+            })
+            .unwrap_or_default();
+        teams
+            .iter()
+            .filter_map(|team| grant(&team_owner(team)))
+            .max()
+            .unwrap_or(default_role)
+    }
+}
+
+/// The role `username` acts with, falling back to deployer if roles aren't set up
+pub fn effective_role(username: &str) -> PlatformRole {
+    ROLES
+        .get()
+        .map(|roles| roles.effective_role(username))
+        .unwrap_or(PlatformRole::Deployer)
+}
@@ -0,0 +1,388 @@
+//! Functions composed of several components.
+//!
+//! Next to its main component, a function can have route handlers: components
+//! serving the requests under a path prefix, deployed from the `[[routes]]` entries
+//! of its project's `faasta.toml`. A large app can so be split into components that
+//! compile and load faster, while sharing the function's name, domain, policies and
+//! data. The longest matching route wins; other requests go to the main component.
+//!
+//! A handler's artifacts are stored as `<name>@route-<digest>.wasm` (in artifact
+//! storage) and `<name>@route-<digest>.cwasm`, and it is cached and measured as the
+//! version `<name>@route-<digest>`. A node missing a handler's precompiled artifact
+//! compiles it from storage on its first request. A deploy stores every new
+//! artifact and stages the handlers; the publish of the main component that
+//! follows swaps them in with the function's metadata, in one batch of the metadata
+//! store, so requests see either the old function and handlers or the new ones.
+//! Artifacts no longer routed to are deleted after the swap.
+
+use anyhow::{anyhow, Context, Result};
+use bincode::{Decode, Encode};
+use faasta_interface::RouteHandler;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{error, info};
+use wasmtime::Engine;
+
+use crate::compiler;
+use crate::metadata_store::{Cached, Change, MetadataStore};
+use crate::storage::{wasm_key, write_atomically, ArtifactStorage};
+
+/// Collection of the metadata store holding each function's route handlers, keyed
+/// by function name
+const ROUTE_HANDLERS_TREE: &str = "function_route_handlers";
+/// Collection of the metadata store holding the handlers staged for the next
+/// publish of a function, keyed by `<username>/<function>`
+const STAGED_ROUTES_TREE: &str = "staged_route_handlers";
+/// Staged handlers not swapped in by a publish within this many seconds are
+/// dropped
+const STAGED_ROUTES_TTL_SECS: i64 = 3600;
+/// Suffix distinguishing a handler's artifacts, cache entry and metrics from the
+/// main component, followed by part of its digest
+pub const ROUTE_SUFFIX: &str = "@route-";
+
+/// Handlers waiting for the publish that swaps them in
+#[derive(Clone, Debug, Encode, Decode)]
+struct StagedRoutes {
+    handlers: Vec<RouteHandler>,
+    /// Unix timestamp (seconds) of the staging
+    staged_at: i64,
+}
+
+pub struct RouteHandlers {
+    store: Arc<dyn MetadataStore>,
+    handlers: Cached<Vec<RouteHandler>>,
+    functions_dir: PathBuf,
+    storage: Arc<dyn ArtifactStorage>,
+}
+
+impl RouteHandlers {
+    pub fn new(
+        store: Arc<dyn MetadataStore>,
+        db: &sled::Db,
+        functions_dir: &Path,
+        storage: Arc<dyn ArtifactStorage>,
+    ) -> Result<Self> {
+        adopt_local_routes(store.as_ref(), db)?;
+        Ok(Self {
+            handlers: Cached::new(store.clone(), ROUTE_HANDLERS_TREE, decode)?,
+            store,
+            functions_dir: functions_dir.to_path_buf(),
+            storage,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Vec<RouteHandler> {
+        self.handlers.get(name).unwrap_or_default()
+    }
+
+    /// The version of `name` serving `path` and its precompiled artifact, if one of
+    /// its handlers serves it
+    pub fn route(&self, name: &str, path: &str) -> Option<(String, PathBuf)> {
+        let handler = self
+            .get(name)
+            .into_iter()
+            .filter(|handler| serves(&handler.route, path))
+            .max_by_key(|handler| handler.route.trim_end_matches('/').len())?;
+        let version = version(name, &handler.digest);
+        let cwasm_path = self.cwasm_path(&version);
+        Some((version, cwasm_path))
+    }
+
+    /// Versions of the handlers `name` has now
+    pub fn versions(&self, name: &str) -> BTreeSet<String> {
+        versions(name, &self.get(name))
+    }
+
+    /// Versions of the handlers staged for a publish, and not dropped yet
+    pub fn staged_versions(&self) -> Result<BTreeSet<String>> {
+        Ok(self
+            .store
+            .scan(STAGED_ROUTES_TREE)?
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let (_, name) = key.split_once('/')?;
+                let (staged, _) = bincode::decode_from_slice::<StagedRoutes, _>(
+                    &value,
+                    bincode::config::standard(),
+                )
+                .ok()?;
+                Some(versions(name, &staged.handlers))
+            })
+            .flatten()
+            .collect())
+    }
+
+    /// Path of a handler version's precompiled artifact
+    pub fn cwasm_path(&self, version: &str) -> PathBuf {
+        self.functions_dir.join(format!("{version}.cwasm"))
+    }
+
+    /// Store a handler's artifacts, ahead of swapping it in
+    pub async fn save(&self, name: &str, digest: &str, wasm: &[u8], cwasm: &[u8]) -> Result<()> {
+        let version = version(name, digest);
+        self.storage.put(&wasm_key(&version), wasm).await?;
+        write_atomically(&self.cwasm_path(&version), cwasm)
+    }
+
+    /// Compile the handler `version` from artifact storage, unless its precompiled
+    /// artifact is already here
+    pub async fn hydrate(&self, engine: &Engine, version: &str) -> Result<()> {
+        let path = self.cwasm_path(version);
+        if path.exists() {
+            return Ok(());
+        }
+        let wasm = self
+            .storage
+            .get(&wasm_key(version))
+            .await?
+            .ok_or_else(|| anyhow!("Route handler '{version}' is missing from storage"))?;
+        let cwasm = compiler::precompile(engine, &wasm).await?;
+        write_atomically(&path, &cwasm)?;
+        info!("Hydrated route handler '{}' from storage", version);
+        Ok(())
+    }
+
+    /// Stage `handlers` for the next publish of `name` by `username`, replacing
+    /// those staged before
+    pub fn stage(&self, username: &str, name: &str, handlers: &[RouteHandler]) -> Result<()> {
+        let staged = StagedRoutes {
+            handlers: handlers.to_vec(),
+            staged_at: chrono::Utc::now().timestamp(),
+        };
+        let encoded = bincode::encode_to_vec(&staged, bincode::config::standard())?;
+        self.store
+            .insert(STAGED_ROUTES_TREE, &staged_key(username, name), encoded)
+    }
+
+    /// Changes swapping in the handlers `username` staged for `name`, to apply in
+    /// the batch completing the publish. None when nothing was staged, or so long
+    /// ago it's dropped.
+    pub fn staged_swap(&self, username: &str, name: &str) -> Result<Vec<Change>> {
+        let key = staged_key(username, name);
+        let Some(value) = self.store.get(STAGED_ROUTES_TREE, &key)? else {
+            return Ok(Vec::new());
+        };
+        let drop = Change::Remove {
+            collection: STAGED_ROUTES_TREE,
+            key,
+        };
+        let staged =
+            bincode::decode_from_slice::<StagedRoutes, _>(&value, bincode::config::standard())
+                .ok()
+                .map(|(staged, _)| staged)
+                .filter(|staged| {
+                    chrono::Utc::now().timestamp() - staged.staged_at < STAGED_ROUTES_TTL_SECS
+                });
+        let Some(staged) = staged else {
+            return Ok(vec![drop]);
+        };
+        let mut changes = self.swap(name, &staged.handlers)?;
+        changes.push(drop);
+        Ok(changes)
+    }
+
+    /// Changes making `handlers` the routes of `name`
+    fn swap(&self, name: &str, handlers: &[RouteHandler]) -> Result<Vec<Change>> {
+        let key = name.to_string();
+        Ok(vec![if handlers.is_empty() {
+            Change::Remove {
+                collection: ROUTE_HANDLERS_TREE,
+                key,
+            }
+        } else {
+            Change::Insert {
+                collection: ROUTE_HANDLERS_TREE,
+                key,
+                value: bincode::encode_to_vec(handlers, bincode::config::standard())?,
+            }
+        }])
+    }
+
+    /// Pick up the routes of `name` swapped in by a batch, and delete the artifacts
+    /// of the `previous` versions no longer routed to. Returns the versions
+    /// removed, to evict from the caches. Failures are only logged, as the swap
+    /// already happened.
+    pub async fn swapped(&self, name: &str, previous: &BTreeSet<String>) -> Vec<String> {
+        if let Err(e) = self.handlers.refresh(name) {
+            error!("Failed to read the route handlers of '{}': {}", name, e);
+            return Vec::new();
+        }
+        let current = self.versions(name);
+        let removed: Vec<String> = previous.difference(&current).cloned().collect();
+        for version in &removed {
+            if let Err(e) = self.delete_artifacts(version).await {
+                error!("Failed to delete route handler '{}': {:#}", version, e);
+            }
+        }
+        removed
+    }
+
+    async fn delete_artifacts(&self, version: &str) -> Result<()> {
+        self.storage.delete(&wasm_key(version)).await?;
+        let cwasm = self.cwasm_path(version);
+        if cwasm.exists() {
+            fs::remove_file(&cwasm)
+                .with_context(|| format!("Failed to remove {}", cwasm.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Swap in `handlers` as the routes of `name` in one write, deleting the
+    /// artifacts no longer used. Returns the versions removed, to evict from the
+    /// caches.
+    pub async fn replace(&self, name: &str, handlers: &[RouteHandler]) -> Result<Vec<String>> {
+        let previous = self.versions(name);
+        self.store.apply(&self.swap(name, handlers)?)?;
+        Ok(self.swapped(name, &previous).await)
+    }
+
+    /// Delete the handlers of `name`, returning their versions
+    pub async fn remove(&self, name: &str) -> Result<Vec<String>> {
+        self.replace(name, &[]).await
+    }
+
+    /// Read every function's routes again, for changes made by other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.handlers.reload()
+    }
+}
+
+/// Move the routes each node kept in its own database, before they moved to the
+/// metadata store, into a store of another backend
+fn adopt_local_routes(store: &dyn MetadataStore, db: &sled::Db) -> Result<()> {
+    if store.name() == "sled" {
+        return Ok(());
+    }
+    let tree = db.open_tree(ROUTE_HANDLERS_TREE)?;
+    for entry in tree.iter() {
+        let (key, value) = entry?;
+        let name = String::from_utf8_lossy(&key).into_owned();
+        if !store.contains(ROUTE_HANDLERS_TREE, &name)? {
+            store.insert(ROUTE_HANDLERS_TREE, &name, value.to_vec())?;
+        }
+        tree.remove(key)?;
+    }
+    Ok(())
+}
+
+fn decode(value: &[u8]) -> Option<Vec<RouteHandler>> {
+    bincode::decode_from_slice(value, bincode::config::standard())
+        .ok()
+        .map(|(handlers, _)| handlers)
+}
+
+fn staged_key(username: &str, name: &str) -> String {
+    format!("{username}/{name}")
+}
+
+/// Version a handler is cached and measured as
+fn version(name: &str, digest: &str) -> String {
+    let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+    format!("{name}{ROUTE_SUFFIX}{}", &hex[..hex.len().min(16)])
+}
+
+fn versions(name: &str, handlers: &[RouteHandler]) -> BTreeSet<String> {
+    handlers
+        .iter()
+        .map(|handler| version(name, &handler.digest))
+        .collect()
+}
+
+/// Whether requests for `path` are under `route`
+fn serves(route: &str, path: &str) -> bool {
+    let route = route.trim_end_matches('/');
+    path.strip_prefix(route)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_store::SledStore;
+    use crate::storage::{build_storage, StorageConfig, StorageKind};
+
+    fn handler(route: &str, digest: &str) -> RouteHandler {
+        RouteHandler {
+            route: route.to_string(),
+            digest: digest.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_pick_the_longest_prefix_and_replace_together() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let dir = std::env::temp_dir().join(format!("faasta-routes-{}", rand::random::<u64>()));
+        let storage = build_storage(&StorageConfig {
+            kind: StorageKind::Filesystem,
+            functions_dir: dir.clone(),
+            s3_bucket: None,
+            s3_region: String::new(),
+            s3_endpoint: None,
+            s3_prefix: String::new(),
+            s3_access_key_id: None,
+            s3_secret_access_key: None,
+            s3_session_token: None,
+        })
+        .unwrap();
+        let store: Arc<dyn MetadataStore> = Arc::new(SledStore::new(&db));
+        let handlers = RouteHandlers::new(store.clone(), &db, &dir, storage).unwrap();
+
+        let (admin, reports) = ("sha256:aaaaaaaaaaaaaaaaaaaa", "sha256:bbbbbbbbbbbbbbbbbbbb");
+        for digest in [admin, reports] {
+            handlers
+                .save("app", digest, b"wasm", b"cwasm")
+                .await
+                .unwrap();
+        }
+        let routes = [
+            handler("/admin", admin),
+            handler("/admin/reports/", reports),
+        ];
+        assert!(handlers.replace("app", &routes).await.unwrap().is_empty());
+
+        let version = |path| handlers.route("app", path).map(|(version, _)| version);
+        assert_eq!(
+            version("/admin").as_deref(),
+            Some("app@route-aaaaaaaaaaaaaaaa")
+        );
+        assert_eq!(
+            version("/admin/reports/2024").as_deref(),
+            Some("app@route-bbbbbbbbbbbbbbbb")
+        );
+        assert_eq!(version("/administrator"), None);
+        assert_eq!(version("/"), None);
+        let (_, cwasm) = handlers.route("app", "/admin/users").unwrap();
+        assert!(cwasm.exists());
+
+        // Artifacts of handlers no longer routed to are deleted
+        let removed = handlers.replace("app", &routes[1..]).await.unwrap();
+        assert_eq!(removed, ["app@route-aaaaaaaaaaaaaaaa"]);
+        assert!(!cwasm.exists());
+
+        // Staged handlers are only swapped in by the batch of their publisher
+        handlers
+            .save("app", admin, b"wasm", b"cwasm")
+            .await
+            .unwrap();
+        handlers.stage("alice", "app", &routes[..1]).unwrap();
+        assert_eq!(
+            handlers.staged_versions().unwrap(),
+            BTreeSet::from(["app@route-aaaaaaaaaaaaaaaa".to_string()])
+        );
+        assert!(handlers.get("app")[0].route.starts_with("/admin/reports"));
+        assert_eq!(handlers.staged_swap("bob", "app").unwrap().len(), 0);
+        let previous = handlers.versions("app");
+        store
+            .apply(&handlers.staged_swap("alice", "app").unwrap())
+            .unwrap();
+        let removed = handlers.swapped("app", &previous).await;
+        assert_eq!(removed, ["app@route-bbbbbbbbbbbbbbbb"]);
+        assert_eq!(handlers.get("app"), routes[..1]);
+        assert!(handlers.staged_versions().unwrap().is_empty());
+
+        assert_eq!(handlers.remove("app").await.unwrap().len(), 1);
+        assert!(handlers.get("app").is_empty());
+    }
+}
@@ -1,12 +1,80 @@
-use crate::metrics::get_metrics;
-use crate::wasi_server::SERVER;
-use faasta_interface::{FunctionError, FunctionInfo, FunctionResult, FunctionService, Metrics};
+use crate::access_keys::ACCESS_KEYS;
+use crate::aliases::{Aliases, ALIASES};
+use crate::anomalies::ANOMALIES;
+use crate::api_keys::parse_api_key;
+use crate::artifact_policy::{Artifact, POLICIES};
+use crate::audit::{self, audited, AUDIT, MAX_AUDIT_PAGE};
+use crate::backup::BACKUPS;
+use crate::canary::CANARY_SUFFIX;
+use crate::capacity_report;
+use crate::circuit_breaker::{CircuitBreakers, BREAKERS};
+use crate::cluster::CLUSTER;
+use crate::compiler;
+use crate::consistency::{self, Repair};
+use crate::cors::CORS;
+use crate::deploy_queue::{self, DEPLOY_QUEUE};
+use crate::events::{self, PlatformEvent};
+use crate::function_data;
+use crate::function_locks;
+use crate::gc::GC;
+use crate::intents::{Intent, Intents, INTENTS};
+use crate::journal::{self, JOURNAL, MAX_JOURNAL_PAGE};
+use crate::jwt_auth::JWT_AUTH;
+use crate::keep_warm::{self, is_kept_warm, KEEP_WARM};
+use crate::logs::{self, LogStore, LOGS};
+use crate::metadata_store::{Change, MetadataStore};
+use crate::metrics::{self, function_stats, get_metrics, rename_function_metrics};
+use crate::profiling::{Profiling, PROFILING};
+use crate::provenance::{Statement, PROVENANCE};
+use crate::redaction;
+use crate::redirects::MAX_REDIRECT_HOURS;
+use crate::registry::{SizeExceeded, REGISTRIES};
+use crate::roles::{self, ROLES};
+use crate::sessions::{Admission, SESSIONS};
+use crate::snapshots::{Snapshots, SNAPSHOTS};
+use crate::specs::{self, AppliedSpec};
+use crate::static_assets::{StaticAssets, STATIC_ASSETS};
+use crate::storage::{wasm_key, write_atomically};
+use crate::suspensions::Suspension;
+use crate::teams::Team;
+use crate::transfers::{Transfers, TRANSFERS};
+use crate::transforms::TRANSFORMS;
+use crate::trash::{trashed_key, Trash, TRASH};
+use crate::uploads::{max_artifact_bytes, UnexpectedOffset, UploadLimit, Uploads, UPLOADS};
+use crate::usage::{self, USAGE};
+use crate::validation;
+use crate::wasi_server::{FaastaServer, SERVER};
+use crate::webhooks::{Webhooks, WEBHOOKS};
+use bincode::Decode;
+use faasta_interface::components::ComponentRules;
+use faasta_interface::oci::{OciReference, RegistryCredentials};
+use faasta_interface::{
+    team_owner, validate_routes, validate_static_path, AliasMode, AnomalyAlert, ApiKeyInfo,
+    ApiKeyScope, ApplyOutcome, AuditEvent, BackupInfo, BreakerState, CanaryUpdate, CapacityReport,
+    CircuitBreaker, ClusterNode, ConsistencyReport, CorsPolicy, DebugSnapshot, DebugSnapshots,
+    DeliveryStatus, DeltaUpload, DriftKind, EventSeverity, FaastaError, FunctionAlias,
+    FunctionDefinition, FunctionInfo, FunctionMetadata, FunctionPage, FunctionQuery,
+    FunctionResult, FunctionService, FunctionSort, FunctionStats, FunctionStatus, GarbageReport,
+    GuestProfiles, JwtAuthPolicy, LogLevel, LogLevelSetting, LogPage, LogQuery, Metrics, NewApiKey,
+    PlatformRole, ProvenanceInfo, PublishTarget, QuotaUsage, RedactionRules, RedactionSettings,
+    RoleGrant, RouteHandler, RouteUpload, ServerEvent, ServerEventKind, ServerInfo, SessionInfo,
+    SigningKeys, StaticAsset, TeamInfo, TeamRole, TransformRule, UsageFormat, WebhookDelivery,
+    LIMIT_ARTIFACT_BYTES, LIMIT_FUNCTION_ALIASES, LIMIT_FUNCTION_TAGS, LIMIT_ROUTE_HANDLERS,
+    LIMIT_STATIC_ASSETS, LIMIT_STATIC_ASSET_BYTES, LIMIT_TRANSFORM_RULES, MAX_FUNCTION_ALIASES,
+    MAX_FUNCTION_TAGS, MAX_ROUTE_HANDLERS, MAX_STATIC_ASSETS, MAX_TRANSFORM_RULES,
+    PROTOCOL_VERSION, TEAM_OWNER_PREFIX,
+};
 use std::fs;
-use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
 use tracing::{debug, error, info};
 
-/// Sled tree name for function metadata
-const FUNCTIONS_DB_TREE: &str = "functions";
+/// Collection of the metadata store holding function metadata
+pub const FUNCTIONS_DB_TREE: &str = "functions";
+
+/// Serializes renames and clones so two of them can't claim the same target name
+static RENAME_LOCK: Mutex<()> = Mutex::const_new(());
 
 /// Implementation of the FunctionService
 /// The FaastaServer struct is the one holding the pre_cache, but we need a way to
@@ -14,206 +82,1038 @@ const FUNCTIONS_DB_TREE: &str = "functions";
 ///
 #[derive(Clone)]
 pub struct FunctionServiceImpl {
-    functions_tree: sled::Tree,
+    /// Where function metadata is kept
+    metadata: Arc<dyn MetadataStore>,
+    /// Address of the client, when the transport knows it
+    peer: Option<IpAddr>,
 }
 
 impl FunctionServiceImpl {
     /// Create a new FunctionServiceImpl
     /// Create a new FunctionServiceImpl, loading persisted metadata from sled
-    pub fn new() -> anyhow::Result<Self> {
+    pub fn new(peer: Option<IpAddr>) -> anyhow::Result<Self> {
         // Ensure functions directory exists
         let server = SERVER.get().unwrap();
         if !server.functions_dir.exists() {
             fs::create_dir_all(&server.functions_dir)?;
         }
 
-        // Open or create sled tree for function metadata
-        let functions_tree = server.metadata_db.open_tree(FUNCTIONS_DB_TREE)?;
+        Ok(Self {
+            metadata: server.metadata.clone(),
+            peer,
+        })
+    }
+}
+
+/// Resolve the caller's username from a provider token or a server-issued API key.
+///
+/// API keys are checked locally and must carry `scope`. The caller must also hold
+/// the platform role the operation needs: viewer to read, deployer to change
+/// functions.
+async fn authenticate(token: &str, scope: ApiKeyScope) -> FunctionResult<String> {
+    let username = caller(token, Some(scope)).await?;
+
+    // Read-only access needs the viewer role, anything that changes functions needs deployer
+    let needed = match scope {
+        ApiKeyScope::Deploy | ApiKeyScope::Manage => PlatformRole::Deployer,
+        ApiKeyScope::Read => PlatformRole::Viewer,
+    };
+    let role = roles::effective_role(&username);
+    if role < needed {
+        return Err(FaastaError::PermissionDenied(format!(
+            "This operation requires the {needed} role, you have {role}"
+        )));
+    }
+    Ok(username)
+}
+
+/// Resolve the caller's username from a provider token, for operations on the
+/// account itself. API keys are refused, which is how key management is kept out
+/// of reach of the keys themselves.
+async fn authenticate_account(token: &str) -> FunctionResult<String> {
+    caller(token, None).await
+}
+
+/// Identify the caller and check they aren't suspended
+async fn caller(token: &str, scope: Option<ApiKeyScope>) -> FunctionResult<String> {
+    if let Some((claimed, _)) = token
+        .split_once(':')
+        .filter(|(claimed, _)| !claimed.is_empty())
+    {
+        audit::set_caller(claimed);
+    }
+    let username = identify(token, scope).await?;
+    audit::set_caller(&username);
+
+    let server = SERVER.get().unwrap();
+    let suspension = server
+        .suspensions
+        .get(&username)
+        .map_err(|e| internal_error(format!("Failed to read suspensions: {e}")))?;
+    if let Some(suspension) = suspension {
+        return Err(FaastaError::PermissionDenied(format!(
+            "Account '{username}' is suspended: {}",
+            suspension.reason
+        )));
+    }
+    Ok(username)
+}
+
+/// Resolve the caller's username from a provider token or an API key
+async fn identify(token: &str, scope: Option<ApiKeyScope>) -> FunctionResult<String> {
+    let server = SERVER.get().unwrap();
+
+    if let Some((provided, key)) = parse_api_key(token) {
+        let record = server
+            .github_auth
+            .api_keys
+            .validate(key)
+            .map_err(|e| internal_error(format!("Failed to read API keys: {e}")))?
+            .filter(|record| provided.is_none_or(|username| username == record.username))
+            .ok_or_else(|| FaastaError::AuthFailed("Invalid or revoked API key".to_string()))?;
+
+        if let Some(sessions) = SESSIONS.get() {
+            if let Err(e) = sessions.touch_api_key(&record.username, key, &record.info) {
+                error!("Failed to record use of API key '{}': {e}", record.info.id);
+            }
+        }
+
+        return match scope {
+            Some(scope) if record.info.scopes.contains(&scope) => Ok(record.username),
+            Some(scope) => Err(FaastaError::PermissionDenied(format!(
+                "API key '{}' does not have the '{scope}' scope",
+                record.info.id
+            ))),
+            None => Err(FaastaError::PermissionDenied(format!(
+                "This operation requires a {} login, not an API key",
+                server.github_auth.provider_name()
+            ))),
+        };
+    }
+
+    let (username, is_valid) = server
+        .github_auth
+        .authenticate(token)
+        .await
+        .map_err(|e| FaastaError::AuthFailed(format!("Authentication error: {e}")))?;
+
+    if !is_valid || username.is_empty() {
+        return Err(FaastaError::AuthFailed(format!(
+            "Invalid {} authentication token",
+            server.github_auth.provider_name()
+        )));
+    }
+
+    if let Err(e) = server.github_auth.register_user(&username) {
+        error!("Failed to record user '{username}': {e}");
+    }
+
+    if let Some(sessions) = SESSIONS.get() {
+        let admission = sessions
+            .touch_login(&username, token)
+            .map_err(|e| internal_error(format!("Failed to record session: {e}")))?;
+        match admission {
+            Admission::Allowed => {}
+            Admission::Revoked => {
+                return Err(FaastaError::AuthFailed(
+                    "This login was revoked".to_string(),
+                ))
+            }
+        }
+    }
+
+    Ok(username)
+}
+
+/// The caller's role for functions owned by `owner` (a username or a team owner)
+async fn owner_role(owner: &str, username: &str, token: &str) -> Option<TeamRole> {
+    if owner == username {
+        return Some(TeamRole::Owner);
+    }
+    let team = owner.strip_prefix(TEAM_OWNER_PREFIX)?;
+    SERVER
+        .get()
+        .unwrap()
+        .github_auth
+        .team_role(team, username, token)
+        .await
+}
+
+/// Check that the caller's role for `owner`'s functions is at least `needed`
+async fn require_role(
+    owner: &str,
+    username: &str,
+    token: &str,
+    needed: TeamRole,
+    denied: &str,
+) -> FunctionResult<()> {
+    match owner_role(owner, username, token).await {
+        Some(role) if role >= needed => Ok(()),
+        _ => Err(FaastaError::PermissionDenied(denied.to_string())),
+    }
+}
+
+/// Check that a function name (also used for team names) is a valid subdomain label
+fn validate_name(name: &str) -> FunctionResult<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(FaastaError::InvalidInput(
+            "Invalid function name. Use only alphanumeric characters, underscores, and hyphens."
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Canary weights are percentages
+fn validate_canary_weight(weight: u8) -> FunctionResult<()> {
+    if weight > 100 {
+        return Err(FaastaError::InvalidInput(
+            "Canary weight must be a percentage between 0 and 100".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// An [`FaastaError::Internal`] for `message`, which is logged under the error's id
+/// rather than sent to the caller, as it may reveal how the server is set up
+pub fn internal_error(message: impl std::fmt::Display) -> FaastaError {
+    let id = format!("err_{}", hex::encode(rand::random::<[u8; 8]>()));
+    error!(error_id = %id, "{}", message);
+    FaastaError::Internal { id }
+}
+
+/// An [`FaastaError::InvalidInput`] for asking for `feature`, which this server
+/// was started without
+fn not_enabled(feature: &str) -> FaastaError {
+    FaastaError::InvalidInput(format!("This server doesn't have {feature} enabled"))
+}
+
+/// Reject artifacts over the server's size limit
+fn check_artifact_size(size: u64) -> FunctionResult<()> {
+    let limit = max_artifact_bytes();
+    if size > limit {
+        return Err(FaastaError::TooLarge { size, limit });
+    }
+    Ok(())
+}
+
+fn uploads() -> FunctionResult<&'static Uploads> {
+    UPLOADS.get().ok_or_else(|| {
+        FaastaError::InvalidInput("Uploads are not available on this server".to_string())
+    })
+}
+
+/// Error of appending to an upload, invalid input when the chunk doesn't continue it
+fn append_error(error: anyhow::Error) -> FaastaError {
+    match error.downcast_ref::<UnexpectedOffset>() {
+        Some(offset) => FaastaError::InvalidInput(format!("Chunk refused, {offset}")),
+        None => internal_error(format!("Failed to store upload: {error}")),
+    }
+}
+
+/// Error of starting an upload, a quota when too many are in progress
+fn begin_upload_error(error: anyhow::Error) -> FaastaError {
+    match error.downcast_ref::<UploadLimit>() {
+        Some(limit) => FaastaError::QuotaExceeded {
+            quota: limit.quota.to_string(),
+            limit: limit.limit,
+        },
+        None => internal_error(format!("Failed to start upload: {error}")),
+    }
+}
+
+fn transfers() -> FunctionResult<&'static Transfers> {
+    TRANSFERS.get().ok_or_else(|| {
+        FaastaError::InvalidInput("Transfers are not available on this server".to_string())
+    })
+}
+
+fn aliases() -> FunctionResult<&'static Aliases> {
+    ALIASES.get().ok_or_else(|| {
+        FaastaError::InvalidInput("Aliases are not available on this server".to_string())
+    })
+}
+
+/// Names `owner` takes toward their project limit: their projects and the aliases
+/// of their functions
+fn names_used(owner: &str) -> FunctionResult<usize> {
+    let projects = SERVER
+        .get()
+        .unwrap()
+        .github_auth
+        .get_user_projects(owner)
+        .map_err(|e| internal_error(format!("Failed to read projects: {e}")))?;
+    let aliases = ALIASES
+        .get()
+        .map_or(0, |aliases| aliases.count_of(&projects));
+    Ok(projects.len() + aliases)
+}
+
+/// Check `owner` may take `names` more names for `name`, unless it's already one
+/// of their projects
+fn check_project_quota(owner: &str, name: &str, names: usize) -> FunctionResult<()> {
+    let auth = &SERVER.get().unwrap().github_auth;
+    let owned = auth
+        .get_user_projects(owner)
+        .map_err(|e| internal_error(format!("Failed to read projects: {e}")))?
+        .iter()
+        .any(|project| project == name);
+    let limit = auth.project_limit(owner);
+    if !owned && names_used(owner)? + names > limit {
+        return Err(FaastaError::QuotaExceeded {
+            quota: "projects".to_string(),
+            limit: limit as u64,
+        });
+    }
+    Ok(())
+}
+
+fn logs() -> FunctionResult<&'static LogStore> {
+    LOGS.get().ok_or_else(|| {
+        FaastaError::InvalidInput("Function logs are not kept on this server".to_string())
+    })
+}
+
+fn breakers() -> FunctionResult<&'static CircuitBreakers> {
+    BREAKERS
+        .get()
+        .ok_or_else(|| internal_error("Circuit breakers are not configured".to_string()))
+}
+
+fn intents() -> FunctionResult<&'static Intents> {
+    INTENTS
+        .get()
+        .ok_or_else(|| internal_error("Intent log is not configured".to_string()))
+}
+
+fn snapshots() -> FunctionResult<&'static Snapshots> {
+    SNAPSHOTS.get().ok_or_else(|| {
+        FaastaError::InvalidInput("Debug snapshots are turned off on this server".to_string())
+    })
+}
+
+fn profiling() -> FunctionResult<&'static Profiling> {
+    PROFILING.get().ok_or_else(|| {
+        FaastaError::InvalidInput("Guest profiling is turned off on this server".to_string())
+    })
+}
+
+fn webhooks() -> FunctionResult<&'static Webhooks> {
+    WEBHOOKS.get().ok_or_else(|| {
+        FaastaError::InvalidInput("Webhook delivery is turned off on this server".to_string())
+    })
+}
+
+/// Reject a component that doesn't implement the `wasi:http/proxy` world.
+/// Returns the interfaces it imports.
+fn check_component(
+    engine: &wasmtime::Engine,
+    cwasm: &[u8],
+    name: &str,
+) -> FunctionResult<Vec<String>> {
+    validation::check_proxy_component(engine, cwasm).map_err(|problems| {
+        journal::record(
+            EventSeverity::Warning,
+            ServerEventKind::UploadRejected,
+            Some(name),
+            format!("Not a wasi:http/proxy component: {}", problems.join("; ")),
+        );
+        FaastaError::InvalidComponent { details: problems }
+    })
+}
+
+/// Run the operator's artifact policies on a component that passed
+/// [`check_component`], journaling refusals
+async fn check_policies(artifact: &Artifact<'_>) -> FunctionResult<()> {
+    let Some(policies) = POLICIES.get() else {
+        return Ok(());
+    };
+    match policies.check(artifact).await {
+        Ok(None) => Ok(()),
+        Ok(Some((policy, reason))) => {
+            journal::record(
+                EventSeverity::Warning,
+                ServerEventKind::UploadRejected,
+                Some(artifact.function),
+                format!("Refused by the {policy} policy: {reason}"),
+            );
+            Err(FaastaError::PermissionDenied(format!(
+                "This server's {policy} policy refuses the component: {reason}"
+            )))
+        }
+        Err(e) => Err(internal_error(format!(
+            "Failed to check '{}' against the artifact policies: {e:#}",
+            artifact.function
+        ))),
+    }
+}
+
+/// Compile `wasm` and check it as a publish by `uploader` would, for artifacts
+/// that come back from the trash or a backup rather than an upload
+pub async fn check_artifact(
+    engine: &wasmtime::Engine,
+    name: &str,
+    uploader: &str,
+    wasm: &[u8],
+) -> FunctionResult<()> {
+    let cwasm = compiler::precompile(engine, wasm)
+        .await
+        .map_err(|_| FaastaError::InvalidInput("Invalid Wasm".to_string()))?;
+    let imports = check_component(engine, &cwasm, name)?;
+    check_policies(&Artifact {
+        function: name,
+        uploader,
+        wasm,
+        cwasm: &cwasm,
+        imports: &imports,
+    })
+    .await
+}
+
+/// Check an artifact's provenance against the server's policy before it is
+/// published, journaling refusals. Returns the digest and document to record once
+/// the artifact is live.
+fn check_provenance(
+    wasm: &[u8],
+    document: Option<Vec<u8>>,
+    name: &str,
+) -> FunctionResult<Option<(String, Vec<u8>)>> {
+    let Some(store) = PROVENANCE.get() else {
+        return Ok(None);
+    };
+    let digest = function_data::artifact_digest(wasm);
+    match store.verify(&digest, document.as_deref()) {
+        Ok(statement) => {
+            if let Some(statement) = statement {
+                info!(
+                    "Provenance of '{name}' ({digest}) verified, built by {}",
+                    statement
+                        .builder_id
+                        .as_deref()
+                        .unwrap_or("an unnamed builder")
+                );
+            }
+            Ok(document.map(|document| (digest, document)))
+        }
+        Err(problem) => {
+            journal::record(
+                EventSeverity::Warning,
+                ServerEventKind::UploadRejected,
+                Some(name),
+                problem.clone(),
+            );
+            Err(match document {
+                Some(_) => FaastaError::InvalidInput(problem),
+                None => FaastaError::PermissionDenied(format!(
+                    "{problem}; attach it to the upload (cargo faasta deploy --provenance)"
+                )),
+            })
+        }
+    }
+}
+
+/// Keep provenance checked by [`check_provenance`] once its artifact is live
+fn record_provenance(checked: Option<(String, Vec<u8>)>) {
+    if let (Some(store), Some((digest, document))) = (PROVENANCE.get(), checked) {
+        if let Err(e) = store.record(&digest, &document) {
+            error!("Failed to record the provenance of {digest}: {e}");
+        }
+    }
+}
+
+/// How to reach a published function
+pub fn function_usage(name: &str) -> String {
+    format!("https://{name}.faasta.xyz or https://faasta.xyz/{name}")
+}
+
+/// Addresses `name` is served at: its subdomain and its path on the base domain,
+/// then the routes of its handlers
+fn function_urls(server: &FaastaServer, name: &str) -> Vec<String> {
+    let base_domain = server.base_domain.load();
+    let mut urls = vec![
+        format!("https://{name}.{base_domain}"),
+        format!("https://{base_domain}/{name}"),
+    ];
+    urls.extend(
+        server
+            .routes
+            .get(name)
+            .into_iter()
+            .map(|handler| format!("https://{name}.{base_domain}{}", handler.route)),
+    );
+    urls
+}
+
+/// `FunctionInfo` as stored before functions carried metadata
+#[derive(Decode)]
+pub struct LegacyFunctionInfo {
+    name: String,
+    owner: String,
+    published_at: String,
+    usage: String,
+}
+
+impl From<LegacyFunctionInfo> for FunctionInfo {
+    fn from(legacy: LegacyFunctionInfo) -> Self {
+        Self {
+            name: legacy.name,
+            owner: legacy.owner,
+            published_at: legacy.published_at,
+            usage: legacy.usage,
+            metadata: FunctionMetadata::default(),
+        }
+    }
+}
+
+/// Decode a stored function record, including ones written before functions
+/// carried metadata
+pub fn decode_function_info(bytes: &[u8]) -> Result<FunctionInfo, bincode::error::DecodeError> {
+    let config = bincode::config::standard();
+    match bincode::decode_from_slice::<FunctionInfo, _>(bytes, config) {
+        Ok((info, _)) => Ok(info),
+        Err(e) => bincode::decode_from_slice::<LegacyFunctionInfo, _>(bytes, config)
+            .map(|(legacy, _)| legacy.into())
+            .map_err(|_| e),
+    }
+}
+
+/// The page of `functions` `query` asks for, counting invocations with `invocations`
+fn page_functions(
+    mut functions: Vec<FunctionInfo>,
+    query: &FunctionQuery,
+    invocations: impl Fn(&str) -> u64,
+) -> FunctionPage {
+    let total = functions.len() as u64;
+    if let Some(filter) = &query.name_filter {
+        let filter = filter.to_lowercase();
+        functions.retain(|function| function.name.to_lowercase().contains(&filter));
+    }
+    if let Some(tag) = &query.tag {
+        functions.retain(|function| function.metadata.has_tag(tag));
+    }
+    match query.sort {
+        FunctionSort::Name => functions.sort_by(|a, b| a.name.cmp(&b.name)),
+        // RFC 3339 times in UTC sort as text
+        FunctionSort::LastDeploy => functions.sort_by(|a, b| {
+            b.published_at
+                .cmp(&a.published_at)
+                .then_with(|| a.name.cmp(&b.name))
+        }),
+        FunctionSort::Invocations => {
+            functions.sort_by_cached_key(|function| {
+                (
+                    std::cmp::Reverse(invocations(&function.name)),
+                    function.name.clone(),
+                )
+            });
+        }
+    }
+    if query.reverse {
+        functions.reverse();
+    }
+
+    let matching = functions.len() as u64;
+    let page = query.page.max(1);
+    if query.limit > 0 {
+        let start = (page as usize - 1).saturating_mul(query.limit as usize);
+        functions = functions
+            .into_iter()
+            .skip(start)
+            .take(query.limit as usize)
+            .collect();
+    }
+    FunctionPage {
+        functions,
+        page,
+        matching,
+        total,
+    }
+}
+
+/// Publishing over an existing or trashed function keeps its owner,
+/// as long as the caller may publish for that owner
+async fn authorize_update(
+    existing_owner: &str,
+    team: Option<&str>,
+    username: &str,
+    token: &str,
+) -> FunctionResult<()> {
+    require_role(
+        existing_owner,
+        username,
+        token,
+        TeamRole::Developer,
+        "A function with this name already exists and belongs to another user",
+    )
+    .await?;
 
-        Ok(Self { functions_tree })
+    if let Some(team) = team {
+        if existing_owner != team_owner(team) {
+            return Err(FaastaError::InvalidInput(format!(
+                "A function with this name already exists and belongs to '{existing_owner}'"
+            )));
+        }
     }
+    Ok(())
+}
+
+/// Load `team`, checking that the caller is one of its owners
+async fn owned_team(team: &str, username: &str, token: &str) -> FunctionResult<Team> {
+    let server = SERVER.get().unwrap();
+    let found = server
+        .github_auth
+        .teams
+        .get(team)
+        .map_err(|e| internal_error(format!("Failed to read teams: {e}")))?
+        .ok_or_else(|| FaastaError::NotFound(format!("Team '{team}' not found")))?;
+    require_role(
+        &team_owner(team),
+        username,
+        token,
+        TeamRole::Owner,
+        &format!("You need the owner role in team '{team}' to manage it"),
+    )
+    .await?;
+    Ok(found)
+}
+
+/// Public view of a team, including the functions it owns
+fn team_info(team: Team) -> FunctionResult<TeamInfo> {
+    let functions = SERVER
+        .get()
+        .unwrap()
+        .github_auth
+        .get_user_projects(&team_owner(&team.name))
+        .map_err(|e| internal_error(format!("Failed to read projects: {e}")))?;
+    Ok(TeamInfo {
+        name: team.name,
+        github_org: team.github_org,
+        members: team.members,
+        functions,
+    })
 }
 
 // Helper implementation that uses references to avoid cloning
 impl FunctionServiceImpl {
+    /// Check that no live, trashed or (someone else's) redirected function, nor any
+    /// alias, is called `name`
+    fn ensure_name_free(&self, name: &str, owner: &str) -> FunctionResult<()> {
+        let server = SERVER.get().unwrap();
+        let taken = server.functions_dir.join(format!("{name}.cwasm")).exists()
+            || self
+                .metadata
+                .contains(FUNCTIONS_DB_TREE, name)
+                .unwrap_or(true)
+            || TRASH.get().is_some_and(|trash| trash.get(name).is_some())
+            || ALIASES
+                .get()
+                .is_some_and(|aliases| aliases.get(name).is_some());
+        let taken_by_redirect = server
+            .redirects
+            .lookup(name)
+            .map_err(|e| internal_error(format!("Failed to read redirects: {e}")))?
+            .is_some_and(|redirect| redirect.owner != owner);
+
+        if taken || taken_by_redirect {
+            return Err(FaastaError::InvalidInput(format!(
+                "A function named '{name}' already exists"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Load the metadata of a published function
+    fn function_info(&self, name: &str) -> FunctionResult<FunctionInfo> {
+        self.metadata
+            .get(FUNCTIONS_DB_TREE, name)
+            .map_err(|e| internal_error(format!("Failed to get function metadata: {e}")))?
+            .and_then(|bytes| decode_function_info(&bytes).ok())
+            .ok_or_else(|| FaastaError::NotFound(format!("Function '{name}' not found")))
+    }
+
+    /// Persist a function's metadata
+    fn save_function_info(&self, function_info: &FunctionInfo) -> FunctionResult<()> {
+        let meta = bincode::encode_to_vec(function_info, bincode::config::standard())
+            .map_err(|e| internal_error(format!("Failed to serialize function metadata: {e}")))?;
+        self.metadata
+            .insert(FUNCTIONS_DB_TREE, &function_info.name, meta)
+            .map_err(|e| internal_error(format!("Failed to persist function metadata: {e}")))?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn publish_impl(
         &self,
         wasm_file: Vec<u8>,
+        provenance: Option<Vec<u8>>,
         name: String,
+        team: Option<String>,
+        keep_warm: Option<bool>,
+        metadata: Option<FunctionMetadata>,
         github_auth_token: String,
     ) -> FunctionResult<String> {
-        // Use the new combined authentication function
         let server = SERVER.get().unwrap();
-        let (username, is_valid) = server
-            .github_auth
-            .authenticate_github(&github_auth_token)
-            .await
-            .map_err(|e| FunctionError::AuthError(format!("Authentication error: {e}")))?;
-
-        if !is_valid || username.is_empty() {
-            return Err(FunctionError::AuthError(
-                "Invalid GitHub authentication token".to_string(),
-            ));
-        }
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
 
         // Check if function name is valid
-        if name.is_empty()
-            || !name
-                .chars()
-                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
-        {
-            return Err(FunctionError::InvalidInput(
-                "Invalid function name. Use only alphanumeric characters, underscores, and hyphens.".to_string()
-            ));
+        validate_name(&name)?;
+        if let Some(metadata) = &metadata {
+            metadata.validate().map_err(FaastaError::InvalidInput)?;
         }
+        // Kept from the version published before unless replaced
+        let mut previous_metadata = FunctionMetadata::default();
+
+        // New functions belong to the requested team, or to the caller
+        let mut owner = match &team {
+            Some(team) => {
+                let owner = team_owner(team);
+                require_role(
+                    &owner,
+                    &username,
+                    &github_auth_token,
+                    TeamRole::Developer,
+                    &format!("You need the developer role in team '{team}' to publish for it"),
+                )
+                .await?;
+                owner
+            }
+            None => username.clone(),
+        };
 
         // Check WASM file size
-        if wasm_file.len() > faasta_interface::MAX_WASM_SIZE {
-            return Err(FunctionError::InvalidInput(format!(
-                "WASM file too large. Maximum allowed size is 30MB, but received {} bytes",
-                wasm_file.len()
+        check_artifact_size(wasm_file.len() as u64)?;
+        let provenance = check_provenance(&wasm_file, provenance, &name)?;
+
+        // Taken before the deploy slot, as the management API holds it while it
+        // waits for one
+        let _lock = function_locks::lock(&name).await;
+        // Wait our turn so a burst of deploys can't starve request serving
+        let _permit = deploy_queue::wait_for_turn(&username, &name).await;
+
+        // Validate and precompile before anything is written or registered, so a
+        // broken upload never replaces a working version
+        let cwasm = compiler::precompile(&server.engine, &wasm_file)
+            .await
+            .map_err(|_| FaastaError::InvalidInput("Invalid Wasm".to_string()))?;
+        let imports = check_component(&server.engine, &cwasm, &name)?;
+        check_policies(&Artifact {
+            function: &name,
+            uploader: &username,
+            wasm: &wasm_file,
+            cwasm: &cwasm,
+            imports: &imports,
+        })
+        .await?;
+
+        // The precompiled artifact is always kept locally, whatever the storage
+        let cwasm_path = server.functions_dir.join(format!("{name}.cwasm"));
+
+        // A trashed function keeps its name reserved for its owner
+        let trashed = TRASH.get().and_then(|trash| trash.get(&name));
+        if let Some(trashed) = &trashed {
+            authorize_update(
+                &trashed.info.owner,
+                team.as_deref(),
+                &username,
+                &github_auth_token,
+            )
+            .await?;
+            owner = trashed.info.owner.clone();
+        }
+
+        // An alias keeps its name until it's removed
+        if let Some(alias) = ALIASES.get().and_then(|aliases| aliases.get(&name)) {
+            return Err(FaastaError::InvalidInput(format!(
+                "'{name}' is an alias of '{}'. Remove the alias before publishing under its name.",
+                alias.target
             )));
         }
 
-        // Simple direct approach: use the exact function name for the WASM file
-        let wasm_filename = format!("{name}.wasm");
-        let wasm_path = server.functions_dir.join(&wasm_filename);
+        // The old name of a renamed function stays reserved while it redirects
+        let redirect = server
+            .redirects
+            .lookup(&name)
+            .map_err(|e| internal_error(format!("Failed to read redirects: {e}")))?;
+        if let Some(redirect) = &redirect {
+            authorize_update(
+                &redirect.owner,
+                team.as_deref(),
+                &username,
+                &github_auth_token,
+            )
+            .await?;
+            owner = redirect.owner.clone();
+        }
 
-        // Check if function already exists
-        if wasm_path.exists() {
-            let entry_result = self.functions_tree.get(name.as_bytes()).map_err(|e| {
-                FunctionError::InternalError(format!("Failed to get function metadata: {e}"))
-            })?;
+        // Check if function already exists (its artifact may have been evicted)
+        if cwasm_path.exists()
+            || self
+                .metadata
+                .contains(FUNCTIONS_DB_TREE, &name)
+                .unwrap_or(false)
+        {
+            let entry_result = self
+                .metadata
+                .get(FUNCTIONS_DB_TREE, &name)
+                .map_err(|e| internal_error(format!("Failed to get function metadata: {e}")))?;
 
             if let Some(entry_bytes) = entry_result {
                 // Deserialize the function info
-                let function_info = match bincode::decode_from_slice::<FunctionInfo, _>(
-                    &entry_bytes,
-                    bincode::config::standard(),
-                ) {
-                    Ok((info, _)) => info,
+                let function_info = match decode_function_info(&entry_bytes) {
+                    Ok(info) => info,
                     Err(e) => {
                         error!("Failed to deserialize function info: {}", e);
-                        return Err(FunctionError::InternalError(format!(
+                        return Err(internal_error(format!(
                             "Failed to deserialize function info: {e}"
                         )));
                     }
                 };
 
-                // Check if user owns the function
-                if function_info.owner != username {
-                    return Err(FunctionError::PermissionDenied(
-                        "A function with this name already exists and belongs to another user"
-                            .to_string(),
-                    ));
-                }
+                // Check if user may publish for the function's owner
+                authorize_update(
+                    &function_info.owner,
+                    team.as_deref(),
+                    &username,
+                    &github_auth_token,
+                )
+                .await?;
+                owner = function_info.owner;
+                previous_metadata = function_info.metadata;
                 // Function exists and user owns it - proceed with update
             } else {
                 // Function exists on disk but not in memory db - this is inconsistent state
                 // Still enforce ownership check through GitHub auth
-                return Err(FunctionError::PermissionDenied(
+                return Err(FaastaError::PermissionDenied(
                     "A function with this name already exists. Please choose a different name."
                         .to_string(),
                 ));
             }
         } else {
             // New function - enforce project limit
-            if !server.github_auth.can_upload_project(&username, &name) {
-                return Err(FunctionError::PermissionDenied(
-                    "You have reached the maximum limit of 10 projects".to_string(),
-                ));
-            }
+            check_project_quota(&owner, &name, 1)?;
+        }
+        let new_function = !self
+            .metadata
+            .contains(FUNCTIONS_DB_TREE, &name)
+            .unwrap_or(false);
+
+        if keep_warm == Some(true) {
+            KEEP_WARM
+                .get()
+                .ok_or_else(|| {
+                    FaastaError::InvalidInput("This server doesn't keep functions warm".to_string())
+                })?
+                .check_limit(&owner, &name)?;
+        }
+
+        // Route handlers staged for this publish are swapped in with it
+        let routes = server
+            .routes
+            .staged_swap(&username, &name)
+            .map_err(|e| internal_error(format!("Failed to read staged route handlers: {e}")))?;
+        let previous_routes = server.routes.versions(&name);
+
+        // A crash from here on is recovered at the next startup
+        let intents = intents()?;
+        let intent = Intent::Publish {
+            owner: owner.clone(),
+            new: new_function,
+        };
+        intents
+            .begin(&name, &intent)
+            .map_err(|e| internal_error(format!("Failed to record the publish: {e}")))?;
+        let swaps_routes = !routes.is_empty();
+
+        // A failure part way is undone, or rolled forward, as a crash would be
+        let published = async {
             // Register ownership
-            match server.github_auth.add_project(&username, &name).await {
-                Ok(_) => debug!("Added project '{}' for user '{}'", name, username),
-                Err(e) => {
-                    error!("Failed to add project: {}", e);
-                    return Err(FunctionError::InternalError(format!(
-                        "Failed to add project: {e}"
-                    )));
+            if new_function {
+                match server.github_auth.add_project(&owner, &name).await {
+                    Ok(_) => debug!("Added project '{}' for owner '{}'", name, owner),
+                    Err(e) => {
+                        error!("Failed to add project: {}", e);
+                        return Err(internal_error(format!("Failed to add project: {e}")));
+                    }
                 }
             }
-        }
 
-        // When publishing a new version, clear any existing cache entry
-        if let Some(server) = SERVER.get() {
+            let now = chrono::Utc::now().to_rfc3339();
+
+            // Swap in the new version. Each artifact is replaced atomically, so requests
+            // already running finish on the old one and none sees a half-written file.
+            server
+                .storage
+                .put(&wasm_key(&name), &wasm_file)
+                .await
+                .map_err(|e| internal_error(format!("Failed to store WASM file: {e}")))?;
+            write_atomically(&cwasm_path, &cwasm)
+                .map_err(|e| internal_error(format!("Failed to write file: {e}")))?;
+            let digest = function_data::artifact_digest(&wasm_file);
+            if let Err(e) = server.artifacts.record_compiled(&name, &digest) {
+                error!("Failed to record what '{name}' was compiled from: {e}");
+            }
+            if let Err(e) =
+                function_data::record_artifact(server.metadata.as_ref(), &name, &wasm_file)
+            {
+                error!("Failed to record the artifact digest of '{name}': {e}");
+            }
+            record_provenance(provenance);
+
+            // Only now drop the cached instance, so the next request loads the new version
             server.remove_from_cache(&name);
-        }
+            server.artifacts.touch(&name);
+            server.trim_artifact_cache(&name);
 
-        // Write the WASM file
-        let mut file = fs::File::create(&wasm_path)
-            .map_err(|e| FunctionError::InternalError(format!("Failed to create file: {e}")))?;
-        file.write_all(&wasm_file)
-            .map_err(|e| FunctionError::InternalError(format!("Failed to write file: {e}")))?;
-        let wasm = fs::read(&wasm_path).unwrap();
+            // Create function info with both subdomain and path-based URLs
+            let function_info = FunctionInfo {
+                name: name.clone(),
+                owner,
+                published_at: now,
+                usage: function_usage(&name),
+                // Routes are listed from the stored handlers instead
+                metadata: FunctionMetadata {
+                    routes: Vec::new(),
+                    ..metadata.unwrap_or(previous_metadata)
+                },
+            };
 
-        // Fix: properly handle the Result to avoid passing it directly to fs::write
-        let cwasm = server
-            .engine
-            .precompile_component(&wasm)
-            .map_err(|_| FunctionError::InvalidInput("Invalid Wasm".to_string()))?;
+            // Serialize metadata with bincode
+            let meta = bincode::encode_to_vec(&function_info, bincode::config::standard())
+                .map_err(|e| {
+                    internal_error(format!("Failed to serialize function metadata: {e}"))
+                })?;
+            // Persist metadata, which completes the publish
+            intents
+                .commit_with(&name, Some(meta), routes)
+                .map_err(|e| internal_error(format!("Failed to persist function metadata: {e}")))
+        }
+        .await;
+        if let Err(e) = published {
+            if let Err(recover_error) = intents.recover(&name, &intent).await {
+                error!("Failed to undo the failed publish of '{name}': {recover_error}");
+            }
+            return Err(e);
+        }
+        if swaps_routes {
+            for version in server.routes.swapped(&name, &previous_routes).await {
+                server.remove_from_cache(&version);
+            }
+        }
 
-        fs::write(wasm_path.with_extension("cwasm"), cwasm).unwrap();
+        // A function published under the name replaces the redirect
+        if redirect.is_some() {
+            if let Err(e) = server.redirects.remove(&name) {
+                error!("Failed to remove redirect for '{name}': {e}");
+            }
+        }
 
-        // Create function info with both subdomain and path-based URLs
-        let now = chrono::Utc::now().to_rfc3339();
-        let function_info = FunctionInfo {
-            name: name.clone(),
-            owner: username,
-            published_at: now,
-            usage: format!("https://{name}.faasta.xyz or https://faasta.xyz/{name}"),
-        };
+        // The new version supersedes any copy waiting in the trash
+        if let (Some(trash), Some(_)) = (TRASH.get(), trashed) {
+            if let Err(e) = trash.discard(&name).await {
+                error!("Failed to discard trashed copy of '{name}': {e}");
+            }
+        }
 
-        // Serialize metadata with bincode
-        let meta =
-            bincode::encode_to_vec(&function_info, bincode::config::standard()).map_err(|e| {
-                FunctionError::InternalError(format!(
-                    "Failed to serialize function metadata: {e}"
-                ))
-            })?;
-        // Persist metadata to sled
-        self.functions_tree
-            .insert(name.as_bytes(), meta)
-            .map_err(|e| {
-                FunctionError::InternalError(format!("Failed to persist function metadata: {e}"))
-            })?;
+        if let (Some(keep_warm), Some(enabled)) = (KEEP_WARM.get(), keep_warm) {
+            keep_warm
+                .set(&name, enabled)
+                .map_err(|e| internal_error(format!("Failed to keep function warm: {e}")))?;
+        }
+        events::publish(PlatformEvent::Deployed {
+            function: name.clone(),
+            username,
+            peer: self.peer,
+        });
 
         Ok(format!("Function '{name}' published successfully"))
     }
 
+    fn server_info_impl(&self) -> ServerInfo {
+        let limits = [
+            (LIMIT_ARTIFACT_BYTES, max_artifact_bytes()),
+            (LIMIT_ROUTE_HANDLERS, MAX_ROUTE_HANDLERS as u64),
+            (LIMIT_STATIC_ASSETS, MAX_STATIC_ASSETS as u64),
+            (
+                LIMIT_STATIC_ASSET_BYTES,
+                STATIC_ASSETS.get().map_or(0, StaticAssets::max_bytes),
+            ),
+            (LIMIT_TRANSFORM_RULES, MAX_TRANSFORM_RULES as u64),
+            (LIMIT_FUNCTION_TAGS, MAX_FUNCTION_TAGS as u64),
+            (LIMIT_FUNCTION_ALIASES, MAX_FUNCTION_ALIASES as u64),
+        ];
+        // Features that depend on how the server is configured
+        let features = [
+            ("cluster", CLUSTER.get().is_some()),
+            ("debug-snapshots", SNAPSHOTS.get().is_some()),
+            ("keep-warm", KEEP_WARM.get().is_some()),
+            ("logs", LOGS.get().is_some()),
+            ("profiling", PROFILING.get().is_some()),
+            ("registries", REGISTRIES.get().is_some()),
+            ("trash", TRASH.get().is_some()),
+            ("webhooks", WEBHOOKS.get().is_some()),
+        ];
+        ServerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            limits: limits
+                .into_iter()
+                .map(|(key, limit)| (key.to_string(), limit))
+                .collect(),
+            features: features
+                .into_iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| feature.to_string())
+                .chain(ComponentRules::current().features())
+                .collect(),
+        }
+    }
+
     async fn list_functions_impl(
         &self,
+        query: FunctionQuery,
         github_auth_token: String,
-    ) -> FunctionResult<Vec<FunctionInfo>> {
-        // Use the new combined authentication function
+    ) -> FunctionResult<FunctionPage> {
         let server = SERVER.get().unwrap();
-        let (username, is_valid) = server
-            .github_auth
-            .authenticate_github(&github_auth_token)
-            .await
-            .map_err(|e| FunctionError::AuthError(format!("Authentication error: {e}")))?;
-
-        if !is_valid || username.is_empty() {
-            return Err(FunctionError::AuthError(
-                "Invalid GitHub authentication token".to_string(),
-            ));
-        }
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
 
         // Get the user's projects from the user_tree
         let mut user_functions = Vec::new();
 
+        // The user's own projects, followed by those of their teams
+        let read_error = |e: anyhow::Error| internal_error(format!("Failed to read projects: {e}"));
+        let owners = std::iter::once(username.clone()).chain(
+            server
+                .github_auth
+                .get_user_teams(&username)
+                .map_err(read_error)?
+                .into_iter()
+                .map(|team| team_owner(&team)),
+        );
+
         // Get user data to find which projects they own
-        if let Some(projects) = server.github_auth.get_user_projects(&username) {
+        for owner in owners {
+            let projects = server
+                .github_auth
+                .get_user_projects(&owner)
+                .map_err(read_error)?;
             // For each project owned by the user, get the function info
             for project_name in projects {
                 // Get function info from the functions tree
-                if let Ok(Some(value)) = self.functions_tree.get(project_name.as_bytes()) {
+                if let Ok(Some(value)) = self.metadata.get(FUNCTIONS_DB_TREE, &project_name) {
                     // Deserialize the function info
-                    match bincode::decode_from_slice::<FunctionInfo, _>(
-                        &value,
-                        bincode::config::standard(),
-                    ) {
-                        Ok((function_info, _)) => {
+                    match decode_function_info(&value) {
+                        Ok(mut function_info) => {
+                            // The handlers stored, even if a route publish failed
+                            function_info.metadata.routes = server
+                                .routes
+                                .get(&project_name)
+                                .into_iter()
+                                .map(|handler| handler.route)
+                                .collect();
                             user_functions.push(function_info);
                         }
                         Err(e) => {
@@ -226,74 +1126,51 @@ impl FunctionServiceImpl {
             }
         }
 
-        Ok(user_functions)
+        Ok(page_functions(user_functions, &query, |name| {
+            function_stats(name).map_or(0, |stats| stats.invocations)
+        }))
     }
 
-    async fn unpublish_impl(&self, name: String, github_auth_token: String) -> FunctionResult<()> {
-        info!("Processing unpublish request for function: {name}");
-
+    /// Take a function out of routing, moving it to `trash` if given and
+    /// deleting it for good otherwise
+    pub async fn remove_function(
+        &self,
+        function_info: FunctionInfo,
+        trash: Option<&Trash>,
+    ) -> FunctionResult<()> {
         let server = SERVER.get().unwrap();
-        // Use the new combined authentication function
-        let (username, is_valid) = server
-            .github_auth
-            .authenticate_github(&github_auth_token)
-            .await
-            .map_err(|e| {
-                error!("Authentication error during unpublish: {e}");
-                FunctionError::AuthError(format!("Authentication error: {e}"))
-            })?;
-
-        if !is_valid || username.is_empty() {
-            error!("Invalid authentication token for unpublish operation");
-            return Err(FunctionError::AuthError(
-                "Invalid GitHub authentication token".to_string(),
-            ));
-        }
-
-        info!("Authentication successful for user: {username}");
-
-        // Check if function exists
-        let entry_result = self.functions_tree.get(name.as_bytes()).map_err(|e| {
-            FunctionError::InternalError(format!("Failed to get function metadata: {e}"))
-        })?;
-
-        if let Some(entry_bytes) = entry_result {
-            // Deserialize the function info
-            let function_info = match bincode::decode_from_slice::<FunctionInfo, _>(
-                &entry_bytes,
-                bincode::config::standard(),
-            ) {
-                Ok((info, _)) => info,
-                Err(e) => {
-                    error!("Failed to deserialize function info: {}", e);
-                    return Err(FunctionError::InternalError(format!(
-                        "Failed to deserialize function info: {e}"
-                    )));
-                }
-            };
+        let name = function_info.name.clone();
 
-            // Check if user owns the function
-            if function_info.owner != username {
-                error!(
-                    "Permission denied: function owned by {} but requested by {}",
-                    function_info.owner, username
-                );
-                return Err(FunctionError::PermissionDenied(
-                    "You don't have permission to unpublish this function".to_string(),
-                ));
-            }
+        // A crash from here on is recovered at the next startup
+        let intents = intents()?;
+        intents
+            .begin(
+                &name,
+                &Intent::Delete {
+                    info: function_info.clone(),
+                    trash: trash.is_some(),
+                },
+            )
+            .map_err(|e| internal_error(format!("Failed to record the delete: {e}")))?;
 
+        if let Some(trash) = trash {
+            // Keep the artifacts and the name so the owner can restore it
+            trash
+                .trash(function_info)
+                .await
+                .map_err(|e| internal_error(format!("Failed to move function to trash: {e}")))?;
+            info!(
+                "Function '{name}' moved to trash for {} hours",
+                trash.retention().as_secs() / 3600
+            );
+        } else {
             // Remove WASM file using direct name
-            let wasm_filename = format!("{name}.wasm");
-            let wasm_path = server.functions_dir.join(wasm_filename);
-            if wasm_path.exists() {
-                if let Err(e) = fs::remove_file(&wasm_path) {
-                    error!("Failed to remove WASM file: {e}");
-                } else {
-                    debug!("Successfully removed WASM file for function '{name}'");
-                }
+            if let Err(e) = server.storage.delete(&wasm_key(&name)).await {
+                error!("Failed to remove WASM file: {e}");
+            } else {
+                debug!("Successfully removed WASM file for function '{name}'");
             }
-            let cwasm_path = wasm_path.with_extension("cwasm");
+            let cwasm_path = server.functions_dir.join(format!("{name}.cwasm"));
             if cwasm_path.exists() {
                 if let Err(e) = fs::remove_file(&cwasm_path) {
                     error!("Failed to remove CWASM file: {e}");
@@ -302,85 +1179,3802 @@ impl FunctionServiceImpl {
                 }
             }
 
-            // Remove metadata from sled
-            match self.functions_tree.remove(name.as_bytes()) {
-                Ok(_) => debug!("Successfully removed metadata for function '{name}'"),
-                Err(e) => error!("Failed to remove function metadata for '{name}': {e}"),
-                // We don't return an error here because the function was already removed
+            if let Err(e) =
+                function_data::remove(server.metadata.as_ref(), &server.metadata_db, &name)
+            {
+                error!("Failed to remove data for function '{name}': {e}");
             }
 
-            // Remove the project from the user's list
-            match server.github_auth.remove_project(&username, &name).await {
+            // Remove the project from the owner's list
+            let owner = &function_info.owner;
+            match server.github_auth.remove_project(owner, &name).await {
                 Ok(_) => {
-                    debug!("Removed project '{name}' for user '{username}'");
+                    debug!("Removed project '{name}' for owner '{owner}'");
                 }
                 Err(e) => {
                     error!("Failed to remove project: {e}");
                 }
             }
+        }
 
-            info!("Function '{name}' unpublished successfully");
-            Ok(())
-        } else {
-            error!("Function '{name}' not found for unpublish operation");
-            Err(FunctionError::NotFound(format!(
-                "Function '{name}' not found"
-            )))
+        // Remove the metadata, which completes the delete
+        match intents.commit(&name, None) {
+            Ok(()) => debug!("Successfully removed metadata for function '{name}'"),
+            Err(e) => error!("Failed to remove function metadata for '{name}': {e}"),
+            // We don't return an error here because the function was already removed
         }
-    }
 
-    async fn get_metrics_impl(&self, github_auth_token: String) -> FunctionResult<Metrics> {
-        // Use the new combined authentication function
-        let server = SERVER.get().unwrap();
-        let (username, is_valid) = server
-            .github_auth
-            .authenticate_github(&github_auth_token)
+        // A canary doesn't outlive its function, nor do its route handlers and aliases
+        if let Err(e) = server.canaries.abort(&name).await {
+            error!("Failed to remove canary of '{name}': {e}");
+        }
+        if let Some(aliases) = ALIASES.get() {
+            if let Err(e) = aliases.remove_all(&name) {
+                error!("Failed to remove aliases of '{name}': {e}");
+            }
+        }
+        match server.routes.remove(&name).await {
+            Ok(versions) => versions
+                .iter()
+                .for_each(|version| server.remove_from_cache(version)),
+            Err(e) => error!("Failed to remove route handlers of '{name}': {e}"),
+        }
+
+        // Stop routing to the function
+        server.remove_from_cache(&name);
+        server.remove_from_cache(&format!("{name}{CANARY_SUFFIX}"));
+        if let Some(anomalies) = ANOMALIES.get() {
+            anomalies.forget_function(&name);
+        }
+        server.artifacts.forget_function(&name);
+        Ok(())
+    }
+
+    async fn unpublish_impl(&self, name: String, github_auth_token: String) -> FunctionResult<()> {
+        info!("Processing unpublish request for function: {name}");
+
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage)
+            .await
+            .inspect_err(|e| error!("Authentication failed for unpublish operation: {e}"))?;
+
+        info!("Authentication successful for user: {username}");
+
+        let _lock = function_locks::lock(&name).await;
+        // Check if function exists
+        let entry_result = self
+            .metadata
+            .get(FUNCTIONS_DB_TREE, &name)
+            .map_err(|e| internal_error(format!("Failed to get function metadata: {e}")))?;
+
+        if let Some(entry_bytes) = entry_result {
+            // Deserialize the function info
+            let function_info = match decode_function_info(&entry_bytes) {
+                Ok(info) => info,
+                Err(e) => {
+                    error!("Failed to deserialize function info: {}", e);
+                    return Err(internal_error(format!(
+                        "Failed to deserialize function info: {e}"
+                    )));
+                }
+            };
+
+            // Check if user owns the function
+            require_role(
+                &function_info.owner,
+                &username,
+                &github_auth_token,
+                TeamRole::Owner,
+                "You don't have permission to unpublish this function",
+            )
+            .await
+            .inspect_err(|_| {
+                error!(
+                    "Permission denied: function owned by {} but requested by {}",
+                    function_info.owner, username
+                )
+            })?;
+
+            self.remove_function(function_info, TRASH.get()).await?;
+
+            info!("Function '{name}' unpublished successfully");
+            Ok(())
+        } else {
+            error!("Function '{name}' not found for unpublish operation");
+            Err(FaastaError::NotFound(format!(
+                "Function '{name}' not found"
+            )))
+        }
+    }
+
+    async fn restore_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+
+        let _lock = function_locks::lock(&name).await;
+        let trash = TRASH.get().ok_or_else(|| {
+            FaastaError::NotFound(
+                "This server deletes unpublished functions immediately".to_string(),
+            )
+        })?;
+        let trashed = trash.get(&name).ok_or_else(|| {
+            FaastaError::NotFound(format!("Function '{name}' is not in the trash"))
+        })?;
+        require_role(
+            &trashed.info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Owner,
+            "You don't have permission to restore this function",
+        )
+        .await?;
+
+        if self.function_info(&name).is_ok() {
+            return Err(FaastaError::InvalidInput(format!(
+                "Function '{name}' has been published again since it was deleted"
+            )));
+        }
+
+        // The policies may have changed since it was published
+        if POLICIES
+            .get()
+            .is_some_and(|policies| !policies.names().is_empty())
+        {
+            let server = SERVER.get().unwrap();
+            let wasm = server
+                .storage
+                .get(&trashed_key(&name))
+                .await
+                .map_err(|e| internal_error(format!("Failed to read trashed function: {e}")))?
+                .ok_or_else(|| {
+                    internal_error(format!("Trashed artifact of '{name}' is missing"))
+                })?;
+            check_artifact(&server.engine, &name, &username, &wasm).await?;
+        }
+
+        // A crash from here on is recovered at the next startup
+        let intents = intents()?;
+        let intent = Intent::Restore {
+            info: trashed.info.clone(),
+        };
+        intents
+            .begin(&name, &intent)
+            .map_err(|e| internal_error(format!("Failed to record the restore: {e}")))?;
+
+        // A failure part way is rolled forward, as a crash would be
+        let restored = async {
+            trash
+                .restore(&name)
+                .await
+                .map_err(|e| internal_error(format!("Failed to restore function: {e}")))?;
+            let meta = bincode::encode_to_vec(&trashed.info, bincode::config::standard()).map_err(
+                |e| internal_error(format!("Failed to serialize function metadata: {e}")),
+            )?;
+            // Persist metadata, which completes the restore
+            intents
+                .commit(&name, Some(meta))
+                .map_err(|e| internal_error(format!("Failed to persist function metadata: {e}")))
+        }
+        .await;
+        if let Err(e) = restored {
+            if let Err(recover_error) = intents.recover(&name, &intent).await {
+                error!("Failed to finish the failed restore of '{name}': {recover_error}");
+            }
+            return Err(e);
+        }
+
+        info!("Function '{name}' restored from trash");
+        Ok(format!("Function '{name}' restored successfully"))
+    }
+
+    async fn deploy_queue_position_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<u32>> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        Ok(DEPLOY_QUEUE
+            .get()
+            .and_then(|queue| queue.position(&username, &name)))
+    }
+
+    async fn begin_upload_impl(
+        &self,
+        size: u64,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        check_artifact_size(size)?;
+        uploads()?
+            .blocking(move |uploads| uploads.begin(&username, size))
+            .await
+            .map_err(begin_upload_error)
+    }
+
+    async fn begin_delta_upload_impl(
+        &self,
+        size: u64,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<DeltaUpload> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        check_artifact_size(size)?;
+        // Only those who may deploy the function get to reuse its artifact
+        let mut base = None;
+        if let Ok(info) = self.function_info(&name) {
+            let allowed = require_role(
+                &info.owner,
+                &username,
+                &github_auth_token,
+                TeamRole::Developer,
+                "",
+            )
+            .await
+            .is_ok();
+            if allowed {
+                let server = SERVER.get().unwrap();
+                base = server
+                    .storage
+                    .get(&wasm_key(&name))
+                    .await
+                    .map_err(|e| internal_error(format!("Failed to read artifact: {e}")))?;
+            }
+        }
+        uploads()?
+            .blocking(move |uploads| uploads.begin_delta(&username, size, base.as_deref()))
+            .await
+            .map_err(begin_upload_error)
+    }
+
+    async fn upload_known_chunks_impl(
+        &self,
+        upload_id: String,
+        offset: Option<u64>,
+        digests: Vec<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let uploads = uploads()?;
+        if uploads.remaining(&username, &upload_id).is_none() {
+            return Err(FaastaError::NotFound(format!(
+                "Upload '{upload_id}' not found"
+            )));
+        }
+        uploads
+            .blocking(move |uploads| uploads.append_known(&username, &upload_id, offset, &digests))
+            .await
+            .map_err(|e| FaastaError::InvalidInput(e.to_string()))
+    }
+
+    async fn upload_chunk_impl(
+        &self,
+        upload_id: String,
+        offset: Option<u64>,
+        chunk: Vec<u8>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let uploads = uploads()?;
+        let remaining = uploads
+            .remaining(&username, &upload_id)
+            .ok_or_else(|| FaastaError::NotFound(format!("Upload '{upload_id}' not found")))?;
+        if chunk.len() > faasta_interface::UPLOAD_CHUNK_SIZE {
+            return Err(FaastaError::InvalidInput(format!(
+                "Upload chunks may be at most {} bytes",
+                faasta_interface::UPLOAD_CHUNK_SIZE
+            )));
+        }
+        // A resent chunk may lie within what was received
+        if offset.is_none() && chunk.len() as u64 > remaining {
+            uploads.discard(&username, &upload_id);
+            return Err(FaastaError::InvalidInput(
+                "Upload is larger than its announced size".to_string(),
+            ));
+        }
+        uploads
+            .blocking(move |uploads| uploads.append(&username, &upload_id, offset, &chunk))
+            .await
+            .map_err(append_error)
+    }
+
+    async fn attach_provenance_impl(
+        &self,
+        upload_id: String,
+        document: Vec<u8>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        if document.len() > faasta_interface::MAX_PROVENANCE_SIZE {
+            return Err(FaastaError::TooLarge {
+                size: document.len() as u64,
+                limit: faasta_interface::MAX_PROVENANCE_SIZE as u64,
+            });
+        }
+        // Refuse what can't be provenance now; the digest is checked at publish
+        Statement::parse(&document).map_err(FaastaError::InvalidInput)?;
+        uploads()?
+            .attach_provenance(&username, &upload_id, document)
+            .map_err(|e| FaastaError::NotFound(e.to_string()))
+    }
+
+    async fn publish_upload_impl(
+        &self,
+        upload_id: String,
+        name: String,
+        target: PublishTarget,
+        keep_warm: Option<bool>,
+        metadata: Option<FunctionMetadata>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let uploads = uploads()?;
+        match uploads.remaining(&username, &upload_id) {
+            None => {
+                return Err(FaastaError::NotFound(format!(
+                    "Upload '{upload_id}' not found"
+                )))
+            }
+            Some(0) => {}
+            Some(missing) => {
+                return Err(FaastaError::InvalidInput(format!(
+                    "Upload is incomplete, {missing} bytes are missing"
+                )))
+            }
+        }
+        let upload = uploads
+            .finish(&username, &upload_id)
+            .map_err(|e| internal_error(format!("Failed to read upload: {e}")))?
+            .ok_or_else(|| FaastaError::NotFound(format!("Upload '{upload_id}' not found")))?;
+
+        self.publish_to_target(
+            upload.wasm,
+            upload.provenance,
+            name,
+            target,
+            keep_warm,
+            metadata,
+            github_auth_token,
+        )
+        .await
+    }
+
+    async fn publish_routes_impl(
+        &self,
+        name: String,
+        routes: Vec<RouteUpload>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's routes",
+        )
+        .await?;
+        let _permit = deploy_queue::wait_for_turn(&username, &name).await;
+        let handlers = self.store_route_handlers(&name, routes, &username).await?;
+
+        let removed = server
+            .routes
+            .replace(&name, &handlers)
+            .await
+            .map_err(|e| internal_error(format!("Failed to swap route handlers: {e}")))?;
+        for version in removed {
+            server.remove_from_cache(&version);
+        }
+        info!(
+            "User '{}' published {} route handlers for '{}'",
+            username,
+            handlers.len(),
+            name
+        );
+        Ok(if handlers.is_empty() {
+            format!("Removed the route handlers of '{name}'")
+        } else {
+            let routes: Vec<&str> = handlers.iter().map(|h| h.route.as_str()).collect();
+            format!("Published handlers for {} of '{name}'", routes.join(", "))
+        })
+    }
+
+    async fn stage_routes_impl(
+        &self,
+        name: String,
+        routes: Vec<RouteUpload>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        validate_name(&name)?;
+        // Who may publish a new function is checked by its publish
+        match self.function_info(&name) {
+            Ok(function_info) => {
+                require_role(
+                    &function_info.owner,
+                    &username,
+                    &github_auth_token,
+                    TeamRole::Developer,
+                    "You don't have permission to change this function's routes",
+                )
+                .await?
+            }
+            Err(FaastaError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+        let _permit = deploy_queue::wait_for_turn(&username, &name).await;
+        let handlers = self.store_route_handlers(&name, routes, &username).await?;
+        server
+            .routes
+            .stage(&username, &name, &handlers)
+            .map_err(|e| internal_error(format!("Failed to stage route handlers: {e}")))?;
+        Ok(format!(
+            "Staged {} route handlers for the next publish of '{name}'",
+            handlers.len()
+        ))
+    }
+
+    /// Check the components of the finished uploads of `routes` and store their
+    /// artifacts, before any is routed to. Callers wait for their deploy's turn.
+    async fn store_route_handlers(
+        &self,
+        name: &str,
+        routes: Vec<RouteUpload>,
+        username: &str,
+    ) -> FunctionResult<Vec<RouteHandler>> {
+        let server = SERVER.get().unwrap();
+        validate_routes(routes.iter().map(|route| route.route.as_str()))
+            .map_err(FaastaError::InvalidInput)?;
+
+        let uploads = uploads()?;
+        let mut checked = Vec::with_capacity(routes.len());
+        for route in routes {
+            if uploads.remaining(username, &route.upload_id) != Some(0) {
+                return Err(FaastaError::InvalidInput(format!(
+                    "Upload '{}' for route '{}' is missing or incomplete",
+                    route.upload_id, route.route
+                )));
+            }
+            let upload = uploads
+                .finish(username, &route.upload_id)
+                .map_err(|e| internal_error(format!("Failed to read upload: {e}")))?
+                .ok_or_else(|| {
+                    FaastaError::NotFound(format!("Upload '{}' not found", route.upload_id))
+                })?;
+            check_artifact_size(upload.wasm.len() as u64)?;
+            let provenance = check_provenance(&upload.wasm, upload.provenance, name)?;
+            checked.push((route.route, upload.wasm, provenance));
+        }
+        let mut handlers = Vec::with_capacity(checked.len());
+        let mut provenances = Vec::with_capacity(checked.len());
+        for (route, wasm, provenance) in checked {
+            let cwasm = compiler::precompile(&server.engine, &wasm)
+                .await
+                .map_err(|_| {
+                    FaastaError::InvalidInput(format!("Invalid Wasm for route '{route}'"))
+                })?;
+            let imports = check_component(&server.engine, &cwasm, name)?;
+            check_policies(&Artifact {
+                function: name,
+                uploader: username,
+                wasm: &wasm,
+                cwasm: &cwasm,
+                imports: &imports,
+            })
+            .await?;
+            let digest = function_data::artifact_digest(&wasm);
+            server
+                .routes
+                .save(name, &digest, &wasm, &cwasm)
+                .await
+                .map_err(|e| internal_error(format!("Failed to store route handler: {e}")))?;
+            handlers.push(RouteHandler { route, digest });
+            provenances.push(provenance);
+        }
+        provenances.into_iter().for_each(record_provenance);
+        Ok(handlers)
+    }
+
+    async fn publish_static_assets_impl(
+        &self,
+        name: String,
+        upload_id: Option<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's static assets",
+        )
+        .await?;
+        let assets = STATIC_ASSETS
+            .get()
+            .ok_or_else(|| not_enabled("static assets"))?;
+
+        let Some(upload_id) = upload_id else {
+            let removed = assets
+                .remove(&name)
+                .map_err(|e| internal_error(format!("Failed to remove static assets: {e}")))?;
+            return Ok(if removed {
+                format!("Removed the static assets of '{name}'")
+            } else {
+                format!("Function '{name}' has no static assets")
+            });
+        };
+        let uploads = uploads()?;
+        if uploads.remaining(&username, &upload_id) != Some(0) {
+            return Err(FaastaError::InvalidInput(format!(
+                "Upload '{upload_id}' is missing or incomplete"
+            )));
+        }
+        let upload = uploads
+            .finish(&username, &upload_id)
+            .map_err(|e| internal_error(format!("Failed to read upload: {e}")))?
+            .ok_or_else(|| FaastaError::NotFound(format!("Upload '{upload_id}' not found")))?;
+        let bundle = StaticAsset::decode_bundle(&upload.wasm).map_err(FaastaError::InvalidInput)?;
+        if bundle.len() > MAX_STATIC_ASSETS {
+            return Err(FaastaError::QuotaExceeded {
+                quota: "static asset files".to_string(),
+                limit: MAX_STATIC_ASSETS as u64,
+            });
+        }
+        let bytes: u64 = bundle.iter().map(|asset| asset.content.len() as u64).sum();
+        if bytes > assets.max_bytes() {
+            return Err(FaastaError::QuotaExceeded {
+                quota: "static asset bytes".to_string(),
+                limit: assets.max_bytes(),
+            });
+        }
+        for asset in &bundle {
+            validate_static_path(&asset.path).map_err(FaastaError::InvalidInput)?;
+        }
+
+        assets
+            .publish(&name, bundle)
+            .map_err(|e| internal_error(format!("Failed to store static assets: {e}")))?;
+        let (files, bytes) = assets.usage(&name);
+        info!(
+            "User '{}' published {} static assets ({} bytes) for '{}'",
+            username, files, bytes, name
+        );
+        Ok(format!(
+            "Published {files} static assets ({} KB) for '{name}'",
+            bytes.div_ceil(1024)
+        ))
+    }
+
+    async fn set_cors_policy_impl(
+        &self,
+        name: String,
+        policy: Option<CorsPolicy>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's CORS policy",
+        )
+        .await?;
+        let cors = CORS.get().ok_or_else(|| not_enabled("CORS policies"))?;
+        if let Some(policy) = &policy {
+            policy.validate().map_err(FaastaError::InvalidInput)?;
+        }
+
+        // An unchanged policy isn't written again, or logged, on every deploy
+        if cors.get(&name) == policy {
+            return Ok(format!("CORS policy of '{name}' is unchanged"));
+        }
+        cors.set(&name, policy.as_ref())
+            .map_err(|e| internal_error(format!("Failed to store CORS policy: {e}")))?;
+        info!(
+            "User '{}' {} the CORS policy of '{}'",
+            username,
+            if policy.is_some() { "set" } else { "removed" },
+            name
+        );
+        Ok(match policy {
+            Some(policy) => format!(
+                "CORS policy of '{name}' allows {}",
+                policy.allowed_origins.join(", ")
+            ),
+            None => format!("Removed the CORS policy of '{name}'"),
+        })
+    }
+
+    async fn protect_function_impl(
+        &self,
+        name: String,
+        private: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<String>> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change who may invoke this function",
+        )
+        .await?;
+        let keys = ACCESS_KEYS
+            .get()
+            .ok_or_else(|| not_enabled("private functions"))?;
+
+        if !private {
+            let removed = keys
+                .remove(&name)
+                .map_err(|e| internal_error(format!("Failed to remove the access key: {e}")))?;
+            if removed {
+                info!("User '{}' made '{}' public", username, name);
+            }
+            return Ok(None);
+        }
+        let key = keys
+            .rotate(&name)
+            .map_err(|e| internal_error(format!("Failed to store the access key: {e}")))?;
+        info!("User '{}' set a new access key for '{}'", username, name);
+        Ok(Some(key))
+    }
+
+    async fn set_jwt_auth_impl(
+        &self,
+        name: String,
+        policy: Option<JwtAuthPolicy>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's authentication",
+        )
+        .await?;
+        let jwt_auth = JWT_AUTH
+            .get()
+            .ok_or_else(|| not_enabled("JWT authentication"))?;
+        if let Some(policy) = &policy {
+            policy.validate().map_err(FaastaError::InvalidInput)?;
+        }
+
+        // An unchanged policy isn't written again, or logged, on every deploy
+        if jwt_auth.get(&name) == policy {
+            return Ok(format!("Authentication of '{name}' is unchanged"));
+        }
+        jwt_auth
+            .set(&name, policy.as_ref())
+            .map_err(|e| internal_error(format!("Failed to store JWT policy: {e}")))?;
+        info!(
+            "User '{}' {} the JWT policy of '{}'",
+            username,
+            if policy.is_some() { "set" } else { "removed" },
+            name
+        );
+        Ok(match policy {
+            Some(policy) => format!("'{name}' requires tokens issued by {}", policy.issuer),
+            None => format!("'{name}' no longer requires tokens"),
+        })
+    }
+
+    async fn set_transforms_impl(
+        &self,
+        name: String,
+        rules: Vec<TransformRule>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's transformation rules",
+        )
+        .await?;
+        let transforms = TRANSFORMS
+            .get()
+            .ok_or_else(|| not_enabled("transformation rules"))?;
+        if rules.len() > MAX_TRANSFORM_RULES {
+            return Err(FaastaError::QuotaExceeded {
+                quota: "transformation rules".to_string(),
+                limit: MAX_TRANSFORM_RULES as u64,
+            });
+        }
+        for rule in &rules {
+            rule.validate().map_err(FaastaError::InvalidInput)?;
+        }
+
+        // Unchanged rules aren't written again, or logged, on every deploy
+        if transforms.get(&name) == rules {
+            return Ok(format!("Transformation rules of '{name}' are unchanged"));
+        }
+        transforms
+            .set(&name, &rules)
+            .map_err(|e| internal_error(format!("Failed to store transformation rules: {e}")))?;
+        info!(
+            "User '{}' set {} transformation rules for '{}'",
+            username,
+            rules.len(),
+            name
+        );
+        Ok(if rules.is_empty() {
+            format!("Removed the transformation rules of '{name}'")
+        } else {
+            format!("Set {} transformation rules for '{name}'", rules.len())
+        })
+    }
+
+    async fn publish_from_registry_impl(
+        &self,
+        reference: String,
+        name: String,
+        target: PublishTarget,
+        credentials: Option<RegistryCredentials>,
+        keep_warm: Option<bool>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let registries = REGISTRIES
+            .get()
+            .ok_or_else(|| not_enabled("registry pulls"))?;
+        let reference: OciReference = reference.parse().map_err(FaastaError::InvalidInput)?;
+        if !registries.is_allowed(&reference.registry) {
+            return Err(FaastaError::PermissionDenied(format!(
+                "Registry '{}' is not allowed on this server",
+                reference.registry
+            )));
+        }
+
+        let wasm_file = registries
+            .pull(&reference, credentials.as_ref(), max_artifact_bytes())
+            .await
+            .map_err(|e| match e.downcast_ref::<SizeExceeded>() {
+                Some(exceeded) => FaastaError::TooLarge {
+                    size: exceeded.size,
+                    limit: exceeded.limit,
+                },
+                None => FaastaError::InvalidInput(format!("Failed to pull {reference}: {e}")),
+            })?;
+        info!(
+            "User {username} pulled {reference} ({} bytes) for function {name}",
+            wasm_file.len()
+        );
+
+        self.publish_to_target(
+            wasm_file,
+            None,
+            name,
+            target,
+            keep_warm,
+            None,
+            github_auth_token,
+        )
+        .await
+    }
+
+    /// Publish an artifact received in chunks or pulled from a registry
+    #[allow(clippy::too_many_arguments)]
+    async fn publish_to_target(
+        &self,
+        wasm_file: Vec<u8>,
+        provenance: Option<Vec<u8>>,
+        name: String,
+        target: PublishTarget,
+        keep_warm: Option<bool>,
+        metadata: Option<FunctionMetadata>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        match target {
+            PublishTarget::Function => {
+                self.publish_impl(
+                    wasm_file,
+                    provenance,
+                    name,
+                    None,
+                    keep_warm,
+                    metadata,
+                    github_auth_token,
+                )
+                .await
+            }
+            PublishTarget::Team(team) => {
+                self.publish_impl(
+                    wasm_file,
+                    provenance,
+                    name,
+                    Some(team),
+                    keep_warm,
+                    metadata,
+                    github_auth_token,
+                )
+                .await
+            }
+            PublishTarget::Canary { .. } if keep_warm.is_some() => Err(FaastaError::InvalidInput(
+                "Keep-warm is set on the function, not on its canary".to_string(),
+            )),
+            PublishTarget::Canary { .. } if metadata.is_some() => Err(FaastaError::InvalidInput(
+                "Metadata is set on the function, not on its canary".to_string(),
+            )),
+            PublishTarget::Canary { weight } => {
+                self.publish_canary_impl(wasm_file, provenance, name, weight, github_auth_token)
+                    .await
+            }
+        }
+    }
+
+    async fn apply_impl(
+        &self,
+        definition: FunctionDefinition,
+        credentials: Option<RegistryCredentials>,
+        dry_run: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<ApplyOutcome> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        definition.validate().map_err(FaastaError::InvalidInput)?;
+        let name = definition.metadata.name;
+        validate_name(&name)?;
+        let spec = definition.spec;
+        spec.artifact
+            .parse::<OciReference>()
+            .map_err(FaastaError::InvalidInput)?;
+
+        let existing = match self.function_info(&name) {
+            Ok(info) => Some(info),
+            Err(FaastaError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        if let Some(info) = &existing {
+            authorize_update(
+                &info.owner,
+                spec.team.as_deref(),
+                &username,
+                &github_auth_token,
+            )
+            .await?;
+        }
+        if !spec.keep_warm.is_empty() {
+            let owner = match (&existing, &spec.team) {
+                (Some(info), _) => info.owner.clone(),
+                (None, Some(team)) => team_owner(team),
+                (None, None) => username.clone(),
+            };
+            keep_warm::check_schedule(&owner, &name)?;
+        }
+        let published = |name: &str| {
+            function_data::published_digest(server.metadata.as_ref(), name)
+                .map_err(|e| internal_error(format!("Failed to read the artifact digest: {e}")))
+        };
+
+        // Redeploy when the reference changed or the function was published over
+        // since it was last applied
+        let applied = server.specs.get(&name);
+        let digest = published(&name)?;
+        let redeploy = match (&existing, &applied) {
+            (Some(_), Some(applied)) => {
+                applied.spec.artifact != spec.artifact
+                    || digest.as_deref() != Some(applied.digest.as_str())
+            }
+            _ => true,
+        };
+        let mut changes = Vec::new();
+        if redeploy {
+            changes.push(match existing {
+                Some(_) => format!("artifact: deploy {}", spec.artifact),
+                None => format!("artifact: create from {}", spec.artifact),
+            });
+        }
+        changes.extend(specs::setting_changes(
+            applied.as_ref().map(|applied| &applied.spec),
+            &spec,
+        ));
+        if dry_run || changes.is_empty() {
+            return Ok(ApplyOutcome { changes, digest });
+        }
+
+        let digest = if redeploy {
+            let target = match &spec.team {
+                Some(team) => PublishTarget::Team(team.clone()),
+                None => PublishTarget::Function,
+            };
+            self.publish_from_registry_impl(
+                spec.artifact.clone(),
+                name.clone(),
+                target,
+                credentials,
+                None,
+                github_auth_token,
+            )
+            .await?;
+            published(&name)?
+        } else {
+            digest
+        };
+        let applied = AppliedSpec {
+            spec,
+            digest: digest.clone().unwrap_or_default(),
+        };
+        server
+            .specs
+            .set(&name, &applied)
+            .map_err(|e| internal_error(format!("Failed to store the function's spec: {e}")))?;
+        // Warm instances run with the environment and limits they were created with
+        server.remove_from_cache(&name);
+        server.remove_from_cache(&format!("{name}{CANARY_SUFFIX}"));
+        if is_kept_warm(&name) {
+            keep_warm::spawn_warm(name.clone());
+        }
+        info!(
+            "User {username} applied {} change(s) to {name}",
+            changes.len()
+        );
+
+        Ok(ApplyOutcome { changes, digest })
+    }
+
+    async fn publish_canary_impl(
+        &self,
+        wasm_file: Vec<u8>,
+        provenance: Option<Vec<u8>>,
+        name: String,
+        weight: u8,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        validate_canary_weight(weight)?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to release this function",
+        )
+        .await?;
+
+        check_artifact_size(wasm_file.len() as u64)?;
+        let provenance = check_provenance(&wasm_file, provenance, &name)?;
+        // Wait our turn so a burst of deploys can't starve request serving
+        let _permit = deploy_queue::wait_for_turn(&username, &name).await;
+        let cwasm = compiler::precompile(&server.engine, &wasm_file)
+            .await
+            .map_err(|_| FaastaError::InvalidInput("Invalid Wasm".to_string()))?;
+        let imports = check_component(&server.engine, &cwasm, &name)?;
+        check_policies(&Artifact {
+            function: &name,
+            uploader: &username,
+            wasm: &wasm_file,
+            cwasm: &cwasm,
+            imports: &imports,
+        })
+        .await?;
+
+        server
+            .canaries
+            .save(&name, &wasm_file, &cwasm)
+            .await
+            .map_err(|e| internal_error(format!("Failed to store canary: {e}")))?;
+        record_provenance(provenance);
+        server.remove_from_cache(&format!("{name}{CANARY_SUFFIX}"));
+        server
+            .canaries
+            .set_weight(&name, weight)
+            .map_err(|e| internal_error(format!("Failed to save canary weight: {e}")))?;
+
+        info!(
+            "'{}' released a canary of '{}' at {}%",
+            username, name, weight
+        );
+        Ok(format!(
+            "Canary of '{name}' published, receiving {weight}% of requests"
+        ))
+    }
+
+    async fn update_canary_impl(
+        &self,
+        name: String,
+        update: CanaryUpdate,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to release this function",
+        )
+        .await?;
+        if server.canaries.weight(&name).is_none() {
+            return Err(FaastaError::NotFound(format!(
+                "'{name}' has no canary release"
+            )));
+        }
+
+        let message = match update {
+            CanaryUpdate::Weight(weight) => {
+                validate_canary_weight(weight)?;
+                server
+                    .canaries
+                    .set_weight(&name, weight)
+                    .map_err(|e| internal_error(format!("Failed to save canary weight: {e}")))?;
+                format!("Canary of '{name}' now receives {weight}% of requests")
+            }
+            CanaryUpdate::Promote => {
+                server
+                    .canaries
+                    .promote(&name)
+                    .await
+                    .map_err(|e| internal_error(format!("Failed to promote canary: {e}")))?;
+                match server.storage.get(&wasm_key(&name)).await {
+                    Ok(Some(wasm)) => {
+                        if let Err(e) =
+                            function_data::record_artifact(server.metadata.as_ref(), &name, &wasm)
+                        {
+                            error!("Failed to record the artifact digest of '{name}': {e}");
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => error!("Failed to read the promoted artifact of '{name}': {e}"),
+                }
+                self.save_function_info(&FunctionInfo {
+                    published_at: chrono::Utc::now().to_rfc3339(),
+                    ..function_info
+                })?;
+                server.remove_from_cache(&name);
+                if is_kept_warm(&name) {
+                    keep_warm::spawn_warm(name.clone());
+                }
+                journal::record(
+                    EventSeverity::Info,
+                    ServerEventKind::CanaryPromoted,
+                    Some(&name),
+                    format!("'{username}' promoted the canary to stable"),
+                );
+                format!("Canary of '{name}' promoted to stable")
+            }
+            CanaryUpdate::Abort => {
+                server
+                    .canaries
+                    .abort(&name)
+                    .await
+                    .map_err(|e| internal_error(format!("Failed to remove canary: {e}")))?;
+                journal::record(
+                    EventSeverity::Info,
+                    ServerEventKind::CanaryAborted,
+                    Some(&name),
+                    format!("'{username}' aborted the canary"),
+                );
+                format!("Canary of '{name}' aborted")
+            }
+        };
+        server.remove_from_cache(&format!("{name}{CANARY_SUFFIX}"));
+
+        info!("'{}': {}", username, message);
+        Ok(message)
+    }
+
+    async fn rename_function_impl(
+        &self,
+        name: String,
+        new_name: String,
+        redirect_hours: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+
+        validate_name(&new_name)?;
+        if new_name == name {
+            return Err(FaastaError::InvalidInput(
+                "The new name is the same as the current one".to_string(),
+            ));
+        }
+        if redirect_hours > MAX_REDIRECT_HOURS {
+            return Err(FaastaError::InvalidInput(format!(
+                "Redirects can last at most {MAX_REDIRECT_HOURS} hours"
+            )));
+        }
+
+        let _guard = RENAME_LOCK.lock().await;
+        let _lock = function_locks::lock(&name).await;
+
+        let mut function_info = self.function_info(&name)?;
+
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Owner,
+            "You don't have permission to rename this function",
+        )
+        .await?;
+
+        // The target name must be free, apart from the owner's own redirect
+        self.ensure_name_free(&new_name, &function_info.owner)?;
+
+        if server.canaries.weight(&name).is_some() {
+            return Err(FaastaError::InvalidInput(format!(
+                "'{name}' has a canary release. Promote or abort it before renaming."
+            )));
+        }
+        if !server.routes.get(&name).is_empty() {
+            return Err(FaastaError::InvalidInput(format!(
+                "'{name}' has route handlers. Deploy it without them before renaming."
+            )));
+        }
+
+        // Move the artifacts, putting back whatever moved if a later step fails
+        let (from_key, to_key) = (wasm_key(&name), wasm_key(&new_name));
+        let move_error =
+            |e: String| internal_error(format!("Failed to move function artifacts: {e}"));
+        server
+            .storage
+            .rename(&from_key, &to_key)
+            .await
+            .map_err(|e| move_error(e.to_string()))?;
+        // An evicted function has no local artifact to move; it hydrates under the new name
+        let from = server.functions_dir.join(format!("{name}.cwasm"));
+        let to = server.functions_dir.join(format!("{new_name}.cwasm"));
+        if from.exists() {
+            if let Err(e) = fs::rename(&from, &to) {
+                let _ = server.storage.rename(&to_key, &from_key).await;
+                return Err(move_error(e.to_string()));
+            }
+        }
+
+        // Swap the metadata, the owner's project and the aliases in one batch
+        function_info.name = new_name.clone();
+        function_info.usage = function_usage(&new_name);
+        let meta = bincode::encode_to_vec(&function_info, bincode::config::standard())
+            .map_err(|e| internal_error(format!("Failed to serialize function metadata: {e}")))?;
+        let project = server
+            .github_auth
+            .rename_project(&function_info.owner, &name, &new_name)
+            .map_err(|e| internal_error(format!("Failed to update projects: {e}")))?;
+        let mut swap = vec![
+            Change::Remove {
+                collection: FUNCTIONS_DB_TREE,
+                key: name.clone(),
+            },
+            Change::Insert {
+                collection: FUNCTIONS_DB_TREE,
+                key: new_name.clone(),
+                value: meta,
+            },
+        ];
+        swap.extend(project);
+        if let Some(transfers) = TRANSFERS.get() {
+            swap.extend(
+                transfers
+                    .rename(&name, &new_name)
+                    .map_err(|e| internal_error(format!("Failed to read transfer offers: {e}")))?,
+            );
+        }
+        if let Some(aliases) = ALIASES.get() {
+            swap.extend(
+                aliases
+                    .retarget(&name, &new_name)
+                    .map_err(|e| internal_error(format!("Failed to read aliases: {e}")))?,
+            );
+        }
+        if let Err(e) = self.metadata.apply(&swap) {
+            let _ = fs::rename(to, from);
+            let _ = server.storage.rename(&to_key, &from_key).await;
+            return Err(internal_error(format!(
+                "Failed to persist function metadata: {e}"
+            )));
+        }
+        if let Some(aliases) = ALIASES.get() {
+            if let Err(e) = aliases.reload() {
+                error!("Failed to reload aliases after renaming '{name}': {e}");
+            }
+        }
+
+        // Follow-up bookkeeping; the function is already reachable under its new name
+        if let Err(e) = function_data::rename(
+            server.metadata.as_ref(),
+            &server.metadata_db,
+            &name,
+            &new_name,
+        ) {
+            error!("Failed to move data from '{name}' to '{new_name}': {e}");
+        }
+        if let Err(e) = rename_function_metrics(&name, &new_name) {
+            error!("Failed to move metrics from '{name}' to '{new_name}': {e}");
+        }
+        if let Some(usage) = USAGE.get() {
+            if let Err(e) = usage.rename_function(&name, &new_name) {
+                error!("Failed to move usage cursor from '{name}' to '{new_name}': {e}");
+            }
+        }
+        if let Some(anomalies) = ANOMALIES.get() {
+            anomalies.forget_function(&name);
+        }
+        server.artifacts.forget_function(&name);
+        server.remove_from_cache(&name);
+
+        if let Err(e) = server.redirects.remove(&new_name) {
+            error!("Failed to remove redirect for '{new_name}': {e}");
+        }
+        if redirect_hours > 0 {
+            server
+                .redirects
+                .add(&name, &new_name, &function_info.owner, redirect_hours)
+                .map_err(|e| internal_error(format!("Failed to create redirect: {e}")))?;
+        }
+
+        info!("Function '{name}' renamed to '{new_name}' by '{username}'");
+        Ok(format!("Function '{name}' renamed to '{new_name}'"))
+    }
+
+    async fn transfer_function_impl(
+        &self,
+        name: String,
+        new_owner: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+        let owner = self.function_info(&name)?.owner;
+        if owner.starts_with(TEAM_OWNER_PREFIX) {
+            return Err(FaastaError::InvalidInput(format!(
+                "'{name}' belongs to a team; manage who works on it with its members"
+            )));
+        }
+        if new_owner.is_empty() || new_owner.starts_with(TEAM_OWNER_PREFIX) {
+            return Err(FaastaError::InvalidInput(
+                "Functions can only be transferred to a user".to_string(),
+            ));
+        }
+        if new_owner == owner {
+            return Err(FaastaError::InvalidInput(format!(
+                "'{name}' already belongs to '{new_owner}'"
+            )));
+        }
+        if !SERVER
+            .get()
+            .unwrap()
+            .github_auth
+            .user_exists(&new_owner)
+            .map_err(|e| internal_error(format!("Failed to read users: {e}")))?
+        {
+            return Err(FaastaError::NotFound(format!(
+                "User '{new_owner}' doesn't exist"
+            )));
+        }
+
+        // Admins move functions at once
+        if roles::effective_role(&username) >= PlatformRole::Admin {
+            self.complete_transfer(&name, &owner, &new_owner).await?;
+            return Ok(format!("Function '{name}' now belongs to '{new_owner}'"));
+        }
+        require_role(
+            &owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Owner,
+            "You don't have permission to transfer this function",
+        )
+        .await?;
+        transfers()?
+            .offer(&name, &owner, &new_owner)
+            .map_err(|e| internal_error(format!("Failed to save transfer: {e}")))?;
+        Ok(format!(
+            "Offered '{name}' to '{new_owner}'. It moves to them once they accept it \
+             with 'cargo faasta transfer {name} --accept' within a week."
+        ))
+    }
+
+    async fn accept_transfer_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+        let offer = transfers()?
+            .pending(&name)
+            .map_err(|e| internal_error(format!("Failed to read transfer: {e}")))?
+            .filter(|offer| offer.to == username)
+            .ok_or_else(|| {
+                FaastaError::NotFound(format!("No transfer of '{name}' to you is pending"))
+            })?;
+        self.complete_transfer(&name, &offer.from, &username)
+            .await?;
+        Ok(format!("Function '{name}' now belongs to you"))
+    }
+
+    async fn set_alias_impl(
+        &self,
+        alias: FunctionAlias,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let aliases = aliases()?;
+        let FunctionAlias { name, target, .. } = &alias;
+        validate_name(name)?;
+        if name == target {
+            return Err(FaastaError::InvalidInput(
+                "A function can't be an alias of itself".to_string(),
+            ));
+        }
+        let function_info = self.function_info(target)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's aliases",
+        )
+        .await?;
+
+        let _guard = RENAME_LOCK.lock().await;
+        match aliases.get(name) {
+            Some(existing) if existing.target != *target => {
+                return Err(FaastaError::InvalidInput(format!(
+                    "'{name}' is already an alias of '{}'",
+                    existing.target
+                )));
+            }
+            // Changing the mode of an alias of the same function
+            Some(_) => {}
+            None => {
+                self.ensure_name_free(name, &function_info.owner)?;
+                let count = aliases
+                    .of(target)
+                    .map_err(|e| internal_error(format!("Failed to read aliases: {e}")))?
+                    .len();
+                if count >= MAX_FUNCTION_ALIASES {
+                    return Err(FaastaError::QuotaExceeded {
+                        quota: "aliases".to_string(),
+                        limit: MAX_FUNCTION_ALIASES as u64,
+                    });
+                }
+                check_project_quota(&function_info.owner, name, 1)?;
+            }
+        }
+        aliases
+            .set(&alias)
+            .map_err(|e| internal_error(format!("Failed to save alias: {e}")))?;
+
+        info!("'{name}' now leads to '{target}', set by '{username}'");
+        Ok(match alias.mode {
+            AliasMode::Alias => format!("'{name}' is now an alias of '{target}'"),
+            AliasMode::Redirect { .. } => format!("'{name}' now redirects to '{target}'"),
+        })
+    }
+
+    async fn remove_alias_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        let aliases = aliases()?;
+        let alias = aliases
+            .get(&name)
+            .ok_or_else(|| FaastaError::NotFound(format!("'{name}' is not an alias")))?;
+        let function_info = self.function_info(&alias.target)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's aliases",
+        )
+        .await?;
+        aliases
+            .remove(&name)
+            .map_err(|e| internal_error(format!("Failed to remove alias: {e}")))?;
+        Ok(format!("Alias '{name}' of '{}' removed", alias.target))
+    }
+
+    async fn list_aliases_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<FunctionAlias>> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function's aliases",
+        )
+        .await?;
+        aliases()?
+            .of(&name)
+            .map_err(|e| internal_error(format!("Failed to read aliases: {e}")))
+    }
+
+    /// Move `name` from `owner` to `new_owner`: the function's metadata, both
+    /// users' projects and the end of any offer, in one batch
+    async fn complete_transfer(
+        &self,
+        name: &str,
+        owner: &str,
+        new_owner: &str,
+    ) -> FunctionResult<()> {
+        let server = SERVER.get().unwrap();
+        let _guard = RENAME_LOCK.lock().await;
+        let _lock = function_locks::lock(name).await;
+
+        // An offer made by an earlier owner doesn't hold
+        let mut function_info = self.function_info(name)?;
+        if function_info.owner != owner {
+            return Err(FaastaError::NotFound(format!(
+                "'{name}' no longer belongs to '{owner}'"
+            )));
+        }
+        // The function's aliases go along
+        let aliases = ALIASES.get().map_or(Ok(0), |aliases| {
+            aliases
+                .of(name)
+                .map(|aliases| aliases.len())
+                .map_err(|e| internal_error(format!("Failed to read aliases: {e}")))
+        })?;
+        check_project_quota(new_owner, name, 1 + aliases)?;
+
+        function_info.owner = new_owner.to_string();
+        let meta = bincode::encode_to_vec(&function_info, bincode::config::standard())
+            .map_err(|e| internal_error(format!("Failed to serialize function metadata: {e}")))?;
+        let projects = server
+            .github_auth
+            .transfer_project(owner, new_owner, name)
+            .map_err(|e| internal_error(format!("Failed to update projects: {e}")))?;
+        let mut changes = vec![
+            Change::Insert {
+                collection: FUNCTIONS_DB_TREE,
+                key: name.to_string(),
+                value: meta,
+            },
+            Transfers::close(name),
+        ];
+        changes.extend(projects);
+        self.metadata
+            .apply(&changes)
+            .map_err(|e| internal_error(format!("Failed to transfer function: {e}")))?;
+        info!("Transferred '{}' from '{}' to '{}'", name, owner, new_owner);
+        Ok(())
+    }
+
+    async fn clone_function_impl(
+        &self,
+        name: String,
+        new_name: String,
+        with_data: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Deploy).await?;
+        validate_name(&new_name)?;
+
+        let source = self.function_info(&name)?;
+        require_role(
+            &source.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to clone this function",
+        )
+        .await?;
+
+        // Clones always belong to the caller
+        let _guard = RENAME_LOCK.lock().await;
+        let _lock = function_locks::lock(&new_name).await;
+        self.ensure_name_free(&new_name, &username)?;
+        check_project_quota(&username, &new_name, 1)?;
+
+        // A crash from here on is recovered at the next startup, as a publish would be
+        let intents = intents()?;
+        let intent = Intent::Publish {
+            owner: username.clone(),
+            new: true,
+        };
+        intents
+            .begin(&new_name, &intent)
+            .map_err(|e| internal_error(format!("Failed to record the clone: {e}")))?;
+
+        // A failure part way is undone, as a crash would be
+        let cloned = async {
+            let copy_error =
+                |e: String| internal_error(format!("Failed to copy function artifacts: {e}"));
+            let new_key = wasm_key(&new_name);
+            match server.storage.copy(&wasm_key(&name), &new_key).await {
+                Ok(true) => {}
+                Ok(false) => return Err(copy_error(format!("'{name}' has no stored WASM file"))),
+                Err(e) => return Err(copy_error(e.to_string())),
+            }
+            let from = server.functions_dir.join(format!("{name}.cwasm"));
+            if from.exists() {
+                fs::copy(
+                    &from,
+                    server.functions_dir.join(format!("{new_name}.cwasm")),
+                )
+                .map_err(|e| copy_error(e.to_string()))?;
+            }
+
+            server
+                .github_auth
+                .add_project(&username, &new_name)
+                .await
+                .map_err(|e| internal_error(format!("Failed to add project: {e}")))?;
+
+            if with_data {
+                function_data::copy(server.metadata.as_ref(), &name, &new_name)
+                    .map_err(|e| internal_error(format!("Failed to copy function data: {e}")))?;
+            }
+            // Other nodes find the clone's artifact by its digest
+            let wasm = server
+                .storage
+                .get(&new_key)
+                .await
+                .map_err(|e| copy_error(e.to_string()))?
+                .ok_or_else(|| copy_error(format!("'{new_name}' has no stored WASM file")))?;
+            if let Err(e) =
+                function_data::record_artifact(server.metadata.as_ref(), &new_name, &wasm)
+            {
+                error!("Failed to record the artifact digest of '{new_name}': {e}");
+            }
+
+            let function_info = FunctionInfo {
+                name: new_name.clone(),
+                owner: username.clone(),
+                published_at: chrono::Utc::now().to_rfc3339(),
+                usage: function_usage(&new_name),
+                // Route handlers aren't copied
+                metadata: FunctionMetadata {
+                    routes: Vec::new(),
+                    ..source.metadata
+                },
+            };
+            let meta = bincode::encode_to_vec(&function_info, bincode::config::standard())
+                .map_err(|e| {
+                    internal_error(format!("Failed to serialize function metadata: {e}"))
+                })?;
+            // Persist metadata, which completes the clone
+            intents
+                .commit(&new_name, Some(meta))
+                .map_err(|e| internal_error(format!("Failed to persist function metadata: {e}")))
+        }
+        .await;
+        if let Err(e) = cloned {
+            if let Err(recover_error) = intents.recover(&new_name, &intent).await {
+                error!("Failed to undo the failed clone to '{new_name}': {recover_error}");
+            }
+            return Err(e);
+        }
+
+        info!("Function '{name}' cloned to '{new_name}' by '{username}'");
+        Ok(format!("Function '{name}' cloned to '{new_name}'"))
+    }
+
+    async fn create_team_impl(
+        &self,
+        name: String,
+        github_org: Option<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<TeamInfo> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate_account(&github_auth_token).await?;
+        validate_name(&name)?;
+
+        let existing = server
+            .github_auth
+            .teams
+            .get(&name)
+            .map_err(|e| internal_error(format!("Failed to read teams: {e}")))?;
+        if existing.is_some() {
+            return Err(FaastaError::InvalidInput(format!(
+                "Team '{name}' already exists"
+            )));
+        }
+
+        let mut team = Team {
+            name: name.clone(),
+            github_org,
+            members: Vec::new(),
+        };
+        team.set_member(&username, TeamRole::Owner);
+        server
+            .github_auth
+            .teams
+            .save(&team)
+            .and_then(|_| {
+                server
+                    .github_auth
+                    .set_team_membership(&username, &name, true)
+            })
+            .map_err(|e| internal_error(format!("Failed to create team: {e}")))?;
+
+        info!("User '{username}' created team '{name}'");
+        team_info(team)
+    }
+
+    async fn list_teams_impl(&self, github_auth_token: String) -> FunctionResult<Vec<TeamInfo>> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        server
+            .github_auth
+            .get_user_teams(&username)
+            .map_err(|e| internal_error(format!("Failed to read teams: {e}")))?
+            .iter()
+            .filter_map(|team| server.github_auth.teams.get(team).transpose())
+            .map(|team| {
+                team.map_err(|e| internal_error(format!("Failed to read teams: {e}")))
+                    .and_then(team_info)
+            })
+            .collect()
+    }
+
+    async fn set_team_member_impl(
+        &self,
+        team: String,
+        member: String,
+        role: TeamRole,
+        github_auth_token: String,
+    ) -> FunctionResult<TeamInfo> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate_account(&github_auth_token).await?;
+        let mut found = owned_team(&team, &username, &github_auth_token).await?;
+
+        let demotes_last_owner = found.member_role(&member) == Some(TeamRole::Owner)
+            && role != TeamRole::Owner
+            && found.owner_count() == 1;
+        if demotes_last_owner {
+            return Err(FaastaError::InvalidInput(format!(
+                "'{member}' is the last owner of team '{team}'"
+            )));
+        }
+
+        found.set_member(&member, role);
+        server
+            .github_auth
+            .teams
+            .save(&found)
+            .and_then(|_| server.github_auth.set_team_membership(&member, &team, true))
+            .map_err(|e| internal_error(format!("Failed to update team: {e}")))?;
+
+        info!("User '{username}' set role of '{member}' in team '{team}' to {role}");
+        team_info(found)
+    }
+
+    async fn remove_team_member_impl(
+        &self,
+        team: String,
+        member: String,
+        github_auth_token: String,
+    ) -> FunctionResult<TeamInfo> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate_account(&github_auth_token).await?;
+
+        // Members may always leave; removing someone else needs the owner role
+        let mut found = if member == username {
+            server
+                .github_auth
+                .teams
+                .get(&team)
+                .map_err(|e| internal_error(format!("Failed to read teams: {e}")))?
+                .ok_or_else(|| FaastaError::NotFound(format!("Team '{team}' not found")))?
+        } else {
+            owned_team(&team, &username, &github_auth_token).await?
+        };
+
+        match found.member_role(&member) {
+            None => {
+                return Err(FaastaError::NotFound(format!(
+                    "'{member}' is not a member of team '{team}'"
+                )))
+            }
+            Some(TeamRole::Owner) if found.owner_count() == 1 => {
+                return Err(FaastaError::InvalidInput(format!(
+                    "'{member}' is the last owner of team '{team}'"
+                )))
+            }
+            Some(_) => {}
+        }
+
+        found.members.retain(|m| m.username != member);
+        server
+            .github_auth
+            .teams
+            .save(&found)
+            .and_then(|_| {
+                server
+                    .github_auth
+                    .set_team_membership(&member, &team, false)
+            })
+            .map_err(|e| internal_error(format!("Failed to update team: {e}")))?;
+
+        info!("User '{username}' removed '{member}' from team '{team}'");
+        team_info(found)
+    }
+
+    async fn delete_team_impl(
+        &self,
+        team: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate_account(&github_auth_token).await?;
+        let found = owned_team(&team, &username, &github_auth_token).await?;
+
+        let functions = server
+            .github_auth
+            .get_user_projects(&team_owner(&team))
+            .map_err(|e| internal_error(format!("Failed to read projects: {e}")))?;
+        if !functions.is_empty() {
+            return Err(FaastaError::InvalidInput(format!(
+                "Team '{team}' still owns {} function(s); unpublish them first",
+                functions.len()
+            )));
+        }
+
+        for member in &found.members {
+            if let Err(e) = server
+                .github_auth
+                .set_team_membership(&member.username, &team, false)
+            {
+                error!("Failed to update membership of '{}': {e}", member.username);
+            }
+        }
+        server
+            .github_auth
+            .teams
+            .remove(&team)
+            .map_err(|e| internal_error(format!("Failed to delete team: {e}")))?;
+
+        info!("User '{username}' deleted team '{team}'");
+        Ok(())
+    }
+
+    async fn function_logs_impl(
+        &self,
+        name: String,
+        query: LogQuery,
+        github_auth_token: String,
+    ) -> FunctionResult<LogPage> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to read this function's logs",
+        )
+        .await?;
+
+        logs()?
+            .query(&name, &query)
+            .map_err(|e| FaastaError::InvalidInput(format!("Failed to query logs: {e}")))
+    }
+
+    async fn get_provenance_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<ProvenanceInfo>> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function",
+        )
+        .await?;
+
+        let digest = function_data::published_digest(server.metadata.as_ref(), &name)
+            .map_err(|e| internal_error(format!("Failed to read the artifact digest: {e}")))?;
+        let (Some(store), Some(digest)) = (PROVENANCE.get(), digest) else {
+            return Ok(None);
+        };
+        store
+            .get(&digest)
+            .map_err(|e| internal_error(format!("Failed to read provenance: {e}")))
+    }
+
+    async fn get_log_level_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<LogLevelSetting> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to read this function's logs",
+        )
+        .await?;
+
+        logs()?
+            .level(&name)
+            .map_err(|e| internal_error(format!("Failed to read log level: {e}")))
+    }
+
+    async fn set_log_level_impl(
+        &self,
+        name: String,
+        level: Option<LogLevel>,
+        duration_secs: u64,
+        github_auth_token: String,
+    ) -> FunctionResult<LogLevelSetting> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+
+        let _lock = function_locks::lock(&name).await;
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's log level",
+        )
+        .await?;
+
+        if duration_secs > 0 && logs::expiry(duration_secs).is_none() {
+            return Err(FaastaError::InvalidInput(format!(
+                "Duration of {duration_secs}s is too long"
+            )));
+        }
+        let setting = logs()?
+            .set_level(&name, level, duration_secs)
+            .map_err(|e| internal_error(format!("Failed to save log level: {e}")))?;
+        info!(
+            "User '{}' set the log level of '{}' to {} until {}",
+            username,
+            name,
+            setting.level,
+            setting.expires_at.as_deref().unwrap_or("changed")
+        );
+        Ok(setting)
+    }
+
+    async fn get_log_redaction_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<RedactionSettings> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to read this function's logs",
+        )
+        .await?;
+
+        logs()?
+            .redaction(&name)
+            .map_err(|e| internal_error(format!("Failed to read redaction rules: {e}")))
+    }
+
+    async fn set_log_redaction_impl(
+        &self,
+        name: String,
+        rules: RedactionRules,
+        github_auth_token: String,
+    ) -> FunctionResult<RedactionSettings> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+
+        let _lock = function_locks::lock(&name).await;
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's redaction rules",
+        )
+        .await?;
+
+        redaction::validate(&rules).map_err(|e| FaastaError::InvalidInput(e.to_string()))?;
+        let settings = logs()?
+            .set_redaction(&name, rules)
+            .map_err(|e| internal_error(format!("Failed to save redaction rules: {e}")))?;
+        info!(
+            "User '{}' set {} header and {} pattern redaction rules on '{}'",
+            username,
+            settings.function.headers.len(),
+            settings.function.patterns.len(),
+            name
+        );
+        Ok(settings)
+    }
+
+    async fn get_signing_keys_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<SigningKeys> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function's signing keys",
+        )
+        .await?;
+
+        SERVER
+            .get()
+            .unwrap()
+            .signing
+            .keys(&name)
+            .map_err(|e| internal_error(format!("Failed to read signing keys: {e}")))
+    }
+
+    async fn set_response_signing_impl(
+        &self,
+        name: String,
+        enabled: bool,
+        rotate: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<SigningKeys> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's response signing",
+        )
+        .await?;
+
+        let signing = &SERVER.get().unwrap().signing;
+        let keys = if enabled {
+            signing.enable(&name, rotate)
+        } else {
+            signing.disable(&name).map(|()| SigningKeys::default())
+        }
+        .map_err(|e| internal_error(format!("Failed to save signing keys: {e}")))?;
+        info!(
+            "User '{}' turned response signing of '{}' {}{}",
+            username,
+            name,
+            if enabled { "on" } else { "off" },
+            if enabled && rotate {
+                " with a new key"
+            } else {
+                ""
+            }
+        );
+        Ok(keys)
+    }
+
+    async fn list_debug_snapshots_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<DebugSnapshots> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function's snapshots",
+        )
+        .await?;
+
+        snapshots()?
+            .list(&name)
+            .map_err(|e| internal_error(format!("Failed to read debug snapshots: {e}")))
+    }
+
+    async fn set_debug_snapshots_impl(
+        &self,
+        name: String,
+        enabled: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<DebugSnapshots> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to change this function's snapshots",
+        )
+        .await?;
+
+        let snapshots = snapshots()?
+            .set_enabled(&name, enabled)
+            .map_err(|e| internal_error(format!("Failed to save debug snapshots: {e}")))?;
+        info!(
+            "User '{}' turned debug snapshots of '{}' {}",
+            username,
+            name,
+            if enabled { "on" } else { "off" }
+        );
+        Ok(snapshots)
+    }
+
+    async fn get_debug_snapshot_impl(
+        &self,
+        name: String,
+        id: String,
+        github_auth_token: String,
+    ) -> FunctionResult<DebugSnapshot> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to read this function's snapshots",
+        )
+        .await?;
+
+        snapshots()?
+            .get(&name, &id)
+            .map_err(|e| internal_error(format!("Failed to read debug snapshot: {e}")))?
+            .ok_or_else(|| FaastaError::NotFound(format!("No debug snapshot '{id}'")))
+    }
+
+    async fn read_core_dump_impl(
+        &self,
+        name: String,
+        id: String,
+        index: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<u8>> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to read this function's snapshots",
+        )
+        .await?;
+
+        snapshots()?
+            .core_dump_chunk(&name, &id, index)
+            .map_err(|e| internal_error(format!("Failed to read core dump: {e}")))?
+            .ok_or_else(|| FaastaError::NotFound(format!("No debug snapshot '{id}'")))
+    }
+
+    async fn list_guest_profiles_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<GuestProfiles> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function's profiles",
+        )
+        .await?;
+
+        profiling()?
+            .list(&name)
+            .map_err(|e| internal_error(format!("Failed to read guest profiles: {e}")))
+    }
+
+    async fn set_guest_profiling_impl(
+        &self,
+        name: String,
+        sample_rate: f64,
+        github_auth_token: String,
+    ) -> FunctionResult<GuestProfiles> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to profile this function",
+        )
+        .await?;
+
+        if !(0.0..=1.0).contains(&sample_rate) {
+            return Err(FaastaError::InvalidInput(
+                "The sample rate must be between 0 and 1".to_string(),
+            ));
+        }
+        profiling()?
+            .set_sample_rate(&name, sample_rate)
+            .map_err(|e| internal_error(format!("Failed to update guest profiling: {e}")))
+    }
+
+    async fn read_guest_profile_impl(
+        &self,
+        name: String,
+        id: String,
+        index: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<u8>> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function's profiles",
+        )
+        .await?;
+
+        profiling()?
+            .chunk(&name, &id, index)
+            .map_err(|e| internal_error(format!("Failed to read guest profile: {e}")))?
+            .ok_or_else(|| FaastaError::NotFound(format!("No guest profile '{id}'")))
+    }
+
+    async fn list_webhook_deliveries_impl(
+        &self,
+        name: String,
+        status: Option<DeliveryStatus>,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<WebhookDelivery>> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function's webhooks",
+        )
+        .await?;
+
+        webhooks()?
+            .list(&name, status)
+            .map_err(|e| internal_error(format!("Failed to read webhook deliveries: {e}")))
+    }
+
+    async fn redeliver_webhook_impl(
+        &self,
+        name: String,
+        id: String,
+        github_auth_token: String,
+    ) -> FunctionResult<WebhookDelivery> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Developer,
+            "You don't have permission to redeliver this function's webhooks",
+        )
+        .await?;
+
+        let delivery = webhooks()?
+            .redeliver(&name, &id)
+            .map_err(|e| internal_error(format!("Failed to queue the delivery: {e}")))?
+            .ok_or_else(|| FaastaError::NotFound(format!("No webhook delivery '{id}'")))?;
+        info!(
+            "User '{}' queued webhook {} of '{}' again",
+            username, id, name
+        );
+        Ok(delivery)
+    }
+
+    async fn get_metrics_impl(&self, github_auth_token: String) -> FunctionResult<Metrics> {
+        authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        // Use the metrics module to get metrics from sled
+        let metrics = get_metrics();
+
+        Ok(metrics)
+    }
+
+    async fn get_function_metrics_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<FunctionStats> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function's metrics",
+        )
+        .await?;
+
+        Ok(function_stats(&name).unwrap_or_else(|| FunctionStats {
+            function_name: name,
+            ..Default::default()
+        }))
+    }
+
+    async fn function_status_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<FunctionStatus> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function's status",
+        )
+        .await?;
+
+        let digest = function_data::published_digest(server.metadata.as_ref(), &name)
+            .map_err(|e| internal_error(format!("Failed to read the artifact digest: {e}")))?;
+        let artifact_bytes = server
+            .storage
+            .size(&wasm_key(&name))
+            .await
+            .map_err(|e| internal_error(format!("Failed to read the artifact size: {e}")))?;
+        let stats = function_stats(&name).unwrap_or_default();
+
+        let owner = &function_info.owner;
+        let mut quotas = vec![QuotaUsage {
+            quota: "projects".to_string(),
+            used: names_used(owner)? as u64,
+            limit: server.github_auth.project_limit(owner) as u64,
+        }];
+        if let Some(keep_warm) = KEEP_WARM.get() {
+            quotas.push(QuotaUsage {
+                quota: "functions kept warm".to_string(),
+                used: keep_warm.used(owner)? as u64,
+                limit: keep_warm.max_per_owner() as u64,
+            });
+        }
+
+        Ok(FunctionStatus {
+            urls: function_urls(server, &name),
+            warm: server.warm_state(&name),
+            kept_warm: is_kept_warm(&name),
+            canary_weight: server.canaries.weight(&name),
+            invocations: stats.invocations,
+            errors: stats.errors,
+            last_error: metrics::last_error(&name),
+            digest,
+            artifact_bytes,
+            quotas,
+            name,
+            owner: function_info.owner,
+            published_at: function_info.published_at,
+        })
+    }
+
+    async fn create_api_key_impl(
+        &self,
+        name: String,
+        scopes: Vec<ApiKeyScope>,
+        github_auth_token: String,
+    ) -> FunctionResult<NewApiKey> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate_account(&github_auth_token).await?;
+
+        if scopes.is_empty() {
+            return Err(FaastaError::InvalidInput(
+                "An API key needs at least one scope".to_string(),
+            ));
+        }
+
+        let (key, info) = server
+            .github_auth
+            .api_keys
+            .create(&username, &name, scopes)
+            .map_err(|e| internal_error(format!("Failed to create API key: {e}")))?;
+
+        info!("Created API key '{}' for user '{}'", info.id, username);
+        Ok(NewApiKey { info, key })
+    }
+
+    async fn list_api_keys_impl(
+        &self,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<ApiKeyInfo>> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate_account(&github_auth_token).await?;
+
+        server
+            .github_auth
+            .api_keys
+            .list(&username)
+            .map_err(|e| internal_error(format!("Failed to read API keys: {e}")))
+    }
+
+    async fn revoke_api_key_impl(
+        &self,
+        id: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate_account(&github_auth_token).await?;
+
+        let revoked = server
+            .github_auth
+            .api_keys
+            .revoke(&username, &id)
+            .map_err(|e| internal_error(format!("Failed to revoke API key: {e}")))?;
+
+        if !revoked {
+            return Err(FaastaError::NotFound(format!("API key '{id}' not found")));
+        }
+
+        info!("Revoked API key '{}' for user '{}'", id, username);
+        Ok(())
+    }
+
+    async fn list_sessions_impl(
+        &self,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<SessionInfo>> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate_account(&github_auth_token).await?;
+        let api_keys = server
+            .github_auth
+            .api_keys
+            .list(&username)
+            .map_err(|e| internal_error(format!("Failed to read API keys: {e}")))?;
+        match SESSIONS.get() {
+            Some(sessions) => sessions
+                .list(&username, api_keys, &github_auth_token)
+                .map_err(|e| internal_error(format!("Failed to read sessions: {e}"))),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn revoke_session_impl(
+        &self,
+        id: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let server = SERVER.get().unwrap();
+        let username = authenticate_account(&github_auth_token).await?;
+
+        let revoked_login = match SESSIONS.get() {
+            Some(sessions) => sessions
+                .revoke_login(&username, &id)
+                .map_err(|e| internal_error(format!("Failed to revoke session: {e}")))?,
+            None => false,
+        };
+        let revoked = revoked_login
+            || server
+                .github_auth
+                .api_keys
+                .revoke(&username, &id)
+                .map_err(|e| internal_error(format!("Failed to revoke API key: {e}")))?;
+
+        if !revoked {
+            return Err(FaastaError::NotFound(format!("Session '{id}' not found")));
+        }
+
+        info!("Revoked session '{}' for user '{}'", id, username);
+        Ok(())
+    }
+
+    async fn label_session_impl(
+        &self,
+        label: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        authenticate_account(&github_auth_token).await?;
+        let label = label.trim();
+        if label.is_empty() {
+            return Err(FaastaError::InvalidInput(
+                "Session label can't be empty".to_string(),
+            ));
+        }
+
+        let labeled = match SESSIONS.get() {
+            Some(sessions) => sessions
+                .set_label(&github_auth_token, label)
+                .map_err(|e| internal_error(format!("Failed to label session: {e}")))?,
+            None => false,
+        };
+        if !labeled {
+            return Err(FaastaError::NotFound(
+                "This server doesn't track sessions".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn list_alerts_impl(
+        &self,
+        all: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<AnomalyAlert>> {
+        let server = SERVER.get().unwrap();
+        let Some(anomalies) = ANOMALIES.get() else {
+            return Ok(Vec::new());
+        };
+        if all {
+            require_admin(&github_auth_token).await?;
+            return Ok(anomalies.alerts());
+        }
+
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+        let mut owners = vec![username.clone()];
+        owners.extend(
+            server
+                .github_auth
+                .get_user_teams(&username)
+                .map_err(|e| internal_error(format!("Failed to read teams: {e}")))?
+                .into_iter()
+                .map(|team| team_owner(&team)),
+        );
+        Ok(anomalies
+            .alerts()
+            .into_iter()
+            .filter(|alert| owners.contains(&alert.owner))
+            .collect())
+    }
+
+    async fn resolve_alert_impl(&self, id: u64, github_auth_token: String) -> FunctionResult<()> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+        let anomalies = ANOMALIES
+            .get()
+            .ok_or_else(|| FaastaError::NotFound(format!("Alert {id} not found")))?;
+        let alert = anomalies
+            .get(id)
+            .ok_or_else(|| FaastaError::NotFound(format!("Alert {id} not found")))?;
+
+        // Owners resolve their own alerts, admins anyone's
+        if roles::effective_role(&username) < PlatformRole::Admin {
+            require_role(
+                &alert.owner,
+                &username,
+                &github_auth_token,
+                TeamRole::Owner,
+                "You don't have permission to resolve this alert",
+            )
+            .await?;
+        }
+
+        anomalies
+            .resolve(id)
+            .map_err(|e| internal_error(format!("Failed to resolve alert: {e}")))?;
+        info!("'{}' resolved alert {} ({})", username, id, alert.kind);
+        Ok(())
+    }
+
+    async fn set_role_impl(
+        &self,
+        principal: String,
+        role: Option<PlatformRole>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let (username, roles) = require_admin(&github_auth_token).await?;
+        if principal.is_empty() {
+            return Err(FaastaError::InvalidInput(
+                "A user or team name is required".to_string(),
+            ));
+        }
+        if principal == username && role.is_none_or(|role| role < PlatformRole::Admin) {
+            return Err(FaastaError::InvalidInput(
+                "You can't lower your own role".to_string(),
+            ));
+        }
+
+        roles
+            .set_grant(&principal, role)
+            .map_err(|e| internal_error(format!("Failed to save role: {e}")))?;
+
+        match role {
+            Some(role) => info!(
+                "'{}' granted the {} role to '{}'",
+                username, role, principal
+            ),
+            None => info!("'{}' cleared the role of '{}'", username, principal),
+        }
+        Ok(())
+    }
+
+    async fn list_roles_impl(&self, github_auth_token: String) -> FunctionResult<Vec<RoleGrant>> {
+        let (_, roles) = require_admin(&github_auth_token).await?;
+        roles
+            .grants()
+            .map_err(|e| internal_error(format!("Failed to read roles: {e}")))
+    }
+
+    async fn suspend_user_impl(
+        &self,
+        username: String,
+        reason: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let server = SERVER.get().unwrap();
+        let (admin, _) = require_admin(&github_auth_token).await?;
+        if username == admin {
+            return Err(FaastaError::InvalidInput(
+                "You can't suspend your own account".to_string(),
+            ));
+        }
+
+        let suspension = Suspension {
+            reason,
+            suspended_by: admin.clone(),
+            suspended_at: chrono::Utc::now().timestamp(),
+        };
+        server
+            .suspensions
+            .suspend(&username, &suspension, &server.github_auth)
+            .map_err(|e| internal_error(format!("Failed to suspend user: {e}")))?;
+
+        info!(
+            "'{}' suspended '{}': {}",
+            admin, username, suspension.reason
+        );
+        journal::record(
+            EventSeverity::Warning,
+            ServerEventKind::AccountSuspended,
+            Some(&username),
+            format!("Suspended by '{admin}': {}", suspension.reason),
+        );
+        Ok(())
+    }
+
+    async fn unsuspend_user_impl(
+        &self,
+        username: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let server = SERVER.get().unwrap();
+        let (admin, _) = require_admin(&github_auth_token).await?;
+
+        let was_suspended = server
+            .suspensions
+            .unsuspend(&username, &server.github_auth)
+            .map_err(|e| internal_error(format!("Failed to unsuspend user: {e}")))?;
+        if !was_suspended {
+            return Err(FaastaError::NotFound(format!(
+                "User '{username}' is not suspended"
+            )));
+        }
+
+        info!("'{}' lifted the suspension of '{}'", admin, username);
+        journal::record(
+            EventSeverity::Info,
+            ServerEventKind::AccountReinstated,
+            Some(&username),
+            format!("Suspension lifted by '{admin}'"),
+        );
+        Ok(())
+    }
+
+    async fn kill_function_impl(
+        &self,
+        name: String,
+        reason: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let (admin, _) = require_admin(&github_auth_token).await?;
+        self.function_info(&name)?;
+
+        breakers()?
+            .kill(&name, reason.clone(), &admin)
+            .map_err(|e| internal_error(format!("Failed to kill function: {e}")))?;
+
+        info!("'{}' killed function '{}': {}", admin, name, reason);
+        journal::record(
+            EventSeverity::Warning,
+            ServerEventKind::FunctionKilled,
+            Some(&name),
+            format!("Killed by '{admin}': {reason}"),
+        );
+        Ok(())
+    }
+
+    async fn get_circuit_breaker_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<CircuitBreaker>> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Read).await?;
+
+        let function_info = self.function_info(&name)?;
+        require_role(
+            &function_info.owner,
+            &username,
+            &github_auth_token,
+            TeamRole::Viewer,
+            "You don't have permission to view this function",
+        )
+        .await?;
+
+        Ok(breakers()?.get(&name))
+    }
+
+    async fn reset_circuit_breaker_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let username = authenticate(&github_auth_token, ApiKeyScope::Manage).await?;
+
+        let function_info = self.function_info(&name)?;
+        let breakers = breakers()?;
+        let breaker = breakers
+            .get(&name)
+            .ok_or_else(|| FaastaError::NotFound(format!("Function '{name}' is being invoked")))?;
+
+        // Only admins bring back a function an admin killed
+        if breaker.state == BreakerState::Killed {
+            require_admin(&github_auth_token).await?;
+        } else {
+            require_role(
+                &function_info.owner,
+                &username,
+                &github_auth_token,
+                TeamRole::Developer,
+                "You don't have permission to reset this function's circuit breaker",
+            )
+            .await?;
+        }
+
+        breakers
+            .reset(&name)
+            .map_err(|e| internal_error(format!("Failed to reset circuit breaker: {e}")))?;
+
+        info!(
+            "'{}' reset the {} breaker of '{}'",
+            username, breaker.state, name
+        );
+        journal::record(
+            EventSeverity::Info,
+            ServerEventKind::BreakerReset,
+            Some(&name),
+            format!("Reset by '{username}' after it was {}", breaker.state),
+        );
+        Ok(())
+    }
+
+    async fn audit_log_impl(
+        &self,
+        after: Option<u64>,
+        limit: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<AuditEvent>> {
+        require_admin(&github_auth_token).await?;
+        let audit = AUDIT
+            .get()
+            .ok_or_else(|| internal_error("Audit log is not configured".to_string()))?;
+        Ok(audit.events(after, limit.clamp(1, MAX_AUDIT_PAGE) as usize))
+    }
+
+    async fn server_events_impl(
+        &self,
+        after: Option<u64>,
+        limit: u32,
+        min_severity: EventSeverity,
+        kind: Option<ServerEventKind>,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<ServerEvent>> {
+        require_admin(&github_auth_token).await?;
+        let journal = JOURNAL
+            .get()
+            .ok_or_else(|| internal_error("Event journal is not configured".to_string()))?;
+        Ok(journal.events(
+            after,
+            limit.clamp(1, MAX_JOURNAL_PAGE) as usize,
+            min_severity,
+            kind,
+        ))
+    }
+
+    async fn consistency_report_impl(
+        &self,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<ConsistencyReport>> {
+        require_admin(&github_auth_token).await?;
+        let server = SERVER.get().unwrap();
+        consistency::latest(&server.metadata_db)
+            .map_err(|e| internal_error(format!("Failed to read the consistency report: {e}")))
+    }
+
+    async fn check_consistency_impl(
+        &self,
+        repair: Vec<DriftKind>,
+        github_auth_token: String,
+    ) -> FunctionResult<ConsistencyReport> {
+        let (admin, _) = require_admin(&github_auth_token).await?;
+        let repair = if repair.is_empty() {
+            Repair::Off
+        } else {
+            Repair::Kinds(repair)
+        };
+        let report = consistency::check(repair)
+            .await
+            .map_err(|e| internal_error(format!("Consistency check failed: {e}")))?;
+        info!(
+            "{} ran a consistency check: {} drifted records",
+            admin,
+            report.drift.len()
+        );
+        Ok(report)
+    }
+
+    async fn capacity_report_impl(
+        &self,
+        refresh: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<CapacityReport> {
+        require_admin(&github_auth_token).await?;
+        let server = SERVER.get().unwrap();
+        if !refresh {
+            let latest = capacity_report::latest(&server.metadata_db)
+                .map_err(|e| internal_error(format!("Failed to read the capacity report: {e}")))?;
+            if let Some(report) = latest {
+                return Ok(report);
+            }
+        }
+        capacity_report::generate()
+            .await
+            .map_err(|e| internal_error(format!("Capacity report failed: {e}")))
+    }
+
+    async fn create_backup_impl(&self, github_auth_token: String) -> FunctionResult<BackupInfo> {
+        require_admin(&github_auth_token).await?;
+        let server = SERVER.get().unwrap();
+        if self.metadata.name() != "sled" {
+            return Err(FaastaError::InvalidInput(format!(
+                "This server keeps metadata in {}; back it up with the database's own tools",
+                self.metadata.name()
+            )));
+        }
+        let backups = BACKUPS
+            .get()
+            .ok_or_else(|| internal_error("Backups are not set up"))?;
+        backups
+            .take(&server.metadata_db, server.storage.as_ref())
+            .await
+            .map_err(|e| internal_error(format!("Backup failed: {e:#}")))
+    }
+
+    async fn read_backup_impl(
+        &self,
+        name: String,
+        index: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<u8>> {
+        require_admin(&github_auth_token).await?;
+        let backups = BACKUPS
+            .get()
+            .ok_or_else(|| internal_error("Backups are not set up"))?;
+        backups
+            .chunk(&name, index)
+            .map_err(|e| internal_error(format!("Failed to read backup: {e}")))?
+            .ok_or_else(|| FaastaError::NotFound(format!("No backup '{name}'")))
+    }
+
+    async fn collect_garbage_impl(
+        &self,
+        dry_run: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<GarbageReport> {
+        require_admin(&github_auth_token).await?;
+        let gc = GC
+            .get()
+            .ok_or_else(|| internal_error("Garbage collection is not set up"))?;
+        gc.collect(dry_run)
+            .await
+            .map_err(|e| internal_error(format!("Garbage collection failed: {e}")))
+    }
+
+    async fn list_cluster_nodes_impl(
+        &self,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<ClusterNode>> {
+        require_admin(&github_auth_token).await?;
+        let cluster = CLUSTER.get().ok_or_else(|| {
+            FaastaError::InvalidInput("This server doesn't run in a cluster".to_string())
+        })?;
+        cluster
+            .nodes()
+            .map_err(|e| internal_error(format!("Failed to list cluster nodes: {e}")))
+    }
+
+    async fn force_delete_function_impl(
+        &self,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let server = SERVER.get().unwrap();
+        let (admin, _) = require_admin(&github_auth_token).await?;
+
+        let owner = match self.function_info(&name) {
+            Ok(function_info) => {
+                let owner = function_info.owner.clone();
+                self.remove_function(function_info, None).await?;
+                owner
+            }
+            Err(FaastaError::NotFound(message)) => {
+                // It may only be waiting in the trash
+                let trashed = TRASH
+                    .get()
+                    .and_then(|trash| trash.get(&name).map(|trashed| (trash, trashed)));
+                let Some((trash, trashed)) = trashed else {
+                    return Err(FaastaError::NotFound(message));
+                };
+                trash
+                    .discard(&name)
+                    .await
+                    .map_err(|e| internal_error(format!("Failed to delete function: {e}")))?;
+                if let Err(e) =
+                    function_data::remove(server.metadata.as_ref(), &server.metadata_db, &name)
+                {
+                    error!("Failed to remove data for function '{name}': {e}");
+                }
+                if let Err(e) = server
+                    .github_auth
+                    .remove_project(&trashed.info.owner, &name)
+                    .await
+                {
+                    error!("Failed to release project '{name}': {e}");
+                }
+                trashed.info.owner
+            }
+            Err(e) => return Err(e),
+        };
+        server.suspensions.forget_function(&name);
+
+        info!(
+            "'{}' force-deleted function '{}' owned by '{}'",
+            admin, name, owner
+        );
+        Ok(())
+    }
+
+    async fn set_project_limit_impl(
+        &self,
+        username: String,
+        limit: Option<u32>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        let server = SERVER.get().unwrap();
+        let (admin, _) = require_admin(&github_auth_token).await?;
+
+        server
+            .github_auth
+            .set_project_limit(&username, limit.map(|limit| limit as usize))
+            .map_err(|e| internal_error(format!("Failed to save project limit: {e}")))?;
+
+        info!(
+            "'{}' set the project limit of '{}' to {}",
+            admin,
+            username,
+            server.github_auth.project_limit(&username)
+        );
+        Ok(())
+    }
+
+    async fn export_usage_impl(
+        &self,
+        period: Option<String>,
+        format: UsageFormat,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        require_admin(&github_auth_token).await?;
+        let usage = USAGE
+            .get()
+            .ok_or_else(|| internal_error("Usage accounting is not configured".to_string()))?;
+
+        let current = usage::current_period();
+        let period = period.unwrap_or_else(|| current.clone());
+        usage::parse_period(&period).map_err(|e| FaastaError::InvalidInput(e.to_string()))?;
+        // The current period is brought up to date first
+        let records = if period == current {
+            usage.accumulate().await
+        } else {
+            usage.records(&period)
+        }
+        .map_err(|e| internal_error(format!("Failed to read usage: {e}")))?;
+
+        usage::render(&records, format)
+            .map_err(|e| internal_error(format!("Failed to render usage: {e}")))
+    }
+}
+
+/// Authenticate a provider login with the admin role
+async fn require_admin(token: &str) -> FunctionResult<(String, &'static roles::Roles)> {
+    let username = authenticate_account(token).await?;
+    let roles = ROLES
+        .get()
+        .ok_or_else(|| internal_error("Roles are not configured".to_string()))?;
+    if roles.effective_role(&username) < PlatformRole::Admin {
+        return Err(FaastaError::PermissionDenied(
+            "This operation requires the admin role".to_string(),
+        ));
+    }
+    Ok((username, roles))
+}
+
+// Now implement the trait methods that use the reference-based implementations
+impl FunctionService for FunctionServiceImpl {
+    async fn server_info(self, _: tarpc::context::Context) -> ServerInfo {
+        // Asked for on every connection, so kept out of the audit log
+        self.server_info_impl()
+    }
+
+    async fn publish(
+        self,
+        _: tarpc::context::Context,
+        wasm_file: Vec<u8>,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "publish",
+            Some(name.clone()),
+            self.peer,
+            self.publish_impl(wasm_file, None, name, None, None, None, github_auth_token),
+        )
+        .await
+    }
+
+    async fn publish_to_team(
+        self,
+        _: tarpc::context::Context,
+        wasm_file: Vec<u8>,
+        name: String,
+        team: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "publish_to_team",
+            Some(name.clone()),
+            self.peer,
+            self.publish_impl(
+                wasm_file,
+                None,
+                name,
+                Some(team),
+                None,
+                None,
+                github_auth_token,
+            ),
+        )
+        .await
+    }
+
+    async fn list_functions(
+        self,
+        _: tarpc::context::Context,
+        query: FunctionQuery,
+        github_auth_token: String,
+    ) -> FunctionResult<FunctionPage> {
+        audited(
+            "list_functions",
+            None,
+            self.peer,
+            self.list_functions_impl(query, github_auth_token),
+        )
+        .await
+    }
+
+    async fn unpublish(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "unpublish",
+            Some(name.clone()),
+            self.peer,
+            self.unpublish_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn deploy_queue_position(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<u32>> {
+        // Polled while a publish waits, so kept out of the audit log
+        self.deploy_queue_position_impl(name, github_auth_token)
+            .await
+    }
+
+    async fn publish_canary(
+        self,
+        _: tarpc::context::Context,
+        wasm_file: Vec<u8>,
+        name: String,
+        weight: u8,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "publish_canary",
+            Some(name.clone()),
+            self.peer,
+            self.publish_canary_impl(wasm_file, None, name, weight, github_auth_token),
+        )
+        .await
+    }
+
+    async fn begin_upload(
+        self,
+        _: tarpc::context::Context,
+        size: u64,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "begin_upload",
+            None,
+            self.peer,
+            self.begin_upload_impl(size, github_auth_token),
+        )
+        .await
+    }
+
+    async fn upload_chunk(
+        self,
+        _: tarpc::context::Context,
+        upload_id: String,
+        chunk: Vec<u8>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        // Sent many times per upload, so kept out of the audit log
+        self.upload_chunk_impl(upload_id, None, chunk, github_auth_token)
+            .await
+    }
+
+    async fn begin_delta_upload(
+        self,
+        _: tarpc::context::Context,
+        size: u64,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<DeltaUpload> {
+        audited(
+            "begin_delta_upload",
+            Some(name.clone()),
+            self.peer,
+            self.begin_delta_upload_impl(size, name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn upload_known_chunks(
+        self,
+        _: tarpc::context::Context,
+        upload_id: String,
+        digests: Vec<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        // Sent many times per upload, like upload_chunk
+        self.upload_known_chunks_impl(upload_id, None, digests, github_auth_token)
             .await
-            .map_err(|e| FunctionError::AuthError(format!("Authentication error: {e}")))?;
+    }
+
+    async fn transfer_function(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        new_owner: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "transfer_function",
+            Some(name.clone()),
+            self.peer,
+            self.transfer_function_impl(name, new_owner, github_auth_token),
+        )
+        .await
+    }
+
+    async fn accept_transfer(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "accept_transfer",
+            Some(name.clone()),
+            self.peer,
+            self.accept_transfer_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_alias(
+        self,
+        _: tarpc::context::Context,
+        alias: FunctionAlias,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "set_alias",
+            Some(alias.target.clone()),
+            self.peer,
+            self.set_alias_impl(alias, github_auth_token),
+        )
+        .await
+    }
+
+    async fn remove_alias(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "remove_alias",
+            Some(name.clone()),
+            self.peer,
+            self.remove_alias_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn list_aliases(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<FunctionAlias>> {
+        audited(
+            "list_aliases",
+            Some(name.clone()),
+            self.peer,
+            self.list_aliases_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn attach_provenance(
+        self,
+        _: tarpc::context::Context,
+        upload_id: String,
+        document: Vec<u8>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "attach_provenance",
+            None,
+            self.peer,
+            self.attach_provenance_impl(upload_id, document, github_auth_token),
+        )
+        .await
+    }
+
+    async fn publish_upload(
+        self,
+        _: tarpc::context::Context,
+        upload_id: String,
+        name: String,
+        target: PublishTarget,
+        keep_warm: Option<bool>,
+        metadata: Option<FunctionMetadata>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "publish_upload",
+            Some(name.clone()),
+            self.peer,
+            self.publish_upload_impl(
+                upload_id,
+                name,
+                target,
+                keep_warm,
+                metadata,
+                github_auth_token,
+            ),
+        )
+        .await
+    }
+
+    async fn publish_routes(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        routes: Vec<RouteUpload>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "publish_routes",
+            Some(name.clone()),
+            self.peer,
+            self.publish_routes_impl(name, routes, github_auth_token),
+        )
+        .await
+    }
+
+    async fn publish_static_assets(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        upload_id: Option<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "publish_static_assets",
+            Some(name.clone()),
+            self.peer,
+            self.publish_static_assets_impl(name, upload_id, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_cors_policy(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        policy: Option<CorsPolicy>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "set_cors_policy",
+            Some(name.clone()),
+            self.peer,
+            self.set_cors_policy_impl(name, policy, github_auth_token),
+        )
+        .await
+    }
+
+    async fn protect_function(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        private: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<String>> {
+        audited(
+            "protect_function",
+            Some(name.clone()),
+            self.peer,
+            self.protect_function_impl(name, private, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_jwt_auth(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        policy: Option<JwtAuthPolicy>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "set_jwt_auth",
+            Some(name.clone()),
+            self.peer,
+            self.set_jwt_auth_impl(name, policy, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_transforms(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        rules: Vec<TransformRule>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "set_transforms",
+            Some(name.clone()),
+            self.peer,
+            self.set_transforms_impl(name, rules, github_auth_token),
+        )
+        .await
+    }
+
+    async fn publish_from_registry(
+        self,
+        _: tarpc::context::Context,
+        reference: String,
+        name: String,
+        target: PublishTarget,
+        credentials: Option<RegistryCredentials>,
+        keep_warm: Option<bool>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "publish_from_registry",
+            Some(name.clone()),
+            self.peer,
+            self.publish_from_registry_impl(
+                reference,
+                name,
+                target,
+                credentials,
+                keep_warm,
+                github_auth_token,
+            ),
+        )
+        .await
+    }
+
+    async fn update_canary(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        update: CanaryUpdate,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "update_canary",
+            Some(name.clone()),
+            self.peer,
+            self.update_canary_impl(name, update, github_auth_token),
+        )
+        .await
+    }
+
+    async fn rename_function(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        new_name: String,
+        redirect_hours: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "rename_function",
+            Some(format!("{name} -> {new_name}")),
+            self.peer,
+            self.rename_function_impl(name, new_name, redirect_hours, github_auth_token),
+        )
+        .await
+    }
+
+    async fn clone_function(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        new_name: String,
+        with_data: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "clone_function",
+            Some(format!("{name} -> {new_name}")),
+            self.peer,
+            self.clone_function_impl(name, new_name, with_data, github_auth_token),
+        )
+        .await
+    }
+
+    async fn restore(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "restore",
+            Some(name.clone()),
+            self.peer,
+            self.restore_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn create_team(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_org: Option<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<TeamInfo> {
+        audited(
+            "create_team",
+            Some(name.clone()),
+            self.peer,
+            self.create_team_impl(name, github_org, github_auth_token),
+        )
+        .await
+    }
+
+    async fn list_teams(
+        self,
+        _: tarpc::context::Context,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<TeamInfo>> {
+        audited(
+            "list_teams",
+            None,
+            self.peer,
+            self.list_teams_impl(github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_team_member(
+        self,
+        _: tarpc::context::Context,
+        team: String,
+        username: String,
+        role: TeamRole,
+        github_auth_token: String,
+    ) -> FunctionResult<TeamInfo> {
+        audited(
+            "set_team_member",
+            Some(format!("{team}/{username}")),
+            self.peer,
+            self.set_team_member_impl(team, username, role, github_auth_token),
+        )
+        .await
+    }
+
+    async fn remove_team_member(
+        self,
+        _: tarpc::context::Context,
+        team: String,
+        username: String,
+        github_auth_token: String,
+    ) -> FunctionResult<TeamInfo> {
+        audited(
+            "remove_team_member",
+            Some(format!("{team}/{username}")),
+            self.peer,
+            self.remove_team_member_impl(team, username, github_auth_token),
+        )
+        .await
+    }
+
+    async fn delete_team(
+        self,
+        _: tarpc::context::Context,
+        team: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "delete_team",
+            Some(team.clone()),
+            self.peer,
+            self.delete_team_impl(team, github_auth_token),
+        )
+        .await
+    }
+
+    async fn apply(
+        self,
+        _: tarpc::context::Context,
+        definition: FunctionDefinition,
+        credentials: Option<RegistryCredentials>,
+        dry_run: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<ApplyOutcome> {
+        audited(
+            "apply",
+            Some(definition.metadata.name.clone()),
+            self.peer,
+            self.apply_impl(definition, credentials, dry_run, github_auth_token),
+        )
+        .await
+    }
+
+    async fn function_logs(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        query: LogQuery,
+        github_auth_token: String,
+    ) -> FunctionResult<LogPage> {
+        audited(
+            "function_logs",
+            Some(name.clone()),
+            self.peer,
+            self.function_logs_impl(name, query, github_auth_token),
+        )
+        .await
+    }
+
+    async fn get_provenance(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<ProvenanceInfo>> {
+        audited(
+            "get_provenance",
+            Some(name.clone()),
+            self.peer,
+            self.get_provenance_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn get_log_level(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<LogLevelSetting> {
+        audited(
+            "get_log_level",
+            Some(name.clone()),
+            self.peer,
+            self.get_log_level_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_log_level(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        level: Option<LogLevel>,
+        duration_secs: u64,
+        github_auth_token: String,
+    ) -> FunctionResult<LogLevelSetting> {
+        audited(
+            "set_log_level",
+            Some(name.clone()),
+            self.peer,
+            self.set_log_level_impl(name, level, duration_secs, github_auth_token),
+        )
+        .await
+    }
+
+    async fn get_log_redaction(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<RedactionSettings> {
+        audited(
+            "get_log_redaction",
+            Some(name.clone()),
+            self.peer,
+            self.get_log_redaction_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_log_redaction(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        rules: RedactionRules,
+        github_auth_token: String,
+    ) -> FunctionResult<RedactionSettings> {
+        audited(
+            "set_log_redaction",
+            Some(name.clone()),
+            self.peer,
+            self.set_log_redaction_impl(name, rules, github_auth_token),
+        )
+        .await
+    }
+
+    async fn list_debug_snapshots(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<DebugSnapshots> {
+        audited(
+            "list_debug_snapshots",
+            Some(name.clone()),
+            self.peer,
+            self.list_debug_snapshots_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_debug_snapshots(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        enabled: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<DebugSnapshots> {
+        audited(
+            "set_debug_snapshots",
+            Some(name.clone()),
+            self.peer,
+            self.set_debug_snapshots_impl(name, enabled, github_auth_token),
+        )
+        .await
+    }
 
-        if !is_valid || username.is_empty() {
-            return Err(FunctionError::AuthError(
-                "Invalid GitHub authentication token".to_string(),
-            ));
-        }
+    async fn get_debug_snapshot(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        id: String,
+        github_auth_token: String,
+    ) -> FunctionResult<DebugSnapshot> {
+        audited(
+            "get_debug_snapshot",
+            Some(name.clone()),
+            self.peer,
+            self.get_debug_snapshot_impl(name, id, github_auth_token),
+        )
+        .await
+    }
 
-        // Use the metrics module to get metrics from sled
-        let metrics = get_metrics();
+    async fn read_core_dump(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        id: String,
+        index: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<u8>> {
+        audited(
+            "read_core_dump",
+            Some(name.clone()),
+            self.peer,
+            self.read_core_dump_impl(name, id, index, github_auth_token),
+        )
+        .await
+    }
 
-        Ok(metrics)
+    async fn list_guest_profiles(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<GuestProfiles> {
+        audited(
+            "list_guest_profiles",
+            Some(name.clone()),
+            self.peer,
+            self.list_guest_profiles_impl(name, github_auth_token),
+        )
+        .await
     }
-}
 
-// Now implement the trait methods that use the reference-based implementations
-impl FunctionService for FunctionServiceImpl {
-    async fn publish(
+    async fn set_guest_profiling(
         self,
         _: tarpc::context::Context,
-        wasm_file: Vec<u8>,
         name: String,
+        sample_rate: f64,
         github_auth_token: String,
-    ) -> FunctionResult<String> {
-        // Create a reference to self and call the impl method
-        self.publish_impl(wasm_file, name, github_auth_token).await
+    ) -> FunctionResult<GuestProfiles> {
+        audited(
+            "set_guest_profiling",
+            Some(name.clone()),
+            self.peer,
+            self.set_guest_profiling_impl(name, sample_rate, github_auth_token),
+        )
+        .await
     }
 
-    async fn list_functions(
+    async fn read_guest_profile(
         self,
         _: tarpc::context::Context,
+        name: String,
+        id: String,
+        index: u32,
         github_auth_token: String,
-    ) -> FunctionResult<Vec<FunctionInfo>> {
-        // Create a reference to self and call the impl method
-        self.list_functions_impl(github_auth_token).await
+    ) -> FunctionResult<Vec<u8>> {
+        audited(
+            "read_guest_profile",
+            Some(name.clone()),
+            self.peer,
+            self.read_guest_profile_impl(name, id, index, github_auth_token),
+        )
+        .await
     }
 
-    async fn unpublish(
+    async fn list_webhook_deliveries(
         self,
         _: tarpc::context::Context,
         name: String,
+        status: Option<DeliveryStatus>,
         github_auth_token: String,
-    ) -> FunctionResult<()> {
-        // Create a reference to self and call the impl method
-        self.unpublish_impl(name, github_auth_token).await
+    ) -> FunctionResult<Vec<WebhookDelivery>> {
+        audited(
+            "list_webhook_deliveries",
+            Some(name.clone()),
+            self.peer,
+            self.list_webhook_deliveries_impl(name, status, github_auth_token),
+        )
+        .await
+    }
+
+    async fn redeliver_webhook(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        id: String,
+        github_auth_token: String,
+    ) -> FunctionResult<WebhookDelivery> {
+        audited(
+            "redeliver_webhook",
+            Some(name.clone()),
+            self.peer,
+            self.redeliver_webhook_impl(name, id, github_auth_token),
+        )
+        .await
+    }
+
+    async fn get_signing_keys(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<SigningKeys> {
+        audited(
+            "get_signing_keys",
+            Some(name.clone()),
+            self.peer,
+            self.get_signing_keys_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_response_signing(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        enabled: bool,
+        rotate: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<SigningKeys> {
+        audited(
+            "set_response_signing",
+            Some(name.clone()),
+            self.peer,
+            self.set_response_signing_impl(name, enabled, rotate, github_auth_token),
+        )
+        .await
     }
 
     async fn get_metrics(
@@ -388,21 +4982,642 @@ impl FunctionService for FunctionServiceImpl {
         _: tarpc::context::Context,
         github_auth_token: String,
     ) -> FunctionResult<Metrics> {
-        // Create a reference to self and call the impl method
-        self.get_metrics_impl(github_auth_token).await
+        audited(
+            "get_metrics",
+            None,
+            self.peer,
+            self.get_metrics_impl(github_auth_token),
+        )
+        .await
+    }
+
+    async fn function_status(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<FunctionStatus> {
+        audited(
+            "function_status",
+            Some(name.clone()),
+            self.peer,
+            self.function_status_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn get_function_metrics(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<FunctionStats> {
+        audited(
+            "get_function_metrics",
+            Some(name.clone()),
+            self.peer,
+            self.get_function_metrics_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn create_api_key(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        scopes: Vec<ApiKeyScope>,
+        github_auth_token: String,
+    ) -> FunctionResult<NewApiKey> {
+        audited(
+            "create_api_key",
+            Some(name.clone()),
+            self.peer,
+            self.create_api_key_impl(name, scopes, github_auth_token),
+        )
+        .await
+    }
+
+    async fn list_api_keys(
+        self,
+        _: tarpc::context::Context,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<ApiKeyInfo>> {
+        audited(
+            "list_api_keys",
+            None,
+            self.peer,
+            self.list_api_keys_impl(github_auth_token),
+        )
+        .await
+    }
+
+    async fn revoke_api_key(
+        self,
+        _: tarpc::context::Context,
+        id: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "revoke_api_key",
+            Some(id.clone()),
+            self.peer,
+            self.revoke_api_key_impl(id, github_auth_token),
+        )
+        .await
+    }
+
+    async fn list_sessions(
+        self,
+        _: tarpc::context::Context,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<SessionInfo>> {
+        audited(
+            "list_sessions",
+            None,
+            self.peer,
+            self.list_sessions_impl(github_auth_token),
+        )
+        .await
+    }
+
+    async fn revoke_session(
+        self,
+        _: tarpc::context::Context,
+        id: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "revoke_session",
+            Some(id.clone()),
+            self.peer,
+            self.revoke_session_impl(id, github_auth_token),
+        )
+        .await
+    }
+
+    async fn label_session(
+        self,
+        _: tarpc::context::Context,
+        label: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "label_session",
+            None,
+            self.peer,
+            self.label_session_impl(label, github_auth_token),
+        )
+        .await
+    }
+
+    async fn list_alerts(
+        self,
+        _: tarpc::context::Context,
+        all: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<AnomalyAlert>> {
+        audited(
+            "list_alerts",
+            None,
+            self.peer,
+            self.list_alerts_impl(all, github_auth_token),
+        )
+        .await
+    }
+
+    async fn resolve_alert(
+        self,
+        _: tarpc::context::Context,
+        id: u64,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "resolve_alert",
+            Some(id.to_string()),
+            self.peer,
+            self.resolve_alert_impl(id, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_role(
+        self,
+        _: tarpc::context::Context,
+        principal: String,
+        role: Option<PlatformRole>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "set_role",
+            Some(principal.clone()),
+            self.peer,
+            self.set_role_impl(principal, role, github_auth_token),
+        )
+        .await
+    }
+
+    async fn list_roles(
+        self,
+        _: tarpc::context::Context,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<RoleGrant>> {
+        audited(
+            "list_roles",
+            None,
+            self.peer,
+            self.list_roles_impl(github_auth_token),
+        )
+        .await
+    }
+
+    async fn suspend_user(
+        self,
+        _: tarpc::context::Context,
+        username: String,
+        reason: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "suspend_user",
+            Some(username.clone()),
+            self.peer,
+            self.suspend_user_impl(username, reason, github_auth_token),
+        )
+        .await
+    }
+
+    async fn unsuspend_user(
+        self,
+        _: tarpc::context::Context,
+        username: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "unsuspend_user",
+            Some(username.clone()),
+            self.peer,
+            self.unsuspend_user_impl(username, github_auth_token),
+        )
+        .await
+    }
+
+    async fn kill_function(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        reason: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "kill_function",
+            Some(name.clone()),
+            self.peer,
+            self.kill_function_impl(name, reason, github_auth_token),
+        )
+        .await
+    }
+
+    async fn get_circuit_breaker(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<CircuitBreaker>> {
+        audited(
+            "get_circuit_breaker",
+            Some(name.clone()),
+            self.peer,
+            self.get_circuit_breaker_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn reset_circuit_breaker(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "reset_circuit_breaker",
+            Some(name.clone()),
+            self.peer,
+            self.reset_circuit_breaker_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn audit_log(
+        self,
+        _: tarpc::context::Context,
+        after: Option<u64>,
+        limit: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<AuditEvent>> {
+        audited(
+            "audit_log",
+            None,
+            self.peer,
+            self.audit_log_impl(after, limit, github_auth_token),
+        )
+        .await
+    }
+
+    async fn server_events(
+        self,
+        _: tarpc::context::Context,
+        after: Option<u64>,
+        limit: u32,
+        min_severity: EventSeverity,
+        kind: Option<ServerEventKind>,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<ServerEvent>> {
+        audited(
+            "server_events",
+            None,
+            self.peer,
+            self.server_events_impl(after, limit, min_severity, kind, github_auth_token),
+        )
+        .await
+    }
+
+    async fn consistency_report(
+        self,
+        _: tarpc::context::Context,
+        github_auth_token: String,
+    ) -> FunctionResult<Option<ConsistencyReport>> {
+        audited(
+            "consistency_report",
+            None,
+            self.peer,
+            self.consistency_report_impl(github_auth_token),
+        )
+        .await
+    }
+
+    async fn check_consistency(
+        self,
+        _: tarpc::context::Context,
+        repair: Vec<DriftKind>,
+        github_auth_token: String,
+    ) -> FunctionResult<ConsistencyReport> {
+        audited(
+            "check_consistency",
+            None,
+            self.peer,
+            self.check_consistency_impl(repair, github_auth_token),
+        )
+        .await
+    }
+
+    async fn capacity_report(
+        self,
+        _: tarpc::context::Context,
+        refresh: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<CapacityReport> {
+        audited(
+            "capacity_report",
+            None,
+            self.peer,
+            self.capacity_report_impl(refresh, github_auth_token),
+        )
+        .await
+    }
+
+    async fn create_backup(
+        self,
+        _: tarpc::context::Context,
+        github_auth_token: String,
+    ) -> FunctionResult<BackupInfo> {
+        audited(
+            "create_backup",
+            None,
+            self.peer,
+            self.create_backup_impl(github_auth_token),
+        )
+        .await
+    }
+
+    async fn read_backup(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        index: u32,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<u8>> {
+        audited(
+            "read_backup",
+            None,
+            self.peer,
+            self.read_backup_impl(name, index, github_auth_token),
+        )
+        .await
+    }
+
+    async fn collect_garbage(
+        self,
+        _: tarpc::context::Context,
+        dry_run: bool,
+        github_auth_token: String,
+    ) -> FunctionResult<GarbageReport> {
+        audited(
+            "collect_garbage",
+            None,
+            self.peer,
+            self.collect_garbage_impl(dry_run, github_auth_token),
+        )
+        .await
+    }
+
+    async fn list_cluster_nodes(
+        self,
+        _: tarpc::context::Context,
+        github_auth_token: String,
+    ) -> FunctionResult<Vec<ClusterNode>> {
+        audited(
+            "list_cluster_nodes",
+            None,
+            self.peer,
+            self.list_cluster_nodes_impl(github_auth_token),
+        )
+        .await
+    }
+
+    async fn force_delete_function(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "force_delete_function",
+            Some(name.clone()),
+            self.peer,
+            self.force_delete_function_impl(name, github_auth_token),
+        )
+        .await
+    }
+
+    async fn set_project_limit(
+        self,
+        _: tarpc::context::Context,
+        username: String,
+        limit: Option<u32>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        audited(
+            "set_project_limit",
+            Some(username.clone()),
+            self.peer,
+            self.set_project_limit_impl(username, limit, github_auth_token),
+        )
+        .await
+    }
+
+    async fn export_usage(
+        self,
+        _: tarpc::context::Context,
+        period: Option<String>,
+        format: UsageFormat,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "export_usage",
+            period.clone(),
+            self.peer,
+            self.export_usage_impl(period, format, github_auth_token),
+        )
+        .await
+    }
+
+    async fn stage_routes(
+        self,
+        _: tarpc::context::Context,
+        name: String,
+        routes: Vec<RouteUpload>,
+        github_auth_token: String,
+    ) -> FunctionResult<String> {
+        audited(
+            "stage_routes",
+            Some(name.clone()),
+            self.peer,
+            self.stage_routes_impl(name, routes, github_auth_token),
+        )
+        .await
+    }
+
+    async fn upload_chunk_at(
+        self,
+        _: tarpc::context::Context,
+        upload_id: String,
+        offset: u64,
+        chunk: Vec<u8>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        // Sent many times per upload, like upload_chunk
+        self.upload_chunk_impl(upload_id, Some(offset), chunk, github_auth_token)
+            .await
+    }
+
+    async fn upload_known_chunks_at(
+        self,
+        _: tarpc::context::Context,
+        upload_id: String,
+        offset: u64,
+        digests: Vec<String>,
+        github_auth_token: String,
+    ) -> FunctionResult<()> {
+        // Sent many times per upload, like upload_chunk
+        self.upload_known_chunks_impl(upload_id, Some(offset), digests, github_auth_token)
+            .await
     }
 }
 
 /// Helper function to create a service implementation with GitHub auth
-pub fn create_service() -> anyhow::Result<FunctionServiceImpl> {
+pub fn create_service(peer: Option<IpAddr>) -> anyhow::Result<FunctionServiceImpl> {
     use crate::metrics::Timer;
     use tracing::info;
 
     info!("Initializing RPC service...");
     let rpc_init_timer = Timer::new("rpc_service_initialization".to_string());
-    let service = FunctionServiceImpl::new()?;
+    let service = FunctionServiceImpl::new(peer)?;
     drop(rpc_init_timer); // Explicitly drop to record timing
     info!("RPC service initialization complete");
 
     Ok(service)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, published_at: &str, tags: &[&str]) -> FunctionInfo {
+        FunctionInfo {
+            name: name.to_string(),
+            owner: "alice".to_string(),
+            published_at: published_at.to_string(),
+            usage: function_usage(name),
+            metadata: FunctionMetadata {
+                description: format!("The {name} function"),
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                routes: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_errors_keep_their_kind() {
+        assert!(matches!(
+            not_enabled("static assets"),
+            FaastaError::InvalidInput(message) if message == "This server doesn't have static assets enabled"
+        ));
+
+        let offset = anyhow::Error::new(UnexpectedOffset {
+            offset: 0,
+            received: 10,
+        });
+        assert!(matches!(append_error(offset), FaastaError::InvalidInput(_)));
+        assert!(matches!(
+            append_error(anyhow::anyhow!("disk full")),
+            FaastaError::Internal { .. }
+        ));
+
+        let limit = anyhow::Error::new(UploadLimit {
+            quota: "uploads in progress",
+            limit: 4,
+        });
+        assert!(matches!(
+            begin_upload_error(limit),
+            FaastaError::QuotaExceeded { limit: 4, .. }
+        ));
+        // Internal errors only carry the id the message was logged under
+        assert!(matches!(
+            begin_upload_error(anyhow::anyhow!("/var/lib/faasta is full")),
+            FaastaError::Internal { id } if id.starts_with("err_")
+        ));
+    }
+
+    #[test]
+    fn test_function_records_decode_with_and_without_metadata() {
+        let config = bincode::config::standard();
+        let current = info("api", "2025-01-01T00:00:00+00:00", &["orders"]);
+        let encoded = bincode::encode_to_vec(&current, config).unwrap();
+        let decoded = decode_function_info(&encoded).unwrap();
+        assert_eq!(decoded.name, current.name);
+        assert_eq!(decoded.metadata, current.metadata);
+
+        // Written before functions carried metadata
+        let legacy = bincode::encode_to_vec(
+            ("api", "alice", "2025-01-01T00:00:00+00:00", "usage"),
+            config,
+        )
+        .unwrap();
+        let decoded = decode_function_info(&legacy).unwrap();
+        assert_eq!(
+            (decoded.name.as_str(), decoded.usage.as_str()),
+            ("api", "usage")
+        );
+        assert_eq!(decoded.metadata, FunctionMetadata::default());
+
+        assert!(decode_function_info(b"\xff").is_err());
+    }
+
+    #[test]
+    fn test_functions_are_filtered_sorted_and_paged() {
+        let functions = vec![
+            info("web", "2025-03-01T00:00:00+00:00", &["frontend"]),
+            info("API", "2025-01-01T00:00:00+00:00", &["orders"]),
+            info("orders-api", "2025-02-01T00:00:00+00:00", &["orders"]),
+        ];
+        let invocations = |name: &str| match name {
+            "orders-api" => 50,
+            _ => 5,
+        };
+        let names = |page: &FunctionPage| -> Vec<String> {
+            page.functions.iter().map(|f| f.name.clone()).collect()
+        };
+        let list = |query: &FunctionQuery| page_functions(functions.clone(), query, invocations);
+
+        let mut query = FunctionQuery::all();
+        assert_eq!(names(&list(&query)), ["API", "orders-api", "web"]);
+        query.sort = FunctionSort::LastDeploy;
+        assert_eq!(names(&list(&query)), ["web", "orders-api", "API"]);
+        query.reverse = true;
+        assert_eq!(names(&list(&query)), ["API", "orders-api", "web"]);
+        // Equal counts fall back to the name
+        query = FunctionQuery {
+            sort: FunctionSort::Invocations,
+            ..FunctionQuery::all()
+        };
+        assert_eq!(names(&list(&query)), ["orders-api", "API", "web"]);
+
+        // Filters ignore case and count separately from the total
+        query = FunctionQuery {
+            name_filter: Some("api".to_string()),
+            tag: Some("orders".to_string()),
+            ..FunctionQuery::all()
+        };
+        let page = list(&query);
+        assert_eq!(names(&page), ["API", "orders-api"]);
+        assert_eq!((page.matching, page.total), (2, 3));
+
+        // Page 0 is the first page, and pages past the end are empty
+        query = FunctionQuery {
+            page: 0,
+            limit: 2,
+            ..FunctionQuery::all()
+        };
+        let page = list(&query);
+        assert_eq!(
+            (names(&page), page.page),
+            (vec!["API".to_string(), "orders-api".to_string()], 1)
+        );
+        query.page = 2;
+        assert_eq!(names(&list(&query)), ["web"]);
+        query.page = u32::MAX;
+        let page = list(&query);
+        assert!(page.functions.is_empty());
+        assert_eq!((page.page, page.matching), (u32::MAX, 3));
+    }
+}
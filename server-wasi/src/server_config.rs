@@ -0,0 +1,467 @@
+//! Server configuration file, reloaded while the server runs.
+//!
+//! `--config` names a TOML file of listen addresses, the base domain, TLS paths,
+//! request and body limits, and auth settings. Every key is optional: what the
+//! file sets overrides the flag or environment variable of the same setting, what
+//! it leaves out keeps the flag's value. A sample:
+//!
+//! ```toml
+//! [listen]
+//! https = "0.0.0.0:443"
+//! http = "0.0.0.0:80"
+//!
+//! [domains]
+//! base = "faasta.xyz"
+//!
+//! [tls]
+//! cert = "./certs/cert.pem"
+//! key = "./certs/key.pem"
+//!
+//! [limits]
+//! max_request_body_mb = 100
+//! header_read_timeout = 30
+//!
+//! [auth]
+//! admins = ["alice"]
+//! default_role = "deployer"
+//! max_sessions = 10
+//! ```
+//!
+//! The file is read again on SIGHUP, and whenever it changes on disk. Only the
+//! settings that changed are applied: a listener is re-bound only if its address
+//! changed, and the certificate reloaded only if its paths did. Connections already
+//! open keep the listener and limits they started with. A file that doesn't parse,
+//! an address that can't be bound or a certificate that can't be loaded leaves the
+//! setting as it was.
+
+use anyhow::{Context, Result};
+use faasta_interface::PlatformRole;
+use serde::Deserialize;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{error, info, warn};
+
+use crate::http::{RequestLimits, REQUEST_LIMITS};
+use crate::roles::ROLES;
+use crate::sessions::SESSIONS;
+use crate::streaming::{BodyLimits, BODY_LIMITS};
+use crate::systemd;
+use crate::tls::CERTIFICATES;
+use crate::wasi_server::SERVER;
+
+/// How often the file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A setting read on every use, so a reload applies to what happens next
+pub struct Reloadable<T>(RwLock<Arc<T>>);
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Self(RwLock::new(Arc::new(value)))
+    }
+
+    /// The current value
+    pub fn load(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn store(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
+
+/// The configuration file
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    listen: ListenSection,
+    domains: DomainsSection,
+    tls: TlsSection,
+    limits: LimitsSection,
+    auth: AuthSection,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct ListenSection {
+    https: Option<SocketAddr>,
+    http: Option<SocketAddr>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct DomainsSection {
+    base: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+}
+
+/// Limits in the units of their flags: MiB, KiB and seconds
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsSection {
+    max_request_body_mb: Option<u64>,
+    max_response_body_mb: Option<u64>,
+    body_read_timeout: Option<u64>,
+    max_stream_secs: Option<u64>,
+    stream_idle_timeout: Option<u64>,
+    sse_keepalive: Option<u64>,
+    max_request_headers: Option<usize>,
+    max_request_head_kb: Option<usize>,
+    header_read_timeout: Option<u64>,
+    max_concurrent_streams: Option<u32>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSection {
+    admins: Option<Vec<String>>,
+    default_role: Option<String>,
+    max_sessions: Option<usize>,
+}
+
+/// Read and parse the configuration file at `path`
+pub fn load(path: &Path) -> Result<ConfigFile> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("Invalid server config {}", path.display()))
+}
+
+/// The settings the configuration file can change
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub listen_addr: SocketAddr,
+    pub http_listen_addr: SocketAddr,
+    pub base_domain: String,
+    pub tls_cert_path: PathBuf,
+    pub tls_key_path: PathBuf,
+    pub body_limits: BodyLimits,
+    pub request_limits: RequestLimits,
+    pub admins: Vec<String>,
+    pub default_role: PlatformRole,
+    pub max_sessions: usize,
+}
+
+impl Settings {
+    /// These settings with what `file` sets instead
+    pub fn with_file(&self, file: &ConfigFile) -> Result<Settings> {
+        let mut settings = self.clone();
+        let ConfigFile {
+            listen,
+            domains,
+            tls,
+            limits,
+            auth,
+        } = file;
+
+        set(&mut settings.listen_addr, listen.https);
+        set(&mut settings.http_listen_addr, listen.http);
+        set(&mut settings.base_domain, domains.base.clone());
+        set(&mut settings.tls_cert_path, tls.cert.clone());
+        set(&mut settings.tls_key_path, tls.key.clone());
+
+        let body = &mut settings.body_limits;
+        set(
+            &mut body.max_request_bytes,
+            limits.max_request_body_mb.map(|mb| mb * 1024 * 1024),
+        );
+        set(
+            &mut body.max_response_bytes,
+            limits.max_response_body_mb.map(|mb| mb * 1024 * 1024),
+        );
+        set(
+            &mut body.read_timeout,
+            limits.body_read_timeout.map(Duration::from_secs),
+        );
+        set(
+            &mut body.max_stream,
+            limits.max_stream_secs.map(Duration::from_secs),
+        );
+        set(
+            &mut body.idle_timeout,
+            limits.stream_idle_timeout.map(Duration::from_secs),
+        );
+        set(
+            &mut body.keepalive,
+            limits.sse_keepalive.map(Duration::from_secs),
+        );
+        let request = &mut settings.request_limits;
+        set(&mut request.max_headers, limits.max_request_headers);
+        set(
+            &mut request.max_head_bytes,
+            limits.max_request_head_kb.map(|kb| kb * 1024),
+        );
+        set(
+            &mut request.header_read_timeout,
+            limits.header_read_timeout.map(Duration::from_secs),
+        );
+        set(
+            &mut request.max_concurrent_streams,
+            limits.max_concurrent_streams,
+        );
+
+        set(&mut settings.admins, auth.admins.clone());
+        if let Some(role) = &auth.default_role {
+            settings.default_role = role.parse().map_err(anyhow::Error::msg)?;
+        }
+        set(&mut settings.max_sessions, auth.max_sessions);
+        Ok(settings)
+    }
+
+    /// Names of the settings that differ in `other`
+    fn changes(&self, other: &Settings) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        if self.listen_addr != other.listen_addr {
+            changes.push("listen.https");
+        }
+        if self.http_listen_addr != other.http_listen_addr {
+            changes.push("listen.http");
+        }
+        if self.base_domain != other.base_domain {
+            changes.push("domains");
+        }
+        if (&self.tls_cert_path, &self.tls_key_path) != (&other.tls_cert_path, &other.tls_key_path)
+        {
+            changes.push("tls");
+        }
+        if self.body_limits != other.body_limits || self.request_limits != other.request_limits {
+            changes.push("limits");
+        }
+        if (&self.admins, self.default_role, self.max_sessions)
+            != (&other.admins, other.default_role, other.max_sessions)
+        {
+            changes.push("auth");
+        }
+        changes
+    }
+}
+
+fn set<T>(setting: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *setting = value;
+    }
+}
+
+/// Where re-bound listeners are handed to the servers accepting on them
+pub struct Listeners {
+    pub https: UnboundedSender<TcpListener>,
+    pub http: UnboundedSender<TcpListener>,
+}
+
+/// Reload the file at `path` on SIGHUP and when it changes. `base` are the settings
+/// from flags, `current` those in effect.
+pub fn spawn_reloader(path: PathBuf, base: Settings, mut current: Settings, listeners: Listeners) {
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!("Failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        let mut last_modified = modified_at(&path);
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => info!("Received SIGHUP, reloading {}", path.display()),
+                _ = poll.tick() => {
+                    let modified = modified_at(&path);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                    info!("{} changed, reloading it", path.display());
+                }
+            }
+            systemd::notify_reloading();
+            match load(&path).and_then(|file| base.with_file(&file)) {
+                Ok(next) => apply(&mut current, next, &listeners).await,
+                Err(e) => error!("Keeping the current configuration: {:#}", e),
+            }
+            systemd::notify("READY=1");
+        }
+    });
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Apply the settings of `next` that differ from `current`, recording in `current`
+/// those that took effect
+async fn apply(current: &mut Settings, next: Settings, listeners: &Listeners) {
+    let changes = current.changes(&next);
+    if changes.is_empty() {
+        info!("Configuration reloaded, nothing changed");
+        return;
+    }
+
+    if next.listen_addr != current.listen_addr {
+        match rebind(next.listen_addr, &listeners.https).await {
+            Ok(()) => current.listen_addr = next.listen_addr,
+            Err(e) => error!("Keeping HTTPS on {}: {:#}", current.listen_addr, e),
+        }
+    }
+    if next.http_listen_addr != current.http_listen_addr {
+        match rebind(next.http_listen_addr, &listeners.http).await {
+            Ok(()) => current.http_listen_addr = next.http_listen_addr,
+            Err(e) => error!("Keeping HTTP on {}: {:#}", current.http_listen_addr, e),
+        }
+    }
+
+    if next.base_domain != current.base_domain {
+        if let Some(server) = SERVER.get() {
+            server.base_domain.store(next.base_domain.clone());
+        }
+        current.base_domain = next.base_domain.clone();
+    }
+
+    if (&next.tls_cert_path, &next.tls_key_path) != (&current.tls_cert_path, &current.tls_key_path)
+    {
+        let reloaded = match CERTIFICATES.get() {
+            Some(certificates) => certificates.reload(&next.tls_cert_path, &next.tls_key_path),
+            None => Ok(()),
+        };
+        match reloaded {
+            Ok(()) => {
+                current.tls_cert_path = next.tls_cert_path.clone();
+                current.tls_key_path = next.tls_key_path.clone();
+            }
+            Err(e) => error!("Keeping the current TLS certificate: {:#}", e),
+        }
+    }
+
+    if next.body_limits != current.body_limits {
+        if let Some(limits) = BODY_LIMITS.get() {
+            limits.store(next.body_limits.clone());
+        }
+        current.body_limits = next.body_limits.clone();
+    }
+    if next.request_limits != current.request_limits {
+        if let Some(limits) = REQUEST_LIMITS.get() {
+            limits.store(next.request_limits.clone());
+        }
+        current.request_limits = next.request_limits.clone();
+    }
+
+    if (&next.admins, next.default_role) != (&current.admins, current.default_role) {
+        if let Some(roles) = ROLES.get() {
+            roles.reconfigure(next.admins.clone(), next.default_role);
+        }
+        current.admins = next.admins.clone();
+        current.default_role = next.default_role;
+    }
+    if next.max_sessions != current.max_sessions {
+        if let Some(sessions) = SESSIONS.get() {
+            sessions.set_max_sessions(next.max_sessions);
+        }
+        current.max_sessions = next.max_sessions;
+    }
+
+    let failed = current.changes(&next);
+    if failed.is_empty() {
+        info!("Configuration reloaded, changed {}", changes.join(", "));
+    } else {
+        warn!(
+            "Configuration partly reloaded, changed {} but not {}",
+            changes
+                .iter()
+                .filter(|change| !failed.contains(change))
+                .copied()
+                .collect::<Vec<_>>()
+                .join(", "),
+            failed.join(", ")
+        );
+    }
+}
+
+/// Bind `addr` and hand the listener to the server behind `listener`
+async fn rebind(addr: SocketAddr, listener: &UnboundedSender<TcpListener>) -> Result<()> {
+    let bound = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind to {addr}"))?;
+    listener
+        .send(bound)
+        .map_err(|_| anyhow::anyhow!("The server on {addr} stopped"))?;
+    info!("Now listening on {}", addr);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags() -> Settings {
+        Settings {
+            listen_addr: "0.0.0.0:443".parse().unwrap(),
+            http_listen_addr: "0.0.0.0:80".parse().unwrap(),
+            base_domain: "faasta.xyz".to_string(),
+            tls_cert_path: PathBuf::from("./certs/cert.pem"),
+            tls_key_path: PathBuf::from("./certs/key.pem"),
+            body_limits: BodyLimits {
+                max_request_bytes: 0,
+                max_response_bytes: 0,
+                read_timeout: Duration::from_secs(30),
+                max_stream: Duration::ZERO,
+                idle_timeout: Duration::ZERO,
+                keepalive: Duration::from_secs(15),
+            },
+            request_limits: RequestLimits::default(),
+            admins: Vec::new(),
+            default_role: PlatformRole::Deployer,
+            max_sessions: 0,
+        }
+    }
+
+    #[test]
+    fn test_file_overrides_flags_and_reports_changes() {
+        let base = flags();
+        let file: ConfigFile = toml::from_str(
+            r#"
+            [listen]
+            http = "0.0.0.0:8080"
+
+            [limits]
+            max_request_body_mb = 2
+            header_read_timeout = 5
+
+            [auth]
+            admins = ["alice"]
+            default_role = "viewer"
+            "#,
+        )
+        .unwrap();
+        let settings = base.with_file(&file).unwrap();
+        assert_eq!(settings.listen_addr, base.listen_addr);
+        assert_eq!(settings.http_listen_addr.port(), 8080);
+        assert_eq!(settings.body_limits.max_request_bytes, 2 * 1024 * 1024);
+        assert_eq!(
+            settings.request_limits.header_read_timeout,
+            Duration::from_secs(5)
+        );
+        assert_eq!(settings.admins, ["alice"]);
+        assert_eq!(settings.default_role, PlatformRole::Viewer);
+        assert_eq!(base.changes(&settings), ["listen.http", "limits", "auth"]);
+
+        // Keys taken out of the file go back to their flags
+        let settings = base.with_file(&ConfigFile::default()).unwrap();
+        assert_eq!(settings, base);
+        assert!(base.changes(&settings).is_empty());
+
+        // Typos and bad values are errors, not silently ignored
+        assert!(toml::from_str::<ConfigFile>("[limits]\nmax_body_mb = 1").is_err());
+        let file: ConfigFile = toml::from_str("[auth]\ndefault_role = \"owner\"").unwrap();
+        assert!(base.with_file(&file).is_err());
+    }
+}
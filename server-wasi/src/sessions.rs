@@ -0,0 +1,400 @@
+//! Credentials seen by the server.
+//!
+//! Every provider login and API key that authenticates is recorded with the time
+//! it was last used, so users can spot credentials they don't recognise and cut
+//! them off. Revoking a login keeps its record around, marked revoked, so the
+//! token is refused even though the provider itself still accepts it. An account
+//! at its most active logins that logs in again has its least recently used login
+//! revoked that way, rather than being locked out.
+
+use anyhow::Result;
+use bincode::{Decode, Encode};
+use chrono::{DateTime, Utc};
+use faasta_interface::{ApiKeyInfo, SessionInfo, SessionKind};
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::info;
+
+use crate::metadata_store::{hex_encode_keys, MetadataStore};
+
+/// Collection of the metadata store holding sessions, keyed by the hex SHA-256
+/// hash of the credential
+const SESSIONS_TREE: &str = "sessions";
+/// Logins unused for this long no longer count as active
+const SESSION_IDLE_DAYS: i64 = 30;
+/// Logins are recorded as used at most this often, in seconds
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+/// Global session registry, set at startup
+pub static SESSIONS: OnceCell<SessionStore> = OnceCell::new();
+
+#[derive(Clone, Debug, Encode, Decode)]
+struct Session {
+    id: String,
+    username: String,
+    kind: SessionKind,
+    label: String,
+    /// Unix timestamps (seconds)
+    created_at: i64,
+    last_used: i64,
+    revoked: bool,
+}
+
+/// Whether a login may go ahead
+pub enum Admission {
+    Allowed,
+    Revoked,
+}
+
+pub struct SessionStore {
+    store: Arc<dyn MetadataStore>,
+    /// Most active logins per account (0 for no limit)
+    max_sessions: AtomicUsize,
+}
+
+impl SessionStore {
+    pub fn new(store: Arc<dyn MetadataStore>, db: &sled::Db, max_sessions: usize) -> Result<Self> {
+        hex_encode_keys(db, SESSIONS_TREE)?;
+        Ok(Self {
+            store,
+            max_sessions: AtomicUsize::new(max_sessions),
+        })
+    }
+
+    /// Change the most active logins per account, for logins from now on
+    pub fn set_max_sessions(&self, max_sessions: usize) {
+        self.max_sessions.store(max_sessions, Ordering::Relaxed);
+    }
+
+    /// Record a use of a provider login, creating its session the first time
+    pub fn touch_login(&self, username: &str, token: &str) -> Result<Admission> {
+        let hash = hash_credential(token);
+        let now = Utc::now().timestamp();
+
+        let session = match self.get(&hash)? {
+            Some(session) if session.revoked => return Ok(Admission::Revoked),
+            Some(session) if now - session.last_used < TOUCH_INTERVAL_SECS => {
+                return Ok(Admission::Allowed)
+            }
+            Some(session) => Session {
+                last_used: now,
+                ..session
+            },
+            None => {
+                let max_sessions = self.max_sessions.load(Ordering::Relaxed);
+                if max_sessions > 0 {
+                    self.evict_logins(username, now, max_sessions - 1)?;
+                }
+                Session {
+                    id: hex_id(&hash),
+                    username: username.to_string(),
+                    kind: SessionKind::Login,
+                    label: "unlabeled".to_string(),
+                    created_at: now,
+                    last_used: now,
+                    revoked: false,
+                }
+            }
+        };
+        self.save(&hash, &session)?;
+        Ok(Admission::Allowed)
+    }
+
+    /// Record a use of an API key
+    pub fn touch_api_key(&self, username: &str, key: &str, info: &ApiKeyInfo) -> Result<()> {
+        let hash = hash_credential(key);
+        let now = Utc::now().timestamp();
+        let existing = self.get(&hash)?;
+        if existing
+            .as_ref()
+            .is_some_and(|session| now - session.last_used < TOUCH_INTERVAL_SECS)
+        {
+            return Ok(());
+        }
+
+        let session = Session {
+            id: info.id.clone(),
+            username: username.to_string(),
+            kind: SessionKind::ApiKey,
+            label: info.name.clone(),
+            created_at: existing.map_or(now, |session| session.created_at),
+            last_used: now,
+            revoked: false,
+        };
+        self.save(&hash, &session)
+    }
+
+    /// Name the device a login is used from
+    pub fn set_label(&self, token: &str, label: &str) -> Result<bool> {
+        let hash = hash_credential(token);
+        let Some(session) = self.get(&hash)? else {
+            return Ok(false);
+        };
+        self.save(
+            &hash,
+            &Session {
+                label: label.to_string(),
+                ..session
+            },
+        )?;
+        Ok(true)
+    }
+
+    /// `username`'s logins, plus their API keys with the time each was last used
+    pub fn list(
+        &self,
+        username: &str,
+        api_keys: Vec<ApiKeyInfo>,
+        token: &str,
+    ) -> Result<Vec<SessionInfo>> {
+        let current = hex_id(&hash_credential(token));
+        // API key ids are random, so the key in use is found by its session
+        let current_key = match crate::api_keys::parse_api_key(token) {
+            Some((_, key)) => self.get(&hash_credential(key))?.map(|session| session.id),
+            None => None,
+        };
+        let sessions: Vec<Session> = self
+            .sessions()?
+            .into_iter()
+            .filter(|(_, session)| session.username == username && !session.revoked)
+            .map(|(_, session)| session)
+            .collect();
+
+        let logins = sessions
+            .iter()
+            .filter(|session| session.kind == SessionKind::Login)
+            .map(|session| SessionInfo {
+                id: session.id.clone(),
+                kind: SessionKind::Login,
+                label: session.label.clone(),
+                created_at: rfc3339(session.created_at),
+                last_used: Some(rfc3339(session.last_used)),
+                current: session.id == current,
+            });
+        let keys = api_keys.into_iter().map(|key| {
+            let last_used = sessions
+                .iter()
+                .find(|session| session.kind == SessionKind::ApiKey && session.id == key.id)
+                .map(|session| rfc3339(session.last_used));
+            SessionInfo {
+                current: current_key.as_deref() == Some(key.id.as_str()),
+                id: key.id,
+                kind: SessionKind::ApiKey,
+                label: key.name,
+                created_at: key.created_at,
+                last_used,
+            }
+        });
+        Ok(logins.chain(keys).collect())
+    }
+
+    /// Revoke `username`'s login with `id`, returning whether there was one
+    pub fn revoke_login(&self, username: &str, id: &str) -> Result<bool> {
+        let Some((hash, session)) = self.sessions()?.into_iter().find(|(_, session)| {
+            session.username == username
+                && session.kind == SessionKind::Login
+                && session.id == id
+                && !session.revoked
+        }) else {
+            return Ok(false);
+        };
+        self.save(
+            &hash,
+            &Session {
+                revoked: true,
+                ..session
+            },
+        )?;
+        Ok(true)
+    }
+
+    /// Revoke `username`'s least recently used active logins until at most `keep`
+    /// are left
+    fn evict_logins(&self, username: &str, now: i64, keep: usize) -> Result<()> {
+        let cutoff = now - SESSION_IDLE_DAYS * 24 * 3600;
+        let mut active: Vec<(String, Session)> = self
+            .sessions()?
+            .into_iter()
+            .filter(|(_, session)| {
+                session.username == username
+                    && session.kind == SessionKind::Login
+                    && !session.revoked
+                    && session.last_used > cutoff
+            })
+            .collect();
+        if active.len() <= keep {
+            return Ok(());
+        }
+        active.sort_by_key(|(_, session)| (session.last_used, session.created_at));
+        let evicted = active.len() - keep;
+        for (hash, session) in active.into_iter().take(evicted) {
+            info!(
+                "Revoking login {} of '{}', the least recently used",
+                session.id, username
+            );
+            self.save(
+                &hash,
+                &Session {
+                    revoked: true,
+                    ..session
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, hash: &str) -> Result<Option<Session>> {
+        let Some(value) = self.store.get(SESSIONS_TREE, hash)? else {
+            return Ok(None);
+        };
+        Ok(
+            bincode::decode_from_slice::<Session, _>(&value, bincode::config::standard())
+                .ok()
+                .map(|(session, _)| session),
+        )
+    }
+
+    fn save(&self, hash: &str, session: &Session) -> Result<()> {
+        let encoded = bincode::encode_to_vec(session, bincode::config::standard())?;
+        self.store.insert(SESSIONS_TREE, hash, encoded)
+    }
+
+    fn sessions(&self) -> Result<Vec<(String, Session)>> {
+        Ok(self
+            .store
+            .scan(SESSIONS_TREE)?
+            .into_iter()
+            .filter_map(|(hash, value)| {
+                bincode::decode_from_slice::<Session, _>(&value, bincode::config::standard())
+                    .ok()
+                    .map(|(session, _)| (hash, session))
+            })
+            .collect())
+    }
+}
+
+fn hash_credential(credential: &str) -> String {
+    hex::encode(Sha256::digest(credential.as_bytes()))
+}
+
+/// Short id shown to users for a login
+fn hex_id(hash: &str) -> String {
+    hash[..8].to_string()
+}
+
+fn rfc3339(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_keys::ApiKeyStore;
+    use crate::metadata_store::SledStore;
+
+    #[test]
+    fn test_the_api_key_in_use_is_current() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store: Arc<dyn MetadataStore> = Arc::new(SledStore::new(&db));
+        let sessions = SessionStore::new(store.clone(), &db, 0).unwrap();
+        let api_keys = ApiKeyStore::new(store, &db).unwrap();
+        let (ci, ci_info) = api_keys.create("alice", "ci", vec![]).unwrap();
+        let (deploy, deploy_info) = api_keys.create("alice", "deploy", vec![]).unwrap();
+        sessions.touch_api_key("alice", &ci, &ci_info).unwrap();
+        sessions
+            .touch_api_key("alice", &deploy, &deploy_info)
+            .unwrap();
+
+        let current = |token: &str| -> Vec<String> {
+            sessions
+                .list("alice", api_keys.list("alice").unwrap(), token)
+                .unwrap()
+                .into_iter()
+                .filter(|session| session.current)
+                .map(|session| session.id)
+                .collect()
+        };
+        assert_eq!(current(&format!("alice:{ci}")), [ci_info.id]);
+        assert_eq!(current(&deploy), [deploy_info.id]);
+        assert!(current("gho_token").is_empty());
+    }
+
+    fn logins(sessions: &SessionStore, username: &str) -> Vec<SessionInfo> {
+        sessions.list(username, vec![], "").unwrap()
+    }
+
+    #[test]
+    fn test_revoked_logins_are_refused() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let sessions = SessionStore::new(Arc::new(SledStore::new(&db)), &db, 0).unwrap();
+        assert!(matches!(
+            sessions.touch_login("alice", "gho_laptop").unwrap(),
+            Admission::Allowed
+        ));
+        assert!(sessions.set_label("gho_laptop", "laptop").unwrap());
+        assert!(!sessions.set_label("gho_unknown", "phone").unwrap());
+        let listed = logins(&sessions, "alice");
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].label, "laptop");
+        assert!(logins(&sessions, "bob").is_empty());
+
+        let id = listed[0].id.clone();
+        assert!(!sessions.revoke_login("bob", &id).unwrap());
+        assert!(sessions.revoke_login("alice", &id).unwrap());
+        assert!(!sessions.revoke_login("alice", &id).unwrap());
+        assert!(logins(&sessions, "alice").is_empty());
+        assert!(matches!(
+            sessions.touch_login("alice", "gho_laptop").unwrap(),
+            Admission::Revoked
+        ));
+    }
+
+    #[test]
+    fn test_a_login_past_the_limit_revokes_the_least_recently_used() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let sessions = SessionStore::new(Arc::new(SledStore::new(&db)), &db, 2).unwrap();
+        let now = Utc::now().timestamp();
+        // Logged in from the laptop first, but used the phone longest ago
+        for (token, last_used) in [("gho_laptop", now - 10), ("gho_phone", now - 20)] {
+            sessions.touch_login("alice", token).unwrap();
+            let hash = hash_credential(token);
+            let session = sessions.get(&hash).unwrap().unwrap();
+            sessions
+                .save(
+                    &hash,
+                    &Session {
+                        last_used,
+                        ..session
+                    },
+                )
+                .unwrap();
+        }
+        sessions.touch_login("bob", "gho_bob").unwrap();
+
+        assert!(matches!(
+            sessions.touch_login("alice", "gho_desktop").unwrap(),
+            Admission::Allowed
+        ));
+        assert_eq!(logins(&sessions, "alice").len(), 2);
+        assert!(matches!(
+            sessions.touch_login("alice", "gho_phone").unwrap(),
+            Admission::Revoked
+        ));
+        assert!(matches!(
+            sessions.touch_login("alice", "gho_laptop").unwrap(),
+            Admission::Allowed
+        ));
+        assert_eq!(logins(&sessions, "bob").len(), 1);
+
+        // Lowering the limit takes effect on the next login
+        sessions.set_max_sessions(1);
+        sessions.touch_login("alice", "gho_tablet").unwrap();
+        let listed = logins(&sessions, "alice");
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, hex_id(&hash_credential("gho_tablet")));
+    }
+}
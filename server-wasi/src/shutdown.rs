@@ -0,0 +1,170 @@
+//! Graceful shutdown.
+//!
+//! On SIGTERM or SIGINT the server stops accepting HTTP, HTTPS and RPC connections,
+//! and asks the open HTTP connections to close once their current request is
+//! answered. Open RPC connections take no new streams and close each stream once
+//! the requests in flight on it are answered. It then waits up to `--drain-timeout` seconds for the connections and
+//! invocations in flight to finish, folds metrics and usage into their records,
+//! flushes its databases and exports the spans left, so a restart neither drops
+//! requests nor leaves sled half-written.
+
+use faasta_interface::{EventSeverity, ServerEventKind};
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::journal;
+use crate::metrics::{self, METRICS_DB};
+use crate::systemd;
+use crate::telemetry;
+use crate::usage::USAGE;
+use crate::wasi_server::SERVER;
+
+/// The server's shutdown state
+pub static SHUTDOWN: Lazy<Shutdown> = Lazy::new(Shutdown::default);
+
+#[derive(Default)]
+pub struct Shutdown {
+    /// Cancelled once the server starts draining
+    draining: CancellationToken,
+    /// Connections and invocations not finished yet
+    in_flight: AtomicUsize,
+    /// Woken whenever the last of them finishes
+    idle: Notify,
+}
+
+impl Shutdown {
+    /// Stop taking new work
+    pub fn begin(&self) {
+        self.draining.cancel();
+    }
+
+    /// Whether the server started draining
+    pub fn is_draining(&self) -> bool {
+        self.draining.is_cancelled()
+    }
+
+    /// Resolves once the server starts draining
+    pub async fn draining(&self) {
+        self.draining.cancelled().await
+    }
+
+    /// Count work in flight until the returned guard is dropped
+    pub fn track(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self)
+    }
+
+    /// Wait up to `timeout` for the work in flight to finish, returning whether it
+    /// all did
+    pub async fn drained(&self, timeout: Duration) -> bool {
+        let idle = async {
+            loop {
+                let notified = self.idle.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if self.in_flight.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, idle).await.is_ok()
+    }
+}
+
+/// Work the server waits for before exiting
+pub struct InFlight<'a>(&'a Shutdown);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// Start draining on the first SIGTERM or SIGINT
+pub fn spawn_signal_handler() {
+    tokio::spawn(async {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                return;
+            }
+        };
+        let received = tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+        };
+        info!("Received {}, draining connections", received);
+        systemd::notify("STOPPING=1");
+        SHUTDOWN.begin();
+    });
+}
+
+/// Wait for the work in flight, up to `drain_timeout`, then persist what is only
+/// in memory and flush the databases
+pub async fn finish(drain_timeout: Duration) {
+    let in_flight = SHUTDOWN.in_flight.load(Ordering::SeqCst);
+    info!("Waiting for {} connections and invocations", in_flight);
+    if !SHUTDOWN.drained(drain_timeout).await {
+        warn!(
+            "{} connections and invocations still running after {:?}, closing them",
+            SHUTDOWN.in_flight.load(Ordering::SeqCst),
+            drain_timeout
+        );
+    }
+
+    if let Some(usage) = USAGE.get() {
+        if let Err(e) = usage.accumulate().await {
+            error!("Failed to account usage: {}", e);
+        }
+    }
+    metrics::flush_metrics_to_db();
+    journal::record(
+        EventSeverity::Info,
+        ServerEventKind::ServerStopped,
+        None,
+        format!("faasta server {} stopped", env!("CARGO_PKG_VERSION")),
+    );
+    if let Err(e) = METRICS_DB.flush_async().await {
+        error!("Failed to flush the metrics database: {}", e);
+    }
+    if let Some(server) = SERVER.get() {
+        if let Err(e) = server.metadata_db.flush_async().await {
+            error!("Failed to flush the database: {}", e);
+        }
+    }
+    telemetry::shutdown().await;
+    info!("Shutdown complete");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain_waits_for_work_in_flight() {
+        let shutdown = Shutdown::default();
+        assert!(shutdown.drained(Duration::ZERO).await);
+
+        let request = shutdown.track();
+        let invocation = shutdown.track();
+        shutdown.begin();
+        assert!(!shutdown.drained(Duration::from_millis(10)).await);
+
+        drop(request);
+        let finished = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(invocation);
+        };
+        let (drained, ()) = tokio::join!(shutdown.drained(Duration::from_secs(5)), finished);
+        assert!(drained);
+    }
+}
@@ -0,0 +1,359 @@
+//! Signed responses.
+//!
+//! A function with response signing on gets an Ed25519 key, and each of its
+//! responses carries the `webhook-id`, `webhook-timestamp` and `webhook-signature`
+//! headers of the Standard Webhooks spec (<https://www.standardwebhooks.com>).
+//! The signature covers `{id}.{timestamp}.{body}`, so a consumer holding the
+//! public key, from `cargo faasta keys`, can check a response came from the
+//! function unaltered, even when it was relayed as a webhook delivery. The body is
+//! buffered to be signed, so streamed responses arrive all at once.
+//!
+//! After a rotation the old key keeps signing next to the new one for a day,
+//! each signature listed in the header as the spec allows.
+//!
+//! Message ids are random and made by the server, so a client can't pick the id a
+//! signed response carries, nor have two responses share one. The keys are kept in
+//! the metadata store, so every node of a cluster signs with the keys
+//! `cargo faasta keys` lists. The key pairs are parsed once and cached until the
+//! function's keys change.
+
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use dashmap::DashMap;
+use faasta_interface::{SigningKey, SigningKeys, MAX_SIGNED_RESPONSE_SIZE};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::header::HeaderValue;
+use hyper::Response;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+
+use crate::metadata_store::{Cached, MetadataStore};
+
+/// Collection of the metadata store holding each function's signing keys, keyed by
+/// function name
+pub const SIGNING_KEYS_TREE: &str = "response_signing_keys";
+
+/// How long a rotated-out key keeps signing
+const ROTATION_OVERLAP: chrono::Duration = chrono::Duration::hours(24);
+
+/// Prefix of public keys in the Standard Webhooks format
+const PUBLIC_KEY_PREFIX: &str = "whpk_";
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    /// The key pair as PKCS#8, in base64
+    pkcs8: String,
+    created_at: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKeys {
+    current: StoredKey,
+    previous: Option<StoredKey>,
+    previous_expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl StoredKey {
+    fn generate() -> Result<Self> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| anyhow!("Failed to generate a signing key"))?;
+        Ok(Self {
+            pkcs8: BASE64.encode(pkcs8.as_ref()),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        })
+    }
+
+    fn key_pair(&self) -> Result<Ed25519KeyPair> {
+        let pkcs8 = BASE64.decode(&self.pkcs8)?;
+        Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|e| anyhow!("Invalid signing key: {e}"))
+    }
+
+    fn public(&self) -> Result<SigningKey> {
+        Ok(SigningKey {
+            public_key: public_key(&self.key_pair()?),
+            created_at: self.created_at.clone(),
+        })
+    }
+}
+
+impl StoredKeys {
+    /// The previous key, while it still signs
+    fn live_previous(&self) -> Option<&StoredKey> {
+        match self.previous_expires_at {
+            Some(expires_at) if expires_at > chrono::Utc::now() => self.previous.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn info(&self) -> Result<SigningKeys> {
+        let previous = self.live_previous();
+        Ok(SigningKeys {
+            current: Some(self.current.public()?),
+            previous: previous.map(StoredKey::public).transpose()?,
+            previous_expires_at: previous
+                .and(self.previous_expires_at)
+                .map(|at| at.to_rfc3339()),
+        })
+    }
+}
+
+/// `whpk_`-prefixed base64 of a key pair's public key
+fn public_key(key_pair: &Ed25519KeyPair) -> String {
+    format!(
+        "{PUBLIC_KEY_PREFIX}{}",
+        BASE64.encode(key_pair.public_key().as_ref())
+    )
+}
+
+/// Key pairs a function signs with, as parsed
+struct Signers {
+    key_pairs: Arc<Vec<Ed25519KeyPair>>,
+    /// When the previous key stops signing, which makes these stale
+    stale_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub struct ResponseSigning {
+    /// Stored keys of each function, as JSON
+    keys: Cached<Vec<u8>>,
+    /// Parsed key pairs of the functions that served requests, by name
+    signers: DashMap<String, Signers>,
+}
+
+impl ResponseSigning {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Result<Self> {
+        Ok(Self {
+            keys: Cached::new(store, SIGNING_KEYS_TREE, |value| Some(value.to_vec()))?,
+            signers: DashMap::new(),
+        })
+    }
+
+    fn stored(&self, name: &str) -> Result<Option<StoredKeys>> {
+        self.keys
+            .get(name)
+            .map(|value| Ok(serde_json::from_slice(&value)?))
+            .transpose()
+    }
+
+    /// The public keys `name`'s responses are signed with
+    pub fn keys(&self, name: &str) -> Result<SigningKeys> {
+        match self.stored(name)? {
+            Some(stored) => stored.info(),
+            None => Ok(SigningKeys::default()),
+        }
+    }
+
+    /// Turn signing on for `name`, generating a key if it has none or `rotate` is set
+    pub fn enable(&self, name: &str, rotate: bool) -> Result<SigningKeys> {
+        let stored = match self.stored(name)? {
+            Some(stored) if !rotate => return stored.info(),
+            Some(stored) => StoredKeys {
+                current: StoredKey::generate()?,
+                previous: Some(stored.current),
+                previous_expires_at: Some(chrono::Utc::now() + ROTATION_OVERLAP),
+            },
+            None => StoredKeys {
+                current: StoredKey::generate()?,
+                previous: None,
+                previous_expires_at: None,
+            },
+        };
+        let encoded = serde_json::to_vec(&stored)?;
+        self.keys.insert(name, encoded.clone(), encoded)?;
+        self.forget(name);
+        stored.info()
+    }
+
+    /// Turn signing off for `name`, deleting its keys
+    pub fn disable(&self, name: &str) -> Result<()> {
+        self.keys.remove(name)?;
+        self.forget(name);
+        Ok(())
+    }
+
+    /// Read the keys of `name` again, after they were moved or deleted
+    pub fn refresh(&self, name: &str) -> Result<()> {
+        self.keys.refresh(name)?;
+        self.forget(name);
+        Ok(())
+    }
+
+    /// Read every function's keys again, for those enabled, rotated or disabled on
+    /// other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.keys.reload()?;
+        self.signers.clear();
+        Ok(())
+    }
+
+    /// Drop the cached key pairs of `name`, after its keys changed
+    fn forget(&self, name: &str) {
+        self.signers.remove(name);
+    }
+
+    /// Key pairs to sign `name`'s responses with, none when signing is off
+    pub fn signers(&self, name: &str) -> Result<Arc<Vec<Ed25519KeyPair>>> {
+        if let Some(signers) = self.signers.get(name) {
+            if signers
+                .stale_at
+                .is_none_or(|stale_at| stale_at > chrono::Utc::now())
+            {
+                return Ok(Arc::clone(&signers.key_pairs));
+            }
+        }
+        let signers = match self.stored(name)? {
+            Some(stored) => Signers {
+                key_pairs: Arc::new(
+                    std::iter::once(&stored.current)
+                        .chain(stored.live_previous())
+                        .map(StoredKey::key_pair)
+                        .collect::<Result<_>>()?,
+                ),
+                stale_at: stored.live_previous().and(stored.previous_expires_at),
+            },
+            None => Signers {
+                key_pairs: Arc::default(),
+                stale_at: None,
+            },
+        };
+        let key_pairs = Arc::clone(&signers.key_pairs);
+        self.signers.insert(name.to_string(), signers);
+        Ok(key_pairs)
+    }
+}
+
+/// A new message id for a signed response
+pub fn new_message_id() -> String {
+    format!("msg_{}", hex::encode(rand::random::<[u8; 16]>()))
+}
+
+/// `webhook-signature` value: one `v1a,<signature>` for each key, space separated
+fn signature_header(signers: &[Ed25519KeyPair], id: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut message = format!("{id}.{timestamp}.").into_bytes();
+    message.extend_from_slice(body);
+    signers
+        .iter()
+        .map(|key_pair| format!("v1a,{}", BASE64.encode(key_pair.sign(&message).as_ref())))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The Standard Webhooks headers signing `body` as the message `id`, sent now
+pub fn signature_headers(
+    signers: &[Ed25519KeyPair],
+    id: &str,
+    body: &[u8],
+) -> [(&'static str, String); 3] {
+    let timestamp = chrono::Utc::now().timestamp();
+    [
+        ("webhook-id", id.to_string()),
+        ("webhook-timestamp", timestamp.to_string()),
+        (
+            "webhook-signature",
+            signature_header(signers, id, timestamp, body),
+        ),
+    ]
+}
+
+/// Buffer `response`'s body and add the signature headers, with `id` as the
+/// message id. Bodies over [`MAX_SIGNED_RESPONSE_SIZE`] are an error.
+pub async fn sign_response(
+    response: Response<HyperOutgoingBody>,
+    signers: &[Ed25519KeyPair],
+    id: &str,
+) -> Result<Response<HyperOutgoingBody>> {
+    let (mut parts, body) = response.into_parts();
+    let body = Limited::new(body, MAX_SIGNED_RESPONSE_SIZE)
+        .collect()
+        .await
+        .map_err(|e| anyhow!("Failed to read the response to sign: {e}"))?
+        .to_bytes();
+
+    for (name, value) in signature_headers(signers, id, &body) {
+        parts.headers.insert(name, HeaderValue::from_str(&value)?);
+    }
+
+    let body = Full::new(body)
+        .map_err(|_| ErrorCode::InternalError(None))
+        .boxed();
+    Ok(Response::from_parts(parts, HyperOutgoingBody::new(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    fn temp_signing() -> ResponseSigning {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        ResponseSigning::new(Arc::new(crate::metadata_store::SledStore::new(&db))).unwrap()
+    }
+
+    #[test]
+    fn test_signatures_verify_with_the_public_key() {
+        let signing = temp_signing();
+        let keys = signing.enable("hook", false).unwrap();
+        let public_key = keys.current.unwrap().public_key;
+        let raw_key = BASE64
+            .decode(public_key.strip_prefix(PUBLIC_KEY_PREFIX).unwrap())
+            .unwrap();
+
+        let header = signature_header(
+            &signing.signers("hook").unwrap(),
+            "msg_1",
+            1700000000,
+            b"{}",
+        );
+        let signature = BASE64.decode(header.strip_prefix("v1a,").unwrap()).unwrap();
+        let verifier = UnparsedPublicKey::new(&ED25519, raw_key);
+        assert!(verifier.verify(b"msg_1.1700000000.{}", &signature).is_ok());
+        assert!(verifier.verify(b"msg_1.1700000000.[]", &signature).is_err());
+    }
+
+    #[test]
+    fn test_rotation_keeps_the_old_key_signing() {
+        let signing = temp_signing();
+        assert!(signing.signers("hook").unwrap().is_empty());
+
+        let first = signing.enable("hook", false).unwrap().current.unwrap();
+        // Enabling again keeps the key
+        let again = signing.enable("hook", false).unwrap().current.unwrap();
+        assert_eq!(first.public_key, again.public_key);
+
+        let rotated = signing.enable("hook", true).unwrap();
+        assert_ne!(rotated.current.unwrap().public_key, first.public_key);
+        assert_eq!(rotated.previous.unwrap().public_key, first.public_key);
+        assert_eq!(signing.signers("hook").unwrap().len(), 2);
+
+        signing.disable("hook").unwrap();
+        assert!(signing.keys("hook").unwrap().current.is_none());
+        assert!(signing.signers("hook").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cached_signers_follow_key_changes() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store: Arc<dyn MetadataStore> = Arc::new(crate::metadata_store::SledStore::new(&db));
+        let signing = ResponseSigning::new(store.clone()).unwrap();
+        assert!(signing.signers("hook").unwrap().is_empty());
+        signing.enable("hook", false).unwrap();
+        let first = signing.signers("hook").unwrap();
+        assert_eq!(first.len(), 1);
+        assert!(Arc::ptr_eq(&first, &signing.signers("hook").unwrap()));
+
+        // A key moved under another name, as renames do, is found once refreshed
+        let value = store.get(SIGNING_KEYS_TREE, "hook").unwrap().unwrap();
+        store.remove(SIGNING_KEYS_TREE, "hook").unwrap();
+        store.insert(SIGNING_KEYS_TREE, "renamed", value).unwrap();
+        signing.refresh("hook").unwrap();
+        signing.refresh("renamed").unwrap();
+        assert!(signing.signers("hook").unwrap().is_empty());
+        assert_eq!(signing.signers("renamed").unwrap().len(), 1);
+
+        assert_ne!(new_message_id(), new_message_id());
+    }
+}
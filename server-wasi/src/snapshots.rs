@@ -0,0 +1,549 @@
+//! Debug snapshots of trapped invocations, core-dump style.
+//!
+//! Owners opt a function in with `cargo faasta snapshots --enable`. When one of its
+//! invocations then traps (a Rust panic aborts with a trap too), the guest's linear
+//! memory is written as a Wasm core dump, in the tool-conventions format core dump
+//! debuggers such as wasmgdb read, and kept with the trap, its backtrace and the
+//! request being handled. The CLI downloads the bundle and replays the request
+//! against the function locally with `cargo faasta run --replay`.
+//!
+//! Snapshots hold whatever the function had in memory, so reading them takes the
+//! developer role. They are bounded: core dumps over the server's size limit are
+//! left out, keeping only the request, a function is snapshotted at most once a
+//! minute and keeps its latest few, and snapshots expire after the retention
+//! period. Turning snapshots off deletes them.
+
+use anyhow::Result;
+use bincode::{Decode, Encode};
+use bytes::Bytes;
+use dashmap::DashMap;
+use faasta_interface::{
+    CapturedRequest, DebugSnapshot, DebugSnapshots, SnapshotInfo, MAX_SNAPSHOT_BODY, REDACTED,
+    UPLOAD_CHUNK_SIZE,
+};
+use hyper::body::{Body, Frame, SizeHint};
+use once_cell::sync::OnceCell;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+use tracing::{debug, error, info};
+use wasmtime::{Store, WasmBacktrace, WasmCoreDump};
+
+use crate::wasi_server::FaastaClientState;
+
+/// Sled tree listing each function's snapshots, keyed by function name. A function
+/// has an entry while snapshots are on.
+pub const SNAPSHOT_INDEX_TREE: &str = "debug_snapshots";
+/// Sled tree holding snapshot requests and core dump chunks, keyed by snapshot key
+const SNAPSHOT_DATA_TREE: &str = "debug_snapshot_data";
+
+/// Snapshots kept per function; older ones are deleted as new ones are taken
+const MAX_SNAPSHOTS_PER_FUNCTION: usize = 5;
+/// Shortest time between two snapshots of a function
+const MIN_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Global snapshot store, set at startup unless snapshots are turned off
+pub static SNAPSHOTS: OnceCell<Snapshots> = OnceCell::new();
+
+#[derive(Default, Encode, Decode)]
+struct Index {
+    /// Newest first
+    snapshots: Vec<SnapshotInfo>,
+}
+
+pub struct Snapshots {
+    index: sled::Tree,
+    data: sled::Tree,
+    /// Largest core dump kept, in bytes
+    max_core_dump: u64,
+    /// How long snapshots are kept
+    retention: Duration,
+    /// Headers whose values aren't kept with captured requests
+    redact_headers: Vec<String>,
+    /// When each function was last snapshotted
+    last_taken: DashMap<String, Instant>,
+    /// Serializes changes to the index
+    lock: Mutex<()>,
+}
+
+impl Snapshots {
+    pub fn new(
+        db: &sled::Db,
+        max_core_dump: u64,
+        retention: Duration,
+        redact_headers: Vec<String>,
+    ) -> Result<Self> {
+        Ok(Self {
+            index: db.open_tree(SNAPSHOT_INDEX_TREE)?,
+            data: db.open_tree(SNAPSHOT_DATA_TREE)?,
+            max_core_dump,
+            retention,
+            redact_headers: redact_headers
+                .into_iter()
+                .map(|header| header.to_ascii_lowercase())
+                .collect(),
+            last_taken: DashMap::new(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn load_index(&self, function: &str) -> Result<Option<Index>> {
+        self.index
+            .get(function.as_bytes())?
+            .map(|value| Ok(bincode::decode_from_slice(&value, bincode::config::standard())?.0))
+            .transpose()
+    }
+
+    fn save_index(&self, function: &str, index: &Index) -> Result<()> {
+        self.index.insert(
+            function.as_bytes(),
+            bincode::encode_to_vec(index, bincode::config::standard())?,
+        )?;
+        Ok(())
+    }
+
+    /// Whether an invocation of `function` that traps now would be snapshotted, so
+    /// its request should be captured
+    pub fn wants(&self, function: &str) -> bool {
+        let recently_taken = self
+            .last_taken
+            .get(function)
+            .is_some_and(|taken| taken.elapsed() < MIN_SNAPSHOT_INTERVAL);
+        !recently_taken
+            && self
+                .index
+                .contains_key(function.as_bytes())
+                .unwrap_or(false)
+    }
+
+    pub fn list(&self, function: &str) -> Result<DebugSnapshots> {
+        Ok(match self.load_index(function)? {
+            Some(index) => DebugSnapshots {
+                enabled: true,
+                snapshots: index.snapshots,
+            },
+            None => DebugSnapshots::default(),
+        })
+    }
+
+    /// Start or stop snapshotting `function`, deleting its snapshots when stopping
+    pub fn set_enabled(&self, function: &str, enabled: bool) -> Result<DebugSnapshots> {
+        {
+            let _lock = self.lock.lock().unwrap();
+            match (self.load_index(function)?, enabled) {
+                (None, true) => self.save_index(function, &Index::default())?,
+                (Some(index), false) => {
+                    for info in &index.snapshots {
+                        self.remove_data(&info.id)?;
+                    }
+                    self.index.remove(function.as_bytes())?;
+                }
+                _ => {}
+            }
+        }
+        self.list(function)
+    }
+
+    fn info(&self, function: &str, id: &str) -> Result<Option<SnapshotInfo>> {
+        Ok(self
+            .load_index(function)?
+            .and_then(|index| index.snapshots.into_iter().find(|info| info.id == id)))
+    }
+
+    /// `function`'s snapshot `id` with its request, `None` if it has no such snapshot
+    pub fn get(&self, function: &str, id: &str) -> Result<Option<DebugSnapshot>> {
+        let (Some(info), Some(key)) = (self.info(function, id)?, parse_snapshot_id(id)) else {
+            return Ok(None);
+        };
+        let Some(request) = self.data.get(request_key(&key))? else {
+            return Ok(None);
+        };
+        let request = bincode::decode_from_slice(&request, bincode::config::standard())?.0;
+        Ok(Some(DebugSnapshot { info, request }))
+    }
+
+    /// Chunk `index` of the core dump of `function`'s snapshot `id`, empty past its
+    /// end. `None` if the function has no such snapshot.
+    pub fn core_dump_chunk(&self, function: &str, id: &str, index: u32) -> Result<Option<Vec<u8>>> {
+        let (Some(_), Some(key)) = (self.info(function, id)?, parse_snapshot_id(id)) else {
+            return Ok(None);
+        };
+        Ok(Some(
+            self.data
+                .get(chunk_key(&key, index))?
+                .map(|chunk| chunk.to_vec())
+                .unwrap_or_default(),
+        ))
+    }
+
+    /// Snapshot `function` if `error` is a trap with a core dump and the function
+    /// is still opted in
+    pub fn record(
+        &self,
+        function: &str,
+        request_id: &str,
+        error: &anyhow::Error,
+        request: CapturedRequest,
+        store: &mut Store<FaastaClientState>,
+    ) -> Result<()> {
+        let Some(dump) = error.downcast_ref::<WasmCoreDump>() else {
+            return Ok(());
+        };
+        if !self.wants(function) {
+            return Ok(());
+        }
+        self.last_taken.insert(function.to_string(), Instant::now());
+
+        let memory: u64 = dump
+            .memories()
+            .iter()
+            .map(|memory| memory.data_size(&*store) as u64)
+            .sum();
+        let core_dump =
+            (memory <= self.max_core_dump).then(|| dump.serialize(&mut *store, function));
+
+        let now = chrono::Utc::now();
+        let key = snapshot_key(now.timestamp_millis());
+        let info = SnapshotInfo {
+            id: snapshot_id(&key),
+            created_at: now.to_rfc3339(),
+            request_id: request_id.to_string(),
+            error: trap_message(error),
+            core_dump_size: core_dump.as_ref().map(|dump| dump.len() as u64),
+        };
+        self.data.insert(
+            request_key(&key),
+            bincode::encode_to_vec(&request, bincode::config::standard())?,
+        )?;
+        for (index, chunk) in core_dump
+            .iter()
+            .flat_map(|dump| dump.chunks(UPLOAD_CHUNK_SIZE))
+            .enumerate()
+        {
+            self.data.insert(chunk_key(&key, index as u32), chunk)?;
+        }
+
+        let _lock = self.lock.lock().unwrap();
+        let Some(mut index) = self.load_index(function)? else {
+            // Snapshots were turned off while this one was taken
+            return self.remove_data(&info.id);
+        };
+        info!(
+            "Took debug snapshot {} of '{}' ({} bytes of memory{})",
+            info.id,
+            function,
+            memory,
+            if core_dump.is_some() {
+                ""
+            } else {
+                ", over the limit and left out"
+            }
+        );
+        index.snapshots.insert(0, info);
+        for dropped in index
+            .snapshots
+            .split_off(MAX_SNAPSHOTS_PER_FUNCTION.min(index.snapshots.len()))
+        {
+            self.remove_data(&dropped.id)?;
+        }
+        self.save_index(function, &index)
+    }
+
+    fn remove_data(&self, id: &str) -> Result<()> {
+        let Some(key) = parse_snapshot_id(id) else {
+            return Ok(());
+        };
+        for entry in self.data.scan_prefix(key) {
+            let (key, _) = entry?;
+            self.data.remove(key)?;
+        }
+        Ok(())
+    }
+
+    /// Delete snapshots older than the retention period, including those of
+    /// functions deleted since they were taken
+    fn purge_expired(&self) -> Result<usize> {
+        let cutoff = chrono::Utc::now() - self.retention;
+        let cutoff_key = cutoff.timestamp_millis().max(0).to_be_bytes();
+        let mut purged = 0;
+        for entry in self.data.range(..cutoff_key.as_slice()) {
+            let (key, _) = entry?;
+            self.data.remove(key)?;
+            purged += 1;
+        }
+
+        let _lock = self.lock.lock().unwrap();
+        for entry in self.index.iter() {
+            let (function, value) = entry?;
+            let mut index: Index =
+                bincode::decode_from_slice(&value, bincode::config::standard())?.0;
+            let before = index.snapshots.len();
+            index.snapshots.retain(|info| {
+                chrono::DateTime::parse_from_rfc3339(&info.created_at)
+                    .is_ok_and(|created_at| created_at > cutoff)
+            });
+            if index.snapshots.len() != before {
+                self.index.insert(
+                    function,
+                    bincode::encode_to_vec(&index, bincode::config::standard())?,
+                )?;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Start capturing the request in `parts` for a snapshot
+    pub fn capture(&self, parts: &http::request::Parts) -> RequestCapture {
+        let headers = parts
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if self
+                    .redact_headers
+                    .iter()
+                    .any(|header| header == name.as_str())
+                {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+        RequestCapture {
+            method: parts.method.to_string(),
+            uri: parts
+                .uri
+                .path_and_query()
+                .map_or_else(|| "/".to_string(), |path| path.to_string()),
+            headers,
+            body: Arc::default(),
+        }
+    }
+}
+
+/// The start of a request body, copied as the guest reads it
+#[derive(Default)]
+struct BodyCopy {
+    bytes: Vec<u8>,
+    truncated: bool,
+}
+
+/// A request being captured for a snapshot
+pub struct RequestCapture {
+    method: String,
+    uri: String,
+    headers: Vec<(String, String)>,
+    body: Arc<Mutex<BodyCopy>>,
+}
+
+impl RequestCapture {
+    /// `body`, copying what the guest reads of it into the capture
+    pub fn tee<B>(&self, body: B) -> CapturingBody<B> {
+        CapturingBody {
+            inner: body,
+            copy: Some(self.body.clone()),
+        }
+    }
+
+    pub fn finish(self) -> CapturedRequest {
+        let body = std::mem::take(&mut *self.body.lock().unwrap());
+        CapturedRequest {
+            method: self.method,
+            uri: self.uri,
+            headers: self.headers,
+            body: body.bytes,
+            body_truncated: body.truncated,
+        }
+    }
+}
+
+/// A request body that copies its start into a [`RequestCapture`], if it has one
+pub struct CapturingBody<B> {
+    inner: B,
+    copy: Option<Arc<Mutex<BodyCopy>>>,
+}
+
+impl<B> CapturingBody<B> {
+    /// `body` as it is, for requests that aren't captured
+    pub fn passthrough(body: B) -> Self {
+        Self {
+            inner: body,
+            copy: None,
+        }
+    }
+}
+
+impl<B> Body for CapturingBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+        if let (Poll::Ready(Some(Ok(frame))), Some(copy)) = (&polled, &self.copy) {
+            if let Some(data) = frame.data_ref() {
+                let mut copy = copy.lock().unwrap();
+                let room = MAX_SNAPSHOT_BODY - copy.bytes.len();
+                copy.truncated |= data.len() > room;
+                copy.bytes.extend_from_slice(&data[..data.len().min(room)]);
+            }
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// The trap, then the guest's backtrace; the core dump's own summary is left out
+fn trap_message(error: &anyhow::Error) -> String {
+    let trap = format!("wasm trap: {}", error.root_cause());
+    match error.downcast_ref::<WasmBacktrace>() {
+        Some(backtrace) => format!("{trap}\n{backtrace}"),
+        None => trap,
+    }
+}
+
+/// Taken time (ms) and a random number, so keys sort by age and don't collide
+fn snapshot_key(created_ms: i64) -> Vec<u8> {
+    let mut key = created_ms.max(0).to_be_bytes().to_vec();
+    key.extend_from_slice(&rand::random::<u64>().to_be_bytes());
+    key
+}
+
+fn snapshot_id(key: &[u8]) -> String {
+    format!("snap_{}", hex::encode(key))
+}
+
+fn parse_snapshot_id(id: &str) -> Option<Vec<u8>> {
+    let key = hex::decode(id.strip_prefix("snap_")?).ok()?;
+    (key.len() == 16).then_some(key)
+}
+
+fn request_key(key: &[u8]) -> Vec<u8> {
+    let mut request_key = key.to_vec();
+    request_key.push(0);
+    request_key
+}
+
+fn chunk_key(key: &[u8], index: u32) -> Vec<u8> {
+    let mut chunk_key = key.to_vec();
+    chunk_key.push(1);
+    chunk_key.extend_from_slice(&index.to_be_bytes());
+    chunk_key
+}
+
+/// Spawn a task that purges expired snapshots every `interval_secs` seconds
+pub fn spawn_periodic_purge(interval_secs: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            let Some(snapshots) = SNAPSHOTS.get() else {
+                continue;
+            };
+            match snapshots.purge_expired() {
+                Ok(0) => {}
+                Ok(purged) => debug!("Purged {} expired debug snapshot records", purged),
+                Err(e) => error!("Failed to purge expired debug snapshots: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    fn temp_snapshots() -> Snapshots {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        Snapshots::new(
+            &db,
+            1024 * 1024,
+            Duration::from_secs(3600),
+            vec!["Authorization".to_string()],
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_captured_redacted_and_bounded() {
+        let snapshots = temp_snapshots();
+        let (parts, ()) = http::Request::post("https://hook.faasta.xyz/charge?id=7")
+            .header("authorization", "Bearer secret")
+            .header("content-type", "application/json")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let capture = snapshots.capture(&parts);
+
+        let body = http_body_util::Full::new(Bytes::from(vec![b'x'; MAX_SNAPSHOT_BODY + 10]));
+        let read = capture.tee(body).collect().await.unwrap().to_bytes();
+        // The guest still gets the whole body
+        assert_eq!(read.len(), MAX_SNAPSHOT_BODY + 10);
+
+        let request = capture.finish();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.uri, "/charge?id=7");
+        assert_eq!(
+            request.headers,
+            vec![
+                ("authorization".to_string(), REDACTED.to_string()),
+                ("content-type".to_string(), "application/json".to_string()),
+            ]
+        );
+        assert_eq!(request.body.len(), MAX_SNAPSHOT_BODY);
+        assert!(request.body_truncated);
+    }
+
+    #[test]
+    fn test_turning_snapshots_off_deletes_them() {
+        let snapshots = temp_snapshots();
+        assert!(!snapshots.wants("hello"));
+        assert!(snapshots.set_enabled("hello", true).unwrap().enabled);
+        assert!(snapshots.wants("hello"));
+
+        let key = snapshot_key(chrono::Utc::now().timestamp_millis());
+        let id = snapshot_id(&key);
+        snapshots
+            .data
+            .insert(chunk_key(&key, 0), b"core".as_slice())
+            .unwrap();
+        let mut index = snapshots.load_index("hello").unwrap().unwrap();
+        index.snapshots.push(SnapshotInfo {
+            id: id.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            request_id: "req".to_string(),
+            error: "wasm trap: unreachable".to_string(),
+            core_dump_size: Some(4),
+        });
+        snapshots.save_index("hello", &index).unwrap();
+        assert_eq!(
+            snapshots.core_dump_chunk("hello", &id, 0).unwrap().unwrap(),
+            b"core"
+        );
+        // Another function can't read it
+        assert!(snapshots
+            .core_dump_chunk("other", &id, 0)
+            .unwrap()
+            .is_none());
+
+        let off = snapshots.set_enabled("hello", false).unwrap();
+        assert!(!off.enabled && off.snapshots.is_empty());
+        assert!(snapshots.data.is_empty());
+    }
+}
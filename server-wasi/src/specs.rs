@@ -0,0 +1,318 @@
+//! Function definitions applied with the `apply` RPC.
+//!
+//! The spec a function was last reconciled to is kept with the digest its
+//! artifact had then, so a later apply can tell whether the function still runs
+//! it. The environment, routes and limits are read on every request.
+
+use anyhow::Result;
+use bincode::{Decode, Encode};
+use faasta_interface::{FunctionLimits, FunctionSpec, WarmSchedule};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::metadata_store::{Cached, MetadataStore};
+
+/// Collection of the metadata store holding the applied spec of each function
+pub const FUNCTION_SPECS_TREE: &str = "function_specs";
+
+/// The spec a function was reconciled to
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct AppliedSpec {
+    pub spec: FunctionSpec,
+    /// Digest of the WebAssembly published for `spec.artifact`
+    pub digest: String,
+}
+
+/// `AppliedSpec` as stored before keep-warm schedules
+#[derive(Decode)]
+struct UnscheduledAppliedSpec {
+    artifact: String,
+    team: Option<String>,
+    env: BTreeMap<String, String>,
+    routes: Vec<String>,
+    limits: FunctionLimits,
+    digest: String,
+}
+
+/// `AppliedSpec` as stored before streams could be limited
+#[derive(Decode)]
+struct LegacyAppliedSpec {
+    artifact: String,
+    team: Option<String>,
+    env: BTreeMap<String, String>,
+    routes: Vec<String>,
+    timeout_ms: Option<u64>,
+    memory_mb: Option<u32>,
+    digest: String,
+}
+
+impl AppliedSpec {
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let config = bincode::config::standard();
+        if let Ok((applied, _)) = bincode::decode_from_slice::<AppliedSpec, _>(bytes, config) {
+            return Some(applied);
+        }
+        if let Ok((unscheduled, _)) =
+            bincode::decode_from_slice::<UnscheduledAppliedSpec, _>(bytes, config)
+        {
+            return Some(Self {
+                spec: FunctionSpec {
+                    artifact: unscheduled.artifact,
+                    team: unscheduled.team,
+                    env: unscheduled.env,
+                    routes: unscheduled.routes,
+                    limits: unscheduled.limits,
+                    keep_warm: Vec::new(),
+                },
+                digest: unscheduled.digest,
+            });
+        }
+        bincode::decode_from_slice::<LegacyAppliedSpec, _>(bytes, config)
+            .ok()
+            .map(|(legacy, _)| Self {
+                spec: FunctionSpec {
+                    artifact: legacy.artifact,
+                    team: legacy.team,
+                    env: legacy.env,
+                    routes: legacy.routes,
+                    limits: FunctionLimits {
+                        timeout_ms: legacy.timeout_ms,
+                        memory_mb: legacy.memory_mb,
+                        max_stream_secs: None,
+                    },
+                    keep_warm: Vec::new(),
+                },
+                digest: legacy.digest,
+            })
+    }
+}
+
+pub struct Specs {
+    applied: Cached<AppliedSpec>,
+}
+
+impl Specs {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Result<Self> {
+        Ok(Self {
+            applied: Cached::new(store, FUNCTION_SPECS_TREE, AppliedSpec::decode)?,
+        })
+    }
+
+    /// The spec `name` was last reconciled to, if it was ever applied
+    pub fn get(&self, name: &str) -> Option<AppliedSpec> {
+        self.applied.get(name)
+    }
+
+    /// Every function's applied spec
+    pub fn all(&self) -> Vec<(String, AppliedSpec)> {
+        self.applied.all()
+    }
+
+    pub fn set(&self, name: &str, applied: &AppliedSpec) -> Result<()> {
+        let encoded = bincode::encode_to_vec(applied, bincode::config::standard())?;
+        self.applied.insert(name, applied.clone(), encoded)
+    }
+
+    /// Read `name`'s spec again, after its record was moved or copied
+    pub fn refresh(&self, name: &str) -> Result<()> {
+        self.applied.refresh(name)
+    }
+
+    /// Read every spec again, for changes made by other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.applied.reload()
+    }
+}
+
+/// The settings `spec` changes compared to `current`, one line each. The artifact
+/// is compared separately, against what the function runs.
+pub fn setting_changes(current: Option<&FunctionSpec>, spec: &FunctionSpec) -> Vec<String> {
+    let default = FunctionSpec::default();
+    let current = current.unwrap_or(&default);
+    let mut changes = Vec::new();
+
+    for (key, value) in &spec.env {
+        match current.env.get(key) {
+            None => changes.push(format!("env: add {key}")),
+            Some(old) if old != value => changes.push(format!("env: change {key}")),
+            Some(_) => {}
+        }
+    }
+    for key in current.env.keys() {
+        if !spec.env.contains_key(key) {
+            changes.push(format!("env: remove {key}"));
+        }
+    }
+    if current.routes != spec.routes {
+        changes.push(if spec.routes.is_empty() {
+            "routes: every path".to_string()
+        } else {
+            format!("routes: {}", spec.routes.join(", "))
+        });
+    }
+    if current.limits.timeout_ms != spec.limits.timeout_ms {
+        changes.push(format!(
+            "limits: timeoutMs {}",
+            describe(spec.limits.timeout_ms)
+        ));
+    }
+    if current.limits.memory_mb != spec.limits.memory_mb {
+        changes.push(format!(
+            "limits: memoryMb {}",
+            describe(spec.limits.memory_mb)
+        ));
+    }
+    if current.limits.max_stream_secs != spec.limits.max_stream_secs {
+        changes.push(format!(
+            "limits: maxStreamSecs {}",
+            describe(spec.limits.max_stream_secs)
+        ));
+    }
+    if current.keep_warm != spec.keep_warm {
+        changes.push(if spec.keep_warm.is_empty() {
+            "keepWarm: no schedule".to_string()
+        } else {
+            let windows: Vec<String> = spec.keep_warm.iter().map(describe_window).collect();
+            format!("keepWarm: {}", windows.join(", "))
+        });
+    }
+    changes
+}
+
+/// A keep-warm window as shown in a diff, such as `5 mon-fri 09:00-18:00 +05:00`
+fn describe_window(schedule: &WarmSchedule) -> String {
+    let mut window = format!(
+        "{} {} {}-{}",
+        schedule.instances,
+        schedule.days.as_deref().unwrap_or("daily"),
+        schedule.from,
+        schedule.to
+    );
+    if let Some(offset) = &schedule.utc_offset {
+        window.push(' ');
+        window.push_str(offset);
+    }
+    window
+}
+
+fn describe<T: ToString>(limit: Option<T>) -> String {
+    limit.map_or_else(|| "server default".to_string(), |limit| limit.to_string())
+}
+
+/// Whether a function with `routes` serves `path`. A route matches itself and
+/// the paths below it.
+pub fn serves(routes: &[String], path: &str) -> bool {
+    routes.is_empty()
+        || routes.iter().any(|route| {
+            let route = route.trim_end_matches('/');
+            route.is_empty()
+                || path == route
+                || path
+                    .strip_prefix(route)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> FunctionSpec {
+        FunctionSpec {
+            artifact: "ghcr.io/user/fn:v1".to_string(),
+            team: None,
+            env: [("MODE".to_string(), "prod".to_string())].into(),
+            routes: vec!["/api".to_string()],
+            limits: Default::default(),
+            keep_warm: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_routes_match_whole_segments() {
+        let routes = vec!["/api".to_string()];
+        assert!(serves(&routes, "/api"));
+        assert!(serves(&routes, "/api/users"));
+        assert!(!serves(&routes, "/apiary"));
+        assert!(!serves(&routes, "/"));
+        assert!(serves(&[], "/anything"));
+        assert!(serves(&["/".to_string()], "/anything"));
+    }
+
+    #[test]
+    fn test_setting_changes() {
+        assert_eq!(
+            setting_changes(None, &spec()),
+            vec!["env: add MODE", "routes: /api"]
+        );
+        let mut changed = spec();
+        changed.env.clear();
+        changed.limits.timeout_ms = Some(5000);
+        assert_eq!(
+            setting_changes(Some(&spec()), &changed),
+            vec!["env: remove MODE", "limits: timeoutMs 5000"]
+        );
+        assert!(setting_changes(Some(&spec()), &spec()).is_empty());
+    }
+
+    #[derive(Encode)]
+    struct OldAppliedSpec {
+        artifact: String,
+        team: Option<String>,
+        env: BTreeMap<String, String>,
+        routes: Vec<String>,
+        timeout_ms: Option<u64>,
+        memory_mb: Option<u32>,
+        digest: String,
+    }
+
+    #[derive(Encode)]
+    struct UnscheduledSpec {
+        artifact: String,
+        team: Option<String>,
+        env: BTreeMap<String, String>,
+        routes: Vec<String>,
+        limits: FunctionLimits,
+        digest: String,
+    }
+
+    #[test]
+    fn test_decode_spec_without_keep_warm_schedule() {
+        let old = UnscheduledSpec {
+            artifact: "ghcr.io/user/fn:v1".to_string(),
+            team: Some("acme".to_string()),
+            env: BTreeMap::new(),
+            routes: vec!["/api".to_string()],
+            limits: FunctionLimits {
+                max_stream_secs: Some(60),
+                ..Default::default()
+            },
+            digest: format!("sha256:{}", "0".repeat(64)),
+        };
+        let bytes = bincode::encode_to_vec(&old, bincode::config::standard()).unwrap();
+        let applied = AppliedSpec::decode(&bytes).unwrap();
+        assert_eq!(applied.spec.team.as_deref(), Some("acme"));
+        assert_eq!(applied.spec.limits.max_stream_secs, Some(60));
+        assert!(applied.spec.keep_warm.is_empty());
+        assert_eq!(applied.digest, old.digest);
+    }
+
+    #[test]
+    fn test_decode_spec_without_stream_limit() {
+        let old = OldAppliedSpec {
+            artifact: "ghcr.io/user/fn:v1".to_string(),
+            team: None,
+            env: BTreeMap::new(),
+            routes: Vec::new(),
+            timeout_ms: Some(5000),
+            memory_mb: Some(128),
+            digest: format!("sha256:{}", "0".repeat(64)),
+        };
+        let bytes = bincode::encode_to_vec(&old, bincode::config::standard()).unwrap();
+        let applied = AppliedSpec::decode(&bytes).unwrap();
+        assert_eq!(applied.spec.limits.timeout_ms, Some(5000));
+        assert_eq!(applied.spec.limits.memory_mb, Some(128));
+        assert_eq!(applied.spec.limits.max_stream_secs, None);
+        assert_eq!(applied.digest, old.digest);
+    }
+}
@@ -0,0 +1,497 @@
+//! Static assets deployed alongside functions.
+//!
+//! A project's `static/` directory is uploaded with its function and served by the
+//! server itself for `GET` and `HEAD` requests under `/static/`, so simple sites
+//! don't spend an invocation on every stylesheet and image. Responses carry the
+//! file's digest as a strong `ETag` and a `Cache-Control` max-age, and a matching
+//! `If-None-Match` is answered 304 without reading the file.
+//!
+//! Each function's manifest, mapping paths to digests and content types, is a
+//! per-function record. File contents are kept once per digest, shared by the
+//! versions and clones that have them, with a count of the manifests referring to
+//! them. Manifests, contents and counts change in one transaction, and contents
+//! are dropped as soon as no manifest refers to them any more. A function's assets
+//! may take up to `--static-assets-max-mb`.
+
+use anyhow::{anyhow, Result};
+use bincode::{Decode, Encode};
+use bytes::Bytes;
+use faasta_interface::{StaticAsset, STATIC_PATH_PREFIX};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::{Method, Request, Response};
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+use sled::transaction::{
+    ConflictableTransactionError, TransactionError, TransactionalTree, UnabortableTransactionError,
+};
+use sled::Transactional;
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::{error, info};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+
+/// Sled tree holding each function's manifest, keyed by function name
+pub const STATIC_MANIFESTS_TREE: &str = "function_static_assets";
+/// Sled tree holding file contents, keyed by their SHA-256 digest
+const STATIC_BLOBS_TREE: &str = "static_asset_blobs";
+/// Sled tree holding how many manifests refer to each content, keyed by digest
+const STATIC_REFS_TREE: &str = "static_asset_refs";
+
+/// Global static assets, set at startup
+pub static STATIC_ASSETS: OnceCell<StaticAssets> = OnceCell::new();
+
+/// A file of a function's static assets
+#[derive(Clone, Debug, Encode, Decode)]
+struct ManifestEntry {
+    /// Hex SHA-256 digest of the content
+    digest: String,
+    content_type: String,
+    size: u64,
+}
+
+/// A function's static assets, keyed by path
+type Manifest = BTreeMap<String, ManifestEntry>;
+
+pub struct StaticAssets {
+    manifests: sled::Tree,
+    blobs: sled::Tree,
+    refs: sled::Tree,
+    /// `max-age` of served assets, in seconds
+    max_age: u64,
+    /// Most bytes a function's assets may take up
+    max_bytes: u64,
+}
+
+impl StaticAssets {
+    pub fn new(db: &sled::Db, max_age: u64, max_bytes: u64) -> Result<Self> {
+        let assets = Self {
+            manifests: db.open_tree(STATIC_MANIFESTS_TREE)?,
+            blobs: db.open_tree(STATIC_BLOBS_TREE)?,
+            refs: db.open_tree(STATIC_REFS_TREE)?,
+            max_age,
+            max_bytes,
+        };
+        if assets.refs.is_empty() && !assets.manifests.is_empty() {
+            assets.count_refs()?;
+        }
+        Ok(assets)
+    }
+
+    /// Most bytes a function's assets may take up
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    fn manifest(&self, name: &str) -> Option<Manifest> {
+        match self.manifests.get(name.as_bytes()) {
+            Ok(value) => decode_manifest(&value?),
+            Err(e) => {
+                error!("Failed to read the static assets of '{}': {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Replace the static assets of `name` with `assets`
+    pub fn publish(&self, name: &str, assets: Vec<StaticAsset>) -> Result<()> {
+        let mut manifest = Manifest::new();
+        let mut contents = BTreeMap::new();
+        for asset in assets {
+            let digest = hex::encode(Sha256::digest(&asset.content));
+            let entry = ManifestEntry {
+                digest: digest.clone(),
+                content_type: content_type(&asset.path).to_string(),
+                size: asset.content.len() as u64,
+            };
+            contents.insert(digest, asset.content);
+            manifest.insert(asset.path, entry);
+        }
+        self.write(&[(name, Some(&manifest))], &contents)
+    }
+
+    /// Remove the static assets of `name`, returning whether it had any
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let removed = self.manifests.contains_key(name.as_bytes())?;
+        if removed {
+            self.write(&[(name, None)], &BTreeMap::new())?;
+        }
+        Ok(removed)
+    }
+
+    /// Give `to` the static assets of `from`, replacing any it had
+    pub fn copy(&self, from: &str, to: &str) -> Result<()> {
+        match self.manifest(from) {
+            Some(manifest) => self.write(&[(to, Some(&manifest))], &BTreeMap::new()),
+            None => Ok(()),
+        }
+    }
+
+    /// Move the static assets of `from` to `to`
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        match self.manifest(from) {
+            Some(manifest) => self.write(&[(to, Some(&manifest)), (from, None)], &BTreeMap::new()),
+            None => Ok(()),
+        }
+    }
+
+    /// Functions with static assets
+    pub fn functions(&self) -> Result<Vec<String>> {
+        self.manifests
+            .iter()
+            .keys()
+            .map(|key| Ok(String::from_utf8_lossy(&key?).into_owned()))
+            .collect()
+    }
+
+    /// Set each function's manifest to the one given, `None` removing it, storing
+    /// `contents` by digest and dropping the contents no manifest refers to any more
+    fn write(
+        &self,
+        manifests: &[(&str, Option<&Manifest>)],
+        contents: &BTreeMap<String, Vec<u8>>,
+    ) -> Result<()> {
+        (&self.manifests, &self.blobs, &self.refs)
+            .transaction(|(manifest_tree, blobs, refs)| {
+                for (digest, content) in contents {
+                    blobs.insert(digest.as_bytes(), content.as_slice())?;
+                }
+                for &(name, manifest) in manifests {
+                    let previous = manifest_tree
+                        .get(name.as_bytes())?
+                        .and_then(|value| decode_manifest(&value))
+                        .unwrap_or_default();
+                    match manifest {
+                        Some(manifest) => {
+                            let encoded =
+                                bincode::encode_to_vec(manifest, bincode::config::standard())
+                                    .map_err(ConflictableTransactionError::Abort)?;
+                            manifest_tree.insert(name.as_bytes(), encoded)?;
+                            for digest in digests(manifest) {
+                                add_ref(refs, digest, 1)?;
+                            }
+                        }
+                        None => {
+                            manifest_tree.remove(name.as_bytes())?;
+                        }
+                    }
+                    for digest in digests(&previous) {
+                        if add_ref(refs, digest, -1)? == 0 {
+                            blobs.remove(digest.as_bytes())?;
+                        }
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|e: TransactionError<bincode::error::EncodeError>| {
+                anyhow!("Failed to write static assets: {e}")
+            })
+    }
+
+    /// Count the manifests referring to each content, and drop the contents none
+    /// does, for assets stored before the counts were kept
+    fn count_refs(&self) -> Result<()> {
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        for value in self.manifests.iter().values() {
+            if let Some(manifest) = decode_manifest(&value?) {
+                for digest in digests(&manifest) {
+                    *counts.entry(digest.to_string()).or_default() += 1;
+                }
+            }
+        }
+        for (digest, count) in &counts {
+            self.refs.insert(digest.as_bytes(), &count.to_be_bytes())?;
+        }
+        let mut pruned = 0;
+        for key in self.blobs.iter().keys() {
+            let key = key?;
+            if !counts.contains_key(String::from_utf8_lossy(&key).as_ref()) {
+                self.blobs.remove(key)?;
+                pruned += 1;
+            }
+        }
+        info!(
+            "Counted the references to {} static assets, pruned {} unreferenced",
+            counts.len(),
+            pruned
+        );
+        Ok(())
+    }
+
+    /// Files of `name`'s static assets and their total size
+    pub fn usage(&self, name: &str) -> (usize, u64) {
+        self.manifest(name).map_or((0, 0), |manifest| {
+            (
+                manifest.len(),
+                manifest.values().map(|entry| entry.size).sum(),
+            )
+        })
+    }
+
+    /// The response to `req` from `name`'s static assets, if it's a `GET` or
+    /// `HEAD` of a path under `/static/` and the function has static assets
+    pub fn serve<B>(
+        &self,
+        name: &str,
+        req: &Request<B>,
+    ) -> Result<Option<Response<HyperOutgoingBody>>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return Ok(None);
+        }
+        let Some(path) = req.uri().path().strip_prefix(STATIC_PATH_PREFIX) else {
+            return Ok(None);
+        };
+        let Some(manifest) = self.manifest(name) else {
+            return Ok(None);
+        };
+
+        let path = match percent_decode(path) {
+            Some(path) if path.is_empty() || path.ends_with('/') => format!("{path}index.html"),
+            Some(path) => path,
+            None => return not_found().map(Some),
+        };
+        let Some(entry) = manifest.get(&path) else {
+            return not_found().map(Some);
+        };
+
+        let etag = format!("\"{}\"", entry.digest);
+        let cache_control = format!("public, max-age={}", self.max_age);
+        let builder = Response::builder()
+            .header(hyper::header::ETAG, &etag)
+            .header(hyper::header::CACHE_CONTROL, cache_control);
+        if let Some(if_none_match) = req.headers().get(hyper::header::IF_NONE_MATCH) {
+            if etag_matches(if_none_match.to_str().unwrap_or(""), &etag) {
+                return Ok(Some(builder.status(304).body(empty_body())?));
+            }
+        }
+
+        let builder = builder
+            .status(200)
+            .header(hyper::header::CONTENT_TYPE, &entry.content_type)
+            .header(hyper::header::CONTENT_LENGTH, entry.size);
+        if req.method() == Method::HEAD {
+            return Ok(Some(builder.body(empty_body())?));
+        }
+        let content = self
+            .blobs
+            .get(entry.digest.as_bytes())?
+            .ok_or_else(|| anyhow!("Static asset '{path}' of '{name}' is missing"))?;
+        let body = Full::new(Bytes::from(content.to_vec()))
+            .map_err(|_| ErrorCode::InternalError(None))
+            .boxed();
+        Ok(Some(builder.body(HyperOutgoingBody::new(body))?))
+    }
+}
+
+fn decode_manifest(value: &[u8]) -> Option<Manifest> {
+    bincode::decode_from_slice(value, bincode::config::standard())
+        .ok()
+        .map(|(manifest, _)| manifest)
+}
+
+/// Contents `manifest` refers to, each once
+fn digests(manifest: &Manifest) -> BTreeSet<&str> {
+    manifest
+        .values()
+        .map(|entry| entry.digest.as_str())
+        .collect()
+}
+
+/// Add `delta` to the count of manifests referring to the content `digest`,
+/// returning the new count. A count reaching zero is removed.
+fn add_ref(
+    refs: &TransactionalTree,
+    digest: &str,
+    delta: i64,
+) -> Result<u64, UnabortableTransactionError> {
+    let count = refs
+        .get(digest.as_bytes())?
+        .and_then(|value| <[u8; 8]>::try_from(value.as_ref()).ok())
+        .map_or(0, u64::from_be_bytes);
+    let count = count.saturating_add_signed(delta);
+    if count == 0 {
+        refs.remove(digest.as_bytes())?;
+    } else {
+        refs.insert(digest.as_bytes(), &count.to_be_bytes())?;
+    }
+    Ok(count)
+}
+
+fn empty_body() -> HyperOutgoingBody {
+    HyperOutgoingBody::new(
+        Empty::new()
+            .map_err(|_| ErrorCode::InternalError(None))
+            .boxed(),
+    )
+}
+
+fn not_found() -> Result<Response<HyperOutgoingBody>> {
+    crate::wasi_server::text_response(404, "Not found")
+}
+
+/// Whether an `If-None-Match` header value matches `etag`
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Decode `%XX` escapes in a URL path, refusing ones that aren't valid UTF-8
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Content type of a file, from its extension
+fn content_type(path: &str) -> &'static str {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "webmanifest" => "application/manifest+json",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(path: &str, if_none_match: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri(path);
+        if let Some(tag) = if_none_match {
+            builder = builder.header(hyper::header::IF_NONE_MATCH, tag);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_assets_are_served_with_etags_and_pruned() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let assets = StaticAssets::new(&db, 300, 1024).unwrap();
+        let asset = |path: &str, content: &str| StaticAsset {
+            path: path.to_string(),
+            content: content.as_bytes().to_vec(),
+        };
+        assets
+            .publish(
+                "site",
+                vec![
+                    asset("index.html", "<h1>hi</h1>"),
+                    asset("css/a b.css", "p{}"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(assets.usage("site"), (2, 14));
+
+        // Other paths and functions without assets go to the function
+        assert!(assets.serve("site", &get("/api", None)).unwrap().is_none());
+        assert!(assets
+            .serve("api", &get("/static/x", None))
+            .unwrap()
+            .is_none());
+
+        let resp = assets
+            .serve("site", &get("/static/css/a%20b.css", None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "text/css; charset=utf-8");
+        assert_eq!(resp.headers()["cache-control"], "public, max-age=300");
+        let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+
+        let resp = assets
+            .serve("site", &get("/static/css/a%20b.css", Some(&etag)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.status(), 304);
+        let resp = assets
+            .serve("site", &get("/static/", None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.headers()["content-type"], "text/html; charset=utf-8");
+        let resp = assets
+            .serve("site", &get("/static/missing.png", None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.status(), 404);
+
+        // A new version drops the contents only the old one had
+        assets
+            .publish("site", vec![asset("index.html", "<h1>hi</h1>")])
+            .unwrap();
+        assert_eq!(assets.blobs.len(), 1);
+        // Contents shared with a clone stay until neither refers to them
+        assets.copy("site", "clone").unwrap();
+        assets.rename("clone", "copy").unwrap();
+        assert_eq!(assets.functions().unwrap(), ["copy", "site"]);
+        assert!(assets.remove("site").unwrap());
+        assert_eq!(assets.blobs.len(), 1);
+        assert!(assets.remove("copy").unwrap());
+        assert!(!assets.remove("copy").unwrap());
+        assert_eq!(assets.blobs.len(), 0);
+        assert!(assets.refs.is_empty());
+    }
+
+    #[test]
+    fn test_references_are_counted_for_assets_stored_without_counts() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let assets = StaticAssets::new(&db, 300, 1024).unwrap();
+        let asset = StaticAsset {
+            path: "index.html".to_string(),
+            content: b"<h1>hi</h1>".to_vec(),
+        };
+        assets.publish("site", vec![asset]).unwrap();
+        assets.blobs.insert("orphan", "gone").unwrap();
+        assets.refs.clear().unwrap();
+        drop(assets);
+
+        let assets = StaticAssets::new(&db, 300, 1024).unwrap();
+        assert_eq!(assets.refs.len(), 1);
+        assert_eq!(assets.blobs.len(), 1);
+        assert!(assets.remove("site").unwrap());
+        assert!(assets.blobs.is_empty());
+    }
+
+    #[test]
+    fn test_if_none_match_lists_and_weak_tags_match() {
+        assert!(etag_matches("\"a\", W/\"b\"", "\"b\""));
+        assert!(etag_matches("*", "\"b\""));
+        assert!(!etag_matches("\"a\"", "\"b\""));
+    }
+}
@@ -0,0 +1,550 @@
+//! Request and response bodies streamed through functions.
+//!
+//! Neither body of an invocation is buffered: the guest reads its request body as
+//! the client sends it, and its response body is sent as the guest writes it, so a
+//! function can take uploads larger than the server's memory or proxy a large
+//! download. `--max-request-body-mb` and `--max-response-body-mb` bound how much
+//! may pass: a request declaring a larger body is answered 413 before the function
+//! runs, and a body outgrowing its limit while it streams fails, with
+//! `HttpRequestBodySize` for the guest reading it, or by aborting the response.
+//! `--body-read-timeout` bounds how long the guest waits for the client's next
+//! bytes. A request body cut off for either reason is noted, and the client gets a
+//! JSON 413 or 408 instead of whatever the guest answered, as long as the guest
+//! hadn't answered yet. Signed responses are the exception, as their body is
+//! buffered to be signed.
+//!
+//! Responses may stream for long, e.g. Server-Sent Events or chunked long polls.
+//! Each write the guest flushes is sent on at once. A response that sends nothing
+//! for `--stream-idle-timeout` is aborted, except for an event stream
+//! (`text/event-stream`), which instead gets a `: keep-alive` comment between
+//! events every `--sse-keepalive` seconds it is quiet, so proxies and clients don't
+//! give up on it. Any response is ended after `--max-stream-secs`, or its
+//! function's own `maxStreamSecs` if shorter; event stream clients reconnect.
+//! Event streams are never signed, as that would buffer them.
+
+use anyhow::Result;
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::header::{HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Request, Response};
+use once_cell::sync::OnceCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{sleep, Instant, Sleep};
+use wasmtime::component::Resource;
+use wasmtime_wasi::IoView;
+use wasmtime_wasi_http::bindings::http::types::{ErrorCode, Scheme};
+use wasmtime_wasi_http::body::{HostIncomingBody, HyperOutgoingBody};
+use wasmtime_wasi_http::hyper_response_error;
+use wasmtime_wasi_http::types::HostIncomingRequest;
+
+use crate::server_config::Reloadable;
+use crate::wasi_server::{request_error_response, FaastaClientState};
+
+/// Global body limits, set at startup
+pub static BODY_LIMITS: OnceCell<Reloadable<BodyLimits>> = OnceCell::new();
+
+/// Comment sent on a quiet event stream
+const KEEPALIVE: &[u8] = b": keep-alive\n\n";
+
+/// How long the guest waits for the next bytes of its request body by default
+const DEFAULT_BODY_READ_TIMEOUT: Duration = Duration::from_secs(600);
+/// The guest's own read timeout is only a backstop for the server's, which goes off
+/// first so the server knows the client stalled
+const GUEST_READ_TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BodyLimits {
+    /// Most bytes of a request body (0 for no limit)
+    pub max_request_bytes: u64,
+    /// Most bytes of a response body (0 for no limit)
+    pub max_response_bytes: u64,
+    /// Longest wait for the next bytes of a request body
+    pub read_timeout: Duration,
+    /// Longest a response body streams (zero for no limit)
+    pub max_stream: Duration,
+    /// Longest a response other than an event stream may send nothing (zero for no limit)
+    pub idle_timeout: Duration,
+    /// Quiet time after which an event stream gets a comment (zero for none)
+    pub keepalive: Duration,
+}
+
+impl BodyLimits {
+    /// Whether a request declares a body larger than allowed
+    pub fn request_too_large(&self, headers: &HeaderMap) -> bool {
+        exceeds(declared_length(headers), self.max_request_bytes)
+    }
+
+    /// Whether a response declares a body larger than allowed
+    pub fn response_too_large(&self, headers: &HeaderMap) -> bool {
+        exceeds(declared_length(headers), self.max_response_bytes)
+    }
+}
+
+/// Whether `headers` announce Server-Sent Events
+pub fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(';')
+                .next()
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/event-stream"))
+        })
+}
+
+fn declared_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+}
+
+fn exceeds(length: Option<u64>, limit: u64) -> bool {
+    limit > 0 && length.is_some_and(|length| length > limit)
+}
+
+/// Why the server cut off a request body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyFault {
+    /// It outgrew the request body limit, in bytes
+    TooLarge { limit: u64 },
+    /// The client sent none of it for the read timeout
+    TimedOut { after: Duration },
+}
+
+impl BodyFault {
+    /// The response the client gets instead of the function's
+    pub fn response(&self) -> Result<Response<HyperOutgoingBody>> {
+        match self {
+            BodyFault::TooLarge { limit } => request_error_response(
+                413,
+                "request-too-large",
+                &format!("Request body is larger than the {limit} bytes allowed"),
+                *limit,
+            ),
+            BodyFault::TimedOut { after } => request_error_response(
+                408,
+                "request-timeout",
+                &format!(
+                    "Request body stalled: nothing was received for {} seconds",
+                    after.as_secs()
+                ),
+                after.as_secs(),
+            ),
+        }
+    }
+}
+
+/// Where a request body notes why it was cut off, for the invocation reading it
+#[derive(Clone, Debug, Default)]
+pub struct BodyFaults(Arc<OnceLock<BodyFault>>);
+
+impl BodyFaults {
+    pub fn get(&self) -> Option<BodyFault> {
+        self.0.get().copied()
+    }
+
+    fn note(&self, fault: BodyFault) {
+        let _ = self.0.set(fault);
+    }
+}
+
+/// A request body streamed to a guest. It fails once more than `limit` bytes went
+/// through it or the client sent nothing for `read_timeout`, noting which.
+struct RequestBody<B> {
+    inner: LimitedBody<B>,
+    read_timeout: Duration,
+    /// Fires once the client was quiet for `read_timeout`
+    idle: Pin<Box<Sleep>>,
+    faults: BodyFaults,
+}
+
+impl<B> RequestBody<B> {
+    fn new(inner: B, limit: u64, read_timeout: Duration, faults: BodyFaults) -> Self {
+        Self {
+            inner: LimitedBody::new(inner, limit, ErrorCode::HttpRequestBodySize),
+            read_timeout,
+            idle: Box::pin(sleep(read_timeout)),
+            faults,
+        }
+    }
+}
+
+impl<B> Body for RequestBody<B>
+where
+    B: Body<Data = Bytes, Error = ErrorCode> + Unpin,
+{
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, ErrorCode>>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                this.idle.as_mut().reset(Instant::now() + this.read_timeout);
+                return Poll::Ready(Some(Ok(frame)));
+            }
+            Poll::Ready(Some(Err(ErrorCode::HttpRequestBodySize(Some(limit))))) => {
+                this.faults.note(BodyFault::TooLarge { limit });
+                return Poll::Ready(Some(Err(ErrorCode::HttpRequestBodySize(Some(limit)))));
+            }
+            Poll::Pending => {}
+            done => return done,
+        }
+
+        if this.idle.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        this.faults.note(BodyFault::TimedOut {
+            after: this.read_timeout,
+        });
+        Poll::Ready(Some(Err(ErrorCode::ConnectionReadTimeout)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A body that fails once more than `limit` bytes went through it
+pub struct LimitedBody<B> {
+    inner: B,
+    /// Most bytes passed on (0 for no limit)
+    limit: u64,
+    /// Bytes passed on so far
+    passed: u64,
+    /// Error a body over its limit fails with, given the limit
+    too_large: fn(Option<u64>) -> ErrorCode,
+}
+
+impl<B> LimitedBody<B> {
+    pub fn new(inner: B, limit: u64, too_large: fn(Option<u64>) -> ErrorCode) -> Self {
+        Self {
+            inner,
+            limit,
+            passed: 0,
+            too_large,
+        }
+    }
+}
+
+impl<B> Body for LimitedBody<B>
+where
+    B: Body<Data = Bytes, Error = ErrorCode> + Unpin,
+{
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, ErrorCode>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &polled {
+            if let Some(data) = frame.data_ref() {
+                self.passed += data.len() as u64;
+                if self.limit > 0 && self.passed > self.limit {
+                    return Poll::Ready(Some(Err((self.too_large)(Some(self.limit)))));
+                }
+            }
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A response body that may stream for long: it is aborted at its deadline, and
+/// when it goes quiet it is aborted too or, for an event stream, kept alive with
+/// comments
+pub struct LongLivedBody<B> {
+    inner: B,
+    /// Aborts the body once it's reached
+    deadline: Option<Pin<Box<Sleep>>>,
+    /// Fires once the body was quiet for `quiet`
+    idle: Option<Pin<Box<Sleep>>>,
+    quiet: Duration,
+    /// Whether the body is an event stream, kept alive rather than aborted
+    event_stream: bool,
+    /// Newlines the body sent last, two of which end an event
+    trailing_newlines: usize,
+}
+
+impl<B> LongLivedBody<B> {
+    /// `inner`, aborted after `max_stream` and, when quiet for `quiet`, kept alive
+    /// if it's an event stream or aborted otherwise (zero for neither)
+    pub fn new(inner: B, max_stream: Duration, quiet: Duration, event_stream: bool) -> Self {
+        let timer = |after: Duration| (!after.is_zero()).then(|| Box::pin(sleep(after)));
+        Self {
+            inner,
+            deadline: timer(max_stream),
+            idle: timer(quiet),
+            quiet,
+            event_stream,
+            // Nothing sent yet is as good as the end of an event
+            trailing_newlines: 2,
+        }
+    }
+
+    fn sent(&mut self, data: &[u8]) {
+        let newlines = data
+            .iter()
+            .rev()
+            .filter(|byte| **byte != b'\r')
+            .take_while(|byte| **byte == b'\n')
+            .count();
+        let only_newlines = data.iter().all(|byte| matches!(byte, b'\r' | b'\n'));
+        self.trailing_newlines = if only_newlines {
+            self.trailing_newlines + newlines
+        } else {
+            newlines
+        };
+    }
+
+    fn restart_idle(&mut self) {
+        if let Some(idle) = &mut self.idle {
+            idle.as_mut().reset(Instant::now() + self.quiet);
+        }
+    }
+}
+
+impl<B> Body for LongLivedBody<B>
+where
+    B: Body<Data = Bytes, Error = ErrorCode> + Unpin,
+{
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, ErrorCode>>> {
+        let this = &mut *self;
+        if let Some(deadline) = &mut this.deadline {
+            // An error, so a client can't take a cut-off body for a whole one
+            if deadline.as_mut().poll(cx).is_ready() {
+                this.deadline = None;
+                return Poll::Ready(Some(Err(ErrorCode::HttpResponseTimeout)));
+            }
+        }
+
+        match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.sent(data);
+                }
+                this.restart_idle();
+                return Poll::Ready(Some(Ok(frame)));
+            }
+            Poll::Pending => {}
+            done => return done,
+        }
+
+        let Some(idle) = &mut this.idle else {
+            return Poll::Pending;
+        };
+        if idle.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        if !this.event_stream {
+            return Poll::Ready(Some(Err(ErrorCode::HttpResponseTimeout)));
+        }
+        this.restart_idle();
+        // A comment can't go in the middle of an event the guest is still writing
+        if this.trailing_newlines >= 2 {
+            return Poll::Ready(Some(Ok(Frame::data(Bytes::from_static(KEEPALIVE)))));
+        }
+        if let Some(idle) = &mut this.idle {
+            let _ = idle.as_mut().poll(cx);
+        }
+        Poll::Pending
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Hand `req` to the guest in `state`, its body streamed within the limits, noting
+/// in `faults` if it was cut off
+pub fn incoming_request<B>(
+    state: &mut FaastaClientState,
+    req: Request<B>,
+    faults: BodyFaults,
+) -> Result<Resource<HostIncomingRequest>>
+where
+    B: Body<Data = Bytes, Error = hyper::Error> + Send + Sync + Unpin + 'static,
+{
+    let (max_request_bytes, read_timeout) = match BODY_LIMITS.get().map(Reloadable::load) {
+        Some(limits) => (limits.max_request_bytes, limits.read_timeout),
+        None => (0, DEFAULT_BODY_READ_TIMEOUT),
+    };
+    let (parts, body) = req.into_parts();
+    let body = RequestBody::new(
+        body.map_err(hyper_response_error),
+        max_request_bytes,
+        read_timeout,
+        faults,
+    );
+    let body = HostIncomingBody::new(body.boxed(), read_timeout + GUEST_READ_TIMEOUT_GRACE);
+    let request = HostIncomingRequest::new(state, parts, Scheme::Http, Some(body))?;
+    Ok(state.table().push(request)?)
+}
+
+/// `response` with its body aborted if it outgrows the limit, goes quiet or
+/// streams for longer than the limit or `function_max_stream`, whichever is shorter
+pub fn limit_response(
+    mut response: Response<HyperOutgoingBody>,
+    function_max_stream: Option<Duration>,
+) -> Response<HyperOutgoingBody> {
+    let Some(limits) = BODY_LIMITS.get().map(Reloadable::load) else {
+        return response;
+    };
+    let max_stream = match function_max_stream {
+        Some(max) if limits.max_stream.is_zero() => max,
+        Some(max) => max.min(limits.max_stream),
+        None => limits.max_stream,
+    };
+    let event_stream = is_event_stream(response.headers());
+    if event_stream {
+        // Nothing between the function and the client should hold events back
+        let headers = response.headers_mut();
+        headers
+            .entry(CACHE_CONTROL)
+            .or_insert(HeaderValue::from_static("no-cache"));
+        headers.insert("x-accel-buffering", HeaderValue::from_static("no"));
+    }
+    let quiet = if event_stream {
+        limits.keepalive
+    } else {
+        limits.idle_timeout
+    };
+
+    let max_response_bytes = limits.max_response_bytes;
+    response.map(|body| {
+        let body = LongLivedBody::new(body, max_stream, quiet, event_stream);
+        LimitedBody::new(body, max_response_bytes, ErrorCode::HttpResponseBodySize).boxed()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream::{self, StreamExt};
+    use http_body_util::StreamBody;
+
+    fn chunks(sizes: &[usize]) -> impl Body<Data = Bytes, Error = ErrorCode> {
+        let frames: Vec<_> = sizes
+            .iter()
+            .map(|size| Ok(Frame::data(Bytes::from(vec![0; *size]))))
+            .collect();
+        LimitedBody::new(
+            StreamBody::new(stream::iter(frames)),
+            10,
+            ErrorCode::HttpRequestBodySize,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_body_fails_once_it_outgrows_the_limit() {
+        let body = chunks(&[4, 6]).collect().await.unwrap();
+        assert_eq!(body.to_bytes().len(), 10);
+
+        let error = chunks(&[4, 6, 1]).collect().await.unwrap_err();
+        assert!(matches!(error, ErrorCode::HttpRequestBodySize(Some(10))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, "11".parse().unwrap());
+        let limits = BodyLimits {
+            max_request_bytes: 10,
+            max_response_bytes: 0,
+            read_timeout: DEFAULT_BODY_READ_TIMEOUT,
+            max_stream: Duration::ZERO,
+            idle_timeout: Duration::ZERO,
+            keepalive: Duration::ZERO,
+        };
+        assert!(limits.request_too_large(&headers));
+        assert!(!limits.response_too_large(&headers));
+    }
+
+    #[tokio::test]
+    async fn test_cut_off_request_bodies_are_noted() {
+        let data = |size: usize| Ok(Frame::data(Bytes::from(vec![0; size])));
+        let timeout = Duration::from_millis(10);
+
+        let faults = BodyFaults::default();
+        let body = StreamBody::new(stream::iter(vec![data(4), data(7)]));
+        let error = RequestBody::new(body, 10, timeout, faults.clone())
+            .collect()
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ErrorCode::HttpRequestBodySize(Some(10))));
+        assert_eq!(faults.get(), Some(BodyFault::TooLarge { limit: 10 }));
+
+        let faults = BodyFaults::default();
+        let stalled = StreamBody::new(stream::iter(vec![data(4)]).chain(stream::pending()));
+        let mut body = RequestBody::new(stalled, 10, timeout, faults.clone());
+        body.frame().await.unwrap().unwrap();
+        assert_eq!(faults.get(), None);
+        let error = body.frame().await.unwrap().unwrap_err();
+        assert!(matches!(error, ErrorCode::ConnectionReadTimeout));
+        assert_eq!(faults.get(), Some(BodyFault::TimedOut { after: timeout }));
+        let response = BodyFault::TimedOut { after: timeout }.response().unwrap();
+        assert_eq!(response.status(), 408);
+    }
+
+    #[tokio::test]
+    async fn test_quiet_streams_are_kept_alive_or_aborted() {
+        let quiet = Duration::from_millis(10);
+        let data = |bytes: &'static [u8]| Ok(Frame::data(Bytes::from_static(bytes)));
+        let silent =
+            || StreamBody::new(stream::iter(vec![data(b"data: 1\n\n")]).chain(stream::pending()));
+
+        // An event stream gets a comment once it's quiet between events
+        let mut events = LongLivedBody::new(silent(), Duration::ZERO, quiet, true);
+        let first = events.frame().await.unwrap().unwrap();
+        assert_eq!(first.into_data().unwrap(), "data: 1\n\n");
+        let comment = events.frame().await.unwrap().unwrap();
+        assert_eq!(comment.into_data().unwrap(), KEEPALIVE);
+
+        let mut download = LongLivedBody::new(silent(), Duration::ZERO, quiet, false);
+        download.frame().await.unwrap().unwrap();
+        let error = download.frame().await.unwrap().unwrap_err();
+        assert!(matches!(error, ErrorCode::HttpResponseTimeout));
+
+        // Past its deadline a stream is cut off
+        let mut capped = LongLivedBody::new(silent(), quiet, Duration::ZERO, true);
+        capped.frame().await.unwrap().unwrap();
+        let error = capped.frame().await.unwrap().unwrap_err();
+        assert!(matches!(error, ErrorCode::HttpResponseTimeout));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            "text/event-stream; charset=utf-8".parse().unwrap(),
+        );
+        assert!(is_event_stream(&headers));
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use bincode::{Decode, Encode};
+use dashmap::DashSet;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::github_auth::GitHubAuth;
+use crate::metadata_store::MetadataStore;
+
+/// Collection of the metadata store holding suspended accounts, keyed by username
+const SUSPENSIONS_TREE: &str = "suspensions";
+
+/// Why and when an admin suspended an account
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct Suspension {
+    pub reason: String,
+    pub suspended_by: String,
+    /// Unix timestamp (seconds) of the suspension
+    pub suspended_at: i64,
+}
+
+/// Accounts suspended by an admin. Suspended users can't authenticate and the
+/// functions they own stop being served until they are unsuspended.
+pub struct Suspensions {
+    store: Arc<dyn MetadataStore>,
+    /// Functions owned by suspended users, checked on every request
+    blocked_functions: DashSet<String>,
+}
+
+impl Suspensions {
+    pub fn new(store: Arc<dyn MetadataStore>, github_auth: &GitHubAuth) -> Result<Self> {
+        let suspensions = Self {
+            store,
+            blocked_functions: DashSet::new(),
+        };
+        suspensions.reload(github_auth)?;
+        Ok(suspensions)
+    }
+
+    /// Block the functions of every suspended user again, and only those, for
+    /// suspensions made and lifted by other nodes
+    pub fn reload(&self, github_auth: &GitHubAuth) -> Result<()> {
+        let mut blocked = HashSet::new();
+        for (username, _) in self.store.scan(SUSPENSIONS_TREE)? {
+            blocked.extend(github_auth.get_user_projects(&username)?);
+        }
+        for function_name in &blocked {
+            self.blocked_functions.insert(function_name.clone());
+        }
+        self.blocked_functions
+            .retain(|function_name| blocked.contains(function_name));
+        Ok(())
+    }
+
+    pub fn get(&self, username: &str) -> Result<Option<Suspension>> {
+        let Some(value) = self.store.get(SUSPENSIONS_TREE, username)? else {
+            return Ok(None);
+        };
+        Ok(
+            bincode::decode_from_slice::<Suspension, _>(&value, bincode::config::standard())
+                .ok()
+                .map(|(suspension, _)| suspension),
+        )
+    }
+
+    /// Whether `function_name` belongs to a suspended user
+    pub fn is_function_blocked(&self, function_name: &str) -> bool {
+        self.blocked_functions.contains(function_name)
+    }
+
+    /// Suspend `username` and stop serving their functions
+    pub fn suspend(
+        &self,
+        username: &str,
+        suspension: &Suspension,
+        github_auth: &GitHubAuth,
+    ) -> Result<()> {
+        let encoded = bincode::encode_to_vec(suspension, bincode::config::standard())?;
+        self.store.insert(SUSPENSIONS_TREE, username, encoded)?;
+        self.block_functions(username, github_auth)
+    }
+
+    /// Lift a suspension, returning whether there was one
+    pub fn unsuspend(&self, username: &str, github_auth: &GitHubAuth) -> Result<bool> {
+        let removed = self.store.contains(SUSPENSIONS_TREE, username)?;
+        self.store.remove(SUSPENSIONS_TREE, username)?;
+        for project in github_auth.get_user_projects(username)? {
+            self.blocked_functions.remove(&project);
+        }
+        Ok(removed)
+    }
+
+    /// Stop blocking a function that has been deleted, so its name can be reused
+    pub fn forget_function(&self, function_name: &str) {
+        self.blocked_functions.remove(function_name);
+    }
+
+    fn block_functions(&self, username: &str, github_auth: &GitHubAuth) -> Result<()> {
+        for project in github_auth.get_user_projects(username)? {
+            self.blocked_functions.insert(project);
+        }
+        Ok(())
+    }
+}
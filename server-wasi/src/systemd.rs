@@ -0,0 +1,256 @@
+//! systemd integration.
+//!
+//! With socket activation, systemd binds the listening sockets and passes them to
+//! the server (`sd_listen_fds`), so they stay open across restarts: connections
+//! arriving while the server restarts wait in the socket's backlog instead of being
+//! refused, and the server itself needs no privilege to bind ports below 1024. The
+//! sockets are told apart by their `FileDescriptorName=`: `https`, `http`, `rpc-tcp`
+//! and `admin`; a lone unnamed socket is taken as `https`. Listeners systemd didn't
+//! pass are bound as usual, and the QUIC RPC listener always is.
+//!
+//! The sockets are taken from the environment before the async runtime starts, as
+//! clearing `LISTEN_FDS` and friends isn't safe once other threads may read it.
+//!
+//! The server also reports its state over `sd_notify` when run as a `Type=notify`
+//! or `Type=notify-reload` unit: `READY=1` once it takes requests, `RELOADING=1`
+//! while it reloads its configuration, `STOPPING=1` when it starts draining, and
+//! `WATCHDOG=1` keep-alives at half of `WatchdogSec=`, so systemd restarts a hung
+//! server. Until it's ready, it extends systemd's start timeout every few seconds
+//! (`EXTEND_TIMEOUT_USEC=`), as recovering intents and warming functions may take
+//! longer than `TimeoutStartSec=`, for at most half an hour.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tracing::{info, warn};
+
+/// First file descriptor systemd passes
+const LISTEN_FDS_START: RawFd = 3;
+/// How often the start timeout is extended until the server is ready
+const STARTUP_EXTENSION_INTERVAL: Duration = Duration::from_secs(10);
+/// How far each extension pushes the start timeout back
+const STARTUP_EXTENSION: Duration = Duration::from_secs(30);
+/// How long the start timeout is extended at most, so a startup that hangs still
+/// times out
+const STARTUP_EXTENSION_LIMIT: Duration = Duration::from_secs(30 * 60);
+
+/// Whether `READY=1` was sent, which ends the start timeout extensions
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Listening sockets passed by systemd, by name
+#[derive(Default)]
+pub struct ListenFds {
+    sockets: HashMap<String, std::net::TcpListener>,
+    /// Names of the passed sockets that aren't TCP listeners
+    ignored: Vec<String>,
+}
+
+impl ListenFds {
+    /// Take the sockets systemd passed to this process, if any. Must be called
+    /// before any other thread is started, as it clears their environment
+    /// variables; nothing is logged yet, see [`ListenFds::log_ignored`].
+    pub fn from_env() -> Self {
+        let pid = env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok());
+        if pid != Some(std::process::id()) {
+            return Self::default();
+        }
+        let count = env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse::<RawFd>().ok())
+            .unwrap_or(0);
+        let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+
+        let names = socket_names(&names, count as usize);
+        let mut activated = Self::default();
+        for (offset, name) in names.into_iter().enumerate() {
+            let fd = LISTEN_FDS_START + offset as RawFd;
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            if !is_stream_socket(fd) {
+                activated.ignored.push(name);
+                continue;
+            }
+            // systemd hands the descriptors over to this process, once
+            let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            activated.sockets.insert(name, listener);
+        }
+        activated
+    }
+
+    /// Warn about the passed sockets that won't be used, once logging is set up
+    pub fn log_ignored(&self) {
+        for name in &self.ignored {
+            warn!(
+                "Ignoring socket '{}' from systemd, it isn't a TCP listener",
+                name
+            );
+        }
+    }
+
+    /// The socket systemd passed as `name`, else one bound to `addr` now
+    pub async fn bind<A>(&mut self, name: &str, addr: A) -> Result<TcpListener>
+    where
+        A: ToSocketAddrs + fmt::Display,
+    {
+        match self.sockets.remove(name) {
+            Some(listener) => {
+                listener.set_nonblocking(true)?;
+                let listener = TcpListener::from_std(listener)?;
+                if let Ok(local) = listener.local_addr() {
+                    info!("Using the {} socket on {} passed by systemd", name, local);
+                }
+                Ok(listener)
+            }
+            None => {
+                let context = format!("Failed to bind to {addr}");
+                TcpListener::bind(addr).await.context(context)
+            }
+        }
+    }
+
+    /// Whether systemd passed a socket as `name`
+    pub fn contains(&self, name: &str) -> bool {
+        self.sockets.contains_key(name)
+    }
+}
+
+/// Name of each of `count` sockets, from `LISTEN_FDNAMES`
+fn socket_names(names: &str, count: usize) -> Vec<String> {
+    let names: Vec<&str> = names.split(':').filter(|name| !name.is_empty()).collect();
+    (0..count)
+        .map(|i| match names.get(i) {
+            Some(&"unknown") | None if count == 1 => "https".to_string(),
+            Some(name) => name.to_string(),
+            None => "unknown".to_string(),
+        })
+        .collect()
+}
+
+fn is_stream_socket(fd: RawFd) -> bool {
+    let mut kind: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut kind as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    result == 0 && kind == libc::SOCK_STREAM
+}
+
+/// Tell systemd about the server's state, if it's listening. Returns whether the
+/// message was sent.
+pub fn notify(state: &str) -> bool {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return false;
+    };
+    let send = || -> std::io::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+        match path.as_encoded_bytes().strip_prefix(b"@") {
+            Some(name) => {
+                socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?
+            }
+            None => socket.send_to(state.as_bytes(), &path)?,
+        };
+        Ok(())
+    };
+    match send() {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to notify systemd: {}", e);
+            false
+        }
+    }
+}
+
+/// Extend systemd's start timeout until [`notify_ready`] is called, if it's
+/// listening, for at most [`STARTUP_EXTENSION_LIMIT`]
+pub fn spawn_startup_extension() {
+    if env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    let extension = format!("EXTEND_TIMEOUT_USEC={}", STARTUP_EXTENSION.as_micros());
+    let deadline = tokio::time::Instant::now() + STARTUP_EXTENSION_LIMIT;
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(STARTUP_EXTENSION_INTERVAL);
+        loop {
+            let at = ticker.tick().await;
+            if at >= deadline || READY.load(Ordering::Relaxed) || !notify(&extension) {
+                break;
+            }
+        }
+    });
+}
+
+/// `READY=1`, which also ends the start timeout extensions
+pub fn notify_ready() {
+    READY.store(true, Ordering::Relaxed);
+    notify("READY=1");
+}
+
+/// `RELOADING=1` with the monotonic timestamp `Type=notify-reload` expects
+pub fn notify_reloading() {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    let usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={usec}"));
+}
+
+/// Send watchdog keep-alives at half the interval systemd expects them, if it does
+pub fn spawn_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    info!("Sending systemd watchdog keep-alives every {:?}", interval);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
+
+fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    let pid = env::var("WATCHDOG_PID").ok();
+    if pid.is_some_and(|pid| pid.parse::<u32>().ok() != Some(std::process::id())) {
+        return None;
+    }
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sockets_are_named_by_listen_fdnames() {
+        assert_eq!(
+            socket_names("https:http:admin", 3),
+            ["https", "http", "admin"]
+        );
+        // A lone unnamed socket serves HTTPS; other unnamed ones aren't used
+        assert_eq!(socket_names("", 1), ["https"]);
+        assert_eq!(socket_names("unknown", 1), ["https"]);
+        assert_eq!(socket_names("https", 2), ["https", "unknown"]);
+        assert!(socket_names("https", 0).is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::Result;
+use bincode::{Decode, Encode};
+use faasta_interface::{TeamMember, TeamRole};
+use std::sync::Arc;
+
+use crate::metadata_store::MetadataStore;
+
+/// Collection of the metadata store holding team definitions, keyed by team name
+const TEAMS_TREE: &str = "teams";
+
+/// A server-defined team. Functions owned by a team use
+/// [`faasta_interface::team_owner`] as their owner.
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct Team {
+    pub name: String,
+    /// GitHub organization whose members count as members of the team
+    pub github_org: Option<String>,
+    pub members: Vec<TeamMember>,
+}
+
+impl Team {
+    /// Role of `username` in the team's explicit member list
+    pub fn member_role(&self, username: &str) -> Option<TeamRole> {
+        self.members
+            .iter()
+            .find(|member| member.username == username)
+            .map(|member| member.role)
+    }
+
+    /// Add `username` or change their role
+    pub fn set_member(&mut self, username: &str, role: TeamRole) {
+        match self
+            .members
+            .iter_mut()
+            .find(|member| member.username == username)
+        {
+            Some(member) => member.role = role,
+            None => self.members.push(TeamMember {
+                username: username.to_string(),
+                role,
+            }),
+        }
+    }
+
+    /// Number of members with the owner role
+    pub fn owner_count(&self) -> usize {
+        self.members
+            .iter()
+            .filter(|member| member.role == TeamRole::Owner)
+            .count()
+    }
+}
+
+pub struct TeamStore {
+    store: Arc<dyn MetadataStore>,
+}
+
+impl TeamStore {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Self {
+        Self { store }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<Team>> {
+        let Some(value) = self.store.get(TEAMS_TREE, name)? else {
+            return Ok(None);
+        };
+        Ok(
+            bincode::decode_from_slice::<Team, _>(&value, bincode::config::standard())
+                .ok()
+                .map(|(team, _)| team),
+        )
+    }
+
+    pub fn save(&self, team: &Team) -> Result<()> {
+        let encoded = bincode::encode_to_vec(team, bincode::config::standard())?;
+        self.store.insert(TEAMS_TREE, &team.name, encoded)
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.store.remove(TEAMS_TREE, name)
+    }
+}
@@ -0,0 +1,275 @@
+//! Optional export of tracing spans over OTLP.
+//!
+//! The HTTP path is instrumented with nested spans (`request` for routing,
+//! `instantiate`, `guest` for the function's handler and `response_body` while the
+//! body streams out), and every RPC runs in an `rpc` span. With an OTLP endpoint
+//! configured these spans are batched to a collector such as the OpenTelemetry
+//! Collector, Jaeger or Tempo, so operators can see where a request's latency
+//! goes. A caller's W3C `traceparent` header makes the request part of its trace,
+//! but whether it's sampled is still up to the server's ratio, so callers can't
+//! have every request exported. Spans still batched are sent on shutdown. Without
+//! an endpoint spans only feed the log output.
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use once_cell::sync::OnceCell;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{
+    Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId, TracerProvider as _,
+};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{ShouldSample, TracerProvider};
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use tracing::{error, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+
+/// Where and how spans are exported
+pub struct TracingExportConfig {
+    /// OTLP/HTTP endpoint of the collector, without the `/v1/traces` path
+    pub endpoint: String,
+    /// Extra headers sent with every export, such as an API key
+    pub headers: HashMap<String, String>,
+    /// `service.name` the spans are reported under
+    pub service_name: String,
+    /// Share of traces that are exported, from 0 to 1
+    pub sample_ratio: f64,
+}
+
+/// Provider exporting the spans, shut down to send the last ones
+static PROVIDER: OnceCell<TracerProvider> = OnceCell::new();
+
+/// Samples a share of the traces started here, including those continuing a
+/// caller's trace, whose sampled flag isn't trusted. Spans within a trace follow
+/// their local parent.
+#[derive(Clone, Debug)]
+struct LocalRootSampler {
+    ratio: f64,
+}
+
+impl ShouldSample for LocalRootSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&opentelemetry::Context>,
+        _trace_id: TraceId,
+        _name: &str,
+        _span_kind: &SpanKind,
+        _attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        let parent = parent_context
+            .filter(|cx| cx.has_active_span())
+            .map(|cx| cx.span().span_context().clone());
+        let sampled = match &parent {
+            Some(parent) if !parent.is_remote() => parent.is_sampled(),
+            _ => rand::random::<f64>() < self.ratio,
+        };
+        SamplingResult {
+            decision: if sampled {
+                SamplingDecision::RecordAndSample
+            } else {
+                SamplingDecision::Drop
+            },
+            attributes: Vec::new(),
+            trace_state: parent
+                .map(|parent| parent.trace_state().clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Parse `key=value` pairs separated by commas, as in `OTEL_EXPORTER_OTLP_HEADERS`
+pub fn parse_headers(headers: &str) -> Result<HashMap<String, String>> {
+    headers
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .with_context(|| format!("Invalid OTLP header '{pair}', expected key=value"))?;
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Set up logging to stdout, and span export when `export` is given. Must be
+/// called from within the Tokio runtime.
+pub fn init(export: Option<TracingExportConfig>) -> Result<()> {
+    let otel_layer = export
+        .map(|export| -> Result<_> {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(format!(
+                    "{}/v1/traces",
+                    export.endpoint.trim_end_matches('/')
+                ))
+                .with_headers(export.headers);
+            let provider = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(exporter)
+                .with_trace_config(
+                    opentelemetry_sdk::trace::Config::default()
+                        .with_sampler(LocalRootSampler {
+                            ratio: export.sample_ratio,
+                        })
+                        .with_resource(Resource::new([KeyValue::new(
+                            "service.name",
+                            export.service_name,
+                        )])),
+                )
+                .install_batch(opentelemetry_sdk::runtime::Tokio)
+                .context("Failed to set up the OTLP exporter")?;
+            let tracer = provider.tracer("server-wasi");
+            let _ = PROVIDER.set(provider.clone());
+            opentelemetry::global::set_tracer_provider(provider);
+            Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+        })
+        .transpose()?;
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel_layer)
+        .init();
+    Ok(())
+}
+
+/// Export the spans still batched and stop exporting, when spans are exported
+pub async fn shutdown() {
+    let Some(provider) = PROVIDER.get() else {
+        return;
+    };
+    // Waits for the exporter
+    let shut_down = tokio::task::spawn_blocking(|| provider.shutdown()).await;
+    if let Ok(Err(e)) = shut_down {
+        error!("Failed to export the last spans: {}", e);
+    }
+}
+
+/// Make `span` part of the trace named by the request's `traceparent` header,
+/// when it has one
+pub fn set_remote_parent(span: &Span, headers: &hyper::HeaderMap) {
+    struct Headers<'a>(&'a hyper::HeaderMap);
+    impl opentelemetry::propagation::Extractor for Headers<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|value| value.to_str().ok())
+        }
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|key| key.as_str()).collect()
+        }
+    }
+    if headers.contains_key("traceparent") {
+        span.set_parent(TraceContextPropagator::new().extract(&Headers(headers)));
+    }
+}
+
+/// Keep `span` open until `body` has been sent, so it times the response streaming
+pub fn trace_body(body: HyperOutgoingBody, span: Span) -> HyperOutgoingBody {
+    HyperOutgoingBody::new(SpanBody {
+        inner: body,
+        span: Some(span),
+    })
+}
+
+struct SpanBody {
+    inner: HyperOutgoingBody,
+    /// Closed once the body ends or fails
+    span: Option<Span>,
+}
+
+impl Body for SpanBody {
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, ErrorCode>>> {
+        let this = &mut *self;
+        let entered = this.span.as_ref().map(Span::enter);
+        let poll = Pin::new(&mut this.inner).poll_frame(cx);
+        drop(entered);
+        if matches!(poll, Poll::Ready(None | Some(Err(_)))) {
+            this.span = None;
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers_are_parsed() {
+        let headers = parse_headers("x-api-key=abc, x-tenant = faasta,").unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["x-api-key"], "abc");
+        assert_eq!(headers["x-tenant"], "faasta");
+        assert!(parse_headers("no-value").is_err());
+    }
+
+    #[test]
+    fn test_a_callers_sampled_flag_is_not_trusted() {
+        use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceState};
+
+        let span_context = |remote| {
+            SpanContext::new(
+                TraceId::from_bytes([1; 16]),
+                SpanId::from_bytes([1; 8]),
+                TraceFlags::SAMPLED,
+                remote,
+                TraceState::default(),
+            )
+        };
+        let decision = |sampler: &LocalRootSampler, remote| {
+            let cx = opentelemetry::Context::new().with_remote_span_context(span_context(remote));
+            sampler
+                .should_sample(
+                    Some(&cx),
+                    TraceId::from_bytes([1; 16]),
+                    "request",
+                    &SpanKind::Server,
+                    &[],
+                    &[],
+                )
+                .decision
+        };
+        let never = LocalRootSampler { ratio: 0.0 };
+        assert_eq!(decision(&never, true), SamplingDecision::Drop);
+        assert_eq!(decision(&never, false), SamplingDecision::RecordAndSample);
+        let always = LocalRootSampler { ratio: 1.0 };
+        assert_eq!(
+            always
+                .should_sample(
+                    None,
+                    TraceId::from_bytes([1; 16]),
+                    "rpc",
+                    &SpanKind::Server,
+                    &[],
+                    &[]
+                )
+                .decision,
+            SamplingDecision::RecordAndSample
+        );
+    }
+}
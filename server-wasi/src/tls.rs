@@ -0,0 +1,227 @@
+//! Certificates served by the TLS listeners.
+//!
+//! The HTTPS listener and both RPC listeners, on QUIC and TCP, pick their
+//! certificate from the [`CertResolver`] on every handshake. The resolver watches
+//! its certificate and key files, rewritten by renewals (the Porkbun store with
+//! `--auto-cert`, or any other tool), and swaps in the new pair once both load and
+//! match, so renewed certificates are served from the next connection on without a
+//! restart. Connections already open keep the certificate they started with; a
+//! pair that doesn't load leaves the current one in place.
+
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tracing::{error, info};
+
+/// Global certificate of the listeners, set at startup
+pub static CERTIFICATES: OnceCell<Arc<CertResolver>> = OnceCell::new();
+
+/// How often the certificate and key files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The certificate currently served, swapped as a whole
+#[derive(Debug)]
+pub struct CertResolver(RwLock<Installed>);
+
+#[derive(Debug)]
+struct Installed {
+    key: Arc<CertifiedKey>,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    /// When the files were last modified as of the last load
+    modified: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl CertResolver {
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        let modified = modified_at(cert_path, key_path);
+        Ok(Self(RwLock::new(Installed {
+            key: Arc::new(certified_key(cert_path, key_path)?),
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            modified,
+        })))
+    }
+
+    /// Serve, and watch, the certificate at `cert_path` from now on. The current one
+    /// is kept if the new one can't be loaded.
+    pub fn reload(&self, cert_path: &Path, key_path: &Path) -> Result<()> {
+        let modified = modified_at(cert_path, key_path);
+        let key = certified_key(cert_path, key_path)?;
+        *self.0.write().unwrap() = Installed {
+            key: Arc::new(key),
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            modified,
+        };
+        Ok(())
+    }
+
+    /// Reload the files if they changed since they were last loaded, returning
+    /// whether a new certificate is served
+    fn refresh(&self) -> Result<bool> {
+        let (cert_path, key_path) = {
+            let installed = self.0.read().unwrap();
+            let modified = modified_at(&installed.cert_path, &installed.key_path);
+            if modified == installed.modified {
+                return Ok(false);
+            }
+            (installed.cert_path.clone(), installed.key_path.clone())
+        };
+        let modified = modified_at(&cert_path, &key_path);
+        let loaded = certified_key(&cert_path, &key_path);
+        let mut installed = self.0.write().unwrap();
+        // A half-written pair fails to load; it's retried once the other file changes
+        installed.modified = modified;
+        installed.key = Arc::new(loaded?);
+        Ok(true)
+    }
+
+    /// Whether the certificate served is valid now, saying why not
+    pub fn check_validity(&self) -> Result<(), String> {
+        let key = self.0.read().unwrap().key.clone();
+        let leaf = key.end_entity_cert().map_err(|e| e.to_string())?;
+        let (_, x509) = x509_parser::parse_x509_certificate(leaf)
+            .map_err(|e| format!("certificate unreadable: {e}"))?;
+        let validity = x509.validity();
+        if validity.is_valid() {
+            Ok(())
+        } else {
+            Err(format!(
+                "certificate only valid from {} to {}",
+                validity.not_before, validity.not_after
+            ))
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().unwrap().key.clone())
+    }
+}
+
+/// Configuration of the HTTPS listener serving the certificates of `resolver`,
+/// offering HTTP/2 and HTTP/1.1
+pub fn server_config(resolver: Arc<CertResolver>) -> Arc<ServerConfig> {
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Arc::new(config)
+}
+
+/// Swap in the certificate of [`CERTIFICATES`] whenever its files change
+pub fn spawn_watcher() {
+    tokio::spawn(async {
+        let mut ticker = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let Some(certificates) = CERTIFICATES.get() else {
+                continue;
+            };
+            match certificates.refresh() {
+                Ok(true) => info!("TLS certificate changed on disk, serving the new one"),
+                Ok(false) => {}
+                Err(e) => error!("Keeping the current TLS certificate: {:#}", e),
+            }
+        }
+    });
+}
+
+fn modified_at(cert_path: &Path, key_path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    (modified(cert_path), modified(key_path))
+}
+
+/// The certificate chain at `cert_path` and the private key at `key_path`
+fn read_key_pair(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    // Load TLS certificate
+    let cert_file = File::open(cert_path)
+        .with_context(|| format!("Failed to open TLS cert file: {cert_path:?}"))?;
+    let mut cert_reader = BufReader::new(cert_file);
+
+    // Parse the certificates
+    let certs = rustls_pemfile::certs(&mut cert_reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificate found in TLS cert file"));
+    }
+
+    // Load TLS private key
+    let key_file = File::open(key_path)
+        .with_context(|| format!("Failed to open TLS key file: {key_path:?}"))?;
+    let mut key_reader = BufReader::new(key_file);
+
+    // Parse the private key
+    let key = rustls_pemfile::private_key(&mut key_reader)?
+        .ok_or_else(|| anyhow!("No private key found in TLS key file"))?;
+
+    Ok((certs, key))
+}
+
+fn certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let (certs, key) = read_key_pair(cert_path, key_path)?;
+    CertifiedKey::from_der(certs, key, &ring::default_provider())
+        .with_context(|| format!("Failed to load TLS key {key_path:?} for {cert_path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &[u8], modified: SystemTime) {
+        fs::write(path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_changed_files_are_swapped_in_once_they_load() {
+        let certs = Path::new(env!("CARGO_MANIFEST_DIR")).join("certs");
+        let dir = std::env::temp_dir().join(format!("faasta-tls-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let cert = fs::read(certs.join("cert.pem")).unwrap();
+        let key = fs::read(certs.join("key.pem")).unwrap();
+        let epoch = SystemTime::UNIX_EPOCH;
+        write(&cert_path, &cert, epoch);
+        write(&key_path, &key, epoch);
+
+        let resolver = CertResolver::load(&cert_path, &key_path).unwrap();
+        let served = || resolver.0.read().unwrap().key.clone();
+        let first = served();
+        assert!(!resolver.refresh().unwrap());
+        // The bundled development certificate expired in April 2026
+        let invalid = resolver.check_validity().unwrap_err();
+        assert!(invalid.contains("2026"), "{invalid}");
+
+        // A key that doesn't load keeps the current certificate
+        write(&key_path, b"not a key", epoch + Duration::from_secs(60));
+        assert!(resolver.refresh().is_err());
+        assert!(Arc::ptr_eq(&served(), &first));
+        assert!(!resolver.refresh().unwrap());
+
+        write(&key_path, &key, epoch + Duration::from_secs(120));
+        assert!(resolver.refresh().unwrap());
+        assert!(!Arc::ptr_eq(&served(), &first));
+        assert_eq!(served().cert, first.cert);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+//! Handing functions over to another user.
+//!
+//! A function changes owner only when both sides agree: its owner offers it to
+//! another user, who takes it over by accepting within a week. Offering it again
+//! replaces the offer. Platform admins may move a function at once. When the
+//! transfer happens, the function's metadata and both users' lists of projects are
+//! written in one batch of the metadata store, with the offer removed, so the
+//! function never shows up under both users or neither.
+
+use anyhow::Result;
+use bincode::{Decode, Encode};
+use once_cell::sync::OnceCell;
+use std::sync::Arc;
+
+use crate::metadata_store::{Change, MetadataStore};
+
+/// Collection of the metadata store holding pending offers, keyed by function name
+pub const TRANSFERS_TREE: &str = "ownership_transfers";
+/// Seconds an offer stays open
+const OFFER_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Global pending transfers, set at startup
+pub static TRANSFERS: OnceCell<Transfers> = OnceCell::new();
+
+/// A function offered to another user
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Offer {
+    pub from: String,
+    pub to: String,
+    /// Unix timestamp (seconds) of the offer
+    pub offered_at: i64,
+}
+
+impl Offer {
+    fn is_open(&self, now: i64) -> bool {
+        now - self.offered_at < OFFER_TTL_SECS
+    }
+}
+
+pub struct Transfers {
+    store: Arc<dyn MetadataStore>,
+}
+
+impl Transfers {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Self {
+        Self { store }
+    }
+
+    /// Offer `name` from `from` to `to`, replacing any earlier offer
+    pub fn offer(&self, name: &str, from: &str, to: &str) -> Result<Offer> {
+        let offer = Offer {
+            from: from.to_string(),
+            to: to.to_string(),
+            offered_at: chrono::Utc::now().timestamp(),
+        };
+        let encoded = bincode::encode_to_vec(&offer, bincode::config::standard())?;
+        self.store.insert(TRANSFERS_TREE, name, encoded)?;
+        Ok(offer)
+    }
+
+    /// The open offer of `name`, if there is one
+    pub fn pending(&self, name: &str) -> Result<Option<Offer>> {
+        let Some(bytes) = self.store.get(TRANSFERS_TREE, name)? else {
+            return Ok(None);
+        };
+        let (offer, _) =
+            bincode::decode_from_slice::<Offer, _>(&bytes, bincode::config::standard())?;
+        Ok(offer
+            .is_open(chrono::Utc::now().timestamp())
+            .then_some(offer))
+    }
+
+    /// Changes moving any offer of `from` to `to`, to apply with the rename of the
+    /// function
+    pub fn rename(&self, from: &str, to: &str) -> Result<Vec<Change>> {
+        let Some(value) = self.store.get(TRANSFERS_TREE, from)? else {
+            return Ok(Vec::new());
+        };
+        Ok(vec![
+            Self::close(from),
+            Change::Insert {
+                collection: TRANSFERS_TREE,
+                key: to.to_string(),
+                value,
+            },
+        ])
+    }
+
+    /// Change removing the offer of `name`, to apply with the transfer itself
+    pub fn close(name: &str) -> Change {
+        Change::Remove {
+            collection: TRANSFERS_TREE,
+            key: name.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_store::SledStore;
+
+    #[test]
+    fn test_offers_replace_each_other_and_expire() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store: Arc<dyn MetadataStore> = Arc::new(SledStore::new(&db));
+        let transfers = Transfers::new(store.clone());
+        assert_eq!(transfers.pending("api").unwrap(), None);
+
+        transfers.offer("api", "alice", "bob").unwrap();
+        let offer = transfers.offer("api", "alice", "carol").unwrap();
+        assert_eq!(transfers.pending("api").unwrap(), Some(offer.clone()));
+
+        let expired = Offer {
+            offered_at: offer.offered_at - OFFER_TTL_SECS,
+            ..offer
+        };
+        let encoded = bincode::encode_to_vec(&expired, bincode::config::standard()).unwrap();
+        store.insert(TRANSFERS_TREE, "api", encoded).unwrap();
+        assert_eq!(transfers.pending("api").unwrap(), None);
+
+        store.apply(&[Transfers::close("api")]).unwrap();
+        assert!(store.get(TRANSFERS_TREE, "api").unwrap().is_none());
+
+        // An offer follows the function to its new name
+        let offer = transfers.offer("api", "alice", "bob").unwrap();
+        store
+            .apply(&transfers.rename("api", "edge").unwrap())
+            .unwrap();
+        assert_eq!(transfers.pending("api").unwrap(), None);
+        assert_eq!(transfers.pending("edge").unwrap(), Some(offer));
+        assert!(transfers.rename("api", "web").unwrap().is_empty());
+    }
+}
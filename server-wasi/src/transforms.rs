@@ -0,0 +1,216 @@
+//! Request and response transformation rules of functions.
+//!
+//! A function's rules, set from the `[[transforms]]` entries of its project's
+//! `faasta.toml` on deploy, change its requests and responses without touching its
+//! code, e.g. to front clients that can't be changed. Every rule whose route
+//! prefixes the request's path applies, in order: headers are set and removed, the
+//! `Host` is rewritten and query parameters stripped before the function is
+//! invoked, and statuses are mapped and headers set and removed once it answered.
+//!
+//! The request id and bot signal headers are set after the rules, so rules can't
+//! forge them.
+
+use anyhow::Result;
+use faasta_interface::TransformRule;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, HOST};
+use hyper::http::uri::PathAndQuery;
+use hyper::{Request, Response, StatusCode, Uri};
+use once_cell::sync::OnceCell;
+use std::sync::Arc;
+
+use crate::metadata_store::{Cached, MetadataStore};
+
+/// Collection of the metadata store holding each function's rules, keyed by
+/// function name
+pub const TRANSFORMS_TREE: &str = "function_transforms";
+
+/// Global transformation rules, set at startup
+pub static TRANSFORMS: OnceCell<Transforms> = OnceCell::new();
+
+pub struct Transforms {
+    rules: Cached<Vec<TransformRule>>,
+}
+
+impl Transforms {
+    pub fn new(store: Arc<dyn MetadataStore>) -> Result<Self> {
+        Ok(Self {
+            rules: Cached::new(store, TRANSFORMS_TREE, |value| {
+                bincode::decode_from_slice(value, bincode::config::standard())
+                    .ok()
+                    .map(|(rules, _)| rules)
+            })?,
+        })
+    }
+
+    /// Replace the rules of `name`, removing them when `rules` is empty
+    pub fn set(&self, name: &str, rules: &[TransformRule]) -> Result<()> {
+        if rules.is_empty() {
+            self.rules.remove(name)?;
+        } else {
+            let encoded = bincode::encode_to_vec(rules, bincode::config::standard())?;
+            self.rules.insert(name, rules.to_vec(), encoded)?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Vec<TransformRule> {
+        self.rules.get(name).unwrap_or_default()
+    }
+
+    /// Read `name`'s rules again, after its record was moved or copied
+    pub fn refresh(&self, name: &str) -> Result<()> {
+        self.rules.refresh(name)
+    }
+
+    /// Read every function's rules again, for changes made by other nodes
+    pub fn reload(&self) -> Result<()> {
+        self.rules.reload()
+    }
+}
+
+/// The rules of `rules` that apply to `req`
+pub fn matching<B>(rules: Vec<TransformRule>, req: &Request<B>) -> Vec<TransformRule> {
+    let path = req.uri().path();
+    rules
+        .into_iter()
+        .filter(|rule| rule.matches(path))
+        .collect()
+}
+
+/// Make the request changes of `rules` to `req`
+pub fn transform_request<B>(rules: &[TransformRule], req: &mut Request<B>) -> Result<()> {
+    for rule in rules {
+        let headers = req.headers_mut();
+        remove_headers(headers, &rule.remove_request_headers);
+        add_headers(headers, rule.add_request_headers.iter())?;
+        if let Some(host) = &rule.host {
+            headers.insert(HOST, HeaderValue::from_str(host)?);
+        }
+        if !rule.strip_query.is_empty() {
+            *req.uri_mut() = strip_query(req.uri(), &rule.strip_query)?;
+        }
+    }
+    Ok(())
+}
+
+/// Make the response changes of `rules` to `resp`
+pub fn transform_response<B>(rules: &[TransformRule], resp: &mut Response<B>) -> Result<()> {
+    for rule in rules {
+        let status = resp.status().as_u16();
+        if let Some(mapping) = rule
+            .map_status
+            .iter()
+            .find(|mapping| mapping.from == status)
+        {
+            *resp.status_mut() = StatusCode::from_u16(mapping.to)?;
+        }
+        let headers = resp.headers_mut();
+        remove_headers(headers, &rule.remove_response_headers);
+        add_headers(headers, rule.add_response_headers.iter())?;
+    }
+    Ok(())
+}
+
+fn remove_headers(headers: &mut HeaderMap, names: &[String]) {
+    for name in names {
+        headers.remove(name.as_str());
+    }
+}
+
+fn add_headers<'a>(
+    headers: &mut HeaderMap,
+    added: impl Iterator<Item = (&'a String, &'a String)>,
+) -> Result<()> {
+    for (name, value) in added {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    Ok(())
+}
+
+/// `uri` without the query parameters named in `params`
+fn strip_query(uri: &Uri, params: &[String]) -> Result<Uri> {
+    let Some(query) = uri.query() else {
+        return Ok(uri.clone());
+    };
+    let stripped = |pair: &&str| {
+        let name = pair.split('=').next().unwrap_or("");
+        params.iter().any(|param| match param.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == param,
+        })
+    };
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !stripped(pair))
+        .collect();
+    let path_and_query = if kept.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), kept.join("&"))
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+    Ok(Uri::from_parts(parts)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faasta_interface::StatusMapping;
+
+    #[test]
+    fn test_rules_transform_requests_and_responses_under_their_route() {
+        let rule = TransformRule {
+            route: Some("/legacy".to_string()),
+            add_request_headers: [("x-api-version".to_string(), "1".to_string())].into(),
+            remove_request_headers: vec!["cookie".to_string()],
+            host: Some("api.example.com".to_string()),
+            strip_query: vec!["utm_*".to_string(), "debug".to_string()],
+            add_response_headers: [("cache-control".to_string(), "no-store".to_string())].into(),
+            remove_response_headers: vec!["server".to_string()],
+            map_status: vec![StatusMapping { from: 404, to: 200 }],
+        };
+        let request = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header(HOST, "fn.faasta.xyz")
+                .header("cookie", "session=1")
+                .body(())
+                .unwrap()
+        };
+
+        assert!(matching(vec![rule.clone()], &request("/other")).is_empty());
+        assert!(matching(vec![rule.clone()], &request("/legacyx")).is_empty());
+        let mut req = request("/legacy/items?utm_source=mail&id=7&debug&utm_medium=x");
+        let rules = matching(vec![rule], &req);
+        assert_eq!(rules.len(), 1);
+
+        transform_request(&rules, &mut req).unwrap();
+        assert_eq!(req.uri(), "/legacy/items?id=7");
+        assert_eq!(req.headers()[HOST], "api.example.com");
+        assert_eq!(req.headers()["x-api-version"], "1");
+        assert!(!req.headers().contains_key("cookie"));
+
+        let mut resp = Response::builder()
+            .status(404)
+            .header("server", "legacy/1.0")
+            .body(())
+            .unwrap();
+        transform_response(&rules, &mut resp).unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["cache-control"], "no-store");
+        assert!(!resp.headers().contains_key("server"));
+    }
+
+    #[test]
+    fn test_stripping_every_parameter_drops_the_query() {
+        let uri: Uri = "/search?utm_source=a".parse().unwrap();
+        assert_eq!(
+            strip_query(&uri, &["utm_*".to_string()]).unwrap(),
+            "/search"
+        );
+    }
+}
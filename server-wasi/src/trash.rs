@@ -0,0 +1,250 @@
+//! Soft-deleted functions.
+//!
+//! Unpublishing moves a function's artifacts into `.trash/` (in artifact storage and
+//! the functions directory) and its metadata into a separate sled tree, which takes
+//! it out of routing while keeping everything needed to bring it back. The name
+//! stays reserved for the owner until the retention period runs out and the
+//! periodic purge deletes it for good.
+
+use anyhow::{Context, Result};
+use bincode::{Decode, Encode};
+use faasta_interface::{EventSeverity, FunctionInfo, ServerEventKind};
+use once_cell::sync::OnceCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{error, info};
+
+use crate::function_data;
+use crate::journal;
+use crate::rpc_service::LegacyFunctionInfo;
+use crate::storage::{wasm_key, ArtifactStorage};
+use crate::wasi_server::SERVER;
+
+/// Sled tree holding trashed function metadata, keyed by function name
+pub const TRASH_TREE: &str = "trash";
+/// Directory (inside the functions directory and artifact storage) holding trashed artifacts
+pub const TRASH_DIR: &str = ".trash";
+
+/// Global trash, only set when a retention period is configured
+pub static TRASH: OnceCell<Trash> = OnceCell::new();
+
+/// A function waiting in the trash
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct TrashedFunction {
+    pub info: FunctionInfo,
+    /// Unix timestamp (seconds) of the unpublish
+    pub deleted_at: i64,
+}
+
+/// `TrashedFunction` as stored before functions carried metadata
+#[derive(Decode)]
+struct LegacyTrashedFunction {
+    info: LegacyFunctionInfo,
+    deleted_at: i64,
+}
+
+impl TrashedFunction {
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let config = bincode::config::standard();
+        if let Ok((trashed, _)) = bincode::decode_from_slice::<TrashedFunction, _>(bytes, config) {
+            return Some(trashed);
+        }
+        bincode::decode_from_slice::<LegacyTrashedFunction, _>(bytes, config)
+            .ok()
+            .map(|(legacy, _)| Self {
+                info: legacy.info.into(),
+                deleted_at: legacy.deleted_at,
+            })
+    }
+}
+
+pub struct Trash {
+    tree: sled::Tree,
+    functions_dir: PathBuf,
+    storage: Arc<dyn ArtifactStorage>,
+    retention: Duration,
+}
+
+impl Trash {
+    pub fn new(
+        db: &sled::Db,
+        functions_dir: &Path,
+        storage: Arc<dyn ArtifactStorage>,
+        retention: Duration,
+    ) -> Result<Self> {
+        fs::create_dir_all(functions_dir.join(TRASH_DIR))?;
+        Ok(Self {
+            tree: db.open_tree(TRASH_TREE)?,
+            functions_dir: functions_dir.to_path_buf(),
+            storage,
+            retention,
+        })
+    }
+
+    /// How long trashed functions are kept before being purged
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    /// Look up a trashed function by name
+    pub fn get(&self, name: &str) -> Option<TrashedFunction> {
+        match self.tree.get(name.as_bytes()) {
+            Ok(value) => TrashedFunction::decode(&value?),
+            Err(e) => {
+                error!("Failed to read trashed function '{}': {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Names of the functions in the trash
+    pub fn names(&self) -> Vec<String> {
+        self.tree
+            .iter()
+            .keys()
+            .flatten()
+            .map(|key| String::from_utf8_lossy(&key).into_owned())
+            .collect()
+    }
+
+    /// Move a function's artifacts into the trash and remember its metadata
+    pub async fn trash(&self, info: FunctionInfo) -> Result<()> {
+        self.move_artifacts(&info.name, true).await?;
+
+        let trashed = TrashedFunction {
+            info,
+            deleted_at: chrono::Utc::now().timestamp(),
+        };
+        let encoded = bincode::encode_to_vec(&trashed, bincode::config::standard())?;
+        self.tree.insert(trashed.info.name.as_bytes(), encoded)?;
+        Ok(())
+    }
+
+    /// Move a trashed function's artifacts back and return its metadata
+    pub async fn restore(&self, name: &str) -> Result<Option<FunctionInfo>> {
+        let Some(trashed) = self.get(name) else {
+            return Ok(None);
+        };
+
+        self.move_artifacts(name, false).await?;
+        self.tree.remove(name.as_bytes())?;
+        Ok(Some(trashed.info))
+    }
+
+    /// Permanently delete a trashed function
+    pub async fn discard(&self, name: &str) -> Result<()> {
+        self.storage.delete(&trashed_key(name)).await?;
+        let path = self.trash_dir().join(format!("{name}.cwasm"));
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        self.tree.remove(name.as_bytes())?;
+        Ok(())
+    }
+
+    /// Functions whose retention period has run out
+    pub fn expired(&self) -> Vec<TrashedFunction> {
+        let cutoff = chrono::Utc::now().timestamp() - self.retention.as_secs() as i64;
+        self.tree
+            .iter()
+            .flatten()
+            .filter_map(|(_, value)| TrashedFunction::decode(&value))
+            .filter(|trashed| trashed.deleted_at <= cutoff)
+            .collect()
+    }
+
+    /// Delete every function whose retention period has run out, returning them
+    pub async fn purge_expired(&self) -> Vec<TrashedFunction> {
+        let mut purged = Vec::new();
+        for trashed in self.expired() {
+            match self.discard(&trashed.info.name).await {
+                Ok(()) => purged.push(trashed),
+                Err(e) => error!("Failed to purge '{}' from trash: {}", trashed.info.name, e),
+            }
+        }
+        purged
+    }
+
+    fn trash_dir(&self) -> PathBuf {
+        self.functions_dir.join(TRASH_DIR)
+    }
+
+    /// Move a function's artifacts into the trash, or back out of it
+    async fn move_artifacts(&self, name: &str, into_trash: bool) -> Result<()> {
+        let (live, trashed) = (wasm_key(name), trashed_key(name));
+        let (from, to) = if into_trash {
+            (&live, &trashed)
+        } else {
+            (&trashed, &live)
+        };
+        self.storage.rename(from, to).await?;
+
+        let (live, trashed) = (
+            self.functions_dir.join(format!("{name}.cwasm")),
+            self.trash_dir().join(format!("{name}.cwasm")),
+        );
+        let (from, to) = if into_trash {
+            (&live, &trashed)
+        } else {
+            (&trashed, &live)
+        };
+        if from.exists() {
+            fs::rename(from, to).with_context(|| format!("Failed to move {}", from.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Spawn a task that purges expired functions every `interval_secs`
+pub fn spawn_periodic_purge(interval_secs: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            purge().await;
+        }
+    });
+}
+
+/// Delete expired functions for good, with their data and project, returning them
+pub async fn purge() -> Vec<TrashedFunction> {
+    let (Some(trash), Some(server)) = (TRASH.get(), SERVER.get()) else {
+        return Vec::new();
+    };
+
+    let purged = trash.purge_expired().await;
+    for TrashedFunction { info, .. } in &purged {
+        if let Err(e) =
+            function_data::remove(server.metadata.as_ref(), &server.metadata_db, &info.name)
+        {
+            error!("Failed to remove data for function '{}': {}", info.name, e);
+        }
+        if let Err(e) = server
+            .github_auth
+            .remove_project(&info.owner, &info.name)
+            .await
+        {
+            error!("Failed to release project '{}': {}", info.name, e);
+        }
+        info!("Purged '{}' from trash", info.name);
+        journal::record(
+            EventSeverity::Info,
+            ServerEventKind::TrashPurged,
+            Some(&info.name),
+            format!(
+                "Deleted for good after {}h in the trash",
+                trash.retention().as_secs() / 3600
+            ),
+        );
+    }
+    purged
+}
+
+/// Storage key of a trashed function's WebAssembly
+pub fn trashed_key(name: &str) -> String {
+    format!("{TRASH_DIR}/{}", wasm_key(name))
+}
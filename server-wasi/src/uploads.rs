@@ -0,0 +1,522 @@
+//! Artifacts uploaded in chunks ahead of a publish.
+//!
+//! A publish that carries its WebAssembly inline is buffered whole and capped by
+//! the RPC frame size. Clients instead announce an upload's size, which is checked
+//! against the server's limit up front, and send it in chunks that are appended to
+//! a file in `.uploads/` as they arrive. A chunk running past the announced size is
+//! refused, so an oversized upload is never held in memory. Uploads that aren't
+//! finished within an hour are discarded, and at most 256 may be in progress, 16
+//! of them by any one user.
+//!
+//! Clients may send the offset each chunk starts at. A chunk resent because its
+//! reply was lost is then recognised as already stored and skipped, rather than
+//! appended twice; one that would leave a gap or overlap the stored bytes is
+//! refused.
+//!
+//! A delta upload starts from the artifact deployed under the function's name: it
+//! is copied next to the upload and split into content-defined chunks, whose
+//! digests the client compares with the chunks of the new artifact. Chunks both
+//! share are appended from the copy by digest, so only changed bytes are sent. The
+//! chunks of the last few artifacts started from are cached by their digest.
+
+use anyhow::{anyhow, Result};
+use dashmap::DashMap;
+use faasta_interface::{chunking, DeltaUpload};
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, error};
+
+/// Global uploads, set at startup
+pub static UPLOADS: OnceCell<Uploads> = OnceCell::new();
+
+/// Directory (inside the functions directory) holding uploads in progress
+const UPLOADS_DIR: &str = ".uploads";
+/// Seconds an upload may take before it is discarded
+const UPLOAD_TTL_SECS: i64 = 60 * 60;
+/// Most uploads in progress at once, of all users
+const MAX_ACTIVE_UPLOADS: usize = 256;
+/// Most uploads in progress at once for one owner
+const MAX_UPLOADS_PER_OWNER: usize = 16;
+/// How often expired uploads are discarded, in seconds
+const SWEEP_INTERVAL_SECS: u64 = 5 * 60;
+/// Most deployed artifacts whose chunks are kept for delta uploads
+const MAX_CACHED_BASES: usize = 16;
+
+/// Where each chunk of an artifact is in it, by digest
+type ChunkRanges = HashMap<String, Range<u64>>;
+
+/// Largest artifact accepted for publishing, in bytes
+pub fn max_artifact_bytes() -> u64 {
+    UPLOADS
+        .get()
+        .map_or(faasta_interface::MAX_WASM_SIZE as u64, Uploads::max_bytes)
+}
+
+/// As many uploads are in progress as the server allows
+#[derive(Debug)]
+pub struct UploadLimit {
+    pub quota: &'static str,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for UploadLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "at most {} {} allowed", self.limit, self.quota)
+    }
+}
+
+impl std::error::Error for UploadLimit {}
+
+/// A chunk sent at an offset that doesn't continue an upload
+#[derive(Debug)]
+pub struct UnexpectedOffset {
+    pub offset: u64,
+    /// Bytes received so far, where the next chunk starts
+    pub received: u64,
+}
+
+impl std::fmt::Display for UnexpectedOffset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the upload continues at byte {}, not {}",
+            self.received, self.offset
+        )
+    }
+}
+
+impl std::error::Error for UnexpectedOffset {}
+
+struct Upload {
+    owner: String,
+    /// Announced size in bytes
+    size: u64,
+    received: AtomicU64,
+    /// Unix timestamp (seconds) the upload started
+    started_at: i64,
+    /// Provenance document attached to the upload
+    provenance: Mutex<Option<Vec<u8>>>,
+    /// Chunks of the artifact a delta upload started from, in its copy
+    base_chunks: Arc<ChunkRanges>,
+    /// Held while bytes are appended, so appends to one upload happen in order
+    /// without the map of uploads being locked during file I/O
+    appending: Mutex<()>,
+}
+
+/// A finished upload, ready to publish
+pub struct FinishedUpload {
+    pub wasm: Vec<u8>,
+    pub provenance: Option<Vec<u8>>,
+}
+
+pub struct Uploads {
+    dir: PathBuf,
+    /// Largest artifact accepted, in bytes
+    max_bytes: u64,
+    active: DashMap<String, Arc<Upload>>,
+    /// Chunks of recently deployed artifacts, by the artifact's digest, so an
+    /// artifact is split once however many delta uploads start from it
+    base_chunks: DashMap<String, Arc<ChunkRanges>>,
+}
+
+impl Uploads {
+    pub fn new(functions_dir: &Path, max_bytes: u64) -> Result<Self> {
+        let dir = functions_dir.join(UPLOADS_DIR);
+        // Uploads interrupted by a restart can't be finished
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            max_bytes,
+            active: DashMap::new(),
+            base_chunks: DashMap::new(),
+        })
+    }
+
+    /// Largest artifact accepted, in bytes
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Run `f` on a blocking thread, as uploads are files
+    pub async fn blocking<T: Send + 'static>(
+        &'static self,
+        f: impl FnOnce(&Uploads) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        tokio::task::spawn_blocking(move || f(self)).await?
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.part"))
+    }
+
+    /// Copy of the artifact a delta upload started from
+    fn base_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.base"))
+    }
+
+    /// `owner`'s upload `id`
+    fn upload(&self, owner: &str, id: &str) -> Result<Arc<Upload>> {
+        self.active
+            .get(id)
+            .filter(|upload| upload.owner == owner)
+            .map(|upload| Arc::clone(&upload))
+            .ok_or_else(|| anyhow!("Upload '{id}' not found"))
+    }
+
+    /// Start an upload of `size` bytes for `owner`, returning its id
+    pub fn begin(&self, owner: &str, size: u64) -> Result<String> {
+        Ok(self.begin_delta(owner, size, None)?.upload_id)
+    }
+
+    /// Start an upload of `size` bytes for `owner` that may reuse chunks of `base`.
+    /// Fails with [`UploadLimit`] when too many uploads are in progress, in all or
+    /// for `owner`.
+    pub fn begin_delta(&self, owner: &str, size: u64, base: Option<&[u8]>) -> Result<DeltaUpload> {
+        self.discard_expired();
+        if self.active.len() >= MAX_ACTIVE_UPLOADS {
+            return Err(UploadLimit {
+                quota: "uploads in progress",
+                limit: MAX_ACTIVE_UPLOADS as u64,
+            }
+            .into());
+        }
+        let owned = self
+            .active
+            .iter()
+            .filter(|upload| upload.owner == owner)
+            .count();
+        if owned >= MAX_UPLOADS_PER_OWNER {
+            return Err(UploadLimit {
+                quota: "uploads in progress per user",
+                limit: MAX_UPLOADS_PER_OWNER as u64,
+            }
+            .into());
+        }
+        let id = format!("{:016x}", rand::thread_rng().gen::<u64>());
+        File::create(self.path(&id))?;
+        let base_chunks = match base {
+            Some(base) => {
+                fs::write(self.base_path(&id), base)?;
+                self.chunks_of(base)
+            }
+            None => Arc::default(),
+        };
+        let digests = base_chunks.keys().cloned().collect();
+        self.active.insert(
+            id.clone(),
+            Arc::new(Upload {
+                owner: owner.to_string(),
+                size,
+                received: AtomicU64::new(0),
+                started_at: chrono::Utc::now().timestamp(),
+                provenance: Mutex::new(None),
+                base_chunks,
+                appending: Mutex::new(()),
+            }),
+        );
+        Ok(DeltaUpload {
+            upload_id: id,
+            base_chunks: digests,
+        })
+    }
+
+    /// Chunks of `artifact`, split on first use and then cached
+    fn chunks_of(&self, artifact: &[u8]) -> Arc<ChunkRanges> {
+        let digest = chunking::digest(artifact);
+        if let Some(chunks) = self.base_chunks.get(&digest) {
+            return Arc::clone(&chunks);
+        }
+        let mut chunks = HashMap::new();
+        for range in chunking::chunks(artifact) {
+            chunks
+                .entry(chunking::digest(&artifact[range.clone()]))
+                .or_insert(range.start as u64..range.end as u64);
+        }
+        let chunks = Arc::new(chunks);
+        if self.base_chunks.len() >= MAX_CACHED_BASES {
+            // Any will do; it's split again if another upload starts from it
+            let evicted = self
+                .base_chunks
+                .iter()
+                .next()
+                .map(|entry| entry.key().clone());
+            if let Some(evicted) = evicted {
+                self.base_chunks.remove(&evicted);
+            }
+        }
+        self.base_chunks.insert(digest, chunks.clone());
+        chunks
+    }
+
+    /// Bytes still expected by `owner`'s upload `id`, if there is one
+    pub fn remaining(&self, owner: &str, id: &str) -> Option<u64> {
+        self.active
+            .get(id)
+            .filter(|upload| upload.owner == owner)
+            .map(|upload| upload.size - upload.received.load(Ordering::Acquire))
+    }
+
+    /// Append the next chunk of `owner`'s upload `id`, which starts at `offset` if
+    /// the client says so
+    pub fn append(&self, owner: &str, id: &str, offset: Option<u64>, chunk: &[u8]) -> Result<()> {
+        let upload = self.upload(owner, id)?;
+        let _appending = upload.appending.lock().unwrap();
+        let received = upload.received.load(Ordering::Acquire);
+        if is_resent(offset, chunk.len() as u64, received)? {
+            return Ok(());
+        }
+        if received + chunk.len() as u64 > upload.size {
+            return Err(anyhow!("Upload '{id}' runs past its announced size"));
+        }
+        OpenOptions::new()
+            .append(true)
+            .open(self.path(id))?
+            .write_all(chunk)?;
+        upload
+            .received
+            .store(received + chunk.len() as u64, Ordering::Release);
+        Ok(())
+    }
+
+    /// Append chunks of the artifact `owner`'s delta upload `id` started from, by
+    /// digest, the first starting at `offset` if the client says so
+    pub fn append_known(
+        &self,
+        owner: &str,
+        id: &str,
+        offset: Option<u64>,
+        digests: &[String],
+    ) -> Result<()> {
+        let upload = self.upload(owner, id)?;
+        let ranges = digests
+            .iter()
+            .map(|digest| {
+                upload
+                    .base_chunks
+                    .get(digest)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Upload '{id}' has no chunk '{digest}'"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let len: u64 = ranges.iter().map(|range| range.end - range.start).sum();
+        let _appending = upload.appending.lock().unwrap();
+        let received = upload.received.load(Ordering::Acquire);
+        if is_resent(offset, len, received)? {
+            return Ok(());
+        }
+        if received + len > upload.size {
+            return Err(anyhow!("Upload '{id}' runs past its announced size"));
+        }
+        let mut base = File::open(self.base_path(id))?;
+        let mut part = OpenOptions::new().append(true).open(self.path(id))?;
+        let mut chunk = Vec::new();
+        for range in ranges {
+            chunk.resize((range.end - range.start) as usize, 0);
+            base.seek(SeekFrom::Start(range.start))?;
+            base.read_exact(&mut chunk)?;
+            part.write_all(&chunk)?;
+        }
+        upload.received.store(received + len, Ordering::Release);
+        Ok(())
+    }
+
+    /// Attach a provenance document to `owner`'s upload `id`, replacing any
+    /// attached before
+    pub fn attach_provenance(&self, owner: &str, id: &str, document: Vec<u8>) -> Result<()> {
+        let upload = self.upload(owner, id)?;
+        *upload.provenance.lock().unwrap() = Some(document);
+        Ok(())
+    }
+
+    /// Take `owner`'s finished upload `id`. `None` if there is no such upload or it
+    /// is still missing bytes.
+    pub fn finish(&self, owner: &str, id: &str) -> Result<Option<FinishedUpload>> {
+        let Ok(upload) = self.upload(owner, id) else {
+            return Ok(None);
+        };
+        {
+            let _appending = upload.appending.lock().unwrap();
+            if upload.received.load(Ordering::Acquire) != upload.size
+                || self
+                    .active
+                    .remove_if(id, |_, active| Arc::ptr_eq(active, &upload))
+                    .is_none()
+            {
+                return Ok(None);
+            }
+        }
+        let wasm = fs::read(self.path(id))?;
+        self.remove_files(id);
+        let provenance = upload.provenance.lock().unwrap().take();
+        Ok(Some(FinishedUpload { wasm, provenance }))
+    }
+
+    /// Drop `owner`'s upload `id` and whatever was received of it
+    pub fn discard(&self, owner: &str, id: &str) {
+        if self
+            .active
+            .remove_if(id, |_, upload| upload.owner == owner)
+            .is_some()
+        {
+            self.remove_files(id);
+        }
+    }
+
+    fn remove_files(&self, id: &str) {
+        let _ = fs::remove_file(self.path(id));
+        let _ = fs::remove_file(self.base_path(id));
+    }
+
+    /// Discard the uploads that weren't finished in time
+    pub fn discard_expired(&self) {
+        let cutoff = chrono::Utc::now().timestamp() - UPLOAD_TTL_SECS;
+        self.active.retain(|id, upload| {
+            if upload.started_at >= cutoff {
+                return true;
+            }
+            debug!("Discarding expired upload '{}'", id);
+            self.remove_files(id);
+            false
+        });
+    }
+}
+
+/// Whether `len` bytes at `offset` were stored already, `received` bytes in.
+/// Fails with [`UnexpectedOffset`] if they would leave a gap or only partly
+/// overlap what was stored.
+fn is_resent(offset: Option<u64>, len: u64, received: u64) -> Result<bool> {
+    match offset {
+        None => Ok(false),
+        Some(offset) if offset == received => Ok(false),
+        Some(offset) if offset.checked_add(len).is_some_and(|end| end <= received) => Ok(true),
+        Some(offset) => Err(UnexpectedOffset { offset, received }.into()),
+    }
+}
+
+/// Discard expired uploads every few minutes, as they are otherwise only
+/// discarded when another upload starts
+pub fn spawn_periodic_sweep() {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(SWEEP_INTERVAL_SECS));
+        loop {
+            ticker.tick().await;
+            let Some(uploads) = UPLOADS.get() else {
+                continue;
+            };
+            let swept = uploads
+                .blocking(|uploads| {
+                    uploads.discard_expired();
+                    Ok(())
+                })
+                .await;
+            if let Err(e) = swept {
+                error!("Failed to discard expired uploads: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_uploads_reuse_unchanged_chunks() {
+        let dir = std::env::temp_dir().join(format!("faasta-uploads-{}", rand::random::<u64>()));
+        let uploads = Uploads::new(&dir, 1 << 20).unwrap();
+
+        let mut state = 1u64;
+        let base: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect();
+        let mut new = base.clone();
+        new.splice(100_000..100_000, b"inserted".iter().copied());
+
+        let delta = uploads
+            .begin_delta("alice", new.len() as u64, Some(&base))
+            .unwrap();
+        let chunks = chunking::chunks(&new);
+        // An insertion only changes the chunks around it
+        let known: Vec<bool> = chunks
+            .iter()
+            .map(|range| {
+                delta
+                    .base_chunks
+                    .contains(&chunking::digest(&new[range.clone()]))
+            })
+            .collect();
+        assert!(known.iter().filter(|known| !**known).count() <= 2);
+
+        let id = &delta.upload_id;
+        for (range, known) in chunks.into_iter().zip(known) {
+            if known {
+                let digest = chunking::digest(&new[range]);
+                uploads.append_known("alice", id, None, &[digest]).unwrap();
+            } else {
+                uploads.append("alice", id, None, &new[range]).unwrap();
+            }
+        }
+        assert!(uploads
+            .append_known("alice", id, None, &["0".repeat(64)])
+            .is_err());
+        let finished = uploads.finish("alice", id).unwrap().unwrap();
+        assert_eq!(finished.wasm, new);
+        assert!(!uploads.base_path(id).exists());
+
+        // Another upload from the same artifact reuses its chunks
+        let again = uploads.begin_delta("bob", 1, Some(&base)).unwrap();
+        assert_eq!(again.base_chunks.len(), delta.base_chunks.len());
+        assert_eq!(uploads.base_chunks.len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resent_chunks_are_stored_once() {
+        let dir = std::env::temp_dir().join(format!("faasta-uploads-{}", rand::random::<u64>()));
+        let uploads = Uploads::new(&dir, 1 << 20).unwrap();
+        let id = uploads.begin("alice", 10).unwrap();
+        uploads.append("alice", &id, Some(0), b"hello").unwrap();
+        // The reply was lost, so the client sends the chunk again
+        uploads.append("alice", &id, Some(0), b"hello").unwrap();
+        assert_eq!(uploads.remaining("alice", &id), Some(5));
+        let gap = uploads.append("alice", &id, Some(7), b"abc").unwrap_err();
+        assert_eq!(gap.downcast_ref::<UnexpectedOffset>().unwrap().received, 5);
+        assert!(uploads.append("alice", &id, Some(3), b"lo wo").is_err());
+        uploads.append("alice", &id, Some(5), b"world").unwrap();
+        let finished = uploads.finish("alice", &id).unwrap().unwrap();
+        assert_eq!(finished.wasm, b"helloworld");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_each_owner_has_a_share_of_uploads() {
+        let dir = std::env::temp_dir().join(format!("faasta-uploads-{}", rand::random::<u64>()));
+        let uploads = Uploads::new(&dir, 1 << 20).unwrap();
+        for _ in 0..MAX_UPLOADS_PER_OWNER {
+            uploads.begin("alice", 1).unwrap();
+        }
+        let refused = uploads.begin("alice", 1).unwrap_err();
+        assert_eq!(
+            refused.downcast_ref::<UploadLimit>().unwrap().limit,
+            MAX_UPLOADS_PER_OWNER as u64
+        );
+        uploads.begin("bob", 1).unwrap();
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+//! Usage accounting per account and billing period.
+//!
+//! Each account's usage in a billing period (calendar month, UTC) is one
+//! [`UsageRecord`] in sled: requests and wall-clock compute time folded in from
+//! the function metrics, response bytes its functions sent, and the most artifact
+//! storage its functions took up. Records are brought up to date periodically and
+//! before every billing export, whether or not a billing provider is configured,
+//! and admins export a period as CSV or JSON for their own invoicing.
+
+use anyhow::{anyhow, bail, Result};
+use dashmap::DashMap;
+use faasta_interface::{UsageFormat, UsageRecord};
+use http_body_util::BodyExt;
+use hyper::Response;
+use once_cell::sync::OnceCell;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{error, info};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+
+use crate::metrics::get_metrics;
+use crate::storage::wasm_key;
+use crate::wasi_server::SERVER;
+
+/// Sled tree holding usage records, keyed by `period/username`
+const USAGE_TREE: &str = "billing_usage";
+/// Sled tree holding the cumulative metrics already accounted for, keyed by function name
+const CURSOR_TREE: &str = "billing_cursor";
+
+/// Global usage ledger, set at startup
+pub static USAGE: OnceCell<UsageLedger> = OnceCell::new();
+
+pub struct UsageLedger {
+    usage: sled::Tree,
+    cursors: sled::Tree,
+    /// Response bytes each function sent since usage was last accumulated
+    egress: DashMap<String, Arc<AtomicU64>>,
+}
+
+impl UsageLedger {
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            usage: db.open_tree(USAGE_TREE)?,
+            cursors: db.open_tree(CURSOR_TREE)?,
+            egress: DashMap::new(),
+        })
+    }
+
+    /// Count the response body bytes of a function as they are sent
+    pub fn meter_egress(
+        &self,
+        function_name: &str,
+        response: Response<HyperOutgoingBody>,
+    ) -> Response<HyperOutgoingBody> {
+        let counter = self
+            .egress
+            .entry(function_name.to_string())
+            .or_default()
+            .clone();
+        response.map(|body| {
+            body.map_frame(move |frame| {
+                if let Some(data) = frame.data_ref() {
+                    counter.fetch_add(data.len() as u64, Ordering::Relaxed);
+                }
+                frame
+            })
+            .boxed()
+        })
+    }
+
+    /// Carry a function's usage cursor over to its new name, so a rename
+    /// doesn't bill its existing metrics a second time
+    pub fn rename_function(&self, old_name: &str, new_name: &str) -> Result<()> {
+        if let Some(cursor) = self.cursors.remove(old_name.as_bytes())? {
+            self.cursors.insert(new_name.as_bytes(), cursor)?;
+        }
+        if let Some((_, egress)) = self.egress.remove(old_name) {
+            self.egress
+                .entry(new_name.to_string())
+                .or_default()
+                .fetch_add(egress.swap(0, Ordering::Relaxed), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Fold usage since the last run into the current period's records.
+    /// Returns the up-to-date records for the current period.
+    pub async fn accumulate(&self) -> Result<Vec<UsageRecord>> {
+        let server = SERVER
+            .get()
+            .ok_or_else(|| anyhow!("Server not initialised"))?;
+        let period = current_period();
+        let metrics = get_metrics();
+        let by_function: HashMap<_, _> = metrics
+            .function_metrics
+            .iter()
+            .map(|m| (m.function_name.as_str(), m))
+            .collect();
+
+        let mut records = Vec::new();
+        for user in server.github_auth.all_users()? {
+            let mut record = self
+                .get_record(&period, &user.github_username)?
+                .unwrap_or_else(|| UsageRecord {
+                    username: user.github_username.clone(),
+                    period: period.clone(),
+                    ..Default::default()
+                });
+
+            let mut storage_bytes = 0;
+            for project in &user.projects {
+                if let Some(egress) = self.egress.get(project) {
+                    record.egress_bytes += egress.swap(0, Ordering::Relaxed);
+                }
+                match server.storage.size(&wasm_key(project)).await {
+                    Ok(size) => storage_bytes += size.unwrap_or(0),
+                    Err(e) => error!("Failed to measure the artifact of '{}': {}", project, e),
+                }
+
+                let Some(metric) = by_function.get(project.as_str()) else {
+                    continue;
+                };
+                let (seen_calls, seen_millis) = self
+                    .cursors
+                    .get(project.as_bytes())?
+                    .and_then(|bytes| {
+                        bincode::decode_from_slice::<(u64, u64), _>(
+                            &bytes,
+                            bincode::config::standard(),
+                        )
+                        .ok()
+                    })
+                    .map(|(cursor, _)| cursor)
+                    .unwrap_or((0, 0));
+
+                // Counters only go backwards if the function was removed and re-created
+                let (new_calls, new_millis) = if metric.call_count < seen_calls {
+                    (metric.call_count, metric.total_time_millis)
+                } else {
+                    (
+                        metric.call_count - seen_calls,
+                        metric.total_time_millis.saturating_sub(seen_millis),
+                    )
+                };
+                record.call_count += new_calls;
+                record.compute_millis += new_millis;
+
+                let cursor = bincode::encode_to_vec(
+                    (metric.call_count, metric.total_time_millis),
+                    bincode::config::standard(),
+                )?;
+                self.cursors.insert(project.as_bytes(), cursor)?;
+            }
+            record.storage_bytes = record.storage_bytes.max(storage_bytes);
+
+            self.usage.insert(
+                format!("{period}/{}", record.username).as_bytes(),
+                bincode::encode_to_vec(&record, bincode::config::standard())?,
+            )?;
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+
+    /// Every account's record of `period`, ordered by username
+    pub fn records(&self, period: &str) -> Result<Vec<UsageRecord>> {
+        self.usage
+            .scan_prefix(format!("{period}/").as_bytes())
+            .map(|entry| decode_record(&entry?.1))
+            .collect()
+    }
+
+    fn get_record(&self, period: &str, username: &str) -> Result<Option<UsageRecord>> {
+        self.usage
+            .get(format!("{period}/{username}").as_bytes())?
+            .map(|bytes| decode_record(&bytes))
+            .transpose()
+    }
+}
+
+/// Decode a usage record, including those stored before egress and storage were
+/// accounted for
+fn decode_record(bytes: &[u8]) -> Result<UsageRecord> {
+    if let Ok((record, _)) = bincode::decode_from_slice(bytes, bincode::config::standard()) {
+        return Ok(record);
+    }
+    let ((username, period, call_count, compute_millis), _) =
+        bincode::decode_from_slice::<(String, String, u64, u64), _>(
+            bytes,
+            bincode::config::standard(),
+        )?;
+    Ok(UsageRecord {
+        username,
+        period,
+        call_count,
+        compute_millis,
+        ..Default::default()
+    })
+}
+
+/// Current billing period label
+pub fn current_period() -> String {
+    chrono::Utc::now().format("%Y-%m").to_string()
+}
+
+/// Check that `period` is a billing period label, `YYYY-MM`
+pub fn parse_period(period: &str) -> Result<()> {
+    if period.len() != 7
+        || chrono::NaiveDate::parse_from_str(&format!("{period}-01"), "%Y-%m-%d").is_err()
+    {
+        bail!("Invalid billing period '{period}', expected YYYY-MM");
+    }
+    Ok(())
+}
+
+/// Render usage records as `format`
+pub fn render(records: &[UsageRecord], format: UsageFormat) -> Result<String> {
+    Ok(match format {
+        UsageFormat::Json => serde_json::to_string_pretty(records)?,
+        UsageFormat::Csv => {
+            let mut csv =
+                "period,username,requests,compute_ms,egress_bytes,storage_bytes\n".to_string();
+            for record in records {
+                writeln!(
+                    csv,
+                    "{},{},{},{},{},{}",
+                    csv_field(&record.period),
+                    csv_field(&record.username),
+                    record.call_count,
+                    record.compute_millis,
+                    record.egress_bytes,
+                    record.storage_bytes
+                )?;
+            }
+            csv
+        }
+    })
+}
+
+/// `field` as a CSV field, quoted if it holds a comma, quote or line break
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Spawn a Tokio task that accumulates usage every `interval_secs` seconds
+pub fn spawn_periodic_accounting(interval_secs: u64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            if let Some(usage) = USAGE.get() {
+                match usage.accumulate().await {
+                    Ok(records) => info!("Accounted usage of {} accounts", records.len()),
+                    Err(e) => error!("Failed to account usage: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_stored_before_egress_still_decode() {
+        let legacy = bincode::encode_to_vec(
+            ("octocat".to_string(), "2026-09".to_string(), 12u64, 340u64),
+            bincode::config::standard(),
+        )
+        .unwrap();
+        let record = decode_record(&legacy).unwrap();
+        assert_eq!((record.call_count, record.compute_millis), (12, 340));
+        assert_eq!(record.egress_bytes, 0);
+
+        let csv = render(&[record], UsageFormat::Csv).unwrap();
+        assert_eq!(
+            csv,
+            "period,username,requests,compute_ms,egress_bytes,storage_bytes\n\
+             2026-09,octocat,12,340,0,0\n"
+        );
+        assert_eq!(csv_field("acme, inc"), "\"acme, inc\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert!(parse_period("2026-09").is_ok());
+        assert!(parse_period("2026-13").is_err());
+        assert!(parse_period("2026-9").is_err());
+    }
+}
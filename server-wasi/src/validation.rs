@@ -0,0 +1,53 @@
+//! Publish-time checks that an upload can actually be served.
+//!
+//! Functions run as `wasi:http/proxy` components. Instead of failing on the first
+//! request, publishing inspects the component's imports and exports and rejects it
+//! with a list of everything that doesn't fit the world. Besides WASI, functions
+//! may import the platform's own interfaces, such as `faasta:webhook`. The rules
+//! are shared with the CLI, which checks components before uploading them.
+
+use faasta_interface::components::{ComponentRules, Export};
+use wasmtime::component::types::ComponentItem;
+use wasmtime::component::Component;
+use wasmtime::Engine;
+use wasmtime_wasi_http::bindings::ProxyPre;
+
+use crate::wasi_server::shared_linker;
+
+/// Check a precompiled component against the `wasi:http/proxy` world, returning
+/// the interfaces it imports, or every problem found
+pub fn check_proxy_component(engine: &Engine, cwasm: &[u8]) -> Result<Vec<String>, Vec<String>> {
+    // SAFETY: the artifact was just produced by `engine.precompile_component`
+    let component = unsafe { Component::deserialize(engine, cwasm) }
+        .map_err(|e| vec![format!("failed to load component: {e}")])?;
+    let ty = component.component_type();
+
+    let exports: Vec<(String, Export)> = ty
+        .exports(engine)
+        .map(|(name, item)| {
+            let export = match item {
+                ComponentItem::ComponentInstance(instance) => Export::Interface(
+                    instance
+                        .exports(engine)
+                        .filter(|(_, item)| matches!(item, ComponentItem::ComponentFunc(_)))
+                        .map(|(func, _)| func.to_string())
+                        .collect(),
+                ),
+                _ => Export::Other,
+            };
+            (name.to_string(), export)
+        })
+        .collect();
+    let imports: Vec<String> = ty
+        .imports(engine)
+        .map(|(name, _)| name.to_string())
+        .collect();
+    ComponentRules::current().check(&exports, &imports)?;
+
+    // Whatever else the linker can't satisfy, such as mismatched signatures
+    shared_linker(engine)
+        .instantiate_pre(&component)
+        .and_then(ProxyPre::new)
+        .map_err(|e| vec![format!("{e:#}")])?;
+    Ok(imports)
+}
@@ -1,25 +1,66 @@
 use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
-use dashmap::DashMap;
 use http_body_util::{BodyExt, Full};
 use hyper::{header::HOST, Method, Request, Response};
 use once_cell::sync::OnceCell;
-use std::{path::PathBuf, time::Instant};
-use tracing::{debug, error, info};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use wasmtime::{
     component::{Component, Linker, ResourceTable},
-    Engine, Store,
+    Engine, ResourceLimiter, Store, StoreLimits, StoreLimitsBuilder,
 };
 use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiView};
-use wasmtime_wasi_http::bindings::http::types::{ErrorCode, Scheme};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
 use wasmtime_wasi_http::bindings::ProxyPre;
 use wasmtime_wasi_http::body::HyperOutgoingBody;
 use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
 
+use crate::access_keys::ACCESS_KEYS;
+use crate::aliases::ALIASES;
+use crate::anomalies::ANOMALIES;
+use crate::artifact_cache::ArtifactCache;
+use crate::auth_provider::AuthProvider;
+use crate::bot_signals::ClientSignals;
+use crate::canary::{Canaries, CANARY_SUFFIX};
+use crate::capacity::CAPACITY;
+use crate::circuit_breaker::open_breaker;
+use crate::component_cache::ComponentCache;
+use crate::compression;
+use crate::cors::{self, CORS};
+use crate::events::{self, PlatformEvent};
 use crate::github_auth::GitHubAuth;
-use crate::metrics::Timer;
+use crate::instance_pool::{WarmInstance, INSTANCE_POOL};
+use crate::jwt_auth::{AuthFailure, JWT_AUTH};
+use crate::logs::{OutputCapture, LOGS};
+use crate::metadata_store::MetadataStore;
+use crate::metrics::{InvocationErrors, Timer};
+use crate::profiling::{self, PROFILING};
+use crate::readiness;
+use crate::redirects::Redirects;
+use crate::route_handlers::RouteHandlers;
 use crate::rpc_service;
-use faasta_interface::FunctionService;
+use crate::server_config::Reloadable;
+use crate::shutdown::SHUTDOWN;
+use crate::signing::{self, ResponseSigning};
+use crate::snapshots::{CapturingBody, SNAPSHOTS};
+use crate::specs::{self, Specs};
+use crate::static_assets::STATIC_ASSETS;
+use crate::storage::ArtifactStorage;
+use crate::streaming::{self, BodyFault, BodyFaults, BODY_LIMITS};
+use crate::suspensions::Suspensions;
+use crate::telemetry;
+use crate::transforms::{self, TRANSFORMS};
+use crate::uploads::max_artifact_bytes;
+use crate::usage::USAGE;
+use crate::webhooks;
+use faasta_interface::{
+    AliasMode, FaastaError, FunctionService, FunctionSpec, LogQuery, WarmState,
+};
 
 // Global server reference for cache management
 pub static SERVER: OnceCell<FaastaServer> = OnceCell::new();
@@ -29,15 +70,57 @@ pub struct FaastaClientState {
     pub table: ResourceTable,
     pub wasi: WasiCtx,
     pub http: WasiHttpCtx,
+    pub limits: StoreLimits,
+    /// Linear memory the instance's memories have grown to, in bytes
+    pub memory_bytes: usize,
+    /// Function the store runs, for host APIs acting on its behalf
+    pub function_name: String,
+    /// Profiler sampling the guest, for profiled invocations
+    pub profiler: Option<crate::profiling::Sampler>,
+}
+
+tokio::task_local! {
+    /// Id of the HTTP request being handled, set by [`FaastaServer::serve_request`]
+    static REQUEST_ID: String;
+}
+
+/// Id of the HTTP request the current task is handling, if any
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
 }
 
 pub static SHARED_LINKER: OnceCell<Linker<FaastaClientState>> = OnceCell::new();
 pub static STORE_TEMPLATE_CTX: OnceCell<Box<dyn Fn() -> FaastaClientState + Send + Sync>> =
     OnceCell::new();
 
+/// The linker functions are instantiated with, providing WASI and WASI-HTTP
+pub fn shared_linker(engine: &Engine) -> &'static Linker<FaastaClientState> {
+    SHARED_LINKER.get_or_init(|| {
+        info!("Initializing shared linker (first time)");
+        build_linker(engine)
+    })
+}
+
+/// A linker for `engine` providing WASI, WASI-HTTP and the faasta host APIs
+pub fn build_linker(engine: &Engine) -> Linker<FaastaClientState> {
+    let mut linker = Linker::new(engine);
+
+    wasmtime_wasi::add_to_linker_async(&mut linker).expect("Failed to add WASI to linker");
+    wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)
+        .expect("Failed to add WASI-HTTP to linker");
+    webhooks::add_to_linker(&mut linker).expect("Failed to add faasta:webhook to linker");
+
+    linker
+}
+
 // Helper function to create text responses
 pub fn text_response(status_code: u16, message: &str) -> Result<Response<HyperOutgoingBody>> {
-    let body = Full::new(Bytes::from(message.to_string()))
+    // Error pages name the request, for users to quote when asking for help
+    let message = match current_request_id() {
+        Some(id) if status_code >= 400 => format!("{message}\n\nRequest ID: {id}\n"),
+        _ => message.to_string(),
+    };
+    let body = Full::new(Bytes::from(message))
         .map_err(|_| ErrorCode::InternalError(None))
         .boxed();
 
@@ -47,6 +130,72 @@ pub fn text_response(status_code: u16, message: &str) -> Result<Response<HyperOu
         .body(HyperOutgoingBody::new(body))?)
 }
 
+/// JSON error response for a failed call made over HTTP
+pub fn error_response(err: &FaastaError) -> Result<Response<HyperOutgoingBody>> {
+    let status_code = match err {
+        FaastaError::AuthFailed(_) => 401,
+        FaastaError::QuotaExceeded { .. } => 403,
+        FaastaError::InvalidComponent { .. } => 422,
+        FaastaError::NotFound(_) => 404,
+        FaastaError::PermissionDenied(_) => 403,
+        FaastaError::InvalidInput(_) => 400,
+        FaastaError::Internal { .. } => 500,
+        FaastaError::TooLarge { .. } => 413,
+    };
+
+    let mut json = serde_json::json!({
+        "success": false,
+        "kind": err.kind(),
+        "error": err.to_string()
+    });
+    match err {
+        FaastaError::TooLarge { limit, .. } | FaastaError::QuotaExceeded { limit, .. } => {
+            json["limit"] = (*limit).into();
+        }
+        FaastaError::InvalidComponent { details } => json["details"] = details.clone().into(),
+        FaastaError::Internal { id } => json["id"] = id.clone().into(),
+        _ => {}
+    }
+    json_error_response(status_code, json)
+}
+
+/// JSON error response for a request the server refused on its own, in the shape
+/// of [`error_response`], with the `limit` it broke
+pub fn request_error_response(
+    status_code: u16,
+    kind: &str,
+    error: &str,
+    limit: u64,
+) -> Result<Response<HyperOutgoingBody>> {
+    json_error_response(
+        status_code,
+        serde_json::json!({
+            "success": false,
+            "kind": kind,
+            "error": error,
+            "limit": limit
+        }),
+    )
+}
+
+fn json_error_response(
+    status_code: u16,
+    mut json: serde_json::Value,
+) -> Result<Response<HyperOutgoingBody>> {
+    if let Some(id) = current_request_id() {
+        json["request_id"] = id.into();
+    }
+
+    let body = Full::new(Bytes::from(json.to_string()))
+        .map_err(|_| ErrorCode::InternalError(None))
+        .boxed();
+
+    Ok(Response::builder()
+        .status(status_code)
+        .header("Content-Type", "application/json")
+        .body(HyperOutgoingBody::new(body))?)
+}
+
 // Helper function to redirect to the main website
 pub fn redirect_to_website() -> Result<Response<HyperOutgoingBody>> {
     let body = Full::new(Bytes::from("Redirecting to website..."))
@@ -59,6 +208,139 @@ pub fn redirect_to_website() -> Result<Response<HyperOutgoingBody>> {
         .body(HyperOutgoingBody::new(body))?)
 }
 
+/// Permanent redirect that keeps the request method (used for renamed functions)
+fn redirect_response(location: &str) -> Result<Response<HyperOutgoingBody>> {
+    redirect_with_status(308, location)
+}
+
+/// Redirect to `location` with `status`, such as the 301 or 302 of an alias
+fn redirect_with_status(status: u16, location: &str) -> Result<Response<HyperOutgoingBody>> {
+    let body = Full::new(Bytes::from(format!("Moved to {location}")))
+        .map_err(|_| ErrorCode::InternalError(None))
+        .boxed();
+
+    Ok(Response::builder()
+        .status(status)
+        .header("Location", location)
+        .body(HyperOutgoingBody::new(body))?)
+}
+
+/// Read a request body holding WebAssembly, refusing it as soon as it outgrows
+/// the artifact size limit
+pub async fn read_artifact_body(
+    req: Request<hyper::body::Incoming>,
+) -> Result<Vec<u8>, FaastaError> {
+    let limit = max_artifact_bytes();
+    let declared_size = req
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if let Some(size) = declared_size.filter(|size| *size > limit) {
+        return Err(FaastaError::TooLarge { size, limit });
+    }
+    let body = http_body_util::Limited::new(req.into_body(), limit as usize);
+    let wasm_bytes = match BodyExt::collect(body).await {
+        Ok(collected) => collected.to_bytes().to_vec(),
+        Err(e) if e.is::<http_body_util::LengthLimitError>() => {
+            return Err(FaastaError::TooLarge {
+                size: declared_size.unwrap_or(limit + 1),
+                limit,
+            });
+        }
+        Err(e) => {
+            error!("Failed to read request body: {}", e);
+            return Err(FaastaError::InvalidInput(
+                "Failed to read request body".to_string(),
+            ));
+        }
+    };
+
+    // Validate WASM bytes aren't empty
+    if wasm_bytes.is_empty() {
+        return Err(FaastaError::InvalidInput("Empty WASM file".to_string()));
+    }
+    Ok(wasm_bytes)
+}
+
+/// Token from a request's `Authorization` header, with any `Bearer ` prefix removed
+pub fn authorization_token<B>(req: &Request<B>) -> Result<String, &'static str> {
+    let value = req
+        .headers()
+        .get("Authorization")
+        .ok_or("Missing Authorization header")?;
+    let token = value
+        .to_str()
+        .map_err(|_| "Invalid Authorization header format")?
+        .trim_start_matches("Bearer ");
+    if token.is_empty() {
+        return Err("Empty Authorization token");
+    }
+    Ok(token.to_string())
+}
+
+/// Address of the client that sent a request, when the listener recorded it
+pub fn peer_ip<B>(req: &Request<B>) -> Option<IpAddr> {
+    req.extensions().get::<SocketAddr>().map(|addr| addr.ip())
+}
+
+/// Log query from the query string of `GET /v1/logs/{function}`. Times are RFC 3339
+/// or Unix milliseconds.
+fn log_query(query_string: &str) -> Result<LogQuery, FaastaError> {
+    let invalid = |name: &str, value: &str| {
+        FaastaError::InvalidInput(format!("Invalid value '{value}' for '{name}'"))
+    };
+    let time = |name: &str, value: &str| {
+        value
+            .parse::<i64>()
+            .ok()
+            .or_else(|| {
+                chrono::DateTime::parse_from_rfc3339(value)
+                    .ok()
+                    .map(|time| time.timestamp_millis())
+            })
+            .ok_or_else(|| invalid(name, value))
+    };
+
+    let mut query = LogQuery {
+        limit: 100,
+        ..LogQuery::default()
+    };
+    for (name, value) in url::form_urlencoded::parse(query_string.as_bytes()) {
+        match name.as_ref() {
+            "since" => query.since = Some(time(&name, &value)?),
+            "until" => query.until = Some(time(&name, &value)?),
+            "level" => query.min_level = Some(value.parse().map_err(|_| invalid(&name, &value))?),
+            "request_id" => query.request_id = Some(value.into_owned()),
+            "grep" => query.grep = Some(value.into_owned()),
+            "cursor" => query.cursor = Some(value.into_owned()),
+            "limit" => query.limit = value.parse().map_err(|_| invalid(&name, &value))?,
+            _ => {}
+        }
+    }
+    Ok(query)
+}
+
+/// Id a request's log lines are stored under, always chosen by the server so
+/// callers can't forge or collide with another request's id
+fn new_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Longest a request may wait for its function's response
+const MAX_REQUEST_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How long to wait for the function's response: the time the caller said it still
+/// waits (in `X-Faasta-Timeout-Ms`), at most [`MAX_REQUEST_TIMEOUT`]
+fn request_timeout<B>(req: &Request<B>) -> Duration {
+    req.headers()
+        .get("x-faasta-timeout-ms")
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .map(Duration::from_millis)
+        .map_or(MAX_REQUEST_TIMEOUT, |timeout| {
+            timeout.min(MAX_REQUEST_TIMEOUT)
+        })
+}
+
 impl wasmtime_wasi::IoView for FaastaClientState {
     fn table(&mut self) -> &mut ResourceTable {
         &mut self.table
@@ -77,42 +359,294 @@ impl WasiHttpView for FaastaClientState {
     }
 }
 
+// Enforce the store's limits, measuring the memory it grows to along the way
+impl ResourceLimiter for FaastaClientState {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        let allowed = self.limits.memory_growing(current, desired, maximum)?;
+        if allowed {
+            self.memory_bytes += desired.saturating_sub(current);
+        }
+        Ok(allowed)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
+
 // Server state
 pub struct FaastaServer {
     pub engine: Engine,
     pub metadata_db: sled::Db,
-    pre_cache: DashMap<String, ProxyPre<FaastaClientState>>,
-    pub base_domain: String,
+    /// Users and function metadata, in sled or shared with other nodes
+    pub metadata: Arc<dyn MetadataStore>,
+    /// Pre-instantiated components, the least recently used dropped past the budget
+    pre_cache: ComponentCache,
+    /// Domain functions are served under, changed by config reloads
+    pub base_domain: Reloadable<String>,
     pub functions_dir: PathBuf,
+    /// Where published WebAssembly is kept
+    pub storage: Arc<dyn ArtifactStorage>,
     pub github_auth: GitHubAuth,
+    /// Redirects from the old names of renamed functions
+    pub redirects: Redirects,
+    /// Accounts suspended by an admin
+    pub suspensions: Suspensions,
+    /// Canary versions receiving part of their function's traffic
+    pub canaries: Canaries,
+    /// Components serving routes of functions instead of their main component
+    pub routes: RouteHandlers,
+    /// Precompiled functions kept on local disk
+    pub artifacts: ArtifactCache,
+    /// Environment, routes and limits of functions managed with `apply`
+    pub specs: Specs,
+    /// Keys of functions whose responses are signed
+    pub signing: ResponseSigning,
+}
+
+/// Store state of a new instance of `function_name`, with the environment and
+/// memory limit of its spec, and the captures its output is logged from
+pub(crate) fn client_state(
+    function_name: &str,
+    spec: Option<&FunctionSpec>,
+) -> (FaastaClientState, OutputCapture, OutputCapture) {
+    // Initialize a store template function if not already done
+    let store_template = STORE_TEMPLATE_CTX.get_or_init(|| {
+        // This template function will be used to create a similarly configured store each time
+        Box::new(move || FaastaClientState {
+            table: ResourceTable::new(),
+            wasi: WasiCtxBuilder::new().inherit_stdio().build(),
+            http: WasiHttpCtx::new(),
+            limits: StoreLimits::default(),
+            memory_bytes: 0,
+            function_name: String::new(),
+            profiler: None,
+        })
+    });
+
+    // Use the template to create a store with similar configuration
+    let mut client_state = store_template();
+    client_state.function_name = function_name.to_string();
+
+    // Update environment for this specific function, capturing its output for the logs
+    let stdout = OutputCapture::default();
+    let stderr = OutputCapture::default();
+    let mut wasi = WasiCtxBuilder::new();
+    if let Some(spec) = spec {
+        for (key, value) in &spec.env {
+            wasi.env(key, value);
+        }
+        if let Some(memory_mb) = spec.limits.memory_mb {
+            client_state.limits = StoreLimitsBuilder::new()
+                .memory_size(memory_mb as usize * 1024 * 1024)
+                .build();
+        }
+    }
+    client_state.wasi = wasi
+        .env("FUNCTION_NAME", function_name)
+        .stdout(stdout.clone())
+        .stderr(stderr.clone())
+        .build();
+    (client_state, stdout, stderr)
 }
 
 impl FaastaServer {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         engine: Engine,
         metadata_db: sled::Db,
+        metadata: Arc<dyn MetadataStore>,
         base_domain: String,
         functions_dir: PathBuf,
+        storage: Arc<dyn ArtifactStorage>,
+        artifact_cache_bytes: u64,
+        component_cache_bytes: u64,
+        auth_provider: Arc<dyn AuthProvider>,
     ) -> Result<Self> {
-        // Initialize GitHub auth
-        let github_auth = GitHubAuth::new(metadata_db.clone()).await?;
+        // Initialize user/project tracking with the configured auth provider
+        let github_auth = GitHubAuth::new(metadata.clone(), &metadata_db, auth_provider).await?;
+        let redirects = Redirects::new(metadata.clone())?;
+        let specs = Specs::new(metadata.clone())?;
+        let signing = ResponseSigning::new(metadata.clone())?;
+        let suspensions = Suspensions::new(metadata.clone(), &github_auth)?;
+        let canaries = Canaries::new(&metadata_db, &functions_dir, storage.clone())?;
+        let routes = RouteHandlers::new(
+            metadata.clone(),
+            &metadata_db,
+            &functions_dir,
+            storage.clone(),
+        )?;
+        let artifacts = ArtifactCache::new(
+            &metadata_db,
+            metadata.clone(),
+            &functions_dir,
+            storage.clone(),
+            artifact_cache_bytes,
+        )?;
 
         Ok(Self {
             engine,
             metadata_db,
-            pre_cache: DashMap::new(),
-            base_domain,
+            metadata,
+            pre_cache: ComponentCache::new(component_cache_bytes),
+            base_domain: Reloadable::new(base_domain),
             functions_dir,
+            storage,
             github_auth,
+            redirects,
+            suspensions,
+            canaries,
+            routes,
+            artifacts,
+            specs,
+            signing,
         })
     }
 
-    /// Remove a function from the pre_cache
+    /// Components in the pre_cache, and their estimated bytes
+    pub fn component_cache_usage(&self) -> (usize, u64) {
+        self.pre_cache.usage()
+    }
+
+    /// Whether `function_name` is loaded, and how many of its instances wait
+    pub fn warm_state(&self, function_name: &str) -> WarmState {
+        if !self.pre_cache.contains(function_name) {
+            return WarmState::Cold;
+        }
+        match INSTANCE_POOL
+            .get()
+            .map_or(0, |pool| pool.idle_count(function_name))
+        {
+            0 => WarmState::Loaded,
+            idle => WarmState::Warm {
+                idle_instances: idle as u32,
+            },
+        }
+    }
+
+    /// Remove a function from the pre_cache, along with its warm instances
     pub fn remove_from_cache(&self, function_name: &str) {
-        if self.pre_cache.contains_key(function_name) {
-            self.pre_cache.remove(function_name);
+        if self.pre_cache.remove(function_name) {
             debug!("Removed function '{}' from component cache", function_name);
         }
+        if let Some(pool) = INSTANCE_POOL.get() {
+            pool.evict(function_name);
+        }
+    }
+
+    /// Path of a function's precompiled artifact, hydrating it from storage if it
+    /// isn't cached locally. `None` if no such function is published.
+    async fn local_artifact(&self, function_name: &str) -> Result<Option<PathBuf>> {
+        let cached = self
+            .functions_dir
+            .join(format!("{function_name}.cwasm"))
+            .exists();
+        let path = self.artifacts.hydrate(&self.engine, function_name).await?;
+        if !cached && path.is_some() {
+            self.trim_artifact_cache(function_name);
+        }
+        Ok(path)
+    }
+
+    /// The function answering for `name`, which is its own name or an alias of it,
+    /// with the path of its precompiled artifact. `None` if neither is published.
+    async fn serving_function(&self, name: &str) -> Result<Option<(String, PathBuf)>> {
+        if let Some(path) = self.local_artifact(name).await? {
+            return Ok(Some((name.to_string(), path)));
+        }
+        let Some(alias) = ALIASES
+            .get()
+            .and_then(|aliases| aliases.get(name))
+            .filter(|alias| alias.mode == AliasMode::Alias)
+        else {
+            return Ok(None);
+        };
+        Ok(self
+            .local_artifact(&alias.target)
+            .await?
+            .map(|path| (alias.target, path)))
+    }
+
+    /// Where a redirecting alias called `name` sends requests, with the status
+    fn alias_redirect(name: &str) -> Option<(u16, String)> {
+        let alias = ALIASES.get()?.get(name)?;
+        Some((alias.mode.redirect_status()?, alias.target))
+    }
+
+    /// Evict cold functions until the artifact cache fits, keeping `keep`
+    pub fn trim_artifact_cache(&self, keep: &str) {
+        for function_name in self.artifacts.evict(keep) {
+            self.remove_from_cache(&function_name);
+        }
+    }
+
+    /// Handle an HTTP request under its request id: the id is in the span of
+    /// every log line the request causes, in the `X-Request-Id` response header
+    /// and in error pages, and the request is logged once it's answered
+    pub async fn serve_request(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<HyperOutgoingBody>> {
+        let id = new_request_id();
+        let span = info_span!(
+            "request",
+            request_id = %id,
+            method = %req.method(),
+            host = req.headers().get(HOST).and_then(|h| h.to_str().ok()).unwrap_or(""),
+            path = req.uri().path(),
+        );
+        telemetry::set_remote_parent(&span, req.headers());
+        let started = Instant::now();
+
+        let response = REQUEST_ID
+            .scope(id.clone(), async {
+                match self.handle_request(req).await {
+                    Ok(response) => Ok(response),
+                    Err(e) => {
+                        error!("Error handling request: {}", e);
+                        text_response(500, "Internal Server Error")
+                    }
+                }
+            })
+            .instrument(span.clone())
+            .await;
+
+        let (mut parts, body) = response?.into_parts();
+        parts
+            .headers
+            .insert("x-request-id", hyper::header::HeaderValue::from_str(&id)?);
+        let body = telemetry::trace_body(body, info_span!(parent: &span, "response_body"));
+        let response = Response::from_parts(parts, body);
+        span.in_scope(|| {
+            info!(
+                status = response.status().as_u16(),
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                "Request handled"
+            )
+        });
+        Ok(response)
     }
 
     pub async fn handle_request(
@@ -126,18 +660,17 @@ impl FaastaServer {
             .and_then(|h| h.to_str().ok())
             .map(|s| s.to_string());
         let path = req.uri().path().to_string();
+        let base_domain = self.base_domain.load();
 
         debug!("Handling request with path: {}", path);
 
         // Check if it's the root domain or local development host
         if host_header
             .as_deref()
-            .map(|h| {
-                h == self.base_domain || h.starts_with("localhost") || h.starts_with("127.0.0.1")
-            })
+            .map(|h| h == *base_domain || h.starts_with("localhost") || h.starts_with("127.0.0.1"))
             .unwrap_or(false)
         {
-            debug!("Processing request on root domain: {}", self.base_domain);
+            debug!("Processing request on root domain: {}", base_domain);
             // Root domain with no subdomain - try to route based on path
             let path_str = path.as_str();
             let path_parts: Vec<&str> = path_str.split('/').collect();
@@ -157,30 +690,13 @@ impl FaastaServer {
                     let function_name = path_parts[3].to_string();
 
                     // Get GitHub auth token from Authorization header
-                    let github_auth_token = match req.headers().get("Authorization") {
-                        Some(value) => {
-                            match value.to_str() {
-                                Ok(token) => {
-                                    // Remove "Bearer " prefix if present
-                                    let token = token.trim_start_matches("Bearer ").to_string();
-                                    if token.is_empty() {
-                                        return text_response(401, "Empty Authorization token");
-                                    }
-                                    token
-                                }
-                                Err(_) => {
-                                    return text_response(
-                                        401,
-                                        "Invalid Authorization header format",
-                                    )
-                                }
-                            }
-                        }
-                        None => return text_response(401, "Missing Authorization header"),
+                    let github_auth_token = match authorization_token(&req) {
+                        Ok(token) => token,
+                        Err(message) => return text_response(401, message),
                     };
 
                     // Create service implementation
-                    let service_impl = match rpc_service::create_service() {
+                    let service_impl = match rpc_service::create_service(peer_ip(&req)) {
                         Ok(service) => service,
                         Err(e) => {
                             error!("Failed to create function service: {}", e);
@@ -188,20 +704,11 @@ impl FaastaServer {
                         }
                     };
 
-                    // Read the body as WASM bytes
-                    let wasm_bytes = match http_body_util::BodyExt::collect(req.into_body()).await {
-                        Ok(collected) => collected.to_bytes().to_vec(),
-                        Err(e) => {
-                            error!("Failed to read request body: {}", e);
-                            return text_response(400, "Failed to read request body");
-                        }
+                    let wasm_bytes = match read_artifact_body(req).await {
+                        Ok(wasm_bytes) => wasm_bytes,
+                        Err(e) => return error_response(&e),
                     };
 
-                    // Validate WASM bytes aren't empty
-                    if wasm_bytes.is_empty() {
-                        return text_response(400, "Empty WASM file");
-                    }
-
                     // Call the service to publish the function
                     let result = service_impl
                         .publish(
@@ -229,31 +736,66 @@ impl FaastaServer {
                                 .body(HyperOutgoingBody::new(body))
                                 .unwrap());
                         }
-                        Err(err) => {
-                            let status_code = match &err {
-                                faasta_interface::FunctionError::AuthError(_) => 401,
-                                faasta_interface::FunctionError::NotFound(_) => 404,
-                                faasta_interface::FunctionError::PermissionDenied(_) => 403,
-                                faasta_interface::FunctionError::InvalidInput(_) => 400,
-                                faasta_interface::FunctionError::InternalError(_) => 500,
-                            };
+                        Err(err) => return error_response(&err),
+                    }
+                } else if path_parts.len() == 4
+                    && path_parts[2] == "logs"
+                    && req.method() == Method::GET
+                {
+                    let github_auth_token = match authorization_token(&req) {
+                        Ok(token) => token,
+                        Err(message) => return text_response(401, message),
+                    };
+                    let query = match log_query(req.uri().query().unwrap_or("")) {
+                        Ok(query) => query,
+                        Err(e) => return error_response(&e),
+                    };
+                    let service_impl = match rpc_service::create_service(peer_ip(&req)) {
+                        Ok(service) => service,
+                        Err(e) => {
+                            error!("Failed to create function service: {}", e);
+                            return text_response(500, "Internal server error");
+                        }
+                    };
 
+                    let result = service_impl
+                        .function_logs(
+                            tarpc::context::current(),
+                            path_parts[3].to_string(),
+                            query,
+                            github_auth_token,
+                        )
+                        .await;
+                    return match result {
+                        Ok(page) => {
                             let json = serde_json::json!({
-                                "success": false,
-                                "error": err.to_string()
+                                "success": true,
+                                "entries": page.entries,
+                                "next_cursor": page.next_cursor
                             });
-
                             let body = Full::new(Bytes::from(json.to_string()))
                                 .map_err(|_| ErrorCode::InternalError(None))
                                 .boxed();
-
-                            return Ok(Response::builder()
-                                .status(status_code)
+                            Ok(Response::builder()
+                                .status(200)
                                 .header("Content-Type", "application/json")
-                                .body(HyperOutgoingBody::new(body))
-                                .unwrap());
+                                .body(HyperOutgoingBody::new(body))?)
                         }
-                    }
+                        Err(err) => error_response(&err),
+                    };
+                } else if path_parts.len() == 4
+                    && path_parts[2] == "billing"
+                    && path_parts[3] == "webhook"
+                    && req.method() == Method::POST
+                {
+                    return crate::billing::handle_webhook_request(req).await;
+                } else if path_parts.len() >= 3 && path_parts[2] == "functions" {
+                    let resource: Vec<String> = path_parts[3..]
+                        .iter()
+                        .filter(|part| !part.is_empty())
+                        .map(|part| part.to_string())
+                        .collect();
+                    return crate::management_api::handle_request(req, &resource).await;
                 } else {
                     // Invalid v1 path
                     return text_response(403, "Forbidden: Invalid API endpoint");
@@ -265,15 +807,17 @@ impl FaastaServer {
                     function_name
                 );
 
-                // Use direct function name approach
-                let wasm_filename = format!("{function_name}.cwasm");
-                debug!("Looking for WASM file: {}", wasm_filename);
-
-                // Create a timer for this function call - will be moved to execute_function
-                let function_path = self.functions_dir.join(&wasm_filename);
+                // Find the precompiled function, hydrating it from storage if needed
+                let function = match self.serving_function(&function_name).await {
+                    Ok(function) => function,
+                    Err(e) => {
+                        error!("Failed to load function '{}': {}", function_name, e);
+                        return text_response(500, "Failed to load function");
+                    }
+                };
 
                 // Debug logging to track function path
-                if function_path.exists() {
+                if let Some((function_name, function_path)) = function {
                     debug!("Found function at path: {:?}", function_path);
                     // Create a new path to remove the /{function_name} prefix
                     let new_path = if path_parts.len() > 2 {
@@ -297,14 +841,34 @@ impl FaastaServer {
                         builder = builder.header(name, value);
                     }
 
-                    let (_, body) = req.into_parts();
-                    let new_req = builder.body(body)?;
+                    let (parts, body) = req.into_parts();
+                    let mut new_req = builder.body(body)?;
+                    *new_req.extensions_mut() = parts.extensions;
 
                     return self
                         .execute_function(new_req, &function_name, &function_path)
                         .await;
                 } else {
-                    debug!("Function not found at path: {:?}", function_path);
+                    debug!("Function '{}' not found", function_name);
+                    // A renamed function's old path redirects to its new one
+                    if let Some(redirect) = self.redirects.active(&function_name) {
+                        let rest = path_parts[2..].join("/");
+                        let query = req
+                            .uri()
+                            .query()
+                            .map(|q| format!("?{q}"))
+                            .unwrap_or_default();
+                        return redirect_response(&format!("/{}/{rest}{query}", redirect.target));
+                    }
+                    if let Some((status, target)) = Self::alias_redirect(&function_name) {
+                        let rest = path_parts[2..].join("/");
+                        let query = req
+                            .uri()
+                            .query()
+                            .map(|q| format!("?{q}"))
+                            .unwrap_or_default();
+                        return redirect_with_status(status, &format!("/{target}/{rest}{query}"));
+                    }
                     // If we're looking for a specific function but it doesn't exist, return a 404
                     return text_response(404, &format!("Function '{function_name}' not found"));
                 }
@@ -316,7 +880,7 @@ impl FaastaServer {
         }
 
         if let Some(host) = &host_header {
-            let expected_suffix = format!(".{}", self.base_domain);
+            let expected_suffix = format!(".{}", base_domain);
             debug!("Checking host: {} for subdomain routing", host);
 
             if !host
@@ -341,20 +905,47 @@ impl FaastaServer {
 
             debug!("Processing subdomain request for function: {}", subdomain);
 
-            // Use direct function name approach - only format once
-            let wasm_filename = format!("{subdomain}.cwasm");
-            debug!("Looking for WASM file: {}", wasm_filename);
-
-            // Create a timer for this function call - will be moved to execute_function
-            let function_path = self.functions_dir.join(&wasm_filename);
-            if !function_path.exists() {
-                debug!("Function not found at path: {:?}", function_path);
+            // Find the precompiled function, hydrating it from storage if needed
+            let function = match self.serving_function(subdomain).await {
+                Ok(function) => function,
+                Err(e) => {
+                    error!("Failed to load function '{}': {}", subdomain, e);
+                    return text_response(500, "Failed to load function");
+                }
+            };
+            let Some((function_name, function_path)) = function else {
+                debug!("Function '{}' not found", subdomain);
+                // A renamed function's old subdomain redirects to its new one
+                if let Some(redirect) = self.redirects.active(subdomain) {
+                    let path_and_query = req
+                        .uri()
+                        .path_and_query()
+                        .map(|pq| pq.as_str())
+                        .unwrap_or("/");
+                    return redirect_response(&format!(
+                        "https://{}.{}{path_and_query}",
+                        redirect.target, base_domain
+                    ));
+                }
+                if let Some((status, target)) = Self::alias_redirect(subdomain) {
+                    let path_and_query = req
+                        .uri()
+                        .path_and_query()
+                        .map(|pq| pq.as_str())
+                        .unwrap_or("/");
+                    return redirect_with_status(
+                        status,
+                        &format!("https://{target}.{base_domain}{path_and_query}"),
+                    );
+                }
                 return text_response(404, &format!("Function '{subdomain}' not found"));
-            }
+            };
 
             // Execute the function
             debug!("Executing function from subdomain route");
-            return self.execute_function(req, subdomain, &function_path).await;
+            return self
+                .execute_function(req, &function_name, &function_path)
+                .await;
         } else {
             // No host header, redirect to website
             debug!("No host header found, redirecting to website");
@@ -362,85 +953,433 @@ impl FaastaServer {
         }
     }
 
+    /// Answer a request to a function: its transformation rules change the request
+    /// and the response, its CORS preflights are answered from its policy, if it
+    /// has one, and anything else invokes it once authenticated. The response is
+    /// compressed last, so rules see it as the function sent it.
     async fn execute_function(
         &self,
-        req: Request<hyper::body::Incoming>,
+        mut req: Request<hyper::body::Incoming>,
         function_name: &str,
         function_path: &PathBuf,
     ) -> Result<Response<HyperOutgoingBody>> {
-        let _timer = Timer::new(function_name.to_string());
+        let rules = TRANSFORMS
+            .get()
+            .map(|rules| transforms::matching(rules.get(function_name), &req))
+            .unwrap_or_default();
+        transforms::transform_request(&rules, &mut req)?;
+        let encoding = compression::negotiate(&req);
+
+        let policy = CORS.get().and_then(|cors| cors.get(function_name));
+        let mut resp = match policy {
+            Some(policy) => match cors::preflight(&policy, &req) {
+                Some(resp) => resp?,
+                None => {
+                    let origin = req.headers().get(hyper::header::ORIGIN).cloned();
+                    let mut resp = self
+                        .invoke_function(req, function_name, function_path)
+                        .await?;
+                    cors::apply(&policy, origin.as_ref(), resp.headers_mut());
+                    resp
+                }
+            },
+            None => {
+                self.invoke_function(req, function_name, function_path)
+                    .await?
+            }
+        };
+        transforms::transform_response(&rules, &mut resp)?;
+        let resp = compression::compress(resp, encoding);
+        Ok(match USAGE.get() {
+            Some(usage) => usage.meter_egress(function_name, resp),
+            None => resp,
+        })
+    }
+
+    async fn invoke_function(
+        &self,
+        mut req: Request<hyper::body::Incoming>,
+        function_name: &str,
+        function_path: &PathBuf,
+    ) -> Result<Response<HyperOutgoingBody>> {
+        // Readiness probes come from the server only
+        if req.uri().path() == readiness::READY_PATH {
+            return text_response(404, "Not found");
+        }
+        if let Some(keys) = ACCESS_KEYS.get() {
+            if !keys.admit(function_name, &mut req) {
+                let mut response = text_response(401, "Unauthorized")?;
+                response.headers_mut().insert(
+                    hyper::header::WWW_AUTHENTICATE,
+                    hyper::header::HeaderValue::from_str(&format!(
+                        "Basic realm=\"{function_name}\""
+                    ))?,
+                );
+                return Ok(response);
+            }
+        }
+        if let Some(auth) = JWT_AUTH.get() {
+            if let Some(policy) = auth.get(function_name) {
+                match auth.authenticate(&policy, &mut req).await {
+                    Ok(()) => {}
+                    Err(AuthFailure::Unauthorized(reason)) => {
+                        debug!("Refused request to {}: {}", function_name, reason);
+                        let mut response = text_response(401, "Unauthorized")?;
+                        response.headers_mut().insert(
+                            hyper::header::WWW_AUTHENTICATE,
+                            hyper::header::HeaderValue::from_static(
+                                "Bearer error=\"invalid_token\"",
+                            ),
+                        );
+                        return Ok(response);
+                    }
+                    Err(AuthFailure::KeysUnavailable(reason)) => {
+                        warn!("No signing keys for {}: {}", function_name, reason);
+                        return text_response(503, "Authentication is unavailable, retry shortly");
+                    }
+                }
+            }
+        }
+        if self.suspensions.is_function_blocked(function_name) {
+            return text_response(403, &format!("Function '{function_name}' is suspended"));
+        }
+        if let Some(breaker) = open_breaker(function_name) {
+            return text_response(
+                503,
+                &format!(
+                    "Function '{function_name}' is unavailable ({})",
+                    breaker.state
+                ),
+            );
+        }
+        // Static assets are served without invoking the function
+        if let Some(resp) = STATIC_ASSETS
+            .get()
+            .map(|assets| assets.serve(function_name, &req))
+            .transpose()?
+            .flatten()
+        {
+            return Ok(resp);
+        }
+        if let Some(limits) = BODY_LIMITS.get().map(Reloadable::load) {
+            if limits.request_too_large(req.headers()) {
+                let limit = limits.max_request_bytes;
+                return BodyFault::TooLarge { limit }.response();
+            }
+        }
+        // Held until the guest finishes, which may be after it responded
+        let invocation = match CAPACITY.get() {
+            Some(capacity) => match capacity.invocation() {
+                Some(permit) => Some(permit),
+                None => {
+                    let mut response = text_response(503, "Server is at capacity, retry shortly")?;
+                    response
+                        .headers_mut()
+                        .insert(hyper::header::RETRY_AFTER, "1".parse()?);
+                    return Ok(response);
+                }
+            },
+            None => None,
+        };
+        let spec = self.specs.get(function_name).map(|applied| applied.spec);
+        if let Some(spec) = &spec {
+            if !specs::serves(&spec.routes, req.uri().path()) {
+                return text_response(404, "Not found");
+            }
+        }
+
+        // A route handler serves the requests under its route, and a canary takes
+        // its share of the others, each cached and measured as its own version
+        let routed = self.routes.route(function_name, req.uri().path());
+        if let Some((version, _)) = &routed {
+            self.routes.hydrate(&self.engine, version).await?;
+        }
+        let canary = routed.is_none() && self.canaries.pick(function_name);
+        let canary_path;
+        let (version, function_path) = match &routed {
+            Some((version, cwasm_path)) => (version.clone(), cwasm_path),
+            None if canary => {
+                canary_path = self.canaries.cwasm_path(function_name);
+                (format!("{function_name}{CANARY_SUFFIX}"), &canary_path)
+            }
+            None => (function_name.to_string(), function_path),
+        };
+        let _timer = Timer::new(version.clone());
+        let errors = Arc::new(InvocationErrors::new(version.clone()));
 
         debug!(
             "Executing function: {} [path: {:?}]",
-            function_name, function_path
+            version, function_path
         );
 
-        // Initialize a store template function if not already done
-        let store_template = STORE_TEMPLATE_CTX.get_or_init(|| {
-            // This template function will be used to create a similarly configured store each time
-            Box::new(move || FaastaClientState {
-                table: ResourceTable::new(),
-                wasi: WasiCtxBuilder::new().inherit_stdio().build(),
-                http: WasiHttpCtx::new(),
-            })
-        });
-
-        // Use the template to create a store with similar configuration
-        let mut client_state = store_template();
-
-        // Update environment for this specific function
-        client_state.wasi = WasiCtxBuilder::new()
-            // .inherit_stdio()
-            .env("FUNCTION_NAME", function_name)
-            .build();
-
-        // Get or load the ProxyPre
-        let pre = self
-            .get_or_load_proxy_pre(function_name, function_path)
-            .await?;
-
-        // Create store with client state
-        let mut store = Store::new(pre.engine(), client_state);
+        // Stop waiting at the function's own timeout if it has a shorter one
+        let mut timeout = request_timeout(&req);
+        if let Some(timeout_ms) = spec.as_ref().and_then(|spec| spec.limits.timeout_ms) {
+            timeout = timeout.min(Duration::from_millis(timeout_ms));
+        }
+        let (client_state, stdout, stderr) = client_state(function_name, spec.as_ref());
+        let request_id = current_request_id().unwrap_or_else(new_request_id);
+        let message_id = signing::new_message_id();
+
+        // The function sees the id its request is logged under, the id its caller
+        // sent for correlation, and the signals of its client being a bot
+        let (mut parts, body) = req.into_parts();
+        if let Some(client_id) = parts.headers.remove("x-request-id") {
+            parts.headers.insert("x-client-request-id", client_id);
+        }
+        parts.headers.insert(
+            "x-request-id",
+            hyper::header::HeaderValue::from_str(&request_id)?,
+        );
+        let signals = ClientSignals::of(&parts);
+        debug!(
+            bot_score = signals.score,
+            signals = ?signals.signals,
+            "Client signals"
+        );
+        signals.apply(&mut parts.headers);
+
+        // An opted-in function's request is captured in case it traps
+        let capture = SNAPSHOTS
+            .get()
+            .filter(|snapshots| snapshots.wants(function_name))
+            .map(|snapshots| snapshots.capture(&parts));
+        let body = match &capture {
+            Some(capture) => capture.tee(body),
+            None => CapturingBody::passthrough(body),
+        };
+        let req = Request::from_parts(parts, body);
+        let signers = self.signing.signers(function_name)?;
+
+        let instantiate_span = info_span!("instantiate", function = function_name);
+
+        // Get or load the ProxyPre. A sampled canary invocation runs on the
+        // profiling engine instead, and a hot function's request is served by one
+        // of its parked instances when there is one.
+        let profiling = PROFILING
+            .get()
+            .filter(|profiling| canary && profiling.sample(function_name));
+        let pool = INSTANCE_POOL.get().filter(|_| profiling.is_none());
+        let mut instance = match pool.and_then(|pool| pool.take(&version)) {
+            Some(instance) => instance,
+            None => {
+                let (pre, profiler) = match profiling {
+                    Some(profiling) => {
+                        let (pre, profiler) = profiling
+                            .prepare(function_name, &version, function_path)
+                            .instrument(instantiate_span.clone())
+                            .await?;
+                        (pre, Some(profiler))
+                    }
+                    None => (
+                        self.get_or_load_proxy_pre(&version, function_path)
+                            .instrument(instantiate_span.clone())
+                            .await?,
+                        None,
+                    ),
+                };
+
+                // Create store with client state
+                let mut store = Store::new(pre.engine(), client_state);
+                store.limiter(|state| state);
+                if let Some(profiler) = profiler {
+                    profiling::start_sampling(&mut store, profiler);
+                }
+                let proxy = pre
+                    .instantiate_async(&mut store)
+                    .instrument(instantiate_span)
+                    .await?;
+                WarmInstance::new(store, proxy, stdout, stderr)
+            }
+        };
 
         // Setup the response channel
         let (sender, receiver) = tokio::sync::oneshot::channel();
 
         // Create the WASI HTTP request
-        let wasi_req = store.data_mut().new_incoming_request(Scheme::Http, req)?;
-        let wasi_resp_out = store.data_mut().new_response_outparam(sender)?;
-
-        let proxy = pre.instantiate_async(&mut store).await?;
-
-        // Spawn a task to handle the function execution
-        let task = tokio::task::spawn(async move {
-            proxy
-                .wasi_http_incoming_handler()
-                .call_handle(store, wasi_req, wasi_resp_out)
-                .await?;
-            Ok::<_, anyhow::Error>(())
-        });
+        let state = instance.store.data_mut();
+        let faults = BodyFaults::default();
+        let wasi_req = streaming::incoming_request(state, req, faults.clone())?;
+        let wasi_resp_out = state.new_response_outparam(sender)?;
+
+        // Spawn a task to handle the function execution, storing its output once it's done
+        let log_name = function_name.to_string();
+        let metric_name = version.clone();
+        let task_errors = errors.clone();
+        let in_flight = SHUTDOWN.track();
+        let task = tokio::task::spawn(
+            async move {
+                let (_invocation, _in_flight) = (invocation, in_flight);
+                let started = Instant::now();
+                let result = instance
+                    .proxy
+                    .wasi_http_incoming_handler()
+                    .call_handle(&mut instance.store, wasi_req, wasi_resp_out)
+                    .await;
+                let store = &mut instance.store;
+                profiling::finish_sampling(store, &log_name, &request_id, started.elapsed());
+                if let Err(e) = &result {
+                    task_errors.record(format!("Trapped: {}", e.root_cause()));
+                }
+                events::publish(PlatformEvent::InvokeCompleted {
+                    function: log_name.clone(),
+                    version: metric_name.clone(),
+                    trapped: result.is_err(),
+                    instance: instance.id,
+                    memory_bytes: store.data().memory_bytes as u64,
+                });
+                if let Some(logs) = LOGS.get() {
+                    let failure = result
+                        .as_ref()
+                        .err()
+                        .map(|e| format!("Request failed: {e:#}"));
+                    if let Err(e) = logs.append(
+                        &log_name,
+                        &request_id,
+                        &instance.stdout.contents(),
+                        &instance.stderr.contents(),
+                        failure,
+                    ) {
+                        error!("Failed to store logs of '{}': {}", log_name, e);
+                    }
+                }
+                match (&result, pool) {
+                    (Err(e), _) => {
+                        if let (Some(capture), Some(snapshots)) = (capture, SNAPSHOTS.get()) {
+                            if let Err(e) = snapshots.record(
+                                &log_name,
+                                &request_id,
+                                e,
+                                capture.finish(),
+                                &mut instance.store,
+                            ) {
+                                error!("Failed to take a debug snapshot of '{}': {}", log_name, e);
+                            }
+                        }
+                    }
+                    // Only an instance that didn't trap is called again
+                    (Ok(()), Some(pool)) => pool.park(&metric_name, instance),
+                    (Ok(()), None) => {}
+                }
+                result
+            }
+            .instrument(info_span!("guest", function = function_name)),
+        );
 
-        // Wait for response with a 10-minute timeout
-        match tokio::time::timeout(std::time::Duration::from_secs(600), receiver).await {
+        // Wait for the response no longer than the caller does
+        let result = match tokio::time::timeout(timeout, receiver).await {
             Ok(receiver_result) => match receiver_result {
-                Ok(Ok(resp)) => Ok(resp),
+                Ok(Ok(resp)) => {
+                    if let Some(anomalies) = ANOMALIES.get() {
+                        let response_bytes = resp
+                            .headers()
+                            .get(hyper::header::CONTENT_LENGTH)
+                            .and_then(|value| value.to_str().ok()?.parse().ok())
+                            .unwrap_or(0);
+                        anomalies.record_request(function_name, response_bytes);
+                    }
+                    if BODY_LIMITS
+                        .get()
+                        .is_some_and(|limits| limits.load().response_too_large(resp.headers()))
+                    {
+                        error!("Response of '{}' is too large", function_name);
+                        return text_response(502, "Function response is too large");
+                    }
+                    let max_stream = spec
+                        .as_ref()
+                        .and_then(|spec| spec.limits.max_stream_secs)
+                        .map(Duration::from_secs);
+                    // Limited before signing, which would otherwise buffer a body of any
+                    // size for as long as the function takes to send it
+                    let resp = streaming::limit_response(resp, max_stream);
+                    // Signing buffers the body, which an event stream never finishes
+                    let resp = if signers.is_empty() || streaming::is_event_stream(resp.headers()) {
+                        resp
+                    } else {
+                        match signing::sign_response(resp, &signers, &message_id).await {
+                            Ok(resp) => resp,
+                            Err(e) => {
+                                error!("Failed to sign the response of '{}': {}", function_name, e);
+                                return text_response(502, "Function response could not be signed");
+                            }
+                        }
+                    };
+                    Ok(resp)
+                }
                 Ok(Err(err_code)) => {
                     error!("Function returned error: {:?}", err_code);
                     Err(anyhow!("Function error: {:?}", err_code))
                 }
                 Err(_) => match task.await {
-                    Ok(Ok(())) => bail!("Function did not set response"),
+                    Ok(Ok(())) => {
+                        errors.record("Did not set a response");
+                        bail!("Function did not set response")
+                    }
                     Ok(Err(e)) => Err(e),
-                    Err(e) => Err(e.into()),
+                    Err(e) => {
+                        errors.record(format!("Failed: {e}"));
+                        Err(e.into())
+                    }
                 },
             },
             Err(_) => {
-                error!("Function execution timed out after 10 minutes");
-                Err(anyhow!("Function execution timed out after 10 minutes"))
+                errors.record(format!("Timed out after {timeout:?}"));
+                error!("Function execution timed out after {:?}", timeout);
+                Err(anyhow!("Function execution timed out after {:?}", timeout))
             }
+        };
+        // A request cut off for its body failed the client's way, whatever the
+        // guest made of it
+        match faults.get() {
+            Some(fault) => fault.response(),
+            None => result,
         }
     }
 
+    /// Load a function ahead of its requests: hydrate its artifact, create its
+    /// pre-instantiated component and, with instance reuse on, park an instance
+    pub async fn prewarm(&self, function_name: &str) -> Result<()> {
+        self.prewarm_instances(function_name, 1).await
+    }
+
+    /// Load a function ahead of its requests and, with instance reuse on, park
+    /// instances until `count` are parked or the pool is full
+    pub async fn prewarm_instances(&self, function_name: &str, count: usize) -> Result<()> {
+        let function_path = self
+            .local_artifact(function_name)
+            .await?
+            .ok_or_else(|| anyhow!("Function '{function_name}' is not published"))?;
+        let pre = self
+            .get_or_load_proxy_pre(function_name, &function_path)
+            .await?;
+        let Some(pool) = INSTANCE_POOL.get() else {
+            return Ok(());
+        };
+        let spec = self.specs.get(function_name).map(|applied| applied.spec);
+        let probed = readiness::answers_probes(self.metadata.as_ref(), function_name);
+        while pool.idle_count(function_name) < count {
+            let (client_state, stdout, stderr) = client_state(function_name, spec.as_ref());
+            let mut store = Store::new(pre.engine(), client_state);
+            store.limiter(|state| state);
+            let proxy = pre.instantiate_async(&mut store).await?;
+            let mut instance = WarmInstance::new(store, proxy, stdout, stderr);
+            // Traffic only reaches the instance once it initialized
+            if probed && !readiness::wait_until_ready(function_name, &mut instance).await? {
+                warn!(
+                    "'{}' wasn't ready within {:?}",
+                    function_name,
+                    readiness::READY_TIMEOUT
+                );
+                break;
+            }
+            if !pool.put(function_name, instance) {
+                break;
+            }
+            debug!("Pre-instantiated '{}'", function_name);
+        }
+        Ok(())
+    }
+
     async fn get_or_load_proxy_pre(
         &self,
         function_name: &str,
@@ -459,7 +1398,7 @@ impl FaastaServer {
                 "Proxy pre-cache hit for '{}', retrieved in {:?}",
                 function_name, elapsed
             );
-            return Ok(cached.value().clone());
+            return Ok(cached);
         }
 
         info!(
@@ -477,17 +1416,7 @@ impl FaastaServer {
 
         // Get the shared linker or create it once
         let linker_start = Instant::now();
-        let linker = SHARED_LINKER.get_or_init(|| {
-            info!("Initializing shared linker (first time)");
-            let mut linker = Linker::new(&self.engine);
-
-            // Set up WASI and WASI-HTTP definitions - only needs to be done once
-            wasmtime_wasi::add_to_linker_async(&mut linker).expect("Failed to add WASI to linker");
-            wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)
-                .expect("Failed to add WASI-HTTP to linker");
-
-            linker
-        });
+        let linker = shared_linker(&self.engine);
         let linker_time = linker_start.elapsed();
         if linker_time.as_millis() > 1 {
             info!("Linker initialization took {:?}", linker_time);
@@ -499,9 +1428,17 @@ impl FaastaServer {
         let pre_time = pre_start.elapsed();
         info!("ProxyPre created for '{}' in {:?}", function_name, pre_time);
 
-        // Cache it for future use - only clone the string once when inserting
-        self.pre_cache
-            .insert(function_name.to_string(), pre.clone());
+        // Cache it for future use, sized by the code it maps
+        let bytes = std::fs::metadata(function_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        self.pre_cache.insert(function_name, pre.clone(), bytes);
+        let (loaded, loaded_bytes) = self.pre_cache.usage();
+        debug!(
+            "{} components loaded, {:.1}MB",
+            loaded,
+            loaded_bytes as f64 / (1024.0 * 1024.0)
+        );
 
         let total_elapsed = start_time.elapsed();
         info!(
@@ -0,0 +1,700 @@
+//! Outgoing webhooks delivered by the platform.
+//!
+//! Functions import `faasta:webhook/deliveries` (see `wit/webhook.wit`) to queue a
+//! POST instead of sending it themselves inside a time-limited invocation. Queued
+//! deliveries are kept in sled and retried with exponential backoff until a
+//! receiver answers with a 2xx status or the attempts run out, which is journaled
+//! as `webhook-failed` and logged to the function's logs. Every attempt is
+//! recorded, so owners can see what happened with `cargo faasta webhooks`.
+//!
+//! Delivery is at least once: an attempt cut short by a restart is made again.
+//! Receivers can use `webhook-id`, which stays the same across attempts, to drop
+//! duplicates.
+//!
+//! Deliveries follow their function when it's renamed and are deleted with it.
+
+use anyhow::Result;
+use bincode::{Decode, Encode};
+use dashmap::DashMap;
+use faasta_interface::{
+    DeliveryAttempt, DeliveryStatus, EventSeverity, ServerEventKind, WebhookDelivery,
+    MAX_WEBHOOK_PAYLOAD,
+};
+use futures::StreamExt;
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{debug, error, warn};
+use wasmtime::component::Linker;
+
+use crate::journal;
+use crate::logs::LOGS;
+use crate::wasi_server::{FaastaClientState, SERVER};
+
+pub mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit/webhook.wit",
+        world: "host",
+    });
+}
+
+use bindings::faasta::webhook::deliveries::{self, Delivery};
+
+/// Global webhook queue, set at startup unless webhooks are turned off
+pub static WEBHOOKS: OnceCell<Webhooks> = OnceCell::new();
+
+/// Sled tree holding deliveries, keyed by function name and delivery id
+const DELIVERIES_TREE: &str = "webhook_deliveries";
+/// Sled tree of pending deliveries, keyed by when they are due
+const QUEUE_TREE: &str = "webhook_queue";
+
+/// Most deliveries a function may have pending at once
+const MAX_PENDING_PER_FUNCTION: usize = 1000;
+/// Most deliveries listed at once
+const MAX_LISTED: usize = 100;
+/// Deliveries attempted at the same time
+const MAX_CONCURRENT_ATTEMPTS: usize = 16;
+/// Time a receiver has to answer
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Wait before the first retry, doubled for every further one
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Longest wait between two attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+/// How often the queue is checked when nothing wakes the worker
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often finished deliveries past their retention are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+/// Headers the platform sets itself
+const RESERVED_HEADERS: &[&str] = &["host", "content-length", "transfer-encoding"];
+
+#[derive(Encode, Decode)]
+struct StoredDelivery {
+    function: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    payload: Vec<u8>,
+    sign: bool,
+    status: DeliveryStatus,
+    created_at: String,
+    /// When the next attempt is due (Unix milliseconds), while pending
+    next_attempt_ms: i64,
+    /// Attempts made since the delivery was last queued
+    tries: u32,
+    attempts: Vec<DeliveryAttempt>,
+    /// When the delivery last changed (Unix milliseconds)
+    updated_ms: i64,
+}
+
+impl StoredDelivery {
+    fn info(&self, id: &str) -> WebhookDelivery {
+        WebhookDelivery {
+            id: id.to_string(),
+            url: self.url.clone(),
+            status: self.status,
+            created_at: self.created_at.clone(),
+            next_attempt_at: (self.status == DeliveryStatus::Pending)
+                .then(|| chrono::DateTime::from_timestamp_millis(self.next_attempt_ms))
+                .flatten()
+                .map(|at| at.to_rfc3339()),
+            attempts: self.attempts.clone(),
+        }
+    }
+}
+
+pub struct Webhooks {
+    db: sled::Db,
+    deliveries: sled::Tree,
+    queue: sled::Tree,
+    client: reqwest::Client,
+    /// Attempts made before a delivery fails
+    max_attempts: u32,
+    /// How long finished deliveries are kept
+    retention: Duration,
+    /// Wakes the worker when a delivery is queued
+    queued: Notify,
+    /// Pending deliveries of each function, counted at startup and kept up to date
+    /// so queueing checks the limit without reading them all
+    pending: DashMap<String, usize>,
+}
+
+impl Webhooks {
+    pub fn new(db: &sled::Db, max_attempts: u32, retention: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(ATTEMPT_TIMEOUT)
+            // A receiver's redirect isn't followed, so it can't send the payload elsewhere
+            .redirect(reqwest::redirect::Policy::none())
+            .user_agent(concat!("faasta-webhooks/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        let deliveries = db.open_tree(DELIVERIES_TREE)?;
+        let pending = DashMap::new();
+        for entry in deliveries.iter() {
+            let (_, value) = entry?;
+            let stored = decode(&value)?;
+            if stored.status == DeliveryStatus::Pending {
+                *pending.entry(stored.function).or_default() += 1;
+            }
+        }
+        Ok(Self {
+            db: db.clone(),
+            deliveries,
+            queue: db.open_tree(QUEUE_TREE)?,
+            client,
+            max_attempts: max_attempts.max(1),
+            retention,
+            queued: Notify::new(),
+            pending,
+        })
+    }
+
+    /// Queue `delivery` for `function`, returning its id or why it was refused
+    pub fn enqueue(&self, function: &str, delivery: Delivery) -> Result<String, String> {
+        check_delivery(&delivery)?;
+        if delivery.sign && !signing_enabled(function) {
+            return Err(
+                "response signing is off for this function (turn it on with `cargo faasta keys --enable`)"
+                    .to_string(),
+            );
+        }
+        // The slot is taken under the count's lock, so deliveries queued at the
+        // same time can't both take the last one
+        {
+            let mut pending = self.pending.entry(function.to_string()).or_default();
+            if *pending >= MAX_PENDING_PER_FUNCTION {
+                return Err(format!(
+                    "{MAX_PENDING_PER_FUNCTION} deliveries are already pending"
+                ));
+            }
+            *pending += 1;
+        }
+
+        let now = chrono::Utc::now();
+        let id = self.db.generate_id().map_err(|e| {
+            self.count_pending(function, false);
+            format!("failed to queue the delivery: {e}")
+        })?;
+        let key = delivery_key(function, now.timestamp_millis(), id);
+        let stored = StoredDelivery {
+            function: function.to_string(),
+            url: delivery.url,
+            headers: delivery.headers,
+            payload: delivery.payload,
+            sign: delivery.sign,
+            status: DeliveryStatus::Pending,
+            created_at: now.to_rfc3339(),
+            next_attempt_ms: now.timestamp_millis(),
+            tries: 0,
+            attempts: Vec::new(),
+            updated_ms: now.timestamp_millis(),
+        };
+        self.save(&key, &stored).map_err(|e| {
+            self.count_pending(function, false);
+            format!("failed to queue the delivery: {e}")
+        })?;
+        self.queued.notify_one();
+        Ok(delivery_id(&key))
+    }
+
+    /// Count one more, or one fewer, pending delivery of `function`
+    fn count_pending(&self, function: &str, more: bool) {
+        if more {
+            *self.pending.entry(function.to_string()).or_default() += 1;
+        } else {
+            self.pending.remove_if_mut(function, |_, pending| {
+                *pending = pending.saturating_sub(1);
+                *pending == 0
+            });
+        }
+    }
+
+    /// Store `stored`, queueing it while it is pending
+    fn save(&self, key: &[u8], stored: &StoredDelivery) -> Result<()> {
+        self.deliveries.insert(
+            key,
+            bincode::encode_to_vec(stored, bincode::config::standard())?,
+        )?;
+        if stored.status == DeliveryStatus::Pending {
+            self.queue
+                .insert(queue_key(stored.next_attempt_ms, key), &[])?;
+        }
+        Ok(())
+    }
+
+    fn load(&self, key: &[u8]) -> Result<Option<StoredDelivery>> {
+        self.deliveries
+            .get(key)?
+            .map(|value| decode(&value))
+            .transpose()
+    }
+
+    /// `function`'s deliveries, newest first
+    pub fn list(
+        &self,
+        function: &str,
+        status: Option<DeliveryStatus>,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let mut deliveries = Vec::new();
+        for entry in self.deliveries.scan_prefix(function_prefix(function)).rev() {
+            let (key, value) = entry?;
+            let stored = decode(&value)?;
+            if status.is_none_or(|status| stored.status == status) {
+                deliveries.push(stored.info(&delivery_id(&key)));
+                if deliveries.len() == MAX_LISTED {
+                    break;
+                }
+            }
+        }
+        Ok(deliveries)
+    }
+
+    /// Queue `function`'s delivery `id` again from its first attempt, keeping the
+    /// log of earlier ones. `None` if there is no such delivery.
+    pub fn redeliver(&self, function: &str, id: &str) -> Result<Option<WebhookDelivery>> {
+        let Some(key) = parse_delivery_id(function, id) else {
+            return Ok(None);
+        };
+        let Some(mut stored) = self.load(&key)? else {
+            return Ok(None);
+        };
+        if stored.status == DeliveryStatus::Pending {
+            self.queue.remove(queue_key(stored.next_attempt_ms, &key))?;
+        } else {
+            self.count_pending(function, true);
+        }
+        let now = chrono::Utc::now().timestamp_millis();
+        stored.status = DeliveryStatus::Pending;
+        stored.next_attempt_ms = now;
+        stored.tries = 0;
+        stored.updated_ms = now;
+        self.save(&key, &stored)?;
+        self.queued.notify_one();
+        Ok(Some(stored.info(id)))
+    }
+
+    /// Keys of the deliveries due by now, at most `limit`
+    fn due(&self, limit: usize) -> Result<Vec<Vec<u8>>> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut due = Vec::new();
+        for entry in self.queue.range(..queue_key(now, &[0xff])) {
+            let (key, _) = entry?;
+            due.push(key.to_vec());
+            if due.len() == limit {
+                break;
+            }
+        }
+        Ok(due)
+    }
+
+    /// Make the attempt the queue entry `queued` is due for
+    async fn attempt(&self, queued: &[u8]) -> Result<()> {
+        let key = &queued[8..];
+        let stored = self.load(key)?;
+        // The queue entry goes once the attempt is recorded, so a restart in
+        // between makes the attempt again rather than losing the delivery
+        let Some(mut stored) = stored.filter(|stored| {
+            stored.status == DeliveryStatus::Pending
+                && queue_key(stored.next_attempt_ms, key) == queued
+        }) else {
+            self.queue.remove(queued)?;
+            return Ok(());
+        };
+        let id = delivery_id(key);
+
+        let started = Instant::now();
+        let at = chrono::Utc::now();
+        let outcome = self.send(&id, &stored).await;
+        let (status, failure) = match &outcome {
+            Outcome::Answered(status) if (200..300).contains(status) => (Some(*status), None),
+            Outcome::Answered(status) => (
+                Some(*status),
+                Some(format!("the receiver answered {status}")),
+            ),
+            Outcome::Unsignable => (
+                None,
+                Some("response signing was turned off for the function".to_string()),
+            ),
+            Outcome::Failed(e) => (None, Some(e.clone())),
+        };
+        stored.tries += 1;
+        stored.attempts.push(DeliveryAttempt {
+            at: at.to_rfc3339(),
+            status,
+            error: failure.clone(),
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+        stored.updated_ms = chrono::Utc::now().timestamp_millis();
+
+        match failure {
+            None => {
+                debug!("Delivered webhook {} of '{}'", id, stored.function);
+                stored.status = DeliveryStatus::Delivered;
+            }
+            // A delivery that can't be signed stays that way, so it isn't retried
+            Some(_)
+                if stored.tries < self.max_attempts && !matches!(outcome, Outcome::Unsignable) =>
+            {
+                stored.next_attempt_ms =
+                    stored.updated_ms + retry_delay(stored.tries).as_millis() as i64;
+            }
+            Some(reason) => {
+                stored.status = DeliveryStatus::Failed;
+                self.notify_failure(&id, &stored, &reason);
+            }
+        }
+        self.save(key, &stored)?;
+        self.queue.remove(queued)?;
+        if stored.status != DeliveryStatus::Pending {
+            self.count_pending(&stored.function, false);
+        }
+        Ok(())
+    }
+
+    async fn send(&self, id: &str, stored: &StoredDelivery) -> Outcome {
+        let mut request = self.client.post(&stored.url).body(stored.payload.clone());
+        for (name, value) in &stored.headers {
+            request = request.header(name, value);
+        }
+        if stored.sign {
+            let signers = SERVER
+                .get()
+                .and_then(|server| server.signing.signers(&stored.function).ok())
+                .unwrap_or_default();
+            if signers.is_empty() {
+                return Outcome::Unsignable;
+            }
+            for (name, value) in crate::signing::signature_headers(&signers, id, &stored.payload) {
+                request = request.header(name, value);
+            }
+        }
+        match request.send().await {
+            Ok(response) => Outcome::Answered(response.status().as_u16()),
+            Err(e) if e.is_timeout() => {
+                Outcome::Failed(format!("no answer within {}s", ATTEMPT_TIMEOUT.as_secs()))
+            }
+            Err(e) => Outcome::Failed(e.to_string()),
+        }
+    }
+
+    /// Tell the function's owner a delivery was given up on, in the journal and the
+    /// function's logs
+    fn notify_failure(&self, id: &str, stored: &StoredDelivery, reason: &str) {
+        let detail = format!(
+            "Gave up on webhook {id} to {} after {} attempt(s): {reason}",
+            stored.url, stored.tries
+        );
+        warn!("{} ('{}')", detail, stored.function);
+        journal::record(
+            EventSeverity::Warning,
+            ServerEventKind::WebhookFailed,
+            Some(&stored.function),
+            detail.clone(),
+        );
+        if let Some(logs) = LOGS.get() {
+            if let Err(e) = logs.append(&stored.function, id, &[], &[], Some(detail)) {
+                error!(
+                    "Failed to log webhook failure of '{}': {}",
+                    stored.function, e
+                );
+            }
+        }
+    }
+
+    /// Move `from`'s deliveries to `to`, pending ones staying queued
+    pub fn rename_function(&self, from: &str, to: &str) -> Result<()> {
+        let prefix = function_prefix(from);
+        for entry in self.deliveries.scan_prefix(&prefix) {
+            let (key, value) = entry?;
+            let mut stored = decode(&value)?;
+            let mut new_key = function_prefix(to);
+            new_key.extend_from_slice(&key[prefix.len()..]);
+            stored.function = to.to_string();
+            if stored.status == DeliveryStatus::Pending {
+                self.queue.remove(queue_key(stored.next_attempt_ms, &key))?;
+            }
+            self.save(&new_key, &stored)?;
+            self.deliveries.remove(key)?;
+        }
+        if let Some((_, pending)) = self.pending.remove(from) {
+            *self.pending.entry(to.to_string()).or_default() += pending;
+        }
+        Ok(())
+    }
+
+    /// Delete all of `function`'s deliveries, pending ones included
+    pub fn remove_function(&self, function: &str) -> Result<()> {
+        for entry in self.deliveries.scan_prefix(function_prefix(function)) {
+            let (key, value) = entry?;
+            let stored = decode(&value)?;
+            if stored.status == DeliveryStatus::Pending {
+                self.queue.remove(queue_key(stored.next_attempt_ms, &key))?;
+            }
+            self.deliveries.remove(key)?;
+        }
+        self.pending.remove(function);
+        Ok(())
+    }
+
+    /// Names of the functions that have deliveries
+    pub fn functions(&self) -> Result<BTreeSet<String>> {
+        let mut functions = BTreeSet::new();
+        for key in self.deliveries.iter().keys() {
+            let key = key?;
+            let end = key.iter().position(|&byte| byte == 0).unwrap_or(key.len());
+            functions.insert(String::from_utf8_lossy(&key[..end]).into_owned());
+        }
+        Ok(functions)
+    }
+
+    /// Delete finished deliveries last changed before the retention period
+    fn prune(&self) -> Result<usize> {
+        let cutoff = chrono::Utc::now().timestamp_millis() - self.retention.as_millis() as i64;
+        let mut removed = 0;
+        for entry in self.deliveries.iter() {
+            let (key, value) = entry?;
+            let stored = decode(&value)?;
+            if stored.status != DeliveryStatus::Pending && stored.updated_ms < cutoff {
+                self.deliveries.remove(key)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// What came of one attempt
+enum Outcome {
+    /// The receiver answered with this status
+    Answered(u16),
+    /// The delivery asks to be signed, but the function has no key anymore
+    Unsignable,
+    /// The request couldn't be made or wasn't answered
+    Failed(String),
+}
+
+/// Wait before the attempt after the `tries`th: doubling from
+/// [`BASE_RETRY_DELAY`] up to [`MAX_RETRY_DELAY`], with jitter so deliveries that
+/// failed together don't all come back at once
+fn retry_delay(tries: u32) -> Duration {
+    let delay = BASE_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(tries.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY);
+    delay.mul_f64(rand::thread_rng().gen_range(0.8..=1.0))
+}
+
+/// Why `delivery` can't be queued, if it can't
+fn check_delivery(delivery: &Delivery) -> Result<(), String> {
+    let url = url::Url::parse(&delivery.url).map_err(|e| format!("invalid URL: {e}"))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err("the URL must be an http or https URL with a host".to_string());
+    }
+    if delivery.payload.len() > MAX_WEBHOOK_PAYLOAD {
+        return Err(format!(
+            "the payload is larger than {MAX_WEBHOOK_PAYLOAD} bytes"
+        ));
+    }
+    for (name, value) in &delivery.headers {
+        let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("invalid header name '{name}'"))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| format!("invalid value for header '{name}'"))?;
+        let reserved = RESERVED_HEADERS.contains(&name.as_str())
+            || (delivery.sign && name.as_str().starts_with("webhook-"));
+        if reserved {
+            return Err(format!("header '{name}' is set by the platform"));
+        }
+    }
+    Ok(())
+}
+
+fn signing_enabled(function: &str) -> bool {
+    SERVER
+        .get()
+        .and_then(|server| server.signing.signers(function).ok())
+        .is_some_and(|signers| !signers.is_empty())
+}
+
+fn decode(value: &[u8]) -> Result<StoredDelivery> {
+    Ok(bincode::decode_from_slice(value, bincode::config::standard())?.0)
+}
+
+fn function_prefix(function: &str) -> Vec<u8> {
+    let mut prefix = function.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+/// Deliveries sort by function, then by when they were queued
+fn delivery_key(function: &str, created_ms: i64, id: u64) -> Vec<u8> {
+    let mut key = function_prefix(function);
+    key.extend_from_slice(&created_ms.to_be_bytes());
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+/// `whd_` and the hex of the key's timestamp and sequence number
+fn delivery_id(key: &[u8]) -> String {
+    format!("whd_{}", hex::encode(&key[key.len() - 16..]))
+}
+
+fn parse_delivery_id(function: &str, id: &str) -> Option<Vec<u8>> {
+    let suffix = hex::decode(id.strip_prefix("whd_")?).ok()?;
+    if suffix.len() != 16 {
+        return None;
+    }
+    let mut key = function_prefix(function);
+    key.extend_from_slice(&suffix);
+    Some(key)
+}
+
+fn queue_key(due_ms: i64, delivery_key: &[u8]) -> Vec<u8> {
+    let mut key = due_ms.max(0).to_be_bytes().to_vec();
+    key.extend_from_slice(delivery_key);
+    key
+}
+
+impl deliveries::Host for FaastaClientState {
+    fn enqueue(&mut self, delivery: Delivery) -> Result<String, String> {
+        let webhooks = WEBHOOKS
+            .get()
+            .ok_or_else(|| "webhook delivery is turned off on this server".to_string())?;
+        webhooks.enqueue(&self.function_name, delivery)
+    }
+}
+
+/// Make `faasta:webhook/deliveries` available to functions
+pub fn add_to_linker(linker: &mut Linker<FaastaClientState>) -> Result<()> {
+    deliveries::add_to_linker(linker, |state| state)
+}
+
+/// Spawn the task that makes due attempts and prunes old deliveries
+pub fn spawn_delivery_worker() {
+    tokio::spawn(async move {
+        let Some(webhooks) = WEBHOOKS.get() else {
+            return;
+        };
+        let mut last_prune = Instant::now();
+        loop {
+            let due = webhooks
+                .due(MAX_CONCURRENT_ATTEMPTS * 4)
+                .unwrap_or_else(|e| {
+                    error!("Failed to read the webhook queue: {}", e);
+                    Vec::new()
+                });
+            if due.is_empty() {
+                tokio::select! {
+                    _ = webhooks.queued.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            } else {
+                futures::stream::iter(due)
+                    .for_each_concurrent(MAX_CONCURRENT_ATTEMPTS, |queued| async move {
+                        if let Err(e) = webhooks.attempt(&queued).await {
+                            error!("Failed to attempt a webhook delivery: {}", e);
+                        }
+                    })
+                    .await;
+            }
+
+            if last_prune.elapsed() >= PRUNE_INTERVAL {
+                last_prune = Instant::now();
+                match webhooks.prune() {
+                    Ok(0) => {}
+                    Ok(removed) => debug!("Pruned {} finished webhook deliveries", removed),
+                    Err(e) => error!("Failed to prune webhook deliveries: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_webhooks() -> Webhooks {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        Webhooks::new(&db, 3, Duration::from_secs(3600)).unwrap()
+    }
+
+    fn delivery(url: &str) -> Delivery {
+        Delivery {
+            url: url.to_string(),
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            payload: b"{}".to_vec(),
+            sign: false,
+        }
+    }
+
+    #[test]
+    fn test_deliveries_are_checked() {
+        assert!(check_delivery(&delivery("https://example.com/hook")).is_ok());
+        assert!(check_delivery(&delivery("ftp://example.com/hook")).is_err());
+
+        let mut oversized = delivery("https://example.com/hook");
+        oversized.payload = vec![0; MAX_WEBHOOK_PAYLOAD + 1];
+        assert!(check_delivery(&oversized).is_err());
+
+        let mut reserved = delivery("https://example.com/hook");
+        reserved
+            .headers
+            .push(("Host".to_string(), "elsewhere".to_string()));
+        assert!(check_delivery(&reserved).is_err());
+    }
+
+    #[test]
+    fn test_retry_delay_backs_off_up_to_the_limit() {
+        assert!(retry_delay(1) <= BASE_RETRY_DELAY);
+        assert!(retry_delay(3) > BASE_RETRY_DELAY * 3);
+        assert!(retry_delay(40) <= MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_queued_deliveries_are_due_and_listed() {
+        let webhooks = temp_webhooks();
+        let id = webhooks
+            .enqueue("hook", delivery("https://example.com/hook"))
+            .unwrap();
+        webhooks
+            .enqueue("other", delivery("https://example.com/other"))
+            .unwrap();
+        assert_eq!(webhooks.due(10).unwrap().len(), 2);
+
+        let listed = webhooks.list("hook", None).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, id);
+        assert_eq!(listed[0].status, DeliveryStatus::Pending);
+        assert!(webhooks
+            .list("hook", Some(DeliveryStatus::Failed))
+            .unwrap()
+            .is_empty());
+
+        // Redelivering doesn't queue a pending delivery twice
+        assert!(webhooks.redeliver("hook", &id).unwrap().is_some());
+        assert_eq!(webhooks.due(10).unwrap().len(), 2);
+        assert_eq!(*webhooks.pending.get("hook").unwrap(), 1);
+        assert!(webhooks.redeliver("other", &id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_deliveries_follow_renames_and_deletes() {
+        let webhooks = temp_webhooks();
+        let id = webhooks
+            .enqueue("hook", delivery("https://example.com/hook"))
+            .unwrap();
+        webhooks.rename_function("hook", "renamed").unwrap();
+        assert!(webhooks.list("hook", None).unwrap().is_empty());
+        assert_eq!(webhooks.list("renamed", None).unwrap()[0].id, id);
+        assert_eq!(webhooks.due(10).unwrap().len(), 1);
+        assert_eq!(*webhooks.pending.get("renamed").unwrap(), 1);
+        assert_eq!(
+            webhooks.functions().unwrap(),
+            BTreeSet::from(["renamed".to_string()])
+        );
+
+        webhooks.remove_function("renamed").unwrap();
+        assert!(webhooks.list("renamed", None).unwrap().is_empty());
+        assert!(webhooks.due(10).unwrap().is_empty());
+        assert!(webhooks.pending.is_empty());
+    }
+}